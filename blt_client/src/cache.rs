@@ -0,0 +1,113 @@
+//! The local copies of fetched shards.
+//!
+//! A shard is downloaded to `NAME.part` in the cache directory and renamed to `NAME` once
+//! complete, so an interrupted download resumes from where it stopped instead of starting over,
+//! and a file named after the shard is always whole. Shards stay cached, so later epochs read
+//! them locally.
+
+use crate::source::ShardSource;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+/// A directory of downloaded shards.
+#[derive(Debug)]
+pub(crate) struct ShardCache {
+    dir: PathBuf,
+}
+
+impl ShardCache {
+    /// Caches shards in `dir`, creating it if needed.
+    pub(crate) fn new(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Returns the path of the local copy of `name`, downloading whatever is missing from
+    /// `source`. A cached copy is used as is unless it differs from the expected `size`.
+    pub(crate) async fn fetch(
+        &self,
+        source: &dyn ShardSource,
+        name: &str,
+        size: Option<u64>,
+    ) -> io::Result<PathBuf> {
+        let mut components = Path::new(name).components();
+        let (Some(Component::Normal(_)), None) = (components.next(), components.next()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Refusing to cache shard '{name}' outside the cache directory"),
+            ));
+        };
+        let path = self.dir.join(name);
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) if size.is_none_or(|size| size == metadata.len()) => return Ok(path),
+            Ok(_) => tokio::fs::remove_file(&path).await?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut part_name = path.as_os_str().to_owned();
+        part_name.push(".part");
+        let part = PathBuf::from(part_name);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&part)
+            .await?;
+        let mut len = file.metadata().await?.len();
+        if size.is_some_and(|size| len > size) {
+            file.set_len(0).await?;
+            len = 0;
+        }
+        len += source.read(name, len, &mut file).await?;
+        file.flush().await?;
+        if let Some(size) = size.filter(|&size| size != len) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Shard {name} ended after {len} of its {size} bytes"),
+            ));
+        }
+        tokio::fs::rename(&part, &path).await?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::DirSource;
+
+    #[tokio::test]
+    async fn test_resumes_partial_downloads() -> io::Result<()> {
+        let remote = tempfile::tempdir()?;
+        std::fs::write(remote.path().join("a.bin"), b"abcdef")?;
+        let local = tempfile::tempdir()?;
+        std::fs::write(local.path().join("a.bin.part"), b"abc")?;
+        let cache = ShardCache::new(local.path())?;
+        let source = DirSource::new(remote.path());
+
+        let path = cache.fetch(&source, "a.bin", Some(6)).await?;
+        assert_eq!(std::fs::read(&path)?, b"abcdef");
+        assert!(!local.path().join("a.bin.part").exists());
+
+        // Cached copies are not fetched again.
+        std::fs::write(remote.path().join("a.bin"), b"ABCDEF")?;
+        let path = cache.fetch(&source, "a.bin", Some(6)).await?;
+        assert_eq!(std::fs::read(&path)?, b"abcdef");
+
+        let error = cache.fetch(&source, "a.bin", Some(9)).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            cache
+                .fetch(&source, "../a.bin", None)
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+//! A client streaming token shards into Rust training loops.
+//!
+//! [`ShardLoader`] fetches the shards of a [`ShardSource`], usually the [`HttpSource`] of a
+//! `blt serve --shards` server, into a local cache directory and reads them back as an
+//! iterator of token sequences. While one shard is being read, the next few are downloaded in
+//! the background, so a training loop rarely waits on the network.
+//!
+//! ```no_run
+//! use blt_client::{HttpSource, ShardLoader};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let source = HttpSource::new("http://shards.internal:8080")?;
+//! let loader = ShardLoader::new(source, "/tmp/blt-cache")?
+//!     .with_prefetch(4)
+//!     .with_sequence_length(Some(2048));
+//! for sequence in loader.sequences()? {
+//!     let tokens: Vec<u16> = sequence?;
+//!     // train on `tokens`
+//! #   let _ = tokens;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Sequences are either whole documents, located with each shard's index sidecar, or windows
+//! of a fixed number of tokens read across shard boundaries. Shards written with an output
+//! header are read at the token width it records; others are taken to hold 16-bit tokens.
+//! Every token is big-endian, as blt writes it.
+
+use blt_core::header::OutputHeader;
+use blt_core::index::{self, DocumentIndex};
+use memmap2::Mmap;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+mod cache;
+mod source;
+use cache::ShardCache;
+pub use source::{DirSource, HttpSource, RemoteShard, ShardSource};
+
+/// The number of shards downloaded ahead of the one being read, by default.
+pub const DEFAULT_PREFETCH: usize = 2;
+
+/// Fetches shards into a local cache and reads them as token sequences.
+///
+/// The loader runs its downloads on a runtime of its own, so it must not be used from within
+/// an async context.
+pub struct ShardLoader {
+    runtime: Runtime,
+    source: Arc<dyn ShardSource>,
+    cache: Arc<ShardCache>,
+    prefetch: usize,
+    sequence_length: Option<usize>,
+}
+
+impl ShardLoader {
+    /// Loads the shards of `source`, caching them in `cache_dir`.
+    pub fn new(
+        source: impl ShardSource + 'static,
+        cache_dir: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("blt-client")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            runtime,
+            source: Arc::new(source),
+            cache: Arc::new(ShardCache::new(cache_dir.as_ref())?),
+            prefetch: DEFAULT_PREFETCH,
+            sequence_length: None,
+        })
+    }
+
+    /// Downloads up to `shards` shards ahead of the one being read.
+    pub fn with_prefetch(mut self, shards: usize) -> Self {
+        self.prefetch = shards;
+        self
+    }
+
+    /// Yields windows of `tokens` tokens instead of whole documents. The last window is
+    /// dropped if the shards run out before it is full.
+    pub fn with_sequence_length(mut self, tokens: Option<usize>) -> Self {
+        self.sequence_length = tokens.filter(|&tokens| tokens > 0);
+        self
+    }
+
+    /// Lists the shards of the source, in the order they are read.
+    pub fn shards(&self) -> io::Result<Vec<RemoteShard>> {
+        self.runtime.block_on(self.source.list())
+    }
+
+    /// Reads every shard of the source, in order, as token sequences.
+    pub fn sequences(&self) -> io::Result<Sequences<'_>> {
+        let mut sequences = Sequences {
+            loader: self,
+            queued: self.shards()?.into(),
+            pending: VecDeque::new(),
+            current: None,
+            window: Vec::new(),
+        };
+        sequences.prefetch();
+        Ok(sequences)
+    }
+
+    /// Starts downloading `shard` and its index, then maps them.
+    fn load(&self, shard: RemoteShard) -> JoinHandle<io::Result<LoadedShard>> {
+        let source = self.source.clone();
+        let cache = self.cache.clone();
+        self.runtime.spawn(async move {
+            let path = cache
+                .fetch(source.as_ref(), &shard.name, Some(shard.bytes))
+                .await?;
+            let index = match shard.documents {
+                Some(_) => {
+                    let index_name = format!("{}.{}", shard.name, index::EXTENSION);
+                    let index_path = cache.fetch(source.as_ref(), &index_name, None).await?;
+                    Some(index::read_index(&index_path).await?)
+                }
+                None => None,
+            };
+            tokio::task::spawn_blocking(move || LoadedShard::open(&path, index))
+                .await
+                .map_err(io::Error::other)?
+        })
+    }
+}
+
+/// A cached shard, mapped for reading.
+struct LoadedShard {
+    map: Mmap,
+    data_start: usize,
+    token_width: usize,
+    /// The `(token offset, tokens)` of each document still to be read, or `None` without an
+    /// index.
+    documents: Option<VecDeque<(usize, usize)>>,
+    /// The next token to read.
+    position: usize,
+}
+
+impl LoadedShard {
+    fn open(path: &Path, index: Option<DocumentIndex>) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: cached shards are only written before they are renamed into place.
+        let map = unsafe { Mmap::map(&file)? };
+        let (token_width, data_start) = match OutputHeader::parse(&map) {
+            Ok((header, header_len)) => (usize::from(header.token_width.max(1)), header_len),
+            Err(_) => (2, 0),
+        };
+        let documents = index.map(|index| {
+            index
+                .entries()
+                .iter()
+                .map(|entry| (entry.token_offset as usize, entry.tokens as usize))
+                .collect()
+        });
+        Ok(Self {
+            data_start: data_start.min(map.len()),
+            map,
+            token_width,
+            documents,
+            position: 0,
+        })
+    }
+
+    fn tokens(&self) -> usize {
+        (self.map.len() - self.data_start) / self.token_width
+    }
+
+    /// Appends tokens `start..end` to `out`.
+    fn decode(&self, start: usize, end: usize, out: &mut Vec<u16>) {
+        let end = end.min(self.tokens());
+        let start = start.min(end);
+        let bytes = &self.map[self.data_start + start * self.token_width..]
+            [..(end - start) * self.token_width];
+        match self.token_width {
+            1 => out.extend(bytes.iter().map(|&b| u16::from(b))),
+            width => out.extend(
+                bytes
+                    .chunks_exact(width)
+                    .map(|token| u16::from_be_bytes([token[width - 2], token[width - 1]])),
+            ),
+        }
+    }
+
+    /// Returns the next document: each indexed one, or the whole shard without an index.
+    fn next_document(&mut self) -> Option<Vec<u16>> {
+        let total = self.tokens();
+        let (start, tokens) = match &mut self.documents {
+            Some(documents) => documents.pop_front()?,
+            None if self.position < total => (0, total),
+            None => return None,
+        };
+        let mut document = Vec::with_capacity(tokens);
+        self.decode(start, start + tokens, &mut document);
+        self.position = start + tokens;
+        Some(document)
+    }
+
+    /// Moves up to `tokens` of the next tokens to `out`, returning how many were moved.
+    fn take(&mut self, tokens: usize, out: &mut Vec<u16>) -> usize {
+        let end = (self.position + tokens).min(self.tokens());
+        self.decode(self.position, end, out);
+        let taken = end - self.position;
+        self.position = end;
+        taken
+    }
+}
+
+/// The token sequences of a [`ShardLoader`]'s shards. Dropping it stops the downloads in
+/// progress; their partial files are resumed by the next run.
+pub struct Sequences<'a> {
+    loader: &'a ShardLoader,
+    /// Shards not yet being downloaded.
+    queued: VecDeque<RemoteShard>,
+    /// Downloads in progress, in reading order.
+    pending: VecDeque<JoinHandle<io::Result<LoadedShard>>>,
+    current: Option<LoadedShard>,
+    /// The tokens of the fixed-length sequence being assembled.
+    window: Vec<u16>,
+}
+
+impl Sequences<'_> {
+    /// Keeps the configured number of shards downloading.
+    fn prefetch(&mut self) {
+        while self.pending.len() <= self.loader.prefetch {
+            let Some(shard) = self.queued.pop_front() else {
+                break;
+            };
+            self.pending.push_back(self.loader.load(shard));
+        }
+    }
+
+    /// Moves on to the next shard, returning `None` once there are no more.
+    fn advance(&mut self) -> Option<io::Result<()>> {
+        self.current = None;
+        let download = self.pending.pop_front()?;
+        self.prefetch();
+        let loaded = self
+            .loader
+            .runtime
+            .block_on(download)
+            .map_err(io::Error::other)
+            .and_then(|result| result);
+        Some(loaded.map(|shard| self.current = Some(shard)))
+    }
+}
+
+impl Iterator for Sequences<'_> {
+    type Item = io::Result<Vec<u16>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let length = self.loader.sequence_length;
+        loop {
+            if let Some(shard) = self.current.as_mut() {
+                match length {
+                    None => {
+                        if let Some(document) = shard.next_document() {
+                            return Some(Ok(document));
+                        }
+                    }
+                    Some(length) => {
+                        let missing = length - self.window.len();
+                        if shard.take(missing, &mut self.window) == missing {
+                            return Some(Ok(std::mem::take(&mut self.window)));
+                        }
+                    }
+                }
+            }
+            if let Err(e) = self.advance()? {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl Drop for Sequences<'_> {
+    fn drop(&mut self) {
+        for download in &self.pending {
+            download.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blt_core::header;
+    use blt_core::StrategyKind;
+
+    /// Writes a headered shard holding `documents`, with an index, into `dir`.
+    fn write_shard(dir: &Path, name: &str, documents: &[&[u16]]) {
+        let mut bytes = OutputHeader::new(StrategyKind::Basic, None)
+            .to_bytes()
+            .to_vec();
+        let mut index = DocumentIndex::new(2, header::HEADER_LEN as u64);
+        for document in documents {
+            bytes.extend(document.iter().flat_map(|token| token.to_be_bytes()));
+            index.push(document.len() as u64);
+        }
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        std::fs::write(index::default_path(&path), index.to_bytes()).unwrap();
+    }
+
+    fn collect(loader: &ShardLoader) -> Vec<Vec<u16>> {
+        loader
+            .sequences()
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reads_documents_and_windows() {
+        let remote = tempfile::tempdir().unwrap();
+        write_shard(remote.path(), "a.bin", &[&[1, 2], &[3]]);
+        write_shard(remote.path(), "b.bin", &[&[4, 5, 6]]);
+        std::fs::write(remote.path().join("c.bin"), [0, 7, 0, 8]).unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let loader = ShardLoader::new(DirSource::new(remote.path()), cache.path())
+            .unwrap()
+            .with_prefetch(1);
+
+        assert_eq!(
+            collect(&loader),
+            [vec![1, 2], vec![3], vec![4, 5, 6], vec![7, 8]]
+        );
+        assert!(cache.path().join("b.bin.idx").exists());
+        let loader = loader.with_sequence_length(Some(3));
+        assert_eq!(collect(&loader), [vec![1, 2, 3], vec![4, 5, 6]]);
+        let loader = loader.with_sequence_length(Some(2));
+        assert_eq!(
+            collect(&loader),
+            [vec![1, 2], vec![3, 4], vec![5, 6], vec![7, 8]]
+        );
+    }
+
+    #[test]
+    fn test_streams_from_shard_server() {
+        use blt_core::server::{Listener, ServerConfig};
+
+        let remote = tempfile::tempdir().unwrap();
+        write_shard(remote.path(), "a.bin", &[&[1, 2], &[3]]);
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = tokio::runtime::Runtime::new().unwrap();
+        server.spawn(blt_core::server::serve(ServerConfig {
+            listener: Listener::Bind(addr),
+            core: blt_core::CoreConfig::default(),
+            quotas: Default::default(),
+            api_keys: None,
+            tls: None,
+            batching: None,
+            shards: Some(remote.path().to_path_buf()),
+        }));
+        while std::net::TcpStream::connect(addr).is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let cache = tempfile::tempdir().unwrap();
+        // A download interrupted earlier is resumed with a range request.
+        let mut partial = std::fs::read(remote.path().join("a.bin")).unwrap();
+        partial.truncate(21);
+        std::fs::write(cache.path().join("a.bin.part"), partial).unwrap();
+        let source = HttpSource::new(&format!("http://{addr}")).unwrap();
+        let loader = ShardLoader::new(source, cache.path()).unwrap();
+        assert_eq!(loader.shards().unwrap()[0].documents, Some(2));
+        assert_eq!(collect(&loader), [vec![1, 2], vec![3]]);
+        assert_eq!(
+            std::fs::read(cache.path().join("a.bin")).unwrap(),
+            std::fs::read(remote.path().join("a.bin")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Where shards are fetched from.
+//!
+//! [`HttpSource`] talks to `blt serve --shards`; [`DirSource`] reads a directory, such as an
+//! object store bucket mounted locally. Other stores plug in by implementing [`ShardSource`].
+
+use async_trait::async_trait;
+use blt_core::index;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Incoming;
+use hyper::header;
+use hyper::{Request, Response, StatusCode, Uri};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A shard a source offers.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct RemoteShard {
+    /// The shard's file name.
+    pub name: String,
+    /// The size of the file in bytes.
+    pub bytes: u64,
+    /// The number of documents, when the shard has an index sidecar (`NAME.idx`).
+    #[serde(default)]
+    pub documents: Option<u64>,
+}
+
+/// A store of token shards.
+#[async_trait]
+pub trait ShardSource: Send + Sync {
+    /// Lists the shards, in the order they are read.
+    async fn list(&self) -> io::Result<Vec<RemoteShard>>;
+
+    /// Appends the bytes of the file `name` from `offset` on to `out`, returning how many were
+    /// appended. Fails with [`io::ErrorKind::NotFound`] if there is no such file.
+    async fn read(&self, name: &str, offset: u64, out: &mut File) -> io::Result<u64>;
+}
+
+/// The shards served by `blt serve --shards`, over plain HTTP.
+#[derive(Clone, Debug)]
+pub struct HttpSource {
+    authority: String,
+    prefix: String,
+    api_key: Option<String>,
+}
+
+impl HttpSource {
+    /// Fetches from the server at `base_url`, e.g. `http://shards.internal:8080`.
+    pub fn new(base_url: &str) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let uri: Uri = base_url
+            .parse()
+            .map_err(|e| invalid(format!("Invalid shard server URL '{base_url}': {e}")))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(invalid(format!(
+                "Shard server URL '{base_url}' must start with http://"
+            )));
+        }
+        let authority = uri
+            .authority()
+            .ok_or_else(|| invalid(format!("Shard server URL '{base_url}' has no host")))?;
+        let authority = match authority.port() {
+            Some(_) => authority.to_string(),
+            None => format!("{authority}:80"),
+        };
+        Ok(Self {
+            authority,
+            prefix: uri.path().trim_end_matches('/').to_string(),
+            api_key: None,
+        })
+    }
+
+    /// Sends `key` with every request, for servers started with `--api-keys`.
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Sends a `GET` for `path`, asking for the bytes from `offset` on when it is nonzero.
+    async fn get(&self, path: &str, offset: u64) -> io::Result<Response<Incoming>> {
+        let stream = TcpStream::connect(&self.authority).await?;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(io::Error::other)?;
+        tokio::spawn(connection);
+        let mut request = Request::get(format!("{}{path}", self.prefix))
+            .header(header::HOST, &self.authority)
+            .header(header::CONNECTION, "close");
+        if offset > 0 {
+            request = request.header(header::RANGE, format!("bytes={offset}-"));
+        }
+        if let Some(key) = &self.api_key {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {key}"));
+        }
+        let request = request
+            .body(Empty::<Bytes>::new())
+            .map_err(io::Error::other)?;
+        sender.send_request(request).await.map_err(io::Error::other)
+    }
+}
+
+#[async_trait]
+impl ShardSource for HttpSource {
+    async fn list(&self) -> io::Result<Vec<RemoteShard>> {
+        let response = check_status(self.get("/shards", 0).await?, "/shards").await?;
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(io::Error::other)?;
+        serde_json::from_slice(&body.to_bytes()).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid shard list: {e}"),
+            )
+        })
+    }
+
+    async fn read(&self, name: &str, offset: u64, out: &mut File) -> io::Result<u64> {
+        let path = format!("/shards/{}", percent_encode(name));
+        let response = self.get(&path, offset).await?;
+        // The file ends where the copy does.
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(0);
+        }
+        let response = check_status(response, &path).await?;
+        // A server ignoring the range sends the whole file.
+        let mut skip = match response.status() {
+            StatusCode::PARTIAL_CONTENT => 0,
+            _ => offset,
+        };
+        let mut body = response.into_body();
+        let mut appended = 0;
+        while let Some(frame) = body.frame().await {
+            let Ok(data) = frame.map_err(io::Error::other)?.into_data() else {
+                continue;
+            };
+            let skipped = skip.min(data.len() as u64);
+            skip -= skipped;
+            out.write_all(&data[skipped as usize..]).await?;
+            appended += data.len() as u64 - skipped;
+        }
+        Ok(appended)
+    }
+}
+
+/// Fails with the server's message unless `response` succeeded.
+async fn check_status(response: Response<Incoming>, path: &str) -> io::Result<Response<Incoming>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(io::Error::other)?;
+    let message = format!(
+        "GET {path} failed with {status}: {}",
+        String::from_utf8_lossy(&body.to_bytes())
+    );
+    Err(match status {
+        StatusCode::NOT_FOUND => io::Error::new(io::ErrorKind::NotFound, message),
+        _ => io::Error::other(message),
+    })
+}
+
+/// Escapes every byte of `name` outside the unreserved URL characters.
+fn percent_encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// The shards in a directory, such as a locally mounted object store bucket.
+#[derive(Clone, Debug)]
+pub struct DirSource {
+    root: PathBuf,
+}
+
+impl DirSource {
+    /// Reads the shards in `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl ShardSource for DirSource {
+    async fn list(&self) -> io::Result<Vec<RemoteShard>> {
+        let mut shards = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() || name.ends_with(&format!(".{}", index::EXTENSION)) {
+                continue;
+            }
+            let documents = match index::read_index(&index::default_path(&entry.path())).await {
+                Ok(index) => Some(index.entries().len() as u64),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            shards.push(RemoteShard {
+                name,
+                bytes: metadata.len(),
+                documents,
+            });
+        }
+        shards.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(shards)
+    }
+
+    async fn read(&self, name: &str, offset: u64, out: &mut File) -> io::Result<u64> {
+        let mut file = File::open(self.root.join(name)).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        tokio::io::copy(&mut file, out).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_base_urls() {
+        let source = HttpSource::new("http://shards.internal:9000/blt/").unwrap();
+        assert_eq!(source.authority, "shards.internal:9000");
+        assert_eq!(source.prefix, "/blt");
+        assert_eq!(HttpSource::new("http://host").unwrap().authority, "host:80");
+        assert!(HttpSource::new("https://host").is_err());
+        assert!(HttpSource::new("not a url").is_err());
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("a-1.bin"), "a-1.bin");
+        assert_eq!(percent_encode("a b/c"), "a%20b%2Fc");
+    }
+}
@@ -0,0 +1,332 @@
+//! Measuring throughput on the current machine.
+//!
+//! [`bench`] tokenizes a sample, either synthetic text or the start of a file, once for every
+//! combination of thread count and chunk size, and recommends the configuration that ran
+//! fastest. The sample is held in memory and mapped like a file input, so the runs measure the
+//! pipeline and the strategy rather than the disk; tokens are discarded rather than written.
+
+use crate::io_handler::{InputSource, OutputWriter};
+use crate::pipeline::{self, FifoScheduler, PipelineOutput, Scheduler};
+use crate::progress::Size;
+use crate::tokenizer::TokenizationStrategy;
+use crate::{chunking, framed_strategy, token_width, validate_config};
+use crate::{BltError, CoreConfig};
+use memmap2::MmapMut;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info};
+
+/// The size of the synthetic sample when none is given.
+pub const DEFAULT_SAMPLE_BYTES: usize = 64 << 20;
+
+/// The chunk sizes swept when none are given.
+pub const DEFAULT_CHUNK_SIZES: [usize; 4] = [256 << 10, 1 << 20, 4 << 20, 16 << 20];
+
+/// How close to the fastest run a configuration must be for [`bench`] to prefer it for using
+/// fewer threads or smaller chunks.
+pub const RECOMMENDATION_TOLERANCE: f64 = 0.05;
+
+/// What [`bench`] tokenizes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BenchInput {
+    /// Generated text of this many bytes.
+    Synthetic(usize),
+    /// The start of this file, up to this many bytes.
+    Sample(PathBuf, usize),
+}
+
+/// The thread counts swept when none are given: powers of two up to the number of cores, and
+/// the number of cores itself.
+pub fn default_thread_counts() -> Vec<usize> {
+    let cores = num_cpus::get().max(1);
+    let mut counts: Vec<usize> = std::iter::successors(Some(1usize), |n| n.checked_mul(2))
+        .take_while(|&n| n < cores)
+        .collect();
+    counts.push(cores);
+    counts
+}
+
+/// One configuration's run.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BenchResult {
+    /// The number of chunks tokenized at once.
+    pub threads: usize,
+    /// The chunk size, after clamping, in bytes.
+    pub chunk_size_bytes: usize,
+    /// Wall-clock duration of the run.
+    pub secs: f64,
+    /// Input throughput, in MB per second.
+    pub mb_per_sec: f64,
+    /// Tokens produced per second.
+    pub tokens_per_sec: f64,
+}
+
+/// The results of a sweep.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BenchReport {
+    /// Bytes in the sample.
+    pub sample_bytes: u64,
+    /// Whether the sample was generated rather than read from a file.
+    pub synthetic: bool,
+    /// Every configuration, in the order run.
+    pub results: Vec<BenchResult>,
+    /// The recommended configuration.
+    pub recommended: BenchResult,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Sample: {} ({})",
+            Size(self.sample_bytes as f64),
+            match self.synthetic {
+                true => "synthetic",
+                false => "file",
+            }
+        )?;
+        writeln!(
+            f,
+            "{:>8} {:>10} {:>10} {:>14}",
+            "threads", "chunk", "MB/s", "tokens/s"
+        )?;
+        for result in &self.results {
+            let marker = match result == &self.recommended {
+                true => "  *",
+                false => "",
+            };
+            writeln!(
+                f,
+                "{:>8} {:>10} {:>10.1} {:>14.0}{marker}",
+                result.threads,
+                chunk_size_arg(result.chunk_size_bytes),
+                result.mb_per_sec,
+                result.tokens_per_sec
+            )?;
+        }
+        writeln!(
+            f,
+            "Recommended: --threads {} --chunksize {}",
+            self.recommended.threads,
+            chunk_size_arg(self.recommended.chunk_size_bytes)
+        )
+    }
+}
+
+/// Renders a chunk size the way `--chunksize` accepts it.
+fn chunk_size_arg(bytes: usize) -> String {
+    match bytes {
+        bytes if bytes % (1 << 20) == 0 => format!("{}MB", bytes >> 20),
+        bytes if bytes % (1 << 10) == 0 => format!("{}KB", bytes >> 10),
+        bytes => bytes.to_string(),
+    }
+}
+
+/// Tokenizes `input` with the strategy `config` selects once for every combination of
+/// `threads` and `chunk_sizes`, and recommends the fastest. Among configurations within
+/// [`RECOMMENDATION_TOLERANCE`] of the fastest, the one with the fewest threads and then the
+/// smallest chunks is recommended, since it leaves more of the machine to everything else.
+///
+/// Chunk sizes are clamped as a run would clamp them, and sizes that clamp to the same value
+/// are only run once.
+pub async fn bench(
+    config: &CoreConfig,
+    input: &BenchInput,
+    threads: &[usize],
+    chunk_sizes: &[usize],
+) -> Result<BenchReport, BltError> {
+    let strategy = framed_strategy(config)?;
+    validate_config(config)?;
+    if threads.is_empty() || chunk_sizes.is_empty() {
+        return Err(BltError::Config(
+            "A benchmark needs at least one thread count and one chunk size".to_string(),
+        ));
+    }
+    let (sample, synthetic) = match input {
+        BenchInput::Synthetic(bytes) => (synthetic_text(*bytes), true),
+        BenchInput::Sample(path, max_bytes) => {
+            let mut sample = Vec::new();
+            std::fs::File::open(path)?
+                .take(*max_bytes as u64)
+                .read_to_end(&mut sample)?;
+            (sample, false)
+        }
+    };
+    if sample.is_empty() {
+        return Err(BltError::Config(
+            "The benchmark sample is empty".to_string(),
+        ));
+    }
+    let mut effective_sizes = Vec::new();
+    for &size in chunk_sizes {
+        let config = CoreConfig {
+            cli_chunk_size: Some(size),
+            ..config.clone()
+        };
+        let size = chunking::get_effective_chunk_size(&config);
+        if !effective_sizes.contains(&size) {
+            effective_sizes.push(size);
+        }
+    }
+    let chunk_sizes = effective_sizes;
+
+    // Warms the caches and the allocator, so the first configuration is not penalized.
+    run_once(&sample, threads[0], chunk_sizes[0], &strategy, config).await?;
+    let mut results = Vec::new();
+    for &threads in threads {
+        for &chunk_size in &chunk_sizes {
+            let (secs, output_bytes) =
+                run_once(&sample, threads, chunk_size, &strategy, config).await?;
+            let secs = secs.max(f64::MIN_POSITIVE);
+            let result = BenchResult {
+                threads,
+                chunk_size_bytes: chunk_size,
+                secs,
+                mb_per_sec: sample.len() as f64 / secs / 1e6,
+                tokens_per_sec: (output_bytes / token_width(config)) as f64 / secs,
+            };
+            debug!(?result, "Benchmarked configuration");
+            results.push(result);
+        }
+    }
+    let recommended = recommend(&results).clone();
+    info!(
+        threads = recommended.threads,
+        chunk_size = recommended.chunk_size_bytes,
+        mb_per_sec = recommended.mb_per_sec,
+        "Benchmark finished"
+    );
+    Ok(BenchReport {
+        sample_bytes: sample.len() as u64,
+        synthetic,
+        results,
+        recommended,
+    })
+}
+
+/// The configuration with the fewest threads, then the smallest chunks, among those within
+/// [`RECOMMENDATION_TOLERANCE`] of the fastest.
+fn recommend(results: &[BenchResult]) -> &BenchResult {
+    let fastest = results
+        .iter()
+        .map(|result| result.mb_per_sec)
+        .fold(0.0, f64::max);
+    results
+        .iter()
+        .filter(|result| result.mb_per_sec >= fastest * (1.0 - RECOMMENDATION_TOLERANCE))
+        .min_by_key(|result| (result.threads, result.chunk_size_bytes))
+        .expect("the fastest configuration is within tolerance of itself")
+}
+
+/// Tokenizes `sample` once, returning the seconds taken and the token bytes produced.
+async fn run_once(
+    sample: &[u8],
+    threads: usize,
+    chunk_size: usize,
+    strategy: &Arc<dyn TokenizationStrategy>,
+    config: &CoreConfig,
+) -> io::Result<(f64, u64)> {
+    let mut map = MmapMut::map_anon(sample.len())?;
+    map.copy_from_slice(sample);
+    let source = InputSource::Mmap(map.make_read_only()?);
+    let scheduler: Arc<dyn Scheduler> = Arc::new(FifoScheduler::new(threads, None));
+    let mut sink: OutputWriter = Box::new(tokio::io::sink());
+    let mut output = PipelineOutput::new(&mut sink, None, None);
+    let started = Instant::now();
+    pipeline::run(
+        source,
+        &mut output,
+        chunk_size,
+        config.deterministic,
+        config.records.as_ref(),
+        &scheduler,
+        strategy.clone(),
+    )
+    .await?;
+    let secs = started.elapsed().as_secs_f64();
+    Ok((secs, output.into_summary().output_bytes))
+}
+
+/// Generates `len` bytes of lowercase words, spaces, and line breaks from a fixed seed, so
+/// every run and every machine tokenizes the same text.
+fn synthetic_text(len: usize) -> Vec<u8> {
+    const WORDS: [&str; 32] = [
+        "the", "of", "and", "to", "in", "is", "that", "for", "it", "as", "was", "with", "be", "by",
+        "on", "not", "he", "this", "are", "or", "his", "from", "at", "which", "but", "have", "an",
+        "had", "they", "you", "were", "their",
+    ];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut text = Vec::with_capacity(len + 8);
+    while text.len() < len {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        text.extend_from_slice(WORDS[(state % WORDS.len() as u64) as usize].as_bytes());
+        text.push(match state >> 60 {
+            0 => b'\n',
+            _ => b' ',
+        });
+    }
+    text.truncate(len);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sweeps_every_configuration_and_recommends_one() -> io::Result<()> {
+        let config = CoreConfig::default();
+        let input = BenchInput::Synthetic(1 << 20);
+        // 100 KB clamps to the 256 KB minimum, so it is only run once.
+        let report = bench(&config, &input, &[1, 2], &[100 << 10, 256 << 10, 1 << 20]).await?;
+        assert!(report.synthetic);
+        assert_eq!(report.sample_bytes, 1 << 20);
+        let swept: Vec<(usize, usize)> = report
+            .results
+            .iter()
+            .map(|r| (r.threads, r.chunk_size_bytes))
+            .collect();
+        assert_eq!(
+            swept,
+            [(1, 256 << 10), (1, 1 << 20), (2, 256 << 10), (2, 1 << 20)]
+        );
+        assert!(report.results.contains(&report.recommended));
+        assert!(report.to_string().contains("Recommended: --threads"));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sample.txt");
+        std::fs::write(&path, "hello world ".repeat(10_000))?;
+        let report = bench(&config, &BenchInput::Sample(path, 1000), &[1], &[256 << 10]).await?;
+        assert!(!report.synthetic);
+        assert_eq!(report.sample_bytes, 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recommends_fewest_threads_within_tolerance() {
+        let result = |threads, chunk_size_bytes, mb_per_sec| BenchResult {
+            threads,
+            chunk_size_bytes,
+            secs: 1.0,
+            mb_per_sec,
+            tokens_per_sec: 0.0,
+        };
+        let results = [
+            result(1, 1 << 20, 100.0),
+            result(4, 1 << 20, 390.0),
+            result(8, 256 << 10, 400.0),
+            result(8, 1 << 20, 380.0),
+        ];
+        assert_eq!(recommend(&results), &results[1]);
+        assert_eq!(chunk_size_arg(256 << 10), "256KB");
+        assert_eq!(chunk_size_arg(16 << 20), "16MB");
+        assert_eq!(synthetic_text(1000), synthetic_text(1000));
+    }
+}
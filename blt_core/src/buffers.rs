@@ -0,0 +1,171 @@
+//! A pool of reusable byte buffers for chunk inputs and tokenized results.
+//!
+//! Every chunk of a streamed input needs a buffer to read into, and every strategy produces a
+//! fresh output buffer per chunk, sized by its [`estimated_expansion`]. At high thread counts allocating and freeing these buffers
+//! puts noticeable pressure on the allocator, so the pipeline and the built-in strategies
+//! take them from the process-wide [`shared`] pool and the pipeline gives them back once a
+//! chunk is written. Buffers keep their capacity, so after the first few chunks of a run
+//! taking one rarely allocates.
+//!
+//! The pool holds a bounded number of buffers; buffers given back to a full pool are freed.
+//!
+//! [`estimated_expansion`]: crate::tokenizer::TokenizationStrategy::estimated_expansion
+
+use crate::tokenizer::TokenizationStrategy;
+use bytes::Bytes;
+use std::sync::{Mutex, OnceLock};
+
+/// The fewest buffers the [`shared`] pool holds, whatever the number of cores.
+const MIN_SHARED_BUFFERS: usize = 16;
+
+/// A bounded free list of byte buffers, shared between tasks.
+#[derive(Debug)]
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// A pool that holds on to at most `max_buffers` buffers.
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            free: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+        }
+    }
+
+    /// Returns an empty buffer with room for at least `capacity` bytes, reusing a pooled
+    /// buffer when there is one.
+    ///
+    /// A buffer already large enough is preferred; otherwise the largest one is grown.
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let reused = {
+            let mut free = self.free.lock().expect("buffer pool lock poisoned");
+            match free.iter().position(|buffer| buffer.capacity() >= capacity) {
+                Some(index) => Some(free.swap_remove(index)),
+                None => free.pop(),
+            }
+        };
+        match reused {
+            Some(mut buffer) => {
+                buffer.reserve(capacity);
+                buffer
+            }
+            None => Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns an empty buffer for the tokens `strategy` produces from `input_len` bytes, with
+    /// room for the most it can produce, so the buffer never grows while it is filled.
+    pub fn take_output(&self, strategy: &dyn TokenizationStrategy, input_len: usize) -> Vec<u8> {
+        let max = strategy.estimated_expansion(input_len as u64).max;
+        self.take(usize::try_from(max).unwrap_or(usize::MAX))
+    }
+
+    /// Hands `buffer` back for reuse. Its contents are discarded.
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 {
+            return;
+        }
+        buffer.clear();
+        let mut free = self.free.lock().expect("buffer pool lock poisoned");
+        if free.len() < self.max_buffers {
+            // Keep large buffers at the end, where `take` falls back to.
+            let at = free.partition_point(|pooled| pooled.capacity() <= buffer.capacity());
+            free.insert(at, buffer);
+        }
+    }
+
+    /// Hands back the buffer behind `bytes`, if nothing else shares it and it was not borrowed
+    /// from elsewhere, such as a memory map.
+    pub fn give_bytes(&self, bytes: Bytes) {
+        if let Ok(buffer) = bytes.try_into_mut() {
+            self.give(buffer.into());
+        }
+    }
+
+    /// The number of buffers waiting to be reused.
+    pub fn len(&self) -> usize {
+        self.free.lock().expect("buffer pool lock poisoned").len()
+    }
+
+    /// Returns `true` when no buffers are waiting to be reused.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The pool shared by the pipeline and the built-in strategies, holding up to four buffers
+/// per core: roughly an input and a result buffer for every chunk in flight, plus as many
+/// results waiting to be written.
+pub fn shared() -> &'static BufferPool {
+    static SHARED: OnceLock<BufferPool> = OnceLock::new();
+    SHARED.get_or_init(|| BufferPool::new((num_cpus::get() * 4).max(MIN_SHARED_BUFFERS)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuses_buffers() {
+        let pool = BufferPool::new(2);
+        let mut buffer = pool.take(64);
+        buffer.extend_from_slice(b"abc");
+        let address = buffer.as_ptr();
+        pool.give(buffer);
+        assert_eq!(pool.len(), 1);
+
+        let reused = pool.take(32);
+        assert!(reused.is_empty());
+        assert!(reused.capacity() >= 64);
+        assert_eq!(reused.as_ptr(), address);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_prefers_buffers_large_enough() {
+        let pool = BufferPool::new(4);
+        pool.give(Vec::with_capacity(16));
+        pool.give(Vec::with_capacity(128));
+        pool.give(Vec::with_capacity(32));
+        assert!(pool.take(100).capacity() >= 128);
+        // Nothing fits, so the largest remaining buffer is grown.
+        let grown = pool.take(1000);
+        assert!(grown.capacity() >= 1000);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.take(0).capacity(), 16);
+    }
+
+    #[test]
+    fn test_sizes_output_by_expansion_estimate() {
+        use crate::tokenizer::{BasicTokenizationStrategy, PassthroughStrategy};
+        let pool = BufferPool::new(4);
+        assert!(pool.take_output(&BasicTokenizationStrategy, 100).capacity() >= 200);
+        let passthrough = pool.take_output(&PassthroughStrategy, 100);
+        assert!((100..200).contains(&passthrough.capacity()));
+    }
+
+    #[test]
+    fn test_gives_back_unshared_bytes() {
+        let pool = BufferPool::new(4);
+        let bytes = Bytes::from(vec![1; 8]);
+        let shared = bytes.clone();
+        pool.give_bytes(bytes);
+        assert!(pool.is_empty());
+        pool.give_bytes(shared);
+        assert_eq!(pool.len(), 1);
+
+        pool.give_bytes(Bytes::from_static(b"static"));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let pool = BufferPool::new(1);
+        pool.give(vec![1; 8]);
+        pool.give(vec![2; 8]);
+        pool.give(Vec::new());
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -20,14 +20,21 @@ const DEFAULT_MAX_CHUNK_SIZE_BYTES: usize = 16 * 1024 * 1024; // 16MB
 const ABSOLUTE_MIN_CHUNK_SIZE: usize = 256 * 1024; // 256KB, absolute floor
 const ABSOLUTE_MAX_CHUNK_SIZE: usize = 128 * 1024 * 1024; // 128MB, absolute ceiling for auto-calc
 
+// Deterministic runs use a fixed size so chunk boundaries do not vary with RAM or thread count.
+const DETERMINISTIC_CHUNK_SIZE_BYTES: usize = DEFAULT_MAX_CHUNK_SIZE_BYTES;
+
 /// Determines the effective chunk size to use for processing.
 /// If `config.cli_chunk_size` is Some, it's used directly (respecting absolute min/max).
-/// Otherwise, dynamically calculates based on system RAM and number of threads.
+/// Otherwise, deterministic runs use a fixed size, and other runs calculate one from system
+/// RAM and the number of threads.
 pub(crate) fn get_effective_chunk_size(config: &CoreConfig) -> usize {
     if let Some(cli_size) = config.cli_chunk_size {
         // User specified a chunk size, use that, but clamp it reasonably.
         return cli_size.clamp(ABSOLUTE_MIN_CHUNK_SIZE, ABSOLUTE_MAX_CHUNK_SIZE);
     }
+    if config.deterministic {
+        return DETERMINISTIC_CHUNK_SIZE_BYTES;
+    }
 
     // Dynamic calculation based on system resources
     let mut sys = System::new_all();
@@ -47,7 +54,7 @@ pub(crate) fn get_effective_chunk_size(config: &CoreConfig) -> usize {
     // So, each "active" chunk might need 2-3x its size in RAM across stages.
     // Let's aim for each thread to comfortably handle one chunk in its pipeline stage.
     // A more conservative approach: RAM per thread / buffer_factor (e.g. 2 or 3)
-    let ram_per_thread_budget = usable_ram_for_buffers / (config.num_threads as u64);
+    let ram_per_thread_budget = usable_ram_for_buffers / (config.max_threads() as u64);
 
     // Tentative chunk size based on RAM per thread.
     // Let's use a buffer factor of, say, 4 to be conservative, meaning a chunk
@@ -81,15 +88,10 @@ mod tests {
         mem_cap_percent: u8,
     ) -> CoreConfig {
         CoreConfig {
-            input: None,
-            output: None,
-            merges_file: None,
-            content_type: None,
             num_threads,
             cli_chunk_size,
             mem_cap_percent,
-            bpe_data: None,
-            passthrough_mode: false,
+            ..CoreConfig::default()
         }
     }
 
@@ -112,6 +114,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_effective_chunk_size_deterministic() {
+        for (threads, mem_cap) in [(1, 80), (128, 1)] {
+            let config = create_test_config(None, threads, mem_cap).with_deterministic(true);
+            assert_eq!(
+                get_effective_chunk_size(&config),
+                DETERMINISTIC_CHUNK_SIZE_BYTES
+            );
+        }
+    }
+
     #[test]
     fn test_get_effective_chunk_size_dynamic() {
         // This test is environment-dependent (relies on actual system RAM).
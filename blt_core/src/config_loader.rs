@@ -5,52 +5,204 @@
 //!
 //! It is not intended for direct use by external crates.
 
+use crate::error::BltError;
+use crate::snapshot;
+use crate::special_tokens;
 use crate::BpeMerges; // Using the type alias from lib.rs
                       // use std::collections::HashMap; // Unused here as BpeMerges is from lib.rs
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::path::Path;
 
-pub(crate) fn load_bpe_merges_from_path(path: &Path) -> io::Result<BpeMerges> {
-    let file = File::open(path)?;
+/// The token ID given to the first merge unless another base is configured.
+pub const DEFAULT_MERGE_ID_BASE: u16 = 256;
+
+/// The most merges a file may contain before merge IDs reach the reserved special-token range.
+pub const MAX_MERGES: usize = max_merges(DEFAULT_MERGE_ID_BASE);
+
+/// The most merges that fit between `base` and the reserved special-token range.
+pub const fn max_merges(base: u16) -> usize {
+    special_tokens::RESERVED_RANGE.start().saturating_sub(base) as usize
+}
+
+/// Loads a merges file: one `byte1 byte2` pair per line, assigned token IDs from 256 upwards
+/// in file order. Blank lines and lines starting with `#` are ignored.
+///
+/// Files with more than [`MAX_MERGES`] merges are rejected, since further IDs would collide
+/// with special tokens. A [vocabulary snapshot](crate::snapshot) is restored as is.
+///
+/// Fails with [`BltError::MergesParse`] if the file is malformed, or [`BltError::Io`] if it
+/// cannot be read.
+pub fn load_bpe_merges_from_path(path: &Path) -> Result<BpeMerges, BltError> {
+    load_bpe_merges_with_base(path, DEFAULT_MERGE_ID_BASE)
+}
+
+/// Loads a merges file like [`load_bpe_merges_from_path`], assigning token IDs from `base`
+/// upwards instead of 256.
+///
+/// A higher base leaves room below the merges, for byte tokens moved to 256-511 or a block
+/// of extra special tokens. Bases inside the byte values are rejected. The merges of a
+/// snapshot are moved from the base they were recorded with.
+pub fn load_bpe_merges_with_base(path: &Path, base: u16) -> Result<BpeMerges, BltError> {
+    if base < DEFAULT_MERGE_ID_BASE {
+        return Err(BltError::Config(format!(
+            "Merge ID base {base} overlaps the byte values 0-255"
+        )));
+    }
+    let invalid = |line: Option<usize>, message: String| BltError::MergesParse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+    let snapshot = snapshot::read_if_snapshot(path).map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidData => invalid(None, e.to_string()),
+        _ => BltError::Io(e),
+    })?;
+    if let Some(snapshot) = snapshot {
+        if snapshot.base == base {
+            return Ok(snapshot.merges);
+        }
+        return Ok(rebase_merges(&snapshot.merges, snapshot.base, base));
+    }
+    let file = File::open(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to open merges file {}: {e}", path.display()),
+        )
+    })?;
     let reader = BufReader::new(file);
-    let mut merges = BpeMerges::new();
-    let mut vocab_size = 256u16; // Start new tokens after byte values
+    let mut merges = BpeMerges::default();
+    let mut vocab_size = base;
 
-    for line in reader.lines() {
+    for (index, line) in reader.lines().enumerate() {
         let line = line?;
+        let number = Some(index + 1);
         if line.starts_with('#') || line.is_empty() {
             continue;
         }
         let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.len() == 2 {
             let byte1 = parts[0].parse::<u8>().map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Failed to parse first byte value: {e} in line '{line}'"),
+                invalid(
+                    number,
+                    format!("Failed to parse first byte value: {e} in '{line}'"),
                 )
             })?;
             let byte2 = parts[1].parse::<u8>().map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Failed to parse second byte value: {e} in line '{line}'",),
+                invalid(
+                    number,
+                    format!("Failed to parse second byte value: {e} in '{line}'"),
                 )
             })?;
+            if special_tokens::is_reserved(vocab_size) {
+                return Err(invalid(
+                    number,
+                    format!(
+                        "Merge rule '{line}' would be assigned token {vocab_size:#06x}, which is reserved for special tokens; merges files may contain at most {} merges from base {base}",
+                        max_merges(base)
+                    ),
+                ));
+            }
             merges.insert((byte1 as u16, byte2 as u16), vocab_size);
             vocab_size += 1;
         } else {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid merge rule format in line: '{line}'. Expected two numbers separated by space.")));
+            return Err(invalid(
+                number,
+                format!(
+                    "Invalid merge rule format: '{line}'. Expected two numbers separated by space."
+                ),
+            ));
         }
     }
     Ok(merges)
 }
 
+/// Moves the IDs of merges assigned from `from` upwards so they start at `to` instead,
+/// keeping their order and spacing.
+///
+/// IDs moved out of the `u16` range are clamped to its ends, where [`validate_merges`]
+/// rejects them.
+pub fn rebase_merges(merges: &BpeMerges, from: u16, to: u16) -> BpeMerges {
+    let shift = to as i32 - from as i32;
+    merges
+        .iter()
+        .map(|(&pair, &token)| {
+            (
+                pair,
+                (token as i32 + shift).clamp(0, u16::MAX as i32) as u16,
+            )
+        })
+        .collect()
+}
+
+/// Checks that every merge in `merges` produces a token outside the byte range, the byte
+/// tokens shifted by `byte_offset`, and the reserved special-token range.
+///
+/// Merges loaded with [`load_bpe_merges_from_path`] always pass with a byte offset of 0;
+/// this guards tables built by other means, and layouts moving byte tokens to 256-511, before
+/// they are used for tokenization.
+pub fn validate_merges(merges: &BpeMerges, byte_offset: u16) -> io::Result<()> {
+    let byte_tokens = byte_offset as u32..byte_offset as u32 + 256;
+    let reason = |token: u16| {
+        if token < 256 {
+            Some("collides with a byte value".to_string())
+        } else if byte_tokens.contains(&(token as u32)) {
+            Some(format!(
+                "collides with the byte tokens at offset {byte_offset}"
+            ))
+        } else if special_tokens::is_reserved(token) {
+            Some("is reserved for special tokens".to_string())
+        } else {
+            None
+        }
+    };
+    let mut invalid: Vec<(&(u16, u16), &u16)> = merges
+        .iter()
+        .filter(|&(_, &token)| reason(token).is_some())
+        .collect();
+    invalid.sort_unstable_by_key(|&(pair, token)| (*token, *pair));
+    match invalid.first() {
+        None => Ok(()),
+        Some(&(&(first, second), &token)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Merge ({first}, {second}) produces token {token:#06x}, which {} ({} invalid merge(s) in total)",
+                reason(token).expect("invalid merges have a reason"),
+                invalid.len()
+            ),
+        )),
+    }
+}
+
+/// Computes a stable fingerprint of a merges table, independent of map iteration order.
+///
+/// The fingerprint is [`merges_hash`] rendered as 16 lowercase hex digits.
+pub fn merges_fingerprint(merges: &BpeMerges) -> String {
+    format!("{:016x}", merges_hash(merges))
+}
+
+/// Computes a stable 64-bit FNV-1a hash over the merges sorted by their resulting token.
+pub fn merges_hash(merges: &BpeMerges) -> u64 {
+    let mut entries: Vec<(&(u16, u16), &u16)> = merges.iter().collect();
+    entries.sort_unstable_by_key(|&(pair, token)| (*token, *pair));
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for ((first, second), token) in entries {
+        for value in [*first, *second, *token] {
+            for byte in value.to_be_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        }
+    }
+    hash
+}
+
 // Other configuration loading functions can be added here later (e.g., for patchers).
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -89,7 +241,7 @@ mod tests {
     fn test_load_bpe_merges_only_comments_or_empty_lines() -> io::Result<()> {
         let mut file = NamedTempFile::new()?;
         writeln!(file, "# comment 1")?;
-        writeln!(file, "")?; // Empty line
+        writeln!(file)?; // Empty line
         writeln!(file, "# comment 2")?;
         file.flush()?;
 
@@ -137,6 +289,7 @@ mod tests {
         if let Err(e) = result {
             assert_eq!(e.kind(), io::ErrorKind::InvalidData);
             assert!(e.to_string().contains("Failed to parse second byte value"));
+            assert!(matches!(e, BltError::MergesParse { line: Some(1), .. }));
         }
     }
 
@@ -161,8 +314,19 @@ mod tests {
         assert!(result.is_err());
         if let Err(e) = result {
             assert_eq!(e.kind(), io::ErrorKind::NotFound);
+            assert!(matches!(e, BltError::Io(_)));
         }
     }
+    #[test]
+    fn test_merges_fingerprint_is_order_independent() {
+        let a = create_merges_map(vec![((97, 98), 256), ((99, 100), 257)]);
+        let b = create_merges_map(vec![((99, 100), 257), ((97, 98), 256)]);
+        let c = create_merges_map(vec![((97, 98), 257), ((99, 100), 256)]);
+        assert_eq!(merges_fingerprint(&a), merges_fingerprint(&b));
+        assert_ne!(merges_fingerprint(&a), merges_fingerprint(&c));
+        assert_eq!(merges_fingerprint(&a).len(), 16);
+    }
+
     #[test]
     fn test_vocab_size_increment() -> io::Result<()> {
         let mut file = NamedTempFile::new()?;
@@ -177,7 +341,7 @@ mod tests {
         file.flush()?;
 
         let merges = load_bpe_merges_from_path(file.path())?;
-        let mut expected_merges = HashMap::new();
+        let mut expected_merges = BpeMerges::default();
         // The vocab_size for "1 2" will be the one from its last appearance if duplicates map to new IDs.
         // However, the function uses a simple incrementing vocab_size for each valid line processed.
         // So, if "1 2" appears twice, it will be inserted twice with different vocab_ids if we didn't use a HashMap.
@@ -200,4 +364,53 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_load_bpe_merges_rejects_reserved_ids() {
+        let mut file = NamedTempFile::new().unwrap();
+        for _ in 0..=MAX_MERGES {
+            writeln!(file, "97 98").unwrap();
+        }
+        file.flush().unwrap();
+
+        let err = load_bpe_merges_from_path(file.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("reserved for special tokens"));
+    }
+
+    #[test]
+    fn test_validate_merges() {
+        let valid = create_merges_map(vec![((97, 98), 256), ((99, 100), 0xFEFF)]);
+        assert!(validate_merges(&valid, 0).is_ok());
+        let content_type = create_merges_map(vec![((97, 98), 256), ((99, 100), 0xFF01)]);
+        let err = validate_merges(&content_type, 0).unwrap_err();
+        assert!(err.to_string().contains("0xff01"));
+        let byte = create_merges_map(vec![((97, 98), 97)]);
+        assert!(validate_merges(&byte, 0).is_err());
+        let err = validate_merges(&valid, 256).unwrap_err();
+        assert!(err.to_string().contains("byte tokens at offset 256"));
+        let shifted = create_merges_map(vec![((97, 98), 512)]);
+        assert!(validate_merges(&shifted, 256).is_ok());
+    }
+
+    #[test]
+    fn test_merge_id_base() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "97 98\n99 100").unwrap();
+        file.flush().unwrap();
+
+        let merges = load_bpe_merges_with_base(file.path(), 512).unwrap();
+        assert_eq!(merges.get(&(97, 98)), Some(&512));
+        assert_eq!(merges.get(&(99, 100)), Some(&513));
+        assert!(validate_merges(&merges, 256).is_ok());
+        let loaded = load_bpe_merges_from_path(file.path()).unwrap();
+        assert_eq!(rebase_merges(&loaded, DEFAULT_MERGE_ID_BASE, 512), merges);
+        assert!(matches!(
+            load_bpe_merges_with_base(file.path(), 200),
+            Err(BltError::Config(_))
+        ));
+
+        let err = load_bpe_merges_with_base(file.path(), 0xFEFF).unwrap_err();
+        assert!(err.to_string().contains("from base 65279"));
+    }
 }
@@ -0,0 +1,187 @@
+//! A registry of content types and their reserved token values.
+//!
+//! The registry always contains the built-in [`ContentType`]s. Additional types are
+//! registered with [`ContentTypeRegistry::register`] or loaded from a file with one
+//! `name token` pair per line, where `token` is decimal or `0x`-prefixed hex:
+//!
+//! ```text
+//! # name   token
+//! image    0xFF10
+//! depth    0xFF11
+//! ```
+//!
+//! Custom tokens must lie in [`special_tokens::RESERVED_RANGE`] and must not collide with
+//! another content type or with a structural token (see [`special_tokens::is_structural`]).
+
+use crate::{special_tokens, ContentType};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The content types known to a run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentTypeRegistry {
+    types: Vec<ContentType>,
+}
+
+impl Default for ContentTypeRegistry {
+    fn default() -> Self {
+        Self {
+            types: ContentType::ALL.to_vec(),
+        }
+    }
+}
+
+impl ContentTypeRegistry {
+    /// Loads the custom types listed in the file at `path` on top of the built-in ones.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut registry = Self::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let [name, token] = parts[..] else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid content type line '{line}'. Expected a name and a token."),
+                ));
+            };
+            registry.register(name, parse_token(token)?)?;
+        }
+        Ok(registry)
+    }
+
+    /// Registers a custom content type named `name` with the token value `token`.
+    pub fn register(&mut self, name: &str, token: u16) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let name = name.to_ascii_lowercase();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(invalid(format!(
+                "Invalid content type name '{name}': use letters, digits, '-' and '_'"
+            )));
+        }
+        if !special_tokens::is_reserved(token) {
+            return Err(invalid(format!(
+                "Content type '{name}' token {token:#06x} is outside the reserved range {:#06x}..={:#06x}",
+                special_tokens::RESERVED_RANGE.start(),
+                special_tokens::RESERVED_RANGE.end()
+            )));
+        }
+        if special_tokens::is_structural(token) {
+            return Err(invalid(format!(
+                "Content type '{name}' token {token:#06x} is a padding, separator, mask, log severity, video segment, or region marker token"
+            )));
+        }
+        if let Some(existing) = self
+            .types
+            .iter()
+            .find(|ct| ct.name() == name || ct.get_token_value() == token)
+        {
+            return Err(invalid(format!(
+                "Content type '{name}' ({token:#06x}) conflicts with '{}' ({:#06x})",
+                existing.name(),
+                existing.get_token_value()
+            )));
+        }
+        self.types.push(ContentType::Custom { name, token });
+        Ok(())
+    }
+
+    /// Looks up a content type by name, ignoring case.
+    pub fn lookup(&self, name: &str) -> Option<&ContentType> {
+        self.types
+            .iter()
+            .find(|ct| ct.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Like [`lookup`](Self::lookup), with an error listing the known names.
+    pub fn resolve(&self, name: &str) -> io::Result<ContentType> {
+        self.lookup(name).cloned().ok_or_else(|| {
+            let known: Vec<&str> = self.types.iter().map(ContentType::name).collect();
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown content type '{name}' (known: {})",
+                    known.join(", ")
+                ),
+            )
+        })
+    }
+
+    /// Iterates over every registered content type, built-in types first.
+    pub fn iter(&self) -> impl Iterator<Item = &ContentType> {
+        self.types.iter()
+    }
+}
+
+fn parse_token(value: &str) -> io::Result<u16> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid token value '{value}': {e}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_register_and_lookup() {
+        let mut registry = ContentTypeRegistry::default();
+        registry.register("Image", 0xFF10).unwrap();
+        assert_eq!(
+            registry.lookup("IMAGE"),
+            Some(&ContentType::Custom {
+                name: "image".into(),
+                token: 0xFF10
+            })
+        );
+        assert_eq!(registry.lookup("text"), Some(&ContentType::Text));
+        assert!(registry.resolve("depth").is_err());
+    }
+
+    #[test]
+    fn test_register_rejects_conflicts() {
+        let mut registry = ContentTypeRegistry::default();
+        assert!(registry.register("image", 0x1234).is_err());
+        assert!(registry
+            .register("image", special_tokens::SEPARATOR)
+            .is_err());
+        assert!(registry.register("image", 0xFF01).is_err());
+        assert!(registry.register("text", 0xFF10).is_err());
+        assert!(registry.register("two words", 0xFF10).is_err());
+        registry.register("image", 0xFF10).unwrap();
+        assert!(registry.register("depth", 0xFF10).is_err());
+    }
+
+    #[test]
+    fn test_from_path() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# custom modalities").unwrap();
+        writeln!(file, "image 0xFF10").unwrap();
+        writeln!(file, "depth 65297").unwrap();
+        let registry = ContentTypeRegistry::from_path(file.path()).unwrap();
+        let tokens: Vec<u16> = registry.iter().map(ContentType::get_token_value).collect();
+        assert_eq!(tokens, vec![0xFF01, 0xFF02, 0xFF03, 0xFF04, 0xFF10, 0xFF11]);
+
+        writeln!(file, "broken").unwrap();
+        assert!(ContentTypeRegistry::from_path(file.path()).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+//! Counting tokens without writing them.
+//!
+//! [`count_tokens`] runs the full pipeline, with the same strategy, pre-processing, and
+//! chunking as a real run, but discards the tokens instead of writing them, so billing and
+//! estimation workflows get exact counts without paying for the output path.
+
+use crate::{tokenize_documents, BltError, CoreConfig};
+use serde::Serialize;
+use std::fmt;
+use tracing::instrument;
+
+/// The tokens counted in one document.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DocumentCount {
+    /// The document's source.
+    pub source: String,
+    /// Input bytes read from the document.
+    pub input_bytes: u64,
+    /// Tokens the document produced.
+    pub tokens: u64,
+}
+
+/// The tokens counted across a run.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TokenCounts {
+    /// Every document, in input order.
+    pub documents: Vec<DocumentCount>,
+    /// Input bytes across all documents.
+    pub input_bytes: u64,
+    /// Tokens across all documents.
+    pub tokens: u64,
+    /// Wall-clock duration of the run.
+    pub wall_secs: f64,
+}
+
+impl fmt::Display for TokenCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>14} {:>14}  source", "tokens", "bytes")?;
+        for document in &self.documents {
+            writeln!(
+                f,
+                "{:>14} {:>14}  {}",
+                document.tokens, document.input_bytes, document.source
+            )?;
+        }
+        writeln!(
+            f,
+            "{:>14} {:>14}  total ({} documents)",
+            self.tokens,
+            self.input_bytes,
+            self.documents.len()
+        )
+    }
+}
+
+/// Tokenizes the input `config` describes and counts the tokens of every document, without
+/// writing them. `config` must not name an output; a manifest is still written if it names one.
+///
+/// Counts are of the tokens the tokenizer produces, before post-processing, like
+/// [`RunReport::tokens`](crate::RunReport::tokens).
+#[instrument(skip_all, fields(input = ?config.input, filelist = ?config.filelist))]
+pub async fn count_tokens(config: CoreConfig) -> Result<TokenCounts, BltError> {
+    let (report, entries) = tokenize_documents(config.with_discard_output(true)).await?;
+    Ok(TokenCounts {
+        documents: entries
+            .into_iter()
+            .map(|entry| DocumentCount {
+                source: entry.source,
+                input_bytes: entry.input_bytes,
+                tokens: entry.tokens,
+            })
+            .collect(),
+        input_bytes: report.input_bytes,
+        tokens: report.tokens,
+        wall_secs: report.wall_secs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[tokio::test]
+    async fn test_counts_every_document_without_writing() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a.txt"), "hello")?;
+        std::fs::write(dir.path().join("b.txt"), "hi")?;
+        let config = CoreConfig {
+            input: Some(dir.path().to_path_buf()),
+            ..CoreConfig::default()
+        };
+        let counts = count_tokens(config.clone()).await?;
+        let tokens: Vec<u64> = counts.documents.iter().map(|d| d.tokens).collect();
+        assert_eq!(tokens, [5, 2]);
+        assert_eq!((counts.tokens, counts.input_bytes), (7, 7));
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 2);
+
+        let config = config.with_index(Some(dir.path().join("index.json")));
+        let err = count_tokens(config).await.unwrap_err();
+        assert!(matches!(err, BltError::Config(_)), "{err}");
+        Ok(())
+    }
+}
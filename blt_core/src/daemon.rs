@@ -0,0 +1,231 @@
+//! Unix domain socket daemon mode.
+//!
+//! Loading a large merges table can take longer than tokenizing a small input. A daemon
+//! started with [`serve`] loads the vocabulary once and tokenizes requests from any number of
+//! short-lived clients over a Unix socket; a run configured with
+//! [`CoreConfig::with_daemon`] sends its chunks to the daemon through [`DaemonStrategy`]
+//! instead of loading merges itself.
+//!
+//! The protocol is a sequence of big-endian length-prefixed frames. On accepting a
+//! connection the daemon writes [`MAGIC`] and [`PROTOCOL_VERSION`]. Each request is an
+//! operation byte (`0` to tokenize, `1` to decode), a `u32` length, and the payload; each is
+//! answered with a status byte (`0` for success), a `u32` length, and the output or an error
+//! message. A connection carries any number of requests, one at a time.
+//!
+//! The daemon produces byte tokens without an offset; clients apply their own
+//! `byte_offset`. Anyone who can connect to the socket can use the daemon, so place it in a
+//! directory only its users can reach. Requires the `daemon` feature on a Unix platform.
+
+use crate::CoreConfig;
+use std::io;
+use std::path::PathBuf;
+
+#[cfg(all(unix, feature = "daemon"))]
+mod client;
+#[cfg(all(unix, feature = "daemon"))]
+pub use client::DaemonStrategy;
+
+/// The bytes a daemon greets each connection with.
+pub const MAGIC: &[u8; 4] = b"BLTD";
+/// The protocol version sent after [`MAGIC`].
+pub const PROTOCOL_VERSION: u8 = 1;
+/// The largest request or response payload, enough for the tokens of the largest chunk.
+pub const MAX_FRAME_BYTES: usize = 256 * 1024 * 1024;
+
+/// Settings for the daemon mode.
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    /// The path of the socket to listen on. A stale socket left by a daemon that exited
+    /// is replaced; a live one is an error.
+    pub socket: PathBuf,
+    /// The tokenizer configuration shared by all requests.
+    pub core: CoreConfig,
+}
+
+#[cfg(all(unix, feature = "daemon"))]
+pub(crate) use imp::daemon_strategy;
+#[cfg(all(unix, feature = "daemon"))]
+pub use imp::serve;
+
+#[cfg(all(unix, feature = "daemon"))]
+mod imp {
+    use super::*;
+    use crate::tokenizer::{TokenDecoder, TokenizationStrategy};
+    use std::future::Future;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::{UnixListener, UnixStream};
+    use tracing::{debug, info};
+
+    pub(super) const OP_TOKENIZE: u8 = 0;
+    pub(super) const OP_DECODE: u8 = 1;
+    pub(super) const STATUS_OK: u8 = 0;
+    const STATUS_ERROR: u8 = 1;
+
+    /// State shared by all connections.
+    pub(super) struct DaemonState {
+        strategy: Arc<dyn TokenizationStrategy>,
+        decoder: TokenDecoder,
+    }
+
+    impl DaemonState {
+        pub(super) fn new(core: &CoreConfig) -> io::Result<Self> {
+            let core = CoreConfig {
+                byte_offset: 0,
+                ..core.clone()
+            };
+            Ok(Self {
+                strategy: crate::offload::offload_cpu_bound(&core, crate::select_strategy(&core)?),
+                decoder: TokenDecoder::new(core.bpe_data.as_deref(), 0),
+            })
+        }
+    }
+
+    /// Runs the daemon until SIGTERM or Ctrl-C, then removes its socket.
+    ///
+    /// Connections still open at shutdown are closed without waiting for them.
+    pub async fn serve(config: DaemonConfig) -> io::Result<()> {
+        let state = Arc::new(DaemonState::new(&config.core)?);
+        let listener = bind(&config.socket).await?;
+        info!(socket = %config.socket.display(), "Daemon listening");
+        let result = accept_until(listener, state, wait_for_termination()).await;
+        let _ = std::fs::remove_file(&config.socket);
+        info!("Daemon stopped");
+        result
+    }
+
+    pub(crate) fn daemon_strategy(socket: &Path) -> io::Result<Arc<dyn TokenizationStrategy>> {
+        Ok(Arc::new(DaemonStrategy::new(socket.to_path_buf())))
+    }
+
+    /// Binds `socket`, replacing a stale socket file but not a live daemon's.
+    async fn bind(socket: &Path) -> io::Result<UnixListener> {
+        if socket.exists() {
+            if UnixStream::connect(socket).await.is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("A daemon is already listening on {}", socket.display()),
+                ));
+            }
+            std::fs::remove_file(socket)?;
+        }
+        UnixListener::bind(socket)
+    }
+
+    pub(super) async fn accept_until(
+        listener: UnixListener,
+        state: Arc<DaemonState>,
+        shutdown: impl Future<Output = ()>,
+    ) -> io::Result<()> {
+        let mut shutdown = std::pin::pin!(shutdown);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, &state).await {
+                            debug!(error = %e, "Daemon connection closed with an error");
+                        }
+                    });
+                }
+                _ = &mut shutdown => return Ok(()),
+            }
+        }
+    }
+
+    /// Answers requests on one connection until the client closes it.
+    pub(super) async fn handle_connection(
+        mut stream: impl AsyncRead + AsyncWrite + Unpin,
+        state: &DaemonState,
+    ) -> io::Result<()> {
+        stream.write_all(MAGIC).await?;
+        stream.write_u8(PROTOCOL_VERSION).await?;
+        stream.flush().await?;
+        loop {
+            let op = match stream.read_u8().await {
+                Ok(op) => op,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let payload = read_frame(&mut stream).await?;
+            let result = match op {
+                OP_TOKENIZE => state.strategy.process_chunk(&payload).await,
+                OP_DECODE => state.decoder.decode(&payload),
+                other => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown daemon operation {other}"),
+                )),
+            };
+            let (status, payload) = match result {
+                Ok(output) => (STATUS_OK, output),
+                Err(e) => (STATUS_ERROR, e.to_string().into_bytes()),
+            };
+            stream.write_u8(status).await?;
+            write_frame(&mut stream, &payload).await?;
+            stream.flush().await?;
+        }
+    }
+
+    pub(super) async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+        let len = stream.read_u32().await? as usize;
+        if len > MAX_FRAME_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Daemon frame of {len} bytes exceeds the {MAX_FRAME_BYTES}-byte limit"),
+            ));
+        }
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+
+    pub(super) async fn write_frame(
+        stream: &mut (impl AsyncWrite + Unpin),
+        payload: &[u8],
+    ) -> io::Result<()> {
+        if payload.len() > MAX_FRAME_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Daemon frame of {} bytes exceeds the {MAX_FRAME_BYTES}-byte limit",
+                    payload.len()
+                ),
+            ));
+        }
+        stream.write_u32(payload.len() as u32).await?;
+        stream.write_all(payload).await
+    }
+
+    async fn wait_for_termination() {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm =
+            signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+}
+
+/// Runs the daemon; unavailable in this build.
+#[cfg(not(all(unix, feature = "daemon")))]
+pub async fn serve(_config: DaemonConfig) -> io::Result<()> {
+    Err(missing_feature())
+}
+
+#[cfg(not(all(unix, feature = "daemon")))]
+pub(crate) fn daemon_strategy(
+    _socket: &std::path::Path,
+) -> io::Result<std::sync::Arc<dyn crate::tokenizer::TokenizationStrategy>> {
+    Err(missing_feature())
+}
+
+#[cfg(not(all(unix, feature = "daemon")))]
+fn missing_feature() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Daemon mode requires a Unix platform and blt built with the `daemon` feature",
+    )
+}
@@ -0,0 +1,161 @@
+//! The client side of the daemon protocol behind [`DaemonStrategy`].
+
+use super::imp::{read_frame, write_frame, OP_DECODE, OP_TOKENIZE, STATUS_OK};
+use super::{MAGIC, PROTOCOL_VERSION};
+use crate::tokenizer::TokenizationStrategy;
+use std::io;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+
+/// A tokenization strategy that sends every chunk to a running daemon.
+///
+/// Connections are opened as chunks need them and reused once a chunk's tokens are back.
+pub struct DaemonStrategy {
+    socket: PathBuf,
+    idle: Mutex<Vec<UnixStream>>,
+}
+
+impl DaemonStrategy {
+    /// Creates a strategy talking to the daemon listening on `socket`. Nothing is connected
+    /// until the first chunk.
+    pub fn new(socket: PathBuf) -> Self {
+        Self {
+            socket,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Expands token bytes back into the bytes they encode, using the daemon's vocabulary.
+    pub async fn decode(&self, token_bytes: &[u8]) -> io::Result<Vec<u8>> {
+        self.request(OP_DECODE, token_bytes).await
+    }
+
+    async fn connect(&self) -> io::Result<UnixStream> {
+        let mut stream = UnixStream::connect(&self.socket).await.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to connect to the daemon at {}: {e}",
+                    self.socket.display()
+                ),
+            )
+        })?;
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).await?;
+        if greeting[..4] != MAGIC[..] || greeting[4] != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is not a blt daemon speaking protocol version {PROTOCOL_VERSION}",
+                    self.socket.display()
+                ),
+            ));
+        }
+        Ok(stream)
+    }
+
+    async fn request(&self, op: u8, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let idle = self.idle.lock().await.pop();
+        let mut stream = match idle {
+            Some(stream) => stream,
+            None => self.connect().await?,
+        };
+        stream.write_u8(op).await?;
+        write_frame(&mut stream, payload).await?;
+        stream.flush().await?;
+        let status = stream.read_u8().await?;
+        let output = read_frame(&mut stream).await?;
+        self.idle.lock().await.push(stream);
+        match status {
+            STATUS_OK => Ok(output),
+            _ => Err(io::Error::other(format!(
+                "Daemon request failed: {}",
+                String::from_utf8_lossy(&output)
+            ))),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for DaemonStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        self.request(OP_TOKENIZE, chunk_data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::imp::{accept_until, handle_connection, DaemonState};
+    use super::*;
+    use crate::{BpeMerges, CoreConfig};
+    use std::sync::Arc;
+    use tokio::net::UnixListener;
+
+    fn bpe_state() -> Arc<DaemonState> {
+        let merges: BpeMerges = [((97, 98), 256)].into_iter().collect();
+        let core = CoreConfig {
+            bpe_data: Some(Arc::new(merges)),
+            byte_offset: 256,
+            ..CoreConfig::default()
+        };
+        Arc::new(DaemonState::new(&core).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_strategy_round_trips_through_daemon() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("blt.sock");
+        let listener = UnixListener::bind(&socket)?;
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let daemon = tokio::spawn(accept_until(listener, bpe_state(), async {
+            let _ = stopped.await;
+        }));
+
+        let strategy = DaemonStrategy::new(socket);
+        let (first, second) = tokio::join!(
+            strategy.process_chunk(b"abc"),
+            strategy.process_chunk(b"ab")
+        );
+        // Byte tokens come back unshifted; the client applies its own offset.
+        assert_eq!(first?, [1, 0, 0, 99]);
+        assert_eq!(second?, [1, 0]);
+        assert_eq!(strategy.decode(&[1, 0, 0, 99]).await?, b"abc");
+        let err = strategy.decode(&[1]).await.unwrap_err();
+        assert!(err.to_string().contains("Daemon request failed"));
+        // The connection stays usable after an error.
+        assert_eq!(strategy.process_chunk(b"").await?, b"");
+
+        stop.send(()).unwrap();
+        daemon.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unknown_operations_and_foreign_sockets() -> io::Result<()> {
+        let (mut client, server) = tokio::io::duplex(64);
+        let state = bpe_state();
+        let connection = tokio::spawn(async move { handle_connection(server, &state).await });
+        let mut greeting = [0u8; 5];
+        client.read_exact(&mut greeting).await?;
+        assert_eq!(&greeting, b"BLTD\x01");
+        client.write_all(&[7, 0, 0, 0, 0]).await?;
+        assert_eq!(client.read_u8().await?, 1);
+        drop(client);
+        connection.await.unwrap()?;
+
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("other.sock");
+        let listener = UnixListener::bind(&socket)?;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"HTTP/").await.unwrap();
+        });
+        let err = DaemonStrategy::new(socket)
+            .process_chunk(b"a")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+//! Document IDs recorded in manifests.
+//!
+//! Every manifest entry carries the document's ordinal in the run (`doc_id`), which says
+//! nothing about where the document came from. A [`DocumentIdSource`] other than
+//! [`DocumentIdSource::Ordinal`] also gives each entry an `id` taken from the document itself:
+//! its path, its content hash, a field of its first JSON line, or whatever a callback computes,
+//! so outputs can be joined back to the systems the documents came from.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The most bytes of a document's first line kept for [`DocumentIdSource::JsonField`] and
+/// callbacks; longer lines are cut off.
+pub const MAX_FIRST_LINE: usize = 1 << 20;
+
+/// Computes a document's ID from what is known about it once it is tokenized.
+pub type DocumentIdCallback = Arc<dyn Fn(&DocumentInfo<'_>) -> io::Result<String> + Send + Sync>;
+
+/// Where the `id` of each manifest entry comes from.
+#[derive(Clone, Default)]
+pub enum DocumentIdSource {
+    /// Documents are only numbered in output order, by `doc_id`; entries have no `id`.
+    #[default]
+    Ordinal,
+    /// The document's path relative to its input root, with `/` separators.
+    Path,
+    /// The document's content hash, as `"<algorithm>:<hex>"`.
+    ContentHash,
+    /// The named field of the JSON object on the document's first line, for documents that
+    /// are JSON records. A string is used as is and any other value as JSON text.
+    JsonField(String),
+    /// The result of a callback.
+    Callback(DocumentIdCallback),
+}
+
+/// What is known about a tokenized document when its ID is assigned.
+#[derive(Clone, Copy, Debug)]
+pub struct DocumentInfo<'a> {
+    /// The document's ordinal in the run, its `doc_id`.
+    pub ordinal: u64,
+    /// The document's source, as recorded in the manifest.
+    pub source: &'a str,
+    /// The document's path relative to its input root.
+    pub relative_path: &'a Path,
+    /// The document's content hash, as `"<algorithm>:<hex>"`.
+    pub content_hash: Option<&'a str>,
+    /// The document's first line after pre-processing, without its line break, cut off after
+    /// [`MAX_FIRST_LINE`] bytes. Only captured for JSON fields and callbacks.
+    pub first_line: Option<&'a [u8]>,
+}
+
+impl DocumentIdSource {
+    /// Whether IDs need the document's first line.
+    pub(crate) fn needs_first_line(&self) -> bool {
+        matches!(
+            self,
+            DocumentIdSource::JsonField(_) | DocumentIdSource::Callback(_)
+        )
+    }
+
+    /// Returns the ID of the document described by `info`, or `None` for ordinal numbering.
+    pub(crate) fn assign(&self, info: &DocumentInfo<'_>) -> io::Result<Option<String>> {
+        let missing = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Document {} has no {what} to take its ID from", info.source),
+            )
+        };
+        let id = match self {
+            DocumentIdSource::Ordinal => return Ok(None),
+            DocumentIdSource::Path => info
+                .relative_path
+                .iter()
+                .map(|component| component.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            DocumentIdSource::ContentHash => info
+                .content_hash
+                .ok_or_else(|| missing("content hash"))?
+                .to_string(),
+            DocumentIdSource::JsonField(field) => {
+                let line = info.first_line.ok_or_else(|| missing("first line"))?;
+                let record: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_slice(line)
+                        .map_err(|_| missing("JSON object on its first line"))?;
+                match record.get(field) {
+                    Some(serde_json::Value::String(id)) => id.clone(),
+                    Some(serde_json::Value::Null) | None => {
+                        return Err(missing(&format!("'{field}' field")))
+                    }
+                    Some(value) => value.to_string(),
+                }
+            }
+            DocumentIdSource::Callback(callback) => callback(info)?,
+        };
+        Ok(Some(id))
+    }
+}
+
+impl fmt::Debug for DocumentIdSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentIdSource::Ordinal => f.write_str("Ordinal"),
+            DocumentIdSource::Path => f.write_str("Path"),
+            DocumentIdSource::ContentHash => f.write_str("ContentHash"),
+            DocumentIdSource::JsonField(field) => f.debug_tuple("JsonField").field(field).finish(),
+            DocumentIdSource::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+impl FromStr for DocumentIdSource {
+    type Err = String;
+
+    /// Parses `ordinal`, `path`, `content-hash`, or `json-field:NAME`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("json-field", field)) if !field.is_empty() => {
+                Ok(DocumentIdSource::JsonField(field.to_string()))
+            }
+            _ => match s {
+                "ordinal" => Ok(DocumentIdSource::Ordinal),
+                "path" => Ok(DocumentIdSource::Path),
+                "content-hash" => Ok(DocumentIdSource::ContentHash),
+                _ => Err(format!(
+                    "Unknown document ID source '{s}', expected ordinal, path, content-hash, or json-field:NAME"
+                )),
+            },
+        }
+    }
+}
+
+/// Collects the first line of a document as its bytes go by.
+#[derive(Debug, Default)]
+pub(crate) struct FirstLine {
+    line: Vec<u8>,
+    complete: bool,
+}
+
+impl FirstLine {
+    /// Adds the next bytes of the document.
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        if self.complete {
+            return;
+        }
+        let end = bytes.iter().position(|&b| b == b'\n');
+        let room = MAX_FIRST_LINE - self.line.len();
+        let take = end.unwrap_or(bytes.len()).min(room);
+        self.line.extend_from_slice(&bytes[..take]);
+        self.complete = end.is_some() || self.line.len() == MAX_FIRST_LINE;
+    }
+
+    /// Returns the line, without its line break or a preceding carriage return.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+        self.line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info<'a>(first_line: Option<&'a [u8]>) -> DocumentInfo<'a> {
+        DocumentInfo {
+            ordinal: 4,
+            source: "/data/corpus/a/b.json",
+            relative_path: Path::new("a/b.json"),
+            content_hash: Some("sha256:abc"),
+            first_line,
+        }
+    }
+
+    #[test]
+    fn test_assigns_ids() -> io::Result<()> {
+        let line: &[u8] = br#"{"id": "doc-7", "n": 12, "text": "hello"}"#;
+        let assign = |source: &str| {
+            source
+                .parse::<DocumentIdSource>()
+                .unwrap()
+                .assign(&info(Some(line)))
+        };
+        assert_eq!(assign("ordinal")?, None);
+        assert_eq!(assign("path")?.as_deref(), Some("a/b.json"));
+        assert_eq!(assign("content-hash")?.as_deref(), Some("sha256:abc"));
+        assert_eq!(assign("json-field:id")?.as_deref(), Some("doc-7"));
+        assert_eq!(assign("json-field:n")?.as_deref(), Some("12"));
+        let error = assign("json-field:missing").unwrap_err();
+        assert!(error.to_string().contains("no 'missing' field"));
+
+        let callback = DocumentIdSource::Callback(Arc::new(|info| {
+            Ok(format!("{}#{}", info.source, info.ordinal))
+        }));
+        assert_eq!(
+            callback.assign(&info(None))?.as_deref(),
+            Some("/data/corpus/a/b.json#4")
+        );
+        assert!(DocumentIdSource::JsonField("id".to_string())
+            .assign(&info(Some(b"not json")))
+            .is_err());
+        assert!("json-field:".parse::<DocumentIdSource>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_line_spans_chunks() {
+        let mut first = FirstLine::default();
+        first.update(b"{\"id\":");
+        first.update(b" 1}\r\n{\"id\": 2}\n");
+        first.update(b"more");
+        assert_eq!(first.finish(), b"{\"id\": 1}");
+    }
+}
@@ -0,0 +1,250 @@
+//! Planning a run without tokenizing or writing anything.
+//!
+//! [`dry_run`] validates a configuration the way [`run_tokenizer`](crate::run_tokenizer)
+//! would, loads its strategy, and opens its inputs to plan their chunks. It then tokenizes the
+//! first few chunks as a sample and scales the output they produce to the whole input, so the
+//! estimate reflects the compression a vocabulary achieves rather than the 2x expansion of
+//! byte-level tokens alone. Estimates count token bytes as [`RunReport::output_bytes`] does:
+//! before post-processing, headers, and compression.
+//!
+//! [`RunReport::output_bytes`]: crate::RunReport::output_bytes
+
+use crate::io_handler::{self, InputSource};
+use crate::progress::Size;
+use crate::tokenizer::TokenizationStrategy;
+use crate::{chunking, framed_strategy, preprocess, token_width, validate_config};
+use crate::{BltError, CoreConfig};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use tokio::io::AsyncReadExt;
+use tracing::info;
+
+/// The number of chunks tokenized to measure the compression of the input.
+pub const SAMPLE_CHUNKS: usize = 4;
+
+/// The most input bytes sampled, however large the chunks are.
+pub const MAX_SAMPLE_BYTES: usize = 16 << 20;
+
+/// What a run would read and roughly how much it would write.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DryRunReport {
+    /// The number of documents the run would tokenize.
+    pub documents: u64,
+    /// Input bytes across all documents, if every size is known without reading them.
+    pub input_bytes: Option<u64>,
+    /// The chunk size the input would be split into, in bytes.
+    pub chunk_size_bytes: usize,
+    /// The number of chunks the input would be split into, if every document is mapped.
+    /// Record alignment can add a few.
+    pub chunks: Option<u64>,
+    /// The number of threads the pipeline is configured with.
+    pub threads: usize,
+    /// Input bytes tokenized as a sample, after pre-processing.
+    pub sample_bytes: u64,
+    /// Token bytes the sample produced.
+    pub sample_output_bytes: u64,
+    /// Token bytes per input byte: the sample's, or the strategy's own estimate when there
+    /// was nothing to sample.
+    pub expansion_ratio: f64,
+    /// The expected token bytes, if the input size is known.
+    pub estimated_output_bytes: Option<u64>,
+    /// The expected number of tokens, if the input size is known.
+    pub estimated_tokens: Option<u64>,
+    /// An upper bound on the token bytes, if the input size is known.
+    pub max_output_bytes: Option<u64>,
+}
+
+impl fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |bytes: Option<u64>| match bytes {
+            Some(bytes) => Size(bytes as f64).to_string(),
+            None => "unknown".to_string(),
+        };
+        writeln!(f, "Documents:         {}", self.documents)?;
+        writeln!(f, "Input:             {}", size(self.input_bytes))?;
+        writeln!(
+            f,
+            "Chunks:            {} x {}",
+            self.chunks
+                .map_or("?".to_string(), |chunks| chunks.to_string()),
+            Size(self.chunk_size_bytes as f64)
+        )?;
+        writeln!(f, "Threads:           {}", self.threads)?;
+        writeln!(
+            f,
+            "Sample:            {} -> {} ({:.3} token bytes per input byte)",
+            Size(self.sample_bytes as f64),
+            Size(self.sample_output_bytes as f64),
+            self.expansion_ratio
+        )?;
+        write!(
+            f,
+            "Estimated output:  {}",
+            size(self.estimated_output_bytes)
+        )?;
+        if let (Some(tokens), Some(max)) = (self.estimated_tokens, self.max_output_bytes) {
+            write!(f, " ({tokens} tokens), at most {}", Size(max as f64))?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Plans the run `config` describes and estimates its output, without writing anything.
+///
+/// Standard input is only read as far as the sample needs, and archives are read through to
+/// count their members.
+pub async fn dry_run(config: &CoreConfig) -> Result<DryRunReport, BltError> {
+    let strategy = framed_strategy(config)?;
+    let chunk_size = chunking::get_effective_chunk_size(config);
+    validate_config(config)?;
+    let mut documents = io_handler::setup_input(config)?;
+    let input_bytes = documents.total_bytes();
+    let mut report = DryRunReport {
+        documents: 0,
+        input_bytes,
+        chunk_size_bytes: chunk_size,
+        chunks: Some(0),
+        threads: config.num_threads,
+        sample_bytes: 0,
+        sample_output_bytes: 0,
+        expansion_ratio: 0.0,
+        estimated_output_bytes: None,
+        estimated_tokens: None,
+        max_output_bytes: None,
+    };
+    let mut sampled_chunks = 0;
+    while let Some(document) = documents.next().await? {
+        report.documents += 1;
+        report.chunks = match &document.source {
+            InputSource::Mmap(mmap) => report
+                .chunks
+                .map(|chunks| chunks + (mmap.len() as u64).div_ceil(chunk_size as u64)),
+            InputSource::Stdin(_) | InputSource::Reader(_) => None,
+        };
+        if sampled_chunks < SAMPLE_CHUNKS && report.sample_bytes < MAX_SAMPLE_BYTES as u64 {
+            let source = preprocess(config, document.source)?;
+            sampled_chunks +=
+                sample(source, chunk_size, sampled_chunks, &strategy, &mut report).await?;
+        }
+    }
+    report.expansion_ratio = match report.sample_bytes {
+        0 => strategy.estimated_expansion(1 << 20).ratio(1 << 20),
+        sampled => report.sample_output_bytes as f64 / sampled as f64,
+    };
+    if let Some(input_bytes) = input_bytes {
+        let expected = (input_bytes as f64 * report.expansion_ratio).round() as u64;
+        report.estimated_output_bytes = Some(expected);
+        report.estimated_tokens = Some(expected / token_width(config));
+        report.max_output_bytes = Some(strategy.estimated_expansion(input_bytes).max);
+    }
+    info!(
+        documents = report.documents,
+        sample_bytes = report.sample_bytes,
+        expansion_ratio = report.expansion_ratio,
+        "Dry run planned"
+    );
+    Ok(report)
+}
+
+/// Tokenizes chunks from the start of `source` into the sample until [`SAMPLE_CHUNKS`]
+/// chunks or [`MAX_SAMPLE_BYTES`] bytes are sampled, returning the number of chunks taken.
+async fn sample(
+    source: InputSource,
+    chunk_size: usize,
+    sampled_chunks: usize,
+    strategy: &std::sync::Arc<dyn TokenizationStrategy>,
+    report: &mut DryRunReport,
+) -> io::Result<usize> {
+    let mut taken = 0;
+    let mut offset = 0;
+    let mut reader = match source {
+        InputSource::Mmap(mmap) => {
+            while sampled_chunks + taken < SAMPLE_CHUNKS && offset < mmap.len() {
+                let len = sample_len(chunk_size, report).min(mmap.len() - offset);
+                if len == 0 {
+                    break;
+                }
+                tokenize_sample(&mmap[offset..offset + len], strategy, report).await?;
+                offset += len;
+                taken += 1;
+            }
+            return Ok(taken);
+        }
+        InputSource::Stdin(reader) | InputSource::Reader(reader) => reader,
+    };
+    while sampled_chunks + taken < SAMPLE_CHUNKS {
+        let len = sample_len(chunk_size, report);
+        if len == 0 {
+            break;
+        }
+        let mut chunk = Vec::with_capacity(len);
+        (&mut reader)
+            .take(len as u64)
+            .read_to_end(&mut chunk)
+            .await?;
+        if chunk.is_empty() {
+            break;
+        }
+        tokenize_sample(&chunk, strategy, report).await?;
+        taken += 1;
+    }
+    Ok(taken)
+}
+
+/// The length of the next sampled chunk: a whole chunk, unless the sample is nearly full.
+fn sample_len(chunk_size: usize, report: &DryRunReport) -> usize {
+    chunk_size.min(MAX_SAMPLE_BYTES.saturating_sub(report.sample_bytes as usize))
+}
+
+async fn tokenize_sample(
+    chunk: &[u8],
+    strategy: &std::sync::Arc<dyn TokenizationStrategy>,
+    report: &mut DryRunReport,
+) -> io::Result<()> {
+    let tokens = strategy.process_chunk(chunk).await?;
+    report.sample_bytes += chunk.len() as u64;
+    report.sample_output_bytes += tokens.len() as u64;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BpeMerges;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_plans_chunks_and_estimates_from_sample() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input)?;
+        std::fs::write(input.join("a.txt"), "ab".repeat(150_000))?;
+        std::fs::write(input.join("b.txt"), "ab".repeat(50_000))?;
+        let config = CoreConfig {
+            input: Some(input),
+            cli_chunk_size: Some(256 << 10),
+            ..CoreConfig::default()
+        };
+        let report = dry_run(&config).await?;
+        assert_eq!(report.documents, 2);
+        assert_eq!(report.input_bytes, Some(400_000));
+        assert_eq!(report.chunks, Some(3));
+        assert_eq!(report.expansion_ratio, 2.0);
+        assert_eq!(report.estimated_output_bytes, Some(800_000));
+        assert_eq!(report.estimated_tokens, Some(400_000));
+
+        // Merging every "ab" halves the output, and the estimate follows the sample.
+        let mut merges = BpeMerges::default();
+        merges.insert((u16::from(b'a'), u16::from(b'b')), 256);
+        let config = CoreConfig {
+            bpe_data: Some(Arc::new(merges)),
+            ..config
+        };
+        let report = dry_run(&config).await?;
+        assert_eq!(report.sample_bytes, 400_000);
+        assert_eq!(report.estimated_output_bytes, Some(400_000));
+        assert_eq!(report.max_output_bytes, Some(800_000));
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+//! Marking compressed and plain regions of a stream by byte entropy.
+//!
+//! Compressed or encrypted data looks like uniformly random bytes, close to 8 bits of entropy
+//! per byte, while text, code, and most uncompressed binary formats sit well below that. With
+//! an [`EntropyMarking`], each chunk of input is classified by the Shannon entropy of its
+//! bytes, and a [`special_tokens::COMPRESSED_REGION`] or [`special_tokens::PLAIN_REGION`]
+//! token is written before the first chunk of every run of chunks with the same class, so
+//! downstream filters can skip or down-weight compressed regions.
+//!
+//! Regions are as fine as chunks. A chunk of `n` bytes has at most `log2(n)` bits of entropy
+//! per byte, so chunks much smaller than 256 bytes always count as plain.
+
+use crate::special_tokens;
+use std::str::FromStr;
+
+/// Classifies chunks as compressed when their entropy reaches a threshold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntropyMarking {
+    /// The entropy, in bits per byte, at or above which a chunk counts as compressed.
+    pub threshold: f64,
+}
+
+impl EntropyMarking {
+    /// The threshold used when none is given.
+    pub const DEFAULT_THRESHOLD: f64 = 7.5;
+
+    /// Returns `true` if `chunk` looks compressed.
+    pub fn is_compressed(&self, chunk: &[u8]) -> bool {
+        shannon_entropy(chunk) >= self.threshold
+    }
+
+    /// The marker token for a chunk of the given class.
+    pub fn marker(compressed: bool) -> u16 {
+        match compressed {
+            true => special_tokens::COMPRESSED_REGION,
+            false => special_tokens::PLAIN_REGION,
+        }
+    }
+}
+
+impl Default for EntropyMarking {
+    fn default() -> Self {
+        Self {
+            threshold: Self::DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+impl FromStr for EntropyMarking {
+    type Err = String;
+
+    /// Parses a threshold in bits per byte, between 0 and 8.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f64>() {
+            Ok(threshold) if (0.0..=8.0).contains(&threshold) => Ok(Self { threshold }),
+            _ => Err(format!(
+                "Invalid entropy threshold '{s}'; expected bits per byte between 0 and 8"
+            )),
+        }
+    }
+}
+
+/// The Shannon entropy of `bytes` in bits per byte, from 0 (empty or one repeated byte) to 8.
+pub fn shannon_entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    let total = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy_separates_text_from_random_bytes() {
+        assert_eq!(shannon_entropy(b""), 0.0);
+        assert_eq!(shannon_entropy(b"aaaa"), 0.0);
+        assert_eq!(shannon_entropy(b"abab"), 1.0);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(shannon_entropy(&all_bytes), 8.0);
+
+        let marking = EntropyMarking::default();
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(100);
+        assert!(!marking.is_compressed(text.as_bytes()));
+        // A simple LCG is close enough to uniform for the classifier.
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect();
+        assert!(marking.is_compressed(&noise));
+
+        assert_eq!("7".parse::<EntropyMarking>().unwrap().threshold, 7.0);
+        assert!("9".parse::<EntropyMarking>().is_err());
+        assert!("high".parse::<EntropyMarking>().is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! The error [`run_tokenizer`](crate::run_tokenizer) and the merges loaders fail with.
+//!
+//! The rest of the crate works in [`io::Result`]. A failure that belongs to one of the
+//! [`BltError`] categories travels inside an [`io::Error`] carrying the `BltError`, as a
+//! cancellation carries a [`Cancelled`], and is recovered by the `From<io::Error>` conversion
+//! at the entry points; any other error is reported as [`BltError::Io`]. Converting back with
+//! `io::Error::from` keeps the message and the [kind](BltError::kind), so code working in
+//! `io::Result` can still use `?`.
+
+use crate::interrupt::Cancelled;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why a run or a merges file failed to load.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BltError {
+    /// The configuration is invalid, or combines options that cannot be used together.
+    Config(String),
+    /// A merges file or vocabulary snapshot could not be parsed.
+    MergesParse {
+        /// The file that failed to parse.
+        path: PathBuf,
+        /// The 1-based line the error is on, for merges files.
+        line: Option<usize>,
+        /// What is wrong with it.
+        message: String,
+    },
+    /// Reading an input, writing an output, or another system call failed.
+    Io(io::Error),
+    /// The pipeline failed outside the tokenization of a chunk: a chunk task panicked, or
+    /// the output did not match its expected hash.
+    Pipeline(String),
+    /// The tokenization strategy failed on a chunk, or produced tokens the run rejects.
+    Strategy(io::Error),
+    /// The run was cancelled before it completed.
+    Cancelled(Cancelled),
+}
+
+impl BltError {
+    /// The [`io::ErrorKind`] the error converts to.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            BltError::Config(_) => io::ErrorKind::InvalidInput,
+            BltError::MergesParse { .. } => io::ErrorKind::InvalidData,
+            BltError::Io(e) | BltError::Strategy(e) => e.kind(),
+            BltError::Pipeline(_) => io::ErrorKind::Other,
+            BltError::Cancelled(_) => io::ErrorKind::Interrupted,
+        }
+    }
+
+    /// Tags a chunk's failure as the strategy's, unless it is already categorized.
+    pub(crate) fn strategy(error: io::Error) -> io::Error {
+        match BltError::from(error) {
+            BltError::Io(e) => BltError::Strategy(e).into(),
+            categorized => categorized.into(),
+        }
+    }
+}
+
+impl fmt::Display for BltError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BltError::Config(message) | BltError::Pipeline(message) => f.write_str(message),
+            BltError::MergesParse {
+                path,
+                line: Some(line),
+                message,
+            } => write!(
+                f,
+                "Invalid merges file {}, line {line}: {message}",
+                path.display()
+            ),
+            BltError::MergesParse {
+                path,
+                line: None,
+                message,
+            } => write!(f, "Invalid merges file {}: {message}", path.display()),
+            BltError::Io(e) | BltError::Strategy(e) => e.fmt(f),
+            BltError::Cancelled(cancelled) => cancelled.fmt(f),
+        }
+    }
+}
+
+impl Error for BltError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            // Displayed as the wrapped error, so its source is theirs.
+            BltError::Io(e) | BltError::Strategy(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BltError {
+    fn from(error: io::Error) -> Self {
+        if error.get_ref().is_some_and(|inner| inner.is::<BltError>()) {
+            let inner = error.into_inner().expect("the error wraps a BltError");
+            return *inner.downcast().expect("the error wraps a BltError");
+        }
+        match Cancelled::from_error(&error) {
+            Some(cancelled) => BltError::Cancelled(cancelled.clone()),
+            None => BltError::Io(error),
+        }
+    }
+}
+
+impl From<Cancelled> for BltError {
+    fn from(cancelled: Cancelled) -> Self {
+        BltError::Cancelled(cancelled)
+    }
+}
+
+impl From<BltError> for io::Error {
+    fn from(error: BltError) -> Self {
+        match error {
+            BltError::Io(e) => e,
+            BltError::Cancelled(cancelled) => cancelled.into(),
+            error => io::Error::new(error.kind(), error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_through_io_errors() {
+        let error = io::Error::from(BltError::Config("bad option".to_string()));
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "bad option");
+        assert!(matches!(BltError::from(error), BltError::Config(m) if m == "bad option"));
+
+        let error = io::Error::from(Cancelled { documents: 2 });
+        assert!(matches!(
+            BltError::from(error),
+            BltError::Cancelled(Cancelled { documents: 2 })
+        ));
+
+        let error = BltError::from(io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert!(matches!(&error, BltError::Io(e) if e.kind() == io::ErrorKind::NotFound));
+        assert_eq!(io::Error::from(error).to_string(), "gone");
+    }
+
+    #[test]
+    fn test_tags_strategy_failures() {
+        let error = BltError::strategy(io::Error::other("bad chunk"));
+        assert!(matches!(BltError::from(error), BltError::Strategy(_)));
+        // Categorized failures keep their category.
+        let error = BltError::strategy(BltError::Pipeline("panicked".to_string()).into());
+        assert!(matches!(BltError::from(error), BltError::Pipeline(_)));
+    }
+
+    #[tokio::test]
+    async fn test_runs_fail_with_config_errors() {
+        let config = crate::CoreConfig {
+            previous_manifest: Some(PathBuf::from("previous.jsonl")),
+            ..crate::CoreConfig::default()
+        };
+        let error = crate::run_tokenizer(config).await.unwrap_err();
+        assert!(matches!(error, BltError::Config(_)), "{error:?}");
+    }
+
+    #[test]
+    fn test_displays_merges_errors() {
+        let error = BltError::MergesParse {
+            path: PathBuf::from("merges.txt"),
+            line: Some(3),
+            message: "expected two byte values".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Invalid merges file merges.txt, line 3: expected two byte values"
+        );
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,216 @@
+//! Content hashing used for manifests and dedup keys.
+//!
+//! Hashes are rendered as `"<algorithm>:<lowercase hex digest>"` so that a manifest entry is
+//! self-describing and hashes produced with different algorithms never compare equal. Seeded
+//! algorithms include their seed in the name, as in `xxh3@42`, so every recorded hash says
+//! exactly how to reproduce it.
+//!
+//! SHA-256 and BLAKE3 are cryptographic: two different documents will not share a hash even
+//! if someone tries to make them. XXH3 is several times faster again but offers no such
+//! guarantee, so it suits checksums and sampling of trusted corpora better than the keys of a
+//! content-addressed store shared with others.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use xxhash_rust::xxh3::Xxh3;
+
+/// The algorithm used to hash document contents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// SHA-256, the conservative default.
+    #[default]
+    Sha256,
+    /// BLAKE3, considerably faster on large inputs.
+    Blake3,
+    /// The 128-bit XXH3, non-cryptographic and faster still.
+    Xxh3 {
+        /// The seed the hash starts from; hashes with different seeds are unrelated.
+        seed: u64,
+    },
+}
+
+impl HashAlgorithm {
+    /// Returns the name of the algorithm, without its seed.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 { .. } => "xxh3",
+        }
+    }
+}
+
+/// Renders the name used on the CLI and as the prefix of rendered hashes: the algorithm's
+/// name, followed by `@<seed>` for a nonzero seed.
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashAlgorithm::Xxh3 { seed } if *seed != 0 => write!(f, "{}@{seed}", self.name()),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, seed) = match s.split_once('@') {
+            Some((name, seed)) => {
+                let seed = seed
+                    .parse()
+                    .map_err(|_| format!("Invalid hash seed '{seed}' in '{s}'"))?;
+                (name, Some(seed))
+            }
+            None => (s, None),
+        };
+        match (name.to_ascii_lowercase().as_str(), seed) {
+            ("sha256", None) => Ok(HashAlgorithm::Sha256),
+            ("blake3", None) => Ok(HashAlgorithm::Blake3),
+            ("xxh3", seed) => Ok(HashAlgorithm::Xxh3 {
+                seed: seed.unwrap_or(0),
+            }),
+            ("sha256" | "blake3", Some(_)) => Err(format!(
+                "Hash algorithm '{name}' takes no seed; only xxh3 is seeded"
+            )),
+            _ => Err(format!(
+                "Unsupported hash algorithm: '{s}'. Use sha256, blake3, or xxh3[@SEED]."
+            )),
+        }
+    }
+}
+
+/// An incremental hasher over document contents.
+pub struct ContentHasher {
+    state: HasherState,
+}
+
+enum HasherState {
+    Sha256(Box<Sha256>),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>, u64),
+}
+
+impl ContentHasher {
+    /// Creates a hasher for the given algorithm.
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        let state = match algorithm {
+            HashAlgorithm::Sha256 => HasherState::Sha256(Box::default()),
+            HashAlgorithm::Blake3 => HasherState::Blake3(Box::default()),
+            HashAlgorithm::Xxh3 { seed } => {
+                HasherState::Xxh3(Box::new(Xxh3::with_seed(seed)), seed)
+            }
+        };
+        Self { state }
+    }
+
+    /// Feeds more content into the hash.
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.state {
+            HasherState::Sha256(hasher) => hasher.update(data),
+            HasherState::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            HasherState::Xxh3(hasher, _) => hasher.update(data),
+        }
+    }
+
+    /// Finishes the hash and renders it as `"<algorithm>:<hex>"`.
+    pub fn finalize(self) -> String {
+        match self.state {
+            HasherState::Sha256(hasher) => render(HashAlgorithm::Sha256, &hasher.finalize()),
+            HasherState::Blake3(hasher) => {
+                render(HashAlgorithm::Blake3, hasher.finalize().as_bytes())
+            }
+            // The canonical XXH128 representation is big-endian.
+            HasherState::Xxh3(hasher, seed) => render(
+                HashAlgorithm::Xxh3 { seed },
+                &hasher.digest128().to_be_bytes(),
+            ),
+        }
+    }
+}
+
+/// Hashes `data` in one call.
+pub fn hash_bytes(algorithm: HashAlgorithm, data: &[u8]) -> String {
+    let mut hasher = ContentHasher::new(algorithm);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Hashes everything `reader` yields.
+pub fn hash_reader(algorithm: HashAlgorithm, mut reader: impl io::Read) -> io::Result<String> {
+    let mut hasher = ContentHasher::new(algorithm);
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(hasher.finalize()),
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn render(algorithm: HashAlgorithm, digest: &[u8]) -> String {
+    let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{algorithm}:{hex}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_known_vector() {
+        assert_eq!(
+            hash_bytes(HashAlgorithm::Sha256, b"abc"),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_xxh3_known_vector() {
+        assert_eq!(
+            hash_bytes(HashAlgorithm::Xxh3 { seed: 0 }, b""),
+            "xxh3:99aa06d3014798d86001c324468d497f"
+        );
+        let seeded = hash_bytes(HashAlgorithm::Xxh3 { seed: 42 }, b"abc");
+        assert!(seeded.starts_with("xxh3@42:"));
+        assert_ne!(seeded, hash_bytes(HashAlgorithm::Xxh3 { seed: 43 }, b"abc"));
+    }
+
+    #[test]
+    fn test_incremental_matches_one_shot() {
+        for algorithm in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Xxh3 { seed: 7 },
+        ] {
+            let mut hasher = ContentHasher::new(algorithm);
+            hasher.update(b"hello ");
+            hasher.update(b"world");
+            assert_eq!(hasher.finalize(), hash_bytes(algorithm, b"hello world"));
+            assert_eq!(
+                hash_reader(algorithm, &b"hello world"[..]).unwrap(),
+                hash_bytes(algorithm, b"hello world")
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!("BLAKE3".parse::<HashAlgorithm>(), Ok(HashAlgorithm::Blake3));
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+        for algorithm in [
+            HashAlgorithm::Xxh3 { seed: 0 },
+            HashAlgorithm::Xxh3 { seed: 42 },
+        ] {
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+        }
+        assert!("sha256@1".parse::<HashAlgorithm>().is_err());
+        assert!("xxh3@x".parse::<HashAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Self-describing output header.
+//!
+//! When enabled, raw outputs start with a small fixed header recording how the token stream
+//! was produced, so a `.bin` file can be interpreted without out-of-band knowledge.
+//!
+//! Layout (all multi-byte integers big-endian):
+//!
+//! | Offset | Size | Field                                     |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 4    | magic `b"BLTK"`                           |
+//! | 4      | 2    | format version                            |
+//! | 6      | 2    | header length in bytes (including magic)  |
+//! | 8      | 1    | token width in bytes                      |
+//! | 9      | 1    | endianness (0 = big, 1 = little)          |
+//! | 10     | 1    | strategy (0 = passthrough, 1 = basic, 2 = BPE) |
+//! | 11     | 1    | byte token offset / 256 (0 or 1)          |
+//! | 12     | 8    | merges hash (0 when no merges are loaded) |
+//!
+//! Readers must skip `header length` bytes to reach the token stream, which allows later
+//! versions to append fields without breaking older readers.
+
+use crate::StrategyKind;
+use std::io::{self, Read};
+
+/// The magic bytes that open every header.
+pub const MAGIC: [u8; 4] = *b"BLTK";
+/// The header format version written by this version of blt.
+pub const FORMAT_VERSION: u16 = 1;
+/// The length of a version 1 header in bytes.
+pub const HEADER_LEN: usize = 20;
+
+/// The byte order of the tokens in the stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    /// Most significant byte first (what blt writes).
+    Big,
+    /// Least significant byte first.
+    Little,
+}
+
+/// A parsed output header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputHeader {
+    /// The header format version.
+    pub version: u16,
+    /// The number of bytes per token (1 for passthrough, 2 otherwise).
+    pub token_width: u8,
+    /// The byte order of each token.
+    pub endianness: Endianness,
+    /// The strategy that produced the stream.
+    pub strategy: StrategyKind,
+    /// The [`merges_hash`](crate::config_loader::merges_hash) of the vocabulary, if any.
+    pub merges_hash: Option<u64>,
+    /// The value added to byte tokens (0 or 256). Headers written before this field existed
+    /// read as 0.
+    pub byte_offset: u16,
+}
+
+impl OutputHeader {
+    /// Builds the header describing a stream produced by `strategy`.
+    pub fn new(strategy: StrategyKind, merges_hash: Option<u64>) -> Self {
+        let token_width = if strategy == StrategyKind::Passthrough {
+            1
+        } else {
+            2
+        };
+        Self {
+            version: FORMAT_VERSION,
+            token_width,
+            endianness: Endianness::Big,
+            strategy,
+            merges_hash,
+            byte_offset: 0,
+        }
+    }
+
+    /// Records that byte tokens were shifted by `byte_offset`, a multiple of 256.
+    pub fn with_byte_offset(mut self, byte_offset: u16) -> Self {
+        self.byte_offset = byte_offset;
+        self
+    }
+
+    /// Serializes the header.
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&self.version.to_be_bytes());
+        bytes[6..8].copy_from_slice(&(HEADER_LEN as u16).to_be_bytes());
+        bytes[8] = self.token_width;
+        bytes[9] = endianness_code(self.endianness);
+        bytes[10] = strategy_code(self.strategy);
+        bytes[11] = (self.byte_offset / 256) as u8;
+        bytes[12..20].copy_from_slice(&self.merges_hash.unwrap_or(0).to_be_bytes());
+        bytes
+    }
+
+    /// Parses a header from the start of `bytes`, returning it with its encoded length.
+    ///
+    /// Returns an `InvalidData` error if `bytes` does not start with a valid header.
+    pub fn parse(bytes: &[u8]) -> io::Result<(Self, usize)> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return Err(invalid("missing BLT output header magic"));
+        }
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let header_len = u16::from_be_bytes([bytes[6], bytes[7]]) as usize;
+        if header_len < HEADER_LEN {
+            return Err(invalid("BLT output header is truncated"));
+        }
+        let merges_hash = u64::from_be_bytes(bytes[12..20].try_into().expect("8-byte slice"));
+        let header = Self {
+            version,
+            token_width: bytes[8],
+            endianness: parse_endianness(bytes[9])?,
+            strategy: parse_strategy(bytes[10])?,
+            merges_hash: (merges_hash != 0).then_some(merges_hash),
+            byte_offset: bytes[11] as u16 * 256,
+        };
+        Ok((header, header_len))
+    }
+
+    /// Reads and parses a header from `reader`, consuming exactly the header's bytes.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut fixed = [0u8; HEADER_LEN];
+        reader.read_exact(&mut fixed)?;
+        let (header, header_len) = Self::parse(&fixed)?;
+        io::copy(
+            &mut reader.take((header_len - HEADER_LEN) as u64),
+            &mut io::sink(),
+        )?;
+        Ok(header)
+    }
+}
+
+/// Returns `true` if `bytes` starts with the header magic.
+pub fn has_header(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+fn endianness_code(endianness: Endianness) -> u8 {
+    match endianness {
+        Endianness::Big => 0,
+        Endianness::Little => 1,
+    }
+}
+
+fn parse_endianness(code: u8) -> io::Result<Endianness> {
+    match code {
+        0 => Ok(Endianness::Big),
+        1 => Ok(Endianness::Little),
+        _ => Err(invalid("unknown endianness in BLT output header")),
+    }
+}
+
+fn strategy_code(strategy: StrategyKind) -> u8 {
+    match strategy {
+        StrategyKind::Passthrough => 0,
+        StrategyKind::Basic => 1,
+        StrategyKind::Bpe => 2,
+    }
+}
+
+fn parse_strategy(code: u8) -> io::Result<StrategyKind> {
+    match code {
+        0 => Ok(StrategyKind::Passthrough),
+        1 => Ok(StrategyKind::Basic),
+        2 => Ok(StrategyKind::Bpe),
+        _ => Err(invalid("unknown strategy in BLT output header")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() -> io::Result<()> {
+        let header = OutputHeader::new(StrategyKind::Bpe, Some(0xDEAD_BEEF));
+        let bytes = header.to_bytes();
+        assert!(has_header(&bytes));
+        assert_eq!(OutputHeader::parse(&bytes)?, (header, HEADER_LEN));
+        let shifted = OutputHeader::new(StrategyKind::Basic, None).with_byte_offset(256);
+        assert_eq!(OutputHeader::parse(&shifted.to_bytes())?.0.byte_offset, 256);
+        Ok(())
+    }
+
+    #[test]
+    fn test_passthrough_header_has_byte_width() {
+        let header = OutputHeader::new(StrategyKind::Passthrough, None);
+        assert_eq!(header.token_width, 1);
+        assert_eq!(header.merges_hash, None);
+    }
+
+    #[test]
+    fn test_parse_rejects_missing_magic() {
+        let err = OutputHeader::parse(&[0u8; HEADER_LEN]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_from_skips_extended_fields() -> io::Result<()> {
+        let mut bytes = OutputHeader::new(StrategyKind::Basic, None)
+            .to_bytes()
+            .to_vec();
+        bytes[6..8].copy_from_slice(&24u16.to_be_bytes());
+        bytes.extend_from_slice(&[9, 9, 9, 9, 0, 97]);
+        let mut reader = io::Cursor::new(bytes);
+        let header = OutputHeader::read_from(&mut reader)?;
+        assert_eq!(header.strategy, StrategyKind::Basic);
+        assert_eq!(reader.position(), 24);
+        Ok(())
+    }
+}
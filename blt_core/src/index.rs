@@ -0,0 +1,218 @@
+//! Sidecar index files recording where each document lives in a token output.
+//!
+//! The index is a small little-endian binary file so data loaders can memory-map it directly:
+//!
+//! | Offset | Size | Field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 4    | Magic bytes `BLTI`                     |
+//! | 4      | 2    | Format version                         |
+//! | 6      | 2    | Token width in bytes                   |
+//! | 8      | 8    | Number of entries                      |
+//! | 16     | 24·n | Entries: byte offset, token offset, token count (`u64` each) |
+//!
+//! Byte offsets are absolute positions in the output file (so they account for an output
+//! header); token offsets count tokens from the start of the token data.
+
+use crate::io_handler::AtomicFile;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Magic bytes identifying an index file.
+pub const MAGIC: [u8; 4] = *b"BLTI";
+/// The index format version written by this build.
+pub const FORMAT_VERSION: u16 = 1;
+/// The size in bytes of the fixed index header.
+pub const HEADER_LEN: usize = 16;
+/// The size in bytes of one encoded entry.
+pub const ENTRY_LEN: usize = 24;
+/// The file extension appended to the output path for the default index location.
+pub const EXTENSION: &str = "idx";
+
+/// The location of one document in the token output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Absolute byte offset of the document's first token in the output file.
+    pub byte_offset: u64,
+    /// Offset of the document's first token, in tokens.
+    pub token_offset: u64,
+    /// The number of tokens in the document.
+    pub tokens: u64,
+}
+
+/// An in-memory document index, built while the output is written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocumentIndex {
+    token_width: u16,
+    base_offset: u64,
+    next_token: u64,
+    entries: Vec<IndexEntry>,
+}
+
+impl DocumentIndex {
+    /// Creates an empty index whose token data starts at `base_offset` bytes into the output.
+    pub fn new(token_width: u16, base_offset: u64) -> Self {
+        Self {
+            token_width,
+            base_offset,
+            next_token: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Records a document of `tokens` tokens written directly after the previous one.
+    pub fn push(&mut self, tokens: u64) {
+        let token_offset = self.next_token;
+        self.entries.push(IndexEntry {
+            byte_offset: self.base_offset + token_offset * u64::from(self.token_width),
+            token_offset,
+            tokens,
+        });
+        self.next_token += tokens;
+    }
+
+    /// The recorded entries, in output order.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// The token width, in bytes, the offsets were computed with.
+    pub fn token_width(&self) -> u16 {
+        self.token_width
+    }
+
+    /// Encodes the index in its on-disk format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.entries.len() * ENTRY_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.token_width.to_le_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.byte_offset.to_le_bytes());
+            bytes.extend_from_slice(&entry.token_offset.to_le_bytes());
+            bytes.extend_from_slice(&entry.tokens.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes an index from its on-disk format.
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return Err(invalid("Not a blt index file"));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(invalid(&format!("Unsupported index version {version}")));
+        }
+        let token_width = u16::from_le_bytes([bytes[6], bytes[7]]);
+        let count = read_u64(bytes, 8) as usize;
+        let body = &bytes[HEADER_LEN..];
+        if body.len() != count.saturating_mul(ENTRY_LEN) {
+            return Err(invalid("Index file length does not match its entry count"));
+        }
+        let entries: Vec<IndexEntry> = body
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| IndexEntry {
+                byte_offset: read_u64(entry, 0),
+                token_offset: read_u64(entry, 8),
+                tokens: read_u64(entry, 16),
+            })
+            .collect();
+        let next_token = entries.last().map_or(0, |e| e.token_offset + e.tokens);
+        let base_offset = entries.first().map_or(0, |e| {
+            e.byte_offset - e.token_offset * u64::from(token_width)
+        });
+        Ok(Self {
+            token_width,
+            base_offset,
+            next_token,
+            entries,
+        })
+    }
+}
+
+/// Returns the default index path for `output`: the output path with `.idx` appended.
+pub fn default_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".");
+    path.push(EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Writes `index` to `path`, replacing it atomically. Fails if `path` exists and `overwrite`
+/// is unset.
+pub async fn write_index(path: &Path, index: &DocumentIndex, overwrite: bool) -> io::Result<()> {
+    let mut file = AtomicFile::create(path, overwrite).await?;
+    file.write_all(&index.to_bytes()).await?;
+    file.shutdown().await
+}
+
+/// Reads an index from `path`.
+pub async fn read_index(path: &Path) -> io::Result<DocumentIndex> {
+    DocumentIndex::parse(&tokio::fs::read(path).await?)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offsets_account_for_base_and_width() {
+        let mut index = DocumentIndex::new(2, 20);
+        index.push(3);
+        index.push(5);
+        assert_eq!(
+            index.entries(),
+            &[
+                IndexEntry {
+                    byte_offset: 20,
+                    token_offset: 0,
+                    tokens: 3
+                },
+                IndexEntry {
+                    byte_offset: 26,
+                    token_offset: 3,
+                    tokens: 5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut index = DocumentIndex::new(2, 0);
+        index.push(4);
+        index.push(1);
+        let bytes = index.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * ENTRY_LEN);
+        assert_eq!(DocumentIndex::parse(&bytes).unwrap(), index);
+    }
+
+    #[test]
+    fn test_rejects_truncated_index() {
+        let mut index = DocumentIndex::new(2, 0);
+        index.push(4);
+        let bytes = index.to_bytes();
+        assert!(DocumentIndex::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(DocumentIndex::parse(b"nope").is_err());
+    }
+
+    #[test]
+    fn test_default_path() {
+        assert_eq!(
+            default_path(Path::new("out/tokens.bin")),
+            PathBuf::from("out/tokens.bin.idx")
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Stopping a run early, on request or on SIGINT or SIGTERM.
+//!
+//! Without a handler, Ctrl-C kills the process wherever it is: output written in place or to
+//! stdout ends mid-chunk, and the temporary file of an output being replaced is left behind.
+//! Dropping the future of a run is no better, as its writers are never flushed. A run given a
+//! [`CancellationToken`], or told to stop on signals, instead stops dispatching new chunks once
+//! cancelled, writes the chunks already being tokenized in order, and then disposes of the
+//! incomplete output as its [`PartialOutput`] says. Documents finished before the cancellation
+//! are complete and recorded in the manifest as usual; the run then fails with
+//! [`BltError::Cancelled`](crate::BltError::Cancelled), which converts to an
+//! [`io::ErrorKind::Interrupted`] error carrying a [`Cancelled`].
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// The exit status of a command stopped by a signal, following the shell's `128 + SIGINT`.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// What happens to an output left incomplete by a cancellation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PartialOutput {
+    /// Discard it, leaving any file it was replacing untouched.
+    #[default]
+    Remove,
+    /// Keep it under its own name, ending after the last chunk written.
+    Keep,
+    /// Keep it, renamed with a `.partial` suffix so it is not mistaken for a complete output.
+    Mark,
+}
+
+impl PartialOutput {
+    /// Returns where an incomplete output at `path` is kept, if it is kept at all.
+    pub fn kept_path(&self, path: &Path) -> Option<PathBuf> {
+        match self {
+            PartialOutput::Remove => None,
+            PartialOutput::Keep => Some(path.to_path_buf()),
+            PartialOutput::Mark => {
+                let mut name = path.as_os_str().to_owned();
+                name.push(".partial");
+                Some(name.into())
+            }
+        }
+    }
+}
+
+/// A request to stop a run, shared by whoever requests it and the tasks that honor it.
+/// Clones share the same request.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: Arc<CancellationState>,
+}
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the run to stop. Later requests have no further effect.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Returns `true` once the run has been asked to stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Completes once the run has been asked to stop.
+    pub async fn cancelled(&self) {
+        let notified = self.inner.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// The cause of the error a cancelled run fails with, as [`BltError::Cancelled`]. Recover it
+/// from an [`io::Error`] with [`Cancelled::from_error`].
+///
+/// [`BltError::Cancelled`]: crate::BltError::Cancelled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cancelled {
+    /// The documents completed before the cancellation, including any carried over unchanged.
+    pub documents: u64,
+}
+
+impl Cancelled {
+    /// Returns the cancellation behind `error`, if the run failed because it was cancelled.
+    pub fn from_error(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cancelled after {} complete documents; the output is partial",
+            self.documents
+        )
+    }
+}
+
+impl Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        io::Error::new(io::ErrorKind::Interrupted, cancelled)
+    }
+}
+
+/// Cancels a token on the first termination signal and exits the process on the second, for
+/// when draining the chunks in flight takes too long. Stops listening when dropped.
+pub(crate) struct SignalListener {
+    task: JoinHandle<()>,
+}
+
+impl SignalListener {
+    /// Starts listening to cancel `token`. Must be called within a Tokio runtime.
+    pub(crate) fn spawn(token: CancellationToken) -> Self {
+        let task = tokio::spawn(async move {
+            termination().await;
+            warn!("Interrupted; writing the chunks in flight (interrupt again to exit at once)");
+            token.cancel();
+            termination().await;
+            std::process::exit(EXIT_INTERRUPTED);
+        });
+        Self { task }
+    }
+}
+
+impl Drop for SignalListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Completes on SIGTERM or Ctrl-C.
+pub(crate) async fn termination() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiters() {
+        let token = CancellationToken::new();
+        assert!(!token.is_cancelled());
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+        token.cancel();
+        waiter.await.unwrap();
+        // Cancellation is remembered for later waiters.
+        token.cancelled().await;
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancelled_error_is_typed() {
+        let error = io::Error::from(Cancelled { documents: 3 });
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(
+            Cancelled::from_error(&error),
+            Some(&Cancelled { documents: 3 })
+        );
+        assert_eq!(Cancelled::from_error(&io::Error::other("failed")), None);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_run_removes_partial_output() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        std::fs::write(&input, "abc")?;
+        let output = dir.path().join("output.bin");
+        let token = CancellationToken::new();
+        token.cancel();
+        let config = crate::CoreConfig {
+            input: Some(input),
+            output: Some(output.clone()),
+            ..crate::CoreConfig::default()
+        }
+        .with_cancellation(Some(token));
+
+        let error = crate::run_tokenizer(config.clone()).await.unwrap_err();
+        assert!(matches!(
+            error,
+            crate::BltError::Cancelled(Cancelled { documents: 0 })
+        ));
+        assert!(!output.exists());
+        let config = config.with_partial_output(PartialOutput::Mark);
+        crate::run_tokenizer(config).await.unwrap_err();
+        assert!(dir.path().join("output.bin.partial").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kept_path() {
+        let path = Path::new("out/tokens.bin");
+        assert_eq!(PartialOutput::Remove.kept_path(path), None);
+        assert_eq!(PartialOutput::Keep.kept_path(path).unwrap(), path);
+        assert_eq!(
+            PartialOutput::Mark.kept_path(path).unwrap(),
+            Path::new("out/tokens.bin.partial")
+        );
+    }
+}
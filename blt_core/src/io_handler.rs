@@ -6,13 +6,80 @@
 //! This module provides the logic for setting up input sources and output sinks. It
 //! abstracts away the differences between file-based I/O and standard I/O streams
 //! (stdin/stdout). A key feature is its ability to use memory-mapped files for
-//! efficient processing of file inputs.
+//! efficient processing of file inputs. Files that cannot be mapped, such as named pipes,
+//! devices, `/proc` files, or files on filesystems without mmap support, are streamed instead.
 
-use crate::CoreConfig;
+use crate::{ContentType, CoreConfig, OutputFormat};
 use memmap2::Mmap;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, BufWriter as TokioBufWriter};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+#[cfg(feature = "archive")]
+mod archive;
+#[cfg(feature = "arrow")]
+mod arrow_ipc;
+mod atomic;
+mod byte_range;
+#[cfg(feature = "arrow")]
+mod columnar;
+mod compression;
+#[cfg(feature = "video")]
+mod demux;
+mod digest;
+#[cfg(target_os = "linux")]
+mod direct;
+mod encryption;
+mod follow;
+mod html;
+mod limit;
+mod numbers;
+mod output_dir;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod preprocess;
+mod rate;
+mod timestamps;
+mod transcode;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod walk;
+mod whitespace;
+#[cfg(feature = "archive")]
+pub use archive::ArchiveKind;
+#[cfg(feature = "arrow")]
+pub use arrow_ipc::ArrowIpcEncoder;
+pub(crate) use atomic::{is_special_file, refuse_existing, sync_parent, AtomicFile};
+pub use byte_range::ByteRange;
+#[cfg(feature = "arrow")]
+pub use columnar::{token_schema, BatchEncoder, ColumnarWriter};
+pub use compression::Compression;
+#[cfg(feature = "video")]
+pub use demux::SegmentKind;
+pub(crate) use digest::{HashingWriter, OutputDigest};
+pub use encryption::EncryptionKey;
+#[cfg(feature = "encryption")]
+pub use encryption::{decrypt, DecryptingReader};
+pub use html::HtmlMode;
+pub(crate) use limit::{LimitedWriter, TokenBudget};
+pub use numbers::{NumberBucketing, NumberForm};
+pub(crate) use output_dir::OutputClaims;
+pub use output_dir::OutputDir;
+#[cfg(feature = "parquet")]
+pub use parquet::ParquetEncoder;
+pub use rate::RateLimit;
+pub(crate) use rate::{RateLimitedWriter, TokenBucket};
+pub use timestamps::TimestampStripping;
+pub use transcode::{DecodeErrorPolicy, Encoding, Transcoding, DETECT_SAMPLE_BYTES};
+pub use walk::{collect_input_files, read_filelist, InputFilter};
+pub use whitespace::WhitespaceNormalization;
 
 // --- Type Aliases for I/O ---
 
@@ -21,6 +88,24 @@ pub type InputReader = Box<dyn AsyncRead + Unpin + Send>;
 /// A type alias for a writable, asynchronous output stream.
 pub type OutputWriter = Box<dyn AsyncWrite + Unpin + Send>;
 
+/// The `doc_id` and `source` of the documents written to an output, which columnar formats
+/// store in every row.
+///
+/// Tokens written after [`DocumentLabels::begin`] belong to the document it starts, so the
+/// previous document's tokens must all have been written by then.
+#[derive(Clone, Debug, Default)]
+pub struct DocumentLabels(Arc<Mutex<VecDeque<(u64, String)>>>);
+
+impl DocumentLabels {
+    /// Starts document `doc_id`, read from `source`.
+    pub fn begin(&self, doc_id: u64, source: String) {
+        self.0
+            .lock()
+            .expect("document labels lock poisoned")
+            .push_back((doc_id, source));
+    }
+}
+
 // --- Public Enums and Functions ---
 
 /// Represents the source of input data for the pipeline.
@@ -29,11 +114,95 @@ pub type OutputWriter = Box<dyn AsyncWrite + Unpin + Send>;
 /// - A memory-mapped file (`Mmap`), which offers the highest performance for file-based input
 ///   by avoiding extra copying.
 /// - A standard input stream (`Stdin`), for piping data into the application.
+/// - Any other asynchronous reader (`Reader`), such as an archive member or an input file
+///   that cannot be memory-mapped.
 pub enum InputSource {
     /// A memory-mapped file.
     Mmap(Mmap),
     /// An asynchronous reader for standard input.
     Stdin(InputReader),
+    /// Another asynchronous reader, processed like standard input.
+    Reader(InputReader),
+}
+
+/// One document of a run: an input source and the label it is recorded under.
+pub struct InputDocument {
+    /// A human-readable label for the document, used in manifests and columnar outputs.
+    pub name: String,
+    /// The document's path relative to its input root, used to name per-document outputs.
+    pub relative_path: PathBuf,
+    /// A token written before the document's tokens, such as the [`SegmentKind`] token of
+    /// a demuxed container stream.
+    pub tag: Option<u16>,
+    /// Where the document's bytes come from.
+    pub source: InputSource,
+}
+
+/// The documents a run tokenizes, in output order.
+pub enum InputDocuments {
+    /// A single input file or standard input.
+    Single(Option<InputDocument>),
+    /// Members of an archive, read in the background.
+    Archive(mpsc::Receiver<io::Result<InputDocument>>),
+    /// Segments demuxed from a container file.
+    Segments(std::vec::IntoIter<InputDocument>),
+    /// Input files, memory-mapped one at a time.
+    Files {
+        /// The directory the files' relative paths are taken from.
+        root: PathBuf,
+        /// The remaining files.
+        paths: std::vec::IntoIter<PathBuf>,
+        /// Whether the files are memory-mapped rather than streamed.
+        mmap: bool,
+        /// Whether streamed files are read through io_uring.
+        io_uring: bool,
+    },
+}
+
+impl InputDocuments {
+    /// The number of input bytes left to read, if known without reading them: the size of a
+    /// mapped file, or the sizes of the remaining input files. Streams and archives are `None`.
+    pub fn total_bytes(&self) -> Option<u64> {
+        match self {
+            InputDocuments::Single(Some(InputDocument {
+                source: InputSource::Mmap(mmap),
+                ..
+            })) => Some(mmap.len() as u64),
+            InputDocuments::Single(None) => Some(0),
+            InputDocuments::Single(Some(_))
+            | InputDocuments::Archive(_)
+            | InputDocuments::Segments(_) => None,
+            InputDocuments::Files { paths, .. } => paths
+                .as_slice()
+                .iter()
+                .map(|path| std::fs::metadata(path).map(|m| m.len()).ok())
+                .sum(),
+        }
+    }
+
+    /// Returns the next document, or `None` once every document has been returned.
+    pub async fn next(&mut self) -> io::Result<Option<InputDocument>> {
+        match self {
+            InputDocuments::Single(document) => Ok(document.take()),
+            InputDocuments::Archive(rx) => rx.recv().await.transpose(),
+            InputDocuments::Segments(segments) => Ok(segments.next()),
+            InputDocuments::Files {
+                root,
+                paths,
+                mmap,
+                io_uring,
+            } => paths
+                .next()
+                .map(|path| {
+                    let relative = std::path::absolute(&path)?
+                        .strip_prefix(&*root)
+                        .map(Path::to_path_buf)
+                        .unwrap_or_else(|_| path.clone());
+                    open_file(&path, relative, *mmap, *io_uring)
+                })
+                .transpose(),
+        }
+    }
 }
 
 /// Sets up the input source and output writer based on the provided configuration.
@@ -46,33 +215,445 @@ pub enum InputSource {
 /// * `config` - A reference to the `CoreConfig` containing I/O settings.
 ///
 /// # Returns
-/// A `Result` containing a tuple of `(InputSource, OutputWriter)` on success, or an
+/// A `Result` containing a tuple of `(InputDocuments, OutputWriter)` on success, or an
 /// `io::Error` on failure.
-pub async fn setup_io(config: &CoreConfig) -> io::Result<(InputSource, OutputWriter)> {
-    let input_source = match &config.input {
+pub async fn setup_io(config: &CoreConfig) -> io::Result<(InputDocuments, OutputWriter)> {
+    let input_documents = setup_input(config)?;
+    let labels = DocumentLabels::default();
+    labels.begin(0, source_name(config));
+    let output_writer = setup_output_writer(config, config.output.as_deref(), labels, None).await?;
+    Ok((input_documents, output_writer))
+}
+
+/// Opens the input documents of `config` without setting up an output.
+pub(crate) fn setup_input(config: &CoreConfig) -> io::Result<InputDocuments> {
+    if config.output_dir.is_some() && config.input.is_none() && config.filelist.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--output-dir requires --input or --filelist",
+        ));
+    }
+    if config.byte_range.is_some()
+        && (config.archive
+            || config.filelist.is_some()
+            || config.input.as_ref().is_some_and(|p| p.is_dir()))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A byte range requires a single input file or standard input",
+        ));
+    }
+    if config.follow.is_some()
+        && (config.archive
+            || config.filelist.is_some()
+            || config.byte_range.is_some()
+            || config.input.as_ref().is_none_or(|p| p.is_dir()))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Following requires --input to name a single file, without a byte range",
+        ));
+    }
+    if config.demux {
+        if config.content_type != Some(ContentType::Video) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Demuxing requires the video content type",
+            ));
+        }
+        let path = config
+            .input
+            .as_deref()
+            .filter(|path| !config.archive && config.filelist.is_none() && !path.is_dir())
+            .filter(|_| config.byte_range.is_none() && config.follow.is_none())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Demuxing requires --input to name a single file, read whole",
+                )
+            })?;
+        let relative = path.file_name().map(PathBuf::from).unwrap_or_default();
+        return open_demuxed(path, &relative, config.mmap)
+            .map(|segments| InputDocuments::Segments(segments.into_iter()));
+    }
+    if config.archive {
+        let path = config.input.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Archive input requires --input to name an archive file",
+            )
+        })?;
+        return open_archive(path).map(InputDocuments::Archive);
+    }
+    if let Some(list) = &config.filelist {
+        let files = read_filelist(list)?;
+        let root = output_dir::common_parent(&files)?;
+        return Ok(InputDocuments::Files {
+            root,
+            paths: files.into_iter(),
+            mmap: config.mmap,
+            io_uring: config.io_uring,
+        });
+    }
+    match &config.input {
+        Some(path) if path.is_dir() => {
+            let files = collect_input_files(path, &config.input_filter)?;
+            Ok(InputDocuments::Files {
+                root: std::path::absolute(path)?,
+                paths: files.into_iter(),
+                mmap: config.mmap,
+                io_uring: config.io_uring,
+            })
+        }
         Some(path) => {
-            let file = File::open(path)?;
-            let mmap = unsafe { Mmap::map(&file)? };
-            InputSource::Mmap(mmap)
+            let relative = path.file_name().map(PathBuf::from).unwrap_or_default();
+            let document = match (config.byte_range, config.follow) {
+                (Some(range), _) => {
+                    open_file_range(path, relative, range, config.mmap, config.io_uring)?
+                }
+                (None, Some(interval)) => follow::open_followed(path, relative, interval)?,
+                (None, None) => open_file(path, relative, config.mmap, config.io_uring)?,
+            };
+            Ok(InputDocuments::Single(Some(document)))
         }
         None => {
-            let stdin_reader = Box::new(tokio::io::stdin());
-            InputSource::Stdin(stdin_reader)
+            let stdin: InputReader = Box::new(tokio::io::stdin());
+            Ok(InputDocuments::Single(Some(InputDocument {
+                name: source_name(config),
+                relative_path: PathBuf::from("stdin"),
+                tag: None,
+                source: InputSource::Stdin(match config.byte_range {
+                    Some(range) => range.limit_reader(stdin),
+                    None => stdin,
+                }),
+            })))
         }
+    }
+}
+
+fn open_file(
+    path: &Path,
+    relative_path: PathBuf,
+    mmap: bool,
+    io_uring: bool,
+) -> io::Result<InputDocument> {
+    let file = File::open(path)?;
+    let map = |file: &File| unsafe { Mmap::map(file) };
+    let source = match try_map(path, &file, mmap, map)? {
+        Some(mmap) => InputSource::Mmap(mmap),
+        None => InputSource::Reader(stream_file(file, io_uring)?),
+    };
+    Ok(InputDocument {
+        name: path.display().to_string(),
+        relative_path,
+        tag: None,
+        source,
+    })
+}
+
+fn open_file_range(
+    path: &Path,
+    relative_path: PathBuf,
+    range: ByteRange,
+    mmap: bool,
+    io_uring: bool,
+) -> io::Result<InputDocument> {
+    let file = File::open(path)?;
+    let map = |file: &File| range.map_file(file);
+    let source = match try_map(path, &file, mmap, map)? {
+        Some(mmap) => InputSource::Mmap(mmap),
+        None => InputSource::Reader(range.file_reader(file, io_uring)?),
     };
+    Ok(InputDocument {
+        name: path.display().to_string(),
+        relative_path,
+        tag: None,
+        source,
+    })
+}
+
+/// Maps `file` with `map` if `mmap` is set and the file can be memory-mapped, or returns
+/// `None` so it is streamed.
+///
+/// Named pipes and devices cannot be mapped, `/proc` files report a size of 0 however much
+/// they hold, and some network filesystems refuse to map files, so all of those are read as
+/// streams instead.
+fn try_map(
+    path: &Path,
+    file: &File,
+    mmap: bool,
+    map: impl FnOnce(&File) -> io::Result<Mmap>,
+) -> io::Result<Option<Mmap>> {
+    if !mmap {
+        return Ok(None);
+    }
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+    match map(file) {
+        Ok(mmap) => Ok(Some(mmap)),
+        Err(error) => {
+            debug!(path = %path.display(), %error, "Input cannot be memory-mapped; streaming it");
+            Ok(None)
+        }
+    }
+}
 
-    let output_writer = setup_output_writer(config).await?;
-    Ok((input_source, output_writer))
+/// Streams `file` from its current position, through io_uring if `io_uring` is set.
+pub(crate) fn stream_file(file: File, io_uring: bool) -> io::Result<InputReader> {
+    match io_uring {
+        true => uring_reader(file),
+        false => Ok(Box::new(tokio::fs::File::from_std(file))),
+    }
 }
 
-async fn setup_output_writer(config: &CoreConfig) -> io::Result<OutputWriter> {
-    match &config.output {
+/// Writes `file` from its current position with direct I/O or through io_uring, as `config`
+/// asks.
+fn file_writer(file: File, config: &CoreConfig) -> io::Result<OutputWriter> {
+    if config.direct_io {
+        direct_writer(file)
+    } else if config.io_uring {
+        uring_writer(file)
+    } else {
+        Ok(Box::new(tokio::fs::File::from_std(file)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn direct_writer(file: File) -> io::Result<OutputWriter> {
+    direct::writer(file)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn direct_writer(_file: File) -> io::Result<OutputWriter> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Direct I/O output is only supported on Linux",
+    ))
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn uring_reader(file: File) -> io::Result<InputReader> {
+    uring::reader(file)
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn uring_writer(file: File) -> io::Result<OutputWriter> {
+    uring::writer(file)
+}
+
+// io_uring is Linux-only; elsewhere the feature keeps tokio's file I/O.
+#[cfg(all(feature = "io-uring", not(target_os = "linux")))]
+fn uring_reader(file: File) -> io::Result<InputReader> {
+    Ok(Box::new(tokio::fs::File::from_std(file)))
+}
+
+#[cfg(all(feature = "io-uring", not(target_os = "linux")))]
+fn uring_writer(file: File) -> io::Result<OutputWriter> {
+    Ok(Box::new(tokio::fs::File::from_std(file)))
+}
+
+#[cfg(not(feature = "io-uring"))]
+fn uring_reader(_file: File) -> io::Result<InputReader> {
+    Err(missing_io_uring())
+}
+
+#[cfg(not(feature = "io-uring"))]
+fn uring_writer(_file: File) -> io::Result<OutputWriter> {
+    Err(missing_io_uring())
+}
+
+#[cfg(not(feature = "io-uring"))]
+fn missing_io_uring() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "io_uring I/O requires blt to be built with the `io-uring` feature",
+    )
+}
+
+#[cfg(feature = "video")]
+fn open_demuxed(path: &Path, relative_path: &Path, mmap: bool) -> io::Result<Vec<InputDocument>> {
+    demux::open_demuxed(path, relative_path, mmap)
+}
+
+#[cfg(not(feature = "video"))]
+fn open_demuxed(
+    _path: &Path,
+    _relative_path: &Path,
+    _mmap: bool,
+) -> io::Result<Vec<InputDocument>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Video demuxing requires blt to be built with the `video` feature",
+    ))
+}
+
+#[cfg(feature = "archive")]
+fn open_archive(path: &Path) -> io::Result<mpsc::Receiver<io::Result<InputDocument>>> {
+    archive::open_archive(path)
+}
+
+#[cfg(not(feature = "archive"))]
+fn open_archive(_path: &Path) -> io::Result<mpsc::Receiver<io::Result<InputDocument>>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Archive input requires blt to be built with the `archive` feature",
+    ))
+}
+
+/// Opens the writer that mirrors the original input bytes, if `tee_original` is configured.
+/// Like an output, the mirror is replaced atomically when the writer shuts down, and an
+/// existing file is refused unless `config.overwrite` is set.
+pub async fn setup_tee_writer(config: &CoreConfig) -> io::Result<Option<OutputWriter>> {
+    let Some(path) = &config.tee_original else {
+        return Ok(None);
+    };
+    let writer: OutputWriter = if atomic::is_special_file(path) {
+        Box::new(tokio::fs::File::create(path).await?)
+    } else {
+        Box::new(atomic::AtomicFile::create(path, config.overwrite).await?)
+    };
+    Ok(Some(Box::new(TokioBufWriter::new(writer))))
+}
+
+/// Opens an output writer for `output` (stdout when `None`) with the configured compression
+/// and output format; `labels` names the rows of columnar outputs.
+/// Output files are replaced atomically when the writer shuts down, and an existing file is
+/// refused unless `config.overwrite` is set. The bytes reaching the file, after compression
+/// and encryption, are hashed into `file_digest`.
+pub(crate) async fn setup_output_writer(
+    config: &CoreConfig,
+    output: Option<&Path>,
+    labels: DocumentLabels,
+    file_digest: Option<Arc<OutputDigest>>,
+) -> io::Result<OutputWriter> {
+    if config.discard_output {
+        // Nothing is written, so there is nothing to compress, encrypt, or format either.
+        return Ok(Box::new(tokio::io::sink()));
+    }
+    let mut writer: OutputWriter = match output {
         Some(path) => {
-            let file = tokio::fs::File::create(path).await?;
-            Ok(Box::new(TokioBufWriter::new(file)))
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            if atomic::is_special_file(path) {
+                Box::new(TokioBufWriter::new(tokio::fs::File::create(path).await?))
+            } else if config.follow.is_some() {
+                // A followed input has no end to wait for, so its output grows in place.
+                let file = atomic::create_in_place(path, config.overwrite).await?;
+                let file = file_writer(file.into_std().await, config)?;
+                Box::new(TokioBufWriter::new(file))
+            } else {
+                let open = |file| file_writer(file, config);
+                let file = atomic::AtomicFile::create_with(path, config.overwrite, open).await?;
+                Box::new(TokioBufWriter::new(file))
+            }
         }
-        None => Ok(Box::new(tokio::io::stdout())),
+        None => Box::new(Stdout(tokio::io::stdout())),
+    };
+    if let Some(digest) = file_digest {
+        writer = Box::new(HashingWriter::new(writer, digest));
+    }
+    // Compressing comes first: ciphertext does not compress.
+    if let Some(key) = &config.encryption {
+        writer = encryption::encrypt_writer(writer, key)?;
     }
+    let writer = match config.compression {
+        Some(compression) => compression::compress_writer(writer, compression)?,
+        None => writer,
+    };
+    wrap_output_format(writer, config, labels)
+}
+
+/// Tokio's stdout hands writes to a blocking thread and does not wait for them on shutdown,
+/// so output written just before exit could be lost. This wrapper flushes on shutdown.
+struct Stdout(tokio::io::Stdout);
+
+impl AsyncWrite for Stdout {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(Pin::new(&mut self.0).poll_flush(cx))?;
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+fn wrap_output_format(
+    writer: OutputWriter,
+    config: &CoreConfig,
+    labels: DocumentLabels,
+) -> io::Result<OutputWriter> {
+    if config.output_format != OutputFormat::Raw && config.passthrough_mode {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Columnar output formats cannot be combined with passthrough mode",
+        ));
+    }
+    match config.output_format {
+        OutputFormat::Raw => Ok(writer),
+        OutputFormat::ArrowIpc => arrow_ipc_writer(writer, labels),
+        OutputFormat::Parquet { row_group_size } => parquet_writer(writer, labels, row_group_size),
+    }
+}
+
+#[cfg(feature = "arrow")]
+fn arrow_ipc_writer(writer: OutputWriter, labels: DocumentLabels) -> io::Result<OutputWriter> {
+    Ok(Box::new(ColumnarWriter::new(
+        writer,
+        labels,
+        ArrowIpcEncoder::default(),
+    )))
+}
+
+#[cfg(not(feature = "arrow"))]
+fn arrow_ipc_writer(_writer: OutputWriter, _labels: DocumentLabels) -> io::Result<OutputWriter> {
+    Err(missing_feature("Arrow", "arrow"))
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_writer(
+    writer: OutputWriter,
+    labels: DocumentLabels,
+    row_group_size: usize,
+) -> io::Result<OutputWriter> {
+    let encoder = ParquetEncoder::new(row_group_size);
+    Ok(Box::new(ColumnarWriter::new(writer, labels, encoder)))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_writer(
+    _writer: OutputWriter,
+    _labels: DocumentLabels,
+    _row_group_size: usize,
+) -> io::Result<OutputWriter> {
+    Err(missing_feature("Parquet", "parquet"))
+}
+
+#[cfg(not(all(feature = "arrow", feature = "parquet")))]
+fn missing_feature(format_name: &str, feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{format_name} output requires blt to be built with the `{feature}` feature"),
+    )
+}
+
+/// Returns a human-readable label for the configured input, used in output metadata.
+pub(crate) fn source_name(config: &CoreConfig) -> String {
+    config
+        .input
+        .as_ref()
+        .map_or_else(|| "-".to_string(), |path| path.display().to_string())
 }
 
 // Later, this module could include functions for managing ordered writing of processed chunks, etc.
@@ -0,0 +1,193 @@
+//! Reading tar and zip archives as sequences of documents.
+//!
+//! Archives are read on a blocking thread that sends each regular-file member over a bounded
+//! channel, so only a couple of members are held in memory at a time. The archive type is
+//! detected from its leading bytes: zip (`PK\x03\x04`), gzip-compressed tar, or plain tar.
+
+use super::{InputDocument, InputSource};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// Members read ahead of the pipeline.
+const READ_AHEAD: usize = 2;
+
+/// The archive container formats that can be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// A POSIX tar archive.
+    Tar,
+    /// A gzip-compressed tar archive.
+    TarGz,
+    /// A zip archive.
+    Zip,
+}
+
+impl ArchiveKind {
+    /// Detects the archive kind from the first bytes of the file.
+    pub fn detect(prefix: &[u8]) -> Self {
+        if prefix.starts_with(b"PK\x03\x04") || prefix.starts_with(b"PK\x05\x06") {
+            ArchiveKind::Zip
+        } else if prefix.starts_with(&[0x1f, 0x8b]) {
+            ArchiveKind::TarGz
+        } else {
+            ArchiveKind::Tar
+        }
+    }
+}
+
+/// Starts reading the members of the archive at `path` in the background.
+pub(crate) fn open_archive(path: &Path) -> io::Result<mpsc::Receiver<io::Result<InputDocument>>> {
+    let mut file = File::open(path)?;
+    let mut prefix = [0u8; 4];
+    let read = file.read(&mut prefix)?;
+    file.seek(SeekFrom::Start(0))?;
+    let kind = ArchiveKind::detect(&prefix[..read]);
+
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    let archive_path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let send = |document: io::Result<InputDocument>| tx.blocking_send(document).is_ok();
+        let result = match kind {
+            ArchiveKind::Tar => read_tar(file, &archive_path, send),
+            ArchiveKind::TarGz => read_tar(flate2::read::GzDecoder::new(file), &archive_path, send),
+            ArchiveKind::Zip => read_zip(file, &archive_path, send),
+        };
+        if let Err(e) = result {
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+    Ok(rx)
+}
+
+fn member_document(archive: &Path, member: &str, bytes: Vec<u8>) -> InputDocument {
+    InputDocument {
+        name: format!("{}:{member}", archive.display()),
+        relative_path: member.into(),
+        tag: None,
+        source: InputSource::Reader(Box::new(io::Cursor::new(bytes))),
+    }
+}
+
+fn read_tar<R: Read>(
+    reader: R,
+    archive_path: &Path,
+    mut send: impl FnMut(io::Result<InputDocument>) -> bool,
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let member = entry.path()?.display().to_string();
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes)?;
+        if !send(Ok(member_document(archive_path, &member, bytes))) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_zip(
+    file: File,
+    archive_path: &Path,
+    mut send: impl FnMut(io::Result<InputDocument>) -> bool,
+) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(io::Error::other)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let member = entry.name().to_string();
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes)?;
+        if !send(Ok(member_document(archive_path, &member, bytes))) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::io::AsyncReadExt;
+
+    async fn collect(path: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut rx = open_archive(path).unwrap();
+        let mut members = Vec::new();
+        while let Some(document) = rx.recv().await {
+            let document = document.unwrap();
+            let InputSource::Reader(mut reader) = document.source else {
+                panic!("archive members are in-memory readers");
+            };
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await.unwrap();
+            members.push((document.name, bytes));
+        }
+        members
+    }
+
+    fn tar_bytes() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in [("a.txt", &b"first"[..]), ("dir/b.txt", &b"second"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tar_members_in_order() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&tar_bytes()).unwrap();
+        let members = collect(file.path()).await;
+        let names: Vec<&str> = members
+            .iter()
+            .map(|(n, _)| n.rsplit(':').next().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a.txt", "dir/b.txt"]);
+        assert_eq!(members[1].1, b"second");
+    }
+
+    #[tokio::test]
+    async fn test_tar_gz() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&tar_bytes()).unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&encoder.finish().unwrap()).unwrap();
+        assert_eq!(collect(file.path()).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_zip_members() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let mut writer = zip::ZipWriter::new(File::create(file.path()).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            writer.add_directory("dir/", options).unwrap();
+            writer.start_file("dir/c.txt", options).unwrap();
+            writer.write_all(b"zipped").unwrap();
+            writer.finish().unwrap();
+        }
+        let members = collect(file.path()).await;
+        assert_eq!(members.len(), 1);
+        assert!(members[0].0.ends_with(":dir/c.txt"));
+        assert_eq!(members[0].1, b"zipped");
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(ArchiveKind::detect(b"PK\x03\x04"), ArchiveKind::Zip);
+        assert_eq!(ArchiveKind::detect(&[0x1f, 0x8b, 8, 0]), ArchiveKind::TarGz);
+        assert_eq!(ArchiveKind::detect(b"a.tx"), ArchiveKind::Tar);
+    }
+}
@@ -0,0 +1,113 @@
+//! Arrow IPC output format.
+//!
+//! Writes the tokenized documents as an Arrow IPC stream that pyarrow or polars can read
+//! (and memory-map) directly. Each document is written as its own record batch as soon as it
+//! is complete, so the output streams and memory stays bounded by the largest document.
+
+use super::columnar::{arrow_to_io_error, token_schema, BatchEncoder};
+use arrow_array::RecordBatch;
+use arrow_ipc::writer::StreamWriter;
+use std::io;
+
+/// Encodes token batches as an Arrow IPC stream.
+#[derive(Default)]
+pub struct ArrowIpcEncoder {
+    writer: Option<StreamWriter<Vec<u8>>>,
+}
+
+impl ArrowIpcEncoder {
+    /// The stream writer, opened on first use.
+    fn writer(&mut self) -> io::Result<&mut StreamWriter<Vec<u8>>> {
+        if self.writer.is_none() {
+            let writer =
+                StreamWriter::try_new(Vec::new(), &token_schema()).map_err(arrow_to_io_error)?;
+            self.writer = Some(writer);
+        }
+        Ok(self.writer.as_mut().expect("writer was just opened"))
+    }
+}
+
+impl BatchEncoder for ArrowIpcEncoder {
+    fn batch_rows(&self) -> usize {
+        1
+    }
+
+    fn write(&mut self, batch: &RecordBatch, out: &mut Vec<u8>) -> io::Result<()> {
+        let writer = self.writer()?;
+        writer.write(batch).map_err(arrow_to_io_error)?;
+        out.append(writer.get_mut());
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        let writer = self.writer()?;
+        writer.finish().map_err(arrow_to_io_error)?;
+        out.append(writer.get_mut());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::{ColumnarWriter, DocumentLabels, OutputWriter};
+    use arrow_array::{Array, ListArray, StringArray, UInt16Array};
+    use arrow_ipc::reader::StreamReader;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_arrow_ipc_writer_roundtrip() -> io::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await?);
+        let labels = DocumentLabels::default();
+        labels.begin(0, "input.txt".to_string());
+        let mut writer = ColumnarWriter::new(inner, labels, ArrowIpcEncoder::default());
+        writer.write_all(&[0, 97, 0, 98, 1, 0]).await?;
+        writer.shutdown().await?;
+
+        let reader = StreamReader::try_new(std::fs::File::open(file.path())?, None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(batches.len(), 1);
+        let lists = batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .unwrap();
+        let values = lists.value(0);
+        let tokens = values.as_any().downcast_ref::<UInt16Array>().unwrap();
+        assert_eq!(tokens.values().to_vec(), vec![97, 98, 256]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_arrow_ipc_writer_streams_a_batch_per_document() -> io::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await?);
+        let labels = DocumentLabels::default();
+        let mut writer = ColumnarWriter::new(inner, labels.clone(), ArrowIpcEncoder::default());
+        for (doc_id, tokens) in [[0, 97], [0, 98], [0, 99]].iter().enumerate() {
+            labels.begin(doc_id as u64, format!("doc{doc_id}.txt"));
+            writer.write_all(tokens).await?;
+        }
+        // The first two documents are complete and written before the stream is finished.
+        writer.flush().await?;
+        let reader = StreamReader::try_new(std::fs::File::open(file.path())?, None).unwrap();
+        let streamed: Vec<RecordBatch> = reader.map_while(Result::ok).collect();
+        assert_eq!(streamed.len(), 2);
+        writer.shutdown().await?;
+
+        let reader = StreamReader::try_new(std::fs::File::open(file.path())?, None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(batches.len(), 3);
+        for (doc_id, batch) in batches.iter().enumerate() {
+            assert_eq!(batch.num_rows(), 1);
+            let sources = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            assert_eq!(sources.value(0), format!("doc{doc_id}.txt"));
+        }
+        Ok(())
+    }
+}
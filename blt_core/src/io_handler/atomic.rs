@@ -0,0 +1,251 @@
+//! Atomic replacement of output files.
+//!
+//! Outputs are written to a hidden temporary file beside the destination and renamed over it
+//! only once the writer shuts down cleanly. A run that fails or is killed part-way leaves the
+//! destination untouched instead of truncated, so an output file that exists is complete. The
+//! temporary file is synced before the rename and the directory after it, so the same holds
+//! after a crash or power loss.
+
+use super::OutputWriter;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::task::JoinHandle;
+
+/// A file written under a temporary name and moved to its destination on shutdown.
+pub(crate) struct AtomicFile {
+    file: OutputWriter,
+    /// A second handle on the temporary file, synced before it is renamed.
+    sync: Option<fs::File>,
+    temp: PathBuf,
+    path: PathBuf,
+    /// The sync and rename, running on a blocking thread once the writer has shut down.
+    commit: Option<JoinHandle<io::Result<()>>>,
+    committed: bool,
+    /// Why the commit failed, reported again by every later shutdown.
+    failed: Option<String>,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file for `path`. Fails if `path` exists and `overwrite` is unset.
+    pub(crate) async fn create(path: &Path, overwrite: bool) -> io::Result<Self> {
+        Self::create_with(path, overwrite, |file| {
+            Ok(Box::new(tokio::fs::File::from_std(file)))
+        })
+        .await
+    }
+
+    /// Creates the temporary file for `path`, written through the writer `open` wraps it in.
+    /// Fails if `path` exists and `overwrite` is unset.
+    pub(crate) async fn create_with(
+        path: &Path,
+        overwrite: bool,
+        open: impl FnOnce(fs::File) -> io::Result<OutputWriter>,
+    ) -> io::Result<Self> {
+        refuse_existing(path, overwrite).await?;
+        let temp = temp_path(path);
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .await?;
+        let file = file.into_std().await;
+        Ok(Self {
+            sync: Some(file.try_clone()?),
+            file: open(file)?,
+            temp,
+            path: path.to_path_buf(),
+            commit: None,
+            committed: false,
+            failed: None,
+        })
+    }
+}
+
+/// Creates `path` to be written directly rather than replaced on shutdown, for outputs
+/// that must be readable while they grow. Fails if `path` exists and `overwrite` is unset.
+pub(crate) async fn create_in_place(path: &Path, overwrite: bool) -> io::Result<tokio::fs::File> {
+    refuse_existing(path, overwrite).await?;
+    tokio::fs::File::create(path).await
+}
+
+/// Fails if `path` exists and `overwrite` is unset.
+pub(crate) async fn refuse_existing(path: &Path, overwrite: bool) -> io::Result<()> {
+    if !overwrite && tokio::fs::try_exists(path).await? {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "Output {} already exists; use --force to overwrite it",
+                path.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns `true` if `path` should be written in place rather than replaced, such as
+/// `/dev/null` or a named pipe.
+pub(crate) fn is_special_file(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| !metadata.is_file())
+}
+
+/// The hidden sibling `.<name>.<pid>.tmp` that receives the output until it is complete.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+/// Makes the temporary file `temp` durable with `sync`, renames it to `path`, and syncs the
+/// directory so the rename survives a crash too. Nothing is renamed if the sync fails.
+fn commit(sync: impl FnOnce() -> io::Result<()>, temp: &Path, path: &Path) -> io::Result<()> {
+    sync()?;
+    fs::rename(temp, path)?;
+    sync_parent(path)
+}
+
+/// Syncs the directory holding `path`, which records renames into it.
+#[cfg(unix)]
+pub(crate) fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(parent)?.sync_all()
+}
+
+/// Directories cannot be opened to sync them on other platforms, where renames are left to
+/// the file system.
+#[cfg(not(unix))]
+pub(crate) fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+impl AsyncWrite for AtomicFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.committed {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(reason) = &self.failed {
+            return Poll::Ready(Err(io::Error::other(format!(
+                "Output {} was not committed: {reason}",
+                self.path.display()
+            ))));
+        }
+        if self.commit.is_none() {
+            ready!(Pin::new(&mut self.file).poll_shutdown(cx))?;
+            let file = self
+                .sync
+                .take()
+                .expect("the temporary file is committed once");
+            let (temp, path) = (self.temp.clone(), self.path.clone());
+            self.commit = Some(tokio::task::spawn_blocking(move || {
+                commit(|| file.sync_all(), &temp, &path)
+            }));
+        }
+        let commit = self.commit.as_mut().expect("the commit was just started");
+        let result = ready!(Pin::new(commit).poll(cx))
+            .map_err(io::Error::other)
+            .and_then(|result| result);
+        self.commit = None;
+        match result {
+            Ok(()) => self.committed = true,
+            Err(ref e) => self.failed = Some(e.to_string()),
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // A commit already under way finishes on its own; the output it renames is complete.
+        if !self.committed && self.commit.is_none() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_replaces_only_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.bin");
+        std::fs::write(&path, b"old").unwrap();
+        assert_eq!(
+            AtomicFile::create(&path, false).await.err().unwrap().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        let mut abandoned = AtomicFile::create(&path, true).await.unwrap();
+        abandoned.write_all(b"partial").await.unwrap();
+        drop(abandoned);
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+
+        let mut file = AtomicFile::create(&path, true).await.unwrap();
+        file.write_all(b"new").await.unwrap();
+        file.shutdown().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_commit_fails_every_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        // A file cannot be renamed over a non-empty directory.
+        let path = dir.path().join("out");
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("keep"), b"").unwrap();
+
+        let mut file = AtomicFile::create(&path, true).await.unwrap();
+        file.write_all(b"new").await.unwrap();
+        assert!(file.shutdown().await.is_err());
+        let again = file.shutdown().await.unwrap_err();
+        assert!(again.to_string().contains("was not committed"), "{again}");
+        drop(file);
+        assert!(path.join("keep").exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_commit_syncs_before_renaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let (temp, path) = (dir.path().join(".out.tmp"), dir.path().join("out.bin"));
+        std::fs::write(&temp, b"new").unwrap();
+        std::fs::write(&path, b"old").unwrap();
+
+        let failed = || Err(io::Error::other("disk full"));
+        assert!(commit(failed, &temp, &path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert!(temp.exists());
+
+        let synced_before_rename = || {
+            assert_eq!(std::fs::read(&path).unwrap(), b"old");
+            Ok(())
+        };
+        commit(synced_before_rename, &temp, &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!temp.exists());
+    }
+}
@@ -0,0 +1,187 @@
+//! Restricting input to a byte range.
+//!
+//! Files are mapped from the start of the range only, or, when read as streams, seeked to it,
+//! so slicing a huge corpus costs no more than the range itself. Other streams cannot seek, so
+//! their leading bytes are read and discarded before the range is passed on.
+
+use super::{stream_file, InputReader};
+use memmap2::{Mmap, MmapOptions};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// A slice of the input: `length` bytes starting at `offset`, or everything from `offset`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ByteRange {
+    /// The number of leading bytes to skip.
+    pub offset: u64,
+    /// The most bytes to read after `offset`; `None` reads to the end of the input.
+    pub length: Option<u64>,
+}
+
+impl ByteRange {
+    /// The bytes from `start` up to, but not including, `end`.
+    pub fn between(start: u64, end: u64) -> io::Result<Self> {
+        if end < start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Byte range end {end} is before its start {start}"),
+            ));
+        }
+        Ok(Self {
+            offset: start,
+            length: Some(end - start),
+        })
+    }
+
+    /// Maps the part of `file` inside the range; ranges past the end of the file are empty.
+    pub(crate) fn map_file(&self, file: &File) -> io::Result<Mmap> {
+        let file_len = file.metadata()?.len();
+        let offset = self.offset.min(file_len);
+        let available = file_len - offset;
+        let len = self
+            .length
+            .map_or(available, |length| length.min(available));
+        let len = usize::try_from(len).map_err(io::Error::other)?;
+        // SAFETY: as for whole-file maps, the file must not be truncated while mapped.
+        unsafe { MmapOptions::new().offset(offset).len(len).map(file) }
+    }
+
+    /// Reads the range of `file` as a stream, seeking to its start where the file allows it.
+    /// Reads go through io_uring if `io_uring` is set.
+    pub(crate) fn file_reader(&self, mut file: File, io_uring: bool) -> io::Result<InputReader> {
+        Ok(match file.seek(SeekFrom::Start(self.offset)) {
+            Ok(_) => {
+                let reader = stream_file(file, io_uring)?;
+                Box::new(reader.take(self.length.unwrap_or(u64::MAX)))
+            }
+            Err(_) => self.limit_reader(stream_file(file, io_uring)?),
+        })
+    }
+
+    /// Skips to the start of the range in `reader` and stops reading at its end.
+    pub(crate) fn limit_reader(&self, reader: InputReader) -> InputReader {
+        let skipped = Skip {
+            inner: reader,
+            remaining: self.offset,
+        };
+        Box::new(skipped.take(self.length.unwrap_or(u64::MAX)))
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = io::Error;
+
+    /// Parses `START..END`, `START..`, or `..END`.
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid byte range '{s}', expected START..END, START.., or ..END"),
+            )
+        };
+        let (start, end) = s.split_once("..").ok_or_else(invalid)?;
+        let bound = |value: &str| -> io::Result<Option<u64>> {
+            match value.trim() {
+                "" => Ok(None),
+                value => value.parse().map(Some).map_err(|_| invalid()),
+            }
+        };
+        let start = bound(start)?.unwrap_or(0);
+        match bound(end)? {
+            Some(end) => Self::between(start, end),
+            None => Ok(Self {
+                offset: start,
+                length: None,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.length {
+            Some(length) => write!(f, "{}..{}", self.offset, self.offset + length),
+            None => write!(f, "{}..", self.offset),
+        }
+    }
+}
+
+/// Discards the first `remaining` bytes of a stream.
+struct Skip {
+    inner: InputReader,
+    remaining: u64,
+}
+
+impl AsyncRead for Skip {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut scratch = [0u8; 8192];
+        while self.remaining > 0 {
+            let len = scratch.len().min(self.remaining as usize);
+            let mut discard = ReadBuf::new(&mut scratch[..len]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut discard))?;
+            if discard.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            self.remaining -= discard.filled().len() as u64;
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse() {
+        let range: ByteRange = "10..25".parse().unwrap();
+        assert_eq!(range, ByteRange::between(10, 25).unwrap());
+        assert_eq!(range.to_string(), "10..25");
+        let open: ByteRange = "7..".parse().unwrap();
+        assert_eq!(open.length, None);
+        assert_eq!("..4".parse::<ByteRange>().unwrap().length, Some(4));
+        assert!("9..3".parse::<ByteRange>().is_err());
+        assert!("12".parse::<ByteRange>().is_err());
+    }
+
+    #[test]
+    fn test_map_file() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&(0..=255u8).collect::<Vec<_>>()).unwrap();
+        let slice = ByteRange::between(5000, 5003).unwrap();
+        assert!(slice.map_file(&file).unwrap().is_empty());
+        let slice = ByteRange::between(1, 200).unwrap();
+        assert_eq!(
+            &slice.map_file(&file).unwrap()[..],
+            &(1..200u8).collect::<Vec<_>>()[..]
+        );
+        let tail = ByteRange {
+            offset: 250,
+            length: Some(100),
+        };
+        assert_eq!(
+            &tail.map_file(&file).unwrap()[..],
+            &[250, 251, 252, 253, 254, 255]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limit_reader() {
+        let input: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let range = ByteRange::between(20_000, 20_010).unwrap();
+        let mut reader = range.limit_reader(Box::new(std::io::Cursor::new(input.clone())));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        assert_eq!(out, &input[20_000..20_010]);
+    }
+}
@@ -0,0 +1,243 @@
+//! Shared plumbing for columnar output formats (Arrow IPC, Parquet).
+//!
+//! The pipeline produces a flat stream of big-endian `u16` tokens. [`ColumnarWriter`] cuts that
+//! stream into one row per document (`doc_id`, `source`, `tokens`), where whoever writes the
+//! documents starts each one through [`DocumentLabels`], and hands the rows to a
+//! [`BatchEncoder`] in batches of [`BatchEncoder::batch_rows`] as they complete, so only one
+//! batch is held in memory at a time.
+
+use super::{DocumentLabels, OutputWriter};
+use arrow_array::types::UInt16Type;
+use arrow_array::{ArrayRef, ListArray, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+
+/// Serializes batches of tokenized documents into a columnar file format.
+pub trait BatchEncoder: Send + Unpin {
+    /// The number of documents to collect before encoding them as a batch.
+    fn batch_rows(&self) -> usize;
+
+    /// Encodes `batch`, appending the bytes ready for the output to `out`.
+    fn write(&mut self, batch: &RecordBatch, out: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Completes the file or stream, appending its remaining bytes to `out`.
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+impl DocumentLabels {
+    fn take(&self) -> VecDeque<(u64, String)> {
+        std::mem::take(&mut *self.0.lock().expect("document labels lock poisoned"))
+    }
+}
+
+/// One document's row.
+struct Row {
+    doc_id: u64,
+    source: String,
+    token_bytes: Vec<u8>,
+}
+
+/// An `AsyncWrite` adapter that re-encodes the token stream with a [`BatchEncoder`].
+pub struct ColumnarWriter<E: BatchEncoder> {
+    inner: OutputWriter,
+    labels: DocumentLabels,
+    encoder: E,
+    /// The document being written, if one has started.
+    current: Option<Row>,
+    /// Completed documents not yet encoded.
+    rows: Vec<Row>,
+    /// Encoded bytes not yet written to `inner`.
+    pending: Vec<u8>,
+    written: usize,
+    finished: bool,
+}
+
+impl<E: BatchEncoder> ColumnarWriter<E> {
+    /// Wraps `inner`, taking the row of each document from `labels`. Tokens written before any
+    /// document has started form a row labelled `-`.
+    pub fn new(inner: OutputWriter, labels: DocumentLabels, encoder: E) -> Self {
+        Self {
+            inner,
+            labels,
+            encoder,
+            current: None,
+            rows: Vec::new(),
+            pending: Vec::new(),
+            written: 0,
+            finished: false,
+        }
+    }
+
+    /// Completes the current document, encoding a batch once enough documents are waiting.
+    fn end_document(&mut self) -> io::Result<()> {
+        if let Some(row) = self.current.take() {
+            self.rows.push(row);
+        }
+        if self.rows.len() >= self.encoder.batch_rows().max(1) {
+            self.encode_rows()?;
+        }
+        Ok(())
+    }
+
+    /// Starts the documents begun since the last write, ending the current one.
+    fn start_documents(&mut self) -> io::Result<()> {
+        for (doc_id, source) in self.labels.take() {
+            self.end_document()?;
+            self.current = Some(Row {
+                doc_id,
+                source,
+                token_bytes: Vec::new(),
+            });
+        }
+        Ok(())
+    }
+
+    fn encode_rows(&mut self) -> io::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let batch = build_record_batch(&std::mem::take(&mut self.rows))?;
+        self.encoder.write(&batch, &mut self.pending)
+    }
+
+    /// Writes the encoded bytes to `inner`.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let remaining = &self.pending[self.written..];
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, remaining))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<E: BatchEncoder> AsyncWrite for ColumnarWriter<E> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        this.start_documents()?;
+        let row = this.current.get_or_insert_with(|| Row {
+            doc_id: 0,
+            source: "-".to_string(),
+            token_bytes: Vec::new(),
+        });
+        row.token_bytes.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Documents still being collected are encoded once their batch is complete.
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if !this.finished {
+            this.finished = true;
+            this.start_documents()?;
+            if let Some(row) = this.current.take() {
+                this.rows.push(row);
+            }
+            this.encode_rows()?;
+            this.encoder.finish(&mut this.pending)?;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Returns the schema shared by all columnar output formats.
+pub fn token_schema() -> Schema {
+    let item = Field::new_list_field(DataType::UInt16, true);
+    Schema::new(vec![
+        Field::new("doc_id", DataType::UInt64, false),
+        Field::new("source", DataType::Utf8, false),
+        Field::new_list("tokens", item, false),
+    ])
+}
+
+fn decode_tokens(token_bytes: &[u8]) -> io::Result<Vec<u16>> {
+    if !token_bytes.len().is_multiple_of(2) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Columnar output requires a u16 token stream, got an odd number of bytes",
+        ));
+    }
+    Ok(token_bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+fn build_record_batch(rows: &[Row]) -> io::Result<RecordBatch> {
+    let doc_ids: ArrayRef = Arc::new(UInt64Array::from_iter_values(
+        rows.iter().map(|row| row.doc_id),
+    ));
+    let sources: ArrayRef = Arc::new(StringArray::from_iter_values(
+        rows.iter().map(|row| &row.source),
+    ));
+    let tokens = rows
+        .iter()
+        .map(|row| decode_tokens(&row.token_bytes))
+        .collect::<io::Result<Vec<_>>>()?;
+    let lists = tokens
+        .into_iter()
+        .map(|tokens| Some(tokens.into_iter().map(Some)));
+    let token_lists: ArrayRef = Arc::new(ListArray::from_iter_primitive::<UInt16Type, _, _>(lists));
+    RecordBatch::try_new(
+        Arc::new(token_schema()),
+        vec![doc_ids, sources, token_lists],
+    )
+    .map_err(arrow_to_io_error)
+}
+
+pub(super) fn arrow_to_io_error(e: ArrowError) -> io::Error {
+    io::Error::other(format!("Arrow encoding failed: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_tokens_rejects_odd_length() {
+        assert!(decode_tokens(&[0, 1, 2]).is_err());
+        assert_eq!(decode_tokens(&[0, 97, 1, 0]).unwrap(), vec![97, 256]);
+    }
+
+    #[test]
+    fn test_build_record_batch_one_row_per_document() {
+        let row = |doc_id, source: &str, token_bytes: &[u8]| Row {
+            doc_id,
+            source: source.to_string(),
+            token_bytes: token_bytes.to_vec(),
+        };
+        let rows = [row(0, "a.txt", &[0, 97, 0, 98]), row(3, "b.txt", &[])];
+        let batch = build_record_batch(&rows).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.num_columns(), 3);
+        assert_eq!(batch.schema().field(1).name(), "source");
+        let doc_ids = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(doc_ids.values().to_vec(), vec![0, 3]);
+        assert!(build_record_batch(&[row(0, "a.txt", &[0])]).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Output compression.
+//!
+//! [`Compression`] is always available so configurations can be parsed and validated; the
+//! encoders themselves require the `compression` feature.
+
+use super::OutputWriter;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// A compression codec applied to the whole output stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, with an optional level from 0 to 9.
+    Gzip {
+        /// The compression level; `None` uses the codec default.
+        level: Option<u32>,
+    },
+    /// Zstandard, with an optional level from 1 to 22.
+    Zstd {
+        /// The compression level; `None` uses the codec default.
+        level: Option<i32>,
+    },
+}
+
+impl Compression {
+    /// The conventional file extension for the codec.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip { .. } => "gz",
+            Compression::Zstd { .. } => "zst",
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Gzip { level: None } => write!(f, "gzip"),
+            Compression::Gzip { level: Some(level) } => write!(f, "gzip:{level}"),
+            Compression::Zstd { level: None } => write!(f, "zstd"),
+            Compression::Zstd { level: Some(level) } => write!(f, "zstd:{level}"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = io::Error;
+
+    /// Parses `codec[:level]`, e.g. `zstd`, `zstd:19`, or `gzip:6`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (codec, level) = match s.split_once(':') {
+            Some((codec, level)) => (codec, Some(level)),
+            None => (s, None),
+        };
+        let invalid_level = |level: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid compression level '{level}' for {codec}"),
+            )
+        };
+        match codec.to_ascii_lowercase().as_str() {
+            "gzip" | "gz" => {
+                let level = level
+                    .map(|l| {
+                        l.parse::<u32>()
+                            .ok()
+                            .filter(|l| *l <= 9)
+                            .ok_or_else(|| invalid_level(l))
+                    })
+                    .transpose()?;
+                Ok(Compression::Gzip { level })
+            }
+            "zstd" | "zst" => {
+                let level = level
+                    .map(|l| {
+                        l.parse::<i32>()
+                            .ok()
+                            .filter(|l| (1..=22).contains(l))
+                            .ok_or_else(|| invalid_level(l))
+                    })
+                    .transpose()?;
+                Ok(Compression::Zstd { level })
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported compression codec '{codec}' (expected zstd or gzip)"),
+            )),
+        }
+    }
+}
+
+/// Wraps `writer` with an encoder for `compression`.
+#[cfg(feature = "compression")]
+pub(super) fn compress_writer(
+    writer: OutputWriter,
+    compression: Compression,
+) -> io::Result<OutputWriter> {
+    use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+    use async_compression::Level;
+
+    Ok(match compression {
+        Compression::Gzip { level } => {
+            let level = level.map_or(Level::Default, |l| Level::Precise(l as i32));
+            Box::new(GzipEncoder::with_quality(writer, level))
+        }
+        Compression::Zstd { level } => {
+            let level = level.map_or(Level::Default, Level::Precise);
+            Box::new(ZstdEncoder::with_quality(writer, level))
+        }
+    })
+}
+
+#[cfg(not(feature = "compression"))]
+pub(super) fn compress_writer(
+    _writer: OutputWriter,
+    compression: Compression,
+) -> io::Result<OutputWriter> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{compression} output requires blt to be built with the `compression` feature"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_codecs_and_levels() {
+        assert_eq!(
+            "zstd".parse::<Compression>().unwrap(),
+            Compression::Zstd { level: None }
+        );
+        assert_eq!(
+            "zstd:19".parse::<Compression>().unwrap(),
+            Compression::Zstd { level: Some(19) }
+        );
+        assert_eq!(
+            "gzip:6".parse::<Compression>().unwrap(),
+            Compression::Gzip { level: Some(6) }
+        );
+        assert!("zstd:99".parse::<Compression>().is_err());
+        assert!("gzip:x".parse::<Compression>().is_err());
+        assert!("lz4".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        for spec in ["gzip", "gzip:9", "zstd", "zstd:3"] {
+            assert_eq!(spec.parse::<Compression>().unwrap().to_string(), spec);
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_zstd_writer_roundtrip() {
+        use tokio::io::AsyncWriteExt;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await.unwrap());
+        let mut writer = compress_writer(inner, Compression::Zstd { level: Some(3) }).unwrap();
+        writer.write_all(&[0u8; 4096]).await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        let compressed = std::fs::read(file.path()).unwrap();
+        assert!(compressed.len() < 4096);
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), vec![0u8; 4096]);
+    }
+}
@@ -0,0 +1,463 @@
+//! Demuxing MP4 (ISO BMFF) containers into metadata and per-track streams.
+//!
+//! Tokenized as one byte blob, a video file interleaves container boxes with the samples of
+//! every track, so merges learned on one track's codec bytes run into another's. Demuxing
+//! turns the file into separate documents, each opened by a [`SegmentKind`] token: first the
+//! container metadata (every top-level box but `mdat`, in file order), then one document per
+//! track holding its samples in decoding order, located through the track's sample tables.
+//!
+//! Fragmented files, whose samples are described by `moof` boxes rather than sample tables,
+//! have the payload of each `mdat` box as a single [`SegmentKind::Data`] document instead.
+//! Bytes inside `mdat` that no track refers to are not tokenized.
+
+use super::{InputDocument, InputSource};
+use crate::special_tokens;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The kinds of segment a container is demuxed into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// Container boxes: file type, movie header, track and sample tables.
+    Metadata,
+    /// The samples of a video track (`vide` handler).
+    Video,
+    /// The samples of an audio track (`soun` handler).
+    Audio,
+    /// The samples of any other track, or media data not assigned to tracks.
+    Data,
+}
+
+impl SegmentKind {
+    /// The token opening segments of this kind.
+    pub fn token(self) -> u16 {
+        special_tokens::SEGMENT_RANGE.start() + self as u16
+    }
+
+    fn from_handler(handler: &[u8]) -> Self {
+        match handler {
+            b"vide" => SegmentKind::Video,
+            b"soun" => SegmentKind::Audio,
+            _ => SegmentKind::Data,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SegmentKind::Metadata => "metadata",
+            SegmentKind::Video => "video",
+            SegmentKind::Audio => "audio",
+            SegmentKind::Data => "data",
+        }
+    }
+}
+
+/// One demuxed segment: its kind and the byte ranges of the file it is made of.
+#[derive(Debug, PartialEq)]
+struct Segment {
+    kind: SegmentKind,
+    ranges: Vec<Range<usize>>,
+}
+
+/// The bytes of a demuxed file, shared by the readers of its segments.
+type FileBytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// Demuxes the MP4 file at `path` into one document per segment. The file is memory-mapped
+/// with `mmap`, and read into memory otherwise.
+pub(crate) fn open_demuxed(
+    path: &Path,
+    relative_path: &Path,
+    mmap: bool,
+) -> io::Result<Vec<InputDocument>> {
+    let bytes: FileBytes = match mmap {
+        // SAFETY: as for other mapped inputs, the file must not be truncated while mapped.
+        true => Arc::new(unsafe { Mmap::map(&File::open(path)?)? }),
+        false => Arc::new(std::fs::read(path)?),
+    };
+    let segments = demux((*bytes).as_ref()).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Cannot demux {} as MP4: {e}", path.display()),
+        )
+    })?;
+    let mut tracks = 0;
+    Ok(segments
+        .into_iter()
+        .map(|segment| {
+            let label = match segment.kind {
+                SegmentKind::Metadata => segment.kind.name().to_string(),
+                kind => {
+                    tracks += 1;
+                    format!("{}{tracks}", kind.name())
+                }
+            };
+            InputDocument {
+                name: format!("{}#{label}", path.display()),
+                relative_path: relative_path.join(&label),
+                tag: Some(segment.kind.token()),
+                source: InputSource::Reader(Box::new(RangesReader {
+                    bytes: bytes.clone(),
+                    ranges: segment.ranges.into_iter(),
+                    current: 0..0,
+                })),
+            }
+        })
+        .collect())
+}
+
+/// A box: its type, the range of the whole box, and the range of its payload.
+#[derive(Clone, Debug)]
+struct Mp4Box {
+    kind: [u8; 4],
+    range: Range<usize>,
+    payload: Range<usize>,
+}
+
+fn malformed(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parses the boxes filling `range` of `data`.
+fn boxes(data: &[u8], range: Range<usize>) -> io::Result<Vec<Mp4Box>> {
+    let mut boxes = Vec::new();
+    let mut start = range.start;
+    while start < range.end {
+        if range.end - start < 8 {
+            return Err(malformed(format!("truncated box header at byte {start}")));
+        }
+        let size = u64::from(read_u32(data, start)?);
+        let kind: [u8; 4] = data[start + 4..start + 8].try_into().unwrap();
+        let (header, size) = match size {
+            0 => (8, (range.end - start) as u64),
+            1 => (16, read_u64(data, start + 8)?),
+            size => (8, size),
+        };
+        let end = usize::try_from(size)
+            .ok()
+            .and_then(|size| start.checked_add(size))
+            .filter(|&end| size >= header && end <= range.end)
+            .ok_or_else(|| {
+                malformed(format!(
+                    "box '{}' at byte {start} does not fit its parent",
+                    String::from_utf8_lossy(&kind)
+                ))
+            })?;
+        boxes.push(Mp4Box {
+            kind,
+            range: start..end,
+            payload: start + header as usize..end,
+        });
+        start = end;
+    }
+    Ok(boxes)
+}
+
+/// Returns the first child of `parent` of type `kind`.
+fn child(data: &[u8], parent: &Mp4Box, kind: &[u8; 4]) -> io::Result<Option<Mp4Box>> {
+    Ok(boxes(data, parent.payload.clone())?
+        .into_iter()
+        .find(|b| &b.kind == kind))
+}
+
+fn read_u32(data: &[u8], at: usize) -> io::Result<u32> {
+    data.get(at..at + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| malformed(format!("unexpected end of data at byte {at}")))
+}
+
+fn read_u64(data: &[u8], at: usize) -> io::Result<u64> {
+    data.get(at..at + 8)
+        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| malformed(format!("unexpected end of data at byte {at}")))
+}
+
+/// Splits an MP4 file into its metadata segment and one segment per track with samples.
+fn demux(data: &[u8]) -> io::Result<Vec<Segment>> {
+    let top = boxes(data, 0..data.len())?;
+    if !top.iter().any(|b| &b.kind == b"ftyp" || &b.kind == b"moov") {
+        return Err(malformed("no 'ftyp' or 'moov' box".to_string()));
+    }
+    let mut segments = vec![Segment {
+        kind: SegmentKind::Metadata,
+        ranges: top
+            .iter()
+            .filter(|b| &b.kind != b"mdat")
+            .map(|b| b.range.clone())
+            .collect(),
+    }];
+    for moov in top.iter().filter(|b| &b.kind == b"moov") {
+        for trak in boxes(data, moov.payload.clone())?
+            .iter()
+            .filter(|b| &b.kind == b"trak")
+        {
+            if let Some(segment) = track_segment(data, trak)? {
+                segments.push(segment);
+            }
+        }
+    }
+    if segments.len() == 1 {
+        segments.extend(
+            top.iter()
+                .filter(|b| &b.kind == b"mdat" && !b.payload.is_empty())
+                .map(|b| Segment {
+                    kind: SegmentKind::Data,
+                    ranges: vec![b.payload.clone()],
+                }),
+        );
+    }
+    Ok(segments)
+}
+
+/// Locates the samples of a track through its `stbl` sample tables.
+fn track_segment(data: &[u8], trak: &Mp4Box) -> io::Result<Option<Segment>> {
+    let Some(mdia) = child(data, trak, b"mdia")? else {
+        return Ok(None);
+    };
+    let kind = match child(data, &mdia, b"hdlr")? {
+        Some(hdlr) => data
+            .get(hdlr.payload.start + 8..hdlr.payload.start + 12)
+            .map_or(SegmentKind::Data, SegmentKind::from_handler),
+        None => SegmentKind::Data,
+    };
+    let Some(minf) = child(data, &mdia, b"minf")? else {
+        return Ok(None);
+    };
+    let Some(stbl) = child(data, &minf, b"stbl")? else {
+        return Ok(None);
+    };
+    let ranges = sample_ranges(data, &stbl)?;
+    Ok((!ranges.is_empty()).then_some(Segment { kind, ranges }))
+}
+
+/// Returns the byte ranges of a track's samples in decoding order, merging adjacent ones.
+fn sample_ranges(data: &[u8], stbl: &Mp4Box) -> io::Result<Vec<Range<usize>>> {
+    let (Some(stsz), Some(stsc)) = (child(data, stbl, b"stsz")?, child(data, stbl, b"stsc")?)
+    else {
+        return Ok(Vec::new());
+    };
+    let (offsets, offset_width) = match child(data, stbl, b"stco")? {
+        Some(stco) => (stco, 4),
+        None => match child(data, stbl, b"co64")? {
+            Some(co64) => (co64, 8),
+            None => return Ok(Vec::new()),
+        },
+    };
+
+    let fixed_size = read_u32(data, stsz.payload.start + 4)?;
+    let sample_count = read_u32(data, stsz.payload.start + 8)? as usize;
+    let sizes_at = stsz.payload.start + 12;
+    if fixed_size == 0 && sample_count > stsz.payload.end.saturating_sub(sizes_at) / 4 {
+        return Err(malformed(
+            "'stsz' lists more samples than it holds".to_string(),
+        ));
+    }
+    let stsc_entries = read_u32(data, stsc.payload.start + 4)? as usize;
+    if stsc_entries > stsc.payload.len().saturating_sub(8) / 12 {
+        return Err(malformed(
+            "'stsc' lists more entries than it holds".to_string(),
+        ));
+    }
+    let chunk_count = read_u32(data, offsets.payload.start + 4)? as usize;
+    if chunk_count > offsets.payload.len().saturating_sub(8) / offset_width {
+        return Err(malformed(
+            "chunk offset table lists more chunks than it holds".to_string(),
+        ));
+    }
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut sample = 0;
+    let mut entry = 0;
+    for chunk in 0..chunk_count {
+        // Each stsc entry applies from its first chunk (1-based) until the next entry's.
+        while entry + 1 < stsc_entries
+            && read_u32(data, stsc.payload.start + 8 + (entry + 1) * 12)? as usize <= chunk + 1
+        {
+            entry += 1;
+        }
+        let samples_per_chunk = match stsc_entries {
+            0 => 0,
+            _ => read_u32(data, stsc.payload.start + 8 + entry * 12 + 4)? as usize,
+        };
+        let offset_at = offsets.payload.start + 8 + chunk * offset_width;
+        let mut offset = match offset_width {
+            4 => u64::from(read_u32(data, offset_at)?),
+            _ => read_u64(data, offset_at)?,
+        };
+        for _ in 0..samples_per_chunk {
+            if sample == sample_count {
+                break;
+            }
+            let size = match fixed_size {
+                0 => read_u32(data, sizes_at + sample * 4)?,
+                size => size,
+            };
+            sample += 1;
+            let end = offset.checked_add(u64::from(size)).ok_or_else(|| {
+                malformed(format!("sample {sample} at byte {offset} ends past 2^64"))
+            })?;
+            if end > data.len() as u64 {
+                return Err(malformed(format!(
+                    "sample {sample} ends at byte {end}, past the end of the file"
+                )));
+            }
+            let range = offset as usize..end as usize;
+            match ranges.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ if range.is_empty() => {}
+                _ => ranges.push(range),
+            }
+            offset = end;
+        }
+    }
+    Ok(ranges)
+}
+
+/// Reads a sequence of byte ranges of a shared file.
+struct RangesReader {
+    bytes: FileBytes,
+    ranges: std::vec::IntoIter<Range<usize>>,
+    current: Range<usize>,
+}
+
+impl AsyncRead for RangesReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.current.is_empty() {
+            match self.ranges.next() {
+                Some(range) => self.current = range,
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let take = buf.remaining().min(self.current.len());
+        let start = self.current.start;
+        buf.put_slice(&(*self.bytes).as_ref()[start..start + take]);
+        self.current.start += take;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn full_box(kind: &[u8; 4], words: &[u32]) -> Vec<u8> {
+        let mut payload = vec![0; 4];
+        for word in words {
+            payload.extend_from_slice(&word.to_be_bytes());
+        }
+        mp4_box(kind, &payload)
+    }
+
+    /// A track whose samples have `sizes` and sit in chunks at `offsets`, `per_chunk` each.
+    fn trak(handler: &[u8; 4], sizes: &[u32], offsets: &[u32], per_chunk: u32) -> Vec<u8> {
+        let hdlr = mp4_box(b"hdlr", &[&[0; 8][..], handler, &[0; 12]].concat());
+        let stsz = full_box(
+            b"stsz",
+            &[[0, sizes.len() as u32].as_slice(), sizes].concat(),
+        );
+        let stsc = full_box(b"stsc", &[1, 1, per_chunk, 1]);
+        let stco = full_box(
+            b"stco",
+            &[[offsets.len() as u32].as_slice(), offsets].concat(),
+        );
+        let stbl = mp4_box(b"stbl", &[stsz, stsc, stco].concat());
+        let minf = mp4_box(b"minf", &stbl);
+        mp4_box(b"trak", &mp4_box(b"mdia", &[hdlr, minf].concat()))
+    }
+
+    #[test]
+    fn test_demuxes_tracks_from_sample_tables() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        // The mdat payload interleaves video ("VV", "vvv") and audio ("aa", "AA") samples.
+        let payload = b"VVaaAAvvv";
+        let mdat_len = payload.len() + 8;
+        let moov_of = |mdat_at: u32| {
+            let video = trak(b"vide", &[2, 3], &[mdat_at + 8, mdat_at + 14], 1);
+            let audio = trak(b"soun", &[2, 2], &[mdat_at + 10], 2);
+            mp4_box(b"moov", &[video, audio].concat())
+        };
+        // The moov size does not depend on the offsets it holds.
+        let moov_len = moov_of(0).len();
+        let mdat_at = (ftyp.len() + moov_len) as u32;
+        let file = [ftyp.clone(), moov_of(mdat_at), mp4_box(b"mdat", payload)].concat();
+        assert_eq!(file.len(), ftyp.len() + moov_len + mdat_len);
+
+        let segments = demux(&file).unwrap();
+        let bytes = |segment: &Segment| -> Vec<u8> {
+            segment
+                .ranges
+                .iter()
+                .flat_map(|range| file[range.clone()].to_vec())
+                .collect()
+        };
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].kind, SegmentKind::Metadata);
+        assert_eq!(bytes(&segments[0]), file[..mdat_at as usize]);
+        assert_eq!(segments[1].kind, SegmentKind::Video);
+        assert_eq!(bytes(&segments[1]), b"VVvvv");
+        assert_eq!(segments[2].kind, SegmentKind::Audio);
+        // The audio chunk's samples are adjacent, so they form one range.
+        assert_eq!(segments[2].ranges.len(), 1);
+        assert_eq!(bytes(&segments[2]), b"aaAA");
+    }
+
+    #[test]
+    fn test_falls_back_to_media_data_and_rejects_other_files() {
+        let file = [
+            mp4_box(b"ftyp", b"isom"),
+            mp4_box(b"moof", b""),
+            mp4_box(b"mdat", b"frames"),
+        ]
+        .concat();
+        let segments = demux(&file).unwrap();
+        assert_eq!(segments[1].kind, SegmentKind::Data);
+        assert_eq!(file[segments[1].ranges[0].clone()], *b"frames");
+
+        assert!(demux(b"plain text, not boxes").is_err());
+        let mut truncated = mp4_box(b"ftyp", b"isom");
+        truncated.truncate(10);
+        assert_eq!(
+            demux(&truncated).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(special_tokens::is_structural(SegmentKind::Data.token()));
+    }
+
+    #[test]
+    fn test_rejects_sample_offsets_that_overflow() {
+        let hdlr = mp4_box(b"hdlr", &[&[0; 8][..], b"vide", &[0; 12]].concat());
+        let stsz = full_box(b"stsz", &[0, 1, 16]);
+        let stsc = full_box(b"stsc", &[1, 1, 1, 1]);
+        // One 64-bit chunk offset, 8 bytes short of the end of the address space.
+        let co64 = full_box(b"co64", &[1, u32::MAX, u32::MAX - 7]);
+        let stbl = mp4_box(b"stbl", &[stsz, stsc, co64].concat());
+        let minf = mp4_box(b"minf", &stbl);
+        let trak = mp4_box(b"trak", &mp4_box(b"mdia", &[hdlr, minf].concat()));
+        let file = [
+            mp4_box(b"ftyp", b"isom"),
+            mp4_box(b"moov", &trak),
+            mp4_box(b"mdat", b"frames"),
+        ]
+        .concat();
+        let err = demux(&file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("past 2^64"), "{err}");
+    }
+}
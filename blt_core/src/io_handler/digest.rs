@@ -0,0 +1,153 @@
+//! Hashing everything a run writes.
+//!
+//! An [`OutputDigest`] is shared by every output of a run. [`HashingWriter`] feeds it the bytes
+//! each output accepts, in write order, so two runs hash equal exactly when they wrote the same
+//! bytes to the same outputs. Once the digest is told the hash the run must match, the next
+//! writer to shut down checks it first, so a mismatched output is never committed.
+
+use super::OutputWriter;
+use crate::hashing::{ContentHasher, HashAlgorithm};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+
+/// The running hash of a run's outputs.
+pub(crate) struct OutputDigest {
+    hasher: Mutex<Option<ContentHasher>>,
+    /// The rendered hash, once finalized.
+    hash: OnceLock<String>,
+    /// The hash checked when the next output shuts down.
+    expected: Mutex<Option<String>>,
+}
+
+impl OutputDigest {
+    pub(crate) fn new(algorithm: HashAlgorithm) -> Arc<Self> {
+        Arc::new(Self {
+            hasher: Mutex::new(Some(ContentHasher::new(algorithm))),
+            hash: OnceLock::new(),
+            expected: Mutex::new(None),
+        })
+    }
+
+    fn update(&self, data: &[u8]) {
+        if let Some(hasher) = self.hasher.lock().expect("digest lock poisoned").as_mut() {
+            hasher.update(data);
+        }
+    }
+
+    /// Renders the hash of everything written so far as `"<algorithm>:<hex>"`. Later writes
+    /// are ignored, and later calls return the same hash, so this is called once every output
+    /// is finished.
+    pub(crate) fn finalize(&self) -> String {
+        self.hash
+            .get_or_init(|| {
+                self.hasher
+                    .lock()
+                    .expect("digest lock poisoned")
+                    .take()
+                    .expect("the hasher is only taken here")
+                    .finalize()
+            })
+            .clone()
+    }
+
+    /// Makes the next output to shut down fail, before its inner writer shuts down, unless
+    /// everything hashed by then matches `expected`. Set before finishing the last output.
+    pub(crate) fn expect(&self, expected: String) {
+        *self.expected.lock().expect("digest lock poisoned") = Some(expected);
+    }
+
+    /// Finalizes the hash and fails unless it is `expected`, ignoring case.
+    pub(crate) fn verify(&self, expected: &str) -> io::Result<String> {
+        let actual = self.finalize();
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Output hash {actual} does not match the expected {expected}"),
+            ));
+        }
+        Ok(actual)
+    }
+}
+
+/// Hashes the bytes the inner writer accepts into a shared [`OutputDigest`].
+pub(crate) struct HashingWriter {
+    inner: OutputWriter,
+    digest: Arc<OutputDigest>,
+}
+
+impl HashingWriter {
+    pub(crate) fn new(inner: OutputWriter, digest: Arc<OutputDigest>) -> Self {
+        Self { inner, digest }
+    }
+}
+
+impl AsyncWrite for HashingWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.digest.update(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let expected = self
+            .digest
+            .expected
+            .lock()
+            .expect("digest lock poisoned")
+            .clone();
+        if let Some(expected) = expected {
+            self.digest.verify(&expected)?;
+        }
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::hash_bytes;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_digest_spans_outputs() {
+        let digest = OutputDigest::new(HashAlgorithm::Sha256);
+        for part in [&b"hello "[..], &b"world"[..]] {
+            let mut writer = HashingWriter::new(Box::<Vec<u8>>::default(), digest.clone());
+            writer.write_all(part).await.unwrap();
+            writer.shutdown().await.unwrap();
+        }
+        assert_eq!(
+            digest.finalize(),
+            hash_bytes(HashAlgorithm::Sha256, b"hello world")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_hash_fails_before_inner_shutdown() {
+        let digest = OutputDigest::new(HashAlgorithm::Sha256);
+        let expected = hash_bytes(HashAlgorithm::Sha256, b"hello");
+        let mut writer = HashingWriter::new(Box::<Vec<u8>>::default(), digest.clone());
+        writer.write_all(b"hello").await.unwrap();
+        digest.expect(expected.to_uppercase());
+        writer.shutdown().await.unwrap();
+
+        let digest = OutputDigest::new(HashAlgorithm::Sha256);
+        let mut writer = HashingWriter::new(Box::<Vec<u8>>::default(), digest.clone());
+        writer.write_all(b"jello").await.unwrap();
+        digest.expect(expected.clone());
+        let err = writer.shutdown().await.unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+        assert!(digest.verify(&expected).is_err());
+    }
+}
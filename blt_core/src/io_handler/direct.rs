@@ -0,0 +1,244 @@
+//! Direct I/O for output files.
+//!
+//! Writing a huge output through the page cache evicts the working sets of everything else on
+//! the machine, for data that will not be read back soon. With `CoreConfig::direct_io` output
+//! files are opened with `O_DIRECT` and written in large blocks from a buffer aligned to
+//! [`ALIGN`] bytes, bypassing the cache. `O_DIRECT` writes must cover whole blocks, so the final
+//! partial block is written with `O_DIRECT` cleared when the writer shuts down, and a flush only
+//! writes the whole blocks buffered so far.
+//!
+//! Filesystems that refuse `O_DIRECT`, such as some network and in-memory filesystems, are
+//! written through the page cache instead, with a warning.
+
+use super::OutputWriter;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// The alignment of buffers, file offsets, and write lengths: the largest logical block size
+/// in common use.
+const ALIGN: usize = 4096;
+/// Bytes buffered before a write is issued.
+const CAPACITY: usize = 1 << 20;
+
+/// Writes `file` with `O_DIRECT` from its start, or through tokio if the filesystem does not
+/// support direct I/O.
+pub(crate) fn writer(file: File) -> io::Result<OutputWriter> {
+    if let Err(error) = set_direct(&file, true) {
+        warn!(%error, "Direct I/O is not supported for the output; writing through the page cache");
+        return Ok(Box::new(tokio::fs::File::from_std(file)));
+    }
+    Ok(Box::new(DirectWriter {
+        state: State::Idle(Box::new(Blocks {
+            file,
+            buffer: AlignedBuffer::new(),
+        })),
+    }))
+}
+
+/// Sets or clears `O_DIRECT` on an open file.
+fn set_direct(file: &File, direct: bool) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    // SAFETY: `fd` is a valid descriptor owned by `file` for the duration of both calls.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = match direct {
+        true => flags | libc::O_DIRECT,
+        false => flags & !libc::O_DIRECT,
+    };
+    // SAFETY: as above.
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A fixed-size buffer whose data starts at an [`ALIGN`]-byte boundary.
+struct AlignedBuffer {
+    storage: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    fn new() -> Self {
+        let storage = vec![0u8; CAPACITY + ALIGN];
+        let start = storage.as_ptr().align_offset(ALIGN);
+        Self {
+            storage,
+            start,
+            len: 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.storage[self.start..self.start + self.len]
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == CAPACITY
+    }
+
+    /// Copies as much of `buf` as fits, returning the number of bytes taken.
+    fn extend(&mut self, buf: &[u8]) -> usize {
+        let n = buf.len().min(CAPACITY - self.len);
+        let end = self.start + self.len;
+        self.storage[end..end + n].copy_from_slice(&buf[..n]);
+        self.len += n;
+        n
+    }
+
+    /// The length of the whole blocks buffered.
+    fn whole_blocks(&self) -> usize {
+        self.len / ALIGN * ALIGN
+    }
+
+    /// Drops the first `n` bytes, moving the rest to the start of the buffer.
+    fn consume(&mut self, n: usize) {
+        let start = self.start;
+        self.storage.copy_within(start + n..start + self.len, start);
+        self.len -= n;
+    }
+}
+
+/// The file and the bytes buffered for it.
+struct Blocks {
+    file: File,
+    buffer: AlignedBuffer,
+}
+
+impl Blocks {
+    /// Writes the whole blocks buffered, keeping any partial block.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        let whole = self.buffer.whole_blocks();
+        self.file.write_all(&self.buffer.data()[..whole])?;
+        self.buffer.consume(whole);
+        Ok(())
+    }
+
+    /// Writes everything buffered, the partial block last and without `O_DIRECT`.
+    fn write_out(&mut self) -> io::Result<()> {
+        self.write_blocks()?;
+        if self.buffer.len > 0 {
+            set_direct(&self.file, false)?;
+            self.file.write_all(self.buffer.data())?;
+            self.buffer.consume(self.buffer.len);
+        }
+        Ok(())
+    }
+}
+
+enum State {
+    /// Ready to buffer writes.
+    Idle(Box<Blocks>),
+    /// Writing on a blocking thread.
+    Busy(JoinHandle<(Box<Blocks>, io::Result<()>)>),
+    /// Shut down.
+    Done,
+}
+
+/// Buffers writes into aligned blocks and writes them with `O_DIRECT` on a blocking thread.
+struct DirectWriter {
+    state: State,
+}
+
+impl DirectWriter {
+    /// Waits for a write in progress, returning its result.
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut Box<Blocks>>> {
+        if let State::Busy(handle) = &mut self.state {
+            let (blocks, result) = ready!(Pin::new(handle).poll(cx)).map_err(io::Error::other)?;
+            self.state = State::Idle(blocks);
+            result?;
+        }
+        match &mut self.state {
+            State::Idle(blocks) => Poll::Ready(Ok(blocks)),
+            State::Busy(_) => unreachable!("the write in progress has finished"),
+            State::Done => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The direct I/O writer is shut down",
+            ))),
+        }
+    }
+
+    /// Runs `write` on the buffered blocks on a blocking thread.
+    fn spawn(&mut self, write: fn(&mut Blocks) -> io::Result<()>) {
+        let State::Idle(mut blocks) = std::mem::replace(&mut self.state, State::Done) else {
+            unreachable!("writes start from the idle state");
+        };
+        self.state = State::Busy(tokio::task::spawn_blocking(move || {
+            let result = write(&mut blocks);
+            (blocks, result)
+        }));
+    }
+}
+
+impl AsyncWrite for DirectWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let blocks = ready!(self.poll_idle(cx))?;
+        if !blocks.buffer.is_full() {
+            return Poll::Ready(Ok(blocks.buffer.extend(buf)));
+        }
+        self.spawn(Blocks::write_blocks);
+        let blocks = ready!(self.poll_idle(cx))?;
+        Poll::Ready(Ok(blocks.buffer.extend(buf)))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let blocks = ready!(self.poll_idle(cx))?;
+        if blocks.buffer.whole_blocks() > 0 {
+            self.spawn(Blocks::write_blocks);
+            ready!(self.poll_idle(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if matches!(self.state, State::Done) {
+            return Poll::Ready(Ok(()));
+        }
+        let blocks = ready!(self.poll_idle(cx))?;
+        if blocks.buffer.len > 0 {
+            self.spawn(Blocks::write_out);
+            ready!(self.poll_idle(cx))?;
+        }
+        self.state = State::Done;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_writes_whole_blocks_then_the_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.bin");
+        // Two full buffers and a partial block, written in pieces that straddle blocks.
+        let data: Vec<u8> = (0..2 * CAPACITY + ALIGN / 2 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        let mut writer = writer(File::create(&path).unwrap()).unwrap();
+        for piece in data.chunks(10_007) {
+            writer.write_all(piece).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+}
@@ -0,0 +1,428 @@
+//! Output encryption at rest.
+//!
+//! Encrypted outputs use AES-256-GCM in a segmented streaming construction, so they can be
+//! written and read back without holding a whole shard in memory, and a truncated or
+//! reordered file fails to decrypt instead of yielding part of the token stream.
+//!
+//! Layout:
+//!
+//! | Offset | Size | Field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 4    | magic `b"BLTE"`                                    |
+//! | 4      | 1    | format version                                     |
+//! | 5      | 16   | random salt                                        |
+//! | 21     | ...  | segments of up to 64 KiB of plaintext, plus a 16-byte tag each |
+//!
+//! Each file is encrypted with its own key, the SHA-256 of a domain label, the configured
+//! key, and the salt. Segment `i` uses the 12-byte nonce `i` (11 bytes, big-endian) followed
+//! by `1` for the last segment and `0` otherwise. The last segment may be empty.
+//!
+//! [`EncryptionKey`] is always available so configurations can be parsed and validated; the
+//! cipher itself requires the `encryption` feature.
+
+use super::OutputWriter;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A 256-bit key for encrypting outputs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Wraps a raw key.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Reads a key file holding either 64 hex digits or exactly 32 raw bytes.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Key file '{}' must hold 64 hex digits or 32 raw bytes",
+                    path.display()
+                ),
+            )
+        };
+        // Too short for hex, so the key itself.
+        if let Ok(raw) = <[u8; 32]>::try_from(contents.as_slice()) {
+            return Ok(Self(raw));
+        }
+        let hex = std::str::from_utf8(&contents)
+            .map_err(|_| invalid())?
+            .trim();
+        if hex.len() != 64 {
+            return Err(invalid());
+        }
+        let mut key = [0; 32];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(key))
+    }
+}
+
+/// Keys never appear in logs or debug output.
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod enabled {
+    use super::*;
+    use aes_gcm::aead::rand_core::RngCore;
+    use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+    use aes_gcm::{Aes256Gcm, Nonce};
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+    use std::pin::Pin;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::AsyncWrite;
+
+    /// The magic bytes that open every encrypted output.
+    const MAGIC: [u8; 4] = *b"BLTE";
+    /// The encrypted format version written by this version of blt.
+    const FORMAT_VERSION: u8 = 1;
+    /// The length of the header before the first segment.
+    pub(super) const HEADER_LEN: usize = 21;
+    /// The length of the random salt in the header.
+    const SALT_LEN: usize = 16;
+    /// The most plaintext bytes in one segment.
+    pub(super) const SEGMENT_LEN: usize = 64 * 1024;
+    /// The length of the authentication tag after each segment.
+    pub(super) const TAG_LEN: usize = 16;
+
+    /// Derives the key of the file with `salt`.
+    fn file_cipher(key: &EncryptionKey, salt: &[u8]) -> Aes256Gcm {
+        let mut hasher = Sha256::new();
+        hasher.update(b"blt-encryption-v1");
+        hasher.update(key.0);
+        hasher.update(salt);
+        Aes256Gcm::new(&hasher.finalize())
+    }
+
+    fn nonce(segment: u64, last: bool) -> Nonce<aes_gcm::aead::consts::U12> {
+        let mut nonce = [0; 12];
+        nonce[3..11].copy_from_slice(&segment.to_be_bytes());
+        nonce[11] = u8::from(last);
+        nonce.into()
+    }
+
+    fn crypto_error(_: aes_gcm::Error) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Encrypted output failed to authenticate: wrong key, or the file is damaged or truncated",
+        )
+    }
+
+    /// Encrypts everything written through it, one segment at a time.
+    struct EncryptingWriter {
+        inner: OutputWriter,
+        cipher: Aes256Gcm,
+        segment: u64,
+        plaintext: Vec<u8>,
+        /// Encrypted bytes not yet accepted by `inner`, from `written` on.
+        pending: Vec<u8>,
+        written: usize,
+        finished: bool,
+    }
+
+    impl EncryptingWriter {
+        fn new(inner: OutputWriter, key: &EncryptionKey) -> Self {
+            let mut salt = [0; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let mut pending = Vec::with_capacity(HEADER_LEN);
+            pending.extend_from_slice(&MAGIC);
+            pending.push(FORMAT_VERSION);
+            pending.extend_from_slice(&salt);
+            Self {
+                inner,
+                cipher: file_cipher(key, &salt),
+                segment: 0,
+                plaintext: Vec::with_capacity(SEGMENT_LEN),
+                pending,
+                written: 0,
+                finished: false,
+            }
+        }
+
+        /// Encrypts the buffered plaintext as the next segment.
+        fn seal(&mut self, last: bool) -> io::Result<()> {
+            let payload = Payload {
+                msg: &self.plaintext,
+                aad: &[],
+            };
+            let sealed = self
+                .cipher
+                .encrypt(&nonce(self.segment, last), payload)
+                .map_err(|_| io::Error::other("Failed to encrypt output segment"))?;
+            self.pending.extend_from_slice(&sealed);
+            self.plaintext.clear();
+            self.segment += 1;
+            Ok(())
+        }
+
+        /// Writes out the pending encrypted bytes.
+        fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            while self.written < self.pending.len() {
+                let n = ready!(
+                    Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..])
+                )?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.written += n;
+            }
+            self.pending.clear();
+            self.written = 0;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for EncryptingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            ready!(this.poll_drain(cx))?;
+            // A full segment is only sealed once more data arrives, since the last segment
+            // is marked as such.
+            if this.plaintext.len() == SEGMENT_LEN && !buf.is_empty() {
+                this.seal(false)?;
+                ready!(this.poll_drain(cx))?;
+            }
+            let n = buf.len().min(SEGMENT_LEN - this.plaintext.len());
+            this.plaintext.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            ready!(this.poll_drain(cx))?;
+            Pin::new(&mut this.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            if !this.finished {
+                ready!(this.poll_drain(cx))?;
+                this.seal(true)?;
+                this.finished = true;
+            }
+            ready!(this.poll_drain(cx))?;
+            Pin::new(&mut this.inner).poll_shutdown(cx)
+        }
+    }
+
+    /// Wraps `writer` so everything written through it is encrypted with `key`.
+    pub(in crate::io_handler) fn encrypt_writer(
+        writer: OutputWriter,
+        key: &EncryptionKey,
+    ) -> io::Result<OutputWriter> {
+        Ok(Box::new(EncryptingWriter::new(writer, key)))
+    }
+
+    /// The length of one sealed segment, except possibly the last.
+    const FRAME_LEN: usize = SEGMENT_LEN + TAG_LEN;
+
+    /// Decrypts an encrypted output as it is read, one segment at a time.
+    ///
+    /// Each segment is authenticated before any of its plaintext is returned, and the
+    /// segment after it is read ahead to tell whether it is the last. A truncated or reordered
+    /// file fails with [`io::ErrorKind::InvalidData`] once the damage is reached, after the
+    /// segments before it have been returned.
+    pub struct DecryptingReader<R> {
+        inner: R,
+        cipher: Aes256Gcm,
+        segment: u64,
+        /// The next sealed segment, empty once the input is exhausted.
+        next: Vec<u8>,
+        plaintext: Vec<u8>,
+        /// How much of `plaintext` has been returned.
+        position: usize,
+        finished: bool,
+    }
+
+    impl<R: Read> DecryptingReader<R> {
+        /// Reads and checks the header of the encrypted output `inner`, and the first segment.
+        pub fn new(mut inner: R, key: &EncryptionKey) -> io::Result<Self> {
+            let mut header = [0; HEADER_LEN];
+            let not_encrypted =
+                || io::Error::new(io::ErrorKind::InvalidData, "Not an encrypted blt output");
+            match inner.read_exact(&mut header) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(not_encrypted()),
+                result => result?,
+            }
+            if header[..4] != MAGIC {
+                return Err(not_encrypted());
+            }
+            if header[4] != FORMAT_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported encrypted output version {}", header[4]),
+                ));
+            }
+            let mut reader = Self {
+                inner,
+                cipher: file_cipher(key, &header[5..]),
+                segment: 0,
+                next: Vec::with_capacity(FRAME_LEN),
+                plaintext: Vec::with_capacity(SEGMENT_LEN),
+                position: 0,
+                finished: false,
+            };
+            reader.read_frame()?;
+            Ok(reader)
+        }
+
+        /// Fills `next` with the following sealed segment, or as much of it as remains.
+        fn read_frame(&mut self) -> io::Result<()> {
+            self.next.clear();
+            (&mut self.inner)
+                .take(FRAME_LEN as u64)
+                .read_to_end(&mut self.next)?;
+            Ok(())
+        }
+
+        /// Authenticates and decrypts the next segment into `plaintext`.
+        fn open_segment(&mut self) -> io::Result<()> {
+            // An output always ends with a last segment, even an empty one.
+            let sealed = std::mem::replace(&mut self.next, Vec::with_capacity(FRAME_LEN));
+            self.read_frame()?;
+            let last = self.next.is_empty();
+            let payload = Payload {
+                msg: &sealed,
+                aad: &[],
+            };
+            self.plaintext = self
+                .cipher
+                .decrypt(&nonce(self.segment, last), payload)
+                .map_err(crypto_error)?;
+            self.position = 0;
+            self.segment += 1;
+            self.finished = last;
+            Ok(())
+        }
+    }
+
+    impl<R: Read> Read for DecryptingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.position == self.plaintext.len() {
+                if self.finished {
+                    return Ok(0);
+                }
+                self.open_segment()?;
+            }
+            let n = buf.len().min(self.plaintext.len() - self.position);
+            buf[..n].copy_from_slice(&self.plaintext[self.position..self.position + n]);
+            self.position += n;
+            Ok(n)
+        }
+    }
+
+    /// Decrypts a whole encrypted output held in memory. Use [`DecryptingReader`] to decrypt
+    /// without holding the plaintext.
+    pub fn decrypt(key: &EncryptionKey, encrypted: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plaintext = Vec::with_capacity(encrypted.len());
+        DecryptingReader::new(encrypted, key)?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(feature = "encryption")]
+pub(super) use enabled::encrypt_writer;
+#[cfg(feature = "encryption")]
+pub use enabled::{decrypt, DecryptingReader};
+
+#[cfg(not(feature = "encryption"))]
+pub(super) fn encrypt_writer(
+    _writer: OutputWriter,
+    _key: &EncryptionKey,
+) -> io::Result<OutputWriter> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Encrypted output requires blt to be built with the `encryption` feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_key_from_path() {
+        let mut hex = tempfile::NamedTempFile::new().unwrap();
+        writeln!(hex, "{}", "0f".repeat(32)).unwrap();
+        assert_eq!(
+            EncryptionKey::from_path(hex.path()).unwrap(),
+            EncryptionKey::new([0x0f; 32])
+        );
+
+        let mut raw = tempfile::NamedTempFile::new().unwrap();
+        raw.write_all(&[0xAB; 32]).unwrap();
+        assert_eq!(
+            EncryptionKey::from_path(raw.path()).unwrap(),
+            EncryptionKey::new([0xAB; 32])
+        );
+
+        let mut short = tempfile::NamedTempFile::new().unwrap();
+        writeln!(short, "abcd").unwrap();
+        assert!(EncryptionKey::from_path(short.path()).is_err());
+        assert_eq!(
+            format!("{:?}", EncryptionKey::new([1; 32])),
+            "EncryptionKey(..)"
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn test_encrypt_roundtrip() {
+        use super::enabled::{HEADER_LEN, SEGMENT_LEN, TAG_LEN};
+        use tokio::io::AsyncWriteExt;
+
+        let key = EncryptionKey::new([7; 32]);
+        for len in [0, 10, SEGMENT_LEN, SEGMENT_LEN * 2 + 5] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let file = tempfile::NamedTempFile::new().unwrap();
+            let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await.unwrap());
+            let mut writer = encrypt_writer(inner, &key).unwrap();
+            writer.write_all(&data).await.unwrap();
+            writer.shutdown().await.unwrap();
+
+            let encrypted = std::fs::read(file.path()).unwrap();
+            let segments = len.div_ceil(SEGMENT_LEN).max(1);
+            assert_eq!(encrypted.len(), HEADER_LEN + len + segments * TAG_LEN);
+            assert_eq!(decrypt(&key, &encrypted).unwrap(), data);
+
+            assert!(decrypt(&EncryptionKey::new([8; 32]), &encrypted).is_err());
+            let truncated = &encrypted[..encrypted.len() - 1];
+            assert!(decrypt(&key, truncated).is_err());
+            // Streaming through a small buffer yields the same bytes.
+            let mut reader = DecryptingReader::new(encrypted.as_slice(), &key).unwrap();
+            let mut streamed = Vec::new();
+            let mut buf = [0; 1000];
+            loop {
+                match std::io::Read::read(&mut reader, &mut buf).unwrap() {
+                    0 => break,
+                    n => streamed.extend_from_slice(&buf[..n]),
+                }
+            }
+            assert_eq!(streamed, data);
+            if segments > 1 {
+                // Dropping whole segments is caught too.
+                let dropped = &encrypted[..HEADER_LEN + SEGMENT_LEN + TAG_LEN];
+                assert!(decrypt(&key, dropped).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Following an input file as it grows, like `tail -f`.
+//!
+//! A followed file is read from its start; once its end is reached it is polled for appended
+//! data until the process receives SIGTERM or Ctrl-C, after which the data appended so far is
+//! read and the input ends. A file that shrinks below the bytes already read, as with
+//! copy-and-truncate log rotation, is read again from its start. A file replaced by renaming
+//! is not reopened: the original file keeps being followed.
+
+use super::{InputDocument, InputReader, InputSource};
+use crate::interrupt::termination;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, ReadBuf};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// The most bytes read from the file at once.
+const READ_BYTES: usize = 64 * 1024;
+/// Reads buffered ahead of the pipeline.
+const READ_AHEAD: usize = 4;
+
+/// Opens `path` to be followed, checking for appended data every `interval`.
+pub(crate) fn open_followed(
+    path: &Path,
+    relative_path: PathBuf,
+    interval: Duration,
+) -> io::Result<InputDocument> {
+    let file = std::fs::File::open(path)?;
+    Ok(InputDocument {
+        name: path.display().to_string(),
+        relative_path,
+        tag: None,
+        source: InputSource::Reader(follow(file, interval, termination())),
+    })
+}
+
+/// Reads `file` in the background, waiting for more data at its end until `stop` completes.
+pub(crate) fn follow(
+    file: std::fs::File,
+    interval: Duration,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> InputReader {
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    tokio::spawn(async move {
+        let result = follow_file(tokio::fs::File::from_std(file), interval, stop, &tx).await;
+        if let Err(e) = result {
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+    Box::new(ChannelReader {
+        rx,
+        pending: Vec::new(),
+        offset: 0,
+    })
+}
+
+async fn follow_file(
+    mut file: tokio::fs::File,
+    interval: Duration,
+    stop: impl Future<Output = ()>,
+    tx: &mpsc::Sender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    let mut stop = std::pin::pin!(stop);
+    let mut stopping = false;
+    let mut position = 0u64;
+    loop {
+        let mut buffer = vec![0; READ_BYTES];
+        let read = file.read(&mut buffer).await?;
+        if read > 0 {
+            buffer.truncate(read);
+            position += read as u64;
+            if tx.send(Ok(buffer)).await.is_err() {
+                // The pipeline stopped reading.
+                return Ok(());
+            }
+            continue;
+        }
+        if stopping || tx.is_closed() {
+            return Ok(());
+        }
+        tokio::select! {
+            _ = &mut stop => {
+                debug!("Stopped following input");
+                stopping = true;
+            }
+            _ = tokio::time::sleep(interval) => {}
+        }
+        if file.metadata().await?.len() < position {
+            warn!("Followed input was truncated; reading it again from the start");
+            file.seek(SeekFrom::Start(0)).await?;
+            position = 0;
+        }
+    }
+}
+
+/// Reads the buffers a background task sends, ending when the task drops its sender.
+struct ChannelReader {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl AsyncRead for ChannelReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.offset == self.pending.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(Ok(bytes))) => {
+                    self.pending = bytes;
+                    self.offset = 0;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let take = buf.remaining().min(self.pending.len() - self.offset);
+        let start = self.offset;
+        buf.put_slice(&self.pending[start..start + take]);
+        self.offset += take;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::sync::oneshot;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    async fn read_exactly(reader: &mut InputReader, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_reads_appended_data_until_stopped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "first\n").unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let mut reader = follow(std::fs::File::open(&path).unwrap(), INTERVAL, async {
+            let _ = stopped.await;
+        });
+        assert_eq!(read_exactly(&mut reader, 6).await, b"first\n");
+
+        let mut appender = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        appender.write_all(b"second\n").unwrap();
+        assert_eq!(read_exactly(&mut reader, 7).await, b"second\n");
+
+        // Data appended before the stop is still read.
+        appender.write_all(b"last").unwrap();
+        stop.send(()).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"last");
+    }
+
+    #[tokio::test]
+    async fn test_rereads_truncated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "old contents\n").unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let mut reader = follow(std::fs::File::open(&path).unwrap(), INTERVAL, async {
+            let _ = stopped.await;
+        });
+        assert_eq!(read_exactly(&mut reader, 13).await, b"old contents\n");
+
+        std::fs::write(&path, "new\n").unwrap();
+        assert_eq!(read_exactly(&mut reader, 4).await, b"new\n");
+        stop.send(()).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+}
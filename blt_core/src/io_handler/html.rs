@@ -0,0 +1,491 @@
+//! Stripping HTML markup from documents before tokenization.
+//!
+//! Web crawls store pages as HTML, and tokenizing the markup wastes most of the token budget
+//! on tags. With stripping enabled, each document is streamed through a small HTML lexer that
+//! drops tags, comments, and the contents of `<script>` and `<style>`, decodes character
+//! references, and starts a new line at block-level elements. [`HtmlMode::Content`]
+//! additionally drops the page furniture around the main content: `<head>`, `<nav>`,
+//! `<header>`, `<footer>`, `<aside>`, and `<form>`.
+//!
+//! The lexer is byte-oriented, so it works on UTF-8 as well as any ASCII-compatible encoding.
+//! It is not a full HTML parser: it keeps no element tree, and unclosed boilerplate elements
+//! hide the rest of the document.
+
+use super::InputSource;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// Which parts of an HTML document are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HtmlMode {
+    /// Keep all text, dropping only markup, scripts, and styles.
+    Tags,
+    /// Keep the main content, also dropping the head, navigation, headers, footers, sidebars,
+    /// and forms.
+    Content,
+}
+
+impl fmt::Display for HtmlMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HtmlMode::Tags => "tags",
+            HtmlMode::Content => "content",
+        })
+    }
+}
+
+impl FromStr for HtmlMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tags" => Ok(HtmlMode::Tags),
+            "content" => Ok(HtmlMode::Content),
+            _ => Err(format!(
+                "Unsupported HTML stripping mode: '{s}'. Use tags or content."
+            )),
+        }
+    }
+}
+
+impl HtmlMode {
+    /// Wraps `source` so that it yields the document's text instead of its markup.
+    #[cfg(feature = "html")]
+    pub(crate) fn wrap(self, source: InputSource) -> io::Result<InputSource> {
+        Ok(super::preprocess::apply(
+            source,
+            stripper::HtmlStripper::new(self),
+        ))
+    }
+
+    #[cfg(not(feature = "html"))]
+    pub(crate) fn wrap(self, _source: InputSource) -> io::Result<InputSource> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "HTML stripping requires blt to be built with the `html` feature",
+        ))
+    }
+}
+
+#[cfg(feature = "html")]
+mod stripper {
+    use super::HtmlMode;
+    use crate::io_handler::preprocess::ByteTransform;
+    use std::io;
+
+    /// Tag names are only compared against the lists below, so longer names are truncated.
+    const MAX_TAG_NAME: usize = 16;
+
+    /// Character references longer than this are passed through as text.
+    const MAX_CHAR_REF: usize = 32;
+
+    /// The end tags of elements whose contents are not markup and never text.
+    const RAW_TEXT_ENDS: [&[u8]; 2] = [b"</script", b"</style"];
+
+    /// Elements whose contents are always dropped.
+    const SKIPPED: [&[u8]; 2] = [b"noscript", b"template"];
+
+    /// Elements additionally dropped in [`HtmlMode::Content`].
+    const BOILERPLATE: [&[u8]; 6] = [b"head", b"nav", b"header", b"footer", b"aside", b"form"];
+
+    /// Elements that start a new line of text.
+    const BLOCK_ELEMENTS: [&[u8]; 31] = [
+        b"address",
+        b"article",
+        b"blockquote",
+        b"br",
+        b"dd",
+        b"div",
+        b"dl",
+        b"dt",
+        b"figcaption",
+        b"figure",
+        b"footer",
+        b"h1",
+        b"h2",
+        b"h3",
+        b"h4",
+        b"h5",
+        b"h6",
+        b"header",
+        b"hr",
+        b"li",
+        b"main",
+        b"nav",
+        b"ol",
+        b"p",
+        b"pre",
+        b"section",
+        b"table",
+        b"td",
+        b"title",
+        b"tr",
+        b"ul",
+    ];
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum State {
+        Text,
+        /// After `<`.
+        TagOpen,
+        /// After `</`.
+        EndTagOpen,
+        /// Reading a tag name into `name`.
+        TagName,
+        /// Inside a tag after its name, until the closing `>`.
+        Attributes {
+            quote: Option<u8>,
+        },
+        /// After `<!`, counting the dashes that open a comment.
+        Declaration {
+            dashes: u8,
+        },
+        /// Inside `<!-- ... -->`, counting the dashes just seen.
+        Comment {
+            dashes: u8,
+        },
+        /// Inside `<!DOCTYPE ...>`, `<?xml ...?>`, and other constructs that end at `>`.
+        Bogus,
+        /// Inside a `<script>` or `<style>` element, matching `raw_end`.
+        RawText {
+            matched: usize,
+        },
+        /// After `&`, reading a character reference into `char_ref`.
+        CharRef,
+    }
+
+    /// Incrementally turns HTML into text. Input may be split anywhere.
+    pub(super) struct HtmlStripper {
+        mode: HtmlMode,
+        state: State,
+        /// The lowercased name of the tag being read.
+        name: Vec<u8>,
+        closing: bool,
+        self_closing: bool,
+        /// The end tag that closes the current raw text element.
+        raw_end: &'static [u8],
+        /// The element whose contents are being dropped, and how many times it is nested.
+        skip: Option<(Vec<u8>, u32)>,
+        char_ref: Vec<u8>,
+        /// The last byte emitted, to avoid blank lines between block elements.
+        last: Option<u8>,
+    }
+
+    impl HtmlStripper {
+        pub(super) fn new(mode: HtmlMode) -> Self {
+            Self {
+                mode,
+                state: State::Text,
+                name: Vec::new(),
+                closing: false,
+                self_closing: false,
+                raw_end: RAW_TEXT_ENDS[0],
+                skip: None,
+                char_ref: Vec::new(),
+                last: None,
+            }
+        }
+
+        /// Feeds one byte, returning `false` if it must be fed again in the new state.
+        fn step(&mut self, byte: u8, out: &mut Vec<u8>) -> bool {
+            match self.state {
+                State::Text => match byte {
+                    b'<' => self.state = State::TagOpen,
+                    b'&' => {
+                        self.char_ref.clear();
+                        self.state = State::CharRef;
+                    }
+                    _ => self.emit(out, &[byte]),
+                },
+                State::TagOpen => {
+                    self.name.clear();
+                    self.closing = false;
+                    self.self_closing = false;
+                    match byte {
+                        b'/' => self.state = State::EndTagOpen,
+                        b'!' => self.state = State::Declaration { dashes: 0 },
+                        b'?' => self.state = State::Bogus,
+                        _ if byte.is_ascii_alphabetic() => {
+                            self.state = State::TagName;
+                            return false;
+                        }
+                        // A stray `<` in text, such as `a < b`.
+                        _ => {
+                            self.emit(out, b"<");
+                            self.state = State::Text;
+                            return false;
+                        }
+                    }
+                }
+                State::EndTagOpen => {
+                    self.closing = true;
+                    match byte {
+                        _ if byte.is_ascii_alphabetic() => {
+                            self.state = State::TagName;
+                            return false;
+                        }
+                        b'>' => self.state = State::Text,
+                        _ => self.state = State::Bogus,
+                    }
+                }
+                State::TagName => match byte {
+                    b'>' | b'/' => {
+                        self.state = State::Attributes { quote: None };
+                        return false;
+                    }
+                    _ if byte.is_ascii_whitespace() => {
+                        self.state = State::Attributes { quote: None };
+                    }
+                    _ => {
+                        if self.name.len() < MAX_TAG_NAME {
+                            self.name.push(byte.to_ascii_lowercase());
+                        }
+                    }
+                },
+                State::Attributes { quote: Some(quote) } => {
+                    if byte == quote {
+                        self.state = State::Attributes { quote: None };
+                    }
+                }
+                State::Attributes { quote: None } => match byte {
+                    b'>' => self.end_tag(out),
+                    b'"' | b'\'' => {
+                        self.self_closing = false;
+                        self.state = State::Attributes { quote: Some(byte) };
+                    }
+                    _ if byte.is_ascii_whitespace() => {}
+                    _ => self.self_closing = byte == b'/',
+                },
+                State::Declaration { dashes } => match byte {
+                    b'-' if dashes == 1 => self.state = State::Comment { dashes: 0 },
+                    b'-' => self.state = State::Declaration { dashes: 1 },
+                    b'>' => self.state = State::Text,
+                    _ => self.state = State::Bogus,
+                },
+                State::Comment { dashes } => match byte {
+                    b'-' => {
+                        self.state = State::Comment {
+                            dashes: dashes.saturating_add(1),
+                        }
+                    }
+                    b'>' if dashes >= 2 => self.state = State::Text,
+                    _ => self.state = State::Comment { dashes: 0 },
+                },
+                State::Bogus => {
+                    if byte == b'>' {
+                        self.state = State::Text;
+                    }
+                }
+                State::RawText { matched } if matched == self.raw_end.len() => {
+                    if byte.is_ascii_whitespace() || byte == b'/' || byte == b'>' {
+                        self.name.clear();
+                        self.name.extend_from_slice(&self.raw_end[2..]);
+                        self.closing = true;
+                        self.state = State::Attributes { quote: None };
+                    } else {
+                        self.state = State::RawText { matched: 0 };
+                    }
+                    return false;
+                }
+                State::RawText { matched } => {
+                    let matched = if byte.to_ascii_lowercase() == self.raw_end[matched] {
+                        matched + 1
+                    } else if byte == b'<' {
+                        1
+                    } else {
+                        0
+                    };
+                    self.state = State::RawText { matched };
+                }
+                State::CharRef => match byte {
+                    b';' => {
+                        self.state = State::Text;
+                        match decode_char_ref(&self.char_ref) {
+                            Some(c) => self.emit(out, c.encode_utf8(&mut [0; 4]).as_bytes()),
+                            None => {
+                                self.emit_char_ref(out);
+                                self.emit(out, b";");
+                            }
+                        }
+                    }
+                    _ if (byte.is_ascii_alphanumeric() || byte == b'#')
+                        && self.char_ref.len() < MAX_CHAR_REF =>
+                    {
+                        self.char_ref.push(byte);
+                    }
+                    _ => {
+                        self.state = State::Text;
+                        self.emit_char_ref(out);
+                        return false;
+                    }
+                },
+            }
+            true
+        }
+
+        /// Handles the `>` ending the tag in `name`.
+        fn end_tag(&mut self, out: &mut Vec<u8>) {
+            self.state = State::Text;
+            let name = std::mem::take(&mut self.name);
+            let opening = !self.closing && !self.self_closing;
+            let starts_skip = opening && self.is_skipped(&name);
+            match &mut self.skip {
+                Some((skipped, depth)) if *skipped == name && self.closing => {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        self.skip = None;
+                    }
+                }
+                Some((skipped, depth)) if *skipped == name && opening => *depth += 1,
+                Some(_) => {}
+                None if starts_skip => self.skip = Some((name.clone(), 1)),
+                None => {}
+            }
+            if opening {
+                if let Some(end) = RAW_TEXT_ENDS.iter().find(|end| end[2..] == name[..]) {
+                    self.raw_end = end;
+                    self.state = State::RawText { matched: 0 };
+                }
+            }
+            if BLOCK_ELEMENTS.contains(&&name[..]) && self.last.is_some_and(|b| b != b'\n') {
+                self.emit(out, b"\n");
+            }
+            self.name = name;
+        }
+
+        fn is_skipped(&self, name: &[u8]) -> bool {
+            SKIPPED.contains(&name)
+                || (self.mode == HtmlMode::Content && BOILERPLATE.contains(&name))
+        }
+
+        /// Passes an undecodable `&...` through as text.
+        fn emit_char_ref(&mut self, out: &mut Vec<u8>) {
+            let char_ref = std::mem::take(&mut self.char_ref);
+            self.emit(out, b"&");
+            self.emit(out, &char_ref);
+            self.char_ref = char_ref;
+        }
+
+        fn emit(&mut self, out: &mut Vec<u8>, bytes: &[u8]) {
+            if self.skip.is_none() && !bytes.is_empty() {
+                out.extend_from_slice(bytes);
+                self.last = bytes.last().copied();
+            }
+        }
+    }
+
+    impl ByteTransform for HtmlStripper {
+        fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+            for &byte in input {
+                while !self.step(byte, out) {}
+            }
+            Ok(())
+        }
+
+        /// Flushes text held back at the end of the input, such as an unterminated reference.
+        fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+            match self.state {
+                State::TagOpen => self.emit(out, b"<"),
+                State::CharRef => self.emit_char_ref(out),
+                _ => {}
+            }
+            self.state = State::Text;
+            Ok(())
+        }
+    }
+
+    /// Decodes the body of a character reference such as `amp` or `#x27`.
+    fn decode_char_ref(body: &[u8]) -> Option<char> {
+        let numeric = |digits: &[u8], radix| {
+            let code = u32::from_str_radix(std::str::from_utf8(digits).ok()?, radix).ok()?;
+            Some(
+                char::from_u32(code)
+                    .filter(|&c| c != '\0')
+                    .unwrap_or('\u{FFFD}'),
+            )
+        };
+        match body {
+            [b'#', b'x' | b'X', hex @ ..] if !hex.is_empty() => numeric(hex, 16),
+            [b'#', decimal @ ..] if !decimal.is_empty() => numeric(decimal, 10),
+            b"amp" => Some('&'),
+            b"lt" => Some('<'),
+            b"gt" => Some('>'),
+            b"quot" => Some('"'),
+            b"apos" => Some('\''),
+            b"nbsp" => Some('\u{A0}'),
+            b"copy" => Some('©'),
+            b"reg" => Some('®'),
+            b"trade" => Some('™'),
+            b"euro" => Some('€'),
+            b"ndash" => Some('–'),
+            b"mdash" => Some('—'),
+            b"hellip" => Some('…'),
+            b"lsquo" => Some('‘'),
+            b"rsquo" => Some('’'),
+            b"ldquo" => Some('“'),
+            b"rdquo" => Some('”'),
+            b"laquo" => Some('«'),
+            b"raquo" => Some('»'),
+            b"middot" => Some('·'),
+            b"bull" => Some('•'),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::io_handler::preprocess::tests::transform_bytewise;
+
+        fn strip(html: &str, mode: HtmlMode) -> String {
+            let mut stripper = HtmlStripper::new(mode);
+            let mut out = Vec::new();
+            stripper.transform(html.as_bytes(), &mut out).unwrap();
+            stripper.finish(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        }
+
+        const PAGE: &str = "<!DOCTYPE html>\n<html><head><title>Title</title>\
+            <style>p > a { color: red }</style></head>\
+            <body><nav><ul><li><a href=\"/\">Home</a></li></ul></nav>\
+            <main><h1 class='x'>Fish &amp; Chips</h1><!-- <p>hidden</p> -->\
+            <p>Price &lt; &#163;5 &#x2014; a&nbsp;b &bogus; AT&T</p>\
+            <script>if (a<b && c) { document.write(\"</p>\") }</script>\
+            <p>Line<br/>break <img src=x alt=\"y/\"> done</p></main>\
+            <footer>&copy; 2024</footer></body></html>";
+
+        #[test]
+        fn test_strips_tags() {
+            assert_eq!(
+                strip(PAGE, HtmlMode::Tags),
+                "\nTitle\nHome\nFish & Chips\nPrice < £5 — a\u{A0}b &bogus; AT&T\n\
+                 Line\nbreak  done\n\u{A9} 2024\n"
+            );
+        }
+
+        #[test]
+        fn test_content_mode_drops_boilerplate() {
+            assert_eq!(
+                strip(PAGE, HtmlMode::Content),
+                "\nFish & Chips\nPrice < £5 — a\u{A0}b &bogus; AT&T\nLine\nbreak  done\n"
+            );
+            assert_eq!(
+                strip(
+                    "<nav>a<nav>b</nav>c</nav>d<noscript>e</noscript>",
+                    HtmlMode::Content
+                ),
+                "d"
+            );
+            assert_eq!(strip("1 < 2 <3 &", HtmlMode::Tags), "1 < 2 <3 &");
+        }
+
+        #[test]
+        fn test_input_split_anywhere() {
+            for mode in [HtmlMode::Tags, HtmlMode::Content] {
+                let bytewise = transform_bytewise(HtmlStripper::new(mode), PAGE.as_bytes());
+                assert_eq!(String::from_utf8(bytewise).unwrap(), strip(PAGE, mode));
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Capping the number of tokens a run writes.
+//!
+//! A [`TokenBudget`] is shared by every output of a run. [`LimitedWriter`] passes writes
+//! through until the budget is spent and silently drops everything after, so outputs end
+//! exactly at the cap. The pipeline polls the budget to stop reading input once it is spent.
+
+use super::OutputWriter;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+
+/// The number of output bytes a run may still write.
+#[derive(Debug)]
+pub(crate) struct TokenBudget {
+    remaining: AtomicU64,
+}
+
+impl TokenBudget {
+    /// A budget of `max_tokens` tokens of `token_width` bytes each.
+    pub(crate) fn new(max_tokens: u64, token_width: u64) -> Arc<Self> {
+        Arc::new(Self {
+            remaining: AtomicU64::new(max_tokens.saturating_mul(token_width)),
+        })
+    }
+
+    /// Returns `true` once no more bytes may be written.
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.remaining.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// Writes through to the inner writer until the shared budget is spent.
+pub(crate) struct LimitedWriter {
+    inner: OutputWriter,
+    budget: Arc<TokenBudget>,
+}
+
+impl LimitedWriter {
+    pub(crate) fn new(inner: OutputWriter, budget: Arc<TokenBudget>) -> Self {
+        Self { inner, budget }
+    }
+}
+
+impl AsyncWrite for LimitedWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let remaining = self.budget.remaining.load(Ordering::Relaxed);
+        let allowed = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        if allowed == 0 {
+            return Poll::Ready(Ok(buf.len()));
+        }
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[..allowed]))?;
+        self.budget.remaining.fetch_sub(n as u64, Ordering::Relaxed);
+        // Once the budget is spent, report the rest of `buf` as written so it is dropped.
+        Poll::Ready(Ok(if n == allowed { buf.len() } else { n }))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_budget_is_shared_between_outputs() {
+        let budget = TokenBudget::new(3, 2);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await.unwrap());
+        let mut first = LimitedWriter::new(inner, budget.clone());
+        first.write_all(&[0, 1, 0, 2]).await.unwrap();
+        first.shutdown().await.unwrap();
+        assert!(!budget.is_exhausted());
+
+        let second_file = tempfile::NamedTempFile::new().unwrap();
+        let inner: OutputWriter =
+            Box::new(tokio::fs::File::create(second_file.path()).await.unwrap());
+        let mut second = LimitedWriter::new(inner, budget.clone());
+        second.write_all(&[0, 3, 0, 4, 0, 5]).await.unwrap();
+        second.shutdown().await.unwrap();
+        assert!(budget.is_exhausted());
+        assert_eq!(std::fs::read(file.path()).unwrap(), [0, 1, 0, 2]);
+        assert_eq!(std::fs::read(second_file.path()).unwrap(), [0, 3]);
+    }
+}
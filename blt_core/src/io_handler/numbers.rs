@@ -0,0 +1,222 @@
+//! Normalizing numbers before tokenization.
+//!
+//! Code and log corpora are full of identifiers, timestamps, and counters whose exact digits
+//! matter little but spread merges across thousands of one-off numbers. [`NumberBucketing`]
+//! rewrites each run of ASCII digits as a document is read: into a fixed placeholder, into its
+//! shape with every digit replaced by `0`, or into a bucket naming its number of digits.
+
+use super::preprocess::{self, ByteTransform};
+use super::InputSource;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// What a digit run is replaced with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NumberForm {
+    /// The given text, whatever the digits were, e.g. `<num>`.
+    Placeholder(String),
+    /// The same number of `0` digits, so `2024-01-15` becomes `0000-00-00`.
+    Shape,
+    /// `<num:N>` where N is the number of digits, so `8080` becomes `<num:4>`.
+    Magnitude,
+}
+
+impl NumberForm {
+    /// The placeholder used when none is given.
+    pub const DEFAULT_PLACEHOLDER: &'static str = "<num>";
+}
+
+impl fmt::Display for NumberForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberForm::Placeholder(text) => write!(f, "placeholder:{text}"),
+            NumberForm::Shape => f.write_str("shape"),
+            NumberForm::Magnitude => f.write_str("magnitude"),
+        }
+    }
+}
+
+impl FromStr for NumberForm {
+    type Err = String;
+
+    /// Parses `placeholder[:TEXT]`, `shape`, or `magnitude`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, text) = match s.split_once(':') {
+            Some((name, text)) => (name, Some(text)),
+            None => (s, None),
+        };
+        match (name.to_ascii_lowercase().as_str(), text) {
+            ("placeholder", None) => Ok(NumberForm::Placeholder(
+                Self::DEFAULT_PLACEHOLDER.to_string(),
+            )),
+            ("placeholder", Some(text)) => Ok(NumberForm::Placeholder(text.to_string())),
+            ("shape", None) => Ok(NumberForm::Shape),
+            ("magnitude", None) => Ok(NumberForm::Magnitude),
+            _ => Err(format!(
+                "Unsupported number form: '{s}'. Use placeholder[:TEXT], shape, or magnitude."
+            )),
+        }
+    }
+}
+
+/// Which digit runs are rewritten, and how.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumberBucketing {
+    /// The replacement for each digit run.
+    pub form: NumberForm,
+    /// Runs shorter than this are kept as they are, e.g. 2 keeps `v1` but not `42`.
+    pub min_digits: usize,
+}
+
+impl NumberBucketing {
+    /// Wraps `source` so that it yields the document with its numbers rewritten.
+    pub(crate) fn wrap(&self, source: InputSource) -> InputSource {
+        preprocess::apply(source, NumberBucketer::new(self.clone()))
+    }
+}
+
+/// Applies a [`NumberBucketing`] incrementally, holding back each digit run until it is long
+/// enough to be rewritten or has ended.
+struct NumberBucketer {
+    bucketing: NumberBucketing,
+    /// The length of the current digit run.
+    run: usize,
+    /// The digits of the current run, while it is shorter than `min_digits`.
+    digits: Vec<u8>,
+}
+
+impl NumberBucketer {
+    fn new(bucketing: NumberBucketing) -> Self {
+        Self {
+            bucketing,
+            run: 0,
+            digits: Vec::new(),
+        }
+    }
+
+    fn push_digit(&mut self, digit: u8, out: &mut Vec<u8>) {
+        self.run += 1;
+        let min_digits = self.bucketing.min_digits.max(1);
+        if self.run < min_digits {
+            self.digits.push(digit);
+        } else if self.bucketing.form == NumberForm::Shape {
+            // The run is known to be rewritten, so its shape can be written as it goes.
+            let zeros = if self.run == min_digits { self.run } else { 1 };
+            out.extend(std::iter::repeat_n(b'0', zeros));
+            self.digits.clear();
+        }
+    }
+
+    /// Writes the replacement for the digit run that just ended, if any.
+    fn end_run(&mut self, out: &mut Vec<u8>) {
+        if self.run == 0 {
+            return;
+        }
+        if self.run < self.bucketing.min_digits {
+            out.extend_from_slice(&self.digits);
+        } else {
+            match &self.bucketing.form {
+                NumberForm::Placeholder(text) => out.extend_from_slice(text.as_bytes()),
+                NumberForm::Shape => {}
+                NumberForm::Magnitude => {
+                    out.extend_from_slice(format!("<num:{}>", self.run).as_bytes())
+                }
+            }
+        }
+        self.run = 0;
+        self.digits.clear();
+    }
+}
+
+impl ByteTransform for NumberBucketer {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            if byte.is_ascii_digit() {
+                self.push_digit(byte, out);
+            } else {
+                self.end_run(out);
+                out.push(byte);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.end_run(out);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::preprocess::tests::transform_bytewise;
+
+    fn bucket(form: &str, min_digits: usize, input: &str) -> String {
+        let bucketing = NumberBucketing {
+            form: form.parse().unwrap(),
+            min_digits,
+        };
+        let whole = {
+            let mut bucketer = NumberBucketer::new(bucketing.clone());
+            let mut out = Vec::new();
+            bucketer.transform(input.as_bytes(), &mut out).unwrap();
+            bucketer.finish(&mut out).unwrap();
+            out
+        };
+        assert_eq!(
+            whole,
+            transform_bytewise(NumberBucketer::new(bucketing), input.as_bytes())
+        );
+        String::from_utf8(whole).unwrap()
+    }
+
+    const LOG: &str = "v2 GET /item/83412 took 7ms at 2024-01-15";
+
+    #[test]
+    fn test_forms() {
+        assert_eq!(
+            bucket("placeholder", 1, LOG),
+            "v<num> GET /item/<num> took <num>ms at <num>-<num>-<num>"
+        );
+        assert_eq!(
+            bucket("placeholder:#", 1, LOG),
+            "v# GET /item/# took #ms at #-#-#"
+        );
+        assert_eq!(
+            bucket("shape", 1, LOG),
+            "v0 GET /item/00000 took 0ms at 0000-00-00"
+        );
+        assert_eq!(
+            bucket("magnitude", 1, LOG),
+            "v<num:1> GET /item/<num:5> took <num:1>ms at <num:4>-<num:2>-<num:2>"
+        );
+    }
+
+    #[test]
+    fn test_min_digits_keeps_short_runs() {
+        assert_eq!(
+            bucket("shape", 3, LOG),
+            "v2 GET /item/00000 took 7ms at 0000-01-15"
+        );
+        assert_eq!(bucket("magnitude", 2, "1 22 333"), "1 <num:2> <num:3>");
+    }
+
+    #[test]
+    fn test_parse_forms() {
+        assert_eq!(
+            "placeholder".parse::<NumberForm>().unwrap(),
+            NumberForm::Placeholder("<num>".to_string())
+        );
+        assert_eq!(
+            "Magnitude".parse::<NumberForm>().unwrap(),
+            NumberForm::Magnitude
+        );
+        assert!("shape:x".parse::<NumberForm>().is_err());
+        assert!("round".parse::<NumberForm>().is_err());
+        for form in ["placeholder:<n>", "shape", "magnitude"] {
+            assert_eq!(form.parse::<NumberForm>().unwrap().to_string(), form);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Naming per-document outputs in batch mode.
+//!
+//! With an output directory, every input document is written to its own file. The file's path
+//! mirrors the document's path relative to its input root (the input directory, the common
+//! parent of a file list, or the archive root) with the extension swapped for the output's.
+//!
+//! A content-addressed directory is laid out as an object store instead: each output is named
+//! by the hash of its bytes, as `<algorithm>/<first two hex digits>/<hex>.<extension>`, and the
+//! run's manifest maps every document to its object. Identical outputs are stored once, and
+//! runs into the same directory add to one store, so corpora can be composed from the
+//! manifests of several runs without copying their outputs.
+
+use crate::{CoreConfig, OutputFormat};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+
+/// The subdirectory of a content-addressed directory holding outputs still being written.
+const STAGING_DIR: &str = ".staging";
+
+/// Where per-document outputs are written.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputDir {
+    /// The directory receiving the outputs.
+    pub dir: PathBuf,
+    /// The extension replacing each input's; defaults to one matching the output format
+    /// (`bin`, `arrow`, or `parquet`).
+    pub extension: Option<String>,
+    /// Whether outputs are named by the hash of their bytes rather than by their inputs.
+    pub content_addressed: bool,
+}
+
+impl OutputDir {
+    /// Creates a layout writing into `dir` with the default extension.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            extension: None,
+            content_addressed: false,
+        }
+    }
+
+    /// Returns the output path of the document at `relative`, under the configured output
+    /// format and compression.
+    ///
+    /// Only the normal components of `relative` are kept, so `..` or absolute member names
+    /// cannot escape the output directory.
+    pub fn output_path(&self, relative: &Path, config: &CoreConfig) -> PathBuf {
+        let mut path = self.dir.clone();
+        path.extend(relative.components().filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        }));
+        self.with_extension(path, config)
+    }
+
+    /// Returns the temporary path that document `doc_id` of a content-addressed run is written
+    /// to before its hash is known. The process ID keeps concurrent runs apart.
+    pub(crate) fn staging_path(&self, doc_id: u64, config: &CoreConfig) -> PathBuf {
+        let name = format!("{}-{doc_id}", std::process::id());
+        self.with_extension(self.dir.join(STAGING_DIR).join(name), config)
+    }
+
+    /// Returns the object path of an output whose bytes hash to `hash`, given as
+    /// `"<algorithm>:<hex>"`.
+    pub fn object_path(&self, hash: &str, config: &CoreConfig) -> PathBuf {
+        let (algorithm, hex) = hash.split_once(':').unwrap_or(("", hash));
+        let mut path = self.dir.join(algorithm);
+        path.push(&hex[..hex.len().min(2)]);
+        path.push(hex);
+        self.with_extension(path, config)
+    }
+
+    /// Moves the finished output at `staged`, whose bytes hash to `hash`, to its object path
+    /// and returns that path. If the store already holds the object, the staged copy is
+    /// discarded instead.
+    pub(crate) async fn store_object(
+        &self,
+        staged: &Path,
+        hash: &str,
+        config: &CoreConfig,
+    ) -> io::Result<PathBuf> {
+        let object = self.object_path(hash, config);
+        if tokio::fs::try_exists(&object).await? {
+            debug!(object = %object.display(), "Output already stored");
+            tokio::fs::remove_file(staged).await?;
+        } else {
+            if let Some(parent) = object.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(staged, &object).await?;
+        }
+        Ok(object)
+    }
+
+    fn with_extension(&self, mut path: PathBuf, config: &CoreConfig) -> PathBuf {
+        let extension = self
+            .extension
+            .as_deref()
+            .unwrap_or(default_extension(&config.output_format));
+        path.set_extension(extension);
+        if let Some(compression) = config.compression {
+            let mut name = path.into_os_string();
+            name.push(".");
+            name.push(compression.extension());
+            path = name.into();
+        }
+        path
+    }
+}
+
+fn default_extension(format: &OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Raw => "bin",
+        OutputFormat::ArrowIpc => "arrow",
+        OutputFormat::Parquet { .. } => "parquet",
+    }
+}
+
+/// Tracks the outputs of a run so two inputs never silently overwrite each other.
+#[derive(Debug, Default)]
+pub(crate) struct OutputClaims {
+    claimed: HashMap<PathBuf, String>,
+}
+
+impl OutputClaims {
+    /// Records that `source` writes `path`, failing if another input already does.
+    pub(crate) fn claim(&mut self, path: &Path, source: &str) -> io::Result<()> {
+        if let Some(previous) = self.claimed.get(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Inputs '{previous}' and '{source}' both map to output {}",
+                    path.display()
+                ),
+            ));
+        }
+        self.claimed.insert(path.to_path_buf(), source.to_string());
+        Ok(())
+    }
+}
+
+/// Returns the deepest directory containing every path in `paths`.
+pub(crate) fn common_parent(paths: &[PathBuf]) -> io::Result<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+    for path in paths {
+        let parent = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        common = Some(match common {
+            None => parent,
+            Some(current) => current
+                .components()
+                .zip(parent.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    Ok(common.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::Compression;
+
+    #[test]
+    fn test_output_path_swaps_extension() {
+        let layout = OutputDir::new("out");
+        let config = CoreConfig::default();
+        assert_eq!(
+            layout.output_path(Path::new("a/b/doc.txt"), &config),
+            PathBuf::from("out/a/b/doc.bin")
+        );
+        let config = config.with_compression(Some(Compression::Zstd { level: None }));
+        assert_eq!(
+            layout.output_path(Path::new("doc"), &config),
+            PathBuf::from("out/doc.bin.zst")
+        );
+    }
+
+    #[test]
+    fn test_output_path_stays_inside_dir() {
+        let layout = OutputDir {
+            dir: "out".into(),
+            extension: Some("tok".into()),
+            content_addressed: false,
+        };
+        let config = CoreConfig::default();
+        assert_eq!(
+            layout.output_path(Path::new("/../../etc/passwd"), &config),
+            PathBuf::from("out/etc/passwd.tok")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_store_object_dedups() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let layout = OutputDir {
+            content_addressed: true,
+            ..OutputDir::new(dir.path())
+        };
+        let config = CoreConfig::default();
+        let hash = "sha256:abcdef";
+        let expected = dir.path().join("sha256/ab/abcdef.bin");
+        assert_eq!(layout.object_path(hash, &config), expected);
+
+        for doc_id in 0..2 {
+            let staged = layout.staging_path(doc_id, &config);
+            std::fs::create_dir_all(staged.parent().unwrap())?;
+            std::fs::write(&staged, b"tokens")?;
+            assert_eq!(layout.store_object(&staged, hash, &config).await?, expected);
+            assert!(!staged.exists());
+        }
+        assert_eq!(std::fs::read(&expected)?, b"tokens");
+        Ok(())
+    }
+
+    #[test]
+    fn test_claims_detect_collisions() {
+        let mut claims = OutputClaims::default();
+        claims.claim(Path::new("out/a.bin"), "a.txt").unwrap();
+        let err = claims.claim(Path::new("out/a.bin"), "a.md").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_common_parent() {
+        let paths = vec![
+            PathBuf::from("/data/shards/en/0.txt"),
+            PathBuf::from("/data/shards/de/1.txt"),
+        ];
+        assert_eq!(
+            common_parent(&paths).unwrap(),
+            PathBuf::from("/data/shards")
+        );
+    }
+}
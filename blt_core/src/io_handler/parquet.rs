@@ -0,0 +1,169 @@
+//! Parquet output format.
+//!
+//! Writes the tokenized documents as a Parquet file with `doc_id`, `source`, and `tokens`
+//! columns, one row per document, for dataset builders that want columnar output directly.
+//! Row groups are written as their documents complete, so memory stays bounded by one row group.
+
+use super::columnar::{token_schema, BatchEncoder};
+use arrow_array::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use std::io;
+use std::sync::Arc;
+
+/// Encodes token batches as a Parquet file, one row group per batch.
+pub struct ParquetEncoder {
+    row_group_size: usize,
+    writer: Option<ArrowWriter<Vec<u8>>>,
+}
+
+impl ParquetEncoder {
+    /// Creates an encoder that writes at most `row_group_size` documents per row group.
+    pub fn new(row_group_size: usize) -> Self {
+        Self {
+            row_group_size: row_group_size.max(1),
+            writer: None,
+        }
+    }
+
+    /// The file writer, opened on first use.
+    fn writer(&mut self) -> io::Result<&mut ArrowWriter<Vec<u8>>> {
+        if self.writer.is_none() {
+            let props = WriterProperties::builder()
+                .set_max_row_group_size(self.row_group_size)
+                .build();
+            let writer = ArrowWriter::try_new(Vec::new(), Arc::new(token_schema()), Some(props))
+                .map_err(parquet_to_io_error)?;
+            self.writer = Some(writer);
+        }
+        Ok(self.writer.as_mut().expect("writer was just opened"))
+    }
+}
+
+impl BatchEncoder for ParquetEncoder {
+    fn batch_rows(&self) -> usize {
+        self.row_group_size
+    }
+
+    fn write(&mut self, batch: &RecordBatch, out: &mut Vec<u8>) -> io::Result<()> {
+        let writer = self.writer()?;
+        writer.write(batch).map_err(parquet_to_io_error)?;
+        writer.flush().map_err(parquet_to_io_error)?;
+        out.append(writer.inner_mut());
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        let writer = self.writer()?;
+        writer.finish().map_err(parquet_to_io_error)?;
+        out.append(writer.inner_mut());
+        Ok(())
+    }
+}
+
+fn parquet_to_io_error(e: ParquetError) -> io::Error {
+    io::Error::other(format!("Parquet encoding failed: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::{ColumnarWriter, DocumentLabels, OutputWriter};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_parquet_writer_produces_readable_file() -> io::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await?);
+        let labels = DocumentLabels::default();
+        labels.begin(0, "input.txt".to_string());
+        let mut writer = ColumnarWriter::new(inner, labels, ParquetEncoder::new(8));
+        writer.write_all(&[0, 97, 0, 98]).await?;
+        writer.shutdown().await?;
+
+        let reader = SerializedFileReader::new(std::fs::File::open(file.path())?).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 1);
+        assert_eq!(metadata.schema_descr().num_columns(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parquet_writer_writes_row_groups_as_documents_complete() -> io::Result<()> {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let file = tempfile::NamedTempFile::new()?;
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await?);
+        let labels = DocumentLabels::default();
+        let mut writer = ColumnarWriter::new(inner, labels.clone(), ParquetEncoder::new(3));
+        let documents = 7u64;
+        let tokens: Vec<u8> = (0..4096u16).flat_map(u16::to_be_bytes).collect();
+        for doc_id in 0..documents {
+            labels.begin(doc_id, format!("doc{doc_id}.txt"));
+            writer.write_all(&tokens).await?;
+        }
+        // The first two row groups are written before the output is finished.
+        writer.flush().await?;
+        assert!(std::fs::metadata(file.path())?.len() > 0);
+        writer.shutdown().await?;
+
+        let reader = SerializedFileReader::new(std::fs::File::open(file.path())?).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), documents as i64);
+        assert_eq!(metadata.num_row_groups(), documents.div_ceil(3) as usize);
+        let row_groups: Vec<i64> = metadata.row_groups().iter().map(|g| g.num_rows()).collect();
+        assert_eq!(row_groups, vec![3, 3, 1]);
+
+        let batches = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(file.path())?)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut sources = Vec::new();
+        for batch in batches {
+            let batch = batch.unwrap();
+            let column = batch.column(1).as_any();
+            let column = column.downcast_ref::<arrow_array::StringArray>().unwrap();
+            sources.extend(column.iter().map(|s| s.unwrap().to_string()));
+        }
+        assert_eq!(sources[0], "doc0.txt");
+        assert_eq!(sources[6], "doc6.txt");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_document_run_writes_a_row_per_document() -> io::Result<()> {
+        use crate::{run_tokenizer, CoreConfig, OutputFormat};
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("docs");
+        std::fs::create_dir(&input)?;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(input.join(name), name)?;
+        }
+        let output = dir.path().join("out.parquet");
+        let config = CoreConfig {
+            input: Some(input),
+            output: Some(output.clone()),
+            output_format: OutputFormat::Parquet { row_group_size: 2 },
+            ..CoreConfig::default()
+        };
+        run_tokenizer(config).await.map_err(io::Error::other)?;
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&output)?).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert!(rows[0].contains("doc_id: 0") && rows[0].contains("a.txt"));
+        assert!(rows[2].contains("doc_id: 2") && rows[2].contains("c.txt"));
+        // Rows separate the documents, so no separator token is written between them.
+        let separator = crate::special_tokens::SEPARATOR.to_string();
+        assert!(rows.iter().all(|row| !row.contains(&separator)));
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! Streaming byte transforms applied to documents before tokenization.
+//!
+//! Pre-processors such as HTML stripping and whitespace normalization rewrite a document as it
+//! is read. Each implements [`ByteTransform`], and [`apply`] wraps a document's source in a
+//! reader that feeds it the input a read at a time, so no pre-processor holds a whole document.
+//! Pre-processed documents are always streamed, even when the file could be memory-mapped.
+
+use super::{InputReader, InputSource};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The size of the reads transformed at a time.
+const READ_BUFFER_BYTES: usize = 64 * 1024;
+
+/// An incremental rewrite of a byte stream. Input may be split anywhere.
+pub(crate) trait ByteTransform: Send + Unpin + 'static {
+    /// Appends the output for the next piece of `input` to `out`.
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Appends output held back for context once the input has ended.
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// Wraps `source` so that it yields `transform`'s output.
+pub(crate) fn apply(source: InputSource, transform: impl ByteTransform) -> InputSource {
+    InputSource::Reader(Box::new(TransformReader::new(
+        into_reader(source),
+        transform,
+    )))
+}
+
+/// Turns any input source into a reader.
+pub(crate) fn into_reader(source: InputSource) -> InputReader {
+    match source {
+        InputSource::Mmap(mmap) => Box::new(std::io::Cursor::new(mmap)),
+        InputSource::Stdin(reader) | InputSource::Reader(reader) => reader,
+    }
+}
+
+/// Yields the output of a [`ByteTransform`] over an inner reader.
+pub(crate) struct TransformReader<T> {
+    inner: InputReader,
+    transform: T,
+    input: Vec<u8>,
+    /// Transformed output not yet returned.
+    output: Vec<u8>,
+    output_pos: usize,
+    finished: bool,
+}
+
+impl<T: ByteTransform> TransformReader<T> {
+    pub(crate) fn new(inner: InputReader, transform: T) -> Self {
+        Self {
+            inner,
+            transform,
+            input: vec![0; READ_BUFFER_BYTES],
+            output: Vec::new(),
+            output_pos: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<T: ByteTransform> AsyncRead for TransformReader<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while this.output_pos == this.output.len() {
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+            this.output.clear();
+            this.output_pos = 0;
+            let mut input = ReadBuf::new(&mut this.input);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut input))?;
+            match input.filled() {
+                [] => {
+                    this.finished = true;
+                    this.transform.finish(&mut this.output)?;
+                }
+                read => this.transform.transform(read, &mut this.output)?,
+            }
+        }
+        let n = buf.remaining().min(this.output.len() - this.output_pos);
+        buf.put_slice(&this.output[this.output_pos..this.output_pos + n]);
+        this.output_pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Runs `transform` over `input` fed one byte at a time.
+    pub(crate) fn transform_bytewise(mut transform: impl ByteTransform, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for byte in input {
+            transform
+                .transform(std::slice::from_ref(byte), &mut out)
+                .unwrap();
+        }
+        transform.finish(&mut out).unwrap();
+        out
+    }
+
+    /// Upper-cases its input and appends a marker at the end.
+    struct Shout;
+
+    impl ByteTransform for Shout {
+        fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+            out.extend(input.iter().map(u8::to_ascii_uppercase));
+            Ok(())
+        }
+
+        fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+            out.push(b'!');
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transform_reader() {
+        let input = b"abc".repeat(READ_BUFFER_BYTES);
+        let source = InputSource::Reader(Box::new(std::io::Cursor::new(input.clone())));
+        let mut reader = into_reader(apply(source, Shout));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        assert_eq!(out.len(), input.len() + 1);
+        assert!(out.starts_with(b"ABCABC"));
+        assert!(out.ends_with(b"ABC!"));
+    }
+}
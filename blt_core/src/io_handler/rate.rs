@@ -0,0 +1,222 @@
+//! Pacing output to a token rate.
+//!
+//! A live consumer reading tokens from a socket or pipe may be far slower than the tokenizer.
+//! A [`RateLimit`] caps the average number of tokens written per second across every output of
+//! a run, allowing bursts of up to `burst` tokens after idle periods. Writes wait for the rate
+//! to allow them, so the pipeline stops taking in input and memory stays bounded.
+
+use super::OutputWriter;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio::time::{Instant, Sleep};
+
+/// The most tokens per second written, and the most written at once after a pause.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The average number of tokens written per second.
+    pub tokens_per_second: u64,
+    /// The most tokens written without waiting, after the output has been idle.
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// A limit of `tokens_per_second`, with bursts of one second's worth of tokens.
+    pub fn per_second(tokens_per_second: u64) -> Self {
+        Self {
+            tokens_per_second,
+            burst: tokens_per_second,
+        }
+    }
+
+    /// Rejects rates or bursts of zero tokens.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.tokens_per_second == 0 || self.burst == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The token rate and burst must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parses `RATE` or `RATE/BURST`, in tokens.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid token rate '{s}', expected RATE or RATE/BURST");
+        let (rate, burst) = match s.split_once('/') {
+            Some((rate, burst)) => (rate, Some(burst)),
+            None => (s, None),
+        };
+        let rate = rate.trim().parse().map_err(|_| invalid())?;
+        let limit = match burst {
+            Some(burst) => Self {
+                tokens_per_second: rate,
+                burst: burst.trim().parse().map_err(|_| invalid())?,
+            },
+            None => Self::per_second(rate),
+        };
+        limit.validate().map_err(|e| e.to_string())?;
+        Ok(limit)
+    }
+}
+
+/// The output bytes a run may write now, refilled at the configured rate.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    /// Bytes per second.
+    rate: f64,
+    /// The most bytes held.
+    capacity: f64,
+    /// Bytes available and when they were last counted.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// A full bucket for `limit`, counting tokens of `token_width` bytes.
+    pub(crate) fn new(limit: RateLimit, token_width: u64) -> Arc<Self> {
+        let capacity = (limit.burst * token_width) as f64;
+        Arc::new(Self {
+            rate: (limit.tokens_per_second * token_width) as f64,
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        })
+    }
+
+    /// Takes up to `wanted` bytes, or returns how long until some are available.
+    fn take(&self, wanted: usize) -> Result<usize, Duration> {
+        let mut state = self.state.lock().expect("token bucket lock poisoned");
+        let (available, counted) = &mut *state;
+        let now = Instant::now();
+        *available = (*available + now.duration_since(*counted).as_secs_f64() * self.rate)
+            .min(self.capacity);
+        *counted = now;
+        let whole = available.floor() as usize;
+        if whole == 0 {
+            let needed = (wanted as f64).min(self.capacity) - *available;
+            return Err(Duration::from_secs_f64(needed / self.rate));
+        }
+        let taken = whole.min(wanted);
+        *available -= taken as f64;
+        Ok(taken)
+    }
+
+    /// Returns bytes taken but not written.
+    fn refund(&self, bytes: usize) {
+        let mut state = self.state.lock().expect("token bucket lock poisoned");
+        state.0 = (state.0 + bytes as f64).min(self.capacity);
+    }
+}
+
+/// Writes through to the inner writer no faster than a shared [`TokenBucket`] allows.
+pub(crate) struct RateLimitedWriter {
+    inner: OutputWriter,
+    bucket: Arc<TokenBucket>,
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl RateLimitedWriter {
+    pub(crate) fn new(inner: OutputWriter, bucket: Arc<TokenBucket>) -> Self {
+        Self {
+            inner,
+            bucket,
+            wait: None,
+        }
+    }
+}
+
+impl AsyncWrite for RateLimitedWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+        loop {
+            if let Some(wait) = self.wait.as_mut() {
+                ready!(wait.as_mut().poll(cx));
+                self.wait = None;
+            }
+            match self.bucket.take(buf.len()) {
+                Ok(allowed) => {
+                    let result = Pin::new(&mut self.inner).poll_write(cx, &buf[..allowed]);
+                    let written = match &result {
+                        Poll::Ready(Ok(n)) => *n,
+                        _ => 0,
+                    };
+                    self.bucket.refund(allowed - written);
+                    return result;
+                }
+                Err(delay) => self.wait = Some(Box::pin(tokio::time::sleep(delay))),
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_parse_rate_limits() {
+        assert_eq!("100".parse(), Ok(RateLimit::per_second(100)));
+        assert_eq!(
+            "100/10".parse(),
+            Ok(RateLimit {
+                tokens_per_second: 100,
+                burst: 10
+            })
+        );
+        assert!("0".parse::<RateLimit>().is_err());
+        assert!("100/0".parse::<RateLimit>().is_err());
+        assert!("fast".parse::<RateLimit>().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_writes_wait_for_the_rate() {
+        let (mut reader, writer) = tokio::io::duplex(1024);
+        let bucket = TokenBucket::new(
+            RateLimit {
+                tokens_per_second: 10,
+                burst: 2,
+            },
+            2,
+        );
+        let mut writer = RateLimitedWriter::new(Box::new(writer), bucket);
+        let started = Instant::now();
+        // The burst goes out at once; the other four tokens take 0.1 s each.
+        writer.write_all(&[0, 1, 0, 2]).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        writer.write_all(&[0, 3, 0, 4, 0, 5, 0, 6]).await.unwrap();
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(400) && elapsed < Duration::from_millis(500),
+            "{elapsed:?}"
+        );
+        writer.shutdown().await.unwrap();
+        drop(writer);
+
+        let mut written = Vec::new();
+        reader.read_to_end(&mut written).await.unwrap();
+        assert_eq!(written, [0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6]);
+    }
+}
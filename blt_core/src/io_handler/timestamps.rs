@@ -0,0 +1,199 @@
+//! Stripping timestamps from log lines before tokenization.
+//!
+//! A timestamp is unique to every line of a log, so tokens spent on it teach a model nothing
+//! about the events themselves. [`TimestampStripping`] removes the first match of a regular
+//! expression from each line, together with the spaces after it. The default pattern matches a
+//! leading RFC 3339 / ISO 8601 timestamp, a syslog `Mmm dd hh:mm:ss` stamp, or Unix epoch
+//! seconds, optionally in square brackets.
+
+use super::preprocess::{self, ByteTransform};
+use super::InputSource;
+use regex::bytes::Regex;
+use std::io;
+use std::str::FromStr;
+
+/// Lines longer than this are only searched for a timestamp in their first this many bytes.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// Removes a timestamp from each line of a document.
+#[derive(Clone, Debug)]
+pub struct TimestampStripping {
+    pattern: Regex,
+}
+
+impl TimestampStripping {
+    /// The pattern used when none is given.
+    pub const DEFAULT_PATTERN: &'static str = concat!(
+        r"^\[?(?:",
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?",
+        r"|[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}",
+        r"|\d{10}(?:\.\d+)?",
+        r")\]?",
+    );
+
+    /// Strips the first match of `pattern` from each line.
+    pub fn new(pattern: &str) -> io::Result<Self> {
+        let pattern = Regex::new(pattern).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid timestamp pattern '{pattern}': {e}"),
+            )
+        })?;
+        Ok(Self { pattern })
+    }
+
+    /// The regular expression timestamps are matched with.
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// Wraps `source` so that it yields the document without timestamps.
+    pub(crate) fn wrap(&self, source: InputSource) -> InputSource {
+        preprocess::apply(source, TimestampStripper::new(self.pattern.clone()))
+    }
+}
+
+impl Default for TimestampStripping {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_PATTERN).expect("the default timestamp pattern is valid")
+    }
+}
+
+impl FromStr for TimestampStripping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s).map_err(|e| e.to_string())
+    }
+}
+
+/// Applies a timestamp pattern incrementally, holding back each line until it ends or grows
+/// past [`MAX_LINE_BYTES`].
+struct TimestampStripper {
+    pattern: Regex,
+    line: Vec<u8>,
+    /// Whether the rest of an over-long line is being passed through.
+    passthrough: bool,
+}
+
+impl TimestampStripper {
+    fn new(pattern: Regex) -> Self {
+        Self {
+            pattern,
+            line: Vec::new(),
+            passthrough: false,
+        }
+    }
+
+    /// Writes the held-back line without its timestamp.
+    fn flush_line(&mut self, out: &mut Vec<u8>) {
+        match self.pattern.find(&self.line) {
+            Some(found) if !found.is_empty() => {
+                out.extend_from_slice(&self.line[..found.start()]);
+                let rest = &self.line[found.end()..];
+                let spaces = rest.iter().take_while(|&&b| b == b' ' || b == b'\t');
+                out.extend_from_slice(&rest[spaces.count()..]);
+            }
+            _ => out.extend_from_slice(&self.line),
+        }
+        self.line.clear();
+    }
+}
+
+impl ByteTransform for TimestampStripper {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let mut input = input;
+        while !input.is_empty() {
+            let (line, rest) = match input.iter().position(|&b| b == b'\n') {
+                Some(end) => input.split_at(end + 1),
+                None => (input, &[][..]),
+            };
+            let ended = line.ends_with(b"\n");
+            if self.passthrough {
+                out.extend_from_slice(line);
+            } else {
+                let take = line.len().min(MAX_LINE_BYTES - self.line.len());
+                self.line.extend_from_slice(&line[..take]);
+                if ended || self.line.len() == MAX_LINE_BYTES {
+                    self.flush_line(out);
+                    out.extend_from_slice(&line[take..]);
+                    self.passthrough = !ended;
+                }
+            }
+            if ended {
+                self.passthrough = false;
+            }
+            input = rest;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.flush_line(out);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::preprocess::tests::transform_bytewise;
+
+    fn strip(stripping: &TimestampStripping, input: &str) -> String {
+        let whole = {
+            let mut stripper = TimestampStripper::new(stripping.pattern.clone());
+            let mut out = Vec::new();
+            stripper.transform(input.as_bytes(), &mut out).unwrap();
+            stripper.finish(&mut out).unwrap();
+            out
+        };
+        let bytewise = transform_bytewise(
+            TimestampStripper::new(stripping.pattern.clone()),
+            input.as_bytes(),
+        );
+        assert_eq!(whole, bytewise);
+        String::from_utf8(whole).unwrap()
+    }
+
+    #[test]
+    fn test_default_pattern() {
+        let stripping = TimestampStripping::default();
+        let log = concat!(
+            "2024-01-15T10:00:00.123Z INFO started\n",
+            "[2024-01-15 10:00:01,5+01:00] WARN slow\n",
+            "Jan  5 10:00:02 host sshd[42]: accepted\n",
+            "1705312803.25\tERROR failed at 2024-01-15T10:00:03Z\n",
+            "  continued 2024-01-15T10:00:04Z\n",
+            "\n",
+            "no timestamp",
+        );
+        assert_eq!(
+            strip(&stripping, log),
+            concat!(
+                "INFO started\n",
+                "WARN slow\n",
+                "host sshd[42]: accepted\n",
+                "ERROR failed at 2024-01-15T10:00:03Z\n",
+                "  continued 2024-01-15T10:00:04Z\n",
+                "\n",
+                "no timestamp",
+            )
+        );
+    }
+
+    #[test]
+    fn test_custom_pattern_and_long_lines() {
+        let stripping: TimestampStripping = r"ts=\d+".parse().unwrap();
+        assert_eq!(stripping.pattern(), r"ts=\d+");
+        assert_eq!(
+            strip(&stripping, "level=info ts=1705 msg=ok\nts=1 ts=2\n"),
+            "level=info msg=ok\nts=2\n"
+        );
+
+        let long = format!("{} ts=3\nts=4 x", "a".repeat(MAX_LINE_BYTES));
+        let expected = format!("{} ts=3\nx", "a".repeat(MAX_LINE_BYTES));
+        assert_eq!(strip(&stripping, &long), expected);
+
+        assert!("[unclosed".parse::<TimestampStripping>().is_err());
+    }
+}
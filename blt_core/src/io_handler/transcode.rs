@@ -0,0 +1,515 @@
+//! Transcoding text inputs to UTF-8 before tokenization.
+//!
+//! Byte-level tokens encode whatever bytes they are given, so the same text stored as Latin-1,
+//! UTF-16, and UTF-8 tokenizes three different ways. With transcoding enabled, each document is
+//! decoded from its declared or detected encoding and re-encoded as UTF-8 as it is read.
+//! Detection looks at a byte order mark, then at whether the first [`DETECT_SAMPLE_BYTES`] look
+//! like UTF-16 or are valid UTF-8, and otherwise falls back to Windows-1252.
+
+use super::{preprocess, InputReader, InputSource};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// How much of a document is inspected to detect its encoding.
+pub const DETECT_SAMPLE_BYTES: usize = 1 << 20;
+
+/// The size of the reads decoded at a time.
+const READ_BUFFER_BYTES: usize = 64 * 1024;
+
+/// A text encoding inputs can be decoded from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8, validated and with a leading byte order mark removed.
+    Utf8,
+    /// UTF-16, little-endian.
+    Utf16Le,
+    /// UTF-16, big-endian.
+    Utf16Be,
+    /// ISO-8859-1, mapping every byte to the code point of the same value.
+    Latin1,
+    /// Windows-1252, Latin-1 with printable characters in 0x80-0x9F.
+    Windows1252,
+}
+
+impl Encoding {
+    /// Returns the name used on the CLI.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin-1",
+            Encoding::Windows1252 => "windows-1252",
+        }
+    }
+
+    /// The byte order mark this encoding starts with, if it has one.
+    fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+            Encoding::Latin1 | Encoding::Windows1252 => &[],
+        }
+    }
+
+    /// Guesses the encoding of a document from its first bytes. `complete` says whether
+    /// `sample` is the whole document, so a sequence cut off at its end is an error rather
+    /// than the sample boundary.
+    pub fn detect(sample: &[u8], complete: bool) -> Encoding {
+        for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be] {
+            if sample.starts_with(encoding.bom()) {
+                return encoding;
+            }
+        }
+        // Mostly-ASCII UTF-16 has a zero in every other byte.
+        let zeros_at = |parity: usize| {
+            sample
+                .iter()
+                .skip(parity)
+                .step_by(2)
+                .filter(|&&b| b == 0)
+                .count()
+        };
+        let units = sample.len() / 2;
+        let (even, odd) = (zeros_at(0), zeros_at(1));
+        if units > 0 && odd * 10 >= units * 3 && even * 10 < units {
+            return Encoding::Utf16Le;
+        }
+        if units > 0 && even * 10 >= units * 3 && odd * 10 < units {
+            return Encoding::Utf16Be;
+        }
+        match std::str::from_utf8(sample) {
+            Ok(_) => Encoding::Utf8,
+            Err(e) if e.error_len().is_none() && !complete => Encoding::Utf8,
+            Err(_) => Encoding::Windows1252,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+            _ => Err(format!(
+                "Unsupported encoding: '{s}'. Use utf-8, utf-16le, utf-16be, latin-1, or windows-1252."
+            )),
+        }
+    }
+}
+
+/// What to do with bytes that are not valid in the input encoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecodeErrorPolicy {
+    /// Substitutes U+FFFD REPLACEMENT CHARACTER for each undecodable sequence.
+    #[default]
+    Replace,
+    /// Drops undecodable sequences.
+    Skip,
+    /// Fails the run.
+    Fail,
+}
+
+impl FromStr for DecodeErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "replace" => Ok(DecodeErrorPolicy::Replace),
+            "skip" => Ok(DecodeErrorPolicy::Skip),
+            "fail" => Ok(DecodeErrorPolicy::Fail),
+            _ => Err(format!(
+                "Unsupported decode error policy: '{s}'. Use replace, skip, or fail."
+            )),
+        }
+    }
+}
+
+/// How documents are transcoded to UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Transcoding {
+    /// The encoding of every document, or `None` to detect it per document.
+    pub encoding: Option<Encoding>,
+    /// What to do with undecodable bytes.
+    pub on_error: DecodeErrorPolicy,
+}
+
+impl Transcoding {
+    /// Wraps `source` so it yields UTF-8. Memory-mapped files are read as streams, since the
+    /// transcoded text no longer lines up with the file's bytes.
+    pub(crate) fn wrap(&self, source: InputSource) -> InputSource {
+        let reader = preprocess::into_reader(source);
+        InputSource::Reader(Box::new(TranscodingReader::new(reader, *self)))
+    }
+}
+
+/// Incrementally decodes one encoding into UTF-8.
+struct Decoder {
+    encoding: Encoding,
+    policy: DecodeErrorPolicy,
+    /// Bytes of a sequence cut off at the end of the previous input.
+    pending: Vec<u8>,
+    /// The input offset of the first pending byte, for error messages.
+    offset: u64,
+    started: bool,
+}
+
+impl Decoder {
+    fn new(encoding: Encoding, policy: DecodeErrorPolicy) -> Self {
+        Self {
+            encoding,
+            policy,
+            pending: Vec::new(),
+            offset: 0,
+            started: false,
+        }
+    }
+
+    /// Appends the UTF-8 for `input` to `out`. With `last`, a sequence cut off at the end of
+    /// `input` is undecodable instead of being kept for the next call.
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>, last: bool) -> io::Result<()> {
+        let joined;
+        let mut bytes = if self.pending.is_empty() {
+            input
+        } else {
+            joined = [std::mem::take(&mut self.pending).as_slice(), input].concat();
+            &joined[..]
+        };
+        if !self.started {
+            let bom = self.encoding.bom();
+            if bytes.len() < bom.len() && bom.starts_with(bytes) && !last {
+                self.pending = bytes.to_vec();
+                return Ok(());
+            }
+            self.started = true;
+            if !bom.is_empty() && bytes.starts_with(bom) {
+                bytes = &bytes[bom.len()..];
+                self.offset += bom.len() as u64;
+            }
+        }
+        let used = match self.encoding {
+            Encoding::Utf8 => self.decode_utf8(bytes, out, last)?,
+            Encoding::Utf16Le => self.decode_utf16(bytes, out, last, u16::from_le_bytes)?,
+            Encoding::Utf16Be => self.decode_utf16(bytes, out, last, u16::from_be_bytes)?,
+            Encoding::Latin1 => {
+                out.extend(bytes.iter().flat_map(|&b| encode(b as char)));
+                bytes.len()
+            }
+            Encoding::Windows1252 => {
+                out.extend(bytes.iter().flat_map(|&b| encode(windows_1252(b))));
+                bytes.len()
+            }
+        };
+        self.pending = bytes[used..].to_vec();
+        self.offset += used as u64;
+        Ok(())
+    }
+
+    /// Decodes UTF-8 and returns how many bytes were consumed.
+    fn decode_utf8(&mut self, bytes: &[u8], out: &mut Vec<u8>, last: bool) -> io::Result<usize> {
+        let mut position = 0;
+        loop {
+            match std::str::from_utf8(&bytes[position..]) {
+                Ok(valid) => {
+                    out.extend_from_slice(valid.as_bytes());
+                    return Ok(bytes.len());
+                }
+                Err(e) => {
+                    let valid_end = position + e.valid_up_to();
+                    out.extend_from_slice(&bytes[position..valid_end]);
+                    match e.error_len() {
+                        Some(len) => {
+                            self.undecodable(out, valid_end)?;
+                            position = valid_end + len;
+                        }
+                        None if last => {
+                            self.undecodable(out, valid_end)?;
+                            return Ok(bytes.len());
+                        }
+                        None => return Ok(valid_end),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decodes UTF-16 code units read with `unit` and returns how many bytes were consumed.
+    fn decode_utf16(
+        &mut self,
+        bytes: &[u8],
+        out: &mut Vec<u8>,
+        last: bool,
+        unit: fn([u8; 2]) -> u16,
+    ) -> io::Result<usize> {
+        let mut end = bytes.len() - bytes.len() % 2;
+        // Keep a trailing high surrogate until the low surrogate after it arrives.
+        if !last && end >= 2 && (0xD800..0xDC00).contains(&unit([bytes[end - 2], bytes[end - 1]])) {
+            end -= 2;
+        }
+        let units = bytes[..end]
+            .chunks_exact(2)
+            .map(|pair| unit([pair[0], pair[1]]));
+        let mut position = 0;
+        for decoded in char::decode_utf16(units) {
+            match decoded {
+                Ok(c) => {
+                    out.extend(encode(c));
+                    position += c.len_utf16() * 2;
+                }
+                Err(_) => {
+                    self.undecodable(out, position)?;
+                    position += 2;
+                }
+            }
+        }
+        if last && end < bytes.len() {
+            self.undecodable(out, end)?;
+            return Ok(bytes.len());
+        }
+        Ok(end)
+    }
+
+    /// Applies the error policy to an undecodable sequence `at` bytes into the current input.
+    fn undecodable(&self, out: &mut Vec<u8>, at: usize) -> io::Result<()> {
+        match self.policy {
+            DecodeErrorPolicy::Replace => out.extend(encode(char::REPLACEMENT_CHARACTER)),
+            DecodeErrorPolicy::Skip => {}
+            DecodeErrorPolicy::Fail => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Input is not valid {} at byte offset {}",
+                        self.encoding,
+                        self.offset + at as u64
+                    ),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode(c: char) -> impl Iterator<Item = u8> {
+    let mut buffer = [0; 4];
+    let len = c.encode_utf8(&mut buffer).len();
+    buffer.into_iter().take(len)
+}
+
+/// Maps a Windows-1252 byte to its character. The five bytes the code page leaves undefined
+/// map to the C1 control of the same value, as web browsers do.
+fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}',
+        '\u{2021}', '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}',
+        '\u{8F}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}',
+        '\u{2014}', '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}',
+        '\u{178}',
+    ];
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// Decodes a reader into UTF-8, detecting the encoding from the first bytes if needed.
+struct TranscodingReader {
+    inner: InputReader,
+    transcoding: Transcoding,
+    decoder: Option<Decoder>,
+    /// Input read but not yet decoded: the detection sample, then each read.
+    input: Vec<u8>,
+    /// Decoded output not yet returned.
+    output: Vec<u8>,
+    output_pos: usize,
+    eof: bool,
+    /// Whether the decoder has been flushed after the end of the input.
+    finished: bool,
+}
+
+impl TranscodingReader {
+    fn new(inner: InputReader, transcoding: Transcoding) -> Self {
+        Self {
+            inner,
+            decoder: transcoding
+                .encoding
+                .map(|encoding| Decoder::new(encoding, transcoding.on_error)),
+            transcoding,
+            input: Vec::new(),
+            output: Vec::new(),
+            output_pos: 0,
+            eof: false,
+            finished: false,
+        }
+    }
+
+    /// Reads more input into `input`, setting `eof` when the inner reader is exhausted.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<io::Result<()>> {
+        let start = self.input.len();
+        self.input.resize(start + limit, 0);
+        let mut buf = ReadBuf::new(&mut self.input[start..]);
+        let result = Pin::new(&mut self.inner).poll_read(cx, &mut buf);
+        let read = buf.filled().len();
+        self.input.truncate(start + read);
+        ready!(result)?;
+        if read == 0 {
+            self.eof = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for TranscodingReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while this.output_pos == this.output.len() {
+            this.output.clear();
+            this.output_pos = 0;
+            if this.decoder.is_none() {
+                while !this.eof && this.input.len() < DETECT_SAMPLE_BYTES {
+                    let limit = DETECT_SAMPLE_BYTES - this.input.len();
+                    ready!(this.poll_fill(cx, limit))?;
+                }
+                let encoding = Encoding::detect(&this.input, this.eof);
+                tracing::debug!(%encoding, "Detected input encoding");
+                this.decoder = Some(Decoder::new(encoding, this.transcoding.on_error));
+            }
+            if this.input.is_empty() && !this.eof {
+                ready!(this.poll_fill(cx, READ_BUFFER_BYTES))?;
+            }
+            let decoder = this.decoder.as_mut().expect("decoder is set");
+            if this.input.is_empty() && this.eof {
+                if this.finished {
+                    return Poll::Ready(Ok(()));
+                }
+                // Flush a sequence cut off by the end of the input.
+                decoder.decode(&[], &mut this.output, true)?;
+                this.finished = true;
+            } else {
+                let input = std::mem::take(&mut this.input);
+                decoder.decode(&input, &mut this.output, false)?;
+            }
+        }
+        let n = buf.remaining().min(this.output.len() - this.output_pos);
+        buf.put_slice(&this.output[this.output_pos..this.output_pos + n]);
+        this.output_pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn transcode(
+        input: &[u8],
+        encoding: Option<Encoding>,
+        on_error: DecodeErrorPolicy,
+    ) -> io::Result<Vec<u8>> {
+        let transcoding = Transcoding { encoding, on_error };
+        let mut reader =
+            TranscodingReader::new(Box::new(std::io::Cursor::new(input.to_vec())), transcoding);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encoding::detect("café".as_bytes(), true), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"caf\xe9", true), Encoding::Windows1252);
+        assert_eq!(Encoding::detect(b"caf\xc3", false), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xff\xfeh\0", true), Encoding::Utf16Le);
+        assert_eq!(
+            Encoding::detect(b"h\0i\0 \0t\0h\0e\0r\0e\0", true),
+            Encoding::Utf16Le
+        );
+        assert_eq!(Encoding::detect(b"\0h\0i\0!", true), Encoding::Utf16Be);
+    }
+
+    #[tokio::test]
+    async fn test_transcodes_detected_encodings() {
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(
+                "h\u{e9}llo \u{1F600}"
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes),
+            )
+            .collect();
+        let out = transcode(&utf16, None, DecodeErrorPolicy::Fail)
+            .await
+            .unwrap();
+        assert_eq!(out, "h\u{e9}llo \u{1F600}".as_bytes());
+
+        let cp1252 = transcode(b"\x93caf\xe9\x94", None, DecodeErrorPolicy::Fail)
+            .await
+            .unwrap();
+        assert_eq!(cp1252, "\u{201C}caf\u{e9}\u{201D}".as_bytes());
+
+        let latin1 = transcode(b"\x93", Some(Encoding::Latin1), DecodeErrorPolicy::Fail)
+            .await
+            .unwrap();
+        assert_eq!(latin1, "\u{93}".as_bytes());
+
+        let bom = transcode(b"\xef\xbb\xbfok", None, DecodeErrorPolicy::Fail)
+            .await
+            .unwrap();
+        assert_eq!(bom, b"ok");
+    }
+
+    #[tokio::test]
+    async fn test_error_policies() {
+        let input = b"ok\xffgo\xe2\x82";
+        let replaced = transcode(input, Some(Encoding::Utf8), DecodeErrorPolicy::Replace)
+            .await
+            .unwrap();
+        assert_eq!(replaced, "ok\u{FFFD}go\u{FFFD}".as_bytes());
+        let skipped = transcode(input, Some(Encoding::Utf8), DecodeErrorPolicy::Skip)
+            .await
+            .unwrap();
+        assert_eq!(skipped, b"okgo");
+        let err = transcode(input, Some(Encoding::Utf8), DecodeErrorPolicy::Fail)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("byte offset 2"));
+    }
+
+    #[test]
+    fn test_sequences_split_across_reads() {
+        let text = "a\u{e9}\u{1F600}z";
+        let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        for (encoding, bytes) in [
+            (Encoding::Utf8, text.as_bytes()),
+            (Encoding::Utf16Be, &utf16[..]),
+        ] {
+            for split in 0..=bytes.len() {
+                let mut decoder = Decoder::new(encoding, DecodeErrorPolicy::Fail);
+                let mut out = Vec::new();
+                decoder.decode(&bytes[..split], &mut out, false).unwrap();
+                decoder.decode(&bytes[split..], &mut out, true).unwrap();
+                assert_eq!(out, text.as_bytes(), "{encoding} split at {split}");
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! File reads and writes through io_uring on Linux.
+//!
+//! Tokio performs file I/O on its blocking thread pool, one syscall and one thread handoff per
+//! read or write. At multi-GB/s that overhead shows, so with `CoreConfig::io_uring` streamed
+//! input files and output files are instead served by a dedicated thread per file that keeps
+//! several large reads or writes in flight on its own ring. Chunks pass between that thread and
+//! the async side over bounded channels, so no more than [`DEPTH`] chunks are held per file.
+//!
+//! A kernel without io_uring support, or one that forbids it, is reported at debug level and the
+//! file is handled through tokio as usual.
+
+use super::{InputReader, OutputWriter};
+use io_uring::{opcode, types, IoUring};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Seek};
+use std::os::fd::AsRawFd;
+use std::pin::Pin;
+use std::sync::mpsc as std_mpsc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Bytes per read or write submitted to the ring.
+const CHUNK: usize = 256 * 1024;
+/// The most reads or writes in flight per file.
+const DEPTH: usize = 4;
+
+/// Streams `file` from its current position through io_uring, or through tokio if the file is
+/// not a regular file or no ring can be set up.
+pub(crate) fn reader(mut file: File) -> io::Result<InputReader> {
+    if !file.metadata()?.is_file() {
+        return Ok(Box::new(tokio::fs::File::from_std(file)));
+    }
+    let Some(mut ring) = new_ring() else {
+        return Ok(Box::new(tokio::fs::File::from_std(file)));
+    };
+    let offset = file.stream_position()?;
+    let (tx, rx) = mpsc::channel(DEPTH);
+    std::thread::Builder::new()
+        .name("blt-io-uring-read".to_string())
+        .spawn(move || read_file(&mut ring, &file, offset, &tx))?;
+    Ok(Box::new(UringReader {
+        chunks: rx,
+        current: Vec::new(),
+        position: 0,
+    }))
+}
+
+/// Writes `file` from its current position through io_uring, or through tokio if no ring can be
+/// set up.
+pub(crate) fn writer(mut file: File) -> io::Result<OutputWriter> {
+    let Some(mut ring) = new_ring() else {
+        return Ok(Box::new(tokio::fs::File::from_std(file)));
+    };
+    let offset = file.stream_position()?;
+    let (write_tx, write_rx) = std_mpsc::channel();
+    let (done_tx, done_rx) = mpsc::unbounded_channel();
+    std::thread::Builder::new()
+        .name("blt-io-uring-write".to_string())
+        .spawn(move || write_file(&mut ring, &file, offset, &write_rx, &done_tx))?;
+    Ok(Box::new(UringWriter {
+        buffer: Vec::with_capacity(CHUNK),
+        in_flight: 0,
+        writes: Some(write_tx),
+        done: done_rx,
+    }))
+}
+
+fn new_ring() -> Option<IoUring> {
+    match IoUring::new(2 * DEPTH as u32) {
+        Ok(ring) => Some(ring),
+        Err(error) => {
+            debug!(%error, "io_uring is unavailable; using tokio file I/O");
+            None
+        }
+    }
+}
+
+/// Submits pending entries and waits for at least one completion, retrying on interrupts.
+fn submit_and_wait(ring: &mut IoUring) -> io::Result<()> {
+    loop {
+        match ring.submit_and_wait(1) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result.map(|_| ()),
+        }
+    }
+}
+
+/// A read in flight: `buf` is filled from `offset` until it is full or the file ends.
+struct Read {
+    buf: Vec<u8>,
+    offset: u64,
+    filled: usize,
+    done: bool,
+    error: Option<io::Error>,
+}
+
+impl Read {
+    fn push(&mut self, ring: &mut IoUring, fd: types::Fd, seq: u64) {
+        let remaining = &mut self.buf[self.filled..];
+        let entry = opcode::Read::new(fd, remaining.as_mut_ptr(), remaining.len() as u32)
+            .offset(self.offset + self.filled as u64)
+            .build()
+            .user_data(seq);
+        // SAFETY: the buffer is owned by `self`, which stays alive, unmoved on the heap, until
+        // its completion is reaped. The ring holds twice as many entries as reads in flight.
+        unsafe { ring.submission().push(&entry) }.expect("io_uring submission queue is full");
+    }
+}
+
+/// Reads `file` from `offset` in chunks, keeping [`DEPTH`] reads in flight, and sends the chunks
+/// in file order until the end of the file, an error, or the receiver going away.
+fn read_file(
+    ring: &mut IoUring,
+    file: &File,
+    mut offset: u64,
+    tx: &mpsc::Sender<io::Result<Vec<u8>>>,
+) {
+    let fd = types::Fd(file.as_raw_fd());
+    let mut reads: VecDeque<Read> = VecDeque::with_capacity(DEPTH);
+    // The sequence number of the front read; reads are tagged with theirs.
+    let mut front = 0u64;
+    let mut stopped = false;
+    loop {
+        while reads.front().is_some_and(|read| read.done) {
+            let mut read = reads.pop_front().expect("front read exists");
+            front += 1;
+            if stopped {
+                continue;
+            }
+            if let Some(error) = read.error {
+                let _ = tx.blocking_send(Err(error));
+                stopped = true;
+                continue;
+            }
+            // Reads are only done short of a full chunk at the end of the file.
+            stopped = read.filled < CHUNK;
+            read.buf.truncate(read.filled);
+            if !read.buf.is_empty() && tx.blocking_send(Ok(read.buf)).is_err() {
+                stopped = true;
+            }
+        }
+        while !stopped && reads.len() < DEPTH {
+            let mut read = Read {
+                buf: vec![0; CHUNK],
+                offset,
+                filled: 0,
+                done: false,
+                error: None,
+            };
+            read.push(ring, fd, front + reads.len() as u64);
+            reads.push_back(read);
+            offset += CHUNK as u64;
+        }
+        if reads.is_empty() {
+            return;
+        }
+        if let Err(error) = submit_and_wait(ring) {
+            let _ = tx.blocking_send(Err(error));
+            // The kernel may still fill these buffers, so they must never be freed.
+            std::mem::forget(reads);
+            return;
+        }
+        let completions: Vec<(u64, i32)> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (seq, result) in completions {
+            let read = &mut reads[(seq - front) as usize];
+            match result {
+                0 => read.done = true,
+                n if n > 0 => {
+                    read.filled += n as usize;
+                    read.done = read.filled == CHUNK;
+                    if !read.done {
+                        read.push(ring, fd, seq);
+                    }
+                }
+                n if -n == libc::EINTR || -n == libc::EAGAIN => read.push(ring, fd, seq),
+                n => {
+                    read.done = true;
+                    read.error = Some(io::Error::from_raw_os_error(-n));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the chunks sent by the ring thread of a file.
+struct UringReader {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl AsyncRead for UringReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.position == self.current.len() {
+            match ready!(self.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => {
+                    self.current = chunk;
+                    self.position = 0;
+                }
+                Some(Err(error)) => return Poll::Ready(Err(error)),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let available = &self.current[self.position..];
+        let n = available.len().min(buf.remaining());
+        buf.put_slice(&available[..n]);
+        self.position += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A write in flight: `buf` is written at `offset` until all of it has been written.
+struct Write {
+    buf: Vec<u8>,
+    offset: u64,
+    written: usize,
+}
+
+impl Write {
+    fn push(&self, ring: &mut IoUring, fd: types::Fd, slot: usize) {
+        let remaining = &self.buf[self.written..];
+        let entry = opcode::Write::new(fd, remaining.as_ptr(), remaining.len() as u32)
+            .offset(self.offset + self.written as u64)
+            .build()
+            .user_data(slot as u64);
+        // SAFETY: as for reads, the buffer outlives its completion.
+        unsafe { ring.submission().push(&entry) }.expect("io_uring submission queue is full");
+    }
+}
+
+/// Writes the chunks received on `writes` to `file` from `offset`, keeping up to [`DEPTH`]
+/// writes in flight and reporting each completed chunk on `done`, until `writes` closes.
+fn write_file(
+    ring: &mut IoUring,
+    file: &File,
+    mut offset: u64,
+    writes: &std_mpsc::Receiver<Vec<u8>>,
+    done: &mpsc::UnboundedSender<io::Result<()>>,
+) {
+    let fd = types::Fd(file.as_raw_fd());
+    let mut slots: Vec<Option<Write>> = (0..DEPTH).map(|_| None).collect();
+    let mut in_flight = 0;
+    loop {
+        while in_flight < DEPTH {
+            // Block for more work only when there is nothing to wait for on the ring.
+            let next = if in_flight == 0 {
+                writes.recv().ok()
+            } else {
+                writes.try_recv().ok()
+            };
+            let Some(buf) = next else {
+                break;
+            };
+            let slot = slots.iter().position(Option::is_none).expect("free slot");
+            let write = Write {
+                offset,
+                buf,
+                written: 0,
+            };
+            offset += write.buf.len() as u64;
+            write.push(ring, fd, slot);
+            slots[slot] = Some(write);
+            in_flight += 1;
+        }
+        if in_flight == 0 {
+            return;
+        }
+        if let Err(error) = submit_and_wait(ring) {
+            let _ = done.send(Err(error));
+            // The kernel may still read these buffers, so they must never be freed.
+            std::mem::forget(slots);
+            return;
+        }
+        let completions: Vec<(u64, i32)> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (slot, result) in completions {
+            let slot = slot as usize;
+            let write = slots[slot].as_mut().expect("write in flight");
+            let result = match result {
+                n if n > 0 => {
+                    write.written += n as usize;
+                    if write.written < write.buf.len() {
+                        write.push(ring, fd, slot);
+                        continue;
+                    }
+                    Ok(())
+                }
+                0 => Err(io::Error::from(io::ErrorKind::WriteZero)),
+                n if -n == libc::EINTR || -n == libc::EAGAIN => {
+                    write.push(ring, fd, slot);
+                    continue;
+                }
+                n => Err(io::Error::from_raw_os_error(-n)),
+            };
+            slots[slot] = None;
+            in_flight -= 1;
+            let _ = done.send(result);
+        }
+    }
+}
+
+/// Collects writes into chunks and hands them to the ring thread of a file.
+struct UringWriter {
+    buffer: Vec<u8>,
+    /// Chunks handed over but not yet reported written.
+    in_flight: usize,
+    /// Closed on shutdown, which lets the ring thread finish.
+    writes: Option<std_mpsc::Sender<Vec<u8>>>,
+    done: mpsc::UnboundedReceiver<io::Result<()>>,
+}
+
+impl UringWriter {
+    /// Waits for the next chunk to be written.
+    fn poll_completion(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match ready!(self.done.poll_recv(cx)) {
+            Some(result) => {
+                self.in_flight -= 1;
+                Poll::Ready(result)
+            }
+            None => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The io_uring writer thread stopped",
+            ))),
+        }
+    }
+
+    /// Hands the buffered bytes to the ring thread once fewer than [`DEPTH`] chunks are in flight.
+    fn poll_submit(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.in_flight >= DEPTH {
+            ready!(self.poll_completion(cx))?;
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK));
+        let sent = self
+            .writes
+            .as_ref()
+            .is_some_and(|tx| tx.send(chunk).is_ok());
+        if !sent {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The io_uring writer is shut down",
+            )));
+        }
+        self.in_flight += 1;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for UringWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.buffer.len() >= CHUNK {
+            ready!(self.poll_submit(cx))?;
+        }
+        let n = buf.len().min(CHUNK - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.buffer.is_empty() {
+            ready!(self.poll_submit(cx))?;
+        }
+        while self.in_flight > 0 {
+            ready!(self.poll_completion(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.writes = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_roundtrip_through_the_ring() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        // Several chunks and a partial one, so reads and writes overlap on the ring.
+        let data: Vec<u8> = (0..(DEPTH * 2 + 1) * CHUNK + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        let mut writer = writer(File::create(&path).unwrap()).unwrap();
+        for piece in data.chunks(10_000) {
+            writer.write_all(piece).await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        let mut file = File::open(&path).unwrap();
+        file.seek(io::SeekFrom::Start(5)).unwrap();
+        let mut read = Vec::new();
+        reader(file).unwrap().read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, data[5..]);
+    }
+}
@@ -0,0 +1,186 @@
+//! Discovering input files: directory walks and file lists.
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Glob filters selecting which files of a directory input are processed.
+///
+/// Patterns are matched against each file's path relative to the input directory, and `*`
+/// also matches across directory separators, so `*.txt` selects text files at any depth.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputFilter {
+    /// Patterns a file must match to be included; an empty list includes every file.
+    pub include: Vec<String>,
+    /// Patterns excluding otherwise included files.
+    pub exclude: Vec<String>,
+}
+
+impl InputFilter {
+    fn compile(patterns: &[String]) -> io::Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = Glob::new(pattern).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid glob pattern '{pattern}': {e}"),
+                )
+            })?;
+            builder.add(glob);
+        }
+        builder.build().map_err(io::Error::other)
+    }
+}
+
+/// Recursively lists the files under `root` selected by `filter`, sorted by path.
+///
+/// Symbolic links to files are followed; symbolic links to directories are not, which keeps
+/// the walk free of cycles.
+pub fn collect_input_files(root: &Path, filter: &InputFilter) -> io::Result<Vec<PathBuf>> {
+    let include = InputFilter::compile(&filter.include)?;
+    let exclude = InputFilter::compile(&filter.exclude)?;
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if !(file_type.is_file() || (file_type.is_symlink() && path.is_file())) {
+                continue;
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let included = filter.include.is_empty() || include.is_match(relative);
+            if included && !exclude.is_match(relative) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads a newline-separated list of input paths from `list`, in list order.
+///
+/// Blank lines and lines starting with `#` are skipped. Every listed path must name an
+/// existing file, so a typo fails the run before any output is written.
+pub fn read_filelist(list: &Path) -> io::Result<Vec<PathBuf>> {
+    let contents = fs::read_to_string(list).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to read file list {}: {e}", list.display()),
+        )
+    })?;
+    let mut files = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let path = PathBuf::from(line);
+        if !path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Input file '{}' listed in {} does not exist",
+                    path.display(),
+                    list.display()
+                ),
+            ));
+        }
+        files.push(path);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        for name in [
+            "top.txt",
+            "a/one.txt",
+            "a/skip.log",
+            "a/b/two.txt",
+            "a/b/data.bin",
+        ] {
+            fs::write(dir.path().join(name), name).unwrap();
+        }
+        dir
+    }
+
+    fn relative(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_collects_everything_sorted() {
+        let dir = tree();
+        let files = collect_input_files(dir.path(), &InputFilter::default()).unwrap();
+        assert_eq!(
+            relative(dir.path(), files),
+            vec![
+                "a/b/data.bin",
+                "a/b/two.txt",
+                "a/one.txt",
+                "a/skip.log",
+                "top.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include_and_exclude() {
+        let dir = tree();
+        let filter = InputFilter {
+            include: vec!["*.txt".into(), "*.log".into()],
+            exclude: vec!["a/b/*".into(), "*.log".into()],
+        };
+        let files = collect_input_files(dir.path(), &filter).unwrap();
+        assert_eq!(relative(dir.path(), files), vec!["a/one.txt", "top.txt"]);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let dir = tree();
+        let filter = InputFilter {
+            include: vec!["[".into()],
+            exclude: Vec::new(),
+        };
+        assert!(collect_input_files(dir.path(), &filter).is_err());
+    }
+
+    #[test]
+    fn test_filelist_preserves_order() {
+        let dir = tree();
+        let list = dir.path().join("inputs.txt");
+        let top = dir.path().join("top.txt");
+        let one = dir.path().join("a/one.txt");
+        fs::write(
+            &list,
+            format!("# shard order\n{}\n\n{}\n", top.display(), one.display()),
+        )
+        .unwrap();
+        assert_eq!(read_filelist(&list).unwrap(), vec![top, one]);
+    }
+
+    #[test]
+    fn test_filelist_missing_file() {
+        let dir = tree();
+        let list = dir.path().join("inputs.txt");
+        fs::write(&list, dir.path().join("missing.txt").display().to_string()).unwrap();
+        let err = read_filelist(&list).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
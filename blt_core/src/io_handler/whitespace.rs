@@ -0,0 +1,195 @@
+//! Whitespace normalization before tokenization.
+//!
+//! Scraped and exported text is full of whitespace that carries no meaning but still costs
+//! tokens: indentation, trailing spaces, runs of blank lines, and tabs whose width depends on
+//! the editor. [`WhitespaceNormalization`] rewrites each document as it is read, with every
+//! option independent of the others.
+
+use super::preprocess::{self, ByteTransform};
+use super::InputSource;
+use std::io;
+
+/// How a document's whitespace is rewritten.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WhitespaceNormalization {
+    /// Replaces each tab with spaces up to the next multiple of this many columns.
+    pub tab_width: Option<usize>,
+    /// Turns each run of spaces and tabs into one space, drops spaces before line breaks,
+    /// and keeps at most one blank line between paragraphs.
+    pub collapse: bool,
+    /// Drops whitespace at the start and end of each document.
+    pub trim: bool,
+}
+
+impl WhitespaceNormalization {
+    /// Returns `true` if documents pass through unchanged.
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Wraps `source` so that it yields the normalized document.
+    pub(crate) fn wrap(self, source: InputSource) -> InputSource {
+        preprocess::apply(source, WhitespaceNormalizer::new(self))
+    }
+}
+
+/// ASCII whitespace, including vertical tabs and form feeds.
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | 0x0B | 0x0C)
+}
+
+/// Applies a [`WhitespaceNormalization`] incrementally, holding back each whitespace run until
+/// the byte after it shows whether it ends the document.
+struct WhitespaceNormalizer {
+    options: WhitespaceNormalization,
+    /// The current column, counted in characters, for tab expansion.
+    column: usize,
+    /// Whether any non-whitespace byte has been written yet.
+    started: bool,
+    /// The pending whitespace run, verbatim, when not collapsing.
+    run: Vec<u8>,
+    /// Line breaks in the pending run, when collapsing.
+    run_newlines: usize,
+    /// Whether the pending run has any other whitespace, when collapsing.
+    run_spaces: bool,
+}
+
+impl WhitespaceNormalizer {
+    fn new(options: WhitespaceNormalization) -> Self {
+        Self {
+            options,
+            column: 0,
+            started: false,
+            run: Vec::new(),
+            run_newlines: 0,
+            run_spaces: false,
+        }
+    }
+
+    /// Feeds one byte after tab expansion.
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) {
+        if !is_whitespace(byte) {
+            self.flush_run(out);
+            out.push(byte);
+            self.started = true;
+        } else if !self.options.collapse {
+            self.run.push(byte);
+        } else if byte == b'\n' {
+            self.run_newlines += 1;
+        } else {
+            self.run_spaces = true;
+        }
+    }
+
+    /// Writes the pending whitespace run, or drops it at the start of a trimmed document.
+    fn flush_run(&mut self, out: &mut Vec<u8>) {
+        if self.started || !self.options.trim {
+            if !self.options.collapse {
+                out.extend_from_slice(&self.run);
+            } else if self.run_newlines > 0 {
+                out.extend(std::iter::repeat_n(b'\n', self.run_newlines.min(2)));
+            } else if self.run_spaces {
+                out.push(b' ');
+            }
+        }
+        self.run.clear();
+        self.run_newlines = 0;
+        self.run_spaces = false;
+    }
+}
+
+impl ByteTransform for WhitespaceNormalizer {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            match (byte, self.options.tab_width) {
+                (b'\t', Some(width)) if width > 0 => {
+                    let spaces = width - self.column % width;
+                    for _ in 0..spaces {
+                        self.push(b' ', out);
+                    }
+                    self.column += spaces;
+                }
+                _ => {
+                    self.push(byte, out);
+                    match byte {
+                        b'\n' => self.column = 0,
+                        // UTF-8 continuation bytes do not start a character.
+                        _ if byte & 0xC0 != 0x80 => self.column += 1,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.options.trim {
+            self.run.clear();
+            self.run_newlines = 0;
+            self.run_spaces = false;
+        } else {
+            self.flush_run(out);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::preprocess::tests::transform_bytewise;
+
+    fn normalize(options: WhitespaceNormalization, input: &str) -> String {
+        let whole = {
+            let mut normalizer = WhitespaceNormalizer::new(options);
+            let mut out = Vec::new();
+            normalizer.transform(input.as_bytes(), &mut out).unwrap();
+            normalizer.finish(&mut out).unwrap();
+            out
+        };
+        let bytewise = transform_bytewise(WhitespaceNormalizer::new(options), input.as_bytes());
+        assert_eq!(whole, bytewise);
+        String::from_utf8(whole).unwrap()
+    }
+
+    const TEXT: &str = "  \tTitle  \r\n\n\n\nOne\t two   \nthree\t\n \n";
+
+    #[test]
+    fn test_options() {
+        let none = WhitespaceNormalization::default();
+        assert!(none.is_noop());
+        assert_eq!(normalize(none, TEXT), TEXT);
+
+        let collapse = WhitespaceNormalization {
+            collapse: true,
+            ..none
+        };
+        assert_eq!(normalize(collapse, TEXT), " Title\n\nOne two\nthree\n\n");
+
+        let trim = WhitespaceNormalization { trim: true, ..none };
+        assert_eq!(
+            normalize(trim, TEXT),
+            "Title  \r\n\n\n\nOne\t two   \nthree"
+        );
+
+        let all = WhitespaceNormalization {
+            tab_width: Some(4),
+            collapse: true,
+            trim: true,
+        };
+        assert_eq!(normalize(all, TEXT), "Title\n\nOne two\nthree");
+    }
+
+    #[test]
+    fn test_expands_tabs_to_tab_stops() {
+        let tabs = WhitespaceNormalization {
+            tab_width: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(tabs, "a\tb\n\tc\nabcd\té\tx"),
+            "a   b\n    c\nabcd    é   x"
+        );
+    }
+}
@@ -16,6 +16,8 @@
 //!   Byte-Pair Encoding and `PassthroughStrategy` for no-op tokenization.
 //! - **I/O Handling (`io_handler`):** Manages input sources (files, stdin) and output sinks
 //!   (files, stdout), abstracting away the details of synchronous vs. asynchronous I/O.
+//! - **Errors (`BltError`):** Runs and merges loaders fail with a typed error saying whether
+//!   the configuration, a merges file, I/O, the pipeline, or the strategy was at fault.
 //!
 //! ## Example Usage
 //!
@@ -42,37 +44,132 @@
 //! }
 //! ```
 
-use std::collections::HashMap;
+use rustc_hash::FxHashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-use tracing::{info, instrument};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, info, instrument, warn};
 
+use crate::content_types::ContentTypeRegistry;
+use crate::doc_ids::{DocumentIdSource, DocumentInfo};
+use crate::entropy::EntropyMarking;
+use crate::hashing::{ContentHasher, HashAlgorithm};
+use crate::interrupt::{CancellationToken, PartialOutput, SignalListener};
+use crate::io_handler::{
+    ByteRange, Compression, DocumentLabels, EncryptionKey, HashingWriter, HtmlMode, InputFilter,
+    LimitedWriter, NumberBucketing, OutputClaims, OutputDigest, OutputDir, RateLimit,
+    RateLimitedWriter, TimestampStripping, TokenBucket, TokenBudget, Transcoding,
+    WhitespaceNormalization,
+};
+use crate::manifest::{ManifestEntry, PreviousSnapshot};
+use crate::metrics::Metrics;
+use crate::pacing::{PacedStrategy, Pacing};
+use crate::pipeline::{FifoScheduler, Scheduler, ThreadScaling};
+use crate::postprocess::{
+    LogRecordPolicy, PackingPolicy, PackingStats, PadToPolicy, ShiftedPairsPolicy,
+    SpanCorruptionPolicy, TruncationPolicy,
+};
+use crate::progress::{Heartbeat, Progress};
+use crate::records::{RecordFramedStrategy, RecordLayout};
+use crate::sandbox::SandboxConfig;
 use crate::tokenizer::{
-    BasicTokenizationStrategy, BpeStrategy, PassthroughStrategy, TokenizationStrategy,
+    BasicTokenizationStrategy, BpeStrategy, ByteOffsetStrategy, LineBoundaryStrategy,
+    PassthroughStrategy, TokenizationStrategy,
 };
+use crate::usage::{ResourceUsage, Stage, StageCounters, UsageTracker};
 
 // --- Module declarations ---
+/// Throughput benchmarks sweeping thread counts and chunk sizes.
+pub mod bench;
+/// A pool of reusable byte buffers for chunk inputs and tokenized results.
+pub mod buffers;
 /// Handles dynamic chunk sizing based on system memory and CLI parameters.
 pub mod chunking;
 /// Responsible for loading BPE merge files.
 pub mod config_loader;
+/// A registry of built-in and custom content types and their tokens.
+pub mod content_types;
+/// Counting tokens per document without writing them.
+pub mod count;
+/// Unix socket daemon mode serving a vocabulary loaded once to many short-lived clients.
+pub mod daemon;
+/// Document IDs recorded in manifests, taken from paths, hashes, JSON fields, or callbacks.
+pub mod doc_ids;
+/// Planning runs and estimating their output without writing anything.
+pub mod dry_run;
+/// Entropy-based marking of compressed and plain regions of the input.
+pub mod entropy;
+/// The typed error runs and merges loaders fail with.
+pub mod error;
+/// Content hashing used for manifests and dedup keys.
+pub mod hashing;
+/// The optional self-describing header written at the start of raw outputs.
+pub mod header;
+/// Sidecar index files recording document offsets in the output.
+pub mod index;
+/// Cancelling a run, on request or on SIGINT or SIGTERM, keeping its output consistent.
+pub mod interrupt;
 /// Manages input and output sources, supporting files and standard I/O.
 pub mod io_handler;
+/// JSON Lines manifests describing each tokenized document.
+pub mod manifest;
+/// Prometheus counters fed by the pipeline, for long-running processes.
+pub mod metrics;
+/// Running CPU-bound strategies on the blocking thread pool, off the async runtime.
+pub mod offload;
+/// Duty-cycle pacing of chunk processing for energy- or carbon-aware runs.
+pub mod pacing;
 /// Contains the core multi-threaded pipeline logic for processing data chunks.
 pub mod pipeline;
+/// Document-level post-processing of the token stream.
+pub mod postprocess;
+/// CPU and I/O scheduling priority for the threads running a job.
+pub mod priority;
+/// Live progress reporting: bytes processed, throughput, and ETA.
+pub mod progress;
+/// Rewriting token shards into evenly sized ones on document boundaries.
+pub mod rebalance;
+/// Record-aware tokenization of length-prefixed binary formats.
+pub mod records;
+/// Rebuilding damaged per-document outputs recorded in a manifest.
+pub mod repair;
+/// Running chunk processing in sandboxed worker processes.
+pub mod sandbox;
+/// HTTP server mode exposing tokenization, decoding, health, and vocabulary endpoints.
+#[cfg(feature = "server")]
+pub mod server;
+/// Warm-start snapshots of a parsed vocabulary.
+pub mod snapshot;
+/// Reserved special token values (padding, separators, content types).
+pub mod special_tokens;
+/// Corpus statistics for evaluating vocabularies (coverage, merge usage).
+pub mod stats;
+/// Helpers for testing against blt: temporary corpora, golden fixtures, and assertions.
+#[cfg(feature = "testkit")]
+pub mod testkit;
 /// Defines tokenization strategies (BPE, Passthrough) and the `TokenizationStrategy` trait.
 pub mod tokenizer;
+/// Resource usage accounting for tokenizer runs.
+pub mod usage;
 /// Utilities for parsing configurations and detecting system resources.
 pub mod utils;
+/// Roundtrip verification that decoded tokens reproduce their input.
+pub mod verify;
 
 // --- Public API ---
 
+pub use error::BltError;
+
 /// A type alias for the BPE merge map.
 ///
 /// The map consists of a pair of tokens (as `u16`) that can be merged into a single new token (`u16`).
-pub type BpeMerges = HashMap<(u16, u16), u16>;
+/// It uses FxHash rather than the default SipHash: pair lookups are the hottest operation of
+/// BPE encoding, and the keys come from trusted merges files rather than untrusted input.
+/// Build one with `BpeMerges::default()` or by collecting pairs.
+pub type BpeMerges = FxHashMap<(u16, u16), u16>;
 
 /// Represents the type of content being processed.
 ///
@@ -88,28 +185,195 @@ pub enum ContentType {
     Bin,
     /// Video data.
     Video,
+    /// A user-registered content type (see [`content_types::ContentTypeRegistry`]).
+    Custom {
+        /// The lowercase name used on the CLI and in reports.
+        name: String,
+        /// The reserved token value prepended to the output.
+        token: u16,
+    },
 }
 
 impl ContentType {
+    /// Every built-in content type, in token order.
+    pub const ALL: [ContentType; 4] = [
+        ContentType::Text,
+        ContentType::Audio,
+        ContentType::Bin,
+        ContentType::Video,
+    ];
+
+    /// Returns the lowercase name used for this content type on the CLI and in reports.
+    pub fn name(&self) -> &str {
+        match self {
+            ContentType::Text => "text",
+            ContentType::Audio => "audio",
+            ContentType::Bin => "bin",
+            ContentType::Video => "video",
+            ContentType::Custom { name, .. } => name,
+        }
+    }
+
+    /// Guesses the content type of a file from its extension, falling back to `Bin`.
+    pub fn from_path(path: &Path) -> ContentType {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some(
+                "txt" | "md" | "rst" | "json" | "jsonl" | "csv" | "tsv" | "xml" | "html" | "htm"
+                | "log" | "py" | "rs" | "c" | "h" | "js" | "ts" | "toml" | "yaml" | "yml",
+            ) => ContentType::Text,
+            Some("wav" | "mp3" | "flac" | "ogg" | "opus" | "m4a" | "aac") => ContentType::Audio,
+            Some("mp4" | "mkv" | "webm" | "avi" | "mov" | "m4v") => ContentType::Video,
+            _ => ContentType::Bin,
+        }
+    }
+
     /// Returns the special token value associated with each content type.
-    /// These tokens are in a reserved range (0xFF01 - 0xFF04).
+    /// Built-in tokens are in a reserved range (0xFF01 - 0xFF04); custom types use the value
+    /// they were registered with.
     pub fn get_token_value(&self) -> u16 {
         match self {
             ContentType::Text => 0xFF01,
             ContentType::Audio => 0xFF02,
             ContentType::Bin => 0xFF03,
             ContentType::Video => 0xFF04,
+            ContentType::Custom { token, .. } => *token,
         }
     }
 }
 
+/// Identifies which tokenization strategy a configuration selects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrategyKind {
+    /// Copies bytes through unchanged.
+    Passthrough,
+    /// Maps each byte to one 16-bit token.
+    Basic,
+    /// Applies BPE merges on top of byte tokens.
+    Bpe,
+}
+
+/// The on-disk layout of the tokenized output.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// A flat stream of big-endian token values (the historical format).
+    #[default]
+    Raw,
+    /// An Arrow IPC stream with `doc_id`, `source`, and `list<uint16>` `tokens` columns.
+    ///
+    /// Requires the `arrow` feature.
+    ArrowIpc,
+    /// A Parquet file with `doc_id`, `source`, and `tokens` columns.
+    ///
+    /// Requires the `parquet` feature.
+    Parquet {
+        /// The maximum number of documents per row group.
+        row_group_size: usize,
+    },
+}
+
+impl OutputFormat {
+    /// The default number of documents per Parquet row group.
+    pub const DEFAULT_ROW_GROUP_SIZE: usize = 1024;
+}
+
+/// Begin-of-stream and end-of-stream tokens written around the tokenized output.
+///
+/// Markers are part of the token stream the post-processors see, so truncation and packing
+/// treat them like any other token of the document they open or close.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamMarkers {
+    /// The token written at the start of each marked span.
+    pub bos: Option<u16>,
+    /// The token written at the end of each marked span.
+    pub eos: Option<u16>,
+    /// Whether the markers wrap each output or each document.
+    pub scope: MarkerScope,
+}
+
+/// What [`StreamMarkers`] wrap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MarkerScope {
+    /// Each output: the run's single output, or each file of an output directory. The
+    /// begin-of-stream token precedes the content type token.
+    #[default]
+    Run,
+    /// Each input document, inside the document separators.
+    Document,
+}
+
+impl StreamMarkers {
+    fn is_empty(&self) -> bool {
+        self.bos.is_none() && self.eos.is_none()
+    }
+
+    /// The markers written around each document, if they are per-document.
+    fn per_document(&self) -> Option<&Self> {
+        (self.scope == MarkerScope::Document).then_some(self)
+    }
+
+    /// The markers written around each output, if they are per-output.
+    fn per_output(&self) -> Option<&Self> {
+        (self.scope == MarkerScope::Run).then_some(self)
+    }
+}
+
+/// How often the content type token is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContentTypeFraming {
+    /// Once at the start of each output, after the begin-of-stream token.
+    #[default]
+    Stream,
+    /// At the start of each input document, after its begin-of-stream token.
+    Document,
+    /// At the start of each output and again whenever this many tokens have passed without
+    /// one.
+    EveryTokens(usize),
+}
+
+impl std::str::FromStr for ContentTypeFraming {
+    type Err = io::Error;
+
+    /// Parses `stream`, `document`, or a positive token interval.
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "stream" => Ok(Self::Stream),
+            "document" => Ok(Self::Document),
+            _ => match s.parse::<usize>() {
+                Ok(interval) if interval > 0 => Ok(Self::EveryTokens(interval)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid content type framing '{s}', expected stream, document, or a positive token count"
+                    ),
+                )),
+            },
+        }
+    }
+}
+
+/// What happens when a chunk fails to tokenize.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Abort the run with the chunk's error.
+    #[default]
+    Fail,
+    /// Log a warning, leave the chunk's tokens out of the output, and carry on. Skipped
+    /// chunks are listed in [`RunReport::skipped_chunks`] and counted as errors in the
+    /// metrics.
+    Skip,
+}
+
 /// Central configuration for the tokenizer pipeline.
 ///
 /// This struct holds all the necessary settings to control the tokenization process,
 /// including I/O paths, tokenization strategy, and performance tuning parameters.
 #[derive(Debug, Clone)]
 pub struct CoreConfig {
-    /// Path to the input file. If `None`, stdin will be used.
+    /// Path to the input file or directory. If `None`, stdin will be used.
     pub input: Option<PathBuf>,
     /// Path to the output file. If `None`, stdout will be used.
     pub output: Option<PathBuf>,
@@ -117,6 +381,11 @@ pub struct CoreConfig {
     pub merges_file: Option<PathBuf>,
     /// The type of content being processed.
     pub content_type: Option<ContentType>,
+    /// The content types known to this run, including custom ones.
+    pub content_types: ContentTypeRegistry,
+    /// Optional layout of the length-prefixed records making up a binary input. Chunks end
+    /// on record boundaries and no token spans two records. Requires `ContentType::Bin`.
+    pub records: Option<RecordLayout>,
     /// The number of threads to use for the processing pipeline.
     pub num_threads: usize,
     /// The chunk size specified via CLI, in bytes.
@@ -127,6 +396,244 @@ pub struct CoreConfig {
     pub bpe_data: Option<Arc<BpeMerges>>,
     /// Whether to use passthrough mode (file copying without tokenization).
     pub passthrough_mode: bool,
+    /// The layout used when writing the output.
+    pub output_format: OutputFormat,
+    /// Glob filters applied when `input` is a directory.
+    pub input_filter: InputFilter,
+    /// The token written between the documents of a multi-document run, or `None` to
+    /// concatenate documents without a separator.
+    pub document_separator: Option<u16>,
+    /// Begin-of-stream and end-of-stream tokens written around each output or document.
+    pub stream_markers: StreamMarkers,
+    /// The value added to byte tokens: 0 maps byte `b` to token `b`, 256 maps it to
+    /// `b + 256` so byte tokens and low merge IDs occupy disjoint ranges.
+    pub byte_offset: u16,
+    /// The token ID assigned to the first merge in `merges_file`; later merges follow in
+    /// file order.
+    pub merge_id_base: u16,
+    /// Whether to fail when tokenization produces a token in the reserved special-token
+    /// range, instead of emitting a stream decoders would misread.
+    pub strict_specials: bool,
+    /// What happens when a chunk fails to tokenize, or breaks the `strict_specials` check.
+    /// Errors reading input or writing output always abort the run.
+    pub on_error: ErrorPolicy,
+    /// Where the content type token is written, if `content_type` is set.
+    pub content_type_framing: ContentTypeFraming,
+    /// Whether an existing output file may be replaced. Outputs are always written to a
+    /// temporary file and renamed into place once complete.
+    pub overwrite: bool,
+    /// Optional directory receiving one output per input document instead of `output`.
+    pub output_dir: Option<OutputDir>,
+    /// Optional file listing input paths, one per line, processed in list order instead of
+    /// `input`.
+    pub filelist: Option<PathBuf>,
+    /// Whether `input` is a tar, tar.gz, or zip archive whose members are tokenized as
+    /// separate documents.
+    pub archive: bool,
+    /// Optional classification of input chunks by entropy, writing a marker token before
+    /// each run of compressed-looking or plain chunks.
+    pub entropy_markers: Option<EntropyMarking>,
+    /// Whether `input` is an MP4 video whose metadata and tracks are tokenized as separate
+    /// documents, each opened by its segment token. Requires `ContentType::Video`.
+    pub demux: bool,
+    /// Optional compression applied to the whole output stream.
+    pub compression: Option<Compression>,
+    /// Optional key the output stream is encrypted with, after compression.
+    pub encryption: Option<EncryptionKey>,
+    /// Optional path that receives a copy of the original input bytes, written in lockstep
+    /// with the token output.
+    pub tee_original: Option<PathBuf>,
+    /// Whether tokens are discarded rather than written, for runs that only count them.
+    pub discard_output: bool,
+    /// Optional path of a JSON Lines manifest describing each tokenized document.
+    pub manifest: Option<PathBuf>,
+    /// The algorithm used for content hashes recorded in the manifest.
+    pub hash_algorithm: HashAlgorithm,
+    /// Optional ID of the corpus snapshot this run produces, recorded in the manifest entries
+    /// of the documents it tokenizes.
+    pub snapshot_id: Option<String>,
+    /// Optional manifest of a previous snapshot. Documents it records with the same source and
+    /// content hash are not tokenized again; their entries are carried over instead. Requires
+    /// `manifest`.
+    pub previous_manifest: Option<PathBuf>,
+    /// Where the `id` recorded in each manifest entry comes from. Anything but ordinal
+    /// numbering requires `manifest`. Entries carried over from a previous snapshot keep the
+    /// `id` recorded there.
+    pub document_ids: DocumentIdSource,
+    /// Whether to prepend a self-describing [`header::OutputHeader`] to raw outputs.
+    pub write_header: bool,
+    /// Optional policy framing each line as a log record, before the other post-processors.
+    pub log_records: Option<LogRecordPolicy>,
+    /// Optional policy for truncating over-long newline-delimited documents.
+    pub truncation: Option<TruncationPolicy>,
+    /// Optional policy turning each document into a span-corrupted `(input, target)` pair.
+    pub span_corruption: Option<SpanCorruptionPolicy>,
+    /// Optional policy for packing newline-delimited documents into fixed-length sequences.
+    pub packing: Option<PackingPolicy>,
+    /// Optional policy emitting next-token `(input, label, mask)` arrays per sequence.
+    pub shifted_pairs: Option<ShiftedPairsPolicy>,
+    /// Optional policy padding each output's token count to a multiple of a block size.
+    pub pad_to: Option<PadToPolicy>,
+    /// Optional path of a sidecar index recording the offset of each output document.
+    pub index: Option<PathBuf>,
+    /// Optional sandbox running chunk processing in isolated worker processes.
+    pub sandbox: Option<SandboxConfig>,
+    /// Optional socket of a running daemon to send chunks to instead of tokenizing them
+    /// in-process. The daemon's vocabulary is used; `merges_file` should be `None`.
+    pub daemon: Option<PathBuf>,
+    /// Optional bounds for adapting the number of concurrent chunk tasks during the run.
+    /// When `None`, `num_threads` chunks are processed concurrently throughout.
+    pub thread_scaling: Option<ThreadScaling>,
+    /// Optional policy deciding when chunks are tokenized and how many may wait to be written,
+    /// replacing the [`FifoScheduler`] built from `num_threads` and `thread_scaling`.
+    pub scheduler: Option<Arc<dyn Scheduler>>,
+    /// Optional cap on the number of tokens written across all outputs of the run. Input
+    /// stops being read once the cap is reached; tokens past it are dropped.
+    pub max_tokens: Option<u64>,
+    /// Optional cap on the rate at which tokens are written across all outputs, for slow
+    /// consumers of a live stream. Input is read no faster than the output allows.
+    pub token_rate: Option<RateLimit>,
+    /// Whether to pin every choice that could make two runs over the same input differ:
+    /// a fixed chunk size instead of one derived from RAM and threads, and streamed input
+    /// cut into full chunks however it arrives. Input files are always taken in sorted or
+    /// list order and span-corruption seeds are fixed, so those need no extra pinning.
+    pub deterministic: bool,
+    /// Optional algorithm for hashing everything the run writes, reported in
+    /// [`RunReport::output_hash`].
+    pub output_hash: Option<HashAlgorithm>,
+    /// Optional `"<algorithm>:<hex>"` hash the run's output must match; the run fails if it
+    /// differs. Implies hashing with the named algorithm.
+    pub expected_output_hash: Option<String>,
+    /// Optional decoding of each document into UTF-8 before it is tokenized.
+    pub transcoding: Option<Transcoding>,
+    /// Optional stripping of HTML markup from each document, after transcoding.
+    pub strip_html: Option<HtmlMode>,
+    /// Optional removal of a timestamp from each line of each document, after HTML stripping.
+    pub timestamps: Option<TimestampStripping>,
+    /// Optional rewriting of digit runs in each document, after timestamp stripping.
+    pub numbers: Option<NumberBucketing>,
+    /// Whitespace rewriting applied to each document after the other pre-processors.
+    pub whitespace: WhitespaceNormalization,
+    /// Optional slice of the input to tokenize instead of the whole input. Only valid for a
+    /// single input file or standard input.
+    pub byte_range: Option<ByteRange>,
+    /// Whether input files are memory-mapped. When unset they are read through async file
+    /// reads, avoiding page-fault stalls on filesystems such as NFS where mapping is slow.
+    pub mmap: bool,
+    /// Whether the pages of memory-mapped inputs are released with `MADV_DONTNEED` as soon as
+    /// the chunks covering them are written, instead of being left for the kernel to evict.
+    pub mmap_dontneed: bool,
+    /// Optional distance in bytes past the dispatched chunks up to which a background thread
+    /// touches the pages of memory-mapped inputs, hiding page-fault latency on cold files such
+    /// as those on network block devices. It stops once the token budget is spent.
+    pub mmap_prefault: Option<usize>,
+    /// Whether streamed input files and output files are read and written through io_uring
+    /// on Linux, cutting per-call overhead at high throughput. Requires the `io-uring` feature;
+    /// kernels without io_uring support fall back to tokio's file I/O.
+    pub io_uring: bool,
+    /// Whether output files are written with direct I/O (`O_DIRECT`, Linux only), bypassing the
+    /// page cache so huge outputs do not evict the data of co-located workloads.
+    pub direct_io: bool,
+    /// Optional interval at which a single input file is polled for appended data, following
+    /// it like `tail -f` until SIGTERM or Ctrl-C. Chunks are tokenized one at a time and
+    /// written as soon as they are read.
+    pub follow: Option<Duration>,
+    /// Optional token that stops the run gracefully once cancelled: no further chunks are
+    /// dispatched, those in flight are written, the incomplete output is handled as
+    /// `partial_output` says, and the run fails with [`BltError::Cancelled`].
+    pub cancellation: Option<CancellationToken>,
+    /// Whether SIGINT and SIGTERM cancel the run as `cancellation` would. A second signal
+    /// exits the process at once. Ignored when following an input, which ends on those
+    /// signals instead.
+    pub stop_on_signal: bool,
+    /// What happens to the output being written when the run is cancelled.
+    pub partial_output: PartialOutput,
+    /// Optional pacing that idles workers to keep to a duty cycle reported by a hook.
+    pub pacing: Option<Pacing>,
+    /// Optional counters the run adds processed input bytes to, for a progress display.
+    pub progress: Option<Arc<Progress>>,
+    /// How often to log the run's progress at `INFO`, if at all.
+    pub heartbeat: Option<Duration>,
+    /// Optional counters every run adds its documents, chunks, and failures to.
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        CoreConfig {
+            input: None,
+            output: None,
+            merges_file: None,
+            content_type: None,
+            content_types: ContentTypeRegistry::default(),
+            records: None,
+            num_threads: utils::determine_thread_count(None),
+            cli_chunk_size: None,
+            mem_cap_percent: 80,
+            bpe_data: None,
+            passthrough_mode: false,
+            output_format: OutputFormat::default(),
+            input_filter: InputFilter::default(),
+            document_separator: Some(special_tokens::SEPARATOR),
+            stream_markers: StreamMarkers::default(),
+            byte_offset: 0,
+            merge_id_base: config_loader::DEFAULT_MERGE_ID_BASE,
+            strict_specials: false,
+            on_error: ErrorPolicy::default(),
+            content_type_framing: ContentTypeFraming::default(),
+            overwrite: false,
+            output_dir: None,
+            filelist: None,
+            archive: false,
+            demux: false,
+            entropy_markers: None,
+            compression: None,
+            encryption: None,
+            tee_original: None,
+            discard_output: false,
+            manifest: None,
+            hash_algorithm: HashAlgorithm::default(),
+            snapshot_id: None,
+            previous_manifest: None,
+            document_ids: DocumentIdSource::default(),
+            write_header: false,
+            log_records: None,
+            truncation: None,
+            span_corruption: None,
+            packing: None,
+            shifted_pairs: None,
+            pad_to: None,
+            index: None,
+            sandbox: None,
+            daemon: None,
+            thread_scaling: None,
+            scheduler: None,
+            max_tokens: None,
+            token_rate: None,
+            deterministic: false,
+            output_hash: None,
+            expected_output_hash: None,
+            transcoding: None,
+            strip_html: None,
+            timestamps: None,
+            numbers: None,
+            whitespace: WhitespaceNormalization::default(),
+            byte_range: None,
+            mmap: true,
+            mmap_dontneed: false,
+            mmap_prefault: None,
+            io_uring: false,
+            direct_io: false,
+            follow: None,
+            cancellation: None,
+            stop_on_signal: false,
+            partial_output: PartialOutput::default(),
+            pacing: None,
+            progress: None,
+            heartbeat: None,
+            metrics: None,
+        }
+    }
 }
 
 impl CoreConfig {
@@ -155,7 +662,7 @@ impl CoreConfig {
         chunksize: Option<String>,
         memcap: Option<u8>,
         passthrough: bool,
-    ) -> io::Result<Self> {
+    ) -> Result<Self, BltError> {
         let num_threads = utils::determine_thread_count(threads);
         let cli_chunk_size = Self::parse_chunksize(chunksize)?;
         let bpe_data = Self::load_bpe_data(&merges)?;
@@ -170,35 +677,424 @@ impl CoreConfig {
             mem_cap_percent: memcap.unwrap_or(80),
             bpe_data,
             passthrough_mode: passthrough,
+            ..Default::default()
         })
     }
 
-    fn parse_chunksize(chunksize: Option<String>) -> io::Result<Option<usize>> {
+    /// Selects the files processed when the input is a directory.
+    pub fn with_input_filter(mut self, input_filter: InputFilter) -> Self {
+        self.input_filter = input_filter;
+        self
+    }
+
+    /// Sets the token written between documents; `None` concatenates them as-is.
+    pub fn with_document_separator(mut self, separator: Option<u16>) -> Self {
+        self.document_separator = separator;
+        self
+    }
+
+    /// Writes begin-of-stream and end-of-stream tokens around each output or document.
+    pub fn with_stream_markers(mut self, markers: StreamMarkers) -> Self {
+        self.stream_markers = markers;
+        self
+    }
+
+    /// Adds `offset` (0 or 256) to every byte token.
+    pub fn with_byte_offset(mut self, offset: u16) -> Self {
+        self.byte_offset = offset;
+        self
+    }
+
+    /// Renumbers the loaded merges so the first is assigned `base` instead of 256, e.g. 512
+    /// to place them after byte tokens moved by [`Self::with_byte_offset`].
+    pub fn with_merge_id_base(mut self, base: u16) -> Self {
+        if let Some(merges) = &self.bpe_data {
+            let rebased = config_loader::rebase_merges(merges, self.merge_id_base, base);
+            self.bpe_data = Some(Arc::new(rebased));
+        }
+        self.merge_id_base = base;
+        self
+    }
+
+    /// Fails the run if tokenization produces a token in the reserved special-token range.
+    pub fn with_strict_specials(mut self, strict: bool) -> Self {
+        self.strict_specials = strict;
+        self
+    }
+
+    /// Skips chunks that fail to tokenize instead of aborting the run, when `policy` says so.
+    pub fn with_on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// Writes the content type token according to `framing` instead of once per output.
+    pub fn with_content_type_framing(mut self, framing: ContentTypeFraming) -> Self {
+        self.content_type_framing = framing;
+        self
+    }
+
+    /// Allows replacing output files that already exist.
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Writes each input document to its own file under `output_dir` instead of `output`.
+    pub fn with_output_dir(mut self, output_dir: Option<OutputDir>) -> Self {
+        self.output_dir = output_dir;
+        self
+    }
+
+    /// Reads the input paths from a newline-separated file list instead of `input`.
+    pub fn with_filelist(mut self, filelist: Option<PathBuf>) -> Self {
+        self.filelist = filelist;
+        self
+    }
+
+    /// Treats the input as an archive and tokenizes each member as a separate document.
+    pub fn with_archive(mut self, archive: bool) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Demuxes the input video into metadata and per-track documents.
+    pub fn with_demux(mut self, demux: bool) -> Self {
+        self.demux = demux;
+        self
+    }
+
+    /// Marks compressed-looking and plain regions of the input per `marking`.
+    pub fn with_entropy_markers(mut self, marking: Option<EntropyMarking>) -> Self {
+        self.entropy_markers = marking;
+        self
+    }
+
+    /// Compresses the output stream with `compression`.
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Encrypts the output stream with `key` (see [`io_handler::EncryptionKey`]).
+    pub fn with_encryption(mut self, key: Option<EncryptionKey>) -> Self {
+        self.encryption = key;
+        self
+    }
+
+    /// Mirrors the original input bytes to `path` alongside the token output.
+    pub fn with_tee_original(mut self, path: Option<PathBuf>) -> Self {
+        self.tee_original = path;
+        self
+    }
+
+    /// Tokenizes without writing the tokens anywhere, so a run only counts them. Cannot be
+    /// combined with an output, output directory, index, or mirrored input.
+    pub fn with_discard_output(mut self, discard: bool) -> Self {
+        self.discard_output = discard;
+        self
+    }
+
+    /// Writes a manifest with per-document content hashes (using `hash_algorithm`) to `path`.
+    pub fn with_manifest(mut self, path: Option<PathBuf>, hash_algorithm: HashAlgorithm) -> Self {
+        self.manifest = path;
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Tags the documents this run tokenizes with snapshot `id` in the manifest.
+    pub fn with_snapshot_id(mut self, id: Option<String>) -> Self {
+        self.snapshot_id = id;
+        self
+    }
+
+    /// Tokenizes only documents that are new or changed since the snapshot described by the
+    /// manifest at `path`.
+    pub fn with_previous_manifest(mut self, path: Option<PathBuf>) -> Self {
+        self.previous_manifest = path;
+        self
+    }
+
+    /// Records an `id` taken from `source` in each manifest entry, so outputs can be joined
+    /// back to the systems the documents came from.
+    pub fn with_document_ids(mut self, source: DocumentIdSource) -> Self {
+        self.document_ids = source;
+        self
+    }
+
+    /// Enables or disables the self-describing output header.
+    pub fn with_header(mut self, write_header: bool) -> Self {
+        self.write_header = write_header;
+        self
+    }
+
+    /// Frames each line as a log record with a severity tag and a record separator.
+    pub fn with_log_records(mut self, policy: Option<LogRecordPolicy>) -> Self {
+        self.log_records = policy;
+        self
+    }
+
+    /// Truncates over-long newline-delimited documents according to `policy`.
+    pub fn with_truncation(mut self, policy: Option<TruncationPolicy>) -> Self {
+        self.truncation = policy;
+        self
+    }
+
+    /// Emits span-corrupted `(input, target)` pairs according to `policy`.
+    pub fn with_span_corruption(mut self, policy: Option<SpanCorruptionPolicy>) -> Self {
+        self.span_corruption = policy;
+        self
+    }
+
+    /// Packs newline-delimited documents into fixed-length sequences according to `policy`.
+    pub fn with_packing(mut self, policy: Option<PackingPolicy>) -> Self {
+        self.packing = policy;
+        self
+    }
+
+    /// Emits shifted next-token `(input, label, mask)` arrays according to `policy`.
+    pub fn with_shifted_pairs(mut self, policy: Option<ShiftedPairsPolicy>) -> Self {
+        self.shifted_pairs = policy;
+        self
+    }
+
+    /// Pads each output's token count to a multiple of a block size according to `policy`.
+    pub fn with_pad_to(mut self, policy: Option<PadToPolicy>) -> Self {
+        self.pad_to = policy;
+        self
+    }
+
+    /// Writes a sidecar index of document offsets in the output to `path`.
+    pub fn with_index(mut self, path: Option<PathBuf>) -> Self {
+        self.index = path;
+        self
+    }
+
+    /// Tokenizes chunks with the daemon listening on `socket` (see [`daemon`]).
+    pub fn with_daemon(mut self, socket: Option<PathBuf>) -> Self {
+        self.daemon = socket;
+        self
+    }
+
+    /// Processes chunks in sandboxed worker processes started per `sandbox`.
+    pub fn with_sandbox(mut self, sandbox: Option<SandboxConfig>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Scales the number of concurrent chunk tasks between the bounds of `scaling` during the
+    /// run, starting from `num_threads`.
+    pub fn with_thread_scaling(mut self, scaling: Option<ThreadScaling>) -> Self {
+        self.thread_scaling = scaling;
+        self
+    }
+
+    /// Schedules chunks with `scheduler` instead of the default [`FifoScheduler`], for
+    /// example to try an alternative policy.
+    pub fn with_scheduler(mut self, scheduler: Option<Arc<dyn Scheduler>>) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Pins chunking so repeated runs over the same input write identical output.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Hashes everything the run writes with `algorithm`.
+    pub fn with_output_hash(mut self, algorithm: Option<HashAlgorithm>) -> Self {
+        self.output_hash = algorithm;
+        self
+    }
+
+    /// Fails the run unless its output hashes to `expected`, a `"<algorithm>:<hex>"` string
+    /// as found in [`RunReport::output_hash`].
+    pub fn with_expected_output_hash(mut self, expected: Option<String>) -> Self {
+        self.expected_output_hash = expected;
+        self
+    }
+
+    /// Reports processed input bytes, and the total when known, to `progress`.
+    pub fn with_progress(mut self, progress: Option<Arc<Progress>>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Logs the input bytes read, tokens written, throughput over the last interval, and
+    /// chunks in flight at `INFO` every `interval`, so long runs show how far they have got.
+    pub fn with_heartbeat(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Adds the documents, chunks, and bytes of every run to `metrics`.
+    pub fn with_metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Transcodes every document to UTF-8 before tokenizing it.
+    pub fn with_transcoding(mut self, transcoding: Option<Transcoding>) -> Self {
+        self.transcoding = transcoding;
+        self
+    }
+
+    /// Tokenizes the text of HTML documents instead of their markup.
+    pub fn with_strip_html(mut self, mode: Option<HtmlMode>) -> Self {
+        self.strip_html = mode;
+        self
+    }
+
+    /// Removes the timestamp from each line of every document before tokenizing it.
+    pub fn with_timestamp_stripping(mut self, timestamps: Option<TimestampStripping>) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Replaces the digit runs of every document with placeholders or bucketed forms.
+    pub fn with_number_bucketing(mut self, numbers: Option<NumberBucketing>) -> Self {
+        self.numbers = numbers;
+        self
+    }
+
+    /// Collapses, trims, or expands the whitespace of every document before tokenizing it.
+    pub fn with_whitespace(mut self, whitespace: WhitespaceNormalization) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// Stops the run after writing `max_tokens` tokens.
+    pub fn with_max_tokens(mut self, max_tokens: Option<u64>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Writes tokens no faster than `limit` allows.
+    pub fn with_token_rate(mut self, limit: Option<RateLimit>) -> Self {
+        self.token_rate = limit;
+        self
+    }
+
+    /// Tokenizes only the bytes of the input inside `range`.
+    pub fn with_byte_range(mut self, range: Option<ByteRange>) -> Self {
+        self.byte_range = range;
+        self
+    }
+
+    /// Memory-maps input files when `mmap` is set, and streams them otherwise.
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Releases the pages of memory-mapped inputs once they are tokenized when `dontneed` is
+    /// set.
+    pub fn with_mmap_dontneed(mut self, dontneed: bool) -> Self {
+        self.mmap_dontneed = dontneed;
+        self
+    }
+
+    /// Touches the pages of memory-mapped inputs up to `distance` bytes ahead of the workers.
+    pub fn with_mmap_prefault(mut self, distance: Option<usize>) -> Self {
+        self.mmap_prefault = distance;
+        self
+    }
+
+    /// Reads streamed input files and writes output files through io_uring when `io_uring` is set.
+    pub fn with_io_uring(mut self, io_uring: bool) -> Self {
+        self.io_uring = io_uring;
+        self
+    }
+
+    /// Writes output files with direct I/O when `direct_io` is set.
+    pub fn with_direct_io(mut self, direct_io: bool) -> Self {
+        self.direct_io = direct_io;
+        self
+    }
+
+    /// Follows the input file as it grows, checking for appended data every `interval`.
+    pub fn with_follow(mut self, interval: Option<Duration>) -> Self {
+        self.follow = interval;
+        self
+    }
+
+    /// Stops the run gracefully once `cancellation` is cancelled.
+    pub fn with_cancellation(mut self, cancellation: Option<CancellationToken>) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Stops the run gracefully on SIGINT and SIGTERM when `stop_on_signal` is set.
+    pub fn with_stop_on_signal(mut self, stop_on_signal: bool) -> Self {
+        self.stop_on_signal = stop_on_signal;
+        self
+    }
+
+    /// Handles the output left incomplete by a cancellation as `partial_output` says.
+    pub fn with_partial_output(mut self, partial_output: PartialOutput) -> Self {
+        self.partial_output = partial_output;
+        self
+    }
+
+    /// Idles workers between chunks to keep to the duty cycle reported by `pacing`.
+    pub fn with_pacing(mut self, pacing: Option<Pacing>) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// The most chunk tasks this configuration may run concurrently.
+    pub fn max_threads(&self) -> usize {
+        self.thread_scaling
+            .map_or(self.num_threads, |scaling| scaling.max)
+    }
+
+    /// Returns the tokenization strategy this configuration selects.
+    pub fn strategy_kind(&self) -> StrategyKind {
+        if self.passthrough_mode {
+            StrategyKind::Passthrough
+        } else if self.bpe_data.is_some() {
+            StrategyKind::Bpe
+        } else {
+            StrategyKind::Basic
+        }
+    }
+
+    /// Sets the content types known to this run, including custom ones.
+    pub fn with_content_types(mut self, content_types: ContentTypeRegistry) -> Self {
+        self.content_types = content_types;
+        self
+    }
+
+    /// Aligns chunks and tokens to the length-prefixed records described by `layout`.
+    pub fn with_records(mut self, layout: Option<RecordLayout>) -> Self {
+        self.records = layout;
+        self
+    }
+
+    /// Sets the layout used when writing the output.
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    fn parse_chunksize(chunksize: Option<String>) -> Result<Option<usize>, BltError> {
         chunksize
             .as_ref()
             .map(|cs_str| utils::parse_chunk_size_str(cs_str))
             .transpose()
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            .map_err(BltError::Config)
     }
 
-    fn load_bpe_data(merges_path: &Option<PathBuf>) -> io::Result<Option<Arc<BpeMerges>>> {
+    fn load_bpe_data(merges_path: &Option<PathBuf>) -> Result<Option<Arc<BpeMerges>>, BltError> {
         match merges_path {
             Some(path) => {
-                let merges_map = Self::load_merges_from_file(path)?;
+                let merges_map = config_loader::load_bpe_merges_from_path(path)?;
                 Ok(Some(Arc::new(merges_map)))
             }
             None => Ok(None),
         }
     }
-
-    fn load_merges_from_file(path: &Path) -> io::Result<BpeMerges> {
-        config_loader::load_bpe_merges_from_path(path).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Failed to load BPE merges: {e}"),
-            )
-        })
-    }
 }
 
 /// Loads BPE merges from a file path.
@@ -213,7 +1109,12 @@ impl CoreConfig {
 /// # Returns
 ///
 /// A HashMap mapping byte pairs to new token IDs.
-pub fn load_bpe_merges(path: &Path) -> io::Result<HashMap<(u8, u8), u16>> {
+///
+/// # Errors
+///
+/// Fails with [`BltError::MergesParse`] if the file is malformed, or [`BltError::Io`] if it
+/// cannot be read.
+pub fn load_bpe_merges(path: &Path) -> Result<HashMap<(u8, u8), u16>, BltError> {
     let merges = config_loader::load_bpe_merges_from_path(path)?;
     // Convert from (u16, u16) to (u8, u8) for Python compatibility
     let converted: HashMap<(u8, u8), u16> = merges
@@ -229,10 +1130,57 @@ pub fn load_bpe_merges(path: &Path) -> io::Result<HashMap<(u8, u8), u16>> {
     Ok(converted)
 }
 
+/// What a tokenizer run processed and the resources it used.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct RunReport {
+    /// The number of documents tokenized.
+    pub documents: u64,
+    /// Input bytes read across all documents.
+    pub input_bytes: u64,
+    /// Token bytes produced by the tokenizer, before post-processing.
+    pub output_bytes: u64,
+    /// Tokens produced by the tokenizer, before post-processing.
+    pub tokens: u64,
+    /// Input bytes per token byte produced; above 1.0 the token stream is smaller than the
+    /// input. `None` when nothing was produced.
+    pub compression_ratio: Option<f64>,
+    /// Chunks tokenized across all documents.
+    pub chunks: u64,
+    /// The chunk size the input was split into, in bytes.
+    pub chunk_size_bytes: usize,
+    /// The number of threads the pipeline was configured with.
+    pub threads: usize,
+    /// The `"<algorithm>:<hex>"` hash of everything written, if output hashing was enabled.
+    /// Headers are included; compression is not, so the hash identifies the token stream.
+    pub output_hash: Option<String>,
+    /// Wall-clock duration of the run.
+    pub wall_secs: f64,
+    /// Peak memory, CPU time, and I/O counters for the run.
+    pub usage: ResourceUsage,
+    /// The chunks left out of the output after failing, under [`ErrorPolicy::Skip`].
+    pub skipped_chunks: Vec<SkippedChunk>,
+    /// How efficiently documents were packed across every output, if packing was enabled.
+    pub packing: Option<PackingStats>,
+}
+
+/// A chunk left out of the output because it failed to tokenize.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct SkippedChunk {
+    /// The source of the document the chunk belongs to.
+    pub source: String,
+    /// The chunk's byte offset in the document, after pre-processing.
+    pub offset: u64,
+    /// The length of the chunk in bytes.
+    pub bytes: u64,
+    /// The error the chunk failed with.
+    pub error: String,
+}
+
 /// Runs the entire tokenization pipeline with the given configuration.
 ///
 /// This is the main entry point of the `blt_core` library. It sets up the I/O,
-/// selects the tokenization strategy, and launches the processing pipeline.
+/// selects the tokenization strategy, launches the processing pipeline, and returns a
+/// [`RunReport`] summarizing the run.
 ///
 /// # Arguments
 ///
@@ -240,44 +1188,784 @@ pub fn load_bpe_merges(path: &Path) -> io::Result<HashMap<(u8, u8), u16>> {
 ///
 /// # Errors
 ///
-/// This function can return an `io::Error` if there are issues with file I/O,
-/// configuration loading, or during the processing pipeline itself.
-#[instrument(skip_all, fields(input = ?config.input, output = ?config.output))]
-pub async fn run_tokenizer(config: CoreConfig) -> io::Result<()> {
+/// Fails with a [`BltError`] saying what went wrong: [`BltError::Config`] for invalid or
+/// conflicting options, [`BltError::Strategy`] when a chunk fails to tokenize,
+/// [`BltError::Pipeline`] when the pipeline itself fails, [`BltError::Cancelled`] when the
+/// run is cancelled, and [`BltError::Io`] when reading or writing fails.
+#[instrument(skip_all, fields(input = ?config.input, filelist = ?config.filelist, output = ?config.output))]
+pub async fn run_tokenizer(config: CoreConfig) -> Result<RunReport, BltError> {
+    tokenize_documents(config).await.map(|(report, _)| report)
+}
+
+/// Runs the pipeline like [`run_tokenizer`], also returning the manifest entry of every
+/// document, whether or not a manifest is written.
+async fn tokenize_documents(
+    mut config: CoreConfig,
+) -> Result<(RunReport, Vec<ManifestEntry>), BltError> {
     info!("Starting tokenizer");
+    if config.heartbeat.is_some() && config.progress.is_none() {
+        // The heartbeat reads the same counters a progress display would.
+        config.progress = Some(Progress::new());
+    }
+    let started = std::time::Instant::now();
+    let usage = UsageTracker::start();
 
-    let strategy = select_strategy(&config);
+    let mut strategy = framed_strategy(&config)?;
+    if let Some(pacing) = config.pacing.clone() {
+        strategy = Arc::new(PacedStrategy::new(strategy, pacing));
+    }
+    // Offloaded outermost, so the tokenize stage's CPU time is measured where the work runs.
+    let strategy = offload::offload_cpu_bound(&config, usage.time_strategy(strategy));
     let effective_chunk_size = chunking::get_effective_chunk_size(&config);
     info!(effective_chunk_size, "Chunk size determined");
 
-    let (input_source, mut output_writer) = io_handler::setup_io(&config).await?;
-    prepend_content_type_token(&mut output_writer, config.content_type.as_ref()).await?;
+    validate_config(&config)?;
+    let digest = output_hash_algorithm(&config)?.map(OutputDigest::new);
+    // A followed input ends on the same signals instead.
+    let stop_on_signal = config.stop_on_signal && config.follow.is_none();
+    let cancellation = match (&config.cancellation, stop_on_signal) {
+        (Some(token), _) => Some(token.clone()),
+        (None, true) => Some(CancellationToken::new()),
+        (None, false) => None,
+    };
+    let _signals = cancellation
+        .clone()
+        .filter(|_| stop_on_signal)
+        .map(SignalListener::spawn);
+    let cancelled = || cancellation.as_ref().is_some_and(|t| t.is_cancelled());
+    let mut documents = io_handler::setup_input(&config)?;
+    if let Some(progress) = &config.progress {
+        progress.set_total(documents.total_bytes());
+    }
+    let _heartbeat = config
+        .heartbeat
+        .zip(config.progress.clone())
+        .map(|(interval, progress)| Heartbeat::spawn(progress, interval, token_width(&config)));
+    let budget = config
+        .max_tokens
+        .map(|max_tokens| TokenBudget::new(max_tokens, token_width(&config)));
+    if let Some(limit) = &config.token_rate {
+        limit
+            .validate()
+            .map_err(|e| BltError::Config(e.to_string()))?;
+    }
+    let shared = SharedOutputState {
+        budget: budget.clone(),
+        bucket: config
+            .token_rate
+            .map(|limit| TokenBucket::new(limit, token_width(&config))),
+        digest: digest.clone(),
+    };
+    // The index is written last, so an existing one is refused before anything is written.
+    if let Some(path) = &config.index {
+        io_handler::refuse_existing(path, config.overwrite).await?;
+    }
+    let mut run_sink = match config.output_dir {
+        Some(_) => None,
+        None => {
+            let output = config.output.as_deref();
+            let stages = usage.stages();
+            Some(OutputSink::open(&config, output, stages, &shared, None).await?)
+        }
+    };
+    let mut tee_writer = io_handler::setup_tee_writer(&config).await?;
+    let mut output_claims = OutputClaims::default();
 
-    pipeline::run(
-        input_source,
-        output_writer,
-        effective_chunk_size,
-        config.num_threads,
-        strategy,
-    )
-    .await?;
+    // A followed input waits at its end, so each chunk is written before the next is read.
+    let scheduler: Arc<dyn Scheduler> = match (&config.scheduler, config.follow) {
+        (Some(scheduler), _) => scheduler.clone(),
+        (None, Some(_)) => Arc::new(FifoScheduler::new(1, None)),
+        (None, None) => Arc::new(FifoScheduler::new(
+            config.num_threads,
+            config.thread_scaling,
+        )),
+    };
+    let previous = match &config.previous_manifest {
+        Some(path) => Some(PreviousSnapshot::load(path).await?),
+        None => None,
+    };
+    let mut manifest_entries = Vec::new();
+    // Documents carried over unchanged from the previous snapshot, by `doc_id`.
+    let mut carried = HashSet::new();
+    let mut documents_written = 0;
+    let mut chunks = 0;
+    let mut skipped_chunks = Vec::new();
+    let mut packing = None;
+    // Whether a cancellation stopped the run before its last document was complete.
+    let mut stopped = false;
+    while let Some(document) = next_document(&mut documents, budget.as_deref()).await? {
+        if cancelled() {
+            stopped = true;
+            break;
+        }
+        let doc_id = manifest_entries.len() as u64;
+        let mut source = preprocess(&config, document.source)?;
+        if let Some(previous) = &previous {
+            // Hashed before anything is written, so an unchanged document leaves no output.
+            let (content_hash, hashed) = hash_source(source, config.hash_algorithm).await?;
+            source = hashed;
+            if let Some(entry) = previous.unchanged(&document.name, &content_hash) {
+                debug!(source = %document.name, "Document unchanged since the previous snapshot");
+                if let Some(progress) = &config.progress {
+                    progress.add(entry.input_bytes);
+                }
+                manifest_entries.push(ManifestEntry {
+                    doc_id,
+                    ..entry.clone()
+                });
+                carried.insert(doc_id);
+                continue;
+            }
+        }
+        // Per-document outputs are recorded in the manifest with a hash of the file's bytes,
+        // so `repair` can find and rebuild damaged ones.
+        let mut document_output = None;
+        let mut document_sink = match &config.output_dir {
+            Some(output_dir) => {
+                let path = if output_dir.content_addressed {
+                    // Moved to its object once the hash of its bytes is known.
+                    output_dir.staging_path(doc_id, &config)
+                } else {
+                    let path = output_dir.output_path(&document.relative_path, &config);
+                    output_claims.claim(&path, &document.name)?;
+                    path
+                };
+                let stages = usage.stages();
+                let file_digest = config
+                    .manifest
+                    .as_ref()
+                    .map(|_| OutputDigest::new(config.hash_algorithm));
+                let sink =
+                    OutputSink::open(&config, Some(&path), stages, &shared, file_digest.clone())
+                        .await?;
+                document_output = Some((path, file_digest));
+                Some(sink)
+            }
+            None => None,
+        };
+        let sink = match document_sink.as_mut() {
+            Some(sink) => sink,
+            None => {
+                let sink = run_sink
+                    .as_mut()
+                    .expect("run-wide output exists without an output directory");
+                if documents_written > 0 {
+                    write_document_separator(&mut sink.writer, &config).await?;
+                }
+                sink
+            }
+        };
+        sink.labels.begin(doc_id, document.name.clone());
+        let hasher = config
+            .manifest
+            .as_ref()
+            .map(|_| ContentHasher::new(config.hash_algorithm));
+        let document_markers = config.stream_markers.per_document();
+        write_marker(&mut sink.writer, document_markers.and_then(|m| m.bos)).await?;
+        if config.content_type_framing == ContentTypeFraming::Document {
+            prepend_content_type_token(&mut sink.writer, config.content_type.as_ref()).await?;
+        }
+        write_marker(&mut sink.writer, document.tag).await?;
+        let mut output =
+            pipeline::PipelineOutput::new(&mut sink.writer, tee_writer.as_mut(), hasher)
+                .with_strict_specials(config.strict_specials)
+                .with_skip_errors(config.on_error == ErrorPolicy::Skip)
+                .with_first_line(config.document_ids.needs_first_line())
+                .with_budget(budget.clone())
+                .with_progress(config.progress.clone())
+                .with_metrics(config.metrics.clone())
+                .with_flush_chunks(config.follow.is_some())
+                .with_release_mapped(config.mmap_dontneed)
+                .with_prefault(config.mmap_prefault)
+                .with_entropy_markers(config.entropy_markers)
+                .with_cancellation(cancellation.clone());
+        pipeline::run(
+            usage.time_source(source),
+            &mut output,
+            effective_chunk_size,
+            config.deterministic,
+            config.records.as_ref(),
+            &scheduler,
+            strategy.clone(),
+        )
+        .await?;
+        let mut summary = output.into_summary();
+        if cancelled() {
+            // The run-wide output is finished after the loop.
+            if let (Some(sink), Some((path, _))) = (document_sink, &document_output) {
+                // Content-addressed outputs are named by their complete bytes, so a partial
+                // one is never kept.
+                let partial = match config
+                    .output_dir
+                    .as_ref()
+                    .is_some_and(|d| d.content_addressed)
+                {
+                    true => PartialOutput::Remove,
+                    false => config.partial_output,
+                };
+                finish_partial(sink, Some(path), partial).await?;
+            }
+            stopped = true;
+            break;
+        }
+        write_marker(&mut sink.writer, document_markers.and_then(|m| m.eos)).await?;
+        chunks += summary.chunks;
+        skipped_chunks.extend(summary.skipped.drain(..).map(|skipped| SkippedChunk {
+            source: document.name.clone(),
+            offset: skipped.offset,
+            bytes: skipped.bytes,
+            error: skipped.error,
+        }));
+        if let Some(metrics) = &config.metrics {
+            metrics.record_document();
+        }
+        let id = config.document_ids.assign(&DocumentInfo {
+            ordinal: doc_id,
+            source: &document.name,
+            relative_path: &document.relative_path,
+            content_hash: summary.content_hash.as_deref(),
+            first_line: summary.first_line.as_deref(),
+        })?;
+        let mut entry = manifest_entry(&config, doc_id, document.name, summary);
+        entry.id = id;
+        if let Some(sink) = document_sink {
+            add_packing_stats(&mut packing, sink.finish(None).await?);
+        }
+        if let Some((mut path, file_digest)) = document_output {
+            let output_hash = file_digest.map(|digest| digest.finalize());
+            if let (Some(output_dir), Some(hash)) = (&config.output_dir, &output_hash) {
+                if output_dir.content_addressed {
+                    path = output_dir.store_object(&path, hash, &config).await?;
+                }
+            }
+            entry.output = Some(path.display().to_string());
+            entry.output_hash = output_hash;
+        }
+        manifest_entries.push(entry);
+        documents_written += 1;
+    }
+    if let Some(sink) = run_sink {
+        match stopped {
+            true => finish_partial(sink, config.output.as_deref(), config.partial_output).await?,
+            false => {
+                // Checked before the output is committed, so a mismatched output never
+                // replaces the file it was to replace. Per-document outputs are committed one by
+                // one, so they are checked, and removed on a mismatch, once all are written.
+                if let (Some(digest), Some(expected)) = (&digest, &config.expected_output_hash) {
+                    digest.expect(expected.clone());
+                }
+                add_packing_stats(&mut packing, sink.finish(config.index.as_deref()).await?)
+            }
+        }
+    }
+    if let Some(mut tee_writer) = tee_writer {
+        tee_writer.shutdown().await?;
+    }
+    let output_hash = match (&digest, &config.expected_output_hash) {
+        (Some(digest), Some(expected)) if !stopped => match digest.verify(expected) {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                remove_document_outputs(&config, &manifest_entries, &carried).await;
+                return Err(BltError::Pipeline(e.to_string()));
+            }
+        },
+        (digest, _) => digest.as_ref().map(|digest| digest.finalize()),
+    };
+    // A cancelled run records the documents it completed.
+    if let Some(path) = &config.manifest {
+        manifest::write_manifest(path, &manifest_entries).await?;
+    }
+    if stopped {
+        let documents = manifest_entries.len() as u64;
+        return Err(BltError::Cancelled(interrupt::Cancelled { documents }));
+    }
 
-    info!("Tokenizer run completed successfully");
-    Ok(())
+    let tokenized = || {
+        manifest_entries
+            .iter()
+            .filter(|e| !carried.contains(&e.doc_id))
+    };
+    let input_bytes = tokenized().map(|e| e.input_bytes).sum();
+    let output_bytes = tokenized().map(|e| e.output_bytes).sum();
+    let report = RunReport {
+        documents: tokenized().count() as u64,
+        input_bytes,
+        output_bytes,
+        tokens: tokenized().map(|e| e.tokens).sum(),
+        compression_ratio: (output_bytes > 0).then(|| input_bytes as f64 / output_bytes as f64),
+        chunks,
+        chunk_size_bytes: effective_chunk_size,
+        threads: config.num_threads,
+        output_hash,
+        wall_secs: started.elapsed().as_secs_f64(),
+        usage: usage.finish(),
+        skipped_chunks,
+        packing,
+    };
+    if !report.skipped_chunks.is_empty() {
+        warn!(
+            skipped_chunks = report.skipped_chunks.len(),
+            "Chunks that failed to tokenize were left out of the output"
+        );
+    }
+    info!(
+        documents = report.documents,
+        input_bytes = report.input_bytes,
+        wall_secs = report.wall_secs,
+        peak_rss_bytes = report.usage.peak_rss_bytes,
+        user_cpu_secs = report.usage.user_cpu_secs,
+        system_cpu_secs = report.usage.system_cpu_secs,
+        "Tokenizer run completed successfully"
+    );
+    Ok((report, manifest_entries))
 }
 
 // --- Private Helper Functions ---
 
-fn select_strategy(config: &CoreConfig) -> Arc<dyn TokenizationStrategy> {
+/// Applies the configured pre-processors to a document: transcoding, HTML stripping, timestamp
+/// stripping, number bucketing, and finally whitespace normalization.
+fn preprocess(
+    config: &CoreConfig,
+    mut source: io_handler::InputSource,
+) -> io::Result<io_handler::InputSource> {
+    if let Some(transcoding) = &config.transcoding {
+        source = transcoding.wrap(source);
+    }
+    if let Some(mode) = config.strip_html {
+        source = mode.wrap(source)?;
+    }
+    if let Some(timestamps) = &config.timestamps {
+        source = timestamps.wrap(source);
+    }
+    if let Some(numbers) = &config.numbers {
+        source = numbers.wrap(source);
+    }
+    if !config.whitespace.is_noop() {
+        source = config.whitespace.wrap(source);
+    }
+    Ok(source)
+}
+
+/// Reads `source` through to hash it with `algorithm`, returning the hash and a source replaying
+/// the same bytes. Mapped inputs are hashed in place; streams are buffered in memory.
+async fn hash_source(
+    source: io_handler::InputSource,
+    algorithm: HashAlgorithm,
+) -> io::Result<(String, io_handler::InputSource)> {
+    match source {
+        io_handler::InputSource::Mmap(mmap) => {
+            let hash = hashing::hash_bytes(algorithm, &mmap);
+            Ok((hash, io_handler::InputSource::Mmap(mmap)))
+        }
+        io_handler::InputSource::Stdin(mut reader)
+        | io_handler::InputSource::Reader(mut reader) => {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let hash = hashing::hash_bytes(algorithm, &bytes);
+            let replay = Box::new(io::Cursor::new(bytes));
+            Ok((hash, io_handler::InputSource::Reader(replay)))
+        }
+    }
+}
+
+/// The algorithm to hash the run's output with: the one named by the expected hash, if any,
+/// otherwise the configured one.
+fn output_hash_algorithm(config: &CoreConfig) -> Result<Option<HashAlgorithm>, BltError> {
+    match &config.expected_output_hash {
+        Some(expected) => {
+            let (algorithm, _) = expected.split_once(':').ok_or_else(|| {
+                BltError::Config(format!(
+                    "Expected output hash '{expected}' is not of the form ALGORITHM:HEX"
+                ))
+            })?;
+            let algorithm = algorithm.parse().map_err(BltError::Config)?;
+            Ok(Some(algorithm))
+        }
+        None => Ok(config.output_hash),
+    }
+}
+
+/// Removes the per-document outputs this run wrote, after their hash failed to match. Objects
+/// in a content-addressed store are named by their bytes and may be shared, so they are kept.
+async fn remove_document_outputs(
+    config: &CoreConfig,
+    entries: &[ManifestEntry],
+    carried: &HashSet<u64>,
+) {
+    if config
+        .output_dir
+        .as_ref()
+        .is_none_or(|d| d.content_addressed)
+    {
+        return;
+    }
+    let written = entries.iter().filter(|e| !carried.contains(&e.doc_id));
+    for path in written.filter_map(|e| e.output.as_deref()) {
+        if let Err(e) = tokio::fs::remove_file(path).await {
+            warn!(output = path, error = %e, "Could not remove an unverified output");
+        }
+    }
+}
+
+/// Disposes of `sink`, left incomplete by a cancellation, as `partial` says. An output to stdout
+/// or a special file cannot be taken back, so it is flushed whatever the setting.
+async fn finish_partial(
+    sink: OutputSink,
+    path: Option<&Path>,
+    partial: PartialOutput,
+) -> io::Result<()> {
+    let Some(path) = path.filter(|path| !io_handler::is_special_file(path)) else {
+        return sink.finish(None).await.map(drop);
+    };
+    match partial.kept_path(path) {
+        // Its temporary file goes with it, leaving any file it was replacing in place.
+        None => drop(sink),
+        Some(kept) => {
+            sink.finish(None).await?;
+            if kept != path {
+                tokio::fs::rename(path, &kept).await?;
+            }
+            warn!(output = %kept.display(), "Kept partial output");
+        }
+    }
+    Ok(())
+}
+
+/// State shared by every output of a run.
+struct SharedOutputState {
+    /// The token cap, charged by every output after its header.
+    budget: Option<Arc<TokenBudget>>,
+    /// The token rate limit, shared by every output after its header.
+    bucket: Option<Arc<TokenBucket>>,
+    /// The hash of everything written, fed by every output including its header.
+    digest: Option<Arc<OutputDigest>>,
+}
+
+/// A tokenized output being written: the post-processing writer and its handles.
+struct OutputSink {
+    writer: io_handler::OutputWriter,
+    handles: postprocess::PostProcessHandles,
+    /// Starts each document's row in columnar formats.
+    labels: DocumentLabels,
+    eos: Option<u16>,
+    /// Whether an existing index may be replaced.
+    overwrite: bool,
+}
+
+impl OutputSink {
+    /// Opens `output` (stdout when `None`) and writes everything preceding the first document.
+    /// Writes count towards the write stage of `stages` and are hashed into the shared digest;
+    /// tokens after the header are charged to the shared budget and paced by the shared rate.
+    /// The bytes of the output file itself are hashed into `file_digest`.
+    async fn open(
+        config: &CoreConfig,
+        output: Option<&Path>,
+        stages: &Arc<StageCounters>,
+        shared: &SharedOutputState,
+        file_digest: Option<Arc<OutputDigest>>,
+    ) -> io::Result<Self> {
+        let labels = DocumentLabels::default();
+        let mut writer =
+            io_handler::setup_output_writer(config, output, labels.clone(), file_digest).await?;
+        if let Some(digest) = &shared.digest {
+            writer = Box::new(HashingWriter::new(writer, digest.clone()));
+        }
+        let header_len = write_output_header(&mut writer, config).await?;
+        if let Some(budget) = &shared.budget {
+            writer = Box::new(LimitedWriter::new(writer, budget.clone()));
+        }
+        if let Some(bucket) = &shared.bucket {
+            writer = Box::new(RateLimitedWriter::new(writer, bucket.clone()));
+        }
+        let (writer, handles) = postprocess::wrap(writer, config, header_len)?;
+        let mut writer: io_handler::OutputWriter =
+            Box::new(usage::Timed::new(writer, stages.clone(), Stage::Write));
+        let output_markers = config.stream_markers.per_output();
+        write_marker(&mut writer, output_markers.and_then(|m| m.bos)).await?;
+        if config.content_type_framing != ContentTypeFraming::Document {
+            prepend_content_type_token(&mut writer, config.content_type.as_ref()).await?;
+        }
+        Ok(Self {
+            writer,
+            handles,
+            labels,
+            eos: output_markers.and_then(|m| m.eos),
+            overwrite: config.overwrite,
+        })
+    }
+
+    /// Writes the end-of-stream token, flushes the output, writes the index to `index_path`,
+    /// and returns the packing statistics, if documents were packed.
+    async fn finish(mut self, index_path: Option<&Path>) -> io::Result<Option<PackingStats>> {
+        write_marker(&mut self.writer, self.eos).await?;
+        self.writer.shutdown().await?;
+        let packing = self
+            .handles
+            .packing_stats
+            .as_ref()
+            .map(|stats| stats.lock().expect("packing stats lock poisoned").clone());
+        if let Some(stats) = &packing {
+            info!(
+                sequences = stats.sequences,
+                content_tokens = stats.content_tokens,
+                padding_tokens = stats.padding_tokens,
+                efficiency = stats.efficiency(),
+                "Packing summary"
+            );
+        }
+        if let (Some(path), Some(document_index)) = (index_path, &self.handles.index) {
+            let document_index = document_index
+                .lock()
+                .expect("document index lock poisoned")
+                .clone();
+            index::write_index(path, &document_index, self.overwrite).await?;
+        }
+        Ok(packing)
+    }
+}
+
+/// Adds the packing statistics of a finished output to the run's.
+fn add_packing_stats(total: &mut Option<PackingStats>, stats: Option<PackingStats>) {
+    if let Some(stats) = stats {
+        total.get_or_insert_with(PackingStats::default).add(&stats);
+    }
+}
+
+/// Rejects combinations of options that cannot run together.
+fn validate_config(config: &CoreConfig) -> Result<(), BltError> {
+    if config.discard_output
+        && (config.output.is_some()
+            || config.output_dir.is_some()
+            || config.index.is_some()
+            || config.tee_original.is_some())
+    {
+        return Err(BltError::Config(
+            "Discarded output cannot be combined with an output, output directory, index, or mirrored input"
+                .to_string(),
+        ));
+    }
+    if config.output_dir.is_some() && config.index.is_some() {
+        return Err(BltError::Config(
+            "A document index cannot be combined with --output-dir".to_string(),
+        ));
+    }
+    if config
+        .output_dir
+        .as_ref()
+        .is_some_and(|dir| dir.content_addressed)
+        && config.manifest.is_none()
+    {
+        // Objects are named by hash alone; only the manifest says which document is which.
+        return Err(BltError::Config(
+            "A content-addressed output directory requires a manifest".to_string(),
+        ));
+    }
+    if config.previous_manifest.is_some() && config.manifest.is_none() {
+        // Unchanged documents are carried over in the manifest rather than written.
+        return Err(BltError::Config(
+            "Tokenizing changes since a previous snapshot requires a manifest".to_string(),
+        ));
+    }
+    if !matches!(config.document_ids, DocumentIdSource::Ordinal) && config.manifest.is_none() {
+        return Err(BltError::Config(
+            "Assigning document IDs requires a manifest".to_string(),
+        ));
+    }
+    if config.strategy_kind() == StrategyKind::Passthrough && !config.stream_markers.is_empty() {
+        return Err(BltError::Config(
+            "BOS/EOS tokens cannot be combined with passthrough mode".to_string(),
+        ));
+    }
+    if config.strategy_kind() == StrategyKind::Passthrough && config.entropy_markers.is_some() {
+        return Err(BltError::Config(
+            "Entropy markers cannot be combined with passthrough mode".to_string(),
+        ));
+    }
+    if config.strategy_kind() == StrategyKind::Passthrough && config.strict_specials {
+        return Err(BltError::Config(
+            "Strict special-token checks cannot be combined with passthrough mode".to_string(),
+        ));
+    }
+    if config.follow.is_some() && config.deterministic {
+        return Err(BltError::Config(
+            "Following an input cannot be combined with deterministic chunking".to_string(),
+        ));
+    }
+    if config.direct_io && (config.follow.is_some() || config.io_uring) {
+        // Direct writes cover whole blocks, so a growing output could not be flushed as it
+        // grows, and the io_uring writer does not align its buffers.
+        return Err(BltError::Config(
+            "Direct I/O output cannot be combined with following an input or io_uring".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Selects the tokenization strategy, framing it by lines when merges could hide the document
+/// boundaries post-processing splits at, and by records when a layout is configured.
+fn framed_strategy(config: &CoreConfig) -> Result<Arc<dyn TokenizationStrategy>, BltError> {
+    let mut strategy = select_strategy(config)?;
+    if postprocess::splits_documents(config)
+        && config
+            .bpe_data
+            .as_deref()
+            .is_some_and(tokenizer::merges_absorb_newline)
+    {
+        info!(
+            "Merges absorb newlines; tokenizing each line separately to keep document boundaries."
+        );
+        strategy = Arc::new(LineBoundaryStrategy::new(strategy));
+    }
+    match config.records.clone() {
+        None => Ok(strategy),
+        Some(_) if config.content_type != Some(ContentType::Bin) => Err(BltError::Config(
+            "A record layout requires the bin content type".to_string(),
+        )),
+        Some(layout) => Ok(Arc::new(RecordFramedStrategy::new(strategy, layout))),
+    }
+}
+
+fn select_strategy(config: &CoreConfig) -> Result<Arc<dyn TokenizationStrategy>, BltError> {
+    if let Some(merges) = &config.bpe_data {
+        config_loader::validate_merges(merges, config.byte_offset)
+            .map_err(|e| BltError::Config(e.to_string()))?;
+    }
+    let strategy = select_unshifted_strategy(config)?;
+    if config.byte_offset == 0 {
+        return Ok(strategy);
+    }
+    if config.strategy_kind() == StrategyKind::Passthrough {
+        return Err(BltError::Config(
+            "A byte token offset cannot be combined with passthrough mode".to_string(),
+        ));
+    }
+    info!(byte_offset = config.byte_offset, "Shifting byte tokens.");
+    let strategy = ByteOffsetStrategy::new(strategy, config.byte_offset)
+        .map_err(|e| BltError::Config(e.to_string()))?;
+    Ok(Arc::new(strategy))
+}
+
+/// Selects the strategy producing byte tokens 0-255, before any byte offset.
+fn select_unshifted_strategy(config: &CoreConfig) -> io::Result<Arc<dyn TokenizationStrategy>> {
+    if let Some(socket) = &config.daemon {
+        info!(socket = %socket.display(), "Sending chunks to a tokenizer daemon.");
+        return daemon::daemon_strategy(socket);
+    }
+    if let Some(sandbox) = &config.sandbox {
+        info!(
+            workers = sandbox.workers,
+            "Using sandboxed worker processes for chunk processing."
+        );
+        let kind = config.strategy_kind();
+        return sandbox::sandboxed_strategy(sandbox, kind, config.bpe_data.as_deref());
+    }
+    let strategy: Arc<dyn TokenizationStrategy> = match (config.strategy_kind(), &config.bpe_data) {
+        (StrategyKind::Passthrough, _) => {
+            info!("Using passthrough strategy (file copying without tokenization).");
+            Arc::new(PassthroughStrategy)
+        }
+        (StrategyKind::Bpe, Some(bpe_data)) => {
+            info!("Using BPE tokenization strategy.");
+            Arc::new(BpeStrategy::new(bpe_data.clone()))
+        }
+        _ => {
+            info!("Using basic tokenization strategy (byte-to-u16 conversion).");
+            Arc::new(BasicTokenizationStrategy)
+        }
+    };
+    Ok(strategy)
+}
+
+/// Writes the output header if enabled, returning the number of bytes written.
+async fn write_output_header(
+    writer: &mut io_handler::OutputWriter,
+    config: &CoreConfig,
+) -> io::Result<u64> {
+    if !config.write_header {
+        return Ok(0);
+    }
+    if config.output_format != OutputFormat::Raw {
+        return Err(BltError::Config(
+            "The output header is only supported for the raw output format".to_string(),
+        )
+        .into());
+    }
+    let merges_hash = config.bpe_data.as_deref().map(config_loader::merges_hash);
+    let header = header::OutputHeader::new(config.strategy_kind(), merges_hash)
+        .with_byte_offset(config.byte_offset);
+    let bytes = header.to_bytes();
+    writer.write_all(&bytes).await?;
+    Ok(bytes.len() as u64)
+}
+
+/// Marks the boundary between two documents of a multi-document run.
+///
+/// Passthrough output is a plain byte copy, so documents are concatenated as-is. The
+/// separator is always written as [`special_tokens::SEPARATOR`]; post-processing relabels it
+/// when another token is configured.
+async fn write_document_separator(
+    writer: &mut io_handler::OutputWriter,
+    config: &CoreConfig,
+) -> io::Result<()> {
+    // Columnar formats keep each document in its own row instead.
+    if config.strategy_kind() == StrategyKind::Passthrough
+        || config.document_separator.is_none()
+        || config.output_format != OutputFormat::Raw
+    {
+        return Ok(());
+    }
+    writer
+        .write_all(&special_tokens::SEPARATOR.to_be_bytes())
+        .await
+}
+
+fn manifest_entry(
+    config: &CoreConfig,
+    doc_id: u64,
+    source: String,
+    summary: pipeline::DocumentSummary,
+) -> ManifestEntry {
+    let token_width = token_width(config);
+    ManifestEntry {
+        doc_id,
+        id: None,
+        source,
+        input_bytes: summary.input_bytes,
+        output_bytes: summary.output_bytes,
+        tokens: summary.output_bytes / token_width,
+        content_hash: summary.content_hash,
+        output: None,
+        output_hash: None,
+        snapshot: config.snapshot_id.clone(),
+        content_type: config.content_type.as_ref().map(|t| t.name().to_string()),
+    }
+}
+
+/// The number of output bytes per token: passthrough output is a byte stream.
+fn token_width(config: &CoreConfig) -> u64 {
     if config.passthrough_mode {
-        info!("Using passthrough strategy (file copying without tokenization).");
-        Arc::new(PassthroughStrategy)
-    } else if let Some(ref bpe_data) = config.bpe_data {
-        info!("Using BPE tokenization strategy.");
-        Arc::new(BpeStrategy::new(bpe_data.clone()))
+        1
     } else {
-        info!("Using basic tokenization strategy (byte-to-u16 conversion).");
-        Arc::new(BasicTokenizationStrategy)
+        2
+    }
+}
+
+/// Returns the next document, or `None` once the input or the token budget runs out.
+async fn next_document(
+    documents: &mut io_handler::InputDocuments,
+    budget: Option<&TokenBudget>,
+) -> io::Result<Option<io_handler::InputDocument>> {
+    if budget.is_some_and(TokenBudget::is_exhausted) {
+        info!("Reached the maximum number of tokens, stopping");
+        return Ok(None);
+    }
+    documents.next().await
+}
+
+async fn write_marker(writer: &mut io_handler::OutputWriter, token: Option<u16>) -> io::Result<()> {
+    match token {
+        Some(token) => writer.write_all(&token.to_be_bytes()).await,
+        None => Ok(()),
     }
 }
 
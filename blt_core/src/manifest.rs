@@ -0,0 +1,348 @@
+//! Run manifests.
+//!
+//! A manifest is a JSON Lines file with one [`ManifestEntry`] per tokenized document. It records
+//! where each document came from, how large it was before and after tokenization, and an
+//! optional content hash that doubles as a dedup key, so downstream tools can reference or
+//! deduplicate documents without re-reading the raw corpus.
+//!
+//! Manifests also describe incremental corpus snapshots. A run tagged with a snapshot ID
+//! records it in the entries of the documents it tokenized. Given the manifest of a previous
+//! snapshot, a run tokenizes only documents that are new or whose content changed, and carries
+//! the entries of unchanged documents over from the previous manifest with their original
+//! snapshot IDs. The new manifest therefore lists the whole corpus, while its outputs, the
+//! entries tagged with the new ID, hold only the delta.
+//!
+//! Every line records the [`SCHEMA_VERSION`] it was written with, so that a line stays
+//! self-describing when manifests are concatenated. Lines written with an older schema, including
+//! the unversioned lines of schema 1, are migrated as they are read, and [`upgrade_manifest`]
+//! rewrites a whole manifest in the current schema. Lines from a newer schema are refused rather
+//! than misread.
+
+use crate::io_handler::AtomicFile;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// The schema version of the manifest lines this version of blt writes.
+///
+/// 1. Unversioned lines, as written before manifests were versioned.
+/// 2. Each line records its `schema_version`.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The field recording the schema version of a line.
+const SCHEMA_VERSION_FIELD: &str = "schema_version";
+
+/// Upgrades a line from the schema at its index plus one to the next; the first entry takes
+/// schema 1 to schema 2.
+const MIGRATIONS: [fn(&mut Map<String, Value>); SCHEMA_VERSION as usize - 1] = [
+    // Schema 2 only added the version field, which is stamped on every migrated line.
+    |_| {},
+];
+
+/// Describes a single tokenized document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The ordinal of the document within the run.
+    pub doc_id: u64,
+    /// The document's ID in the system it came from, when the run assigns IDs from a
+    /// [`DocumentIdSource`](crate::doc_ids::DocumentIdSource) other than ordinal numbering.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The document's source (input path, or `-` for stdin).
+    pub source: String,
+    /// The number of input bytes consumed.
+    pub input_bytes: u64,
+    /// The number of output bytes produced for this document.
+    pub output_bytes: u64,
+    /// The number of tokens produced for this document.
+    pub tokens: u64,
+    /// The hash of the document's original bytes, as `"<algorithm>:<hex>"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// The file the document was written to, when each document gets its own output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// The hash of the bytes of `output` as written, as `"<algorithm>:<hex>"`, used to detect
+    /// damaged outputs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_hash: Option<String>,
+    /// The snapshot whose outputs hold the document's tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// The name of the content type the document was tokenized as, if the run set one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+/// A manifest line: an entry and the schema version it is written with.
+#[derive(Serialize)]
+struct VersionedEntry<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    entry: &'a ManifestEntry,
+}
+
+/// Serializes `entries` as JSON Lines in the current schema.
+pub fn to_jsonl(entries: &[ManifestEntry]) -> io::Result<String> {
+    let mut out = String::new();
+    for entry in entries {
+        let line = VersionedEntry {
+            schema_version: SCHEMA_VERSION,
+            entry,
+        };
+        out.push_str(&serde_json::to_string(&line).map_err(io::Error::other)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Parses a JSON Lines manifest, skipping blank lines and migrating lines written with an
+/// older schema.
+pub fn from_jsonl(contents: &str) -> io::Result<Vec<ManifestEntry>> {
+    parse_lines(contents)
+        .map(|line| line.map(|(_, entry)| entry))
+        .collect()
+}
+
+/// Parses the lines of a manifest along with the schema version each was written with.
+fn parse_lines(contents: &str) -> impl Iterator<Item = io::Result<(u32, ManifestEntry)>> + '_ {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            parse_line(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid manifest line {}: {e}", index + 1),
+                )
+            })
+        })
+}
+
+fn parse_line(line: &str) -> Result<(u32, ManifestEntry), String> {
+    let mut fields: Map<String, Value> = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let version = match fields.remove(SCHEMA_VERSION_FIELD) {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|&version| version >= 1)
+            .ok_or_else(|| format!("invalid schema version {version}"))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "schema version {version} is newer than the supported {SCHEMA_VERSION}; upgrade blt to read it"
+        ));
+    }
+    for migrate in &MIGRATIONS[version as usize - 1..] {
+        migrate(&mut fields);
+    }
+    let entry = serde_json::from_value(Value::Object(fields)).map_err(|e| e.to_string())?;
+    Ok((version, entry))
+}
+
+/// What [`upgrade_manifest`] migrated.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ManifestUpgrade {
+    /// The number of entries in the manifest.
+    pub entries: u64,
+    /// The entries written with an older schema, by the schema version they had.
+    pub upgraded: Vec<(u32, u64)>,
+    /// The schema version every entry now has.
+    pub schema_version: u32,
+}
+
+impl fmt::Display for ManifestUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let upgraded: u64 = self.upgraded.iter().map(|(_, entries)| entries).sum();
+        writeln!(
+            f,
+            "Upgraded {upgraded} of {} entries to manifest schema {}",
+            self.entries, self.schema_version
+        )?;
+        for (version, entries) in &self.upgraded {
+            writeln!(f, "  from schema {version}: {entries}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites the manifest at `path` to `output`, which may be `path` itself, with every entry
+/// migrated to the current schema. The manifest is fully parsed before anything is written.
+pub async fn upgrade_manifest(path: &Path, output: &Path) -> io::Result<ManifestUpgrade> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    let mut entries = Vec::new();
+    let mut upgraded = BTreeMap::new();
+    for line in parse_lines(&contents) {
+        let (version, entry) = line?;
+        if version < SCHEMA_VERSION {
+            *upgraded.entry(version).or_insert(0) += 1;
+        }
+        entries.push(entry);
+    }
+    write_manifest(output, &entries).await?;
+    Ok(ManifestUpgrade {
+        entries: entries.len() as u64,
+        upgraded: upgraded.into_iter().collect(),
+        schema_version: SCHEMA_VERSION,
+    })
+}
+
+/// Writes `entries` to `path` as JSON Lines, replacing any existing manifest atomically.
+pub async fn write_manifest(path: &Path, entries: &[ManifestEntry]) -> io::Result<()> {
+    let mut file = AtomicFile::create(path, true).await?;
+    file.write_all(to_jsonl(entries)?.as_bytes()).await?;
+    file.shutdown().await
+}
+
+/// Reads a JSON Lines manifest from `path`.
+pub async fn read_manifest(path: &Path) -> io::Result<Vec<ManifestEntry>> {
+    from_jsonl(&tokio::fs::read_to_string(path).await?)
+}
+
+/// The documents of a previous snapshot, looked up by source and content hash.
+#[derive(Debug, Default)]
+pub(crate) struct PreviousSnapshot {
+    entries: HashMap<(String, String), ManifestEntry>,
+}
+
+impl PreviousSnapshot {
+    /// Reads the manifest of the previous snapshot. Entries without a content hash cannot be
+    /// matched and are ignored.
+    pub(crate) async fn load(path: &Path) -> io::Result<Self> {
+        let entries = read_manifest(path)
+            .await?
+            .into_iter()
+            .filter_map(|entry| {
+                let hash = entry.content_hash.clone()?;
+                Some(((entry.source.clone(), hash), entry))
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Returns the previous entry of the document at `source` if its content, hashed as
+    /// `content_hash`, is unchanged.
+    pub(crate) fn unchanged(&self, source: &str, content_hash: &str) -> Option<&ManifestEntry> {
+        self.entries
+            .get(&(source.to_string(), content_hash.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_entry() -> ManifestEntry {
+        ManifestEntry {
+            doc_id: 0,
+            id: Some("docs/input.txt".to_string()),
+            source: "input.txt".to_string(),
+            input_bytes: 3,
+            output_bytes: 6,
+            tokens: 3,
+            content_hash: Some("sha256:abc".to_string()),
+            output: None,
+            output_hash: None,
+            snapshot: Some("2026-09".to_string()),
+            content_type: Some("text".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_jsonl_roundtrip() -> io::Result<()> {
+        let entries = vec![sample_entry()];
+        let jsonl = to_jsonl(&entries)?;
+        assert!(jsonl.ends_with('\n'));
+        assert_eq!(from_jsonl(&jsonl)?, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash_is_optional() -> io::Result<()> {
+        let entry = ManifestEntry {
+            content_hash: None,
+            ..sample_entry()
+        };
+        let jsonl = to_jsonl(std::slice::from_ref(&entry))?;
+        assert!(!jsonl.contains("content_hash"));
+        assert_eq!(from_jsonl(&jsonl)?, vec![entry]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_previous_snapshot_matches_source_and_hash() -> io::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        let unhashed = ManifestEntry {
+            source: "unhashed.txt".to_string(),
+            content_hash: None,
+            ..sample_entry()
+        };
+        write_manifest(file.path(), &[sample_entry(), unhashed]).await?;
+        let previous = PreviousSnapshot::load(file.path()).await?;
+        assert_eq!(
+            previous.unchanged("input.txt", "sha256:abc"),
+            Some(&sample_entry())
+        );
+        assert_eq!(previous.unchanged("input.txt", "sha256:def"), None);
+        assert_eq!(previous.unchanged("other.txt", "sha256:abc"), None);
+        assert_eq!(previous.entries.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_jsonl_rejects_garbage() {
+        let err = from_jsonl("{not json}\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_lines_record_their_schema() -> io::Result<()> {
+        let jsonl = to_jsonl(&[sample_entry()])?;
+        assert!(jsonl.starts_with(&format!("{{\"schema_version\":{SCHEMA_VERSION},")));
+
+        // Unversioned lines are schema 1.
+        let unversioned = serde_json::to_string(&sample_entry()).map_err(io::Error::other)?;
+        assert_eq!(from_jsonl(&unversioned)?, vec![sample_entry()]);
+
+        let newer = jsonl.replacen(
+            &format!("\"schema_version\":{SCHEMA_VERSION}"),
+            "\"schema_version\":99",
+            1,
+        );
+        let err = from_jsonl(&format!("\n{newer}")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("line 2: schema version 99 is newer"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upgrades_manifests() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("manifest.jsonl");
+        let unversioned = serde_json::to_string(&sample_entry()).map_err(io::Error::other)?;
+        let current = to_jsonl(&[sample_entry()])?;
+        tokio::fs::write(&path, format!("{unversioned}\n{current}")).await?;
+
+        let upgrade = upgrade_manifest(&path, &path).await?;
+        assert_eq!(
+            upgrade,
+            ManifestUpgrade {
+                entries: 2,
+                upgraded: vec![(1, 1)],
+                schema_version: SCHEMA_VERSION,
+            }
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(&path).await?,
+            to_jsonl(&[sample_entry(), sample_entry()])?
+        );
+        Ok(())
+    }
+}
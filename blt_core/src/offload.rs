@@ -0,0 +1,96 @@
+//! Running CPU-bound strategies on Tokio's blocking thread pool.
+//!
+//! Strategies are async so that the daemon and sandbox clients can wait on I/O, but BPE
+//! merging never yields: a large chunk tokenized directly in a runtime task occupies a worker
+//! thread for its whole duration, and at high thread counts the reactor and the pipeline's
+//! readers and writers starve. [`OffloadedStrategy`] moves chunks of at least
+//! [`MIN_OFFLOAD_BYTES`] to the blocking pool instead, keeping the runtime free for I/O.
+//! Smaller chunks take less time to tokenize than to hand over, so they stay where they are.
+
+use crate::tokenizer::{ExpansionEstimate, TokenizationStrategy};
+use crate::{CoreConfig, StrategyKind};
+use bytes::Bytes;
+use std::io;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+
+/// The smallest chunk tokenized on the blocking pool.
+pub const MIN_OFFLOAD_BYTES: usize = 16 * 1024;
+
+/// A strategy that runs another on the blocking thread pool.
+pub struct OffloadedStrategy {
+    inner: Arc<dyn TokenizationStrategy>,
+}
+
+impl OffloadedStrategy {
+    /// Runs `inner`, which should not wait on I/O, on the blocking thread pool.
+    pub fn new(inner: Arc<dyn TokenizationStrategy>) -> Self {
+        Self { inner }
+    }
+
+    async fn offload(&self, chunk: Bytes) -> io::Result<Bytes> {
+        let inner = self.inner.clone();
+        let runtime = Handle::current();
+        tokio::task::spawn_blocking(move || runtime.block_on(inner.process_bytes(chunk)))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for OffloadedStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        if chunk_data.len() < MIN_OFFLOAD_BYTES {
+            return self.inner.process_chunk(chunk_data).await;
+        }
+        // The task needs the chunk to outlive this call.
+        let tokens = self.offload(Bytes::copy_from_slice(chunk_data)).await?;
+        Ok(tokens.into())
+    }
+
+    async fn process_bytes(&self, chunk: Bytes) -> io::Result<Bytes> {
+        if chunk.len() < MIN_OFFLOAD_BYTES {
+            return self.inner.process_bytes(chunk).await;
+        }
+        self.offload(chunk).await
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        self.inner.estimated_expansion(input_bytes)
+    }
+}
+
+/// Wraps `strategy` in an [`OffloadedStrategy`] if `config` selects in-process BPE; the
+/// other strategies are either cheap or wait on another process.
+pub(crate) fn offload_cpu_bound(
+    config: &CoreConfig,
+    strategy: Arc<dyn TokenizationStrategy>,
+) -> Arc<dyn TokenizationStrategy> {
+    let in_process = config.daemon.is_none() && config.sandbox.is_none();
+    if in_process && config.strategy_kind() == StrategyKind::Bpe {
+        Arc::new(OffloadedStrategy::new(strategy))
+    } else {
+        strategy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::BpeStrategy;
+    use crate::BpeMerges;
+
+    #[tokio::test]
+    async fn test_matches_inner_strategy() -> io::Result<()> {
+        let merges: BpeMerges = vec![((97, 98), 256)].into_iter().collect();
+        let bpe = Arc::new(BpeStrategy::new(Arc::new(merges)));
+        let offloaded = OffloadedStrategy::new(bpe.clone());
+        for len in [3, MIN_OFFLOAD_BYTES * 2] {
+            let data: Vec<u8> = b"abc".iter().copied().cycle().take(len).collect();
+            let expected = bpe.process_chunk(&data).await?;
+            assert_eq!(offloaded.process_chunk(&data).await?, expected);
+            assert_eq!(offloaded.process_bytes(Bytes::from(data)).await?, expected);
+        }
+        Ok(())
+    }
+}
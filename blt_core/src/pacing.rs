@@ -0,0 +1,256 @@
+//! Pacing chunk processing with a duty cycle, for energy- or carbon-aware runs.
+//!
+//! A [`PacingHook`] reports the fraction of time workers may spend processing. After each
+//! chunk, a worker idles long enough to keep its busy time at that fraction, so a duty cycle
+//! of `0.25` makes processing take roughly four times as long while leaving cores idle
+//! three quarters of the time. A duty cycle of `0.0` pauses processing until the hook reports
+//! a positive value again.
+//!
+//! Hooks are consulted at most once per [`Pacing::refresh`], so they may do moderately
+//! expensive work such as running a command or reading system files. Built-in hooks cover a
+//! fixed duty cycle ([`FixedDutyCycle`]), an external command ([`CommandHook`]), and running
+//! on battery power ([`BatteryHook`]); [`MinDutyCycle`] combines several.
+
+use crate::tokenizer::{ExpansionEstimate, TokenizationStrategy};
+use bytes::Bytes;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Reports how much of the time workers may spend processing chunks.
+pub trait PacingHook: fmt::Debug + Send + Sync {
+    /// The current duty cycle, from `0.0` (paused) to `1.0` (full speed). Values outside
+    /// that range are clamped.
+    fn duty_cycle(&self) -> f64;
+}
+
+/// A constant duty cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedDutyCycle(pub f64);
+
+impl PacingHook for FixedDutyCycle {
+    fn duty_cycle(&self) -> f64 {
+        self.0
+    }
+}
+
+/// Runs a shell command and reads the duty cycle from its standard output.
+///
+/// The command can consult a grid carbon-intensity API, a power meter, or anything else. If
+/// it fails or prints something other than a number, processing continues at full speed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandHook {
+    /// The command, run with `sh -c`.
+    pub command: String,
+}
+
+impl PacingHook for CommandHook {
+    fn duty_cycle(&self) -> f64 {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::inherit())
+            .output();
+        let parsed = output.and_then(|output| {
+            if !output.status.success() {
+                return Err(io::Error::other(format!("exited with {}", output.status)));
+            }
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            stdout.trim().parse::<f64>().map_err(io::Error::other)
+        });
+        parsed.unwrap_or_else(|e| {
+            warn!(command = %self.command, "Pacing command failed, running at full speed: {e}");
+            1.0
+        })
+    }
+}
+
+/// Slows processing down while the machine runs on battery power.
+///
+/// Power sources are read from `/sys/class/power_supply` on Linux. Elsewhere, or when no
+/// mains adapter is reported, the machine is assumed to be plugged in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryHook {
+    /// The duty cycle used while on battery.
+    pub on_battery: f64,
+}
+
+impl BatteryHook {
+    /// Returns `true` if a mains adapter exists and none of them is online.
+    pub fn on_battery() -> bool {
+        let Ok(supplies) = std::fs::read_dir("/sys/class/power_supply") else {
+            return false;
+        };
+        let mut mains = supplies.filter_map(Result::ok).filter(|supply| {
+            std::fs::read_to_string(supply.path().join("type")).is_ok_and(|t| t.trim() == "Mains")
+        });
+        let mut found = false;
+        let any_online = mains.any(|supply| {
+            found = true;
+            std::fs::read_to_string(supply.path().join("online")).is_ok_and(|o| o.trim() == "1")
+        });
+        found && !any_online
+    }
+}
+
+impl PacingHook for BatteryHook {
+    fn duty_cycle(&self) -> f64 {
+        if Self::on_battery() {
+            self.on_battery
+        } else {
+            1.0
+        }
+    }
+}
+
+/// The lowest duty cycle reported by any of several hooks.
+#[derive(Clone, Debug)]
+pub struct MinDutyCycle(pub Vec<Arc<dyn PacingHook>>);
+
+impl PacingHook for MinDutyCycle {
+    fn duty_cycle(&self) -> f64 {
+        self.0
+            .iter()
+            .map(|hook| hook.duty_cycle())
+            .fold(1.0, f64::min)
+    }
+}
+
+/// How chunk processing is paced.
+#[derive(Clone, Debug)]
+pub struct Pacing {
+    /// The hook reporting the duty cycle.
+    pub hook: Arc<dyn PacingHook>,
+    /// How long a reported duty cycle is reused before the hook is consulted again.
+    pub refresh: Duration,
+}
+
+impl Pacing {
+    /// The default interval between hook consultations.
+    pub const DEFAULT_REFRESH: Duration = Duration::from_secs(30);
+
+    /// Paces processing with `hook`, consulted every [`Self::DEFAULT_REFRESH`].
+    pub fn new(hook: Arc<dyn PacingHook>) -> Self {
+        Self {
+            hook,
+            refresh: Self::DEFAULT_REFRESH,
+        }
+    }
+}
+
+/// A strategy that idles after each chunk to keep to the duty cycle of a [`Pacing`].
+pub(crate) struct PacedStrategy {
+    inner: Arc<dyn TokenizationStrategy>,
+    pacing: Pacing,
+    current: Mutex<Option<(Instant, f64)>>,
+}
+
+impl PacedStrategy {
+    pub(crate) fn new(inner: Arc<dyn TokenizationStrategy>, pacing: Pacing) -> Self {
+        Self {
+            inner,
+            pacing,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// The duty cycle, consulting the hook if the last reading is older than the refresh.
+    fn duty_cycle(&self) -> f64 {
+        let mut current = self.current.lock().expect("pacing lock poisoned");
+        match *current {
+            Some((read_at, duty)) if read_at.elapsed() < self.pacing.refresh => duty,
+            previous => {
+                let duty = self.pacing.hook.duty_cycle().clamp(0.0, 1.0);
+                if previous.is_none_or(|(_, old)| old != duty) {
+                    debug!(duty, "Pacing duty cycle changed");
+                }
+                *current = Some((Instant::now(), duty));
+                duty
+            }
+        }
+    }
+
+    /// Runs `work` once the duty cycle is positive, then idles to keep to it.
+    async fn paced<T>(&self, work: impl Future<Output = T>) -> T {
+        while self.duty_cycle() == 0.0 {
+            tokio::time::sleep(self.pacing.refresh).await;
+        }
+        let started = Instant::now();
+        let result = work.await;
+        let duty = self.duty_cycle();
+        if duty > 0.0 && duty < 1.0 {
+            let idle = started.elapsed().mul_f64((1.0 - duty) / duty);
+            tokio::time::sleep(idle).await;
+        }
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for PacedStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        self.paced(self.inner.process_chunk(chunk_data)).await
+    }
+
+    async fn process_bytes(&self, chunk: Bytes) -> io::Result<Bytes> {
+        self.paced(self.inner.process_bytes(chunk)).await
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        self.inner.estimated_expansion(input_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::BasicTokenizationStrategy;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Default)]
+    struct CountingHook(AtomicUsize);
+
+    impl PacingHook for CountingHook {
+        fn duty_cycle(&self) -> f64 {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            0.5
+        }
+    }
+
+    #[test]
+    fn test_min_duty_cycle() {
+        let hooks: Vec<Arc<dyn PacingHook>> =
+            vec![Arc::new(FixedDutyCycle(0.8)), Arc::new(FixedDutyCycle(0.3))];
+        assert_eq!(MinDutyCycle(hooks).duty_cycle(), 0.3);
+        assert_eq!(MinDutyCycle(Vec::new()).duty_cycle(), 1.0);
+    }
+
+    #[test]
+    fn test_command_hook() {
+        let hook = CommandHook {
+            command: "echo 0.25".to_string(),
+        };
+        assert_eq!(hook.duty_cycle(), 0.25);
+        let failing = CommandHook {
+            command: "exit 3".to_string(),
+        };
+        assert_eq!(failing.duty_cycle(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_paced_strategy_caches_the_hook() {
+        let hook = Arc::new(CountingHook::default());
+        let strategy = PacedStrategy::new(
+            Arc::new(BasicTokenizationStrategy),
+            Pacing::new(hook.clone()),
+        );
+        for _ in 0..3 {
+            assert_eq!(strategy.process_chunk(b"ab").await.unwrap(), [0, 97, 0, 98]);
+        }
+        assert_eq!(hook.0.load(Ordering::Relaxed), 1);
+    }
+}
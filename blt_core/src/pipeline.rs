@@ -7,427 +7,792 @@
 //!
 //! This module contains the core concurrent processing pipeline for the tokenizer.
 //! It handles reading from an input source, spawning parallel tasks for tokenization,
-//! and writing the ordered results to an output sink.
-
-use crate::io_handler::{self, InputSource, OutputWriter};
+//! and writing the ordered results to an output sink. Reading and spawning run in a dispatch
+//! task of their own, which hands every chunk task to the writer in input order, so a slow
+//! write never holds up reading or tokenizing the chunks behind it.
+//!
+//! Mapped files and streams are cut into chunks by the `mmap` and `stream` pipelines, whose
+//! chunk tasks are written in order by `ordered`. When chunks start and how many may wait to
+//! be written is up to a [`Scheduler`].
+
+use crate::doc_ids::FirstLine;
+use crate::entropy::EntropyMarking;
+use crate::hashing::ContentHasher;
+use crate::interrupt::CancellationToken;
+use crate::io_handler::{InputSource, OutputWriter, TokenBudget};
+use crate::metrics::Metrics;
+use crate::progress::Progress;
+use crate::records::RecordLayout;
+use crate::special_tokens;
 use crate::tokenizer::TokenizationStrategy;
-use std::collections::HashMap;
 use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::mpsc;
-use tracing::{debug, error, info, info_span, instrument, Instrument};
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+
+mod mmap;
+mod ordered;
+mod prefault;
+mod scaling;
+mod scheduler;
+mod stream;
+pub use scaling::ThreadScaling;
+pub use scheduler::{FifoScheduler, Scheduler};
+
+use mmap::{run_mmap_pipeline, ChunkPlan};
+use stream::{run_stream_pipeline, ChunkReader};
+
+/// The destinations that ordered chunk results are written to.
+///
+/// Besides the token stream, the pipeline can optionally mirror the original input bytes to a
+/// second writer and hash them. Everything is done chunk by chunk in input order, so the
+/// streams advance in lockstep.
+pub(crate) struct PipelineOutput<'a> {
+    tokens: &'a mut OutputWriter,
+    original: Option<&'a mut OutputWriter>,
+    hasher: Option<ContentHasher>,
+    /// The document's first line, captured when document IDs are read from it.
+    first_line: Option<FirstLine>,
+    input_bytes: u64,
+    output_bytes: u64,
+    chunks: u64,
+    /// The input offset of the next chunk, counting skipped chunks.
+    position: u64,
+    strict_specials: bool,
+    /// Whether chunks that fail to tokenize are skipped rather than failing the run.
+    skip_errors: bool,
+    skipped: Vec<SkippedRange>,
+    budget: Option<Arc<TokenBudget>>,
+    progress: Option<Arc<Progress>>,
+    metrics: Option<Arc<Metrics>>,
+    /// Whether every chunk is flushed as soon as it is written.
+    flush_chunks: bool,
+    /// Optional classification of chunks into compressed and plain regions.
+    entropy: Option<EntropyMarking>,
+    /// Whether the region being written is compressed, once a marker has been written.
+    compressed_region: Option<bool>,
+    /// Chunks dispatched for tokenization but not yet written.
+    queued: Arc<AtomicU64>,
+    /// Whether the pages of a mapped input are released once their chunks are written.
+    release_mapped: bool,
+    /// How far past the dispatched chunks the pages of a mapped input are touched, if at all.
+    prefault: Option<usize>,
+    /// A request to stop dispatching chunks, after which those in flight are still written.
+    cancellation: Option<CancellationToken>,
+}
 
-/// The main entry point for running the tokenization pipeline.
-#[instrument(skip_all, name = "run_pipeline")]
-pub(crate) async fn run(
-    input_source: InputSource,
-    output_writer: OutputWriter,
-    effective_chunk_size: usize,
-    num_threads: usize,
-    strategy: Arc<dyn TokenizationStrategy>,
-) -> io::Result<()> {
-    match input_source {
-        InputSource::Mmap(mmap) => {
-            run_mmap_pipeline(
-                mmap,
-                output_writer,
-                effective_chunk_size,
-                num_threads,
-                strategy,
-            )
-            .await
+/// The view of a [`PipelineOutput`] the dispatch task works from.
+#[derive(Clone)]
+struct DispatchGauge {
+    budget: Option<Arc<TokenBudget>>,
+    metrics: Option<Arc<Metrics>>,
+    progress: Option<Arc<Progress>>,
+    queued: Arc<AtomicU64>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl DispatchGauge {
+    /// Returns `true` once the output cannot take more tokens, so reading input is pointless.
+    fn is_full(&self) -> bool {
+        self.budget
+            .as_ref()
+            .is_some_and(|budget| budget.is_exhausted())
+    }
+
+    /// Returns `true` once the run has been cancelled.
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Completes once the run has been cancelled, or never without a token.
+    async fn cancelled(&self) {
+        match &self.cancellation {
+            Some(token) => token.cancelled().await,
+            None => std::future::pending().await,
         }
-        InputSource::Stdin(input_reader) => {
-            run_stream_pipeline(
-                input_reader,
-                output_writer,
-                effective_chunk_size,
-                num_threads,
-                strategy,
-            )
-            .await
+    }
+
+    /// Counts `chunks` as queued until they are written.
+    fn enqueue(&self, chunks: u64) {
+        self.queued.fetch_add(chunks, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.enqueue_chunks(chunks);
+        }
+        if let Some(progress) = &self.progress {
+            progress.enqueue_chunks(chunks);
         }
     }
 }
 
-// --- Mmap Pipeline ---
+/// What the pipeline observed while writing a document.
+pub(crate) struct DocumentSummary {
+    pub(crate) input_bytes: u64,
+    pub(crate) output_bytes: u64,
+    pub(crate) chunks: u64,
+    pub(crate) content_hash: Option<String>,
+    pub(crate) first_line: Option<Vec<u8>>,
+    pub(crate) skipped: Vec<SkippedRange>,
+}
 
-async fn run_mmap_pipeline(
-    mmap: memmap2::Mmap,
-    mut output_writer: OutputWriter,
-    effective_chunk_size: usize,
-    num_threads: usize,
-    strategy: Arc<dyn TokenizationStrategy>,
-) -> io::Result<()> {
-    info!(
-        "Running pipeline in Mmap mode for file of size: {}",
-        mmap.len()
-    );
-    let mmap_arc = Arc::new(mmap);
-    let (results_tx, mut results_rx) = mpsc::channel(num_threads * 2);
-    let mut dispatched_task_handles = HashMap::new();
-    let mut received_results = HashMap::new();
-    let mut current_expected_chunk_id = 0;
-
-    let chunks: Vec<(usize, usize)> = mmap_arc
-        .chunks(effective_chunk_size)
-        .enumerate()
-        .map(|(i, chunk)| {
-            let start = i * effective_chunk_size;
-            let len = chunk.len();
-            (start, len)
-        })
-        .collect();
-
-    let mut chunk_iter = chunks.into_iter().enumerate();
-
-    loop {
-        while dispatched_task_handles.len() < num_threads {
-            if let Some((task_id, (start, len))) = chunk_iter.next() {
-                let handle = spawn_mmap_chunk_task(
-                    task_id,
-                    mmap_arc.clone(),
-                    start,
-                    len,
-                    strategy.clone(),
-                    results_tx.clone(),
-                )
-                .await;
-                dispatched_task_handles.insert(task_id, handle);
-            } else {
-                break;
-            }
+/// A chunk of a document left out of the output after failing to tokenize.
+pub(crate) struct SkippedRange {
+    pub(crate) offset: u64,
+    pub(crate) bytes: u64,
+    pub(crate) error: String,
+}
+
+impl<'a> PipelineOutput<'a> {
+    pub(crate) fn new(
+        tokens: &'a mut OutputWriter,
+        original: Option<&'a mut OutputWriter>,
+        hasher: Option<ContentHasher>,
+    ) -> Self {
+        Self {
+            tokens,
+            original,
+            hasher,
+            first_line: None,
+            input_bytes: 0,
+            output_bytes: 0,
+            chunks: 0,
+            position: 0,
+            strict_specials: false,
+            skip_errors: false,
+            skipped: Vec::new(),
+            budget: None,
+            progress: None,
+            metrics: None,
+            flush_chunks: false,
+            entropy: None,
+            compressed_region: None,
+            queued: Arc::new(AtomicU64::new(0)),
+            release_mapped: false,
+            prefault: None,
+            cancellation: None,
         }
+    }
+
+    /// Captures the document's first line, which document IDs can be read from.
+    pub(crate) fn with_first_line(mut self, capture: bool) -> Self {
+        self.first_line = capture.then(FirstLine::default);
+        self
+    }
+
+    /// Stops reading input once `budget` is spent.
+    pub(crate) fn with_budget(mut self, budget: Option<Arc<TokenBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Adds the input and token bytes of every chunk written to `progress`, and tracks the
+    /// chunks in flight there.
+    pub(crate) fn with_progress(mut self, progress: Option<Arc<Progress>>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Counts written chunks, queued chunks, and failures in `metrics`.
+    pub(crate) fn with_metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Flushes the output after every chunk, so readers see tokens as input arrives.
+    pub(crate) fn with_flush_chunks(mut self, flush_chunks: bool) -> Self {
+        self.flush_chunks = flush_chunks;
+        self
+    }
+
+    /// Releases the pages of a memory-mapped input with `MADV_DONTNEED` once the chunks
+    /// covering them are written, so a scan of a huge file does not hold it all in memory.
+    pub(crate) fn with_release_mapped(mut self, release_mapped: bool) -> Self {
+        self.release_mapped = release_mapped;
+        self
+    }
 
-        if dispatched_task_handles.is_empty() {
-            break;
+    /// Touches the pages of a memory-mapped input up to `distance` bytes past the dispatched
+    /// chunks on a background thread, so page faults on cold files do not stall the workers.
+    pub(crate) fn with_prefault(mut self, distance: Option<usize>) -> Self {
+        self.prefault = distance;
+        self
+    }
+
+    /// Writes a region marker before each run of chunks `marking` classifies alike.
+    pub(crate) fn with_entropy_markers(mut self, marking: Option<EntropyMarking>) -> Self {
+        self.entropy = marking;
+        self
+    }
+
+    /// Stops dispatching chunks once `cancellation` is cancelled; the chunks already
+    /// dispatched are still written.
+    pub(crate) fn with_cancellation(mut self, cancellation: Option<CancellationToken>) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// What the dispatch task needs to know about this output.
+    fn gauge(&self) -> DispatchGauge {
+        DispatchGauge {
+            budget: self.budget.clone(),
+            metrics: self.metrics.clone(),
+            progress: self.progress.clone(),
+            queued: self.queued.clone(),
+            cancellation: self.cancellation.clone(),
         }
+    }
 
-        if let Some((task_id, result)) = results_rx.recv().await {
-            debug!(task_id, "Received result for mmap task");
-            dispatched_task_handles.remove(&task_id);
-            received_results.insert(task_id, result);
-            write_ordered_mmap_results(
-                &mut received_results,
-                &mut current_expected_chunk_id,
-                &mut output_writer,
-            )
-            .await?;
-        } else {
-            break;
+    /// Stops counting chunks that will not be written, after the pipeline stops.
+    fn release_queue(&mut self) {
+        let queued = self.queued.swap(0, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.dequeue_chunks(queued);
         }
+        if let Some(progress) = &self.progress {
+            progress.dequeue_chunks(queued);
+        }
+    }
+
+    /// Fails the run when a chunk produces a token in the reserved special-token range.
+    pub(crate) fn with_strict_specials(mut self, strict: bool) -> Self {
+        self.strict_specials = strict;
+        self
     }
 
-    finalize_mmap_results(
-        &mut received_results,
-        &mut current_expected_chunk_id,
-        &mut output_writer,
-    )
-    .await?;
+    /// Leaves chunks that fail to tokenize out of the output instead of failing the run.
+    pub(crate) fn with_skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
 
-    output_writer.flush().await?;
-    Ok(())
-}
+    pub(crate) fn into_summary(self) -> DocumentSummary {
+        DocumentSummary {
+            input_bytes: self.input_bytes,
+            output_bytes: self.output_bytes,
+            chunks: self.chunks,
+            content_hash: self.hasher.map(ContentHasher::finalize),
+            first_line: self.first_line.map(FirstLine::finish),
+            skipped: self.skipped,
+        }
+    }
 
-async fn spawn_mmap_chunk_task(
-    task_id: usize,
-    mmap_arc: Arc<memmap2::Mmap>,
-    start: usize,
-    len: usize,
-    strategy: Arc<dyn TokenizationStrategy>,
-    results_tx: mpsc::Sender<(usize, io::Result<Vec<u8>>)>,
-) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(
-        async move {
-            let chunk_slice = &mmap_arc[start..start + len];
-            let result = strategy.process_chunk(chunk_slice).await;
-            if results_tx.send((task_id, result)).await.is_err() {
-                error!(task_id, "Failed to send mmap result: receiver dropped.");
-            }
+    /// Checks a tokenized chunk before it is written.
+    fn check_chunk(&self, tokens: &[u8]) -> io::Result<()> {
+        if self.strict_specials {
+            self.check_specials(tokens)?;
         }
-        .instrument(info_span!("process_mmap_chunk_task", task_id)),
-    )
-}
+        Ok(())
+    }
 
-async fn write_ordered_mmap_results(
-    received_results: &mut HashMap<usize, io::Result<Vec<u8>>>,
-    current_expected_chunk_id: &mut usize,
-    output_writer: &mut OutputWriter,
-) -> io::Result<()> {
-    while let Some(result_data) = received_results.remove(current_expected_chunk_id) {
-        match result_data {
-            Ok(chunk_data) => {
-                output_writer.write_all(&chunk_data).await?;
+    async fn write_chunk(&mut self, original: &[u8], tokens: &[u8]) -> io::Result<()> {
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(original);
+        }
+        if let Some(first_line) = self.first_line.as_mut() {
+            first_line.update(original);
+        }
+        if let Some(original_writer) = self.original.as_mut() {
+            original_writer.write_all(original).await?;
+        }
+        if let Some(marking) = &self.entropy {
+            let compressed = marking.is_compressed(original);
+            if self.compressed_region != Some(compressed) {
+                let marker = EntropyMarking::marker(compressed).to_be_bytes();
+                self.tokens.write_all(&marker).await?;
+                self.output_bytes += marker.len() as u64;
+                self.compressed_region = Some(compressed);
             }
-            Err(e) => return Err(e),
         }
-        *current_expected_chunk_id += 1;
+        self.input_bytes += original.len() as u64;
+        self.output_bytes += tokens.len() as u64;
+        self.position += original.len() as u64;
+        self.chunks += 1;
+        self.tokens.write_all(tokens).await?;
+        if self.flush_chunks {
+            self.flush().await?;
+        }
+        if let Some(progress) = &self.progress {
+            progress.add_written(tokens.len() as u64);
+        }
+        self.chunk_done(original);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_chunk(original.len() as u64, tokens.len() as u64);
+        }
+        Ok(())
     }
-    Ok(())
-}
 
-async fn finalize_mmap_results(
-    received_results: &mut HashMap<usize, io::Result<Vec<u8>>>,
-    current_expected_chunk_id: &mut usize,
-    output_writer: &mut OutputWriter,
-) -> io::Result<()> {
-    let mut sorted_keys: Vec<usize> = received_results.keys().copied().collect();
-    sorted_keys.sort_unstable();
-
-    for key in sorted_keys {
-        if key == *current_expected_chunk_id {
-            if let Some(result_data) = received_results.remove(&key) {
-                match result_data {
-                    Ok(chunk_data) => {
-                        output_writer.write_all(&chunk_data).await?;
-                    }
-                    Err(e) => return Err(e),
-                }
-                *current_expected_chunk_id += 1;
+    /// Records a chunk that failed with `error` and leaves it out of the output. Its input
+    /// still counts towards the content hash, which identifies the document, but is not
+    /// mirrored, so the mirror matches the tokens.
+    fn skip_chunk(&mut self, original: &[u8], error: &io::Error) {
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(original);
+        }
+        if let Some(first_line) = self.first_line.as_mut() {
+            first_line.update(original);
+        }
+        self.skipped.push(SkippedRange {
+            offset: self.position,
+            bytes: original.len() as u64,
+            error: error.to_string(),
+        });
+        self.position += original.len() as u64;
+        self.chunk_done(original);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_error();
+        }
+    }
+
+    /// Counts a dispatched chunk as handled.
+    fn chunk_done(&mut self, original: &[u8]) {
+        if let Some(progress) = &self.progress {
+            progress.add(original.len() as u64);
+            progress.dequeue_chunks(1);
+        }
+        let _ = self
+            .queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |q| q.checked_sub(1));
+        if let Some(metrics) = &self.metrics {
+            metrics.dequeue_chunks(1);
+        }
+    }
+
+    /// Rejects `tokens` if any of them aliases a special token.
+    fn check_specials(&self, tokens: &[u8]) -> io::Result<()> {
+        let reserved = tokens
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .position(special_tokens::is_reserved);
+        match reserved {
+            None => Ok(()),
+            Some(position) => {
+                let token = u16::from_be_bytes([tokens[position * 2], tokens[position * 2 + 1]]);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Token {token:#06x} at output token {} of the document (input chunk at byte offset {}) lies in the reserved special-token range",
+                        self.output_bytes / 2 + position as u64,
+                        self.position
+                    ),
+                ))
             }
         }
     }
-    Ok(())
-}
 
-// --- Stream Pipeline (for Stdin) ---
+    async fn flush(&mut self) -> io::Result<()> {
+        if let Some(original_writer) = self.original.as_mut() {
+            original_writer.flush().await?;
+        }
+        self.tokens.flush().await
+    }
+}
 
-async fn run_stream_pipeline(
-    mut input_reader: io_handler::InputReader,
-    mut output_writer: OutputWriter,
+/// The main entry point for running the tokenization pipeline.
+///
+/// Streams are cut into chunks as their reads return. With `fill_chunks`, every chunk but the
+/// last is read to exactly `effective_chunk_size` bytes, so chunk boundaries do not depend on
+/// how the input happened to arrive. With `records`, chunks instead end after the last whole
+/// record that fits, and the rest is carried into the next chunk.
+#[instrument(skip_all, name = "run_pipeline")]
+pub(crate) async fn run(
+    input_source: InputSource,
+    output: &mut PipelineOutput<'_>,
     effective_chunk_size: usize,
-    num_threads: usize,
+    fill_chunks: bool,
+    records: Option<&RecordLayout>,
+    scheduler: &Arc<dyn Scheduler>,
     strategy: Arc<dyn TokenizationStrategy>,
 ) -> io::Result<()> {
-    info!("Running pipeline in Stream mode for stdin");
-    let (results_tx, mut results_rx) = mpsc::channel(num_threads * 2);
-    let mut context = ProcessingContext::new();
-
-    loop {
-        manage_task_spawning(
-            &mut context,
-            &mut input_reader,
-            effective_chunk_size,
-            num_threads,
-            strategy.clone(),
-            results_tx.clone(),
-        )
-        .await?;
-
-        if context.is_work_done() {
-            break;
+    let result = match input_source {
+        InputSource::Mmap(mmap) => {
+            let plan = ChunkPlan::new(effective_chunk_size, records);
+            run_mmap_pipeline(mmap, plan, output, scheduler, strategy).await
         }
-
-        if context.no_tasks_running_and_input_available() {
-            continue;
+        InputSource::Stdin(input_reader) | InputSource::Reader(input_reader) => {
+            run_stream_pipeline(
+                input_reader,
+                output,
+                effective_chunk_size,
+                ChunkReader::new(fill_chunks, records.cloned()),
+                scheduler,
+                strategy,
+            )
+            .await
         }
+    };
+    output.release_queue();
+    if let (Err(_), Some(metrics)) = (&result, &output.metrics) {
+        metrics.record_error();
+    }
+    result
+}
 
-        if await_and_process_task_result(&mut context, &mut results_rx, &mut output_writer).await? {
-            break;
+#[cfg(test)]
+mod tests {
+    use super::scheduler::PENDING_CHUNKS_PER_TASK;
+    use super::*;
+    use crate::error::BltError;
+    use crate::io_handler;
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::Notify;
+
+    fn scheduler(num_threads: usize) -> Arc<dyn Scheduler> {
+        Arc::new(FifoScheduler::new(num_threads, None))
+    }
+
+    #[tokio::test]
+    async fn test_strict_specials_reports_offsets() {
+        let mut writer: OutputWriter = Box::<Vec<u8>>::default();
+        let mut output = PipelineOutput::new(&mut writer, None, None).with_strict_specials(true);
+        output.write_chunk(b"ab", &[0, 97, 0, 98]).await.unwrap();
+        let err = output.check_chunk(&[0, 99, 0xFF, 0x01]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("0xff01 at output token 3"));
+        assert!(err.to_string().contains("byte offset 2"));
+    }
+
+    #[tokio::test]
+    async fn test_marks_entropy_regions() {
+        let (mut reader, writer) = tokio::io::duplex(1024);
+        let mut writer: OutputWriter = Box::new(writer);
+        let mut output = PipelineOutput::new(&mut writer, None, None)
+            .with_entropy_markers(Some(EntropyMarking { threshold: 1.5 }));
+        for chunk in [&b"aaaa"[..], b"abab", b"abcd", b"dcba", b"aaaa"] {
+            output.write_chunk(chunk, &[0, chunk[0]]).await.unwrap();
         }
+        assert_eq!(output.output_bytes, 16);
+        drop(output);
+        drop(writer);
+
+        // Entropies of 0, 1, 2, 2, and 0 bits: the third and fourth chunks look compressed.
+        let plain = special_tokens::PLAIN_REGION.to_be_bytes();
+        let compressed = special_tokens::COMPRESSED_REGION.to_be_bytes();
+        let expected = [
+            &plain[..],
+            &[0, b'a'],
+            &[0, b'a'],
+            &compressed,
+            &[0, b'a'],
+            &[0, b'd'],
+            &plain,
+            &[0, b'a'],
+        ]
+        .concat();
+        let mut written = Vec::new();
+        reader.read_to_end(&mut written).await.unwrap();
+        assert_eq!(written, expected);
+    }
 
-        if context.is_all_work_truly_done() {
-            break;
+    #[tokio::test]
+    async fn test_feeds_metrics() {
+        let metrics = Metrics::new();
+        let mut writer: OutputWriter = Box::<Vec<u8>>::default();
+        let mut output =
+            PipelineOutput::new(&mut writer, None, None).with_metrics(Some(metrics.clone()));
+        let input: io_handler::InputReader = Box::new(std::io::Cursor::new(vec![b'a'; 10]));
+        run(
+            InputSource::Reader(input),
+            &mut output,
+            4,
+            true,
+            None,
+            &scheduler(2),
+            Arc::new(crate::tokenizer::BasicTokenizationStrategy),
+        )
+        .await
+        .unwrap();
+
+        let rendered = metrics.render();
+        for line in [
+            "blt_chunks_total 3",
+            "blt_input_bytes_total 10",
+            "blt_output_bytes_total 20",
+            "blt_queued_chunks 0",
+            "blt_errors_total 0",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {line:?}");
         }
     }
 
-    drop(results_tx);
+    /// Finishes chunks starting with a lower byte later, so tasks complete out of order.
+    struct ReverseDelay;
 
-    finalize_results(&mut context, &mut results_rx, &mut output_writer).await?;
-    output_writer.flush().await?;
-    Ok(())
-}
+    #[async_trait::async_trait]
+    impl TokenizationStrategy for ReverseDelay {
+        async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+            let delay = u64::from(b'z' - chunk_data[0]);
+            tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+            Ok(chunk_data.to_vec())
+        }
+    }
 
-// --- Private Structs and Functions ---
+    #[tokio::test]
+    async fn test_writes_in_order_when_tasks_finish_out_of_order() {
+        let data: Vec<u8> = (b'a'..=b'p').collect();
+        let (mut reader, writer) = tokio::io::duplex(1024);
+        let mut writer: OutputWriter = Box::new(writer);
+        let mut output = PipelineOutput::new(&mut writer, None, None);
+        let input: io_handler::InputReader = Box::new(std::io::Cursor::new(data.clone()));
+        run(
+            InputSource::Reader(input),
+            &mut output,
+            2,
+            true,
+            None,
+            &scheduler(4),
+            Arc::new(ReverseDelay),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.chunks, 8);
+        drop(output);
+        drop(writer);
+
+        let mut written = Vec::new();
+        reader.read_to_end(&mut written).await.unwrap();
+        assert_eq!(written, data);
+    }
 
-/// Holds the state for the duration of the processing loop.
-struct ProcessingContext {
-    next_chunk_id: usize,
-    dispatched_task_handles: HashMap<usize, tokio::task::JoinHandle<()>>,
-    received_results: HashMap<usize, io::Result<Vec<u8>>>,
-    current_expected_chunk_id: usize,
-    input_eof: bool,
-}
+    /// Fails every chunk containing an `x`.
+    struct FailOnX;
 
-impl ProcessingContext {
-    fn new() -> Self {
-        Self {
-            next_chunk_id: 0,
-            dispatched_task_handles: HashMap::new(),
-            received_results: HashMap::new(),
-            current_expected_chunk_id: 0,
-            input_eof: false,
+    #[async_trait::async_trait]
+    impl TokenizationStrategy for FailOnX {
+        async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+            match chunk_data.contains(&b'x') {
+                true => Err(io::Error::other("bad chunk")),
+                false => Ok(chunk_data.to_vec()),
+            }
         }
     }
-    fn is_work_done(&self) -> bool {
-        self.input_eof && self.dispatched_task_handles.is_empty()
+
+    #[tokio::test]
+    async fn test_skips_failed_chunks() {
+        let data = b"aabbxxccxd".to_vec();
+        let run_with = |skip_errors: bool| {
+            let data = data.clone();
+            async move {
+                let mut writer: OutputWriter = Box::<Vec<u8>>::default();
+                let metrics = Metrics::new();
+                let mut output = PipelineOutput::new(&mut writer, None, None)
+                    .with_skip_errors(skip_errors)
+                    .with_metrics(Some(metrics.clone()));
+                let result = run(
+                    InputSource::Reader(Box::new(std::io::Cursor::new(data))),
+                    &mut output,
+                    2,
+                    true,
+                    None,
+                    &scheduler(2),
+                    Arc::new(FailOnX),
+                )
+                .await;
+                (result, output.into_summary(), metrics)
+            }
+        };
+
+        let (result, _, _) = run_with(false).await;
+        let error = BltError::from(result.unwrap_err());
+        assert_eq!(error.to_string(), "bad chunk");
+        assert!(matches!(error, BltError::Strategy(_)));
+
+        let (result, summary, metrics) = run_with(true).await;
+        result.unwrap();
+        assert_eq!(summary.chunks, 3);
+        assert_eq!(summary.input_bytes, 6);
+        let skipped: Vec<_> = summary
+            .skipped
+            .iter()
+            .map(|s| (s.offset, s.bytes, s.error.as_str()))
+            .collect();
+        assert_eq!(skipped, [(4, 2, "bad chunk"), (8, 2, "bad chunk")]);
+        assert!(metrics.render().contains("\nblt_errors_total 2\n"));
+    }
+
+    /// Holds up the first chunk until released; every later chunk finishes at once.
+    #[derive(Default)]
+    struct HeadOfLineBlocked {
+        started: std::sync::atomic::AtomicUsize,
+        release: Notify,
     }
-    fn no_tasks_running_and_input_available(&self) -> bool {
-        self.dispatched_task_handles.is_empty() && !self.input_eof
+
+    #[async_trait::async_trait]
+    impl TokenizationStrategy for HeadOfLineBlocked {
+        async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+            if self.started.fetch_add(1, Ordering::SeqCst) == 0 {
+                self.release.notified().await;
+            }
+            Ok(chunk_data.to_vec())
+        }
     }
-    fn is_all_work_truly_done(&self) -> bool {
-        self.input_eof
-            && self.dispatched_task_handles.is_empty()
-            && self.received_results.is_empty()
+
+    #[tokio::test]
+    async fn test_cancellation_ends_waiting_stream() {
+        let token = CancellationToken::new();
+        let mut writer: OutputWriter = Box::<Vec<u8>>::default();
+        let mut output =
+            PipelineOutput::new(&mut writer, None, None).with_cancellation(Some(token.clone()));
+        // The input's other end stays open, so the stream never ends on its own.
+        let (mut input_end, input) = tokio::io::duplex(64);
+        input_end.write_all(b"ab").await.unwrap();
+        let scheduler = scheduler(2);
+        let pipeline = run(
+            InputSource::Reader(Box::new(input)),
+            &mut output,
+            1024,
+            false,
+            None,
+            &scheduler,
+            Arc::new(crate::tokenizer::BasicTokenizationStrategy),
+        );
+        let cancel = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            token.cancel();
+        };
+        let (result, ()) = tokio::join!(pipeline, cancel);
+        result.unwrap();
+        // The chunk read before the cancellation is still written.
+        assert_eq!(output.output_bytes, 4);
+        drop(input_end);
     }
-}
 
-/// Fills the worker pool with new tasks as long as there is capacity and input.
-#[instrument(skip_all)]
-async fn manage_task_spawning(
-    context: &mut ProcessingContext,
-    input_reader: &mut io_handler::InputReader,
-    effective_chunk_size: usize,
-    num_threads: usize,
-    strategy: Arc<dyn TokenizationStrategy>,
-    results_tx_clone: mpsc::Sender<(usize, io::Result<Vec<u8>>)>,
-) -> io::Result<()> {
-    while !context.input_eof && context.dispatched_task_handles.len() < num_threads {
-        if !try_read_and_spawn_task(
-            context,
-            input_reader,
-            effective_chunk_size,
+    #[tokio::test]
+    async fn test_pauses_dispatch_behind_slow_chunk() {
+        let strategy = Arc::new(HeadOfLineBlocked::default());
+        let mut writer: OutputWriter = Box::<Vec<u8>>::default();
+        let mut output = PipelineOutput::new(&mut writer, None, None);
+        let input: io_handler::InputReader = Box::new(std::io::Cursor::new(vec![b'a'; 100]));
+        let scheduler = scheduler(2);
+        let pipeline = run(
+            InputSource::Reader(input),
+            &mut output,
+            1,
+            true,
+            None,
+            &scheduler,
             strategy.clone(),
-            results_tx_clone.clone(),
-        )
-        .await?
-        {
-            break;
-        }
+        );
+        let blocked = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            // The blocked chunk, plus those waiting behind it.
+            let started = strategy.started.load(Ordering::SeqCst);
+            strategy.release.notify_one();
+            started
+        };
+        let (result, started) = tokio::join!(pipeline, blocked);
+        result.unwrap();
+        assert!(
+            started <= 1 + 2 * PENDING_CHUNKS_PER_TASK,
+            "{started} chunks started"
+        );
+        assert_eq!(output.chunks, 100);
     }
-    Ok(())
-}
 
-/// Reads a single chunk and spawns a processing task for it.
-async fn try_read_and_spawn_task(
-    context: &mut ProcessingContext,
-    input_reader: &mut io_handler::InputReader,
-    effective_chunk_size: usize,
-    strategy: Arc<dyn TokenizationStrategy>,
-    results_tx: mpsc::Sender<(usize, io::Result<Vec<u8>>)>,
-) -> io::Result<bool> {
-    let mut chunk_buffer = vec![0; effective_chunk_size];
-    let bytes_read = input_reader.read(&mut chunk_buffer).await?;
-
-    if bytes_read == 0 {
-        context.input_eof = true;
-        debug!("Input stream reached EOF");
-        return Ok(false);
-    }
-    chunk_buffer.truncate(bytes_read);
-
-    let task_id = context.next_chunk_id;
-    context.next_chunk_id += 1;
-
-    debug!(
-        task_id,
-        bytes = bytes_read,
-        "Spawning chunk processing task"
-    );
-    let handle = spawn_chunk_processing_task(task_id, chunk_buffer, strategy, results_tx);
-    context.dispatched_task_handles.insert(task_id, handle);
-    Ok(true)
-}
+    /// Admits chunks one at a time, counting them.
+    #[derive(Debug)]
+    struct CountingScheduler {
+        inner: FifoScheduler,
+        admitted: std::sync::atomic::AtomicUsize,
+    }
 
-/// Spawns a Tokio task to process a single chunk.
-#[instrument(skip_all)]
-fn spawn_chunk_processing_task(
-    task_id: usize,
-    chunk_buffer: Vec<u8>,
-    strategy: Arc<dyn TokenizationStrategy>,
-    results_tx: mpsc::Sender<(usize, io::Result<Vec<u8>>)>,
-) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(
-        async move {
-            let result = strategy.process_chunk(&chunk_buffer).await;
-            if results_tx.send((task_id, result)).await.is_err() {
-                error!(task_id, "Failed to send result: receiver dropped.");
-            }
+    #[async_trait::async_trait]
+    impl Scheduler for CountingScheduler {
+        fn window(&self) -> usize {
+            self.inner.window()
+        }
+
+        async fn admit(&self, chunk_id: usize) {
+            self.inner.admit(chunk_id).await;
+            self.admitted.fetch_add(1, Ordering::SeqCst);
         }
-        .instrument(info_span!("process_chunk_task", task_id)),
-    )
-}
 
-/// Waits for a task result and processes it. Returns `true` if the main loop should break.
-async fn await_and_process_task_result(
-    context: &mut ProcessingContext,
-    results_rx: &mut mpsc::Receiver<(usize, io::Result<Vec<u8>>)>,
-    output_writer: &mut OutputWriter,
-) -> io::Result<bool> {
-    tokio::select! {
-        biased;
-        maybe_result = results_rx.recv(), if !context.dispatched_task_handles.is_empty() || context.input_eof => {
-            return process_received_results(context, maybe_result, output_writer).await;
+        fn finished(&self, chunk_id: usize) {
+            self.inner.finished(chunk_id);
         }
-        else => {
-            Ok(false)
+
+        fn waited(&self, chunk_id: usize, waited: std::time::Duration) {
+            self.inner.waited(chunk_id, waited);
+        }
+
+        fn written(&self, chunk_id: usize, writing: std::time::Duration) {
+            self.inner.written(chunk_id, writing);
         }
     }
-}
 
-/// Handles a received result from a task. Returns `true` if the main loop should break.
-async fn process_received_results(
-    context: &mut ProcessingContext,
-    maybe_result: Option<(usize, io::Result<Vec<u8>>)>,
-    output_writer: &mut OutputWriter,
-) -> io::Result<bool> {
-    match maybe_result {
-        Some((task_id, result)) => {
-            debug!(task_id, "Received result for task");
-            context.dispatched_task_handles.remove(&task_id);
-            context.received_results.insert(task_id, result);
-        }
-        None => {
-            debug!("Result channel disconnected, ending processing loop");
-            return Ok(true);
-        }
-    }
-    write_ordered_results(context, output_writer).await?;
-    Ok(false)
-}
+    #[tokio::test]
+    async fn test_scheduler_serves_consecutive_documents() {
+        let counting = Arc::new(CountingScheduler {
+            inner: FifoScheduler::new(1, None),
+            admitted: Default::default(),
+        });
+        let scheduler: Arc<dyn Scheduler> = counting.clone();
+        for _ in 0..3 {
+            let mut writer: OutputWriter = Box::<Vec<u8>>::default();
+            let mut output = PipelineOutput::new(&mut writer, None, None);
+            let input: io_handler::InputReader = Box::new(std::io::Cursor::new(vec![b'a'; 4]));
+            run(
+                InputSource::Reader(input),
+                &mut output,
+                2,
+                true,
+                None,
+                &scheduler,
+                Arc::new(crate::tokenizer::BasicTokenizationStrategy),
+            )
+            .await
+            .unwrap();
+            assert_eq!(output.chunks, 2);
+        }
+        // Two chunks per document, and the admission that finds the end of the input.
+        assert_eq!(counting.admitted.load(Ordering::SeqCst), 9);
+    }
 
-/// Writes any completed and ordered chunks to the output.
-async fn write_ordered_results(
-    context: &mut ProcessingContext,
-    output_writer: &mut OutputWriter,
-) -> io::Result<()> {
-    while let Some(result_data) = context
-        .received_results
-        .remove(&context.current_expected_chunk_id)
-    {
-        match result_data {
-            Ok(chunk_data) => {
-                debug!(
-                    chunk_id = context.current_expected_chunk_id,
-                    bytes = chunk_data.len(),
-                    "Writing ordered chunk to output"
-                );
-                output_writer.write_all(&chunk_data).await?
-            }
-            Err(e) => {
-                error!(
-                    chunk_id = context.current_expected_chunk_id,
-                    "Error in processed chunk: {:?}", e
-                );
-                return Err(e);
-            }
+    /// Records the length of every chunk it is given.
+    #[derive(Default)]
+    struct ChunkLengths(std::sync::Mutex<Vec<usize>>);
+
+    #[async_trait::async_trait]
+    impl TokenizationStrategy for ChunkLengths {
+        async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+            self.0.lock().unwrap().push(chunk_data.len());
+            Ok(Vec::new())
         }
-        context.current_expected_chunk_id += 1;
     }
-    Ok(())
-}
 
-/// Ensures any remaining results in the channel or context are processed and written.
-async fn finalize_results(
-    context: &mut ProcessingContext,
-    results_rx: &mut mpsc::Receiver<(usize, io::Result<Vec<u8>>)>,
-    output_writer: &mut OutputWriter,
-) -> io::Result<()> {
-    while let Some((task_id, result)) = results_rx.recv().await {
-        context.received_results.insert(task_id, result);
-        write_ordered_results(context, output_writer).await?;
+    #[tokio::test]
+    async fn test_chunks_align_to_records() {
+        let layout: RecordLayout = "len=u8".parse().unwrap();
+        // Records of 3, 2, 4, and 1 bytes, then one longer than a chunk.
+        let data = vec![
+            2, b'a', b'b', 1, b'c', 3, b'd', b'e', b'f', 0, 5, 1, 2, 3, 4, 5,
+        ];
+        let plan = |records| {
+            let mut plan = ChunkPlan::new(4, records);
+            std::iter::from_fn(|| plan.next_chunk(&data)).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            plan(Some(&layout)),
+            [(0, 3), (3, 2), (5, 4), (9, 1), (10, 4), (14, 2)]
+        );
+        assert_eq!(plan(None), [(0, 4), (4, 4), (8, 4), (12, 4)]);
+
+        let strategy = Arc::new(ChunkLengths::default());
+        let mut writer: OutputWriter = Box::<Vec<u8>>::default();
+        let mut output = PipelineOutput::new(&mut writer, None, None);
+        let input: io_handler::InputReader = Box::new(std::io::Cursor::new(data));
+        run(
+            InputSource::Reader(input),
+            &mut output,
+            4,
+            false,
+            Some(&layout),
+            &scheduler(1),
+            strategy.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*strategy.0.lock().unwrap(), [3, 2, 4, 1, 4, 2]);
     }
-    write_ordered_results(context, output_writer).await?; // Final check
-    Ok(())
 }
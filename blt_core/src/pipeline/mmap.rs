@@ -0,0 +1,203 @@
+//! The pipeline for memory-mapped input files.
+
+use super::ordered::{run_ordered, spawn_chunk_task, OrderedSender};
+use super::prefault::Prefaulter;
+use super::{DispatchGauge, PipelineOutput, Scheduler};
+use crate::records::RecordLayout;
+use crate::tokenizer::TokenizationStrategy;
+use bytes::Bytes;
+use std::io;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Cuts mapped input into `(start, len)` chunks of at most `chunk_size` bytes, each ending on
+/// a record boundary when `records` is given and a whole record fits.
+///
+/// Chunks are planned one at a time as they are dispatched rather than all up front, so a
+/// multi-terabyte input with small chunks takes no memory to plan.
+pub(super) struct ChunkPlan {
+    chunk_size: usize,
+    records: Option<RecordLayout>,
+    /// Where the next chunk starts.
+    start: usize,
+}
+
+impl ChunkPlan {
+    pub(super) fn new(chunk_size: usize, records: Option<&RecordLayout>) -> Self {
+        Self {
+            chunk_size,
+            records: records.cloned(),
+            start: 0,
+        }
+    }
+
+    /// Plans the chunk of `data` following the last one, if any input remains.
+    pub(super) fn next_chunk(&mut self, data: &[u8]) -> Option<(usize, usize)> {
+        let start = self.start;
+        if start >= data.len() {
+            return None;
+        }
+        let end = (start + self.chunk_size).min(data.len());
+        let len = match &self.records {
+            Some(layout) if end < data.len() => match layout.whole_records(&data[start..end]) {
+                0 => end - start,
+                whole => whole,
+            },
+            _ => end - start,
+        };
+        self.start += len;
+        Some((start, len))
+    }
+}
+
+pub(super) async fn run_mmap_pipeline(
+    mmap: memmap2::Mmap,
+    plan: ChunkPlan,
+    output: &mut PipelineOutput<'_>,
+    scheduler: &Arc<dyn Scheduler>,
+    strategy: Arc<dyn TokenizationStrategy>,
+) -> io::Result<()> {
+    info!(
+        "Running pipeline in Mmap mode for file of size: {}",
+        mmap.len()
+    );
+    advise_mapped(&mmap, MapAdvice::Sequential, (0, mmap.len()));
+    let mmap = Arc::new(mmap);
+    let release = output.release_mapped;
+    let prefault = output.prefault;
+    let mut released = 0;
+    let gauge = output.gauge();
+    let dispatch_mmap = mmap.clone();
+    run_ordered(
+        output,
+        scheduler,
+        move |scheduler, ordered_tx| {
+            dispatch_mmap_chunks(
+                dispatch_mmap,
+                plan,
+                prefault,
+                strategy,
+                scheduler,
+                gauge,
+                ordered_tx,
+            )
+        },
+        // Chunks are written front to back, so they end where the input written so far ends.
+        |chunk: &[u8]| {
+            if release {
+                release_mapped(&mmap, released, released + chunk.len());
+            }
+            released += chunk.len();
+        },
+    )
+    .await
+}
+
+/// The mapped input, owning the memory behind the chunk slices handed to tasks.
+struct SharedMap(Arc<memmap2::Mmap>);
+
+impl AsRef<[u8]> for SharedMap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Spawns a task for every planned chunk of the mapped input, in order, as the scheduler
+/// admits them. Each task gets a slice of the map itself rather than a copy. With `prefault`,
+/// a [`Prefaulter`] touches the pages that far ahead meanwhile.
+async fn dispatch_mmap_chunks(
+    mmap: Arc<memmap2::Mmap>,
+    mut plan: ChunkPlan,
+    prefault: Option<usize>,
+    strategy: Arc<dyn TokenizationStrategy>,
+    scheduler: Arc<dyn Scheduler>,
+    gauge: DispatchGauge,
+    ordered_tx: OrderedSender,
+) -> io::Result<()> {
+    let mapped = Bytes::from_owner(SharedMap(mmap.clone()));
+    let prefaulter =
+        prefault.map(|distance| Prefaulter::spawn(mmap.clone(), distance, gauge.clone()));
+    let mut next = plan.next_chunk(&mmap);
+    if let Some(first) = next {
+        advise_mapped(&mmap, MapAdvice::WillNeed, first);
+    }
+    let mut chunk_id = 0;
+    while let Some((start, len)) = next {
+        let Ok(pending) = ordered_tx.reserve().await else {
+            break;
+        };
+        scheduler.admit(chunk_id).await;
+        if gauge.is_full() || gauge.is_cancelled() {
+            // The scheduler outlives the document, so the admitted chunk gives its slot back.
+            scheduler.finished(chunk_id);
+            break;
+        }
+        // Start reading the next chunk while this one is tokenized.
+        next = plan.next_chunk(&mmap);
+        if let Some(next) = next {
+            advise_mapped(&mmap, MapAdvice::WillNeed, next);
+        }
+        if let Some(prefaulter) = &prefaulter {
+            prefaulter.advance(start + len);
+        }
+        let chunk = mapped.slice(start..start + len);
+        let task = spawn_chunk_task(chunk_id, chunk, strategy.clone(), scheduler.clone());
+        gauge.enqueue(1);
+        pending.send(task);
+        chunk_id += 1;
+    }
+    if let Some(prefaulter) = prefaulter {
+        let bytes = prefaulter.finish().await;
+        debug!(bytes, "Prefaulted mapped input");
+    }
+    Ok(())
+}
+
+/// Paging hints for the mapped input.
+#[derive(Clone, Copy, Debug)]
+enum MapAdvice {
+    /// The input is read front to back: read ahead aggressively, and pages already read may
+    /// be freed early.
+    Sequential,
+    /// The range is about to be read: start reading it in now.
+    WillNeed,
+}
+
+/// Passes `advice` about a `(start, len)` range of the mapped input to the kernel. The advice
+/// only tunes paging, so failures are logged and otherwise ignored.
+#[cfg(unix)]
+fn advise_mapped(mmap: &memmap2::Mmap, advice: MapAdvice, (start, len): (usize, usize)) {
+    if len == 0 {
+        return;
+    }
+    let madvise = match advice {
+        MapAdvice::Sequential => memmap2::Advice::Sequential,
+        MapAdvice::WillNeed => memmap2::Advice::WillNeed,
+    };
+    if let Err(error) = mmap.advise_range(madvise, start, len) {
+        debug!(%error, ?advice, "madvise on the mapped input failed");
+    }
+}
+
+#[cfg(not(unix))]
+fn advise_mapped(_mmap: &memmap2::Mmap, _advice: MapAdvice, _range: (usize, usize)) {}
+
+/// Drops the pages of the mapped input between `start` and `end`, whose chunks are written.
+#[cfg(unix)]
+fn release_mapped(mmap: &memmap2::Mmap, start: usize, end: usize) {
+    if end <= start {
+        return;
+    }
+    // SAFETY: the map is a read-only shared map of a file, so dropping its pages only makes
+    // later accesses, such as a task reading a page the next chunk shares, fault them back in
+    // from the file.
+    let result = unsafe {
+        mmap.unchecked_advise_range(memmap2::UncheckedAdvice::DontNeed, start, end - start)
+    };
+    if let Err(error) = result {
+        debug!(%error, "madvise(MADV_DONTNEED) on the mapped input failed");
+    }
+}
+
+#[cfg(not(unix))]
+fn release_mapped(_mmap: &memmap2::Mmap, _start: usize, _end: usize) {}
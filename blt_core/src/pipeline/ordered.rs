@@ -0,0 +1,144 @@
+//! Ordered writing of chunk results.
+//!
+//! Chunks are reordered without any map keyed by chunk ID. The ordered channel carries each
+//! chunk task's handle in input order and acts as a sequence window: a task that finishes
+//! early keeps its result in its own handle until the writer reaches it, and the writer only
+//! ever waits on the head of the window. The window's capacity, set by the [`Scheduler`],
+//! bounds how many finished chunks can wait, in the mmap and stream pipelines alike.
+
+use super::{PipelineOutput, Scheduler};
+use crate::buffers;
+use crate::error::BltError;
+use crate::tokenizer::TokenizationStrategy;
+use bytes::Bytes;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info_span, warn, Instrument};
+
+/// The outcome of a chunk task: the input it consumed and the tokenized result.
+///
+/// The input is handed back so the writer can mirror, hash, and count the original bytes
+/// without keeping a separate copy.
+pub(super) struct ChunkResult {
+    original: Bytes,
+    tokens: io::Result<Bytes>,
+}
+
+/// Chunk tasks in input order, from the dispatch task to the writer.
+pub(super) type OrderedSender = mpsc::Sender<JoinHandle<ChunkResult>>;
+type OrderedReceiver = mpsc::Receiver<JoinHandle<ChunkResult>>;
+
+/// Spawns a Tokio task to tokenize a single chunk, telling `scheduler` once it is done.
+pub(super) fn spawn_chunk_task(
+    chunk_id: usize,
+    original: Bytes,
+    strategy: Arc<dyn TokenizationStrategy>,
+    scheduler: Arc<dyn Scheduler>,
+) -> JoinHandle<ChunkResult> {
+    tokio::spawn(
+        async move {
+            let tokens = strategy.process_bytes(original.clone()).await;
+            scheduler.finished(chunk_id);
+            ChunkResult { original, tokens }
+        }
+        .instrument(info_span!("process_chunk_task", chunk_id)),
+    )
+}
+
+/// Runs `dispatch` in its own task and writes the chunks it hands over, in order, as they
+/// complete, so reading and tokenizing continue while a slow write is in progress.
+///
+/// `dispatch` must reserve room in the ordered channel, then wait for the scheduler to
+/// [admit](Scheduler::admit) the chunk, before reading and spawning each chunk. The channel
+/// holds the scheduler's [window](Scheduler::window) of chunks, so once the writer falls that
+/// far behind a slow chunk, dispatch pauses until the head of the line is written. `on_written` is
+/// called with the input of every chunk once it is written; the input and the tokenized
+/// result then go back to the [`buffers::shared`] pool.
+pub(super) async fn run_ordered<D>(
+    output: &mut PipelineOutput<'_>,
+    scheduler: &Arc<dyn Scheduler>,
+    dispatch: impl FnOnce(Arc<dyn Scheduler>, OrderedSender) -> D,
+    on_written: impl FnMut(&[u8]),
+) -> io::Result<()>
+where
+    D: Future<Output = io::Result<()>> + Send + 'static,
+{
+    let (ordered_tx, ordered_rx) = mpsc::channel(scheduler.window().max(1));
+    let dispatcher = tokio::spawn(
+        dispatch(scheduler.clone(), ordered_tx).instrument(info_span!("dispatch_chunks")),
+    );
+    let written = write_ordered(ordered_rx, output, scheduler.as_ref(), on_written).await;
+    match written {
+        // The dispatcher may be waiting for a slot that will never be released.
+        Err(e) => {
+            dispatcher.abort();
+            Err(e)
+        }
+        // The channel closes once the dispatcher is done.
+        Ok(()) => dispatcher.await.map_err(task_failed)?,
+    }
+}
+
+/// Reports a chunk or dispatch task that panicked or was aborted.
+fn task_failed(error: tokio::task::JoinError) -> io::Error {
+    BltError::Pipeline(format!("Chunk task failed: {error}")).into()
+}
+
+/// Awaits each chunk task in input order and writes its result.
+async fn write_ordered(
+    mut ordered_rx: OrderedReceiver,
+    output: &mut PipelineOutput<'_>,
+    scheduler: &dyn Scheduler,
+    mut on_written: impl FnMut(&[u8]),
+) -> io::Result<()> {
+    let mut chunk_id = 0;
+    loop {
+        let waiting = Instant::now();
+        let Some(task) = ordered_rx.recv().await else {
+            break;
+        };
+        let result = task.await.map_err(task_failed)?;
+        scheduler.waited(chunk_id, waiting.elapsed());
+        let checked = result
+            .tokens
+            .and_then(|tokens| output.check_chunk(&tokens).map(|()| tokens));
+        let tokens = match checked {
+            Ok(tokens) => tokens,
+            Err(e) if output.skip_errors => {
+                warn!(
+                    chunk_id,
+                    offset = output.position,
+                    bytes = result.original.len(),
+                    "Skipping chunk that failed to tokenize: {e}"
+                );
+                output.skip_chunk(&result.original, &e);
+                on_written(&result.original);
+                buffers::shared().give_bytes(result.original);
+                chunk_id += 1;
+                continue;
+            }
+            Err(e) => {
+                error!(chunk_id, "Error in processed chunk: {:?}", e);
+                return Err(BltError::strategy(e));
+            }
+        };
+        debug!(
+            chunk_id,
+            bytes = tokens.len(),
+            "Writing ordered chunk to output"
+        );
+        let writing = Instant::now();
+        output.write_chunk(&result.original, &tokens).await?;
+        on_written(&result.original);
+        // The result may share the input's buffer, so the input goes first.
+        buffers::shared().give_bytes(result.original);
+        buffers::shared().give_bytes(tokens);
+        scheduler.written(chunk_id, writing.elapsed());
+        chunk_id += 1;
+    }
+    output.flush().await
+}
@@ -0,0 +1,123 @@
+//! Background prefaulting of memory-mapped input.
+//!
+//! Chunk tasks fault the pages of their chunk in as they read it, and on a cold file behind a
+//! network block device every fault waits for a round trip. `MADV_WILLNEED` on the next chunk
+//! is only a hint the kernel may act on lazily. A [`Prefaulter`] instead touches every page up
+//! to a fixed distance past the chunks dispatched so far, on a blocking thread, so the reads
+//! overlap with tokenization rather than stalling the workers.
+//!
+//! It stays within the run's budgets: it never gets further than the distance ahead of the
+//! dispatched chunks, so it holds at most that much extra input in memory, and it stops once
+//! the token budget is spent or the run is cancelled, so it never reads input the run will not
+//! tokenize.
+
+use super::DispatchGauge;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// The stride pages are touched with. Pages are at least this large, so every page is touched.
+const PAGE_SIZE: usize = 4096;
+
+/// The most bytes touched between checks of the budget and of how far dispatch has got.
+const BATCH_BYTES: usize = 1 << 20;
+
+/// Touches the pages of a mapped input ahead of the chunks dispatched from it.
+pub(super) struct Prefaulter {
+    /// The end of the input dispatched so far.
+    dispatched: watch::Sender<usize>,
+    /// The thread touching pages, returning how many bytes it touched.
+    thread: JoinHandle<usize>,
+}
+
+impl Prefaulter {
+    /// Starts touching the pages of `mmap` up to `distance` bytes past the dispatched input.
+    pub(super) fn spawn(mmap: Arc<memmap2::Mmap>, distance: usize, gauge: DispatchGauge) -> Self {
+        let (dispatched, mut position) = watch::channel(0usize);
+        let runtime = Handle::current();
+        let thread = tokio::task::spawn_blocking(move || {
+            let mut touched = 0;
+            let mut next = 0;
+            loop {
+                let start = *position.borrow_and_update();
+                // Input already dispatched is being read by its tasks.
+                next = next.max(start);
+                let end = start.saturating_add(distance).min(mmap.len());
+                while next < end {
+                    if gauge.is_full() || gauge.is_cancelled() {
+                        return touched;
+                    }
+                    let batch_end = (next + BATCH_BYTES).min(end);
+                    touch_pages(&mmap[next..batch_end]);
+                    touched += batch_end - next;
+                    next = batch_end;
+                }
+                if next >= mmap.len() || runtime.block_on(position.changed()).is_err() {
+                    return touched;
+                }
+            }
+        });
+        Self { dispatched, thread }
+    }
+
+    /// Records that the input up to `end` has been dispatched.
+    pub(super) fn advance(&self, end: usize) {
+        self.dispatched.send_replace(end);
+    }
+
+    /// Stops touching pages, returning how many bytes were touched.
+    pub(super) async fn finish(self) -> usize {
+        drop(self.dispatched);
+        self.thread.await.unwrap_or_default()
+    }
+}
+
+/// Reads a byte of every page of `bytes`, faulting it in.
+fn touch_pages(bytes: &[u8]) {
+    for offset in (0..bytes.len()).step_by(PAGE_SIZE) {
+        std::hint::black_box(bytes[offset]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::TokenBudget;
+    use std::sync::atomic::AtomicU64;
+
+    fn mapped(len: usize) -> Arc<memmap2::Mmap> {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(len as u64).unwrap();
+        Arc::new(unsafe { memmap2::Mmap::map(&file) }.unwrap())
+    }
+
+    fn gauge(budget: Option<Arc<TokenBudget>>) -> DispatchGauge {
+        DispatchGauge {
+            budget,
+            metrics: None,
+            progress: None,
+            queued: Arc::new(AtomicU64::new(0)),
+            cancellation: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stays_a_distance_ahead() {
+        let mmap = mapped(8 * BATCH_BYTES);
+        let prefaulter = Prefaulter::spawn(mmap.clone(), 2 * BATCH_BYTES, gauge(None));
+        prefaulter.advance(BATCH_BYTES);
+        // Never further than the distance past the furthest dispatched input.
+        assert!(prefaulter.finish().await <= 3 * BATCH_BYTES);
+
+        let prefaulter = Prefaulter::spawn(mmap.clone(), usize::MAX, gauge(None));
+        assert_eq!(prefaulter.finish().await, mmap.len());
+    }
+
+    #[tokio::test]
+    async fn test_stops_once_budget_is_spent() {
+        let spent = TokenBudget::new(0, 2);
+        let prefaulter = Prefaulter::spawn(mapped(BATCH_BYTES), usize::MAX, gauge(Some(spent)));
+        assert_eq!(prefaulter.finish().await, 0);
+    }
+}
@@ -0,0 +1,186 @@
+//! Adaptive scaling of the number of chunks processed concurrently.
+//!
+//! The pipeline keeps at most [`Concurrency::limit`] chunk tasks in flight. Without a
+//! [`ThreadScaling`] policy the limit is fixed at the configured thread count. With one, the
+//! limit is re-evaluated after every window of written chunks:
+//!
+//! - If the writer spent longer writing than waiting for results, workers are ahead of it and
+//!   the limit shrinks, freeing CPU and memory for the writer.
+//! - If the writer mostly waited for results and the process is not already using nearly
+//!   every core, the limit grows.
+
+use crate::usage;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Process CPU utilization, as a fraction of all cores, above which the limit stops growing.
+const SATURATED_UTILIZATION: f64 = 0.9;
+
+/// Bounds for adaptively scaling the number of concurrent chunk tasks during a run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadScaling {
+    /// The fewest concurrent chunk tasks.
+    pub min: usize,
+    /// The most concurrent chunk tasks.
+    pub max: usize,
+}
+
+impl ThreadScaling {
+    /// Scales between `min` and `max` concurrent chunk tasks.
+    pub fn new(min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        Self {
+            min,
+            max: max.max(min),
+        }
+    }
+}
+
+/// The current limit on concurrent chunk tasks and what it is adjusted from.
+#[derive(Debug)]
+pub(crate) struct Concurrency {
+    limit: usize,
+    scaling: Option<ThreadScaling>,
+    window: Window,
+}
+
+/// What the writer observed since the limit was last evaluated.
+#[derive(Debug)]
+struct Window {
+    chunks: usize,
+    waiting: Duration,
+    writing: Duration,
+    started: Instant,
+    cpu_started: Option<Duration>,
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            chunks: 0,
+            waiting: Duration::ZERO,
+            writing: Duration::ZERO,
+            started: Instant::now(),
+            cpu_started: usage::process_cpu_time(),
+        }
+    }
+
+    /// Process CPU time over the window divided by the CPU time all cores could provide.
+    fn utilization(&self) -> Option<f64> {
+        let cpu = usage::process_cpu_time()?.saturating_sub(self.cpu_started?);
+        let available = self.started.elapsed().as_secs_f64() * num_cpus::get() as f64;
+        (available > 0.0).then(|| cpu.as_secs_f64() / available)
+    }
+}
+
+impl Concurrency {
+    /// Starts at `num_threads`, clamped to the bounds of `scaling` if one is given.
+    pub(crate) fn new(num_threads: usize, scaling: Option<ThreadScaling>) -> Self {
+        let limit = match scaling {
+            Some(scaling) => num_threads.clamp(scaling.min, scaling.max),
+            None => num_threads.max(1),
+        };
+        Self {
+            limit,
+            scaling,
+            window: Window::new(),
+        }
+    }
+
+    /// The maximum number of chunk tasks to keep in flight.
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The largest limit this run may reach, used to size channels.
+    pub(crate) fn max_limit(&self) -> usize {
+        self.scaling.map_or(self.limit, |scaling| scaling.max)
+    }
+
+    /// Records time the writer spent waiting for a chunk result.
+    pub(crate) fn record_wait(&mut self, waited: Duration) {
+        self.window.waiting += waited;
+    }
+
+    /// Records that the writer wrote `chunks` chunks, taking `elapsed`.
+    pub(crate) fn record_writes(&mut self, chunks: usize, elapsed: Duration) {
+        self.window.chunks += chunks;
+        self.window.writing += elapsed;
+        if self.scaling.is_some() && self.window.chunks >= self.limit.max(4) {
+            let utilization = self.window.utilization();
+            self.adjust(utilization);
+        }
+    }
+
+    /// Re-evaluates the limit from the current window and starts a new one.
+    fn adjust(&mut self, utilization: Option<f64>) {
+        let Some(scaling) = self.scaling else {
+            return;
+        };
+        let previous = self.limit;
+        if self.window.writing > self.window.waiting {
+            self.limit = self.limit.saturating_sub(1).max(scaling.min);
+        } else if utilization.is_none_or(|u| u < SATURATED_UTILIZATION) {
+            self.limit = (self.limit + 1).min(scaling.max);
+        }
+        if self.limit != previous {
+            debug!(
+                previous,
+                limit = self.limit,
+                waiting_ms = self.window.waiting.as_millis() as u64,
+                writing_ms = self.window.writing.as_millis() as u64,
+                utilization,
+                "Adjusted concurrent chunk tasks"
+            );
+        }
+        self.window = Window::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(concurrency: &mut Concurrency, waiting: u64, writing: u64) {
+        concurrency.window.waiting = Duration::from_millis(waiting);
+        concurrency.window.writing = Duration::from_millis(writing);
+    }
+
+    #[test]
+    fn test_shrinks_when_writer_bound() {
+        let mut concurrency = Concurrency::new(4, Some(ThreadScaling::new(2, 8)));
+        for _ in 0..5 {
+            window(&mut concurrency, 1, 10);
+            concurrency.adjust(Some(0.5));
+        }
+        assert_eq!(concurrency.limit(), 2);
+    }
+
+    #[test]
+    fn test_grows_until_saturated() {
+        let mut concurrency = Concurrency::new(4, Some(ThreadScaling::new(2, 8)));
+        window(&mut concurrency, 10, 1);
+        concurrency.adjust(Some(0.5));
+        assert_eq!(concurrency.limit(), 5);
+        window(&mut concurrency, 10, 1);
+        concurrency.adjust(Some(0.95));
+        assert_eq!(concurrency.limit(), 5);
+        for _ in 0..5 {
+            window(&mut concurrency, 10, 1);
+            concurrency.adjust(None);
+        }
+        assert_eq!(concurrency.limit(), 8);
+        assert_eq!(concurrency.max_limit(), 8);
+    }
+
+    #[test]
+    fn test_fixed_without_scaling() {
+        let mut concurrency = Concurrency::new(3, None);
+        concurrency.record_writes(100, Duration::from_secs(1));
+        assert_eq!(concurrency.limit(), 3);
+        assert_eq!(
+            Concurrency::new(16, Some(ThreadScaling::new(1, 4))).limit(),
+            4
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! The policy deciding when chunks are tokenized.
+//!
+//! The pipeline reads every document's chunks in input order and writes their results in input
+//! order. A [`Scheduler`] decides what happens in between: when each chunk may start
+//! tokenizing, which bounds how many are in flight, and how many dispatched chunks may wait to
+//! be written behind a slow one, which bounds the reorder window and the memory it holds. One
+//! scheduler serves every document of a run, so it can carry state, such as an adapted
+//! concurrency limit, from one document to the next.
+//!
+//! [`FifoScheduler`] is the default: chunks start in input order as soon as fewer than the
+//! concurrency limit are in flight, and with a [`ThreadScaling`] policy the limit follows
+//! whether the writer or the workers are behind. Alternative policies, such as ones weighing
+//! documents by priority or deadline, implement [`Scheduler`] and are passed to a run with
+//! [`CoreConfig::with_scheduler`](crate::CoreConfig::with_scheduler).
+
+use super::scaling::Concurrency;
+use super::ThreadScaling;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How many chunks per concurrent chunk task may wait to be written.
+///
+/// When an early chunk is slow, later chunks keep finishing; their inputs and results wait
+/// until it is written. Capping them keeps memory bounded by the chunk size, whatever the
+/// head-of-line chunk's delay.
+pub(crate) const PENDING_CHUNKS_PER_TASK: usize = 2;
+
+/// Decides when the chunks of a run are tokenized.
+///
+/// The pipeline calls [`admit`](Scheduler::admit) before reading or spawning each chunk, and
+/// [`finished`](Scheduler::finished) once its task is done, so admission bounds both the
+/// chunks in flight and the input read ahead. The writer reports how long it waited for each
+/// chunk and how long writing it took, which adaptive policies can act on.
+#[async_trait::async_trait]
+pub trait Scheduler: fmt::Debug + Send + Sync {
+    /// The most chunks of a document that may be dispatched but not yet written. Chunks that
+    /// finish ahead of a slower one wait within this window, and dispatch pauses while it is
+    /// full. Read once per document.
+    fn window(&self) -> usize;
+
+    /// Waits until chunk `chunk_id` of the current document may start tokenizing.
+    async fn admit(&self, chunk_id: usize);
+
+    /// Records that chunk `chunk_id` finished tokenizing, successfully or not, or that it was
+    /// admitted but never dispatched because the input or the run ended.
+    fn finished(&self, chunk_id: usize);
+
+    /// Records that the writer waited `waited` for chunk `chunk_id` to finish.
+    fn waited(&self, chunk_id: usize, waited: Duration);
+
+    /// Records that writing chunk `chunk_id` took `writing`. Chunks skipped after failing to
+    /// tokenize are not written.
+    fn written(&self, chunk_id: usize, writing: Duration);
+}
+
+/// The default [`Scheduler`]: chunks start in input order while fewer than the concurrency
+/// limit are in flight.
+#[derive(Debug)]
+pub struct FifoScheduler {
+    concurrency: Mutex<Concurrency>,
+    in_flight: InFlight,
+}
+
+impl FifoScheduler {
+    /// Keeps `num_threads` chunks in flight, scaled between the bounds of `scaling` if given.
+    pub fn new(num_threads: usize, scaling: Option<ThreadScaling>) -> Self {
+        let concurrency = Concurrency::new(num_threads, scaling);
+        Self {
+            in_flight: InFlight::new(concurrency.limit()),
+            concurrency: Mutex::new(concurrency),
+        }
+    }
+
+    /// The current limit on chunks in flight.
+    pub fn limit(&self) -> usize {
+        self.concurrency().limit()
+    }
+
+    fn concurrency(&self) -> std::sync::MutexGuard<'_, Concurrency> {
+        self.concurrency.lock().expect("concurrency lock poisoned")
+    }
+}
+
+#[async_trait::async_trait]
+impl Scheduler for FifoScheduler {
+    fn window(&self) -> usize {
+        self.concurrency().max_limit() * PENDING_CHUNKS_PER_TASK
+    }
+
+    async fn admit(&self, _chunk_id: usize) {
+        self.in_flight.acquire().await;
+    }
+
+    fn finished(&self, _chunk_id: usize) {
+        self.in_flight.release();
+    }
+
+    fn waited(&self, _chunk_id: usize, waited: Duration) {
+        self.concurrency().record_wait(waited);
+    }
+
+    fn written(&self, _chunk_id: usize, writing: Duration) {
+        let mut concurrency = self.concurrency();
+        concurrency.record_writes(1, writing);
+        self.in_flight.set_limit(concurrency.limit());
+    }
+}
+
+/// The chunks being tokenized, bounded by the current concurrency limit.
+#[derive(Debug)]
+struct InFlight {
+    state: Mutex<(usize, usize)>,
+    released: Notify,
+}
+
+impl InFlight {
+    fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new((0, limit)),
+            released: Notify::new(),
+        }
+    }
+
+    /// Waits until fewer chunks than the limit are in flight, then counts one more.
+    async fn acquire(&self) {
+        loop {
+            {
+                let mut state = self.state.lock().expect("in-flight lock poisoned");
+                let (count, limit) = &mut *state;
+                if *count < *limit {
+                    *count += 1;
+                    return;
+                }
+            }
+            self.released.notified().await;
+        }
+    }
+
+    /// Counts a chunk as tokenized.
+    fn release(&self) {
+        let mut state = self.state.lock().expect("in-flight lock poisoned");
+        state.0 = state.0.saturating_sub(1);
+        drop(state);
+        self.released.notify_one();
+    }
+
+    /// Applies a new concurrency limit.
+    fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().expect("in-flight lock poisoned");
+        let raised = limit > state.1;
+        state.1 = limit;
+        drop(state);
+        if raised {
+            self.released.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fifo_admits_up_to_limit() {
+        let scheduler = FifoScheduler::new(2, None);
+        assert_eq!(scheduler.window(), 2 * PENDING_CHUNKS_PER_TASK);
+        scheduler.admit(0).await;
+        scheduler.admit(1).await;
+        let blocked = tokio::time::timeout(Duration::from_millis(20), scheduler.admit(2)).await;
+        assert!(blocked.is_err(), "admitted past the limit");
+        scheduler.finished(0);
+        scheduler.admit(2).await;
+    }
+}
@@ -0,0 +1,185 @@
+//! The pipeline for streamed input: standard input, pipes, and files read without mapping.
+
+use super::ordered::{run_ordered, spawn_chunk_task, OrderedSender};
+use super::{DispatchGauge, PipelineOutput, Scheduler};
+use crate::buffers;
+use crate::io_handler;
+use crate::records::RecordLayout;
+use crate::tokenizer::TokenizationStrategy;
+use bytes::Bytes;
+use std::io;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tracing::{debug, info};
+
+pub(super) async fn run_stream_pipeline(
+    input_reader: io_handler::InputReader,
+    output: &mut PipelineOutput<'_>,
+    effective_chunk_size: usize,
+    reader: ChunkReader,
+    scheduler: &Arc<dyn Scheduler>,
+    strategy: Arc<dyn TokenizationStrategy>,
+) -> io::Result<()> {
+    info!("Running pipeline in Stream mode for stdin");
+    let gauge = output.gauge();
+    run_ordered(
+        output,
+        scheduler,
+        move |scheduler, ordered_tx| {
+            dispatch_stream_chunks(
+                input_reader,
+                reader,
+                effective_chunk_size,
+                strategy,
+                scheduler,
+                gauge,
+                ordered_tx,
+            )
+        },
+        |_: &[u8]| {},
+    )
+    .await
+}
+
+/// Reads chunks from the stream and spawns a task for each, in order, as the scheduler
+/// admits them. Stops early once the output is full or the run is cancelled.
+async fn dispatch_stream_chunks(
+    mut input_reader: io_handler::InputReader,
+    mut reader: ChunkReader,
+    effective_chunk_size: usize,
+    strategy: Arc<dyn TokenizationStrategy>,
+    scheduler: Arc<dyn Scheduler>,
+    gauge: DispatchGauge,
+    ordered_tx: OrderedSender,
+) -> io::Result<()> {
+    let mut chunk_id = 0;
+    loop {
+        // Wait for the writer to catch up before reading another chunk.
+        let Ok(pending) = ordered_tx.reserve().await else {
+            break;
+        };
+        scheduler.admit(chunk_id).await;
+        let chunk = match gauge.is_full() || gauge.is_cancelled() {
+            true => Ok(None),
+            // A stream may wait indefinitely for input, so cancelling abandons the read.
+            false => tokio::select! {
+                chunk = reader.next_chunk(&mut input_reader, effective_chunk_size) => chunk,
+                _ = gauge.cancelled() => Ok(None),
+            },
+        };
+        let chunk = match chunk {
+            Ok(Some(chunk)) => chunk,
+            // The scheduler outlives the document, so the admitted chunk gives its slot back.
+            Ok(None) => {
+                scheduler.finished(chunk_id);
+                break;
+            }
+            Err(e) => {
+                scheduler.finished(chunk_id);
+                return Err(e);
+            }
+        };
+        debug!(
+            chunk_id,
+            bytes = chunk.len(),
+            "Spawning chunk processing task"
+        );
+        let task = spawn_chunk_task(
+            chunk_id,
+            Bytes::from(chunk),
+            strategy.clone(),
+            scheduler.clone(),
+        );
+        gauge.enqueue(1);
+        pending.send(task);
+        chunk_id += 1;
+    }
+    Ok(())
+}
+
+/// Cuts a stream into chunks.
+pub(super) struct ChunkReader {
+    input_eof: bool,
+    fill_chunks: bool,
+    /// The layout chunks are aligned to, if any.
+    records: Option<RecordLayout>,
+    /// Input read past the last whole record of the previous chunk.
+    carry: Vec<u8>,
+}
+
+impl ChunkReader {
+    pub(super) fn new(fill_chunks: bool, records: Option<RecordLayout>) -> Self {
+        Self {
+            input_eof: false,
+            fill_chunks,
+            records,
+            carry: Vec::new(),
+        }
+    }
+
+    /// Reads the next chunk, or `None` once the input is exhausted.
+    async fn next_chunk(
+        &mut self,
+        input_reader: &mut io_handler::InputReader,
+        effective_chunk_size: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        if self.input_eof && self.carry.is_empty() {
+            return Ok(None);
+        }
+        let mut chunk_buffer = buffers::shared().take(effective_chunk_size.max(self.carry.len()));
+        chunk_buffer.append(&mut self.carry);
+        let mut filled = chunk_buffer.len();
+        chunk_buffer.resize(effective_chunk_size.max(filled), 0);
+        while !self.input_eof {
+            let bytes_read = if self.fill_chunks {
+                read_full(input_reader, &mut chunk_buffer[filled..]).await?
+            } else {
+                input_reader.read(&mut chunk_buffer[filled..]).await?
+            };
+            if bytes_read == 0 {
+                self.input_eof = true;
+                debug!("Input stream reached EOF");
+                break;
+            }
+            filled += bytes_read;
+            // Aligned chunks wait for a whole record, unless it cannot fit.
+            match &self.records {
+                Some(layout)
+                    if filled < chunk_buffer.len()
+                        && layout.whole_records(&chunk_buffer[..filled]) == 0 => {}
+                _ => break,
+            }
+        }
+
+        if filled == 0 {
+            buffers::shared().give(chunk_buffer);
+            return Ok(None);
+        }
+        chunk_buffer.truncate(filled);
+        if let (Some(layout), false) = (&self.records, self.input_eof) {
+            match layout.whole_records(&chunk_buffer) {
+                0 => {}
+                whole => {
+                    self.carry.extend_from_slice(&chunk_buffer[whole..]);
+                    chunk_buffer.truncate(whole);
+                }
+            }
+        }
+        Ok(Some(chunk_buffer))
+    }
+}
+
+/// Reads until `buffer` is full or the input ends, returning the number of bytes read.
+async fn read_full(
+    input_reader: &mut io_handler::InputReader,
+    buffer: &mut [u8],
+) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input_reader.read(&mut buffer[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
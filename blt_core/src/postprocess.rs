@@ -0,0 +1,457 @@
+//! Token-level post-processing.
+//!
+//! Post-processors operate on whole documents of tokens after the pipeline has produced the
+//! ordered token stream and before it reaches the output sink. [`PostProcessWriter`] adapts a
+//! chain of [`PostProcessor`]s to the `AsyncWrite` interface the pipeline writes to: it decodes
+//! the big-endian `u16` stream, splits it into documents after each newline or document
+//! separator token, runs every
+//! document through the chain, and re-encodes the documents the chain emits. When an index is
+//! requested, every emitted document is recorded in a [`DocumentIndex`].
+//!
+//! Inside the chain, documents of a multi-document run are always separated by
+//! [`special_tokens::SEPARATOR`]. When a different document separator is configured, a final
+//! stage relabels every separator token on the way out. Likewise, newlines are always
+//! [`NEWLINE_TOKEN`] inside the chain; with a byte token offset, the writer translates the
+//! shifted newline token on the way in and back on the way out.
+
+use crate::index::DocumentIndex;
+use crate::io_handler::OutputWriter;
+use crate::tokenizer::TokenDecoder;
+use crate::{special_tokens, ContentTypeFraming, CoreConfig, OutputFormat, StrategyKind};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+
+mod framing;
+mod log_records;
+mod packing;
+mod pad_to;
+mod shifted_pairs;
+mod span_corruption;
+mod truncation;
+pub use framing::ContentTypeFramingProcessor;
+pub use log_records::{LogRecordPolicy, LogRecordProcessor, Severity, SEVERITY_SCAN_BYTES};
+pub use packing::{PackingPolicy, PackingProcessor, PackingStats};
+pub use pad_to::{PadToPolicy, PadToProcessor};
+pub use shifted_pairs::{ShiftedPairsPolicy, ShiftedPairsProcessor};
+pub use span_corruption::{SpanCorruptionPolicy, SpanCorruptionProcessor, SpanLength};
+pub use truncation::{TruncationMode, TruncationPolicy, TruncationProcessor};
+
+/// The token a newline byte maps to in the basic and BPE strategies.
+pub const NEWLINE_TOKEN: u16 = b'\n' as u16;
+
+/// Returns `true` for tokens that end a document: a newline, the separator written between
+/// the documents of a multi-document run, or the separator ending a log record.
+pub fn is_terminator(token: u16) -> bool {
+    token == NEWLINE_TOKEN
+        || token == special_tokens::SEPARATOR
+        || token == special_tokens::RECORD_SEPARATOR
+}
+
+/// Removes and returns the document's terminator, if it has one.
+pub(crate) fn pop_terminator(document: &mut Vec<u16>) -> Option<u16> {
+    let terminator = document.last().copied().filter(|&t| is_terminator(t))?;
+    document.pop();
+    Some(terminator)
+}
+
+/// Once this many encoded bytes are pending, new input waits for the inner writer to drain.
+const PENDING_HIGH_WATER: usize = 1 << 20;
+
+/// A stage that transforms documents of tokens.
+///
+/// Documents handed to the first stage end with their terminator (see [`is_terminator`]), if
+/// they had one.
+pub trait PostProcessor: Send {
+    /// Processes one complete document, appending the documents to emit to `out`.
+    fn process_document(&mut self, document: Vec<u16>, out: &mut Vec<Vec<u16>>);
+
+    /// Flushes any buffered state at the end of the stream.
+    fn finish(&mut self, _out: &mut Vec<Vec<u16>>) {}
+}
+
+/// Shared handles to state the post-processors publish while the output is written.
+#[derive(Default)]
+pub(crate) struct PostProcessHandles {
+    pub(crate) packing_stats: Option<Arc<Mutex<PackingStats>>>,
+    pub(crate) index: Option<Arc<Mutex<DocumentIndex>>>,
+}
+
+/// An `AsyncWrite` adapter that runs the token stream through a chain of post-processors.
+pub struct PostProcessWriter {
+    inner: OutputWriter,
+    processors: Vec<Box<dyn PostProcessor>>,
+    carry: Option<u8>,
+    document: Vec<u16>,
+    newline: u16,
+    index: Option<Arc<Mutex<DocumentIndex>>>,
+    pending: Vec<u8>,
+    written: usize,
+    finished: bool,
+}
+
+impl PostProcessWriter {
+    /// Wraps `inner` with the given processors, applied in order.
+    pub fn new(inner: OutputWriter, processors: Vec<Box<dyn PostProcessor>>) -> Self {
+        Self {
+            inner,
+            processors,
+            carry: None,
+            document: Vec::new(),
+            newline: NEWLINE_TOKEN,
+            index: None,
+            pending: Vec::new(),
+            written: 0,
+            finished: false,
+        }
+    }
+
+    /// Records every emitted document in `index`.
+    pub fn with_index(mut self, index: Arc<Mutex<DocumentIndex>>) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Treats `newline` as the newline token of the stream, for shifted byte tokens.
+    pub fn with_newline(mut self, newline: u16) -> Self {
+        self.newline = newline;
+        self
+    }
+
+    fn accept(&mut self, buf: &[u8]) {
+        let mut bytes = buf;
+        if let Some(high) = self.carry.take() {
+            match bytes.split_first() {
+                Some((&low, rest)) => {
+                    self.push_token(u16::from_be_bytes([high, low]));
+                    bytes = rest;
+                }
+                None => self.carry = Some(high),
+            }
+        }
+        let mut pairs = bytes.chunks_exact(2);
+        for pair in &mut pairs {
+            self.push_token(u16::from_be_bytes([pair[0], pair[1]]));
+        }
+        if let [last] = pairs.remainder() {
+            self.carry = Some(*last);
+        }
+    }
+
+    fn push_token(&mut self, token: u16) {
+        let token = if token == self.newline {
+            NEWLINE_TOKEN
+        } else {
+            token
+        };
+        self.document.push(token);
+        if is_terminator(token) {
+            let document = std::mem::take(&mut self.document);
+            self.emit_document(document);
+        }
+    }
+
+    fn emit_document(&mut self, document: Vec<u16>) {
+        if document.is_empty() {
+            return;
+        }
+        let documents = run_chain(&mut self.processors, document);
+        self.queue(documents);
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.carry.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Post-processing requires a u16 token stream, got an odd number of bytes",
+            ));
+        }
+        let document = std::mem::take(&mut self.document);
+        self.emit_document(document);
+        let documents = finish_chain(&mut self.processors);
+        self.queue(documents);
+        Ok(())
+    }
+
+    fn queue(&mut self, documents: Vec<Vec<u16>>) {
+        for document in documents.into_iter().filter(|d| !d.is_empty()) {
+            if let Some(index) = &self.index {
+                let mut index = index.lock().expect("document index lock poisoned");
+                index.push(document.len() as u64);
+            }
+            let newline = self.newline;
+            self.pending.extend(document.iter().flat_map(|&t| {
+                let t = if t == NEWLINE_TOKEN { newline } else { t };
+                t.to_be_bytes()
+            }));
+        }
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let remaining = &self.pending[self.written..];
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, remaining))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for PostProcessWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.pending.len() >= PENDING_HIGH_WATER {
+            ready!(self.poll_drain(cx))?;
+        }
+        self.accept(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.finished {
+            self.finished = true;
+            self.finish()?;
+        }
+        ready!(self.poll_drain(cx))?;
+        // Some writers, such as Tokio's stdout, do not flush on shutdown.
+        ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Whether the output is a raw token stream the chain can run on.
+fn writes_tokens(config: &CoreConfig) -> bool {
+    config.strategy_kind() != StrategyKind::Passthrough && config.output_format == OutputFormat::Raw
+}
+
+/// Replaces [`special_tokens::SEPARATOR`] with the configured document separator.
+struct RelabelSeparator(u16);
+
+impl PostProcessor for RelabelSeparator {
+    fn process_document(&mut self, mut document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        for token in &mut document {
+            if *token == special_tokens::SEPARATOR {
+                *token = self.0;
+            }
+        }
+        out.push(document);
+    }
+}
+
+/// Whether `config` enables a post-processor or an index, which split the token stream into
+/// documents at newline tokens.
+pub(crate) fn splits_documents(config: &CoreConfig) -> bool {
+    config.log_records.is_some()
+        || config.truncation.is_some()
+        || config.span_corruption.is_some()
+        || config.packing.is_some()
+        || config.shifted_pairs.is_some()
+        || config.pad_to.is_some()
+        || config.index.is_some()
+}
+
+/// Wraps `writer` with the post-processors `config` enables, or returns it unchanged if none are.
+///
+/// `base_offset` is the number of bytes already written to `writer` (e.g. an output header).
+pub(crate) fn wrap(
+    writer: OutputWriter,
+    config: &CoreConfig,
+    base_offset: u64,
+) -> io::Result<(OutputWriter, PostProcessHandles)> {
+    let mut handles = PostProcessHandles::default();
+    let mut processors: Vec<Box<dyn PostProcessor>> = Vec::new();
+    if let Some(policy) = &config.log_records {
+        processors.push(Box::new(LogRecordProcessor::new(
+            policy.clone(),
+            TokenDecoder::new(config.bpe_data.as_deref(), config.byte_offset),
+        )));
+    }
+    if let Some(policy) = &config.truncation {
+        processors.push(Box::new(TruncationProcessor::new(policy.clone())));
+    }
+    if let Some(policy) = &config.span_corruption {
+        processors.push(Box::new(SpanCorruptionProcessor::new(policy.clone())));
+    }
+    if let Some(policy) = &config.packing {
+        let stats = Arc::new(Mutex::new(PackingStats::default()));
+        processors.push(Box::new(PackingProcessor::new(
+            policy.clone(),
+            stats.clone(),
+        )));
+        handles.packing_stats = Some(stats);
+    }
+    if let Some(policy) = &config.shifted_pairs {
+        processors.push(Box::new(ShiftedPairsProcessor::new(policy.clone())));
+    }
+    if let Some(separator) = config.document_separator {
+        if separator != special_tokens::SEPARATOR && writes_tokens(config) {
+            processors.push(Box::new(RelabelSeparator(separator)));
+        }
+    }
+    if let (ContentTypeFraming::EveryTokens(interval), Some(content_type)) =
+        (config.content_type_framing, &config.content_type)
+    {
+        processors.push(Box::new(ContentTypeFramingProcessor::new(
+            content_type.get_token_value(),
+            interval,
+        )));
+    }
+    if let Some(policy) = &config.pad_to {
+        processors.push(Box::new(PadToProcessor::new(policy.clone())));
+    }
+    if config.index.is_some() {
+        handles.index = Some(Arc::new(Mutex::new(DocumentIndex::new(2, base_offset))));
+    }
+    if processors.is_empty() && handles.index.is_none() {
+        return Ok((writer, handles));
+    }
+    if config.strategy_kind() == StrategyKind::Passthrough {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Token post-processing and indexing are not supported in passthrough mode",
+        ));
+    }
+    if config.output_format != OutputFormat::Raw {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Token post-processing and indexing are only supported for the raw output format",
+        ));
+    }
+    let mut writer =
+        PostProcessWriter::new(writer, processors).with_newline(NEWLINE_TOKEN + config.byte_offset);
+    if let Some(index) = &handles.index {
+        writer = writer.with_index(index.clone());
+    }
+    Ok((Box::new(writer), handles))
+}
+
+/// Runs `document` through every processor in order.
+fn run_chain(processors: &mut [Box<dyn PostProcessor>], document: Vec<u16>) -> Vec<Vec<u16>> {
+    let mut documents = vec![document];
+    for processor in processors.iter_mut() {
+        let mut out = Vec::new();
+        for document in documents {
+            processor.process_document(document, &mut out);
+        }
+        documents = out;
+    }
+    documents
+}
+
+/// Flushes every processor, feeding each one's trailing output through the rest of the chain.
+fn finish_chain(processors: &mut [Box<dyn PostProcessor>]) -> Vec<Vec<u16>> {
+    let mut tail = Vec::new();
+    for i in 0..processors.len() {
+        let mut flushed = Vec::new();
+        processors[i].finish(&mut flushed);
+        for document in flushed {
+            tail.extend(run_chain(&mut processors[i + 1..], document));
+        }
+    }
+    tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    /// Reverses each document; handy for observing document boundaries.
+    struct Reverse;
+
+    impl PostProcessor for Reverse {
+        fn process_document(&mut self, mut document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+            document.reverse();
+            out.push(document);
+        }
+    }
+
+    fn encode(tokens: &[u16]) -> Vec<u8> {
+        tokens.iter().flat_map(|t| t.to_be_bytes()).collect()
+    }
+
+    async fn run_writer(processors: Vec<Box<dyn PostProcessor>>, input: &[u8]) -> Vec<u8> {
+        run_writer_indexed(processors, input, None).await
+    }
+
+    async fn run_writer_indexed(
+        processors: Vec<Box<dyn PostProcessor>>,
+        input: &[u8],
+        index: Option<Arc<Mutex<DocumentIndex>>>,
+    ) -> Vec<u8> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await.unwrap());
+        let mut writer = PostProcessWriter::new(inner, processors);
+        if let Some(index) = index {
+            writer = writer.with_index(index);
+        }
+        // Write in odd-sized pieces to exercise the carry byte.
+        for piece in input.chunks(3) {
+            writer.write_all(piece).await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+        std::fs::read(file.path()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_documents_split_after_newline() {
+        let input = encode(&[1, 2, NEWLINE_TOKEN, 3, 4, 5]);
+        let output = run_writer(vec![Box::new(Reverse)], &input).await;
+        assert_eq!(output, encode(&[NEWLINE_TOKEN, 2, 1, 5, 4, 3]));
+    }
+
+    #[tokio::test]
+    async fn test_index_only_preserves_output() {
+        let tokens = [1, NEWLINE_TOKEN, 2, 3, NEWLINE_TOKEN, 4];
+        let index = Arc::new(Mutex::new(DocumentIndex::new(2, 0)));
+        let output = run_writer_indexed(Vec::new(), &encode(&tokens), Some(index.clone())).await;
+        assert_eq!(output, encode(&tokens));
+        let lengths: Vec<u64> = index
+            .lock()
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|e| e.tokens)
+            .collect();
+        assert_eq!(lengths, vec![2, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_shifted_newline_splits_documents() {
+        let newline = NEWLINE_TOKEN + 256;
+        let input = encode(&[257, 258, newline, 259]);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let inner: OutputWriter = Box::new(tokio::fs::File::create(file.path()).await.unwrap());
+        let mut writer =
+            PostProcessWriter::new(inner, vec![Box::new(Reverse)]).with_newline(newline);
+        writer.write_all(&input).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let output = std::fs::read(file.path()).unwrap();
+        assert_eq!(output, encode(&[newline, 258, 257, 259]));
+    }
+
+    #[tokio::test]
+    async fn test_separator_relabelled_after_chain() {
+        let sep = special_tokens::SEPARATOR;
+        let input = encode(&[1, 2, sep, 3]);
+        let processors: Vec<Box<dyn PostProcessor>> =
+            vec![Box::new(Reverse), Box::new(RelabelSeparator(7))];
+        let output = run_writer(processors, &input).await;
+        assert_eq!(output, encode(&[7, 2, 1, 3]));
+    }
+}
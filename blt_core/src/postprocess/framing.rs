@@ -0,0 +1,59 @@
+//! Repeating the content type token at a fixed token interval.
+//!
+//! Consumers that read the output in windows need the content type token inside every
+//! window, not just at the start of the stream. This processor re-inserts the token whenever
+//! `interval` tokens have passed since the last one. Content type tokens already in the
+//! stream, such as the one opening each output, restart the count.
+
+use super::PostProcessor;
+
+/// Inserts a content type token before every run of `interval` tokens without one.
+pub struct ContentTypeFramingProcessor {
+    token: u16,
+    interval: usize,
+    since: usize,
+}
+
+impl ContentTypeFramingProcessor {
+    /// Repeats `token` so that at most `interval` tokens pass between two occurrences.
+    pub fn new(token: u16, interval: usize) -> Self {
+        Self {
+            token,
+            interval: interval.max(1),
+            since: 0,
+        }
+    }
+}
+
+impl PostProcessor for ContentTypeFramingProcessor {
+    fn process_document(&mut self, document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        let mut framed = Vec::with_capacity(document.len() + document.len() / self.interval + 1);
+        for token in document {
+            if token == self.token {
+                self.since = 0;
+            } else {
+                if self.since == self.interval {
+                    framed.push(self.token);
+                    self.since = 0;
+                }
+                self.since += 1;
+            }
+            framed.push(token);
+        }
+        out.push(framed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_across_documents() {
+        let mut processor = ContentTypeFramingProcessor::new(9, 2);
+        let mut out = Vec::new();
+        processor.process_document(vec![9, 1, 2, 3], &mut out);
+        processor.process_document(vec![4, 5, 6], &mut out);
+        assert_eq!(out, vec![vec![9, 1, 2, 9, 3], vec![4, 9, 5, 6]]);
+    }
+}
@@ -0,0 +1,217 @@
+//! Structuring log files as one record per line.
+//!
+//! Each line of a log becomes a record: blank lines are dropped, the record is tagged with a
+//! token for its severity when one can be recognized, and its newline is replaced with
+//! [`special_tokens::RECORD_SEPARATOR`], so models see the same frame around every event.
+//! Timestamps are best removed before tokenizing (see
+//! [`TimestampStripping`](crate::io_handler::TimestampStripping)).
+//!
+//! The severity is the first of the usual level names (`INFO`, `warn`, `Error`, `CRIT`, ...)
+//! found as a whole word in the record's first [`SEVERITY_SCAN_BYTES`] bytes. Records are
+//! split at newline tokens, so a merge spanning a line break keeps both lines in one record.
+
+use super::{pop_terminator, PostProcessor};
+use crate::special_tokens;
+use crate::tokenizer::TokenDecoder;
+
+/// How far into a record its severity is looked for.
+pub const SEVERITY_SCAN_BYTES: usize = 64;
+
+/// A log record's severity, in increasing order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// `TRACE`.
+    Trace,
+    /// `DEBUG`, `DBG`.
+    Debug,
+    /// `INFO`, `INF`, `NOTICE`.
+    Info,
+    /// `WARN`, `WARNING`, `WRN`.
+    Warn,
+    /// `ERROR`, `ERR`.
+    Error,
+    /// `FATAL`, `CRITICAL`, `CRIT`, `PANIC`, `ALERT`, `EMERG`.
+    Fatal,
+}
+
+impl Severity {
+    /// The token tagging records of this severity.
+    pub fn token(self) -> u16 {
+        special_tokens::SEVERITY_RANGE.start() + self as u16
+    }
+
+    /// Recognizes a level name, ignoring case.
+    pub fn from_word(word: &[u8]) -> Option<Self> {
+        let severity = match word.to_ascii_uppercase().as_slice() {
+            b"TRACE" => Severity::Trace,
+            b"DEBUG" | b"DBG" => Severity::Debug,
+            b"INFO" | b"INF" | b"NOTICE" => Severity::Info,
+            b"WARN" | b"WARNING" | b"WRN" => Severity::Warn,
+            b"ERROR" | b"ERR" => Severity::Error,
+            b"FATAL" | b"CRITICAL" | b"CRIT" | b"PANIC" | b"ALERT" | b"EMERG" => Severity::Fatal,
+            _ => return None,
+        };
+        Some(severity)
+    }
+
+    /// Finds the severity of a log line from its first [`SEVERITY_SCAN_BYTES`] bytes.
+    pub fn detect(line: &[u8]) -> Option<Self> {
+        let mut head = &line[..line.len().min(SEVERITY_SCAN_BYTES)];
+        if line.get(head.len()).is_some_and(u8::is_ascii_alphabetic) {
+            // A word cut off by the scan limit is not a whole word.
+            let end = head.iter().rposition(|byte| !byte.is_ascii_alphabetic());
+            head = &head[..end.unwrap_or(0)];
+        }
+        head.split(|byte| !byte.is_ascii_alphabetic())
+            .find_map(Self::from_word)
+    }
+}
+
+/// How log records are framed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogRecordPolicy {
+    /// The token ending each record.
+    pub record_separator: u16,
+    /// Whether records are prefixed with a severity token.
+    pub tag_severity: bool,
+}
+
+impl Default for LogRecordPolicy {
+    fn default() -> Self {
+        Self {
+            record_separator: special_tokens::RECORD_SEPARATOR,
+            tag_severity: true,
+        }
+    }
+}
+
+/// Frames each line as a log record according to a [`LogRecordPolicy`].
+pub struct LogRecordProcessor {
+    policy: LogRecordPolicy,
+    decoder: TokenDecoder,
+}
+
+impl LogRecordProcessor {
+    /// Creates a processor applying `policy`, reading severities with `decoder`.
+    pub fn new(policy: LogRecordPolicy, decoder: TokenDecoder) -> Self {
+        Self { policy, decoder }
+    }
+
+    fn severity(&self, record: &[u16]) -> Option<Severity> {
+        // Every token decodes to at least one byte, so this prefix covers the scanned bytes.
+        let prefix = record.iter().take(SEVERITY_SCAN_BYTES + 1).copied();
+        self.decoder
+            .decode_tokens(prefix)
+            .ok()
+            .and_then(|line| Severity::detect(&line))
+    }
+}
+
+impl PostProcessor for LogRecordProcessor {
+    fn process_document(&mut self, mut document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        let terminator = pop_terminator(&mut document);
+        let document_end = terminator == Some(special_tokens::SEPARATOR);
+        if document.is_empty() {
+            if document_end {
+                out.push(vec![special_tokens::SEPARATOR]);
+            }
+            return;
+        }
+        let mut record = Vec::with_capacity(document.len() + 3);
+        if self.policy.tag_severity {
+            record.extend(self.severity(&document).map(Severity::token));
+        }
+        record.extend(document);
+        record.push(self.policy.record_separator);
+        if document_end {
+            record.push(special_tokens::SEPARATOR);
+        }
+        out.push(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RS: u16 = special_tokens::RECORD_SEPARATOR;
+    const SEP: u16 = special_tokens::SEPARATOR;
+
+    fn bytes(text: &str) -> Vec<u16> {
+        text.bytes().map(u16::from).collect()
+    }
+
+    fn process(documents: Vec<Vec<u16>>) -> Vec<Vec<u16>> {
+        let mut processor =
+            LogRecordProcessor::new(LogRecordPolicy::default(), TokenDecoder::new(None, 0));
+        let mut out = Vec::new();
+        for document in documents {
+            processor.process_document(document, &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn test_detects_severity() {
+        let cases: [(&str, Option<Severity>); 7] = [
+            ("[ERROR] disk full", Some(Severity::Error)),
+            ("host app[42]: warning: retrying", Some(Severity::Warn)),
+            ("level=info msg=started", Some(Severity::Info)),
+            ("E1015 12:00:00 failed", None),
+            ("INFORMATION only", None),
+            ("no level here", None),
+            ("", None),
+        ];
+        for (line, severity) in cases {
+            assert_eq!(Severity::detect(line.as_bytes()), severity, "{line}");
+        }
+
+        let late = format!("{} DEBUG", "x".repeat(SEVERITY_SCAN_BYTES));
+        assert_eq!(Severity::detect(late.as_bytes()), None);
+        let cut = format!("{}ERRORS", " ".repeat(SEVERITY_SCAN_BYTES - 3));
+        assert_eq!(Severity::detect(cut.as_bytes()), None);
+    }
+
+    #[test]
+    fn test_frames_records() {
+        let out = process(vec![
+            bytes("ERROR boom\n"),
+            bytes("\n"),
+            bytes("plain\n"),
+            [bytes("trace end"), vec![SEP]].concat(),
+            vec![SEP],
+            bytes("warn last"),
+        ]);
+        assert_eq!(
+            out[0],
+            [vec![Severity::Error.token()], bytes("ERROR boom"), vec![RS]].concat()
+        );
+        assert_eq!(out[1], [bytes("plain"), vec![RS]].concat());
+        assert_eq!(
+            out[2],
+            [
+                vec![Severity::Trace.token()],
+                bytes("trace end"),
+                vec![RS, SEP]
+            ]
+            .concat()
+        );
+        assert_eq!(out[3], vec![SEP]);
+        assert_eq!(
+            out[4],
+            [vec![Severity::Warn.token()], bytes("warn last"), vec![RS]].concat()
+        );
+        assert_eq!(out.len(), 5);
+    }
+
+    #[test]
+    fn test_severity_tokens_are_structural() {
+        for severity in [Severity::Trace, Severity::Fatal] {
+            assert!(special_tokens::is_structural(severity.token()));
+        }
+        assert_eq!(
+            Severity::Fatal.token(),
+            *special_tokens::SEVERITY_RANGE.end()
+        );
+    }
+}
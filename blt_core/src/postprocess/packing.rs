@@ -0,0 +1,211 @@
+//! Packing documents into fixed-length training sequences.
+//!
+//! Every document has its trailing newline or separator replaced by the policy's separator
+//! token and is laid out in sequences of exactly `seq_len` tokens; the unused tail of a
+//! sequence is filled with the pad token. Documents without content (blank lines) are skipped. Documents
+//! shorter than `short_doc_threshold` tokens may share a sequence with other short documents,
+//! which greatly reduces padding waste on corpora with many short records.
+
+use super::{pop_terminator, PostProcessor};
+use crate::special_tokens;
+use std::sync::{Arc, Mutex};
+
+/// How documents are packed into sequences.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackingPolicy {
+    /// The number of tokens per output sequence.
+    pub seq_len: usize,
+    /// Documents with fewer tokens than this share sequences; `0` disables sharing.
+    pub short_doc_threshold: usize,
+    /// The token used to fill the end of a sequence.
+    pub pad_token: u16,
+    /// The token that ends every document, replacing its trailing newline.
+    pub separator_token: u16,
+}
+
+impl PackingPolicy {
+    /// Creates a policy with the given sequence length and default special tokens.
+    pub fn new(seq_len: usize, short_doc_threshold: usize) -> Self {
+        Self {
+            seq_len: seq_len.max(1),
+            short_doc_threshold,
+            pad_token: special_tokens::PAD,
+            separator_token: special_tokens::SEPARATOR,
+        }
+    }
+}
+
+/// Counters describing how efficiently documents were packed.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct PackingStats {
+    /// The number of sequences emitted.
+    pub sequences: u64,
+    /// Document and separator tokens emitted.
+    pub content_tokens: u64,
+    /// Pad tokens emitted.
+    pub padding_tokens: u64,
+}
+
+impl PackingStats {
+    /// The fraction of emitted tokens that are not padding (1.0 when nothing was emitted).
+    pub fn efficiency(&self) -> f64 {
+        let total = self.content_tokens + self.padding_tokens;
+        if total == 0 {
+            1.0
+        } else {
+            self.content_tokens as f64 / total as f64
+        }
+    }
+
+    /// Adds `other`'s counters to these, as when one run writes several outputs.
+    pub fn add(&mut self, other: &Self) {
+        self.sequences += other.sequences;
+        self.content_tokens += other.content_tokens;
+        self.padding_tokens += other.padding_tokens;
+    }
+}
+
+/// Packs documents according to a [`PackingPolicy`].
+pub struct PackingProcessor {
+    policy: PackingPolicy,
+    current: Vec<u16>,
+    current_is_shared: bool,
+    stats: Arc<Mutex<PackingStats>>,
+}
+
+impl PackingProcessor {
+    /// Creates a processor; its counters are published through `stats`.
+    pub fn new(policy: PackingPolicy, stats: Arc<Mutex<PackingStats>>) -> Self {
+        Self {
+            policy,
+            current: Vec::new(),
+            current_is_shared: false,
+            stats,
+        }
+    }
+
+    fn is_short(&self, document: &[u16]) -> bool {
+        document.len() < self.policy.short_doc_threshold
+    }
+
+    fn close_sequence(&mut self, out: &mut Vec<Vec<u16>>) {
+        if self.current.is_empty() {
+            return;
+        }
+        let content = self.current.len();
+        let padding = self.policy.seq_len - content;
+        let mut sequence =
+            std::mem::replace(&mut self.current, Vec::with_capacity(self.policy.seq_len));
+        sequence.resize(self.policy.seq_len, self.policy.pad_token);
+        out.push(sequence);
+        self.current_is_shared = false;
+
+        let mut stats = self.stats.lock().expect("packing stats lock poisoned");
+        stats.sequences += 1;
+        stats.content_tokens += content as u64;
+        stats.padding_tokens += padding as u64;
+    }
+
+    fn push_token(&mut self, token: u16, out: &mut Vec<Vec<u16>>) {
+        self.current.push(token);
+        if self.current.len() == self.policy.seq_len {
+            self.close_sequence(out);
+        }
+    }
+}
+
+impl PostProcessor for PackingProcessor {
+    fn process_document(&mut self, mut document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        pop_terminator(&mut document);
+        if document.is_empty() {
+            return;
+        }
+        document.push(self.policy.separator_token);
+        let short = self.is_short(&document[..document.len() - 1]);
+        let fits = self.current.len() + document.len() <= self.policy.seq_len;
+        if !(short && self.current_is_shared && fits) {
+            self.close_sequence(out);
+        }
+        for token in document {
+            self.push_token(token, out);
+        }
+        self.current_is_shared = short && !self.current.is_empty();
+    }
+
+    fn finish(&mut self, out: &mut Vec<Vec<u16>>) {
+        self.close_sequence(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postprocess::NEWLINE_TOKEN;
+
+    const SEP: u16 = special_tokens::SEPARATOR;
+    const PAD: u16 = special_tokens::PAD;
+
+    fn pack(policy: PackingPolicy, documents: &[&[u16]]) -> (Vec<u16>, PackingStats) {
+        let stats = Arc::new(Mutex::new(PackingStats::default()));
+        let mut processor = PackingProcessor::new(policy, stats.clone());
+        let mut out = Vec::new();
+        for document in documents {
+            processor.process_document(document.to_vec(), &mut out);
+        }
+        processor.finish(&mut out);
+        assert!(out
+            .iter()
+            .all(|sequence| sequence.len() == processor.policy.seq_len));
+        let stats = stats.lock().unwrap().clone();
+        (out.concat(), stats)
+    }
+
+    #[test]
+    fn test_each_document_gets_its_own_sequence_without_threshold() {
+        let (out, stats) = pack(PackingPolicy::new(4, 0), &[&[1], &[2, 3]]);
+        assert_eq!(out, vec![1, SEP, PAD, PAD, 2, 3, SEP, PAD]);
+        assert_eq!(stats.sequences, 2);
+        assert_eq!(stats.padding_tokens, 3);
+    }
+
+    #[test]
+    fn test_short_documents_share_sequences() {
+        let (out, stats) = pack(PackingPolicy::new(6, 3), &[&[1], &[2, 3], &[4]]);
+        assert_eq!(
+            out,
+            vec![1, SEP, 2, 3, SEP, PAD, 4, SEP, PAD, PAD, PAD, PAD]
+        );
+        assert_eq!(stats.sequences, 2);
+        assert_eq!(stats.content_tokens, 7);
+    }
+
+    #[test]
+    fn test_long_documents_span_sequences() {
+        let (out, _) = pack(PackingPolicy::new(3, 2), &[&[1], &[5, 6, 7, 8]]);
+        assert_eq!(out, vec![1, SEP, PAD, 5, 6, 7, 8, SEP, PAD]);
+    }
+
+    #[test]
+    fn test_trailing_newline_becomes_separator() {
+        let (out, _) = pack(PackingPolicy::new(3, 0), &[&[1, NEWLINE_TOKEN]]);
+        assert_eq!(out, vec![1, SEP, PAD]);
+    }
+
+    #[test]
+    fn test_blank_documents_are_skipped() {
+        let (out, stats) = pack(PackingPolicy::new(2, 0), &[&[NEWLINE_TOKEN], &[SEP], &[1]]);
+        assert_eq!(out, vec![1, SEP]);
+        assert_eq!(stats.sequences, 1);
+    }
+
+    #[test]
+    fn test_efficiency() {
+        let stats = PackingStats {
+            sequences: 1,
+            content_tokens: 3,
+            padding_tokens: 1,
+        };
+        assert_eq!(stats.efficiency(), 0.75);
+        assert_eq!(PackingStats::default().efficiency(), 1.0);
+    }
+}
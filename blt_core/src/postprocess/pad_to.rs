@@ -0,0 +1,85 @@
+//! Padding the output to a multiple of a block size.
+//!
+//! Fixed-block training data needs the total token count to divide evenly into blocks. This
+//! processor counts every token that leaves the chain and, at the end of the stream, emits a
+//! final document of padding tokens that rounds the count up to the next multiple.
+
+use super::PostProcessor;
+use crate::special_tokens;
+
+/// How the output is padded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PadToPolicy {
+    /// The total token count is padded to a multiple of this many tokens.
+    pub multiple: usize,
+    /// The token used for padding.
+    pub pad_token: u16,
+}
+
+impl PadToPolicy {
+    /// Pads to a multiple of `multiple` tokens with [`special_tokens::PAD`].
+    pub fn new(multiple: usize) -> Self {
+        Self {
+            multiple: multiple.max(1),
+            pad_token: special_tokens::PAD,
+        }
+    }
+}
+
+/// Pads the stream according to a [`PadToPolicy`].
+pub struct PadToProcessor {
+    policy: PadToPolicy,
+    tokens: u64,
+}
+
+impl PadToProcessor {
+    /// Creates a processor applying `policy`.
+    pub fn new(policy: PadToPolicy) -> Self {
+        Self { policy, tokens: 0 }
+    }
+}
+
+impl PostProcessor for PadToProcessor {
+    fn process_document(&mut self, document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        self.tokens += document.len() as u64;
+        out.push(document);
+    }
+
+    fn finish(&mut self, out: &mut Vec<Vec<u16>>) {
+        let multiple = self.policy.multiple.max(1) as u64;
+        let padding = (multiple - self.tokens % multiple) % multiple;
+        if padding > 0 {
+            out.push(vec![self.policy.pad_token; padding as usize]);
+            self.tokens += padding;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad(multiple: usize, documents: &[&[u16]]) -> Vec<Vec<u16>> {
+        let mut processor = PadToProcessor::new(PadToPolicy {
+            pad_token: 0,
+            ..PadToPolicy::new(multiple)
+        });
+        let mut out = Vec::new();
+        for document in documents {
+            processor.process_document(document.to_vec(), &mut out);
+        }
+        processor.finish(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_pads_to_next_multiple() {
+        assert_eq!(pad(4, &[&[1, 2], &[3]]), vec![vec![1, 2], vec![3], vec![0]]);
+    }
+
+    #[test]
+    fn test_exact_multiple_is_unchanged() {
+        assert_eq!(pad(3, &[&[1, 2, 3]]), vec![vec![1, 2, 3]]);
+        assert_eq!(pad(3, &[]), Vec::<Vec<u16>>::new());
+    }
+}
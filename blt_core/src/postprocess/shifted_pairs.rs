@@ -0,0 +1,81 @@
+//! Next-token `(input, label)` pairs for language-model training.
+//!
+//! Every sequence of `n` tokens becomes three consecutive documents of `n - 1` tokens each:
+//! the input (`tokens[..n-1]`), the labels (`tokens[1..]`), and a loss mask holding `1` where
+//! the label is a real token and `0` where it is padding. Run after packing, this turns the
+//! output into arrays a training loop can consume without further preprocessing. Sequences
+//! shorter than two tokens have nothing to predict and are dropped.
+
+use super::PostProcessor;
+use crate::special_tokens;
+
+/// How shifted pairs are produced.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShiftedPairsPolicy {
+    /// Labels equal to this token are masked out.
+    pub pad_token: u16,
+}
+
+impl Default for ShiftedPairsPolicy {
+    fn default() -> Self {
+        Self {
+            pad_token: special_tokens::PAD,
+        }
+    }
+}
+
+/// Emits shifted `(input, label, mask)` triples according to a [`ShiftedPairsPolicy`].
+pub struct ShiftedPairsProcessor {
+    policy: ShiftedPairsPolicy,
+}
+
+impl ShiftedPairsProcessor {
+    /// Creates a processor applying `policy`.
+    pub fn new(policy: ShiftedPairsPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl PostProcessor for ShiftedPairsProcessor {
+    fn process_document(&mut self, document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        if document.len() < 2 {
+            return;
+        }
+        let labels = document[1..].to_vec();
+        let mask = labels
+            .iter()
+            .map(|&label| u16::from(label != self.policy.pad_token))
+            .collect();
+        let mut input = document;
+        input.pop();
+        out.push(input);
+        out.push(labels);
+        out.push(mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAD: u16 = special_tokens::PAD;
+
+    #[test]
+    fn test_shift_and_mask() {
+        let mut processor = ShiftedPairsProcessor::new(ShiftedPairsPolicy::default());
+        let mut out = Vec::new();
+        processor.process_document(vec![1, 2, 3, PAD, PAD], &mut out);
+        assert_eq!(
+            out,
+            vec![vec![1, 2, 3, PAD], vec![2, 3, PAD, PAD], vec![1, 1, 0, 0]]
+        );
+    }
+
+    #[test]
+    fn test_single_token_sequences_are_dropped() {
+        let mut processor = ShiftedPairsProcessor::new(ShiftedPairsPolicy::default());
+        let mut out = Vec::new();
+        processor.process_document(vec![1], &mut out);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+//! T5-style span corruption.
+//!
+//! Each document is turned into an `(input, target)` pair emitted as two consecutive
+//! documents. Roughly `noise_density` of the document's tokens are chosen in contiguous spans;
+//! in the input every span is replaced by the mask token, and the target lists each masked span
+//! after a mask token, ending with a final mask token:
+//!
+//! ```text
+//! document: a b c d e f g
+//! input:    a M d e M g
+//! target:   M b c M f M
+//! ```
+//!
+//! A trailing newline or separator is kept on both halves of the pair, and documents without
+//! content are dropped. Span placement is driven by a seeded
+//! generator, so the same input and policy always produce the same output.
+
+use super::{pop_terminator, PostProcessor};
+use crate::special_tokens;
+
+/// The distribution span lengths are drawn from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpanLength {
+    /// Every span has the same length.
+    Fixed(usize),
+    /// Span lengths follow a geometric distribution with the given mean.
+    Geometric {
+        /// The mean span length, at least 1.0.
+        mean: f64,
+    },
+}
+
+/// How documents are corrupted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanCorruptionPolicy {
+    /// The fraction of each document's tokens to mask.
+    pub noise_density: f64,
+    /// The distribution span lengths are drawn from.
+    pub span_length: SpanLength,
+    /// The token replacing each masked span.
+    pub mask_token: u16,
+    /// Seed for span placement.
+    pub seed: u64,
+}
+
+impl SpanCorruptionPolicy {
+    /// Creates a policy with the default mask token and seed 0.
+    pub fn new(noise_density: f64, span_length: SpanLength) -> Self {
+        Self {
+            noise_density,
+            span_length,
+            mask_token: special_tokens::MASK,
+            seed: 0,
+        }
+    }
+}
+
+/// Produces span-corrupted `(input, target)` pairs according to a [`SpanCorruptionPolicy`].
+pub struct SpanCorruptionProcessor {
+    policy: SpanCorruptionPolicy,
+    rng: SplitMix64,
+}
+
+impl SpanCorruptionProcessor {
+    /// Creates a processor applying `policy`.
+    pub fn new(policy: SpanCorruptionPolicy) -> Self {
+        let rng = SplitMix64(policy.seed);
+        Self { policy, rng }
+    }
+
+    fn sample_span_length(&mut self) -> usize {
+        match self.policy.span_length {
+            SpanLength::Fixed(length) => length.max(1),
+            SpanLength::Geometric { mean } if mean <= 1.0 => 1,
+            SpanLength::Geometric { mean } => {
+                let u = self.rng.next_f64().max(f64::MIN_POSITIVE);
+                (u.ln() / (1.0 - 1.0 / mean).ln()).ceil().max(1.0) as usize
+            }
+        }
+    }
+
+    /// Returns the lengths of alternating kept and masked runs, starting with a kept run.
+    fn plan_spans(&mut self, len: usize) -> (Vec<usize>, Vec<usize>) {
+        let noise = ((len as f64 * self.policy.noise_density).round() as usize).clamp(1, len - 1);
+        let mut masked = Vec::new();
+        let mut remaining = noise;
+        while remaining > 0 {
+            let span = self.sample_span_length().min(remaining);
+            masked.push(span);
+            remaining -= span;
+        }
+        // Spread the kept tokens over the gaps between and around the spans. The gaps between
+        // two spans must be non-empty, or the spans would merge.
+        let mut kept = vec![0; masked.len() + 1];
+        let inner_gaps = masked.len() - 1;
+        let mut free = len - noise;
+        if free < inner_gaps {
+            // Too few kept tokens to separate every span: fold the extra spans into the last.
+            let extra: usize = masked.drain(free + 1..).sum();
+            *masked.last_mut().expect("at least one span") += extra;
+            kept.truncate(masked.len() + 1);
+        }
+        for gap in &mut kept[1..masked.len()] {
+            *gap = 1;
+            free -= 1;
+        }
+        for _ in 0..free {
+            let gap = self.rng.next_below(kept.len());
+            kept[gap] += 1;
+        }
+        (kept, masked)
+    }
+}
+
+impl PostProcessor for SpanCorruptionProcessor {
+    fn process_document(&mut self, mut document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        let terminator = pop_terminator(&mut document);
+        if document.is_empty() {
+            return;
+        }
+        let mask = self.policy.mask_token;
+        let mut input = Vec::with_capacity(document.len());
+        let mut target = Vec::new();
+        if document.len() < 2 {
+            input = document;
+        } else {
+            let (kept, masked) = self.plan_spans(document.len());
+            let mut tokens = document.into_iter();
+            for (i, kept_len) in kept.into_iter().enumerate() {
+                input.extend(tokens.by_ref().take(kept_len));
+                if let Some(&span) = masked.get(i) {
+                    input.push(mask);
+                    target.push(mask);
+                    target.extend(tokens.by_ref().take(span));
+                }
+            }
+        }
+        target.push(mask);
+        if let Some(terminator) = terminator {
+            input.push(terminator);
+            target.push(terminator);
+        }
+        out.push(input);
+        out.push(target);
+    }
+}
+
+/// A small, fast, reproducible generator (SplitMix64).
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniform float in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A uniform integer in `0..bound`.
+    fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postprocess::NEWLINE_TOKEN;
+
+    const M: u16 = special_tokens::MASK;
+
+    fn corrupt(policy: SpanCorruptionPolicy, document: &[u16]) -> (Vec<u16>, Vec<u16>) {
+        let mut processor = SpanCorruptionProcessor::new(policy);
+        let mut out = Vec::new();
+        processor.process_document(document.to_vec(), &mut out);
+        assert_eq!(out.len(), 2);
+        let target = out.pop().unwrap();
+        (out.pop().unwrap(), target)
+    }
+
+    /// Rebuilds the original document from an input/target pair.
+    fn reconstruct(input: &[u16], target: &[u16]) -> Vec<u16> {
+        let mut spans = target.split(|&t| t == M).skip(1);
+        let mut document = Vec::new();
+        for &token in input {
+            if token == M {
+                document.extend_from_slice(spans.next().unwrap());
+            } else {
+                document.push(token);
+            }
+        }
+        document
+    }
+
+    #[test]
+    fn test_pair_reconstructs_document() {
+        let document: Vec<u16> = (1..=40).collect();
+        for seed in 0..20 {
+            let policy = SpanCorruptionPolicy {
+                seed,
+                ..SpanCorruptionPolicy::new(0.15, SpanLength::Geometric { mean: 3.0 })
+            };
+            let (input, target) = corrupt(policy, &document);
+            assert_eq!(reconstruct(&input, &target), document);
+            assert_eq!(target.last(), Some(&M));
+            let masked = target.iter().filter(|&&t| t != M).count();
+            assert_eq!(masked, 6);
+        }
+    }
+
+    #[test]
+    fn test_fixed_spans_and_newline() {
+        let mut document: Vec<u16> = (1..=10).collect();
+        document.push(NEWLINE_TOKEN);
+        let (input, target) = corrupt(
+            SpanCorruptionPolicy::new(0.4, SpanLength::Fixed(2)),
+            &document,
+        );
+        assert_eq!(input.last(), Some(&NEWLINE_TOKEN));
+        assert_eq!(target.last(), Some(&NEWLINE_TOKEN));
+        let spans = target[..target.len() - 2].split(|&t| t == M).skip(1);
+        assert!(spans.into_iter().all(|span| span.len() == 2));
+    }
+
+    #[test]
+    fn test_same_seed_is_reproducible() {
+        let document: Vec<u16> = (1..=100).collect();
+        let policy = SpanCorruptionPolicy::new(0.3, SpanLength::Geometric { mean: 2.5 });
+        assert_eq!(
+            corrupt(policy.clone(), &document),
+            corrupt(policy, &document)
+        );
+    }
+
+    #[test]
+    fn test_dense_masking_keeps_spans_separated() {
+        let document: Vec<u16> = (1..=5).collect();
+        let (input, target) = corrupt(
+            SpanCorruptionPolicy::new(0.9, SpanLength::Fixed(1)),
+            &document,
+        );
+        assert_eq!(reconstruct(&input, &target), document);
+        assert!(!input.windows(2).any(|w| w == [M, M]));
+    }
+}
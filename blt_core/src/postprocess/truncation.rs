@@ -0,0 +1,112 @@
+//! Truncating over-long documents to a maximum number of tokens.
+//!
+//! The limit applies to a document's content; a trailing newline or separator is kept so
+//! document boundaries survive truncation.
+
+use super::{is_terminator, PostProcessor};
+
+/// Which part of an over-long document is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TruncationMode {
+    /// Keep the first tokens.
+    Head,
+    /// Keep the last tokens.
+    Tail,
+    /// Keep the first `head_ratio` of the budget and fill the rest from the end, dropping
+    /// the middle of the document.
+    HeadTail {
+        /// Fraction of the budget taken from the head, between 0.0 and 1.0.
+        head_ratio: f64,
+    },
+}
+
+/// How over-long documents are truncated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TruncationPolicy {
+    /// The maximum number of content tokens kept per document.
+    pub max_tokens: usize,
+    /// Which part of the document is kept.
+    pub mode: TruncationMode,
+}
+
+impl TruncationPolicy {
+    /// Creates a policy keeping at most `max_tokens` tokens using `mode`.
+    pub fn new(max_tokens: usize, mode: TruncationMode) -> Self {
+        Self { max_tokens, mode }
+    }
+
+    /// The number of tokens kept from the head and the tail of an over-long document.
+    fn split(&self) -> (usize, usize) {
+        let head = match self.mode {
+            TruncationMode::Head => self.max_tokens,
+            TruncationMode::Tail => 0,
+            TruncationMode::HeadTail { head_ratio } => {
+                (self.max_tokens as f64 * head_ratio.clamp(0.0, 1.0)).round() as usize
+            }
+        };
+        (head, self.max_tokens - head)
+    }
+}
+
+/// Truncates documents according to a [`TruncationPolicy`].
+pub struct TruncationProcessor {
+    policy: TruncationPolicy,
+}
+
+impl TruncationProcessor {
+    /// Creates a processor applying `policy`.
+    pub fn new(policy: TruncationPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl PostProcessor for TruncationProcessor {
+    fn process_document(&mut self, mut document: Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        let terminated = document.last().is_some_and(|&t| is_terminator(t));
+        let content_len = document.len() - usize::from(terminated);
+        if content_len > self.policy.max_tokens {
+            let (head, tail) = self.policy.split();
+            document.drain(head..content_len - tail);
+        }
+        out.push(document);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postprocess::NEWLINE_TOKEN;
+
+    fn truncate(mode: TruncationMode, max_tokens: usize, document: &[u16]) -> Vec<u16> {
+        let mut processor = TruncationProcessor::new(TruncationPolicy::new(max_tokens, mode));
+        let mut out = Vec::new();
+        processor.process_document(document.to_vec(), &mut out);
+        out.concat()
+    }
+
+    #[test]
+    fn test_head_and_tail() {
+        let document = [1, 2, 3, 4, 5, NEWLINE_TOKEN];
+        assert_eq!(
+            truncate(TruncationMode::Head, 2, &document),
+            vec![1, 2, NEWLINE_TOKEN]
+        );
+        assert_eq!(
+            truncate(TruncationMode::Tail, 2, &document),
+            vec![4, 5, NEWLINE_TOKEN]
+        );
+    }
+
+    #[test]
+    fn test_head_tail_ratio() {
+        let document = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mode = TruncationMode::HeadTail { head_ratio: 0.75 };
+        assert_eq!(truncate(mode, 4, &document), vec![1, 2, 3, 8]);
+    }
+
+    #[test]
+    fn test_short_documents_untouched() {
+        let document = [1, 2, NEWLINE_TOKEN];
+        assert_eq!(truncate(TruncationMode::Tail, 2, &document), document);
+    }
+}
@@ -0,0 +1,168 @@
+//! Scheduling priority for the threads that run a tokenizer job.
+//!
+//! Long jobs on shared workstations should defer to interactive workloads. A [`Priority`] is
+//! applied per thread, typically from the async runtime's thread-start hook so every worker
+//! and blocking-pool thread picks it up:
+//!
+//! - `nice` raises (or, with privileges, lowers) the CPU niceness. Linux treats niceness as
+//!   a per-thread attribute; on other Unix systems it applies to the whole process.
+//! - [`IoPriority`] sets the I/O scheduling class of the thread. Linux only.
+//! - `background_qos` moves the thread to the background quality-of-service class, which
+//!   also lowers its I/O priority. macOS only.
+
+use std::io;
+
+/// A Linux I/O scheduling class, as set by `ionice`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Only perform I/O when no other process needs the disk.
+    Idle,
+    /// The default class, with a level from 0 (highest) to 7 (lowest).
+    BestEffort(u8),
+}
+
+/// CPU and I/O priority settings applied to each thread of a job.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Priority {
+    /// The niceness to set, from -20 (highest priority) to 19 (lowest).
+    pub nice: Option<i32>,
+    /// The I/O scheduling class to set.
+    pub io: Option<IoPriority>,
+    /// Whether to use the background quality-of-service class.
+    pub background_qos: bool,
+}
+
+impl Priority {
+    /// Returns `true` if no setting differs from the inherited priority.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Applies every configured setting to the calling thread.
+    pub fn apply_to_current_thread(&self) -> io::Result<()> {
+        if let Some(nice) = self.nice {
+            set_nice(nice)?;
+        }
+        if let Some(io) = self.io {
+            set_io_priority(io)?;
+        }
+        if self.background_qos {
+            set_background_qos()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn set_nice(nice: i32) -> io::Result<()> {
+    if !(-20..=19).contains(&nice) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Niceness {nice} is outside -20..=19"),
+        ));
+    }
+    // With `who` 0, Linux sets the calling thread's niceness; other systems set the process's.
+    // SAFETY: `setpriority` takes plain integer arguments.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_nice(_nice: i32) -> io::Result<()> {
+    Err(unsupported("Niceness", "Unix"))
+}
+
+#[cfg(target_os = "linux")]
+fn set_io_priority(io: IoPriority) -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+    let (class, level) = match io {
+        IoPriority::BestEffort(level) if level > 7 => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Best-effort I/O level {level} is outside 0..=7"),
+            ));
+        }
+        IoPriority::BestEffort(level) => (2, level as libc::c_long),
+        IoPriority::Idle => (3, 0),
+    };
+    let ioprio = (class << IOPRIO_CLASS_SHIFT) | level;
+    // With `who` 0, `ioprio_set` applies to the calling thread.
+    // SAFETY: `ioprio_set` takes plain integer arguments.
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_io_priority(_io: IoPriority) -> io::Result<()> {
+    Err(unsupported("I/O scheduling classes", "Linux"))
+}
+
+#[cfg(target_os = "macos")]
+fn set_background_qos() -> io::Result<()> {
+    // SAFETY: `pthread_set_qos_class_self_np` only affects the calling thread.
+    let result =
+        unsafe { libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn set_background_qos() -> io::Result<()> {
+    Err(unsupported("Background QoS", "macOS"))
+}
+
+fn unsupported(setting: &str, platform: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{setting} can only be set on {platform}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_is_a_no_op() {
+        assert!(Priority::default().is_default());
+        Priority::default().apply_to_current_thread().unwrap();
+    }
+
+    #[test]
+    fn test_out_of_range_values_are_rejected() {
+        let nice = Priority {
+            nice: Some(40),
+            ..Priority::default()
+        };
+        assert!(nice.apply_to_current_thread().is_err());
+        let io = Priority {
+            io: Some(IoPriority::BestEffort(8)),
+            ..Priority::default()
+        };
+        assert!(io.apply_to_current_thread().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_applies_to_a_thread() {
+        std::thread::spawn(|| {
+            let priority = Priority {
+                nice: Some(19),
+                io: Some(IoPriority::Idle),
+                background_qos: false,
+            };
+            priority.apply_to_current_thread().unwrap();
+            // SAFETY: `getpriority` takes plain integer arguments.
+            assert_eq!(unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }, 19);
+        })
+        .join()
+        .unwrap();
+    }
+}
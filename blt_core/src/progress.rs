@@ -0,0 +1,313 @@
+//! Live progress reporting for long runs.
+//!
+//! The pipeline adds every input chunk it writes to a shared [`Progress`]. When the input size
+//! is known up front (memory-mapped files and directory or list inputs), the total is set
+//! before the first document, which makes a percentage and an ETA available. A
+//! [`ProgressRenderer`] redraws a one-line summary on stderr until it is finished or dropped.
+//! For runs nobody watches on a terminal, [`CoreConfig::with_heartbeat`] logs the counters at
+//! `INFO` instead.
+//!
+//! [`CoreConfig::with_heartbeat`]: crate::CoreConfig::with_heartbeat
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How often [`ProgressRenderer`] redraws by default.
+pub const DEFAULT_REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How far a run has got, shared between the pipeline and a display.
+#[derive(Debug)]
+pub struct Progress {
+    processed: AtomicU64,
+    /// Token bytes written.
+    written: AtomicU64,
+    /// Chunks dispatched for tokenization but not yet written.
+    in_flight: AtomicU64,
+    /// The total input size, or `u64::MAX` while unknown.
+    total: AtomicU64,
+    started: Instant,
+}
+
+impl Progress {
+    /// Starts tracking a run whose total size is not yet known.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            processed: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            total: AtomicU64::new(u64::MAX),
+            started: Instant::now(),
+        })
+    }
+
+    pub(crate) fn add(&self, bytes: u64) {
+        self.processed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_written(&self, bytes: u64) {
+        self.written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn enqueue_chunks(&self, chunks: u64) {
+        self.in_flight.fetch_add(chunks, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dequeue_chunks(&self, chunks: u64) {
+        let _ = self
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(chunks))
+            });
+    }
+
+    pub(crate) fn set_total(&self, total: Option<u64>) {
+        self.total
+            .store(total.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// The counters as of now.
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let total = self.total.load(Ordering::Relaxed);
+        ProgressSnapshot {
+            processed: self.processed.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            total: (total != u64::MAX).then_some(total),
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+/// A point-in-time view of a run's progress.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressSnapshot {
+    /// Input bytes tokenized and written.
+    pub processed: u64,
+    /// Token bytes written.
+    pub written: u64,
+    /// Chunks dispatched for tokenization but not yet written.
+    pub in_flight: u64,
+    /// The total input size, if known.
+    pub total: Option<u64>,
+    /// Time since the run started.
+    pub elapsed: Duration,
+}
+
+impl ProgressSnapshot {
+    /// Average throughput since the start, in bytes per second.
+    pub fn bytes_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.processed as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// The estimated time left at the average throughput so far, if the total is known.
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.processed);
+        let rate = self.bytes_per_sec();
+        (rate > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+impl fmt::Display for ProgressSnapshot {
+    /// Renders e.g. `1.20 GB / 4.00 GB (30.0%)  150.3 MB/s  ETA 0:18`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Size(self.processed as f64))?;
+        if let Some(total) = self.total {
+            let percent = if total == 0 {
+                100.0
+            } else {
+                self.processed as f64 / total as f64 * 100.0
+            };
+            write!(f, " / {} ({percent:.1}%)", Size(total as f64))?;
+        }
+        write!(f, "  {}/s", Size(self.bytes_per_sec()))?;
+        if let Some(eta) = self.eta() {
+            let secs = eta.as_secs();
+            match secs / 3600 {
+                0 => write!(f, "  ETA {}:{:02}", secs / 60, secs % 60)?,
+                hours => write!(f, "  ETA {hours}:{:02}:{:02}", secs / 60 % 60, secs % 60)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A byte count rendered with a decimal unit.
+pub(crate) struct Size(pub(crate) f64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+        let mut value = self.0;
+        let mut unit = 0;
+        while value >= 1000.0 && unit < UNITS.len() - 1 {
+            value /= 1000.0;
+            unit += 1;
+        }
+        match unit {
+            0 => write!(f, "{value:.0} B"),
+            _ => write!(f, "{value:.2} {}", UNITS[unit]),
+        }
+    }
+}
+
+/// Redraws a run's progress on stderr from a background thread.
+pub struct ProgressRenderer {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ProgressRenderer {
+    /// Starts redrawing `progress` every `interval`. Callers should only do this when stderr
+    /// is a terminal, since each redraw rewrites the current line.
+    pub fn spawn(progress: Arc<Progress>, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let draw = |line: ProgressSnapshot| {
+                let mut stderr = io::stderr().lock();
+                let _ = write!(stderr, "\r\x1b[2K{line}");
+                let _ = stderr.flush();
+            };
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                draw(progress.snapshot());
+            }
+            draw(progress.snapshot());
+            let _ = writeln!(io::stderr());
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Draws the final state and ends the line.
+    pub fn finish(mut self) {
+        self.stop_thread();
+    }
+
+    fn stop_thread(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ProgressRenderer {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+/// Logs a run's progress at `INFO` from a background thread until dropped, so long runs
+/// report how far they have got in their logs.
+pub(crate) struct Heartbeat {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    /// Starts logging `progress` every `interval`, counting tokens `token_width` bytes wide.
+    /// The throughput logged is the average over the last interval, so a stalled run shows
+    /// up at once rather than being averaged away.
+    pub(crate) fn spawn(progress: Arc<Progress>, interval: Duration, token_width: u64) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut last = progress.snapshot();
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let now = progress.snapshot();
+                let secs = (now.elapsed - last.elapsed).as_secs_f64();
+                let bytes = now.processed.saturating_sub(last.processed);
+                let mb_per_sec = match secs > 0.0 {
+                    true => bytes as f64 / secs / 1e6,
+                    false => 0.0,
+                };
+                info!(
+                    bytes_read = now.processed,
+                    tokens_written = now.written / token_width.max(1),
+                    mb_per_sec = (mb_per_sec * 100.0).round() / 100.0,
+                    in_flight_chunks = now.in_flight,
+                    "Heartbeat: {now}"
+                );
+                last = now;
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_rendering() {
+        let snapshot = ProgressSnapshot {
+            processed: 1_200_000_000,
+            written: 0,
+            in_flight: 0,
+            total: Some(4_000_000_000),
+            elapsed: Duration::from_secs(8),
+        };
+        assert_eq!(snapshot.bytes_per_sec(), 150_000_000.0);
+        assert_eq!(
+            snapshot.eta(),
+            Some(Duration::from_secs_f64(2_800_000_000.0 / 150e6))
+        );
+        assert_eq!(
+            snapshot.to_string(),
+            "1.20 GB / 4.00 GB (30.0%)  150.00 MB/s  ETA 0:18"
+        );
+
+        let unknown = ProgressSnapshot {
+            processed: 512,
+            written: 0,
+            in_flight: 0,
+            total: None,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(unknown.eta(), None);
+        assert_eq!(unknown.to_string(), "512 B  256 B/s");
+    }
+
+    #[test]
+    fn test_counts_written_tokens_and_chunks_in_flight() {
+        let progress = Progress::new();
+        progress.enqueue_chunks(3);
+        progress.add(10);
+        progress.add_written(20);
+        progress.dequeue_chunks(1);
+        let snapshot = progress.snapshot();
+        assert_eq!((snapshot.processed, snapshot.written), (10, 20));
+        assert_eq!(snapshot.in_flight, 2);
+        progress.dequeue_chunks(5);
+        assert_eq!(progress.snapshot().in_flight, 0);
+
+        // Stops promptly when dropped, without waiting out the interval.
+        let started = Instant::now();
+        drop(Heartbeat::spawn(progress, Duration::from_secs(60), 2));
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+}
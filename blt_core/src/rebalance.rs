@@ -0,0 +1,522 @@
+//! Rewriting token shards into evenly sized ones.
+//!
+//! Runs that write one output per input leave shards whose sizes follow the inputs. [`rebalance`]
+//! rewrites a directory of shards into shards of about a target size, cutting only between
+//! documents. Document boundaries come from each shard's sidecar [index](crate::index), never
+//! from the bytes themselves, and every new shard gets the output header of the originals and
+//! an index of its own, so rebalanced shards can be read exactly like the ones they replace.
+//!
+//! The new shards are written to a hidden `.rebalance` directory next to the originals, which
+//! are only removed once every new shard and index is complete and synced to disk. If the final
+//! moves are interrupted, the remaining new shards are left in that directory.
+//!
+//! A document of an index is not a manifest entry, so a manifest that records the shards as
+//! outputs cannot follow its documents into the new shards. Rebalancing refuses to run when any
+//! of the manifests it is given does, rather than leave entries pointing at removed shards.
+
+use crate::header::{self, OutputHeader};
+use crate::index::{self, DocumentIndex};
+use crate::io_handler::{self, AtomicFile};
+use crate::manifest;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::info;
+
+/// The extension of the shards that are rebalanced.
+pub const SHARD_EXTENSION: &str = "bin";
+/// The name of the staging directory the new shards are written to.
+const STAGING_DIR: &str = ".rebalance";
+
+/// A shard written by [`rebalance`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RebalancedShard {
+    /// The shard's path.
+    pub path: PathBuf,
+    /// The number of documents in the shard.
+    pub documents: u64,
+    /// The shard's size in bytes, header included.
+    pub bytes: u64,
+}
+
+/// The outcome of rebalancing a directory of shards.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RebalanceReport {
+    /// The shards that were replaced, in the order their documents were read.
+    pub replaced: Vec<PathBuf>,
+    /// The shards that replaced them.
+    pub shards: Vec<RebalancedShard>,
+    /// The number of documents moved.
+    pub documents: u64,
+}
+
+impl fmt::Display for RebalanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} documents in {} shards rewritten into {} shards",
+            self.documents,
+            self.replaced.len(),
+            self.shards.len()
+        )?;
+        for shard in &self.shards {
+            writeln!(
+                f,
+                "{:>14} {:>10} {}",
+                shard.bytes,
+                shard.documents,
+                shard.path.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A document in an existing shard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Document {
+    /// The shard it is stored in, as an index into the shard list.
+    shard: usize,
+    /// The byte offset of its first token.
+    offset: u64,
+    /// The number of tokens in it.
+    tokens: u64,
+}
+
+/// An existing shard and its documents, validated against its index.
+struct Shard {
+    path: PathBuf,
+    header: Vec<u8>,
+    token_width: u16,
+    documents: Vec<Document>,
+}
+
+/// Rewrites the `*.bin` shards in `dir` into shards of about `target_size` bytes each, named
+/// `shard-00000.bin` onwards.
+///
+/// Every shard needs its sidecar index at `<shard>.idx`, as written by `--index`, and all shards
+/// must share the same output header, or all have none. A document larger than `target_size`
+/// gets a shard of its own. Fails without touching the shards if any of `manifests` records one
+/// of them as a document's output.
+pub async fn rebalance(
+    dir: &Path,
+    target_size: u64,
+    manifests: &[PathBuf],
+) -> io::Result<RebalanceReport> {
+    if target_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The target shard size must be positive",
+        ));
+    }
+    let paths = shard_paths(dir)?;
+    let mut shards = Vec::with_capacity(paths.len());
+    for (position, path) in paths.iter().enumerate() {
+        shards.push(read_shard(path, position).await?);
+    }
+    let Some(first) = shards.first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No .{SHARD_EXTENSION} shards in {}", dir.display()),
+        ));
+    };
+    let (header, token_width) = (first.header.clone(), first.token_width);
+    if let Some(other) = shards
+        .iter()
+        .find(|shard| shard.header != header || shard.token_width != token_width)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Shard {} was written with a different header than {}; only shards of one run \
+                 can be rebalanced together",
+                other.path.display(),
+                first.path.display()
+            ),
+        ));
+    }
+    for manifest_path in manifests {
+        refuse_recorded_shards(manifest_path, &shards).await?;
+    }
+
+    let documents: Vec<Document> = shards
+        .iter()
+        .flat_map(|shard| shard.documents.iter().copied())
+        .collect();
+    let sizes: Vec<u64> = documents
+        .iter()
+        .map(|document| document.tokens * u64::from(token_width))
+        .collect();
+    let plan = plan_shards(&sizes, target_size);
+
+    let staging = dir.join(STAGING_DIR);
+    if tokio::fs::try_exists(&staging).await? {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} exists, left by an interrupted rebalance; move its shards into place or \
+                 remove it",
+                staging.display()
+            ),
+        ));
+    }
+    tokio::fs::create_dir(&staging).await?;
+    let mut written = Vec::with_capacity(plan.len());
+    let mut start = 0;
+    for (number, &count) in plan.iter().enumerate() {
+        let name = format!("shard-{number:05}.{SHARD_EXTENSION}");
+        let group = &documents[start..start + count];
+        let staged = staging.join(&name);
+        let bytes = match write_shard(&staged, &header, token_width, group, &shards).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let _ = tokio::fs::remove_dir_all(&staging).await;
+                return Err(e);
+            }
+        };
+        written.push(RebalancedShard {
+            path: dir.join(name),
+            documents: count as u64,
+            bytes,
+        });
+        start += count;
+    }
+    // Each staged shard and index was synced as it was committed; the staging directory's own
+    // entry must be too before the originals are removed.
+    if let Err(e) = sync_parent(&staging).await {
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        return Err(e);
+    }
+
+    for shard in &shards {
+        tokio::fs::remove_file(&shard.path).await?;
+        tokio::fs::remove_file(index::default_path(&shard.path)).await?;
+    }
+    for shard in &written {
+        let name = shard.path.file_name().expect("shards are named");
+        let staged = staging.join(name);
+        tokio::fs::rename(
+            index::default_path(&staged),
+            index::default_path(&shard.path),
+        )
+        .await?;
+        tokio::fs::rename(&staged, &shard.path).await?;
+    }
+    sync_parent(&staging).await?;
+    tokio::fs::remove_dir(&staging).await?;
+    info!(
+        replaced = shards.len(),
+        shards = written.len(),
+        "Rebalanced shards"
+    );
+    Ok(RebalanceReport {
+        replaced: paths,
+        shards: written,
+        documents: documents.len() as u64,
+    })
+}
+
+/// Groups documents of the given sizes into shards of about `target_size` bytes, returning the
+/// number of documents in each shard.
+///
+/// The number of shards is the fewest that keeps their average within `target_size`, and each
+/// cut is made at the document boundary nearest to where an even split would cut.
+fn plan_shards(sizes: &[u64], target_size: u64) -> Vec<usize> {
+    let total: u64 = sizes.iter().sum();
+    let count = total.div_ceil(target_size).max(1);
+    let mut plan = Vec::new();
+    let (mut position, mut in_shard) = (0u64, 0usize);
+    for &size in sizes {
+        let cut = total * (plan.len() as u64 + 1) / count;
+        if in_shard > 0 && cut.abs_diff(position) <= cut.abs_diff(position + size) {
+            plan.push(in_shard);
+            in_shard = 0;
+        }
+        position += size;
+        in_shard += 1;
+    }
+    if in_shard > 0 || plan.is_empty() {
+        plan.push(in_shard);
+    }
+    plan
+}
+
+/// Fails if the manifest at `path` records any of `shards` as a document's output.
+async fn refuse_recorded_shards(path: &Path, shards: &[Shard]) -> io::Result<()> {
+    let entries = manifest::read_manifest(path).await?;
+    let shard_paths = shards
+        .iter()
+        .map(|shard| std::fs::canonicalize(&shard.path))
+        .collect::<io::Result<Vec<_>>>()?;
+    for entry in &entries {
+        let Some(output) = entry
+            .output
+            .as_ref()
+            .and_then(|output| std::fs::canonicalize(output).ok())
+        else {
+            continue;
+        };
+        if let Some(position) = shard_paths.iter().position(|shard| *shard == output) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Manifest {} records document {} in shard {}, which rebalancing would \
+                     remove; rebalance only shards that no manifest records as outputs",
+                    path.display(),
+                    entry.doc_id,
+                    shards[position].path.display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Syncs the directory holding `path`, so the entries created or removed in it are durable.
+async fn sync_parent(path: &Path) -> io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || io_handler::sync_parent(&path))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Lists the shards in `dir` in name order.
+fn shard_paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if !hidden && path.is_file() && path.extension().is_some_and(|ext| ext == SHARD_EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Reads the header and index of the shard at `path`, checking that the index covers the
+/// shard's token data exactly.
+async fn read_shard(path: &Path, position: usize) -> io::Result<Shard> {
+    let index_path = index::default_path(path);
+    let document_index = match index::read_index(&index_path).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Shard {} has no index at {}; rebalancing needs the document boundaries \
+                     recorded by --index",
+                    path.display(),
+                    index_path.display()
+                ),
+            ));
+        }
+        result => result?,
+    };
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let mut start = vec![0u8; header::HEADER_LEN.min(len as usize)];
+    file.read_exact(&mut start).await?;
+    let header = match header::has_header(&start) {
+        true => {
+            let (_, header_len) = OutputHeader::parse(&start)?;
+            let mut header = vec![0u8; header_len];
+            file.seek(io::SeekFrom::Start(0)).await?;
+            file.read_exact(&mut header).await?;
+            header
+        }
+        false => Vec::new(),
+    };
+
+    let token_width = document_index.token_width();
+    let mut end = header.len() as u64;
+    let mut documents = Vec::with_capacity(document_index.entries().len());
+    for entry in document_index.entries() {
+        if entry.byte_offset != end {
+            break;
+        }
+        documents.push(Document {
+            shard: position,
+            offset: entry.byte_offset,
+            tokens: entry.tokens,
+        });
+        end += entry.tokens * u64::from(token_width);
+    }
+    if documents.len() != document_index.entries().len() || end != len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Shard {} does not match its index {}",
+                path.display(),
+                index_path.display()
+            ),
+        ));
+    }
+    Ok(Shard {
+        path: path.to_path_buf(),
+        header,
+        token_width,
+        documents,
+    })
+}
+
+/// Writes `documents` after `header` to `path`, with its index, returning the shard's size.
+async fn write_shard(
+    path: &Path,
+    header: &[u8],
+    token_width: u16,
+    documents: &[Document],
+    shards: &[Shard],
+) -> io::Result<u64> {
+    // Committing the shard syncs it, and the staging directory it is renamed into.
+    let mut output = tokio::io::BufWriter::new(AtomicFile::create(path, false).await?);
+    output.write_all(header).await?;
+    let mut document_index = DocumentIndex::new(token_width, header.len() as u64);
+    let mut open: Option<(usize, tokio::fs::File)> = None;
+    for document in documents {
+        let input = match &mut open {
+            Some((shard, file)) if *shard == document.shard => file,
+            _ => {
+                let file = tokio::fs::File::open(&shards[document.shard].path).await?;
+                &mut open.insert((document.shard, file)).1
+            }
+        };
+        input.seek(io::SeekFrom::Start(document.offset)).await?;
+        let len = document.tokens * u64::from(token_width);
+        let copied = tokio::io::copy(&mut input.take(len), &mut output).await?;
+        if copied != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Shard {} ended inside a document",
+                    shards[document.shard].path.display()
+                ),
+            ));
+        }
+        document_index.push(document.tokens);
+    }
+    output.shutdown().await?;
+    index::write_index(&index::default_path(path), &document_index, false).await?;
+    Ok(header.len() as u64
+        + document_index
+            .entries()
+            .iter()
+            .map(|entry| entry.tokens * u64::from(token_width))
+            .sum::<u64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StrategyKind;
+
+    /// Writes a shard of documents whose tokens are all their ordinal, with its index.
+    fn write_shard_fixture(path: &Path, header: &[u8], documents: &[(u16, u64)]) {
+        let mut bytes = header.to_vec();
+        let mut document_index = DocumentIndex::new(2, header.len() as u64);
+        for &(token, tokens) in documents {
+            for _ in 0..tokens {
+                bytes.extend_from_slice(&token.to_be_bytes());
+            }
+            document_index.push(tokens);
+        }
+        std::fs::write(path, bytes).unwrap();
+        std::fs::write(index::default_path(path), document_index.to_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_plan_cuts_at_nearest_document_boundaries() {
+        assert_eq!(plan_shards(&[10, 10, 10, 10], 20), vec![2, 2]);
+        assert_eq!(plan_shards(&[30, 5, 5, 5, 5], 25), vec![1, 4]);
+        assert_eq!(plan_shards(&[5, 100, 5], 10), vec![1, 1, 1]);
+        assert_eq!(plan_shards(&[1, 1, 1], 100), vec![3]);
+        assert_eq!(plan_shards(&[], 100), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn test_rebalances_on_document_boundaries() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let header = OutputHeader::new(StrategyKind::Basic, None).to_bytes();
+        write_shard_fixture(&dir.path().join("a.bin"), &header, &[(1, 30)]);
+        write_shard_fixture(&dir.path().join("b.bin"), &header, &[(2, 10), (3, 10)]);
+        write_shard_fixture(&dir.path().join("c.bin"), &header, &[(4, 5), (5, 5)]);
+
+        let report = rebalance(dir.path(), 64, &[]).await?;
+        assert_eq!(report.replaced.len(), 3);
+        assert_eq!(report.documents, 5);
+        let documents: Vec<u64> = report.shards.iter().map(|s| s.documents).collect();
+        assert_eq!(documents, vec![1, 4]);
+        assert!(!dir.path().join("a.bin").exists());
+        assert!(!dir.path().join(STAGING_DIR).exists());
+
+        let mut tokens = Vec::new();
+        for shard in &report.shards {
+            let bytes = std::fs::read(&shard.path)?;
+            assert_eq!(bytes.len() as u64, shard.bytes);
+            assert_eq!(bytes[..header.len()], header);
+            let document_index = index::read_index(&index::default_path(&shard.path)).await?;
+            for entry in document_index.entries() {
+                let start = entry.byte_offset as usize;
+                let document = &bytes[start..start + 2 * entry.tokens as usize];
+                assert!(document.chunks(2).all(|t| t == &document[..2]));
+                tokens.push((document[1], entry.tokens));
+            }
+        }
+        assert_eq!(tokens, vec![(1, 30), (2, 10), (3, 10), (4, 5), (5, 5)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_shards_without_matching_index() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let shard = dir.path().join("a.bin");
+        write_shard_fixture(&shard, &[], &[(1, 4)]);
+        std::fs::write(&shard, [0u8; 6])?;
+        let err = rebalance(dir.path(), 4, &[]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_file(index::default_path(&shard))?;
+        let err = rebalance(dir.path(), 4, &[]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(shard.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refuses_shards_recorded_in_a_manifest() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let shard = dir.path().join("a.bin");
+        write_shard_fixture(&shard, &[], &[(1, 4), (2, 4)]);
+        let entry = |output: Option<&Path>| manifest::ManifestEntry {
+            doc_id: 0,
+            id: None,
+            source: "a.txt".to_string(),
+            input_bytes: 8,
+            output_bytes: 16,
+            tokens: 8,
+            content_hash: None,
+            output: output.map(|output| output.display().to_string()),
+            output_hash: None,
+            snapshot: None,
+            content_type: None,
+        };
+        let unrelated = dir.path().join("run.jsonl");
+        manifest::write_manifest(&unrelated, &[entry(None)]).await?;
+        let recording = dir.path().join("outputs.jsonl");
+        manifest::write_manifest(&recording, &[entry(Some(&shard))]).await?;
+
+        let manifests = [unrelated.clone(), recording];
+        let err = rebalance(dir.path(), 8, &manifests).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("outputs.jsonl"), "{err}");
+        assert!(shard.exists());
+        assert!(!dir.path().join(STAGING_DIR).exists());
+
+        let report = rebalance(dir.path(), 8, &[unrelated]).await?;
+        assert_eq!(report.shards.len(), 2);
+        Ok(())
+    }
+}
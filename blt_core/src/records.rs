@@ -0,0 +1,267 @@
+//! Record-aware tokenization of length-prefixed binary formats.
+//!
+//! Many binary inputs are sequences of records, each a fixed-size header holding the length
+//! of the payload that follows: TLV streams, length-delimited protobufs, many log and capture
+//! formats. Cut at arbitrary byte offsets, a chunk starts mid-record and merges run across
+//! record boundaries. With a [`RecordLayout`], chunks end on record boundaries (see
+//! [`RecordLayout::whole_records`]) and [`RecordFramedStrategy`] tokenizes each header and
+//! payload on its own, so no token spans two records or a header and its payload.
+//!
+//! Bytes that do not parse as a record, such as a truncated last record or a length running
+//! past the end of the input, are tokenized as they are. A record larger than a chunk is
+//! split at the chunk boundary.
+
+use crate::buffers;
+use crate::tokenizer::{ExpansionEstimate, TokenizationStrategy};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The byte order of a length field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    /// Most significant byte first.
+    Big,
+    /// Least significant byte first.
+    Little,
+}
+
+/// Describes the header of each record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordLayout {
+    /// Bytes before the length field, such as a type tag.
+    pub tag_bytes: usize,
+    /// The width of the length field in bytes: 1, 2, 4, or 8.
+    pub length_bytes: usize,
+    /// The byte order of the length field.
+    pub endian: Endian,
+    /// Whether the length counts the header too, rather than only the payload.
+    pub length_includes_header: bool,
+}
+
+impl RecordLayout {
+    /// The size of each record's header.
+    pub fn header_len(&self) -> usize {
+        self.tag_bytes + self.length_bytes
+    }
+
+    /// The total length of the record at the start of `bytes`, or `None` if its header is
+    /// incomplete or its length is shorter than its header.
+    pub fn record_len(&self, bytes: &[u8]) -> Option<usize> {
+        let field = bytes.get(self.tag_bytes..self.header_len())?;
+        let length = match self.endian {
+            Endian::Big => field.iter().fold(0u64, |acc, &b| acc << 8 | u64::from(b)),
+            Endian::Little => field
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &b| acc << 8 | u64::from(b)),
+        };
+        let length = usize::try_from(length).ok()?;
+        if self.length_includes_header {
+            (length >= self.header_len()).then_some(length)
+        } else {
+            length.checked_add(self.header_len())
+        }
+    }
+
+    /// The length of the longest prefix of `bytes` made of whole records, possibly 0.
+    pub fn whole_records(&self, bytes: &[u8]) -> usize {
+        let mut end = 0;
+        while let Some(len) = self.record_len(&bytes[end..]) {
+            if len > bytes.len() - end {
+                break;
+            }
+            end += len;
+        }
+        end
+    }
+}
+
+impl fmt::Display for RecordLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tag_bytes > 0 {
+            write!(f, "tag={},", self.tag_bytes)?;
+        }
+        write!(f, "len=u{}", self.length_bytes * 8)?;
+        if self.length_bytes > 1 {
+            f.write_str(match self.endian {
+                Endian::Big => "be",
+                Endian::Little => "le",
+            })?;
+        }
+        if self.length_includes_header {
+            f.write_str(",inclusive")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RecordLayout {
+    type Err = String;
+
+    /// Parses comma-separated fields: `tag=N` (tag bytes before the length, default 0),
+    /// `len=u8|u16be|u16le|u32be|u32le|u64be|u64le` (required), and `inclusive` when the
+    /// length counts the header. For example `tag=1,len=u32be`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tag_bytes = 0;
+        let mut length = None;
+        let mut length_includes_header = false;
+        for field in s.split(',').map(str::trim) {
+            match field.split_once('=') {
+                Some(("tag", n)) => {
+                    tag_bytes = n
+                        .parse()
+                        .map_err(|_| format!("Invalid tag size '{n}' in record layout '{s}'"))?;
+                }
+                Some(("len", width)) => {
+                    length = Some(match width.to_ascii_lowercase().as_str() {
+                        "u8" => (1, Endian::Big),
+                        "u16be" => (2, Endian::Big),
+                        "u16le" => (2, Endian::Little),
+                        "u32be" => (4, Endian::Big),
+                        "u32le" => (4, Endian::Little),
+                        "u64be" => (8, Endian::Big),
+                        "u64le" => (8, Endian::Little),
+                        _ => {
+                            return Err(format!(
+                                "Unsupported length field '{width}' in record layout '{s}'. Use u8, u16be, u16le, u32be, u32le, u64be, or u64le."
+                            ))
+                        }
+                    });
+                }
+                None if field == "inclusive" => length_includes_header = true,
+                _ => return Err(format!("Unknown field '{field}' in record layout '{s}'")),
+            }
+        }
+        let (length_bytes, endian) =
+            length.ok_or_else(|| format!("Record layout '{s}' has no len= field"))?;
+        Ok(Self {
+            tag_bytes,
+            length_bytes,
+            endian,
+            length_includes_header,
+        })
+    }
+}
+
+/// Tokenizes each record's header and payload separately with an inner strategy.
+pub struct RecordFramedStrategy {
+    inner: Arc<dyn TokenizationStrategy>,
+    layout: RecordLayout,
+}
+
+impl RecordFramedStrategy {
+    /// Splits chunks into records per `layout` and tokenizes the pieces with `inner`.
+    pub fn new(inner: Arc<dyn TokenizationStrategy>, layout: RecordLayout) -> Self {
+        Self { inner, layout }
+    }
+
+    /// Splits `chunk` into the pieces tokenized separately: each record's header and
+    /// payload, then any bytes after the last whole record.
+    fn pieces<'a>(&self, chunk: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut pieces = Vec::new();
+        let mut rest = chunk;
+        while let Some(len) = self.layout.record_len(rest) {
+            if len > rest.len() {
+                break;
+            }
+            let (record, tail) = rest.split_at(len);
+            let (header, payload) = record.split_at(self.layout.header_len());
+            pieces.push(header);
+            pieces.push(payload);
+            rest = tail;
+        }
+        pieces.push(rest);
+        pieces.retain(|piece| !piece.is_empty());
+        pieces
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for RecordFramedStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut tokens = buffers::shared().take_output(self, chunk_data.len());
+        for piece in self.pieces(chunk_data) {
+            let piece_tokens = self.inner.process_chunk(piece).await?;
+            tokens.extend_from_slice(&piece_tokens);
+            buffers::shared().give(piece_tokens);
+        }
+        Ok(tokens)
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        self.inner.estimated_expansion(input_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::BpeStrategy;
+    use crate::BpeMerges;
+
+    fn tlv() -> RecordLayout {
+        "tag=1,len=u16be".parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_layouts() {
+        assert_eq!(
+            tlv(),
+            RecordLayout {
+                tag_bytes: 1,
+                length_bytes: 2,
+                endian: Endian::Big,
+                length_includes_header: false,
+            }
+        );
+        for layout in ["len=u8", "tag=2,len=u32le,inclusive", "len=u64be"] {
+            assert_eq!(layout.parse::<RecordLayout>().unwrap().to_string(), layout);
+        }
+        assert!("tag=1".parse::<RecordLayout>().is_err());
+        assert!("len=u24be".parse::<RecordLayout>().is_err());
+        assert!("len=u8,crc".parse::<RecordLayout>().is_err());
+    }
+
+    #[test]
+    fn test_whole_records() {
+        let layout = tlv();
+        let data = [1, 0, 2, b'a', b'b', 2, 0, 1, b'c', 3, 0];
+        assert_eq!(layout.record_len(&data), Some(5));
+        assert_eq!(layout.whole_records(&data), 9);
+        assert_eq!(layout.whole_records(&data[..8]), 5);
+        assert_eq!(layout.whole_records(&data[..4]), 0);
+
+        let inclusive: RecordLayout = "len=u8,inclusive".parse().unwrap();
+        assert_eq!(inclusive.whole_records(&[2, b'a', 3, b'b', b'c']), 5);
+        // A length shorter than the header is not a record.
+        assert_eq!(inclusive.whole_records(&[0, b'a']), 0);
+        let little: RecordLayout = "len=u16le".parse().unwrap();
+        assert_eq!(little.record_len(&[1, 0]), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_merges_stay_within_records() -> io::Result<()> {
+        // "ab" merges across the first payload and the second tag, and within the second
+        // payload.
+        let merges: BpeMerges = [((97, 98), 256)].into_iter().collect();
+        let bpe: Arc<dyn TokenizationStrategy> = Arc::new(BpeStrategy::new(Arc::new(merges)));
+        let data = [b'b', 0, 1, b'a', b'b', 0, 2, b'a', b'b', 9];
+        assert_eq!(bpe.process_chunk(&data).await?.len(), 8 * 2);
+
+        let framed = RecordFramedStrategy::new(bpe, tlv());
+        assert_eq!(
+            framed.pieces(&data),
+            [&[b'b', 0, 1][..], b"a", &[b'b', 0, 2], b"ab", &[9]]
+        );
+        let tokens: Vec<u16> = framed
+            .process_chunk(&data)
+            .await?
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(tokens, [98, 0, 1, 97, 98, 0, 2, 256, 9]);
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+//! Rebuilding damaged per-document outputs.
+//!
+//! A manifest written by a run with an output directory records, for every document, the file
+//! it was written to and a hash of that file's bytes. [`repair`] checks each file against its
+//! hash and re-tokenizes only the documents whose files are missing or differ, from the sources
+//! recorded in the manifest and with the configuration of the original run. Replacements are
+//! staged next to the outputs they replace and moved into place only once every damaged
+//! document has been rebuilt, its source confirmed unchanged, and its replacement synced to
+//! disk; the manifest is then rewritten atomically.
+
+use crate::hashing::{self, HashAlgorithm};
+use crate::io_handler;
+use crate::manifest::{self, ManifestEntry};
+use crate::{run_tokenizer, CoreConfig};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Why an output failed validation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Damage {
+    /// The output file does not exist.
+    Missing,
+    /// The output file could not be read.
+    Unreadable {
+        /// The error reading the file.
+        error: String,
+    },
+    /// The output file's bytes do not match the hash in the manifest.
+    HashMismatch {
+        /// The hash of the file as found.
+        actual: String,
+    },
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Damage::Missing => f.write_str("missing"),
+            Damage::Unreadable { .. } => f.write_str("unreadable"),
+            Damage::HashMismatch { .. } => f.write_str("hash mismatch"),
+        }
+    }
+}
+
+/// An output that failed validation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DamagedOutput {
+    /// The document's ordinal in the manifest.
+    pub doc_id: u64,
+    /// The document's source, which it is rebuilt from.
+    pub source: String,
+    /// The damaged output file.
+    pub output: PathBuf,
+    /// What is wrong with it.
+    pub damage: Damage,
+}
+
+/// The outcome of checking, and optionally rebuilding, the outputs of a manifest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RepairReport {
+    /// The number of outputs checked.
+    pub checked: u64,
+    /// The outputs that failed validation, in manifest order.
+    pub damaged: Vec<DamagedOutput>,
+    /// The number of damaged outputs rebuilt.
+    pub repaired: u64,
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} outputs checked, {} damaged, {} repaired",
+            self.checked,
+            self.damaged.len(),
+            self.repaired
+        )?;
+        for damaged in &self.damaged {
+            writeln!(
+                f,
+                "{:<14} {:>8} {} ({})",
+                damaged.damage.to_string(),
+                damaged.doc_id,
+                damaged.output.display(),
+                damaged.source
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the output of every entry against its recorded hash.
+///
+/// Fails if an entry records no output or output hash, as in manifests of runs without an
+/// output directory, or if two entries share an output.
+pub fn check_outputs(entries: &[ManifestEntry]) -> io::Result<Vec<DamagedOutput>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut damaged = Vec::new();
+    for entry in entries {
+        let (output, expected) = recorded_output(entry)?;
+        if !seen.insert(output.clone()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Output {} is shared by several documents and cannot be repaired",
+                    output.display()
+                ),
+            ));
+        }
+        let damage = match std::fs::File::open(&output) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Some(Damage::Missing),
+            Err(e) => Some(Damage::Unreadable {
+                error: e.to_string(),
+            }),
+            Ok(file) => match hashing::hash_reader(hash_algorithm(expected)?, file) {
+                Ok(actual) if actual == expected => None,
+                Ok(actual) => Some(Damage::HashMismatch { actual }),
+                Err(e) => Some(Damage::Unreadable {
+                    error: e.to_string(),
+                }),
+            },
+        };
+        if let Some(damage) = damage {
+            damaged.push(DamagedOutput {
+                doc_id: entry.doc_id,
+                source: entry.source.clone(),
+                output,
+                damage,
+            });
+        }
+    }
+    Ok(damaged)
+}
+
+/// Checks the outputs recorded in `config.manifest` and, unless `check_only` is set,
+/// re-tokenizes the documents whose outputs are damaged with `config`, which should match the
+/// configuration of the run that wrote the manifest.
+///
+/// A document whose source no longer matches its recorded content hash is not rebuilt, and the
+/// repair fails without touching the manifest.
+pub async fn repair(config: CoreConfig, check_only: bool) -> io::Result<RepairReport> {
+    let manifest_path = config.manifest.clone().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Repair requires the manifest of the run that wrote the outputs",
+        )
+    })?;
+    let mut entries = manifest::read_manifest(&manifest_path).await?;
+    let damaged = check_outputs(&entries)?;
+    let mut report = RepairReport {
+        checked: entries.len() as u64,
+        damaged,
+        repaired: 0,
+    };
+    if check_only || report.damaged.is_empty() {
+        return Ok(report);
+    }
+    let mut rebuilt = Vec::with_capacity(report.damaged.len());
+    for damaged in &report.damaged {
+        let index = entries
+            .iter()
+            .position(|entry| entry.doc_id == damaged.doc_id)
+            .expect("damaged outputs come from the manifest");
+        info!(doc_id = damaged.doc_id, output = %damaged.output.display(), "Rebuilding output");
+        match rebuild(&config, &entries[index], &manifest_path).await {
+            Ok(entry) => rebuilt.push((index, entry, &damaged.output)),
+            Err(e) => {
+                for (_, _, output) in &rebuilt {
+                    let _ = tokio::fs::remove_file(staging_path(output)).await;
+                }
+                return Err(e);
+            }
+        }
+    }
+    // Every replacement was synced when it was written, so only the renames remain to be made
+    // durable before the manifest records them: one output per directory is enough to find it.
+    let mut dirs = BTreeMap::new();
+    for (index, entry, output) in rebuilt {
+        tokio::fs::rename(staging_path(output), output).await?;
+        dirs.entry(output.parent())
+            .or_insert_with(|| output.clone());
+        entries[index] = entry;
+        report.repaired += 1;
+    }
+    for output in dirs.into_values() {
+        tokio::task::spawn_blocking(move || io_handler::sync_parent(&output))
+            .await
+            .map_err(io::Error::other)??;
+    }
+    manifest::write_manifest(&manifest_path, &entries).await?;
+    Ok(report)
+}
+
+/// Re-tokenizes the source of `entry` into a replacement staged at the
+/// [`staging_path`] of its output and returns its updated entry.
+async fn rebuild(
+    config: &CoreConfig,
+    entry: &ManifestEntry,
+    manifest_path: &Path,
+) -> io::Result<ManifestEntry> {
+    let (output, expected) = recorded_output(entry)?;
+    let source = PathBuf::from(&entry.source);
+    if !source.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Source {} of document {} is not a file that can be re-read",
+                entry.source, entry.doc_id
+            ),
+        ));
+    }
+    let staged_output = staging_path(&output);
+    let staged_manifest = staging_path(manifest_path);
+    let content_algorithm = match &entry.content_hash {
+        Some(hash) => hash_algorithm(hash)?,
+        None => config.hash_algorithm,
+    };
+    let document_config = CoreConfig {
+        input: Some(source),
+        output: Some(staged_output.clone()),
+        output_dir: None,
+        filelist: None,
+        archive: false,
+        byte_range: None,
+        follow: None,
+        manifest: Some(staged_manifest.clone()),
+        previous_manifest: None,
+        hash_algorithm: content_algorithm,
+        overwrite: true,
+        index: None,
+        tee_original: None,
+        expected_output_hash: None,
+        progress: None,
+        ..config.clone()
+    };
+    let rebuilt = match run_tokenizer(document_config).await {
+        Ok(_) => manifest::read_manifest(&staged_manifest).await,
+        Err(e) => Err(e.into()),
+    };
+    let _ = tokio::fs::remove_file(&staged_manifest).await;
+    let rebuilt = rebuilt.and_then(|rebuilt| match <[ManifestEntry; 1]>::try_from(rebuilt) {
+        Ok([rebuilt])
+            if entry.content_hash.is_none() || rebuilt.content_hash == entry.content_hash =>
+        {
+            Ok(rebuilt)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Source {} changed since the manifest was written; document {} cannot be rebuilt",
+                entry.source, entry.doc_id
+            ),
+        )),
+    });
+    let rebuilt = match rebuilt {
+        Ok(rebuilt) => rebuilt,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&staged_output).await;
+            return Err(e);
+        }
+    };
+    let output_hash = std::fs::File::open(&staged_output)
+        .and_then(|file| hashing::hash_reader(hash_algorithm(expected)?, file));
+    let output_hash = match output_hash {
+        Ok(output_hash) => output_hash,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&staged_output).await;
+            return Err(e);
+        }
+    };
+    Ok(ManifestEntry {
+        doc_id: entry.doc_id,
+        id: entry.id.clone(),
+        source: entry.source.clone(),
+        output: entry.output.clone(),
+        output_hash: Some(output_hash),
+        snapshot: entry.snapshot.clone(),
+        content_type: entry.content_type.clone(),
+        ..rebuilt
+    })
+}
+
+/// Returns the output path and output hash recorded for `entry`.
+fn recorded_output(entry: &ManifestEntry) -> io::Result<(PathBuf, &str)> {
+    match (&entry.output, &entry.output_hash) {
+        (Some(output), Some(hash)) => Ok((PathBuf::from(output), hash)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Document {} records no output hash; only manifests of runs with an output \
+                 directory can be repaired",
+                entry.doc_id
+            ),
+        )),
+    }
+}
+
+/// Returns the algorithm of a `"<algorithm>:<hex>"` hash.
+fn hash_algorithm(hash: &str) -> io::Result<HashAlgorithm> {
+    let (algorithm, _) = hash.split_once(':').unwrap_or((hash, ""));
+    algorithm
+        .parse()
+        .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The hidden sibling `.<name>.repair` that a replacement is staged at until every replacement
+/// is ready to be moved into place.
+fn staging_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.repair"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::OutputDir;
+
+    #[tokio::test]
+    async fn test_rebuilds_only_damaged_outputs() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input)?;
+        std::fs::write(input.join("a.txt"), "first document")?;
+        std::fs::write(input.join("b.txt"), "second document")?;
+        std::fs::write(input.join("c.txt"), "third document")?;
+        let out = dir.path().join("out");
+        let manifest_path = dir.path().join("manifest.jsonl");
+        let config = CoreConfig {
+            input: Some(input.clone()),
+            ..CoreConfig::default()
+        }
+        .with_output_dir(Some(OutputDir::new(&out)))
+        .with_manifest(Some(manifest_path.clone()), HashAlgorithm::Blake3);
+        run_tokenizer(config.clone()).await?;
+        let original = manifest::read_manifest(&manifest_path).await?;
+        assert!(original.iter().all(|entry| entry.output_hash.is_some()));
+        let intact = std::fs::read(out.join("b.bin"))?;
+
+        std::fs::remove_file(out.join("a.bin"))?;
+        std::fs::write(out.join("c.bin"), b"\0t")?;
+        let report = repair(config.clone(), true).await?;
+        let damage: Vec<_> = report.damaged.iter().map(|d| &d.damage).collect();
+        assert_eq!(damage.len(), 2);
+        assert_eq!(damage[0], &Damage::Missing);
+        assert!(matches!(damage[1], Damage::HashMismatch { .. }));
+        assert_eq!(report.repaired, 0);
+        assert!(!out.join("a.bin").exists());
+
+        let report = repair(config.clone(), false).await?;
+        assert_eq!((report.checked, report.repaired), (3, 2));
+        assert_eq!(std::fs::read(out.join("b.bin"))?, intact);
+        assert_eq!(manifest::read_manifest(&manifest_path).await?, original);
+        assert!(check_outputs(&original)?.is_empty());
+
+        // A changed source is not silently re-tokenized into different output.
+        std::fs::remove_file(out.join("a.bin"))?;
+        std::fs::write(input.join("a.txt"), "edited document")?;
+        let err = repair(config.clone(), false).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!out.join("a.bin").exists());
+        assert_eq!(std::fs::read_dir(&out)?.count(), 2);
+
+        // Nothing is replaced unless every damaged document can be rebuilt.
+        std::fs::write(input.join("a.txt"), "first document")?;
+        std::fs::write(out.join("c.bin"), b"\0t")?;
+        std::fs::write(input.join("c.txt"), "edited document")?;
+        let err = repair(config, false).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!out.join("a.bin").exists());
+        assert_eq!(std::fs::read(out.join("c.bin"))?, b"\0t");
+        assert_eq!(std::fs::read_dir(&out)?.count(), 2);
+        assert_eq!(manifest::read_manifest(&manifest_path).await?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn test_requires_recorded_outputs() {
+        let entry = ManifestEntry {
+            doc_id: 0,
+            id: None,
+            source: "input.txt".to_string(),
+            input_bytes: 1,
+            output_bytes: 2,
+            tokens: 1,
+            content_hash: None,
+            output: None,
+            output_hash: None,
+            snapshot: None,
+            content_type: None,
+        };
+        let err = check_outputs(&[entry]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
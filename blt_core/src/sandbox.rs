@@ -0,0 +1,263 @@
+//! Running chunk processing in sandboxed worker processes.
+//!
+//! [`SandboxedStrategy`] forwards every chunk to a pool of worker subprocesses over pipes
+//! instead of processing it in-process. A worker that panics, crashes, or is killed only fails
+//! the chunk it was processing; the next chunk gets a fresh worker. On Linux (x86_64 and
+//! aarch64) workers install a seccomp filter before reading any input, so a compromised
+//! worker can do little beyond reading its pipe, writing results, and allocating memory.
+//!
+//! Parent-side pooling requires the `sandbox` feature. Workers are started by running [`SandboxConfig::program`] with [`SandboxConfig::args`];
+//! the `blt` binary serves the protocol through its hidden `sandbox-worker` subcommand,
+//! which calls [`run_worker`].
+//!
+//! The protocol is a sequence of big-endian length-prefixed frames. The parent first sends
+//! the strategy (a kind byte, a merge count, and `(a, b, token)` triples), then one frame per
+//! chunk. Each chunk is answered with a status byte (`0` for success) and a frame holding the
+//! output, or an error message.
+
+use crate::tokenizer::{
+    BasicTokenizationStrategy, BpeStrategy, PassthroughStrategy, TokenizationStrategy,
+};
+use crate::{buffers, BpeMerges, StrategyKind};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[cfg(feature = "sandbox")]
+mod pool;
+#[cfg(all(
+    feature = "sandbox",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod seccomp;
+#[cfg(feature = "sandbox")]
+pub use pool::SandboxedStrategy;
+
+pub(crate) const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+
+/// How sandboxed workers are started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SandboxConfig {
+    /// The maximum number of concurrent worker processes.
+    pub workers: usize,
+    /// The program serving the worker protocol.
+    pub program: PathBuf,
+    /// Arguments passed to `program`.
+    pub args: Vec<String>,
+}
+
+impl SandboxConfig {
+    /// Runs up to `workers` workers using the current executable's `sandbox-worker`
+    /// subcommand, which is what the `blt` binary provides.
+    pub fn new(workers: usize) -> io::Result<Self> {
+        Ok(Self {
+            workers: workers.max(1),
+            program: std::env::current_exe()?,
+            args: vec!["sandbox-worker".to_string()],
+        })
+    }
+}
+
+pub(crate) fn frame_len(len: usize) -> io::Result<[u8; 4]> {
+    u32::try_from(len)
+        .map(u32::to_be_bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Chunk too large for sandbox"))
+}
+
+#[cfg(any(feature = "sandbox", test))]
+pub(crate) fn encode_init(kind: StrategyKind, merges: Option<&BpeMerges>) -> Vec<u8> {
+    let kind_byte = match kind {
+        StrategyKind::Passthrough => 0,
+        StrategyKind::Basic => 1,
+        StrategyKind::Bpe => 2,
+    };
+    let merges: Vec<_> = merges.map(|m| m.iter().collect()).unwrap_or_default();
+    let mut init = Vec::with_capacity(5 + merges.len() * 6);
+    init.push(kind_byte);
+    init.extend_from_slice(&(merges.len() as u32).to_be_bytes());
+    for (&(a, b), &token) in merges {
+        for value in [a, b, token] {
+            init.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    init
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u16(input: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    input.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn decode_init(input: &mut impl Read) -> io::Result<Arc<dyn TokenizationStrategy>> {
+    let mut kind = [0u8; 1];
+    input.read_exact(&mut kind)?;
+    let count = read_u32(input)?;
+    let mut merges = BpeMerges::with_capacity_and_hasher(count as usize, Default::default());
+    for _ in 0..count {
+        let pair = (read_u16(input)?, read_u16(input)?);
+        merges.insert(pair, read_u16(input)?);
+    }
+    match kind[0] {
+        0 => Ok(Arc::new(PassthroughStrategy)),
+        1 => Ok(Arc::new(BasicTokenizationStrategy)),
+        2 => Ok(Arc::new(BpeStrategy::new(Arc::new(merges)))),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown sandbox strategy kind {other}"),
+        )),
+    }
+}
+
+/// Wraps the `kind` strategy so it runs in workers started per `sandbox`.
+#[cfg(feature = "sandbox")]
+pub(crate) fn sandboxed_strategy(
+    sandbox: &SandboxConfig,
+    kind: StrategyKind,
+    merges: Option<&BpeMerges>,
+) -> io::Result<Arc<dyn TokenizationStrategy>> {
+    Ok(Arc::new(SandboxedStrategy::new(
+        sandbox.clone(),
+        kind,
+        merges,
+    )))
+}
+
+#[cfg(not(feature = "sandbox"))]
+pub(crate) fn sandboxed_strategy(
+    _sandbox: &SandboxConfig,
+    _kind: StrategyKind,
+    _merges: Option<&BpeMerges>,
+) -> io::Result<Arc<dyn TokenizationStrategy>> {
+    Err(missing_feature())
+}
+
+/// Serves the worker protocol on standard input and output inside a sandbox.
+///
+/// On Linux x86_64 and aarch64 this installs the seccomp filter first and fails if it
+/// cannot; elsewhere the worker only gets process isolation.
+pub fn run_worker() -> io::Result<()> {
+    // Sizing the shared buffer pool counts cores, which the sandbox no longer allows.
+    buffers::shared();
+    enter_sandbox()?;
+    serve(io::stdin().lock(), io::stdout().lock())
+}
+
+#[cfg(all(
+    feature = "sandbox",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn enter_sandbox() -> io::Result<()> {
+    seccomp::install()
+}
+
+#[cfg(all(
+    feature = "sandbox",
+    not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))
+))]
+fn enter_sandbox() -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(feature = "sandbox"))]
+fn enter_sandbox() -> io::Result<()> {
+    Err(missing_feature())
+}
+
+#[cfg(not(feature = "sandbox"))]
+fn missing_feature() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Sandboxed processing requires blt to be built with the `sandbox` feature",
+    )
+}
+
+/// Serves the worker protocol until `input` is closed.
+fn serve(mut input: impl Read, mut output: impl Write) -> io::Result<()> {
+    let strategy = decode_init(&mut input)?;
+    loop {
+        let len = match read_u32(&mut input) {
+            Ok(len) => len as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut chunk = vec![0u8; len];
+        input.read_exact(&mut chunk)?;
+        let (status, payload) = match block_on(strategy.process_chunk(&chunk)) {
+            Ok(tokens) => (STATUS_OK, tokens),
+            Err(e) => (STATUS_ERROR, e.to_string().into_bytes()),
+        };
+        output.write_all(&[status])?;
+        output.write_all(&frame_len(payload.len())?)?;
+        output.write_all(&payload)?;
+        output.flush()?;
+        buffers::shared().give(payload);
+    }
+}
+
+/// Drives a future that never waits on I/O to completion without an async runtime, which
+/// would need system calls the sandbox does not allow.
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(chunks: &[&[u8]], kind: StrategyKind, merges: Option<&BpeMerges>) -> Vec<u8> {
+        let mut input = encode_init(kind, merges);
+        for chunk in chunks {
+            input.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            input.extend_from_slice(chunk);
+        }
+        let mut output = Vec::new();
+        serve(io::Cursor::new(input), &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_serve_bpe_chunks() {
+        let merges: BpeMerges = [((97, 98), 256)].into_iter().collect();
+        let output = request(&[b"abc", b""], StrategyKind::Bpe, Some(&merges));
+        assert_eq!(
+            output,
+            [
+                &[STATUS_OK, 0, 0, 0, 4, 1, 0, 0, 99][..],
+                &[STATUS_OK, 0, 0, 0, 0]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_serve_passthrough() {
+        let output = request(&[b"hi"], StrategyKind::Passthrough, None);
+        assert_eq!(output, vec![STATUS_OK, 0, 0, 0, 2, b'h', b'i']);
+    }
+
+    #[test]
+    fn test_unknown_kind_is_rejected() {
+        let input = vec![9, 0, 0, 0, 0];
+        assert!(serve(io::Cursor::new(input), Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! The pool of worker processes behind [`SandboxedStrategy`].
+
+use super::{encode_init, frame_len, SandboxConfig, STATUS_OK};
+use crate::tokenizer::{ExpansionEstimate, TokenizationStrategy};
+use crate::{BpeMerges, StrategyKind};
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{debug, warn};
+
+/// A tokenization strategy that processes chunks in sandboxed worker processes.
+pub struct SandboxedStrategy {
+    config: SandboxConfig,
+    kind: StrategyKind,
+    init: Vec<u8>,
+    idle: Mutex<Vec<Worker>>,
+    slots: Semaphore,
+}
+
+impl SandboxedStrategy {
+    /// Creates a strategy running `kind` (with `merges` for BPE) in workers started per
+    /// `config`. Workers are spawned lazily, on the first chunks.
+    pub fn new(config: SandboxConfig, kind: StrategyKind, merges: Option<&BpeMerges>) -> Self {
+        let slots = Semaphore::new(config.workers.max(1));
+        Self {
+            init: encode_init(kind, merges),
+            config,
+            kind,
+            idle: Mutex::new(Vec::new()),
+            slots,
+        }
+    }
+
+    async fn spawn_worker(&self) -> io::Result<Worker> {
+        let mut child = Command::new(&self.config.program)
+            .args(&self.config.args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Failed to start sandboxed worker {}: {e}",
+                        self.config.program.display()
+                    ),
+                )
+            })?;
+        let mut stdin = child.stdin.take().expect("worker stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("worker stdout is piped"));
+        stdin.write_all(&self.init).await?;
+        debug!(pid = child.id(), "Started sandboxed worker");
+        Ok(Worker {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for SandboxedStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        let _slot = self
+            .slots
+            .acquire()
+            .await
+            .map_err(|_| io::Error::other("sandbox worker pool closed"))?;
+        let idle = self.idle.lock().await.pop();
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => self.spawn_worker().await?,
+        };
+        match worker.process(chunk_data).await {
+            Ok(output) => {
+                self.idle.lock().await.push(worker);
+                output.map_err(io::Error::other)
+            }
+            Err(e) => {
+                let status = worker.child.try_wait().ok().flatten();
+                warn!(error = %e, ?status, "Sandboxed worker failed; it will be replaced");
+                Err(io::Error::other(format!(
+                    "Sandboxed worker failed while processing a chunk: {e}{}",
+                    status.map_or_else(String::new, |s| format!(" ({s})"))
+                )))
+            }
+        }
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        match self.kind {
+            StrategyKind::Passthrough => ExpansionEstimate::exact(input_bytes),
+            StrategyKind::Basic | StrategyKind::Bpe => ExpansionEstimate::exact(input_bytes * 2),
+        }
+    }
+}
+
+/// A running worker process and its pipes.
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+    /// Sends one chunk; the outer error means the worker is unusable, the inner one that it
+    /// reported a processing error.
+    async fn process(&mut self, chunk: &[u8]) -> io::Result<Result<Vec<u8>, String>> {
+        self.stdin.write_all(&frame_len(chunk.len())?).await?;
+        self.stdin.write_all(chunk).await?;
+        self.stdin.flush().await?;
+        let status = self.stdout.read_u8().await?;
+        let len = self.stdout.read_u32().await? as usize;
+        let mut payload = vec![0u8; len];
+        self.stdout.read_exact(&mut payload).await?;
+        match status {
+            STATUS_OK => Ok(Ok(payload)),
+            _ => Ok(Err(String::from_utf8_lossy(&payload).into_owned())),
+        }
+    }
+}
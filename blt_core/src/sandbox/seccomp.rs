@@ -0,0 +1,110 @@
+//! The seccomp filter installed by sandboxed workers.
+//!
+//! The filter allows the system calls needed to read the request pipe, write results, manage
+//! memory, and exit; any other system call kills the worker with `SIGSYS`.
+
+use std::io;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+/// Offsets into `struct seccomp_data`.
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_close,
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_getrandom,
+    libc::SYS_sched_yield,
+    libc::SYS_clock_gettime,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_sigaltstack,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+fn statement(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn filter() -> Vec<libc::sock_filter> {
+    let load = |offset| statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset);
+    let ret = |action| statement(libc::BPF_RET | libc::BPF_K, action);
+    let jeq = |value, jt, jf| jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, value, jt, jf);
+
+    let mut program = vec![
+        load(SECCOMP_DATA_ARCH),
+        jeq(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(SECCOMP_DATA_NR),
+    ];
+    for &nr in ALLOWED_SYSCALLS {
+        program.push(jeq(nr as u32, 0, 1));
+        program.push(ret(libc::SECCOMP_RET_ALLOW));
+    }
+    program.push(ret(libc::SECCOMP_RET_KILL_PROCESS));
+    program
+}
+
+/// Installs the filter on every thread of the calling process; it cannot be removed
+/// afterwards.
+pub(super) fn install() -> io::Result<()> {
+    let mut program = filter();
+    let prog = libc::sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+    // SAFETY: `prog` points to a valid filter that outlives the calls, and the kernel copies
+    // it. Neither call touches memory owned by Rust otherwise.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        match libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const libc::sock_fprog,
+        ) {
+            0 => Ok(()),
+            -1 => Err(io::Error::last_os_error()),
+            thread => Err(io::Error::other(format!(
+                "Failed to synchronize the seccomp filter to thread {thread}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_checks_arch_and_ends_with_kill() {
+        let program = filter();
+        assert_eq!(program.len(), 5 + 2 * ALLOWED_SYSCALLS.len());
+        assert_eq!(program[1].k, AUDIT_ARCH);
+        assert_eq!(program.last().unwrap().k, libc::SECCOMP_RET_KILL_PROCESS);
+    }
+}
@@ -0,0 +1,614 @@
+//! HTTP server mode.
+//!
+//! Exposes the operational endpoints an orchestrator needs to run blt as a long-lived
+//! service:
+//!
+//! - `GET /healthz`: liveness; answers `200 OK` while the process is up.
+//! - `GET /readyz`: readiness; answers `200 OK` once the vocabulary is loaded and
+//!   `503 Service Unavailable` after a shutdown signal has been received.
+//! - `GET /vocab`: JSON description of the loaded vocabulary (fingerprint, size, special tokens).
+//! - `POST /tokenize`: tokenizes the raw request body and answers with big-endian 16-bit token
+//!   bytes, using the merges and byte offset loaded at startup.
+//! - `POST /decode`: the inverse; expands token bytes back into the original bytes, skipping
+//!   special tokens.
+//! - `GET /metrics`: Prometheus metrics (see [`crate::metrics`]) for the tokenizer runs fed by
+//!   the core configuration's [`Metrics`], or by a fresh set when it has none.
+//!
+//! Given a shard directory, the server also streams the token shards in it read-only, whole,
+//! by byte range, or sliced by token or document (see [`ShardInfo`] and the `shards` module).
+//!
+//! Clients may be required to present an API key (see [`ApiKeys`]), and held to per-minute
+//! request and byte quotas keyed by it (see [`QuotaConfig`]); `/tokenize` and `/decode` answer
+//! `429 Too Many Requests` once a client's quota is used up. With a [`TlsConfig`] the server
+//! only accepts HTTPS.
+//!
+//! Small `/tokenize` requests may be coalesced into batches tokenized together (see
+//! [`BatchConfig`]), which raises throughput for chat-style workloads.
+//!
+//! Every request runs in a span carrying a trace ID, taken from a W3C `traceparent` header
+//! when the caller sends one, and returned in the `X-Trace-Id` response header.
+//!
+//! On SIGTERM or Ctrl-C the server flips readiness to "draining", stops accepting new
+//! connections, and waits for in-flight requests to complete before returning.
+//!
+//! Connections come from a freshly bound socket, a socket inherited through systemd socket
+//! activation, or stdin/stdout in inetd mode (see [`Listener`]).
+
+use crate::content_types::ContentTypeRegistry;
+use crate::metrics::Metrics;
+use crate::tokenizer::{TokenDecoder, TokenizationStrategy};
+use crate::{config_loader, BpeMerges, ContentType, CoreConfig};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::info;
+
+mod auth;
+mod batch;
+mod listener;
+mod quota;
+mod shards;
+mod tls;
+mod trace;
+pub use auth::{ApiKeys, API_KEY_HEADER};
+pub use batch::BatchConfig;
+use batch::Batcher;
+pub use listener::Listener;
+pub use quota::{Quota, QuotaConfig};
+use quota::{QuotaExceeded, Quotas};
+use shards::ShardStore;
+pub use shards::{ShardInfo, TOKEN_WIDTH_HEADER};
+pub use tls::TlsConfig;
+pub use trace::{TRACEPARENT_HEADER, TRACE_ID_HEADER};
+
+/// The largest request body `/tokenize` and `/decode` accept.
+pub const MAX_REQUEST_BYTES: usize = 64 * 1024 * 1024;
+
+/// Settings for the HTTP server mode.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Where connections come from.
+    pub listener: Listener,
+    /// The tokenizer configuration shared by all requests.
+    pub core: CoreConfig,
+    /// Per-client quotas on `/tokenize` and `/decode`.
+    pub quotas: QuotaConfig,
+    /// The API keys clients must present, or `None` to serve anyone.
+    pub api_keys: Option<ApiKeys>,
+    /// The certificate to terminate TLS with, or `None` to serve plain HTTP.
+    pub tls: Option<TlsConfig>,
+    /// How small `/tokenize` requests are coalesced, or `None` to tokenize each on its own.
+    pub batching: Option<BatchConfig>,
+    /// A directory of token shards to serve read-only under `/shards`, or `None` to serve none.
+    pub shards: Option<PathBuf>,
+}
+
+/// Describes the vocabulary the server tokenizes with.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct VocabInfo {
+    /// A stable fingerprint of the merges table (`"none"` when no merges are loaded).
+    pub fingerprint: String,
+    /// The number of distinct token values: 256 byte tokens plus one per merge.
+    pub size: usize,
+    /// The reserved special tokens and their values.
+    pub special_tokens: Vec<SpecialToken>,
+}
+
+/// A named reserved token.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SpecialToken {
+    /// The token's name, e.g. `"text"`.
+    pub name: String,
+    /// The token's value.
+    pub id: u16,
+}
+
+impl VocabInfo {
+    /// Builds the vocabulary description for the given (optional) merges table.
+    pub fn new(merges: Option<&BpeMerges>) -> Self {
+        Self {
+            fingerprint: merges
+                .map_or_else(|| "none".to_string(), config_loader::merges_fingerprint),
+            size: 256 + merges.map_or(0, |m| m.len()),
+            special_tokens: ContentType::ALL.iter().map(SpecialToken::from).collect(),
+        }
+    }
+}
+
+impl VocabInfo {
+    /// Lists every type in `content_types`, including custom ones, as special tokens.
+    pub fn with_content_types(mut self, content_types: &ContentTypeRegistry) -> Self {
+        self.special_tokens = content_types.iter().map(SpecialToken::from).collect();
+        self
+    }
+}
+
+impl From<&ContentType> for SpecialToken {
+    fn from(content_type: &ContentType) -> Self {
+        Self {
+            name: content_type.name().to_string(),
+            id: content_type.get_token_value(),
+        }
+    }
+}
+
+/// State shared by all request handlers.
+struct ServerState {
+    vocab: VocabInfo,
+    strategy: Arc<dyn TokenizationStrategy>,
+    decoder: TokenDecoder,
+    metrics: Arc<Metrics>,
+    quotas: Quotas,
+    api_keys: Option<ApiKeys>,
+    batcher: Option<Batcher>,
+    shards: Option<Arc<ShardStore>>,
+    draining: AtomicBool,
+}
+
+impl ServerState {
+    fn new(
+        core: &CoreConfig,
+        quotas: QuotaConfig,
+        api_keys: Option<ApiKeys>,
+        batching: Option<BatchConfig>,
+    ) -> io::Result<Self> {
+        let strategy = crate::offload::offload_cpu_bound(core, crate::select_strategy(core)?);
+        Ok(Self {
+            vocab: VocabInfo::new(core.bpe_data.as_deref()).with_content_types(&core.content_types),
+            batcher: batching.map(|batching| Batcher::new(strategy.clone(), batching)),
+            strategy,
+            decoder: TokenDecoder::new(core.bpe_data.as_deref(), core.byte_offset),
+            metrics: core.metrics.clone().unwrap_or_else(Metrics::new),
+            quotas: Quotas::new(quotas, api_keys.is_some()),
+            api_keys,
+            shards: None,
+            draining: AtomicBool::new(false),
+        })
+    }
+}
+
+/// Runs the HTTP server until a shutdown signal is received and in-flight requests drain.
+///
+/// In inetd mode the server instead returns once its single connection is closed.
+pub async fn serve(config: ServerConfig) -> io::Result<()> {
+    let tls = config.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+    let shards = config.shards.as_deref().map(ShardStore::open).transpose()?;
+    let state = Arc::new(ServerState {
+        shards: shards.map(Arc::new),
+        ..ServerState::new(
+            &config.core,
+            config.quotas,
+            config.api_keys,
+            config.batching,
+        )?
+    });
+    match config.listener {
+        Listener::Bind(addr) => serve_tcp(TcpListener::bind(addr).await?, tls, state).await,
+        Listener::SocketActivation => {
+            serve_tcp(listener::socket_activation_listener()?, tls, state).await
+        }
+        Listener::Inetd => listener::serve_inetd(router(state), tls).await,
+    }
+}
+
+async fn serve_tcp(
+    tcp_listener: TcpListener,
+    tls: Option<tls::Acceptor>,
+    state: Arc<ServerState>,
+) -> io::Result<()> {
+    info!(addr = %tcp_listener.local_addr()?, tls = tls.is_some(), "Server listening");
+    let router = router(state.clone());
+    let shutdown = shutdown_signal(state);
+    match tls {
+        None => {
+            axum::serve(tcp_listener, router)
+                .with_graceful_shutdown(shutdown)
+                .await?
+        }
+        Some(acceptor) => tls::serve(tcp_listener, acceptor, router, shutdown).await?,
+    }
+    info!("Server stopped");
+    Ok(())
+}
+
+fn router(state: Arc<ServerState>) -> Router {
+    let mut routes = Router::new()
+        .route("/vocab", get(vocab))
+        .route("/metrics", get(metrics))
+        .route("/tokenize", post(tokenize))
+        .route("/decode", post(decode));
+    if let Some(store) = &state.shards {
+        routes = routes.merge(shards::routes(store.clone()));
+    }
+    // Probes stay open so orchestrators need no key.
+    routes
+        .route_layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .layer(DefaultBodyLimit::max(MAX_REQUEST_BYTES))
+        .layer(middleware::from_fn(trace::trace_request))
+        .with_state(state)
+}
+
+/// Rejects requests without an accepted API key, when the server requires one.
+async fn authenticate(
+    State(state): State<Arc<ServerState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(api_keys) = &state.api_keys else {
+        return next.run(request).await;
+    };
+    match auth::client_key(request.headers()) {
+        Some(key) if api_keys.accepts(key) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "A valid API key is required",
+        )
+            .into_response(),
+    }
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz(State(state): State<Arc<ServerState>>) -> (StatusCode, &'static str) {
+    if state.draining.load(Ordering::SeqCst) {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else {
+        (StatusCode::OK, "ready")
+    }
+}
+
+async fn vocab(State(state): State<Arc<ServerState>>) -> Json<VocabInfo> {
+    Json(state.vocab.clone())
+}
+
+/// Charges a request to the quota of the client that sent it.
+fn admit(state: &ServerState, headers: &HeaderMap, body: &[u8]) -> Result<(), QuotaExceeded> {
+    state
+        .quotas
+        .admit(auth::client_key(headers), body.len() as u64)
+}
+
+impl IntoResponse for QuotaExceeded {
+    fn into_response(self) -> Response {
+        let message = match self.retry_after {
+            Some(_) => format!("Quota of {} per minute exceeded", self.resource),
+            None => format!("Request exceeds the quota of {} per minute", self.resource),
+        };
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, message).into_response();
+        if let Some(retry_after) = self.retry_after {
+            let seconds = retry_after.as_secs_f64().ceil() as u64;
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.max(1).into());
+        }
+        response
+    }
+}
+
+async fn tokenize(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, Response> {
+    admit(&state, &headers, &body).map_err(IntoResponse::into_response)?;
+    let bytes = body.len() as u64;
+    let tokens = match &state.batcher {
+        Some(batcher) if batcher.accepts(body.len()) => batcher.process(body).await,
+        _ => state.strategy.process_bytes(body).await,
+    };
+    match tokens {
+        Ok(tokens) => {
+            state.metrics.record_chunk(bytes, tokens.len() as u64);
+            state.metrics.record_document();
+            Ok(([(header::CONTENT_TYPE, "application/octet-stream")], tokens))
+        }
+        Err(e) => {
+            state.metrics.record_error();
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())
+        }
+    }
+}
+
+async fn decode(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, Response> {
+    admit(&state, &headers, &body).map_err(IntoResponse::into_response)?;
+    let decoded = state
+        .decoder
+        .decode(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        decoded,
+    ))
+}
+
+async fn metrics(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+async fn shutdown_signal(state: Arc<ServerState>) {
+    wait_for_termination().await;
+    info!("Shutdown signal received, draining in-flight requests");
+    state.draining.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+async fn wait_for_termination() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_termination() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn test_state() -> Arc<ServerState> {
+        let merges: BpeMerges = vec![((97, 98), 256)].into_iter().collect();
+        let core = CoreConfig {
+            bpe_data: Some(Arc::new(merges)),
+            ..CoreConfig::default()
+        };
+        Arc::new(ServerState::new(&core, QuotaConfig::default(), None, None).unwrap())
+    }
+
+    async fn get_request(state: Arc<ServerState>, uri: &str) -> (StatusCode, Vec<u8>) {
+        send(state, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    async fn post_request(
+        state: Arc<ServerState>,
+        uri: &str,
+        body: &[u8],
+    ) -> (StatusCode, Vec<u8>) {
+        let request = Request::post(uri).body(Body::from(body.to_vec())).unwrap();
+        send(state, request).await
+    }
+
+    async fn send(state: Arc<ServerState>, request: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let response = router(state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_healthz_and_readyz() {
+        let state = test_state();
+        assert_eq!(
+            get_request(state.clone(), "/healthz").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_request(state.clone(), "/readyz").await.0,
+            StatusCode::OK
+        );
+
+        state.draining.store(true, Ordering::SeqCst);
+        let (status, body) = get_request(state, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, b"draining");
+    }
+
+    #[tokio::test]
+    async fn test_vocab_endpoint() {
+        let (status, body) = get_request(test_state(), "/vocab").await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["size"], 257);
+        assert_eq!(json["fingerprint"].as_str().unwrap().len(), 16);
+        assert_eq!(json["special_tokens"][0]["name"], "text");
+        assert_eq!(json["special_tokens"][0]["id"], 0xFF01);
+    }
+
+    #[tokio::test]
+    async fn test_tokenize_and_decode() {
+        let state = test_state();
+        let (status, tokens) = post_request(state.clone(), "/tokenize", b"abc").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tokens, [0x01, 0x00, 0x00, 99]);
+
+        let (status, decoded) = post_request(state.clone(), "/decode", &tokens).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(decoded, b"abc");
+
+        let (status, body) = post_request(state.clone(), "/decode", &[0, 97, 0]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8(body).unwrap().contains("16-bit"));
+
+        let (_, metrics) = get_request(state, "/metrics").await;
+        assert!(String::from_utf8(metrics)
+            .unwrap()
+            .contains("\nblt_input_bytes_total 3\n"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let state = test_state();
+        state.metrics.record_document();
+        let (status, body) = get_request(state, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("# TYPE blt_documents_total counter\nblt_documents_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_quota_rejections() {
+        let core = CoreConfig::default();
+        let quotas = QuotaConfig {
+            default: Quota {
+                requests_per_minute: Some(1),
+                bytes_per_minute: None,
+            },
+            clients: [("team-a".to_string(), Quota::default())].into(),
+        };
+        let state = Arc::new(ServerState::new(&core, quotas, None, None).unwrap());
+        let keyed = |key: &str| {
+            Request::post("/tokenize")
+                .header(API_KEY_HEADER, key)
+                .body(Body::from("a"))
+                .unwrap()
+        };
+
+        assert_eq!(send(state.clone(), keyed("team-b")).await.0, StatusCode::OK);
+        let response = router(state.clone())
+            .oneshot(keyed("team-b"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+
+        // Listed clients are unaffected, while unlisted keys and anonymous clients share the
+        // default quota.
+        for _ in 0..3 {
+            assert_eq!(send(state.clone(), keyed("team-a")).await.0, StatusCode::OK);
+        }
+        assert_eq!(
+            send(state.clone(), keyed("team-c")).await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            post_request(state, "/decode", &[0, 97]).await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requires_api_keys() {
+        let api_keys = ApiKeys::new(["secret"]);
+        let state = Arc::new(
+            ServerState::new(
+                &CoreConfig::default(),
+                QuotaConfig::default(),
+                Some(api_keys),
+                None,
+            )
+            .unwrap(),
+        );
+        let tokenize = |auth: Option<&str>| {
+            let request = Request::post("/tokenize");
+            let request = match auth {
+                Some(auth) => request.header(header::AUTHORIZATION, auth),
+                None => request,
+            };
+            request.body(Body::from("a")).unwrap()
+        };
+
+        let response = router(state.clone()).oneshot(tokenize(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(
+            send(state.clone(), tokenize(Some("Bearer wrong"))).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(state.clone(), tokenize(Some("Bearer secret"))).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_request(state.clone(), "/vocab").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        // Probes need no key.
+        assert_eq!(
+            get_request(state.clone(), "/healthz").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(get_request(state, "/readyz").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_batches_small_requests() {
+        let merges: BpeMerges = vec![((97, 98), 256)].into_iter().collect();
+        let core = CoreConfig {
+            bpe_data: Some(Arc::new(merges)),
+            ..CoreConfig::default()
+        };
+        let batching = BatchConfig {
+            max_request_bytes: 4,
+            ..BatchConfig::default()
+        };
+        let state = Arc::new(
+            ServerState::new(&core, QuotaConfig::default(), None, Some(batching)).unwrap(),
+        );
+        let requests: Vec<_> = [&b"abc"[..], b"ab", b"cab", b"abcab"]
+            .into_iter()
+            .map(|body| tokio::spawn(post_request(state.clone(), "/tokenize", body)))
+            .collect();
+        let mut results = Vec::new();
+        for request in requests {
+            let (status, tokens) = request.await.unwrap();
+            assert_eq!(status, StatusCode::OK);
+            results.push(tokens);
+        }
+        assert_eq!(
+            results,
+            [
+                vec![0x01, 0x00, 0x00, 99],
+                vec![0x01, 0x00],
+                vec![0x00, 99, 0x01, 0x00],
+                vec![0x01, 0x00, 0x00, 99, 0x01, 0x00],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_returns_trace_ids() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let request = Request::post("/tokenize")
+            .header(TRACEPARENT_HEADER, traceparent)
+            .body(Body::from("a"))
+            .unwrap();
+        let response = router(test_state()).oneshot(request).await.unwrap();
+        assert_eq!(
+            response.headers()[TRACE_ID_HEADER],
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+
+        let request = Request::get("/healthz").body(Body::empty()).unwrap();
+        let response = router(test_state()).oneshot(request).await.unwrap();
+        let trace_id = response.headers()[TRACE_ID_HEADER].to_str().unwrap();
+        assert_eq!(trace_id.len(), 32);
+        assert_ne!(trace_id, "0".repeat(32));
+    }
+
+    #[test]
+    fn test_vocab_info_without_merges() {
+        let info = VocabInfo::new(None);
+        assert_eq!(info.fingerprint, "none");
+        assert_eq!(info.size, 256);
+        assert_eq!(info.special_tokens.len(), 4);
+    }
+}
@@ -0,0 +1,111 @@
+//! API-key authentication for the server mode.
+//!
+//! Clients present their key as an `Authorization: Bearer KEY` header or in an `X-Api-Key`
+//! header. The same key selects the client's quota (see [`super::QuotaConfig`]). When the
+//! server is given a set of [`ApiKeys`], every endpoint except the `/healthz` and `/readyz`
+//! probes answers `401 Unauthorized` to requests without one of them.
+//!
+//! Keys are read from a file with one key per line and kept only as SHA-256 digests, so
+//! looking one up takes the same time however much of it matches a valid key.
+
+use axum::http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The header clients may send their API key in, instead of `Authorization: Bearer`.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The API keys the server accepts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApiKeys {
+    digests: HashSet<[u8; 32]>,
+}
+
+impl ApiKeys {
+    /// Reads keys from a file with one key per line. Blank lines and lines starting with `#`
+    /// are skipped.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let keys = Self::new(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#')),
+        );
+        if keys.digests.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("API key file '{}' lists no keys", path.display()),
+            ));
+        }
+        Ok(keys)
+    }
+
+    /// Accepts each of `keys`.
+    pub fn new<'k>(keys: impl IntoIterator<Item = &'k str>) -> Self {
+        Self {
+            digests: keys.into_iter().map(digest).collect(),
+        }
+    }
+
+    /// Returns `true` if `key` is one of the accepted keys.
+    pub fn accepts(&self, key: &str) -> bool {
+        self.digests.contains(&digest(key))
+    }
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// The API key a request was sent with, if any.
+pub(crate) fn client_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer
+        .or_else(|| {
+            headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_from_path() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# team keys").unwrap();
+        writeln!(file, "alpha").unwrap();
+        writeln!(file, "  beta  ").unwrap();
+        let keys = ApiKeys::from_path(file.path()).unwrap();
+        assert!(keys.accepts("alpha"));
+        assert!(keys.accepts("beta"));
+        assert!(!keys.accepts("gamma"));
+        assert!(!keys.accepts("# team keys"));
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        assert!(ApiKeys::from_path(empty.path()).is_err());
+    }
+
+    #[test]
+    fn test_client_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_key(&headers), None);
+        headers.insert(API_KEY_HEADER, "alpha".parse().unwrap());
+        assert_eq!(client_key(&headers), Some("alpha"));
+        headers.insert(header::AUTHORIZATION, "Bearer beta".parse().unwrap());
+        assert_eq!(client_key(&headers), Some("beta"));
+        headers.insert(header::AUTHORIZATION, "Basic Zm9v".parse().unwrap());
+        assert_eq!(client_key(&headers), Some("alpha"));
+    }
+}
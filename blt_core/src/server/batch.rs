@@ -0,0 +1,137 @@
+//! Coalescing of small `/tokenize` requests into batches.
+//!
+//! Chat-style clients send many tiny requests, and tokenizing a few hundred bytes costs far
+//! less than scheduling the work for it. With batching enabled, requests up to
+//! [`BatchConfig::max_request_bytes`] are queued instead of tokenized by their own handler; a
+//! collector gathers queued requests for up to [`BatchConfig::window`] (or until
+//! [`BatchConfig::max_batch`] are waiting) and tokenizes the whole batch in a single task,
+//! handing each request its own result. Requests are never concatenated, so every result is
+//! exactly what tokenizing the request alone would produce.
+
+use crate::tokenizer::TokenizationStrategy;
+use bytes::Bytes;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tracing::{debug, debug_span, Instrument};
+
+/// How small `/tokenize` requests are coalesced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Requests with larger bodies are tokenized on their own.
+    pub max_request_bytes: usize,
+    /// The most requests tokenized in one batch.
+    pub max_batch: usize,
+    /// How long the first request of a batch waits for others to join it.
+    pub window: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_request_bytes: 4096,
+            max_batch: 64,
+            window: Duration::from_micros(500),
+        }
+    }
+}
+
+/// A queued request and where its result goes.
+struct Job {
+    input: Bytes,
+    reply: oneshot::Sender<io::Result<Bytes>>,
+}
+
+/// Queues small requests for the collector task.
+#[derive(Debug)]
+pub(crate) struct Batcher {
+    config: BatchConfig,
+    queue: mpsc::Sender<Job>,
+}
+
+impl Batcher {
+    /// Starts a collector tokenizing batches with `strategy`. It stops once the batcher is
+    /// dropped. Must be called within a Tokio runtime.
+    pub(crate) fn new(strategy: Arc<dyn TokenizationStrategy>, config: BatchConfig) -> Self {
+        let max_batch = config.max_batch.max(1);
+        let (queue, jobs) = mpsc::channel(max_batch * 4);
+        tokio::spawn(collect(jobs, strategy, max_batch, config.window));
+        Self { config, queue }
+    }
+
+    /// Returns `true` if a request of `len` bytes is small enough to be batched.
+    pub(crate) fn accepts(&self, len: usize) -> bool {
+        len <= self.config.max_request_bytes
+    }
+
+    /// Tokenizes `input` as part of the next batch.
+    pub(crate) async fn process(&self, input: Bytes) -> io::Result<Bytes> {
+        let (reply, result) = oneshot::channel();
+        self.queue
+            .send(Job { input, reply })
+            .await
+            .map_err(|_| io::Error::other("batch collector stopped"))?;
+        result
+            .await
+            .map_err(|_| io::Error::other("batch dropped before it was tokenized"))?
+    }
+}
+
+/// Gathers queued requests into batches and spawns a task tokenizing each one.
+async fn collect(
+    mut jobs: mpsc::Receiver<Job>,
+    strategy: Arc<dyn TokenizationStrategy>,
+    max_batch: usize,
+    window: Duration,
+) {
+    while let Some(first) = jobs.recv().await {
+        let deadline = Instant::now() + window;
+        let mut batch = vec![first];
+        while batch.len() < max_batch {
+            match tokio::time::timeout_at(deadline, jobs.recv()).await {
+                Ok(Some(job)) => batch.push(job),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        let span = debug_span!("tokenize_batch", requests = batch.len());
+        tokio::spawn(tokenize_batch(batch, strategy.clone()).instrument(span));
+    }
+}
+
+async fn tokenize_batch(batch: Vec<Job>, strategy: Arc<dyn TokenizationStrategy>) {
+    debug!("Tokenizing batch");
+    for job in batch {
+        // The client may have gone away; its result is simply dropped.
+        let _ = job.reply.send(strategy.process_bytes(job.input).await);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::BasicTokenizationStrategy;
+
+    #[tokio::test]
+    async fn test_demultiplexes_results() {
+        let config = BatchConfig {
+            window: Duration::from_millis(20),
+            ..BatchConfig::default()
+        };
+        let batcher = Arc::new(Batcher::new(Arc::new(BasicTokenizationStrategy), config));
+        assert!(batcher.accepts(4096));
+        assert!(!batcher.accepts(4097));
+
+        let requests: Vec<_> = (0..100u8)
+            .map(|i| {
+                let batcher = batcher.clone();
+                tokio::spawn(async move { batcher.process(Bytes::from(vec![i; 3])).await })
+            })
+            .collect();
+        for (i, request) in requests.into_iter().enumerate() {
+            let tokens = request.await.unwrap().unwrap();
+            assert_eq!(&tokens[..], [0, i as u8].repeat(3));
+        }
+    }
+}
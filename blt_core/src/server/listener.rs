@@ -0,0 +1,152 @@
+//! Listening-socket acquisition for the server mode.
+//!
+//! Besides binding its own TCP socket, the server can inherit a pre-bound listening socket
+//! from systemd socket activation (`LISTEN_FDS`), or run in inetd mode where the process
+//! serves exactly one connection over stdin/stdout.
+
+use super::tls::Acceptor;
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+
+/// The first file descriptor passed by systemd socket activation (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Where the server gets its connections from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listener {
+    /// Bind a new TCP listener on the given address.
+    Bind(SocketAddr),
+    /// Use the listening socket passed by systemd socket activation.
+    SocketActivation,
+    /// Serve a single connection over stdin/stdout, as spawned by inetd.
+    Inetd,
+}
+
+impl Listener {
+    /// Picks the listener for a server process.
+    ///
+    /// `inetd` takes precedence; otherwise a socket passed via `LISTEN_FDS` is used when
+    /// present, falling back to binding `addr`.
+    pub fn detect(addr: SocketAddr, inetd: bool) -> Self {
+        if inetd {
+            Listener::Inetd
+        } else if activated_fd_count(std::process::id()).is_some() {
+            Listener::SocketActivation
+        } else {
+            Listener::Bind(addr)
+        }
+    }
+}
+
+/// Returns the number of sockets passed by systemd to the process with id `pid`, if any.
+fn activated_fd_count(pid: u32) -> Option<usize> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    parse_listen_fds(listen_pid.as_deref(), listen_fds.as_deref(), pid)
+}
+
+fn parse_listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<usize> {
+    if let Some(listen_pid) = listen_pid {
+        if listen_pid.trim().parse::<u32>().ok()? != pid {
+            return None;
+        }
+    }
+    let count = listen_fds?.trim().parse::<usize>().ok()?;
+    (count > 0).then_some(count)
+}
+
+/// Takes ownership of the first socket passed by systemd socket activation.
+#[cfg(unix)]
+pub(super) fn socket_activation_listener() -> io::Result<TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    if activated_fd_count(std::process::id()).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Socket activation requested but LISTEN_FDS does not name a socket for this process",
+        ));
+    }
+    // SAFETY: systemd guarantees that fd 3 is an open listening socket owned by this process
+    // when LISTEN_FDS/LISTEN_PID match, and nothing else in blt takes ownership of it.
+    let std_listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    std_listener.set_nonblocking(true)?;
+    TcpListener::from_std(std_listener)
+}
+
+#[cfg(not(unix))]
+pub(super) fn socket_activation_listener() -> io::Result<TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Socket activation is only supported on Unix",
+    ))
+}
+
+/// Serves a single HTTP/1 connection over `io` until the client closes it.
+pub(super) async fn serve_connection<IO>(io: IO, router: Router) -> io::Result<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // inetd clients commonly close their write side right after sending the request, so a
+    // read-side EOF must not abort the response.
+    http1::Builder::new()
+        .half_close(true)
+        .serve_connection(TokioIo::new(io), TowerToHyperService::new(router))
+        .await
+        .map_err(io::Error::other)
+}
+
+/// Serves a single connection over stdin/stdout (inetd mode), over TLS when `tls` is given.
+pub(super) async fn serve_inetd(router: Router, tls: Option<Acceptor>) -> io::Result<()> {
+    let stdio = tokio::io::join(tokio::io::stdin(), tokio::io::stdout());
+    match tls {
+        None => serve_connection(stdio, router).await,
+        Some(acceptor) => serve_connection(acceptor.accept(stdio).await?, router).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert_eq!(parse_listen_fds(Some("42"), Some("1"), 42), Some(1));
+        assert_eq!(parse_listen_fds(None, Some("2"), 42), Some(2));
+        assert_eq!(parse_listen_fds(Some("41"), Some("1"), 42), None);
+        assert_eq!(parse_listen_fds(Some("42"), Some("0"), 42), None);
+        assert_eq!(parse_listen_fds(Some("42"), None, 42), None);
+        assert_eq!(parse_listen_fds(Some("42"), Some("x"), 42), None);
+    }
+
+    #[test]
+    fn test_detect_prefers_inetd() {
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        assert_eq!(Listener::detect(addr, true), Listener::Inetd);
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection_over_duplex() {
+        let router = Router::new().route("/healthz", get(|| async { "ok" }));
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(serve_connection(server, router));
+
+        client
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("ok"));
+        handle.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+//! Per-client request and byte quotas for the server mode.
+//!
+//! Clients identify themselves with their API key (see [`super::ApiKeys`]). Each key listed in
+//! the quota file, and each accepted key when the server requires keys, gets its own allowance
+//! of requests and request-body bytes per minute, refilled continuously, so one team cannot
+//! monopolize a shared service. Any other key is unverified, so requests with one share a single
+//! default allowance with requests without a key; making up new keys gains nothing.
+//! A request that would exceed either allowance is rejected with `429 Too Many Requests` and a
+//! `Retry-After` header, without being processed.
+//!
+//! Quotas are read from a file with one `KEY REQUESTS BYTES` line per client, where either
+//! limit may be `-` for no limit. The key `*` sets the quota of every key not listed,
+//! including the anonymous one; without it, unlisted clients are not limited.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How much one client may use the server per minute.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// The most requests per minute, if limited.
+    pub requests_per_minute: Option<u64>,
+    /// The most request-body bytes per minute, if limited.
+    pub bytes_per_minute: Option<u64>,
+}
+
+impl Quota {
+    fn is_unlimited(&self) -> bool {
+        self.requests_per_minute.is_none() && self.bytes_per_minute.is_none()
+    }
+}
+
+/// The quota of every client, by API key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuotaConfig {
+    /// The quota of clients not listed in `clients`, including those without a key.
+    pub default: Quota,
+    /// Quotas of individual API keys.
+    pub clients: HashMap<String, Quota>,
+}
+
+impl QuotaConfig {
+    /// Reads quotas from a file of `KEY REQUESTS BYTES` lines.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut config = Self::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let [key, requests, bytes] = parts[..] else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid quota line '{line}'. Expected a key, requests per minute, and bytes per minute."
+                    ),
+                ));
+            };
+            let quota = Quota {
+                requests_per_minute: parse_limit(requests)?,
+                bytes_per_minute: parse_limit(bytes)?,
+            };
+            if key == "*" {
+                config.default = quota;
+            } else {
+                config.clients.insert(key.to_string(), quota);
+            }
+        }
+        Ok(config)
+    }
+
+    /// The quota of the client with `key`.
+    fn quota(&self, key: Option<&str>) -> Quota {
+        key.and_then(|key| self.clients.get(key))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+fn parse_limit(value: &str) -> io::Result<Option<u64>> {
+    if value == "-" {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid quota limit '{value}': {e}"),
+        )
+    })
+}
+
+/// Why a request was rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct QuotaExceeded {
+    /// What ran out: `"requests"` or `"bytes"`.
+    pub(crate) resource: &'static str,
+    /// How long until the request would be admitted, if it ever can be.
+    pub(crate) retry_after: Option<Duration>,
+}
+
+/// The time in which any allowance refills completely.
+const REFILL_PERIOD: Duration = Duration::from_secs(60);
+
+/// What clients have left of their quotas.
+#[derive(Debug)]
+pub(crate) struct Quotas {
+    config: QuotaConfig,
+    /// Whether every key was accepted by the server's [`super::ApiKeys`], so that unlisted keys
+    /// can be told apart.
+    authenticated: bool,
+    usage: Mutex<Ledger>,
+}
+
+/// The allowances of the clients seen within the last [`REFILL_PERIOD`], by bucket: the key, or
+/// `None` for the shared default allowance.
+#[derive(Debug)]
+struct Ledger {
+    clients: HashMap<Option<String>, Usage>,
+    swept: Instant,
+}
+
+impl Quotas {
+    /// Keeps track of the allowances of `config`. Unless `authenticated`, keys not listed in
+    /// `config` are charged to the shared default allowance.
+    pub(crate) fn new(config: QuotaConfig, authenticated: bool) -> Self {
+        Self {
+            config,
+            authenticated,
+            usage: Mutex::new(Ledger {
+                clients: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// The allowance the client with `key` draws on.
+    fn bucket(&self, key: Option<&str>) -> Option<String> {
+        key.filter(|key| self.authenticated || self.config.clients.contains_key(*key))
+            .map(str::to_string)
+    }
+
+    /// Counts a request of `bytes` body bytes from the client with `key`, or rejects it if
+    /// either allowance is short. Rejected requests use up nothing.
+    pub(crate) fn admit(&self, key: Option<&str>, bytes: u64) -> Result<(), QuotaExceeded> {
+        let quota = self.config.quota(key);
+        if quota.is_unlimited() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut usage = self.usage.lock().expect("quota lock poisoned");
+        // A client idle for a whole refill period has its full allowance back, the same as one
+        // never seen, so its entry can go.
+        if now.saturating_duration_since(usage.swept) >= REFILL_PERIOD {
+            usage
+                .clients
+                .retain(|_, client| now.saturating_duration_since(client.counted) < REFILL_PERIOD);
+            usage.swept = now;
+        }
+        let client = usage
+            .clients
+            .entry(self.bucket(key))
+            .or_insert_with(|| Usage::new(quota));
+        client.refill(quota, now);
+        if let Err(retry_after) = client.requests.check(quota.requests_per_minute, 1) {
+            return Err(QuotaExceeded {
+                resource: "requests",
+                retry_after,
+            });
+        }
+        if let Err(retry_after) = client.bytes.check(quota.bytes_per_minute, bytes) {
+            return Err(QuotaExceeded {
+                resource: "bytes",
+                retry_after,
+            });
+        }
+        client.requests.available -= 1.0;
+        client.bytes.available -= bytes as f64;
+        Ok(())
+    }
+}
+
+/// One client's remaining allowances.
+#[derive(Debug)]
+struct Usage {
+    requests: Allowance,
+    bytes: Allowance,
+    counted: Instant,
+}
+
+impl Usage {
+    /// Starts with the whole of `quota` available.
+    fn new(quota: Quota) -> Self {
+        Self {
+            requests: Allowance::full(quota.requests_per_minute),
+            bytes: Allowance::full(quota.bytes_per_minute),
+            counted: Instant::now(),
+        }
+    }
+
+    /// Adds what `quota` grants for the time since the allowances were last counted.
+    fn refill(&mut self, quota: Quota, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.counted);
+        self.requests.refill(quota.requests_per_minute, elapsed);
+        self.bytes.refill(quota.bytes_per_minute, elapsed);
+        self.counted = now;
+    }
+}
+
+/// What is left of one per-minute limit.
+#[derive(Debug)]
+struct Allowance {
+    available: f64,
+}
+
+impl Allowance {
+    fn full(per_minute: Option<u64>) -> Self {
+        Self {
+            available: per_minute.map_or(0.0, |limit| limit as f64),
+        }
+    }
+
+    fn refill(&mut self, per_minute: Option<u64>, elapsed: Duration) {
+        if let Some(limit) = per_minute {
+            let limit = limit as f64;
+            let refilled = elapsed.as_secs_f64() / REFILL_PERIOD.as_secs_f64() * limit;
+            self.available = (self.available + refilled).min(limit);
+        }
+    }
+
+    /// Checks that `wanted` is available, or returns how long until it will be: `None` if it
+    /// exceeds the whole limit and never will be.
+    fn check(&self, per_minute: Option<u64>, wanted: u64) -> Result<(), Option<Duration>> {
+        let Some(limit) = per_minute else {
+            return Ok(());
+        };
+        let wanted = wanted as f64;
+        if wanted <= self.available {
+            return Ok(());
+        }
+        if wanted > limit as f64 {
+            return Err(None);
+        }
+        let missing = wanted - self.available;
+        Err(Some(REFILL_PERIOD.mul_f64(missing / limit as f64)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn limited(requests: Option<u64>, bytes: Option<u64>) -> Quota {
+        Quota {
+            requests_per_minute: requests,
+            bytes_per_minute: bytes,
+        }
+    }
+
+    #[test]
+    fn test_from_path() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# key requests bytes").unwrap();
+        writeln!(file, "* 10 -").unwrap();
+        writeln!(file, "team-a - 1048576").unwrap();
+        let config = QuotaConfig::from_path(file.path()).unwrap();
+        assert_eq!(config.default, limited(Some(10), None));
+        assert_eq!(config.quota(Some("team-a")), limited(None, Some(1 << 20)));
+        assert_eq!(config.quota(Some("team-b")), limited(Some(10), None));
+        assert_eq!(config.quota(None), limited(Some(10), None));
+
+        writeln!(file, "team-c 5").unwrap();
+        assert!(QuotaConfig::from_path(file.path()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_requests_over_quota() {
+        let config = QuotaConfig {
+            default: limited(Some(2), None),
+            clients: HashMap::from([("big".to_string(), Quota::default())]),
+        };
+        let quotas = Quotas::new(config, true);
+        assert!(quotas.admit(Some("a"), 0).is_ok());
+        assert!(quotas.admit(Some("a"), 0).is_ok());
+        let rejected = quotas.admit(Some("a"), 0).unwrap_err();
+        assert_eq!(rejected.resource, "requests");
+        let retry_after = rejected.retry_after.unwrap();
+        assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+
+        // Other authenticated clients have allowances of their own.
+        assert!(quotas.admit(Some("b"), 0).is_ok());
+        assert!(quotas.admit(None, 0).is_ok());
+        for _ in 0..10 {
+            assert!(quotas.admit(Some("big"), 0).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_rejects_bytes_over_quota() {
+        let quotas = Quotas::new(
+            QuotaConfig {
+                default: limited(Some(10), Some(100)),
+                clients: HashMap::new(),
+            },
+            false,
+        );
+        assert!(quotas.admit(None, 60).is_ok());
+        let rejected = quotas.admit(None, 60).unwrap_err();
+        assert_eq!(rejected.resource, "bytes");
+        assert!(rejected.retry_after.is_some());
+        // The rejected request used up nothing.
+        assert!(quotas.admit(None, 40).is_ok());
+        assert_eq!(quotas.admit(None, 101).unwrap_err().retry_after, None);
+    }
+
+    #[test]
+    fn test_unverified_keys_share_the_default_allowance() {
+        let config = QuotaConfig {
+            default: limited(Some(2), None),
+            clients: HashMap::from([("team-a".to_string(), limited(Some(1), None))]),
+        };
+        let quotas = Quotas::new(config, false);
+        assert!(quotas.admit(Some("key-0"), 0).is_ok());
+        assert!(quotas.admit(None, 0).is_ok());
+        // Rotating keys draws on the same allowance, and leaves no entry per key behind.
+        for rotation in 1..100 {
+            let key = format!("key-{rotation}");
+            assert_eq!(
+                quotas.admit(Some(&key), 0).unwrap_err().resource,
+                "requests"
+            );
+        }
+        assert!(quotas.admit(None, 0).is_err());
+        assert_eq!(quotas.usage.lock().unwrap().clients.len(), 1);
+
+        // Listed keys keep their own allowance.
+        assert!(quotas.admit(Some("team-a"), 0).is_ok());
+        assert!(quotas.admit(Some("team-a"), 0).is_err());
+    }
+
+    #[test]
+    fn test_evicts_idle_clients() {
+        let quotas = Quotas::new(
+            QuotaConfig {
+                default: limited(Some(1), None),
+                clients: HashMap::new(),
+            },
+            true,
+        );
+        assert!(quotas.admit(Some("a"), 0).is_ok());
+        assert!(quotas.admit(Some("b"), 0).is_ok());
+        {
+            let mut usage = quotas.usage.lock().unwrap();
+            let idle = Instant::now() - REFILL_PERIOD;
+            usage.swept = idle;
+            usage
+                .clients
+                .get_mut(&Some("a".to_string()))
+                .unwrap()
+                .counted = idle;
+        }
+        assert!(quotas.admit(Some("c"), 0).is_ok());
+        let usage = quotas.usage.lock().unwrap();
+        let mut clients: Vec<_> = usage.clients.keys().flatten().collect();
+        clients.sort();
+        assert_eq!(clients, ["b", "c"]);
+    }
+
+    #[test]
+    fn test_refills_over_time() {
+        let quota = limited(Some(60), None);
+        let mut usage = Usage::new(quota);
+        usage.requests.available = 0.0;
+        let later = usage.counted + Duration::from_secs(2);
+        usage.refill(quota, later);
+        assert!((usage.requests.available - 2.0).abs() < 1e-9);
+        usage.refill(quota, later + Duration::from_secs(600));
+        assert_eq!(usage.requests.available, 60.0);
+    }
+}
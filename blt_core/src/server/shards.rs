@@ -0,0 +1,477 @@
+//! Read-only serving of existing token shards.
+//!
+//! Training jobs often run where the shards are not on a shared filesystem. With a shard
+//! directory configured, the server also answers:
+//!
+//! - `GET /shards`: JSON list of the shards in the directory (see [`ShardInfo`]). Index
+//!   sidecars are served but not listed.
+//! - `GET /shards/{name}`: the file as stored, honoring a single `Range: bytes=…` request so
+//!   clients can resume or fetch it in parts.
+//! - `GET /shards/{name}/tokens?start=S&end=E`: tokens `S..E` of the shard's token data. The
+//!   output header, if any, is skipped and gives the token width.
+//! - `GET /shards/{name}/documents?start=S&end=E`: the tokens of documents `S..E`, located with
+//!   the shard's `.idx` sidecar; shards without one answer `404 Not Found`.
+//!
+//! Both bounds of a slice are optional, and `end` is clamped to the shard. Slices carry the
+//! token width in the [`TOKEN_WIDTH_HEADER`] response header. Files are memory-mapped, so
+//! responses cost no copies; shards must not be rewritten while they are served.
+
+use crate::header::{self, OutputHeader};
+use crate::index::{self, DocumentIndex};
+use crate::io_handler::ByteRange;
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header as http, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// The response header giving the width in bytes of the tokens in a slice.
+pub const TOKEN_WIDTH_HEADER: &str = "x-token-width";
+
+/// The token width of shards written without an output header.
+const DEFAULT_TOKEN_WIDTH: u8 = 2;
+
+/// Describes one served shard.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ShardInfo {
+    /// The shard's file name, as used in its URLs.
+    pub name: String,
+    /// The size of the file in bytes.
+    pub bytes: u64,
+    /// The width of each token in bytes.
+    pub token_width: u8,
+    /// The number of tokens after the output header.
+    pub tokens: u64,
+    /// The number of documents, when the shard has an index sidecar.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documents: Option<u64>,
+}
+
+/// The directory shards are served from.
+#[derive(Debug)]
+pub(super) struct ShardStore {
+    root: PathBuf,
+}
+
+impl ShardStore {
+    /// Serves the files in `root`, which must be a directory.
+    pub(super) fn open(root: &Path) -> io::Result<Self> {
+        if !std::fs::metadata(root)?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Shard directory {} is not a directory", root.display()),
+            ));
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+        })
+    }
+
+    /// Lists the shards, sorted by name.
+    fn list(&self) -> io::Result<Vec<ShardInfo>> {
+        let mut shards = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !entry.file_type()?.is_file() || name.ends_with(&format!(".{}", index::EXTENSION)) {
+                continue;
+            }
+            shards.push(self.shard(&name)?.info(name));
+        }
+        shards.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(shards)
+    }
+
+    /// Opens the shard called `name`, which must be a file directly inside the directory.
+    fn shard(&self, name: &str) -> io::Result<Shard> {
+        let mut components = Path::new(name).components();
+        let (Some(Component::Normal(_)), None) = (components.next(), components.next()) else {
+            return Err(not_found(name));
+        };
+        let path = self.root.join(name);
+        let file = File::open(&path).map_err(|_| not_found(name))?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(not_found(name));
+        }
+        let mut start = Vec::with_capacity(header::HEADER_LEN);
+        (&file)
+            .take(header::HEADER_LEN as u64)
+            .read_to_end(&mut start)?;
+        let (token_width, data_start) = match OutputHeader::parse(&start) {
+            Ok((output_header, header_len)) => (output_header.token_width, header_len as u64),
+            Err(_) => (DEFAULT_TOKEN_WIDTH, 0),
+        };
+        let index_path = index::default_path(&path);
+        let index = match std::fs::read(&index_path) {
+            Ok(bytes) => Some(DocumentIndex::parse(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Shard {
+            file,
+            len: metadata.len(),
+            token_width: token_width.max(1),
+            data_start: data_start.min(metadata.len()),
+            index,
+        })
+    }
+}
+
+/// An open shard.
+struct Shard {
+    file: File,
+    len: u64,
+    token_width: u8,
+    data_start: u64,
+    index: Option<DocumentIndex>,
+}
+
+impl Shard {
+    fn info(&self, name: String) -> ShardInfo {
+        ShardInfo {
+            name,
+            bytes: self.len,
+            token_width: self.token_width,
+            tokens: self.tokens(),
+            documents: self.index.as_ref().map(|i| i.entries().len() as u64),
+        }
+    }
+
+    fn tokens(&self) -> u64 {
+        (self.len - self.data_start) / u64::from(self.token_width)
+    }
+
+    /// Maps bytes `start..end` of the file.
+    fn read(&self, start: u64, end: u64) -> io::Result<Bytes> {
+        let mmap = ByteRange::between(start, end)?.map_file(&self.file)?;
+        Ok(Bytes::from_owner(mmap))
+    }
+
+    /// Maps the bytes of tokens `start..end`, clamped to the shard.
+    fn tokens_between(&self, start: u64, end: Option<u64>) -> io::Result<Bytes> {
+        let end = end.unwrap_or(u64::MAX).min(self.tokens());
+        check_bounds("token", start, end)?;
+        let width = u64::from(self.token_width);
+        self.read(
+            self.data_start + start * width,
+            self.data_start + end * width,
+        )
+    }
+
+    /// Maps the bytes of documents `start..end`, clamped to the shard.
+    fn documents_between(&self, name: &str, start: u64, end: Option<u64>) -> io::Result<Bytes> {
+        let Some(index) = &self.index else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Shard {name} has no index"),
+            ));
+        };
+        let entries = index.entries();
+        let end = end.unwrap_or(u64::MAX).min(entries.len() as u64);
+        check_bounds("document", start, end)?;
+        if start == end {
+            return Ok(Bytes::new());
+        }
+        let width = u64::from(index.token_width());
+        let first = entries[start as usize];
+        let last = entries[end as usize - 1];
+        self.read(first.byte_offset, last.byte_offset + last.tokens * width)
+    }
+}
+
+fn check_bounds(unit: &str, start: u64, end: u64) -> io::Result<()> {
+    if start > end {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The {unit} range starts at {start}, past its end {end}"),
+        ));
+    }
+    Ok(())
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("No shard named {name}"))
+}
+
+/// The bounds of a slice.
+#[derive(Debug, Default, Deserialize)]
+struct SliceQuery {
+    #[serde(default)]
+    start: u64,
+    end: Option<u64>,
+}
+
+/// The routes serving the shards in `store`.
+pub(super) fn routes<S>(store: Arc<ShardStore>) -> Router<S> {
+    Router::new()
+        .route("/shards", get(list))
+        .route("/shards/{name}", get(file))
+        .route("/shards/{name}/tokens", get(tokens))
+        .route("/shards/{name}/documents", get(documents))
+        .with_state(store)
+}
+
+/// Runs blocking filesystem work off the async workers.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> Result<T, Response> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(io::Error::other)
+        .and_then(|result| result)
+        .map_err(error_response)
+}
+
+fn error_response(error: io::Error) -> Response {
+    let status = match error.kind() {
+        io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, error.to_string()).into_response()
+}
+
+async fn list(State(store): State<Arc<ShardStore>>) -> Result<Json<Vec<ShardInfo>>, Response> {
+    blocking(move || store.list()).await.map(Json)
+}
+
+async fn file(
+    State(store): State<Arc<ShardStore>>,
+    UrlPath(name): UrlPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    let range = headers
+        .get(http::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    blocking(move || {
+        let shard = store.shard(&name)?;
+        let Some(range) = range.and_then(|range| parse_range(&range, shard.len)) else {
+            let body = shard.read(0, shard.len)?;
+            return Ok(([(http::ACCEPT_RANGES, "bytes")], octets(body)).into_response());
+        };
+        let Ok((start, end)) = range else {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(http::CONTENT_RANGE, format!("bytes */{}", shard.len))],
+            )
+                .into_response());
+        };
+        let body = shard.read(start, end)?;
+        let content_range = format!("bytes {start}-{}/{}", end - 1, shard.len);
+        Ok((
+            StatusCode::PARTIAL_CONTENT,
+            [(http::CONTENT_RANGE, content_range)],
+            octets(body),
+        )
+            .into_response())
+    })
+    .await
+}
+
+async fn tokens(
+    State(store): State<Arc<ShardStore>>,
+    UrlPath(name): UrlPath<String>,
+    Query(slice): Query<SliceQuery>,
+) -> Result<Response, Response> {
+    blocking(move || {
+        let shard = store.shard(&name)?;
+        let body = shard.tokens_between(slice.start, slice.end)?;
+        Ok(token_slice(body, shard.token_width))
+    })
+    .await
+}
+
+async fn documents(
+    State(store): State<Arc<ShardStore>>,
+    UrlPath(name): UrlPath<String>,
+    Query(slice): Query<SliceQuery>,
+) -> Result<Response, Response> {
+    blocking(move || {
+        let shard = store.shard(&name)?;
+        let body = shard.documents_between(&name, slice.start, slice.end)?;
+        Ok(token_slice(body, shard.token_width))
+    })
+    .await
+}
+
+fn octets(body: Bytes) -> impl IntoResponse {
+    ([(http::CONTENT_TYPE, "application/octet-stream")], body)
+}
+
+fn token_slice(body: Bytes, token_width: u8) -> Response {
+    (
+        [(TOKEN_WIDTH_HEADER, token_width.to_string())],
+        octets(body),
+    )
+        .into_response()
+}
+
+/// Parses a `Range` header against a file of `len` bytes into the half-open range it asks for.
+///
+/// Returns `None` for headers to ignore (malformed ones, and multiple ranges, which are
+/// answered with the whole file), and `Some(Err(()))` for ranges the file cannot satisfy.
+fn parse_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let range = if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        (suffix > 0).then(|| (len.saturating_sub(suffix), len))
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => len,
+            end => end.parse::<u64>().ok()?.checked_add(1)?.min(len),
+        };
+        if end < start {
+            return None;
+        }
+        (start < len).then_some((start, end))
+    };
+    Some(range.filter(|(start, end)| start < end).ok_or(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::DocumentIndex;
+    use crate::StrategyKind;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    /// A directory with a headered, indexed shard of documents `[1, 2]` and `[3]`, and a bare
+    /// one of bytes.
+    fn shard_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bytes = OutputHeader::new(StrategyKind::Basic, None)
+            .to_bytes()
+            .to_vec();
+        bytes.extend_from_slice(&[0, 1, 0, 2, 0, 3]);
+        let path = dir.path().join("a.bin");
+        std::fs::write(&path, bytes).unwrap();
+        let mut index = DocumentIndex::new(2, header::HEADER_LEN as u64);
+        index.push(2);
+        index.push(1);
+        std::fs::write(index::default_path(&path), index.to_bytes()).unwrap();
+        std::fs::write(dir.path().join("b.bin"), b"abcdef").unwrap();
+        dir
+    }
+
+    async fn get(dir: &Path, uri: &str, range: Option<&str>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let store = Arc::new(ShardStore::open(dir).unwrap());
+        let request = Request::get(uri);
+        let request = match range {
+            Some(range) => request.header(http::RANGE, range),
+            None => request,
+        };
+        let response = routes::<()>(store)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_lists_shards() {
+        let dir = shard_dir();
+        let (status, _, body) = get(dir.path(), "/shards", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"name": "a.bin", "bytes": 26, "token_width": 2, "tokens": 3, "documents": 2},
+                {"name": "b.bin", "bytes": 6, "token_width": 2, "tokens": 3},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serves_byte_ranges() {
+        let dir = shard_dir();
+        let (status, headers, body) = get(dir.path(), "/shards/b.bin", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[http::ACCEPT_RANGES], "bytes");
+        assert_eq!(body, b"abcdef");
+
+        let (status, headers, body) = get(dir.path(), "/shards/b.bin", Some("bytes=1-2")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[http::CONTENT_RANGE], "bytes 1-2/6");
+        assert_eq!(body, b"bc");
+        let (_, _, body) = get(dir.path(), "/shards/b.bin", Some("bytes=-2")).await;
+        assert_eq!(body, b"ef");
+        let (_, _, body) = get(dir.path(), "/shards/b.bin", Some("bytes=4-99")).await;
+        assert_eq!(body, b"ef");
+
+        let (status, headers, _) = get(dir.path(), "/shards/b.bin", Some("bytes=6-")).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers[http::CONTENT_RANGE], "bytes */6");
+        let (status, _, _) = get(dir.path(), "/shards/b.bin", Some("bytes=0-1,3-4")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_slices_tokens_and_documents() {
+        let dir = shard_dir();
+        let (status, headers, body) =
+            get(dir.path(), "/shards/a.bin/tokens?start=1&end=9", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[TOKEN_WIDTH_HEADER], "2");
+        assert_eq!(body, [0, 2, 0, 3]);
+        let (_, _, body) = get(dir.path(), "/shards/a.bin/documents?end=1", None).await;
+        assert_eq!(body, [0, 1, 0, 2]);
+        let (_, _, body) = get(dir.path(), "/shards/a.bin/documents?start=1", None).await;
+        assert_eq!(body, [0, 3]);
+        let (_, _, body) = get(dir.path(), "/shards/b.bin/tokens?end=1", None).await;
+        assert_eq!(body, b"ab");
+
+        let (status, _, _) = get(dir.path(), "/shards/a.bin/tokens?start=2&end=1", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _, _) = get(dir.path(), "/shards/b.bin/documents", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_names_outside_the_directory() {
+        let dir = shard_dir();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        for uri in [
+            "/shards/missing.bin",
+            "/shards/..",
+            "/shards/sub",
+            "/shards/%2E%2E%2Fa.bin",
+        ] {
+            assert_eq!(
+                get(dir.path(), uri, None).await.0,
+                StatusCode::NOT_FOUND,
+                "{uri}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-0", 4), Some(Ok((0, 1))));
+        assert_eq!(parse_range("bytes=2-", 4), Some(Ok((2, 4))));
+        assert_eq!(parse_range("bytes=-9", 4), Some(Ok((0, 4))));
+        assert_eq!(parse_range("bytes=4-5", 4), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 4), Some(Err(())));
+        assert_eq!(parse_range("bytes=3-1", 4), None);
+        assert_eq!(parse_range("items=0-1", 4), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Request-scoped trace IDs for the server mode.
+//!
+//! Each request runs inside a `request` span carrying a trace ID. When the caller sends a
+//! W3C `traceparent` header, its trace ID and parent span ID are adopted, so tokenization
+//! spans can be correlated with the caller's own traces; otherwise a fresh trace ID is made
+//! up. Either way, the trace ID is returned in the `X-Trace-Id` response header.
+
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{info_span, Instrument};
+
+/// The W3C trace context header.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The response header carrying the request's trace ID.
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
+
+/// The parts of a `traceparent` header the server uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TraceParent {
+    trace_id: u128,
+    parent_id: u64,
+}
+
+impl TraceParent {
+    /// Parses a `traceparent` header value: `VERSION-TRACE_ID-PARENT_ID-FLAGS` in lowercase
+    /// hex. Unknown versions are accepted as long as they start with these fields, as the
+    /// specification asks; all-zero IDs and version `ff` are not.
+    fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.trim().split('-');
+        let version = fields.next()?;
+        let trace_id = fields.next()?;
+        let parent_id = fields.next()?;
+        let flags = fields.next()?;
+        if !is_hex(version, 2) || version == "ff" || !is_hex(flags, 2) {
+            return None;
+        }
+        if version == "00" && fields.next().is_some() {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || !is_hex(parent_id, 16) {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let parent_id = u64::from_str_radix(parent_id, 16).ok()?;
+        (trace_id != 0 && parent_id != 0).then_some(Self {
+            trace_id,
+            parent_id,
+        })
+    }
+}
+
+fn is_hex(field: &str, len: usize) -> bool {
+    field.len() == len
+        && field
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// A random, non-zero 64-bit ID.
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        match hasher.finish() {
+            0 => continue,
+            id => return id,
+        }
+    }
+}
+
+/// Runs the request in a span carrying its trace ID, and returns the trace ID to the caller.
+pub(super) async fn trace_request(request: Request, next: Next) -> Response {
+    let parent = request
+        .headers()
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceParent::parse);
+    let trace_id = parent.map_or_else(
+        || (u128::from(random_id()) << 64) | u128::from(random_id()),
+        |parent| parent.trace_id,
+    );
+    let trace_id = format!("{trace_id:032x}");
+    let span = info_span!(
+        "request",
+        trace_id = %trace_id,
+        span_id = %format_args!("{:016x}", random_id()),
+        parent_id = parent.map(|parent| tracing::field::display(format!("{:016x}", parent.parent_id))),
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&trace_id) {
+        response.headers_mut().insert(TRACE_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent() {
+        assert_eq!(
+            TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some(TraceParent {
+                trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+                parent_id: 0x00f067aa0ba902b7,
+            })
+        );
+        // Future versions may append fields.
+        assert!(
+            TraceParent::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x")
+                .is_some()
+        );
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceParent::parse(invalid), None, "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_random_ids_differ() {
+        assert_ne!(random_id(), random_id());
+    }
+}
@@ -0,0 +1,178 @@
+//! Warm-start snapshots of a loaded vocabulary.
+//!
+//! Parsing a large text merges file line by line dominates the startup of short-lived
+//! processes such as serverless handlers. A snapshot stores the parsed merges table in a
+//! compact binary form that loads with a single read and no parsing. Wherever a merges file is
+//! accepted, a snapshot may be given instead; it is recognized by its magic bytes.
+//!
+//! Layout (all multi-byte integers big-endian):
+//!
+//! | Offset | Size  | Field                                               |
+//! |--------|-------|-----------------------------------------------------|
+//! | 0      | 4     | magic `b"BLTS"`                                     |
+//! | 4      | 2     | format version                                      |
+//! | 6      | 2     | token ID of the first merge                         |
+//! | 8      | 8     | [`merges_hash`] of the table                        |
+//! | 16     | 4     | number of merges                                    |
+//! | 20     | 6 × n | merges as `first second token`, sorted by token     |
+//!
+//! The merges are stored in the order [`merges_hash`] visits them, so restoring a snapshot
+//! checks its integrity in the same pass that rebuilds the table.
+
+use crate::config_loader::{self, merges_hash, DEFAULT_MERGE_ID_BASE};
+use crate::BpeMerges;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The magic bytes that open every snapshot.
+pub const MAGIC: [u8; 4] = *b"BLTS";
+/// The snapshot format version written by this version of blt.
+pub const FORMAT_VERSION: u16 = 1;
+/// The length of the fixed part of a snapshot, before the merges.
+const HEADER_LEN: usize = 20;
+/// The length of one stored merge.
+const MERGE_LEN: usize = 6;
+
+/// A restored vocabulary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The token ID of the first merge.
+    pub base: u16,
+    /// The merges table.
+    pub merges: BpeMerges,
+}
+
+impl Snapshot {
+    /// Wraps `merges`, whose IDs were assigned from `base` upwards.
+    pub fn new(merges: BpeMerges, base: u16) -> Self {
+        Self { base, merges }
+    }
+
+    /// Encodes the snapshot.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries: Vec<(&(u16, u16), &u16)> = self.merges.iter().collect();
+        entries.sort_unstable_by_key(|&(pair, token)| (*token, *pair));
+        let mut bytes = Vec::with_capacity(HEADER_LEN + entries.len() * MERGE_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&self.base.to_be_bytes());
+        bytes.extend_from_slice(&merges_hash(&self.merges).to_be_bytes());
+        bytes.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (&(first, second), &token) in entries {
+            for value in [first, second, token] {
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Decodes a snapshot, checking that the merges match the recorded hash.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        if bytes.len() < HEADER_LEN || bytes[..4] != MAGIC {
+            return Err(invalid("Not a blt vocabulary snapshot".to_string()));
+        }
+        let u16_at = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let version = u16_at(4);
+        if version != FORMAT_VERSION {
+            return Err(invalid(format!(
+                "Unsupported vocabulary snapshot version {version}; re-create it with `blt snapshot`"
+            )));
+        }
+        let base = u16_at(6);
+        let hash = u64::from_be_bytes(bytes[8..16].try_into().expect("8 bytes"));
+        let count = u32::from_be_bytes(bytes[16..20].try_into().expect("4 bytes")) as usize;
+        let stored = &bytes[HEADER_LEN..];
+        if stored.len() != count * MERGE_LEN {
+            return Err(invalid(format!(
+                "Vocabulary snapshot is truncated or padded: expected {count} merges"
+            )));
+        }
+
+        let mut merges = BpeMerges::with_capacity_and_hasher(count, Default::default());
+        let mut computed: u64 = 0xcbf2_9ce4_8422_2325;
+        for entry in stored.chunks_exact(MERGE_LEN) {
+            for &byte in entry {
+                computed ^= byte as u64;
+                computed = computed.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+            let value = |at: usize| u16::from_be_bytes([entry[at], entry[at + 1]]);
+            merges.insert((value(0), value(2)), value(4));
+        }
+        if computed != hash || merges.len() != count {
+            return Err(invalid(
+                "Vocabulary snapshot is corrupt: its merges do not match the recorded hash"
+                    .to_string(),
+            ));
+        }
+        Ok(Self { base, merges })
+    }
+}
+
+/// Loads the merges file at `merges_path` and writes a snapshot of it to `snapshot_path`.
+pub fn create(merges_path: &Path, snapshot_path: &Path) -> io::Result<Snapshot> {
+    let merges = config_loader::load_bpe_merges_from_path(merges_path)?;
+    let snapshot = Snapshot::new(merges, DEFAULT_MERGE_ID_BASE);
+    fs::write(snapshot_path, snapshot.to_bytes())?;
+    Ok(snapshot)
+}
+
+/// Restores the snapshot at `path`, or returns `None` if the file is not a snapshot.
+pub fn read_if_snapshot(path: &Path) -> io::Result<Option<Snapshot>> {
+    let mut file = File::open(path)?;
+    let mut bytes = vec![0; MAGIC.len()];
+    match file.read_exact(&mut bytes) {
+        Ok(()) if bytes == MAGIC => {}
+        Ok(()) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    file.read_to_end(&mut bytes)?;
+    Snapshot::from_bytes(&bytes).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_roundtrip() {
+        let merges: BpeMerges = vec![((97, 98), 256), ((256, 99), 257), ((32, 32), 258)]
+            .into_iter()
+            .collect();
+        let snapshot = Snapshot::new(merges, DEFAULT_MERGE_ID_BASE);
+        let bytes = snapshot.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 3 * MERGE_LEN);
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN + 1] ^= 1;
+        assert!(Snapshot::from_bytes(&corrupt).is_err());
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut future = bytes;
+        future[5] = 2;
+        assert!(Snapshot::from_bytes(&future).is_err());
+    }
+
+    #[test]
+    fn test_create_and_detect() {
+        let mut merges_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(merges_file, "97 98").unwrap();
+        writeln!(merges_file, "99 100").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_path = dir.path().join("vocab.snap");
+        let created = create(merges_file.path(), &snapshot_path).unwrap();
+
+        let restored = read_if_snapshot(&snapshot_path).unwrap().unwrap();
+        assert_eq!(restored, created);
+        assert_eq!(restored.merges[&(99, 100)], 257);
+        assert_eq!(read_if_snapshot(merges_file.path()).unwrap(), None);
+        // Merges files load as well from either form.
+        assert_eq!(
+            config_loader::load_bpe_merges_from_path(&snapshot_path).unwrap(),
+            created.merges
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Reserved special token values.
+//!
+//! The range `0xFF00..=0xFFFF` is reserved for tokens with structural meaning (content types,
+//! separators, padding, log structure). Byte tokens and merge tokens are expected to stay below
+//! it.
+
+use std::ops::RangeInclusive;
+
+/// The token values reserved for special tokens.
+pub const RESERVED_RANGE: RangeInclusive<u16> = 0xFF00..=0xFFFF;
+
+/// Default padding token.
+pub const PAD: u16 = 0xFF00;
+/// Default end-of-document separator token.
+pub const SEPARATOR: u16 = 0xFF05;
+/// Default sentinel token replacing masked spans.
+pub const MASK: u16 = 0xFF06;
+/// Separator ending each log record.
+pub const RECORD_SEPARATOR: u16 = 0xFF07;
+/// The tokens tagging log records by severity, from trace to fatal.
+pub const SEVERITY_RANGE: RangeInclusive<u16> = 0xFF08..=0xFF0D;
+/// The tokens opening the segments of a demuxed video container: metadata, video, audio,
+/// and other data.
+pub const SEGMENT_RANGE: RangeInclusive<u16> = 0xFFF0..=0xFFF3;
+/// Marker opening a region of input that looks compressed or encrypted.
+pub const COMPRESSED_REGION: u16 = 0xFFF4;
+/// Marker opening a region of input that looks uncompressed.
+pub const PLAIN_REGION: u16 = 0xFFF5;
+
+/// Returns `true` if `token` lies in the reserved special-token range.
+pub fn is_reserved(token: u16) -> bool {
+    RESERVED_RANGE.contains(&token)
+}
+
+/// Returns `true` for the reserved tokens with a fixed structural meaning: padding, separators,
+/// masks, log severities, container segments, and region markers. Custom content types may
+/// not use them.
+pub fn is_structural(token: u16) -> bool {
+    [
+        PAD,
+        SEPARATOR,
+        MASK,
+        RECORD_SEPARATOR,
+        COMPRESSED_REGION,
+        PLAIN_REGION,
+    ]
+    .contains(&token)
+        || SEVERITY_RANGE.contains(&token)
+        || SEGMENT_RANGE.contains(&token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContentType;
+
+    #[test]
+    fn test_defaults_are_reserved_and_distinct_from_content_types() {
+        assert!(is_reserved(PAD));
+        assert!(is_reserved(SEPARATOR));
+        assert!(is_reserved(MASK));
+        assert!(is_reserved(RECORD_SEPARATOR));
+        assert!(is_reserved(*SEVERITY_RANGE.end()));
+        assert!(is_reserved(*SEGMENT_RANGE.end()));
+        assert!(is_reserved(PLAIN_REGION));
+        for content_type in ContentType::ALL {
+            let token = content_type.get_token_value();
+            assert!(is_reserved(token));
+            assert!(!is_structural(token));
+        }
+        assert!(!is_reserved(255));
+    }
+}
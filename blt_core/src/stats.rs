@@ -0,0 +1,70 @@
+//! Corpus statistics for evaluating vocabularies and inputs.
+//!
+//! Most reports tokenize a sample corpus with a merges table and summarize how the table
+//! performs on it; the byte histogram checks the raw inputs themselves, the merges inspection
+//! checks a merges file itself, and the manifest report summarizes a finished run from its
+//! manifest alone. Inputs are tokenized in fixed-size chunks, exactly as the pipeline does, so
+//! the numbers match what a real run would produce.
+
+use crate::tokenizer::BpeStrategy;
+use std::io::{self, Read};
+use std::path::Path;
+
+mod coverage;
+mod histogram;
+mod inspect;
+mod manifest_report;
+mod pruning;
+mod vocab_diff;
+pub use coverage::{coverage_report, CoverageReport, CoverageStats};
+pub use histogram::{
+    byte_histogram_report, quarantine, Anomaly, AnomalyPolicy, ByteHistogramReport, FileByteStats,
+    QuarantineMode,
+};
+pub use inspect::{
+    inspect_merges, DuplicatePair, MalformedLine, MergeRule, MergesFormat, MergesInspection,
+    MergesTable, UndefinedReference,
+};
+pub use manifest_report::{manifest_report, DedupStats, GroupStats, LengthBucket, ManifestReport};
+pub use pruning::{merge_usage, to_merges_file, MergeRanking, MergeUsage};
+pub use vocab_diff::{
+    canary_report, diff_merges, retokenization_deltas, vocab_diff_report, CanaryGate, CanaryReport,
+    CanaryResult, DocumentDelta, MergesDiff, RemappedMerge, VocabDiffReport,
+};
+
+/// The chunk size used when tokenizing sample inputs.
+pub const SAMPLE_CHUNK_SIZE: usize = 1 << 20;
+
+/// Tokenizes the file at `path` chunk by chunk, calling `visit` with each chunk and its tokens.
+pub(crate) fn for_each_chunk(
+    path: &Path,
+    strategy: &BpeStrategy,
+    mut visit: impl FnMut(&[u8], &[u16]),
+) -> io::Result<()> {
+    for_each_raw_chunk(path, |chunk| visit(chunk, &strategy.encode(chunk)))
+}
+
+/// Reads the file at `path` in [`SAMPLE_CHUNK_SIZE`] chunks, calling `visit` with each one.
+pub(crate) fn for_each_raw_chunk(path: &Path, mut visit: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = vec![0u8; SAMPLE_CHUNK_SIZE];
+    loop {
+        let read = read_full(&mut file, &mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        visit(&buffer[..read]);
+    }
+}
+
+/// Reads until `buffer` is full or the reader is exhausted.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
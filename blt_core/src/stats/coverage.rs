@@ -0,0 +1,162 @@
+//! Vocabulary coverage: how much of a corpus a merges table actually merges.
+//!
+//! Every merge covers two bytes, so a byte is either part of a merged token or emitted as a
+//! raw byte token (IDs below 256). A low merged fraction means the vocabulary fits the corpus
+//! poorly.
+
+use super::for_each_chunk;
+use crate::tokenizer::BpeStrategy;
+use crate::{BpeMerges, ContentType};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Coverage counters for a group of inputs.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CoverageStats {
+    /// The number of input files.
+    pub files: u64,
+    /// Input bytes.
+    pub bytes: u64,
+    /// Tokens produced.
+    pub tokens: u64,
+    /// Tokens that are raw bytes rather than merges.
+    pub raw_byte_tokens: u64,
+    /// Input bytes covered by merged tokens.
+    pub merged_bytes: u64,
+}
+
+impl CoverageStats {
+    /// The fraction of input bytes covered by merged tokens.
+    pub fn merged_fraction(&self) -> f64 {
+        ratio(self.merged_bytes, self.bytes)
+    }
+
+    /// The average number of bytes per token.
+    pub fn bytes_per_token(&self) -> f64 {
+        ratio(self.bytes, self.tokens)
+    }
+
+    fn add(&mut self, other: &CoverageStats) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.tokens += other.tokens;
+        self.raw_byte_tokens += other.raw_byte_tokens;
+        self.merged_bytes += other.merged_bytes;
+    }
+}
+
+/// A coverage report broken down by content type.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CoverageReport {
+    /// Per content type counters, keyed by [`ContentType::name`], in token order.
+    pub by_content_type: Vec<(String, CoverageStats)>,
+    /// Counters over every input.
+    pub total: CoverageStats,
+}
+
+/// Tokenizes every input with `merges` and reports coverage per content type.
+pub fn coverage_report(
+    merges: BpeMerges,
+    inputs: &[(PathBuf, ContentType)],
+) -> io::Result<CoverageReport> {
+    let strategy = BpeStrategy::new(Arc::new(merges));
+    let mut groups: BTreeMap<(u16, &str), CoverageStats> = BTreeMap::new();
+    for (path, content_type) in inputs {
+        let mut stats = CoverageStats {
+            files: 1,
+            ..CoverageStats::default()
+        };
+        for_each_chunk(path, &strategy, |chunk, tokens| {
+            let raw = tokens.iter().filter(|&&t| t < 256).count() as u64;
+            stats.bytes += chunk.len() as u64;
+            stats.tokens += tokens.len() as u64;
+            stats.raw_byte_tokens += raw;
+            stats.merged_bytes += chunk.len() as u64 - raw;
+        })?;
+        let key = (content_type.get_token_value(), content_type.name());
+        groups.entry(key).or_default().add(&stats);
+    }
+
+    let mut report = CoverageReport::default();
+    for ((_, name), stats) in groups {
+        report.total.add(&stats);
+        report.by_content_type.push((name.to_string(), stats));
+    }
+    Ok(report)
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:>6} {:>14} {:>14} {:>14} {:>8} {:>8}",
+            "type", "files", "bytes", "tokens", "raw_tokens", "merged", "B/token"
+        )?;
+        let rows = self
+            .by_content_type
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats))
+            .chain(std::iter::once(("total", &self.total)));
+        for (name, stats) in rows {
+            writeln!(
+                f,
+                "{:<8} {:>6} {:>14} {:>14} {:>14} {:>7.2}% {:>8.3}",
+                name,
+                stats.files,
+                stats.bytes,
+                stats.tokens,
+                stats.raw_byte_tokens,
+                stats.merged_fraction() * 100.0,
+                stats.bytes_per_token()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_coverage_by_content_type() {
+        let mut text = tempfile::NamedTempFile::new().unwrap();
+        text.write_all(b"ababx").unwrap();
+        let mut bin = tempfile::NamedTempFile::new().unwrap();
+        bin.write_all(b"xyz").unwrap();
+        let merges: BpeMerges = [((b'a' as u16, b'b' as u16), 256)].into_iter().collect();
+
+        let report = coverage_report(
+            merges,
+            &[
+                (text.path().to_path_buf(), ContentType::Text),
+                (bin.path().to_path_buf(), ContentType::Bin),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(report.by_content_type.len(), 2);
+        let (name, text_stats) = &report.by_content_type[0];
+        assert_eq!(name, "text");
+        assert_eq!(text_stats.tokens, 3);
+        assert_eq!(text_stats.merged_bytes, 4);
+        assert_eq!(text_stats.merged_fraction(), 0.8);
+        assert_eq!(report.by_content_type[1].1.merged_bytes, 0);
+        assert_eq!(report.total.bytes, 8);
+        assert_eq!(report.total.files, 2);
+        assert!(report.to_string().contains("total"));
+    }
+}
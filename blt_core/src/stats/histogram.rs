@@ -0,0 +1,422 @@
+//! Byte-value histograms and input anomaly detection.
+//!
+//! A text corpus that picked up binaries, UTF-16 exports, or Latin-1 files tokenizes without
+//! error but trains on noise. Each input's byte histogram is checked against what its content
+//! type should look like, and inputs that stray past the [`AnomalyPolicy`] thresholds are
+//! flagged and can be moved or copied aside with [`quarantine`].
+
+use super::for_each_raw_chunk;
+use crate::ContentType;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Thresholds above which an input is flagged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnomalyPolicy {
+    /// The largest fraction of control bytes (other than tab, newline, carriage return, and
+    /// form feed) tolerated in text inputs.
+    pub max_control_fraction: f64,
+    /// The largest fraction of bytes outside valid UTF-8 sequences tolerated in text inputs.
+    pub max_invalid_utf8_fraction: f64,
+    /// The highest entropy, in bits per byte, tolerated in text inputs. Natural-language text
+    /// stays well below 6; compressed or encrypted data approaches 8.
+    pub max_text_entropy: f64,
+}
+
+impl Default for AnomalyPolicy {
+    fn default() -> Self {
+        Self {
+            max_control_fraction: 0.001,
+            max_invalid_utf8_fraction: 0.0,
+            max_text_entropy: 7.0,
+        }
+    }
+}
+
+/// Why an input was flagged.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// The input starts with a UTF-16 byte order mark.
+    Utf16,
+    /// Control bytes, such as NULs, suggest binary data in a text input.
+    ControlBytes {
+        /// The fraction of the input's bytes that are control bytes.
+        fraction: f64,
+    },
+    /// Bytes outside valid UTF-8 suggest another encoding, such as Latin-1.
+    InvalidUtf8 {
+        /// The fraction of the input's bytes that are not valid UTF-8.
+        fraction: f64,
+    },
+    /// Near-uniform byte values suggest compressed or encrypted data.
+    HighEntropy {
+        /// The input's entropy in bits per byte.
+        bits_per_byte: f64,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::Utf16 => f.write_str("utf-16"),
+            Anomaly::ControlBytes { fraction } => {
+                write!(f, "control-bytes({:.2}%)", fraction * 100.0)
+            }
+            Anomaly::InvalidUtf8 { fraction } => {
+                write!(f, "invalid-utf8({:.2}%)", fraction * 100.0)
+            }
+            Anomaly::HighEntropy { bits_per_byte } => {
+                write!(f, "high-entropy({bits_per_byte:.2})")
+            }
+        }
+    }
+}
+
+/// The byte histogram of one input and what was flagged in it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FileByteStats {
+    /// The input file.
+    pub path: PathBuf,
+    /// The content type the input was checked against.
+    pub content_type: String,
+    /// How often each byte value occurs, indexed by byte value.
+    pub histogram: Vec<u64>,
+    /// The number of bytes outside valid UTF-8 sequences.
+    pub invalid_utf8_bytes: u64,
+    /// The anomalies found, empty for inputs that look as expected.
+    pub anomalies: Vec<Anomaly>,
+    /// Where the input was moved or copied, if it was quarantined.
+    pub quarantined: Option<PathBuf>,
+}
+
+impl FileByteStats {
+    /// The size of the input in bytes.
+    pub fn bytes(&self) -> u64 {
+        self.histogram.iter().sum()
+    }
+
+    /// The Shannon entropy of the byte values, in bits per byte.
+    pub fn entropy(&self) -> f64 {
+        let total = self.bytes() as f64;
+        self.histogram
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// The fraction of bytes that are control bytes other than common whitespace.
+    pub fn control_fraction(&self) -> f64 {
+        let control: u64 = self
+            .histogram
+            .iter()
+            .enumerate()
+            .filter(|&(byte, _)| is_control(byte as u8))
+            .map(|(_, &count)| count)
+            .sum();
+        ratio(control, self.bytes())
+    }
+
+    /// Returns `true` if any anomaly was found.
+    pub fn is_flagged(&self) -> bool {
+        !self.anomalies.is_empty()
+    }
+}
+
+/// Byte histograms for a set of inputs.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ByteHistogramReport {
+    /// One entry per input, in input order.
+    pub files: Vec<FileByteStats>,
+}
+
+impl ByteHistogramReport {
+    /// The inputs with at least one anomaly.
+    pub fn flagged(&self) -> impl Iterator<Item = &FileByteStats> {
+        self.files.iter().filter(|file| file.is_flagged())
+    }
+}
+
+/// Computes the byte histogram of every input and flags inputs that break `policy` for their
+/// content type. Only text inputs are checked; other types are reported without anomalies.
+pub fn byte_histogram_report(
+    inputs: &[(PathBuf, ContentType)],
+    policy: &AnomalyPolicy,
+) -> io::Result<ByteHistogramReport> {
+    let mut report = ByteHistogramReport::default();
+    for (path, content_type) in inputs {
+        let mut histogram = vec![0u64; 256];
+        let mut utf8 = Utf8Counter::default();
+        let mut starts_with_bom = None;
+        for_each_raw_chunk(path, |chunk| {
+            starts_with_bom.get_or_insert_with(|| {
+                chunk.starts_with(&[0xFF, 0xFE]) || chunk.starts_with(&[0xFE, 0xFF])
+            });
+            for &byte in chunk {
+                histogram[byte as usize] += 1;
+            }
+            utf8.update(chunk);
+        })?;
+        let mut stats = FileByteStats {
+            path: path.clone(),
+            content_type: content_type.name().to_string(),
+            histogram,
+            invalid_utf8_bytes: utf8.finish(),
+            anomalies: Vec::new(),
+            quarantined: None,
+        };
+        if *content_type == ContentType::Text {
+            stats.anomalies = text_anomalies(&stats, starts_with_bom == Some(true), policy);
+        }
+        report.files.push(stats);
+    }
+    Ok(report)
+}
+
+fn text_anomalies(stats: &FileByteStats, utf16_bom: bool, policy: &AnomalyPolicy) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    if utf16_bom {
+        anomalies.push(Anomaly::Utf16);
+    }
+    let control = stats.control_fraction();
+    if control > policy.max_control_fraction {
+        anomalies.push(Anomaly::ControlBytes { fraction: control });
+    }
+    let invalid = ratio(stats.invalid_utf8_bytes, stats.bytes());
+    if invalid > policy.max_invalid_utf8_fraction {
+        anomalies.push(Anomaly::InvalidUtf8 { fraction: invalid });
+    }
+    let entropy = stats.entropy();
+    if entropy > policy.max_text_entropy {
+        anomalies.push(Anomaly::HighEntropy {
+            bits_per_byte: entropy,
+        });
+    }
+    anomalies
+}
+
+/// What [`quarantine`] does with a flagged input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuarantineMode {
+    /// Moves the input into the quarantine directory.
+    #[default]
+    Move,
+    /// Copies the input, leaving the original in place.
+    Copy,
+}
+
+impl FromStr for QuarantineMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "move" => Ok(QuarantineMode::Move),
+            "copy" => Ok(QuarantineMode::Copy),
+            _ => Err(format!(
+                "Unsupported quarantine mode: '{s}'. Use move or copy."
+            )),
+        }
+    }
+}
+
+/// Moves or copies `path` into `dir` under its file name, adding a numeric suffix if the name
+/// is taken, and returns the new path.
+pub fn quarantine(path: &Path, dir: &Path, mode: QuarantineMode) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Cannot quarantine {}: it has no file name", path.display()),
+        )
+    })?;
+    let mut destination = dir.join(name);
+    let mut suffix = 1;
+    while destination.exists() {
+        let mut numbered = name.to_os_string();
+        numbered.push(format!(".{suffix}"));
+        destination = dir.join(numbered);
+        suffix += 1;
+    }
+    match mode {
+        QuarantineMode::Copy => {
+            std::fs::copy(path, &destination)?;
+        }
+        // Renaming fails across file systems, where the input is copied and then removed.
+        QuarantineMode::Move => {
+            if std::fs::rename(path, &destination).is_err() {
+                std::fs::copy(path, &destination)?;
+                std::fs::remove_file(path)?;
+            }
+        }
+    }
+    Ok(destination)
+}
+
+impl fmt::Display for ByteHistogramReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>14} {:>8} {:>9} {:>9}  {:<6} file",
+            "bytes", "entropy", "control", "bad_utf8", "type"
+        )?;
+        for file in &self.files {
+            write!(
+                f,
+                "{:>14} {:>8.3} {:>8.3}% {:>8.3}%  {:<6} {}",
+                file.bytes(),
+                file.entropy(),
+                file.control_fraction() * 100.0,
+                ratio(file.invalid_utf8_bytes, file.bytes()) * 100.0,
+                file.content_type,
+                file.path.display()
+            )?;
+            if file.is_flagged() {
+                let anomalies: Vec<String> =
+                    file.anomalies.iter().map(Anomaly::to_string).collect();
+                write!(f, "  FLAGGED: {}", anomalies.join(", "))?;
+            }
+            if let Some(destination) = &file.quarantined {
+                write!(f, " -> {}", destination.display())?;
+            }
+            writeln!(f)?;
+        }
+        let flagged = self.flagged().count();
+        writeln!(f, "{flagged} of {} file(s) flagged", self.files.len())
+    }
+}
+
+/// Control bytes other than tab, newline, form feed, and carriage return, plus DEL.
+fn is_control(byte: u8) -> bool {
+    (byte < 0x20 && !matches!(byte, b'\t' | b'\n' | 0x0C | b'\r')) || byte == 0x7F
+}
+
+/// Counts bytes outside valid UTF-8 sequences across chunk boundaries.
+#[derive(Default)]
+struct Utf8Counter {
+    /// The start of a sequence cut off at the end of the previous chunk.
+    pending: Vec<u8>,
+    invalid: u64,
+}
+
+impl Utf8Counter {
+    fn update(&mut self, chunk: &[u8]) {
+        let joined;
+        let bytes = if self.pending.is_empty() {
+            chunk
+        } else {
+            joined = [std::mem::take(&mut self.pending).as_slice(), chunk].concat();
+            &joined
+        };
+        let consumed = self.consume(bytes);
+        self.pending = bytes[consumed..].to_vec();
+    }
+
+    /// Counts the invalid bytes in `bytes` and returns the length checked, which stops short
+    /// of an incomplete sequence at the end.
+    fn consume(&mut self, bytes: &[u8]) -> usize {
+        let mut offset = 0;
+        loop {
+            match std::str::from_utf8(&bytes[offset..]) {
+                Ok(_) => return bytes.len(),
+                Err(e) => {
+                    offset += e.valid_up_to();
+                    match e.error_len() {
+                        Some(len) => {
+                            self.invalid += len as u64;
+                            offset += len;
+                        }
+                        None => return offset,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the number of invalid bytes, counting a sequence cut off by the end of input.
+    fn finish(self) -> u64 {
+        self.invalid + self.pending.len() as u64
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_flags_binary_and_misencoded_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let clean = dir.path().join("clean.txt");
+        std::fs::write(&clean, "plain text, caf\u{e9}\n".repeat(100)).unwrap();
+        let latin1 = dir.path().join("latin1.txt");
+        std::fs::write(&latin1, b"caf\xe9 au lait\n".repeat(100)).unwrap();
+        let mut binary = tempfile::NamedTempFile::new_in(dir.path()).unwrap();
+        binary.write_all(&[0u8, 1, 2, b'a'].repeat(100)).unwrap();
+        let inputs = [
+            (clean, ContentType::Text),
+            (latin1, ContentType::Text),
+            (binary.path().to_path_buf(), ContentType::Text),
+            (binary.path().to_path_buf(), ContentType::Bin),
+        ];
+
+        let report = byte_histogram_report(&inputs, &AnomalyPolicy::default()).unwrap();
+        assert!(report.files[0].anomalies.is_empty());
+        assert_eq!(report.files[0].invalid_utf8_bytes, 0);
+        assert_eq!(report.files[1].invalid_utf8_bytes, 100);
+        assert!(matches!(
+            report.files[1].anomalies[..],
+            [Anomaly::InvalidUtf8 { .. }]
+        ));
+        assert!(matches!(
+            report.files[2].anomalies[..],
+            [Anomaly::ControlBytes { fraction }] if fraction == 0.75
+        ));
+        assert!(!report.files[3].is_flagged());
+        assert_eq!(report.flagged().count(), 2);
+        assert_eq!(report.files[2].histogram[0], 100);
+        assert!(report.to_string().contains("2 of 4 file(s) flagged"));
+    }
+
+    #[test]
+    fn test_utf8_sequences_split_across_chunks() {
+        let text = "\u{1F600} ok \u{e9}".as_bytes();
+        for split in 0..text.len() {
+            let mut counter = Utf8Counter::default();
+            counter.update(&text[..split]);
+            counter.update(&text[split..]);
+            assert_eq!(counter.finish(), 0, "split at {split}");
+        }
+        let mut truncated = Utf8Counter::default();
+        truncated.update(&text[..2]);
+        assert_eq!(truncated.finish(), 2);
+    }
+
+    #[test]
+    fn test_quarantine_avoids_name_collisions() {
+        let inputs = tempfile::tempdir().unwrap();
+        let quarantine_dir = inputs.path().join("quarantine");
+        let path = inputs.path().join("bad.txt");
+        std::fs::write(&path, b"\0").unwrap();
+        let copied = quarantine(&path, &quarantine_dir, QuarantineMode::Copy).unwrap();
+        assert!(path.exists());
+        let moved = quarantine(&path, &quarantine_dir, QuarantineMode::Move).unwrap();
+        assert!(!path.exists());
+        assert_eq!(copied, quarantine_dir.join("bad.txt"));
+        assert_eq!(moved, quarantine_dir.join("bad.txt.1"));
+    }
+}
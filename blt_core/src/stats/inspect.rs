@@ -0,0 +1,376 @@
+//! Inspecting a merges file for mistakes before it is used.
+//!
+//! The loader keeps only a map of pairs to tokens, so some mistakes vanish on the way in: a
+//! pair merged twice keeps its last token and leaves the first one unused. [`inspect_merges`]
+//! reads a merges file or vocabulary snapshot rule by rule instead and reports duplicate pairs,
+//! pairs referencing tokens that are neither bytes nor produced by an earlier merge, and tokens
+//! in the reserved special-token range. Whether blt accepts the file is reported alongside.
+
+use crate::config_loader;
+use crate::snapshot;
+use crate::special_tokens;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// How a merges file is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergesFormat {
+    /// One `first second` pair per line.
+    Text,
+    /// A [vocabulary snapshot](crate::snapshot).
+    Snapshot,
+}
+
+/// One merge rule as written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct MergeRule {
+    /// The rule's line in a text merges file; snapshots have none.
+    pub line: Option<usize>,
+    /// The merged pair.
+    pub pair: (u16, u16),
+    /// The token the pair merges into.
+    pub token: u16,
+}
+
+/// A pair merged by more than one rule.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DuplicatePair {
+    /// The pair.
+    pub pair: (u16, u16),
+    /// Every rule merging it, in order. The loader keeps the last one.
+    pub rules: Vec<MergeRule>,
+}
+
+/// A rule referencing a token that is neither a byte nor produced by an earlier rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct UndefinedReference {
+    /// The rule.
+    pub rule: MergeRule,
+    /// The undefined token.
+    pub token: u16,
+}
+
+/// A line of a text merges file that is not a merge rule.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MalformedLine {
+    /// The line number.
+    pub line: usize,
+    /// The line as written.
+    pub text: String,
+}
+
+/// What [`inspect_merges`] found in a merges file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MergesInspection {
+    /// How the file is stored.
+    pub format: MergesFormat,
+    /// The token ID of the first merge.
+    pub base: u16,
+    /// The number of merge rules.
+    pub merges: u64,
+    /// The number of tokens an embedding table must cover: one past the highest token.
+    pub vocab_size: u32,
+    /// The [fingerprint](config_loader::merges_fingerprint) of the table blt loads, if it
+    /// loads.
+    pub fingerprint: Option<String>,
+    /// Why blt rejects the file, if it does.
+    pub load_error: Option<String>,
+    /// Pairs merged by more than one rule.
+    pub duplicates: Vec<DuplicatePair>,
+    /// References to undefined tokens.
+    pub undefined: Vec<UndefinedReference>,
+    /// Rules producing or referencing tokens in the reserved special-token range.
+    pub reserved: Vec<MergeRule>,
+    /// Lines of a text merges file that are not merge rules.
+    pub malformed: Vec<MalformedLine>,
+    /// Every rule, in order, for [`MergesInspection::table`]. Left empty unless requested.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<MergeRule>,
+}
+
+impl MergesInspection {
+    /// The number of problems found.
+    pub fn problems(&self) -> usize {
+        self.duplicates.len() + self.undefined.len() + self.reserved.len() + self.malformed.len()
+    }
+
+    /// Renders every rule with the bytes its token stands for.
+    pub fn table(&self) -> MergesTable<'_> {
+        MergesTable(&self.rules)
+    }
+}
+
+impl fmt::Display for MergesInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            MergesFormat::Text => "text",
+            MergesFormat::Snapshot => "snapshot",
+        };
+        writeln!(f, "Format:           {format}")?;
+        writeln!(
+            f,
+            "Merges:           {} (IDs from {})",
+            self.merges, self.base
+        )?;
+        writeln!(f, "Vocabulary size:  {}", self.vocab_size)?;
+        match (&self.fingerprint, &self.load_error) {
+            (Some(fingerprint), _) => writeln!(f, "Fingerprint:      {fingerprint}")?,
+            (None, Some(error)) => writeln!(f, "Rejected:         {error}")?,
+            (None, None) => {}
+        }
+        writeln!(f, "Duplicate pairs:  {}", self.duplicates.len())?;
+        for duplicate in &self.duplicates {
+            let (first, second) = duplicate.pair;
+            let rules: Vec<String> = duplicate.rules.iter().map(describe).collect();
+            writeln!(f, "  ({first}, {second}): {}", rules.join(", "))?;
+        }
+        writeln!(f, "Undefined tokens: {}", self.undefined.len())?;
+        for reference in &self.undefined {
+            let (first, second) = reference.rule.pair;
+            writeln!(
+                f,
+                "  ({first}, {second}) {}: token {} is not a byte or an earlier merge",
+                describe(&reference.rule),
+                reference.token
+            )?;
+        }
+        writeln!(f, "Reserved tokens:  {}", self.reserved.len())?;
+        for rule in &self.reserved {
+            let (first, second) = rule.pair;
+            writeln!(
+                f,
+                "  ({first}, {second}) {}: in the special-token range {:#06x}-{:#06x}",
+                describe(rule),
+                special_tokens::RESERVED_RANGE.start(),
+                special_tokens::RESERVED_RANGE.end()
+            )?;
+        }
+        if !self.malformed.is_empty() {
+            writeln!(f, "Malformed lines:  {}", self.malformed.len())?;
+            for line in &self.malformed {
+                writeln!(f, "  line {}: {:?}", line.line, line.text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a rule is and what it produces, e.g. `line 3 -> 258`.
+fn describe(rule: &MergeRule) -> String {
+    match rule.line {
+        Some(line) => format!("line {line} -> {}", rule.token),
+        None => format!("-> {}", rule.token),
+    }
+}
+
+/// The merge table of a [`MergesInspection`], one rule per line.
+pub struct MergesTable<'a>(&'a [MergeRule]);
+
+impl fmt::Display for MergesTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: HashMap<u16, (u16, u16)> =
+            self.0.iter().map(|rule| (rule.token, rule.pair)).collect();
+        writeln!(f, "{:>6} {:>6} {:>6}  bytes", "token", "first", "second")?;
+        for rule in self.0 {
+            let mut bytes = Vec::new();
+            let complete = expand(rule.token, &pairs, &mut bytes, 0);
+            writeln!(
+                f,
+                "{:>6} {:>6} {:>6}  \"{}\"{}",
+                rule.token,
+                rule.pair.0,
+                rule.pair.1,
+                bytes.escape_ascii(),
+                if complete { "" } else { " (incomplete)" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends the bytes `token` stands for, returning `false` if it references undefined tokens
+/// or nests deeper than any real vocabulary.
+fn expand(token: u16, pairs: &HashMap<u16, (u16, u16)>, bytes: &mut Vec<u8>, depth: u32) -> bool {
+    if token < 256 {
+        bytes.push(token as u8);
+        return true;
+    }
+    match pairs.get(&token) {
+        Some(&(first, second)) if depth < 64 => {
+            expand(first, pairs, bytes, depth + 1) & expand(second, pairs, bytes, depth + 1)
+        }
+        _ => false,
+    }
+}
+
+/// Reads the merges file at `path`, a text file or a snapshot, and reports its problems.
+/// Text rules are numbered from `base` like [`config_loader::load_bpe_merges_with_base`] does;
+/// with `keep_rules`, every rule is kept for [`MergesInspection::table`].
+pub fn inspect_merges(path: &Path, base: u16, keep_rules: bool) -> io::Result<MergesInspection> {
+    let mut malformed = Vec::new();
+    let (format, base, rules) = match snapshot::read_if_snapshot(path)? {
+        Some(snapshot) => {
+            let mut rules: Vec<MergeRule> = snapshot
+                .merges
+                .iter()
+                .map(|(&pair, &token)| MergeRule {
+                    line: None,
+                    pair,
+                    token,
+                })
+                .collect();
+            rules.sort_unstable_by_key(|rule| (rule.token, rule.pair));
+            (MergesFormat::Snapshot, snapshot.base, rules)
+        }
+        None => {
+            let reader = BufReader::new(std::fs::File::open(path)?);
+            let mut rules = Vec::new();
+            let mut token = u32::from(base);
+            for (index, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.starts_with('#') || line.is_empty() {
+                    continue;
+                }
+                match (parse_rule(&line), u16::try_from(token)) {
+                    (Some(pair), Ok(id)) => {
+                        rules.push(MergeRule {
+                            line: Some(index + 1),
+                            pair,
+                            token: id,
+                        });
+                        token += 1;
+                    }
+                    _ => malformed.push(MalformedLine {
+                        line: index + 1,
+                        text: line,
+                    }),
+                }
+            }
+            (MergesFormat::Text, base, rules)
+        }
+    };
+
+    let mut by_pair: HashMap<(u16, u16), Vec<MergeRule>> = HashMap::new();
+    let mut defined: HashSet<u16> = HashSet::new();
+    let mut undefined = Vec::new();
+    let mut reserved = Vec::new();
+    for rule in &rules {
+        by_pair.entry(rule.pair).or_default().push(*rule);
+        let (first, second) = rule.pair;
+        for token in [first, second] {
+            if token >= 256 && !defined.contains(&token) {
+                undefined.push(UndefinedReference { rule: *rule, token });
+            }
+        }
+        if [first, second, rule.token]
+            .into_iter()
+            .any(special_tokens::is_reserved)
+        {
+            reserved.push(*rule);
+        }
+        defined.insert(rule.token);
+    }
+    let mut duplicates: Vec<DuplicatePair> = by_pair
+        .into_iter()
+        .filter(|(_, rules)| rules.len() > 1)
+        .map(|(pair, rules)| DuplicatePair { pair, rules })
+        .collect();
+    duplicates.sort_unstable_by_key(|duplicate| duplicate.rules[0].token);
+
+    let loaded = config_loader::load_bpe_merges_with_base(path, base)
+        .map_err(|e| e.to_string())
+        .and_then(|merges| {
+            config_loader::validate_merges(&merges, 0)
+                .map(|()| merges)
+                .map_err(|e| e.to_string())
+        });
+    let vocab_size = rules
+        .iter()
+        .map(|rule| u32::from(rule.token) + 1)
+        .max()
+        .unwrap_or(0)
+        .max(256);
+    Ok(MergesInspection {
+        format,
+        base,
+        merges: rules.len() as u64,
+        vocab_size,
+        fingerprint: loaded.as_ref().ok().map(config_loader::merges_fingerprint),
+        load_error: loaded.err(),
+        duplicates,
+        undefined,
+        reserved,
+        malformed,
+        rules: if keep_rules { rules } else { Vec::new() },
+    })
+}
+
+/// Parses a `first second` rule. Tokens above 255 are accepted so references to them can be
+/// reported, although the loader only takes byte pairs.
+fn parse_rule(line: &str) -> Option<(u16, u16)> {
+    let mut parts = line.split_whitespace();
+    let pair = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+    parts.next().is_none().then_some(pair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_loader::DEFAULT_MERGE_ID_BASE;
+
+    #[test]
+    fn test_reports_duplicates_undefined_and_reserved_tokens() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("merges.txt");
+        std::fs::write(
+            &path,
+            "# merges\n104 101\n256 108\n300 1\n104 101\nnot a rule\n",
+        )?;
+        let inspection = inspect_merges(&path, DEFAULT_MERGE_ID_BASE, true)?;
+        assert_eq!(inspection.format, MergesFormat::Text);
+        assert_eq!((inspection.merges, inspection.vocab_size), (4, 260));
+        let duplicate = &inspection.duplicates[0];
+        assert_eq!(duplicate.pair, (104, 101));
+        let tokens: Vec<u16> = duplicate.rules.iter().map(|rule| rule.token).collect();
+        assert_eq!(tokens, [256, 259]);
+        assert_eq!(inspection.undefined.len(), 1);
+        assert_eq!(inspection.undefined[0].token, 300);
+        assert_eq!(inspection.undefined[0].rule.line, Some(4));
+        assert_eq!(inspection.malformed[0].line, 6);
+        // The loader only takes byte pairs, so it rejects the file.
+        assert!(inspection.load_error.is_some());
+        assert_eq!(inspection.problems(), 3);
+        let table = inspection.table().to_string();
+        assert!(table.contains("\"hel\""), "{table}");
+        assert!(table.contains("(incomplete)"), "{table}");
+
+        // Merge IDs that run into the special tokens are flagged.
+        std::fs::write(&path, "104 101\n")?;
+        let inspection = inspect_merges(&path, 0xFF00, false)?;
+        assert_eq!(inspection.reserved.len(), 1);
+        assert!(inspection.rules.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspects_snapshots() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("merges.txt");
+        std::fs::write(&path, "104 101\n108 108\n")?;
+        let snap = dir.path().join("merges.snap");
+        snapshot::create(&path, &snap)?;
+        let inspection = inspect_merges(&snap, DEFAULT_MERGE_ID_BASE, false)?;
+        assert_eq!(inspection.format, MergesFormat::Snapshot);
+        assert_eq!(inspection.problems(), 0);
+        assert_eq!(inspection.vocab_size, 258);
+        assert_eq!(
+            inspection.fingerprint,
+            inspect_merges(&path, DEFAULT_MERGE_ID_BASE, false)?.fingerprint
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+//! Corpus summaries built from run manifests.
+//!
+//! A manifest already records every document's source, size, token count, and content hash,
+//! so a summary of the whole corpus needs no access to the data itself. The report totals the
+//! corpus, breaks it down by source directory and content type, histograms document lengths,
+//! and counts duplicate documents by content hash. It serializes to JSON and renders as a
+//! self-contained HTML page, a dataset card that can be shared as is.
+
+use crate::manifest::ManifestEntry;
+use crate::ContentType;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Counters for a group of documents.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct GroupStats {
+    /// The number of documents.
+    pub documents: u64,
+    /// Tokens produced.
+    pub tokens: u64,
+    /// Input bytes.
+    pub input_bytes: u64,
+    /// Output bytes.
+    pub output_bytes: u64,
+}
+
+impl GroupStats {
+    fn add(&mut self, entry: &ManifestEntry) {
+        self.documents += 1;
+        self.tokens += entry.tokens;
+        self.input_bytes += entry.input_bytes;
+        self.output_bytes += entry.output_bytes;
+    }
+}
+
+/// A bucket of the document length histogram.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LengthBucket {
+    /// The shortest length in the bucket, in tokens.
+    pub min_tokens: u64,
+    /// The length the bucket ends before, in tokens.
+    pub max_tokens: u64,
+    /// The number of documents in the bucket.
+    pub documents: u64,
+}
+
+/// Duplicate documents, found by content hash.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DedupStats {
+    /// Documents with a content hash; the others cannot be compared.
+    pub hashed_documents: u64,
+    /// Distinct content hashes.
+    pub unique_documents: u64,
+    /// Documents repeating the content of an earlier one.
+    pub duplicate_documents: u64,
+    /// Tokens of the duplicate documents.
+    pub duplicate_tokens: u64,
+}
+
+/// A summary of the corpus a manifest describes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ManifestReport {
+    /// Counters over every document.
+    pub total: GroupStats,
+    /// The fewest tokens in a document.
+    pub min_tokens: u64,
+    /// The median number of tokens per document.
+    pub median_tokens: u64,
+    /// The most tokens in a document.
+    pub max_tokens: u64,
+    /// Per source directory counters, keyed by the directory of each document's source.
+    pub by_source: Vec<(String, GroupStats)>,
+    /// Per content type counters, keyed by content type name.
+    pub by_content_type: Vec<(String, GroupStats)>,
+    /// Document lengths in power-of-two buckets, from the shortest document to the longest.
+    pub length_histogram: Vec<LengthBucket>,
+    /// Duplicate documents.
+    pub dedup: DedupStats,
+}
+
+/// Summarizes the documents of a manifest.
+///
+/// Documents whose entries record no content type, as in manifests written before it was
+/// recorded, are counted under the type guessed from their source's extension.
+pub fn manifest_report(entries: &[ManifestEntry]) -> ManifestReport {
+    let mut report = ManifestReport::default();
+    let mut by_source: BTreeMap<String, GroupStats> = BTreeMap::new();
+    let mut by_content_type: BTreeMap<String, GroupStats> = BTreeMap::new();
+    let mut buckets: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut hashes: HashMap<&str, u64> = HashMap::new();
+    for entry in entries {
+        report.total.add(entry);
+        by_source
+            .entry(source_group(&entry.source))
+            .or_default()
+            .add(entry);
+        let content_type = match &entry.content_type {
+            Some(name) => name.clone(),
+            None => ContentType::from_path(Path::new(&entry.source))
+                .name()
+                .to_string(),
+        };
+        by_content_type.entry(content_type).or_default().add(entry);
+        *buckets.entry(length_bucket(entry.tokens)).or_default() += 1;
+        if let Some(hash) = &entry.content_hash {
+            report.dedup.hashed_documents += 1;
+            let seen = hashes.entry(hash).or_default();
+            if *seen > 0 {
+                report.dedup.duplicate_documents += 1;
+                report.dedup.duplicate_tokens += entry.tokens;
+            }
+            *seen += 1;
+        }
+    }
+    report.dedup.unique_documents = hashes.len() as u64;
+    report.by_source = by_source.into_iter().collect();
+    report.by_content_type = by_content_type.into_iter().collect();
+    if let (Some(&first), Some(&last)) = (buckets.keys().next(), buckets.keys().next_back()) {
+        report.length_histogram = (first..=last)
+            .map(|bucket| {
+                let (min_tokens, max_tokens) = bucket_bounds(bucket);
+                LengthBucket {
+                    min_tokens,
+                    max_tokens,
+                    documents: buckets.get(&bucket).copied().unwrap_or(0),
+                }
+            })
+            .collect();
+    }
+    let mut lengths: Vec<u64> = entries.iter().map(|entry| entry.tokens).collect();
+    lengths.sort_unstable();
+    report.min_tokens = lengths.first().copied().unwrap_or(0);
+    report.median_tokens = lengths.get(lengths.len() / 2).copied().unwrap_or(0);
+    report.max_tokens = lengths.last().copied().unwrap_or(0);
+    report
+}
+
+/// The directory a source is grouped under: its parent, or the source itself for standard
+/// input and archive members.
+fn source_group(source: &str) -> String {
+    match Path::new(source).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.display().to_string(),
+        Some(_) => ".".to_string(),
+        None => source.to_string(),
+    }
+}
+
+/// Bucket 0 holds empty documents; bucket `b` holds lengths in `2^(b-1)..2^b`.
+fn length_bucket(tokens: u64) -> u32 {
+    u64::BITS - tokens.leading_zeros()
+}
+
+fn bucket_bounds(bucket: u32) -> (u64, u64) {
+    match bucket {
+        0 => (0, 1),
+        _ => (
+            1 << (bucket - 1),
+            1u64.checked_shl(bucket).unwrap_or(u64::MAX),
+        ),
+    }
+}
+
+impl ManifestReport {
+    /// Renders the report as a self-contained HTML page headed by `title`.
+    pub fn to_html(&self, title: &str) -> String {
+        let mut html = String::new();
+        let title = escape(title);
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+        );
+        let total = &self.total;
+        let _ = write!(
+            html,
+            "<h2>Totals</h2>\n<table>\n\
+             <tr><th>Documents</th><td>{}</td></tr>\n\
+             <tr><th>Tokens</th><td>{}</td></tr>\n\
+             <tr><th>Input bytes</th><td>{}</td></tr>\n\
+             <tr><th>Output bytes</th><td>{}</td></tr>\n\
+             <tr><th>Tokens per document</th><td>min {} · median {} · max {}</td></tr>\n\
+             </table>\n",
+            total.documents,
+            total.tokens,
+            total.input_bytes,
+            total.output_bytes,
+            self.min_tokens,
+            self.median_tokens,
+            self.max_tokens,
+        );
+        group_table(
+            &mut html,
+            "By source",
+            "Source",
+            &self.by_source,
+            total.tokens,
+        );
+        group_table(
+            &mut html,
+            "By content type",
+            "Content type",
+            &self.by_content_type,
+            total.tokens,
+        );
+        html.push_str(
+            "<h2>Document lengths</h2>\n<table>\n\
+             <tr><th>Tokens</th><th>Documents</th><th></th></tr>\n",
+        );
+        let most = self
+            .length_histogram
+            .iter()
+            .map(|bucket| bucket.documents)
+            .max()
+            .unwrap_or(0);
+        for bucket in &self.length_histogram {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}–{}</td><td>{}</td><td>{}</td></tr>",
+                bucket.min_tokens,
+                bucket.max_tokens - 1,
+                bucket.documents,
+                bar(bucket.documents, most),
+            );
+        }
+        html.push_str("</table>\n");
+        let dedup = &self.dedup;
+        let _ = write!(
+            html,
+            "<h2>Duplicates</h2>\n<table>\n\
+             <tr><th>Hashed documents</th><td>{}</td></tr>\n\
+             <tr><th>Unique documents</th><td>{}</td></tr>\n\
+             <tr><th>Duplicate documents</th><td>{}</td></tr>\n\
+             <tr><th>Duplicate tokens</th><td>{}</td></tr>\n\
+             </table>\n</body>\n</html>\n",
+            dedup.hashed_documents,
+            dedup.unique_documents,
+            dedup.duplicate_documents,
+            dedup.duplicate_tokens,
+        );
+        html
+    }
+}
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }\n\
+    table { border-collapse: collapse; margin-bottom: 1.5em; }\n\
+    th, td { border: 1px solid #ccc; padding: 0.25em 0.75em; text-align: left; }\n\
+    td { font-variant-numeric: tabular-nums; }\n\
+    .bar { background: #4a7bd0; height: 0.8em; }\n";
+
+fn group_table(
+    html: &mut String,
+    heading: &str,
+    key: &str,
+    groups: &[(String, GroupStats)],
+    total_tokens: u64,
+) {
+    let _ = write!(
+        html,
+        "<h2>{heading}</h2>\n<table>\n<tr><th>{key}</th><th>Documents</th><th>Tokens</th>\
+         <th>Input bytes</th><th>Share of tokens</th></tr>\n"
+    );
+    for (name, stats) in groups {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(name),
+            stats.documents,
+            stats.tokens,
+            stats.input_bytes,
+            bar(stats.tokens, total_tokens),
+        );
+    }
+    html.push_str("</table>\n");
+}
+
+/// A bar as wide as `value` is a share of `max`, at most 20em.
+fn bar(value: u64, max: u64) -> String {
+    let width = match max {
+        0 => 0.0,
+        _ => value as f64 / max as f64 * 20.0,
+    };
+    format!("<div class=\"bar\" style=\"width: {width:.2}em\"></div>")
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str, tokens: u64, hash: Option<&str>) -> ManifestEntry {
+        ManifestEntry {
+            doc_id: 0,
+            id: None,
+            source: source.to_string(),
+            input_bytes: tokens,
+            output_bytes: tokens * 2,
+            tokens,
+            content_hash: hash.map(str::to_string),
+            output: None,
+            output_hash: None,
+            snapshot: None,
+            content_type: None,
+        }
+    }
+
+    #[test]
+    fn test_summarizes_manifest() {
+        let entries = [
+            entry("wiki/a.txt", 3, Some("sha256:1")),
+            entry("wiki/b.txt", 0, Some("sha256:2")),
+            entry("code/c.rs", 10, Some("sha256:1")),
+            ManifestEntry {
+                content_type: Some("audio".to_string()),
+                ..entry("clip", 5, None)
+            },
+        ];
+        let report = manifest_report(&entries);
+        assert_eq!(report.total.documents, 4);
+        assert_eq!(report.total.tokens, 18);
+        assert_eq!(
+            (report.min_tokens, report.median_tokens, report.max_tokens),
+            (0, 5, 10)
+        );
+        let sources: Vec<_> = report
+            .by_source
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats.documents))
+            .collect();
+        assert_eq!(sources, [(".", 1), ("code", 1), ("wiki", 2)]);
+        let types: Vec<_> = report
+            .by_content_type
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats.tokens))
+            .collect();
+        assert_eq!(types, [("audio", 5), ("text", 13)]);
+        let histogram: Vec<_> = report
+            .length_histogram
+            .iter()
+            .map(|b| (b.min_tokens, b.max_tokens, b.documents))
+            .collect();
+        assert_eq!(
+            histogram,
+            [(0, 1, 1), (1, 2, 0), (2, 4, 1), (4, 8, 1), (8, 16, 1)]
+        );
+        assert_eq!(
+            report.dedup,
+            DedupStats {
+                hashed_documents: 3,
+                unique_documents: 2,
+                duplicate_documents: 1,
+                duplicate_tokens: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn test_renders_html() {
+        let report = manifest_report(&[entry("<dir>/a.txt", 4, None)]);
+        let html = report.to_html("Corpus & co");
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1>Corpus &amp; co</h1>"));
+        assert!(html.contains("<td>&lt;dir&gt;</td>"));
+        assert!(html.contains("<tr><td>4–7</td><td>1</td>"));
+    }
+
+    #[test]
+    fn test_empty_manifest() {
+        let report = manifest_report(&[]);
+        assert_eq!(report, ManifestReport::default());
+        assert!(report.to_html("empty").contains("<h2>Duplicates</h2>"));
+    }
+}
@@ -0,0 +1,131 @@
+//! Ranking merges by realized usage and suggesting pruned merges tables.
+//!
+//! Merges that never fire on a representative corpus only waste vocabulary slots. Pruning
+//! keeps the most used merges for a target vocabulary size. Kept merges stay in their original
+//! relative order, but because token IDs are assigned by position in the merges file, a pruned
+//! table renumbers its tokens: outputs produced with different tables are not interchangeable.
+
+use super::for_each_chunk;
+use crate::tokenizer::BpeStrategy;
+use crate::BpeMerges;
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The number of token IDs taken by raw bytes; merges start after them.
+const BYTE_TOKENS: usize = 256;
+
+/// How often one merge fired on the sample corpus.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MergeUsage {
+    /// The merged pair.
+    pub pair: (u16, u16),
+    /// The token the pair merges into.
+    pub token: u16,
+    /// How many times the merged token was emitted.
+    pub count: u64,
+}
+
+/// Merge usage on a sample corpus, most used first.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MergeRanking {
+    /// Every merge, ordered by descending usage, then by token ID.
+    pub ranked: Vec<MergeUsage>,
+}
+
+impl MergeRanking {
+    /// The total number of merged tokens emitted.
+    pub fn total_uses(&self) -> u64 {
+        self.ranked.iter().map(|u| u.count).sum()
+    }
+
+    /// Returns the merges to keep for a vocabulary of `vocab_size` tokens (bytes included),
+    /// in their original order.
+    pub fn pruned(&self, vocab_size: usize) -> Vec<(u16, u16)> {
+        let keep = vocab_size.saturating_sub(BYTE_TOKENS);
+        let mut kept: Vec<&MergeUsage> = self.ranked.iter().take(keep).collect();
+        kept.sort_by_key(|u| u.token);
+        kept.into_iter().map(|u| u.pair).collect()
+    }
+
+    /// The fraction of merge uses still covered by a vocabulary of `vocab_size` tokens.
+    pub fn retained_fraction(&self, vocab_size: usize) -> f64 {
+        let total = self.total_uses();
+        if total == 0 {
+            return 1.0;
+        }
+        let keep = vocab_size.saturating_sub(BYTE_TOKENS);
+        let retained: u64 = self.ranked.iter().take(keep).map(|u| u.count).sum();
+        retained as f64 / total as f64
+    }
+}
+
+/// Tokenizes every input with `merges` and ranks the merges by how often they fire.
+pub fn merge_usage(merges: BpeMerges, inputs: &[PathBuf]) -> io::Result<MergeRanking> {
+    let mut counts = vec![0u64; u16::MAX as usize + 1];
+    let pairs: Vec<((u16, u16), u16)> = merges.iter().map(|(&p, &t)| (p, t)).collect();
+    let strategy = BpeStrategy::new(Arc::new(merges));
+    for path in inputs {
+        for_each_chunk(path, &strategy, |_, tokens| {
+            for &token in tokens {
+                counts[token as usize] += 1;
+            }
+        })?;
+    }
+
+    let mut ranked: Vec<MergeUsage> = pairs
+        .into_iter()
+        .map(|(pair, token)| MergeUsage {
+            pair,
+            token,
+            count: counts[token as usize],
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.count.cmp(&a.count).then(a.token.cmp(&b.token)));
+    Ok(MergeRanking { ranked })
+}
+
+/// Renders merges in the merges file format, one `byte1 byte2` pair per line.
+pub fn to_merges_file(pairs: &[(u16, u16)]) -> String {
+    pairs.iter().map(|(a, b)| format!("{a} {b}\n")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn sample_ranking() -> MergeRanking {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        input.write_all(b"cdcdcdab").unwrap();
+        let merges: BpeMerges = [
+            ((b'a' as u16, b'b' as u16), 256),
+            ((b'c' as u16, b'd' as u16), 257),
+            ((b'x' as u16, b'y' as u16), 258),
+        ]
+        .into_iter()
+        .collect();
+        merge_usage(merges, &[input.path().to_path_buf()]).unwrap()
+    }
+
+    #[test]
+    fn test_ranking_orders_by_usage() {
+        let ranking = sample_ranking();
+        let tokens: Vec<(u16, u64)> = ranking.ranked.iter().map(|u| (u.token, u.count)).collect();
+        assert_eq!(tokens, vec![(257, 3), (256, 1), (258, 0)]);
+        assert_eq!(ranking.total_uses(), 4);
+    }
+
+    #[test]
+    fn test_pruned_keeps_original_order() {
+        let ranking = sample_ranking();
+        assert_eq!(ranking.pruned(257), vec![(b'c' as u16, b'd' as u16)]);
+        assert_eq!(
+            ranking.pruned(258),
+            vec![(b'a' as u16, b'b' as u16), (b'c' as u16, b'd' as u16)]
+        );
+        assert_eq!(ranking.retained_fraction(257), 0.75);
+        assert_eq!(to_merges_file(&ranking.pruned(257)), "99 100\n");
+    }
+}
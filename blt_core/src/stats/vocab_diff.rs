@@ -0,0 +1,468 @@
+//! Comparing two merges tables.
+//!
+//! [`diff_merges`] lists the merges added, removed, or assigned a different token ID between
+//! two tables. [`retokenization_deltas`] tokenizes a sample with both tables and measures how
+//! much the output changes per document: the token count delta and the segmentation
+//! divergence, i.e. the share of token boundaries present in only one of the two outputs.
+//! [`vocab_diff_report`] combines both into the `blt vocab-diff` report, and
+//! [`canary_report`] checks the deltas against a [`CanaryGate`] to gate vocabulary rollouts.
+
+use super::for_each_raw_chunk;
+use crate::tokenizer::BpeStrategy;
+use crate::BpeMerges;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A merge present in both tables under different token IDs.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RemappedMerge {
+    /// The merged pair.
+    pub pair: (u16, u16),
+    /// The token ID in the old table.
+    pub old_token: u16,
+    /// The token ID in the new table.
+    pub new_token: u16,
+}
+
+/// The differences between two merges tables, each list sorted by pair.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MergesDiff {
+    /// Merges only in the new table, with their new token IDs.
+    pub added: Vec<((u16, u16), u16)>,
+    /// Merges only in the old table, with their old token IDs.
+    pub removed: Vec<((u16, u16), u16)>,
+    /// Merges whose token ID changed.
+    pub remapped: Vec<RemappedMerge>,
+    /// Merges identical in both tables.
+    pub unchanged: usize,
+}
+
+/// Compares two merges tables.
+pub fn diff_merges(old: &BpeMerges, new: &BpeMerges) -> MergesDiff {
+    let mut diff = MergesDiff::default();
+    for (&pair, &new_token) in new {
+        match old.get(&pair) {
+            None => diff.added.push((pair, new_token)),
+            Some(&old_token) if old_token != new_token => diff.remapped.push(RemappedMerge {
+                pair,
+                old_token,
+                new_token,
+            }),
+            Some(_) => diff.unchanged += 1,
+        }
+    }
+    for (&pair, &old_token) in old {
+        if !new.contains_key(&pair) {
+            diff.removed.push((pair, old_token));
+        }
+    }
+    diff.added.sort_unstable();
+    diff.removed.sort_unstable();
+    diff.remapped.sort_unstable_by_key(|m| m.pair);
+    diff
+}
+
+/// How re-tokenizing one document with the new table changes its output.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DocumentDelta {
+    /// The document's path.
+    pub source: String,
+    /// Tokens produced by the old table.
+    pub old_tokens: u64,
+    /// Tokens produced by the new table.
+    pub new_tokens: u64,
+    /// Token boundaries present in only one of the two outputs.
+    pub changed_boundaries: u64,
+    /// Distinct token boundaries across both outputs.
+    pub total_boundaries: u64,
+    /// Tokens with identical byte spans whose IDs differ.
+    pub remapped_tokens: u64,
+}
+
+impl DocumentDelta {
+    /// The relative change in token count (`new / old - 1`).
+    pub fn token_delta(&self) -> f64 {
+        if self.old_tokens == 0 {
+            0.0
+        } else {
+            self.new_tokens as f64 / self.old_tokens as f64 - 1.0
+        }
+    }
+
+    /// The share of token boundaries that differ between the two outputs.
+    pub fn divergence(&self) -> f64 {
+        if self.total_boundaries == 0 {
+            0.0
+        } else {
+            self.changed_boundaries as f64 / self.total_boundaries as f64
+        }
+    }
+
+    /// Adds another document's counters to this one.
+    pub fn accumulate(&mut self, other: &DocumentDelta) {
+        self.old_tokens += other.old_tokens;
+        self.new_tokens += other.new_tokens;
+        self.changed_boundaries += other.changed_boundaries;
+        self.total_boundaries += other.total_boundaries;
+        self.remapped_tokens += other.remapped_tokens;
+    }
+}
+
+/// Tokenizes every input with both tables and reports how each document's output changes.
+pub fn retokenization_deltas(
+    old: BpeMerges,
+    new: BpeMerges,
+    inputs: &[PathBuf],
+) -> io::Result<Vec<DocumentDelta>> {
+    let old_lengths = token_lengths(&old);
+    let new_lengths = token_lengths(&new);
+    let old_strategy = BpeStrategy::new(Arc::new(old));
+    let new_strategy = BpeStrategy::new(Arc::new(new));
+    let mut deltas = Vec::with_capacity(inputs.len());
+    for path in inputs {
+        let mut delta = DocumentDelta {
+            source: path.display().to_string(),
+            ..DocumentDelta::default()
+        };
+        for_each_raw_chunk(path, |chunk| {
+            let old_spans = spans(&old_strategy.encode(chunk), &old_lengths);
+            let new_spans = spans(&new_strategy.encode(chunk), &new_lengths);
+            compare_spans(&old_spans, &new_spans, &mut delta);
+        })?;
+        deltas.push(delta);
+    }
+    Ok(deltas)
+}
+
+/// The merges diff between two tables and its impact on a sample corpus.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VocabDiffReport {
+    /// The differences between the two tables.
+    pub merges: MergesDiff,
+    /// Re-tokenization deltas per sample document.
+    pub documents: Vec<DocumentDelta>,
+    /// Deltas over the whole sample.
+    pub total: DocumentDelta,
+}
+
+/// Diffs `old` against `new` and re-tokenizes `samples` with both tables.
+pub fn vocab_diff_report(
+    old: BpeMerges,
+    new: BpeMerges,
+    samples: &[PathBuf],
+) -> io::Result<VocabDiffReport> {
+    let merges = diff_merges(&old, &new);
+    let documents = retokenization_deltas(old, new, samples)?;
+    let mut total = DocumentDelta {
+        source: "total".to_string(),
+        ..DocumentDelta::default()
+    };
+    for document in &documents {
+        total.accumulate(document);
+    }
+    Ok(VocabDiffReport {
+        merges,
+        documents,
+        total,
+    })
+}
+
+impl fmt::Display for VocabDiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "merges: {} added, {} removed, {} remapped, {} unchanged",
+            self.merges.added.len(),
+            self.merges.removed.len(),
+            self.merges.remapped.len(),
+            self.merges.unchanged
+        )?;
+        if self.documents.is_empty() {
+            return Ok(());
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>12} {:>12} {:>9} {:>10} {:>10}  document",
+            "old_tokens", "new_tokens", "delta", "diverged", "remapped"
+        )?;
+        for delta in self.documents.iter().chain(std::iter::once(&self.total)) {
+            writeln!(
+                f,
+                "{:>12} {:>12} {:>+8.2}% {:>9.2}% {:>10}  {}",
+                delta.old_tokens,
+                delta.new_tokens,
+                delta.token_delta() * 100.0,
+                delta.divergence() * 100.0,
+                delta.remapped_tokens,
+                delta.source
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Limits a vocabulary upgrade must stay within on every sample document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CanaryGate {
+    /// The maximum segmentation divergence, between 0.0 and 1.0.
+    pub max_divergence: Option<f64>,
+    /// The maximum absolute relative change in token count, e.g. 0.05 for ±5%.
+    pub max_token_delta: Option<f64>,
+}
+
+impl CanaryGate {
+    /// Returns whether `delta` stays within every configured limit.
+    pub fn accepts(&self, delta: &DocumentDelta) -> bool {
+        self.max_divergence
+            .is_none_or(|limit| delta.divergence() <= limit)
+            && self
+                .max_token_delta
+                .is_none_or(|limit| delta.token_delta().abs() <= limit)
+    }
+}
+
+/// The canary verdict for one document.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CanaryResult {
+    /// The document's counters.
+    #[serde(flatten)]
+    pub delta: DocumentDelta,
+    /// The relative change in token count.
+    pub token_delta: f64,
+    /// The share of token boundaries that differ.
+    pub divergence: f64,
+    /// Whether the document stays within the gate's limits.
+    pub passed: bool,
+}
+
+impl CanaryResult {
+    fn new(delta: DocumentDelta, gate: &CanaryGate) -> Self {
+        Self {
+            token_delta: delta.token_delta(),
+            divergence: delta.divergence(),
+            passed: gate.accepts(&delta),
+            delta,
+        }
+    }
+}
+
+/// Per-document canary results for a vocabulary upgrade.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CanaryReport {
+    /// Results per sample document, in input order.
+    pub documents: Vec<CanaryResult>,
+    /// The result over the whole sample, which the gate also applies to.
+    pub total: CanaryResult,
+}
+
+impl CanaryReport {
+    /// The number of documents, and the total, that exceed the gate's limits.
+    pub fn failures(&self) -> usize {
+        self.documents
+            .iter()
+            .chain(std::iter::once(&self.total))
+            .filter(|result| !result.passed)
+            .count()
+    }
+}
+
+/// Tokenizes `samples` with both tables in one pass and checks every document against `gate`.
+pub fn canary_report(
+    old: BpeMerges,
+    new: BpeMerges,
+    samples: &[PathBuf],
+    gate: &CanaryGate,
+) -> io::Result<CanaryReport> {
+    let report = vocab_diff_report(old, new, samples)?;
+    Ok(CanaryReport {
+        documents: report
+            .documents
+            .into_iter()
+            .map(|delta| CanaryResult::new(delta, gate))
+            .collect(),
+        total: CanaryResult::new(report.total, gate),
+    })
+}
+
+impl fmt::Display for CanaryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>12} {:>12} {:>9} {:>10}  {:<6} document",
+            "old_tokens", "new_tokens", "delta", "diverged", "status"
+        )?;
+        for result in self.documents.iter().chain(std::iter::once(&self.total)) {
+            writeln!(
+                f,
+                "{:>12} {:>12} {:>+8.2}% {:>9.2}%  {:<6} {}",
+                result.delta.old_tokens,
+                result.delta.new_tokens,
+                result.token_delta * 100.0,
+                result.divergence * 100.0,
+                if result.passed { "ok" } else { "FAIL" },
+                result.delta.source
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps every merged token to the number of bytes it expands to.
+fn token_lengths(merges: &BpeMerges) -> HashMap<u16, usize> {
+    let pairs: HashMap<u16, (u16, u16)> = merges.iter().map(|(&p, &t)| (t, p)).collect();
+    let mut lengths = HashMap::with_capacity(pairs.len());
+    for &token in pairs.keys() {
+        expanded_length(token, &pairs, &mut lengths, 0);
+    }
+    lengths
+}
+
+fn expanded_length(
+    token: u16,
+    pairs: &HashMap<u16, (u16, u16)>,
+    lengths: &mut HashMap<u16, usize>,
+    depth: usize,
+) -> usize {
+    if let Some(&length) = lengths.get(&token) {
+        return length;
+    }
+    // A malformed table could contain cycles; treat anything that deep as a single byte.
+    let length = match pairs.get(&token) {
+        Some(&(a, b)) if depth < pairs.len() => {
+            expanded_length(a, pairs, lengths, depth + 1)
+                + expanded_length(b, pairs, lengths, depth + 1)
+        }
+        _ => 1,
+    };
+    if pairs.contains_key(&token) {
+        lengths.insert(token, length);
+    }
+    length
+}
+
+/// Returns `(end offset, token)` for every token of an encoded chunk.
+fn spans(tokens: &[u16], lengths: &HashMap<u16, usize>) -> Vec<(usize, u16)> {
+    let mut end = 0;
+    tokens
+        .iter()
+        .map(|&token| {
+            end += lengths.get(&token).copied().unwrap_or(1);
+            (end, token)
+        })
+        .collect()
+}
+
+fn compare_spans(old: &[(usize, u16)], new: &[(usize, u16)], delta: &mut DocumentDelta) {
+    delta.old_tokens += old.len() as u64;
+    delta.new_tokens += new.len() as u64;
+    let (mut i, mut j) = (0, 0);
+    let (mut common, mut old_start, mut new_start) = (0u64, 0, 0);
+    while i < old.len() && j < new.len() {
+        let ((old_end, old_token), (new_end, new_token)) = (old[i], new[j]);
+        if old_end == new_end {
+            common += 1;
+            if old_start == new_start && old_token != new_token {
+                delta.remapped_tokens += 1;
+            }
+            old_start = old_end;
+            new_start = new_end;
+            i += 1;
+            j += 1;
+        } else if old_end < new_end {
+            old_start = old_end;
+            i += 1;
+        } else {
+            new_start = new_end;
+            j += 1;
+        }
+    }
+    let total = (old.len() + new.len()) as u64 - common;
+    delta.total_boundaries += total;
+    delta.changed_boundaries += total - common;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn merges(entries: &[(&[u8; 2], u16)]) -> BpeMerges {
+        entries
+            .iter()
+            .map(|(pair, token)| ((pair[0] as u16, pair[1] as u16), *token))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_merges() {
+        let old = merges(&[(b"ab", 256), (b"cd", 257), (b"ef", 258)]);
+        let new = merges(&[(b"ab", 256), (b"ef", 257), (b"gh", 258)]);
+        let diff = diff_merges(&old, &new);
+        assert_eq!(diff.added, vec![((b'g' as u16, b'h' as u16), 258)]);
+        assert_eq!(diff.removed, vec![((b'c' as u16, b'd' as u16), 257)]);
+        assert_eq!(
+            diff.remapped,
+            vec![RemappedMerge {
+                pair: (b'e' as u16, b'f' as u16),
+                old_token: 258,
+                new_token: 257
+            }]
+        );
+        assert_eq!(diff.unchanged, 1);
+    }
+
+    #[test]
+    fn test_identical_tables_do_not_diverge() {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        input.write_all(b"abcdab").unwrap();
+        let table = merges(&[(b"ab", 256)]);
+        let deltas =
+            retokenization_deltas(table.clone(), table, &[input.path().to_path_buf()]).unwrap();
+        assert_eq!(deltas[0].divergence(), 0.0);
+        assert_eq!(deltas[0].token_delta(), 0.0);
+        assert_eq!(deltas[0].old_tokens, 4);
+    }
+
+    #[test]
+    fn test_segmentation_and_remapping_deltas() {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        input.write_all(b"abcd").unwrap();
+        // Old: [ab][c][d]; new: [ab'][cd] with `ab` renumbered.
+        let old = merges(&[(b"ab", 256)]);
+        let new = merges(&[(b"cd", 256), (b"ab", 257)]);
+        let delta = &retokenization_deltas(old, new, &[input.path().to_path_buf()]).unwrap()[0];
+        assert_eq!(delta.old_tokens, 3);
+        assert_eq!(delta.new_tokens, 2);
+        assert_eq!(delta.remapped_tokens, 1);
+        // Boundaries: old {2, 3, 4}, new {2, 4}: 3 distinct, 1 only in old.
+        assert_eq!(delta.total_boundaries, 3);
+        assert_eq!(delta.changed_boundaries, 1);
+    }
+
+    #[test]
+    fn test_canary_gate() {
+        let delta = DocumentDelta {
+            old_tokens: 100,
+            new_tokens: 90,
+            changed_boundaries: 5,
+            total_boundaries: 100,
+            ..DocumentDelta::default()
+        };
+        assert!(CanaryGate::default().accepts(&delta));
+        let gate = CanaryGate {
+            max_divergence: Some(0.1),
+            max_token_delta: None,
+        };
+        assert!(gate.accepts(&delta));
+        let gate = CanaryGate {
+            max_token_delta: Some(0.05),
+            ..gate
+        };
+        assert!(!gate.accepts(&delta));
+    }
+}
@@ -0,0 +1,288 @@
+//! Helpers for testing code built on blt against blt's real behavior.
+//!
+//! Integrators and strategy authors can build small corpora on disk, tokenize them, and check
+//! the results against golden token fixtures and the guarantees blt makes about output headers
+//! and manifests, without copying blt's own test code:
+//!
+//! ```no_run
+//! use blt_core::testkit::{self, Corpus};
+//! use blt_core::CoreConfig;
+//!
+//! let corpus = Corpus::new().with_file("a.txt", "hello").with_file("docs/b.txt", "world");
+//! let manifest = corpus.output("manifest.jsonl");
+//! let config = CoreConfig {
+//!     input: Some(corpus.input_dir().to_path_buf()),
+//!     output: Some(corpus.output("tokens.bin")),
+//!     ..CoreConfig::default()
+//! }
+//! .with_manifest(Some(manifest.clone()), Default::default());
+//! testkit::tokenize(config).unwrap();
+//!
+//! let output = std::fs::read(corpus.output("tokens.bin")).unwrap();
+//! testkit::assert_golden(&output, "tests/golden/hello_world.bin");
+//! testkit::assert_manifest_consistent(&testkit::manifest_entries(&manifest));
+//! ```
+//!
+//! The assertions panic with a description of the mismatch, like `assert_eq!`. Requires the
+//! `testkit` feature.
+
+use crate::hashing::{self, HashAlgorithm};
+use crate::header::{self, Endianness, OutputHeader};
+use crate::manifest::{self, ManifestEntry};
+use crate::{BltError, CoreConfig, RunReport};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Setting this environment variable to a non-empty value makes [`assert_golden`] write the
+/// actual output to the fixture instead of comparing against it.
+pub const UPDATE_GOLDEN_ENV: &str = "BLT_UPDATE_GOLDEN";
+
+/// A corpus of input files in a temporary directory, with a separate directory for outputs so
+/// they are never read back as input. Both are removed when the corpus is dropped.
+#[derive(Debug)]
+pub struct Corpus {
+    input: tempfile::TempDir,
+    output: tempfile::TempDir,
+}
+
+impl Default for Corpus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Corpus {
+    /// Creates an empty corpus.
+    pub fn new() -> Self {
+        Self {
+            input: tempfile::tempdir().expect("failed to create the corpus input directory"),
+            output: tempfile::tempdir().expect("failed to create the corpus output directory"),
+        }
+    }
+
+    /// Adds a file at `relative`, creating its parent directories.
+    pub fn with_file(self, relative: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Self {
+        self.add_file(relative, contents);
+        self
+    }
+
+    /// Adds a JSON Lines file at `relative` with one line per record.
+    pub fn with_jsonl(self, relative: impl AsRef<Path>, records: &[serde_json::Value]) -> Self {
+        let lines: String = records.iter().map(|record| format!("{record}\n")).collect();
+        self.with_file(relative, lines)
+    }
+
+    /// Adds a file at `relative`, creating its parent directories, and returns its path.
+    pub fn add_file(&self, relative: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.input.path().join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("failed to create a corpus directory");
+        }
+        std::fs::write(&path, contents)
+            .unwrap_or_else(|e| panic!("failed to write {}: {e}", path.display()));
+        path
+    }
+
+    /// The directory holding the input files.
+    pub fn input_dir(&self) -> &Path {
+        self.input.path()
+    }
+
+    /// The path of the input file at `relative`.
+    pub fn input(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.input.path().join(relative)
+    }
+
+    /// The directory outputs can be written to.
+    pub fn output_dir(&self) -> &Path {
+        self.output.path()
+    }
+
+    /// A path for an output named `name`.
+    pub fn output(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.output.path().join(name)
+    }
+}
+
+/// Runs [`run_tokenizer`](crate::run_tokenizer) to completion on a runtime of its own, for
+/// tests that are not async.
+pub fn tokenize(config: CoreConfig) -> Result<RunReport, BltError> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(BltError::Io)?
+        .block_on(crate::run_tokenizer(config))
+}
+
+/// The tokens the basic strategy produces for `input`: one token per byte.
+pub fn basic_tokens(input: &[u8]) -> Vec<u16> {
+    input.iter().map(|&byte| u16::from(byte)).collect()
+}
+
+/// Decodes the tokens of a raw output, skipping and honoring its header if it has one.
+/// Outputs without a header are read as big-endian 2-byte tokens.
+pub fn decode_tokens(output: &[u8]) -> Vec<u16> {
+    let (width, endianness, stream) = match header::has_header(output) {
+        true => {
+            let (parsed, len) = OutputHeader::parse(output)
+                .unwrap_or_else(|e| panic!("invalid output header: {e}"));
+            (parsed.token_width, parsed.endianness, &output[len..])
+        }
+        false => (2, Endianness::Big, output),
+    };
+    match width {
+        1 => stream.iter().map(|&byte| u16::from(byte)).collect(),
+        2 => {
+            assert!(
+                stream.len() % 2 == 0,
+                "token stream of {} bytes is not whole 2-byte tokens",
+                stream.len()
+            );
+            stream
+                .chunks_exact(2)
+                .map(|pair| match endianness {
+                    Endianness::Big => u16::from_be_bytes([pair[0], pair[1]]),
+                    Endianness::Little => u16::from_le_bytes([pair[0], pair[1]]),
+                })
+                .collect()
+        }
+        width => panic!("unsupported token width {width}"),
+    }
+}
+
+/// Asserts that `actual` matches the golden fixture at `golden`, reporting the first byte
+/// that differs. With [`UPDATE_GOLDEN_ENV`] set, writes `actual` to the fixture instead.
+pub fn assert_golden(actual: &[u8], golden: impl AsRef<Path>) {
+    let golden = golden.as_ref();
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some_and(|value| !value.is_empty()) {
+        if let Some(parent) = golden.parent() {
+            std::fs::create_dir_all(parent).expect("failed to create the fixture directory");
+        }
+        std::fs::write(golden, actual)
+            .unwrap_or_else(|e| panic!("failed to write {}: {e}", golden.display()));
+        return;
+    }
+    let expected = std::fs::read(golden).unwrap_or_else(|e| {
+        panic!(
+            "failed to read golden fixture {} ({e}); set {UPDATE_GOLDEN_ENV}=1 to create it",
+            golden.display()
+        )
+    });
+    if let Some(offset) = actual
+        .iter()
+        .zip(&expected)
+        .position(|(actual, expected)| actual != expected)
+        .or((actual.len() != expected.len()).then(|| actual.len().min(expected.len())))
+    {
+        panic!(
+            "output differs from golden fixture {} at byte {offset}: {} bytes, expected {}",
+            golden.display(),
+            actual.len(),
+            expected.len()
+        );
+    }
+}
+
+/// Asserts that `output` starts with `expected` as its header, returning the token stream
+/// that follows it.
+pub fn assert_header<'a>(output: &'a [u8], expected: &OutputHeader) -> &'a [u8] {
+    assert!(
+        header::has_header(output),
+        "output does not start with a BLT header"
+    );
+    let (actual, len) =
+        OutputHeader::parse(output).unwrap_or_else(|e| panic!("invalid output header: {e}"));
+    assert_eq!(&actual, expected, "output header differs");
+    &output[len..]
+}
+
+/// Reads the entries of the manifest at `path`.
+pub fn manifest_entries(path: impl AsRef<Path>) -> Vec<ManifestEntry> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read manifest {}: {e}", path.display()));
+    manifest::from_jsonl(&contents)
+        .unwrap_or_else(|e| panic!("invalid manifest {}: {e}", path.display()))
+}
+
+/// Asserts the guarantees blt makes about a manifest: documents are numbered in order from
+/// zero, and every content hash and output hash matches the file it describes. Content hashes
+/// cover the input after pre-processing, so they are only checked against the source file for
+/// runs without pre-processing; sources that are not files, such as stdin, are skipped.
+pub fn assert_manifest_consistent(entries: &[ManifestEntry]) {
+    for (position, entry) in entries.iter().enumerate() {
+        assert_eq!(
+            entry.doc_id, position as u64,
+            "manifest entry {position} ({}) is out of order",
+            entry.source
+        );
+        let source = Path::new(&entry.source);
+        if let (Some(hash), true) = (&entry.content_hash, source.is_file()) {
+            assert_file_hash(source, hash, "content");
+        }
+        if let (Some(output), Some(hash)) = (&entry.output, &entry.output_hash) {
+            assert_file_hash(Path::new(output), hash, "output");
+        }
+    }
+}
+
+/// Asserts that the file at `path` hashes to `expected`, a `"<algorithm>:<hex>"` string.
+fn assert_file_hash(path: &Path, expected: &str, what: &str) {
+    let (algorithm, _) = expected.split_once(':').unwrap_or((expected, ""));
+    let algorithm: HashAlgorithm = algorithm
+        .parse()
+        .unwrap_or_else(|e| panic!("unknown {what} hash algorithm in {expected}: {e}"));
+    let file =
+        File::open(path).unwrap_or_else(|e| panic!("failed to open {}: {e}", path.display()));
+    let actual = hashing::hash_reader(algorithm, file)
+        .unwrap_or_else(|e| panic!("failed to hash {}: {e}", path.display()));
+    assert_eq!(
+        actual,
+        expected,
+        "{what} hash of {} differs from the manifest",
+        path.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_handler::OutputDir;
+    use crate::StrategyKind;
+
+    #[test]
+    fn test_tokenizes_corpus_into_consistent_manifest() {
+        let corpus = Corpus::new()
+            .with_file("a.txt", "hi")
+            .with_jsonl("logs/b.jsonl", &[serde_json::json!({"id": 1})]);
+        let manifest = corpus.output("manifest.jsonl");
+        let config = CoreConfig {
+            input: Some(corpus.input_dir().to_path_buf()),
+            ..CoreConfig::default()
+        }
+        .with_output_dir(Some(OutputDir::new(corpus.output("out"))))
+        .with_manifest(Some(manifest.clone()), HashAlgorithm::default());
+        let report = tokenize(config).unwrap();
+        assert_eq!(report.documents, 2);
+
+        let entries = manifest_entries(&manifest);
+        assert_manifest_consistent(&entries);
+        let output = std::fs::read(entries[0].output.as_ref().unwrap()).unwrap();
+        assert_eq!(decode_tokens(&output), basic_tokens(b"hi"));
+    }
+
+    #[test]
+    fn test_golden_fixtures_and_headers() {
+        let corpus = Corpus::new();
+        let header = OutputHeader::new(StrategyKind::Basic, None);
+        let output = [&header.to_bytes()[..], &[0, b'o', 0, b'k']].concat();
+        assert_eq!(assert_header(&output, &header), [0, b'o', 0, b'k']);
+        assert_eq!(decode_tokens(&output), basic_tokens(b"ok"));
+
+        let golden = corpus.output("golden.bin");
+        std::fs::write(&golden, &output).unwrap();
+        assert_golden(&output, &golden);
+        let mismatch = std::panic::catch_unwind(|| assert_golden(&output[..22], &golden));
+        assert!(mismatch.is_err());
+    }
+}
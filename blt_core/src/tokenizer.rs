@@ -5,8 +5,10 @@
 //! It includes a `BpeStrategy` for Byte-Pair Encoding and a `PassthroughStrategy`
 //! as a default no-op.
 
-use crate::BpeMerges;
+use crate::{buffers, special_tokens, BpeMerges};
 use async_trait;
+use bytes::Bytes;
+use rustc_hash::FxHashMap;
 use std::io;
 use std::sync::Arc;
 use tracing::{debug, instrument};
@@ -28,6 +30,51 @@ pub trait TokenizationStrategy: Send + Sync {
     /// # Returns
     /// A `Result` containing the processed `Vec<u8>` on success, or an `io::Error` on failure.
     async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Processes a chunk held in a shared buffer, such as a slice of a memory-mapped input.
+    ///
+    /// The pipeline hands chunks to their tasks and results to the writer this way, so
+    /// strategies whose output shares the input's bytes can return them without copying. The
+    /// default runs [`TokenizationStrategy::process_chunk`] and takes over its buffer.
+    async fn process_bytes(&self, chunk: Bytes) -> io::Result<Bytes> {
+        self.process_chunk(&chunk).await.map(Bytes::from)
+    }
+
+    /// Estimates the output size for `input_bytes` bytes of input.
+    ///
+    /// Used to pre-allocate output buffers and to plan runs without tokenizing. The default
+    /// assumes one 16-bit token per input byte, which is exact for byte-level tokenization.
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        ExpansionEstimate::exact(input_bytes * 2)
+    }
+}
+
+/// An estimate of how many output bytes a strategy produces for some input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExpansionEstimate {
+    /// The expected number of output bytes.
+    pub expected: u64,
+    /// An upper bound on the number of output bytes.
+    pub max: u64,
+}
+
+impl ExpansionEstimate {
+    /// An estimate for strategies whose output size is known exactly.
+    pub fn exact(output_bytes: u64) -> Self {
+        Self {
+            expected: output_bytes,
+            max: output_bytes,
+        }
+    }
+
+    /// The expected ratio of output bytes to `input_bytes`.
+    pub fn ratio(&self, input_bytes: u64) -> f64 {
+        if input_bytes == 0 {
+            0.0
+        } else {
+            self.expected as f64 / input_bytes as f64
+        }
+    }
 }
 
 // --- BPE Strategy Implementation ---
@@ -38,6 +85,8 @@ pub trait TokenizationStrategy: Send + Sync {
 /// based on a provided `merges` map.
 pub struct BpeStrategy {
     bpe_merges: Arc<BpeMerges>,
+    /// Expected output bytes per input byte, from [`BpeStrategy::calibrated`].
+    expected_ratio: f64,
 }
 
 impl BpeStrategy {
@@ -46,19 +95,26 @@ impl BpeStrategy {
     /// # Arguments
     /// * `bpe_merges` - An `Arc`-wrapped map of byte pairs to their resulting merged token.
     pub fn new(bpe_merges: Arc<BpeMerges>) -> Self {
-        Self { bpe_merges }
+        Self {
+            bpe_merges,
+            expected_ratio: 2.0,
+        }
     }
-}
 
-#[async_trait::async_trait]
-impl TokenizationStrategy for BpeStrategy {
-    #[instrument(skip(self, chunk_data), name = "bpe_strategy_process")]
-    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
-        if chunk_data.is_empty() {
-            return Ok(Vec::new());
+    /// Measures the compression achieved on `sample` and uses it for
+    /// [`TokenizationStrategy::estimated_expansion`].
+    ///
+    /// Without calibration, the estimate assumes no merges apply.
+    pub fn calibrated(mut self, sample: &[u8]) -> Self {
+        if !sample.is_empty() {
+            self.expected_ratio = (self.encode(sample).len() * 2) as f64 / sample.len() as f64;
         }
+        self
+    }
 
-        let mut tokens: Vec<u16> = chunk_data.iter().map(|&b| b as u16).collect();
+    /// Applies the merges to `data` and returns the resulting tokens.
+    pub fn encode(&self, data: &[u8]) -> Vec<u16> {
+        let mut tokens: Vec<u16> = data.iter().map(|&b| b as u16).collect();
 
         loop {
             let mut merges_found = false;
@@ -84,21 +140,41 @@ impl TokenizationStrategy for BpeStrategy {
                 break;
             }
         }
+        tokens
+    }
+}
 
-        let mut output_bytes = Vec::with_capacity(tokens.len() * 2);
+#[async_trait::async_trait]
+impl TokenizationStrategy for BpeStrategy {
+    #[instrument(skip(self, chunk_data), name = "bpe_strategy_process")]
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        if chunk_data.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let tokens = self.encode(chunk_data);
+        let mut output_bytes = buffers::shared().take_output(self, chunk_data.len());
         for token in tokens {
             output_bytes.extend_from_slice(&token.to_be_bytes());
         }
         Ok(output_bytes)
     }
+
+    /// Merges only shrink the output, so byte-level tokenization is the upper bound.
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        let max = input_bytes * 2;
+        let expected = ((input_bytes as f64 * self.expected_ratio).ceil() as u64).min(max);
+        ExpansionEstimate { expected, max }
+    }
 }
 
 // --- Basic Tokenization Strategy (New Default) ---
 
 /// A tokenization strategy that converts each byte to a 16-bit token.
 ///
-/// This strategy converts each input byte to a u16 token (256-511 range)
-/// without applying any BPE merges. This provides true tokenization while
+/// This strategy converts each input byte to a u16 token with the same value (0-255 range)
+/// without applying any BPE merges; wrap it in a [`ByteOffsetStrategy`] to move byte tokens
+/// to 256-511. This provides true tokenization while
 /// maintaining simplicity for users who don't need BPE compression.
 pub struct BasicTokenizationStrategy;
 
@@ -113,7 +189,7 @@ impl TokenizationStrategy for BasicTokenizationStrategy {
         debug!("Converting {} bytes to u16 tokens", chunk_data.len());
 
         // Convert each byte to u16 token (byte value range: 0-255)
-        let mut output_bytes = Vec::with_capacity(chunk_data.len() * 2);
+        let mut output_bytes = buffers::shared().take_output(self, chunk_data.len());
         for &byte in chunk_data {
             let token = byte as u16;
             output_bytes.extend_from_slice(&token.to_be_bytes());
@@ -123,6 +199,104 @@ impl TokenizationStrategy for BasicTokenizationStrategy {
     }
 }
 
+// --- Byte Offset Strategy ---
+
+/// The byte token offsets blt supports: byte `b` becomes token `b + offset`.
+pub const BYTE_OFFSETS: [u16; 2] = [0, 256];
+
+/// A strategy that shifts the byte tokens of another strategy by a fixed offset.
+///
+/// Tokens below 256 produced by the inner strategy are byte tokens and become
+/// `token + offset`; merge tokens are left unchanged. With an offset of 256, byte tokens
+/// occupy 256-511, so merge IDs must start at 512 or above to stay distinct.
+pub struct ByteOffsetStrategy {
+    inner: Arc<dyn TokenizationStrategy>,
+    offset: u16,
+}
+
+impl ByteOffsetStrategy {
+    /// Shifts the byte tokens of `inner` by `offset`, which must be one of [`BYTE_OFFSETS`].
+    pub fn new(inner: Arc<dyn TokenizationStrategy>, offset: u16) -> io::Result<Self> {
+        if !BYTE_OFFSETS.contains(&offset) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Byte token offset {offset} is not supported, expected 0 or 256"),
+            ));
+        }
+        Ok(Self { inner, offset })
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for ByteOffsetStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = self.inner.process_chunk(chunk_data).await?;
+        for pair in output.chunks_exact_mut(2) {
+            let token = u16::from_be_bytes([pair[0], pair[1]]);
+            if token < 256 {
+                pair.copy_from_slice(&(token + self.offset).to_be_bytes());
+            }
+        }
+        Ok(output)
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        self.inner.estimated_expansion(input_bytes)
+    }
+}
+
+/// A strategy that tokenizes each line of a chunk separately, and each newline on its own.
+///
+/// Post-processing finds document boundaries by the newline token, so a merge that absorbs a
+/// newline would hide the boundary. Under this strategy no merge spans a newline, whatever
+/// the merges table contains.
+pub struct LineBoundaryStrategy {
+    inner: Arc<dyn TokenizationStrategy>,
+}
+
+impl LineBoundaryStrategy {
+    /// Tokenizes the lines and newlines of every chunk with `inner`.
+    pub fn new(inner: Arc<dyn TokenizationStrategy>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for LineBoundaryStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut tokens = buffers::shared().take_output(self, chunk_data.len());
+        for line in chunk_data.split_inclusive(|&b| b == b'\n') {
+            let (text, newline) = match line.split_last() {
+                Some((b'\n', text)) => (text, &line[text.len()..]),
+                _ => (line, &[][..]),
+            };
+            for piece in [text, newline] {
+                if piece.is_empty() {
+                    continue;
+                }
+                let piece_tokens = self.inner.process_chunk(piece).await?;
+                tokens.extend_from_slice(&piece_tokens);
+                buffers::shared().give(piece_tokens);
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        self.inner.estimated_expansion(input_bytes)
+    }
+}
+
+/// Whether any merge in `merges` produces a token whose bytes include a newline.
+pub fn merges_absorb_newline(merges: &BpeMerges) -> bool {
+    let decoder = TokenDecoder::new(Some(merges), 0);
+    merges.values().any(|&token| {
+        decoder
+            .decode_tokens([token])
+            .map_or(true, |bytes| bytes.contains(&b'\n'))
+    })
+}
+
 // --- Passthrough Strategy Implementation (Explicit Copy Mode) ---
 
 /// A tokenization strategy that performs no operations.
@@ -140,7 +314,93 @@ impl TokenizationStrategy for PassthroughStrategy {
             "Passthrough mode: returning {} bytes unchanged",
             chunk_data.len()
         );
-        Ok(chunk_data.to_vec())
+        let mut output = buffers::shared().take_output(self, chunk_data.len());
+        output.extend_from_slice(chunk_data);
+        Ok(output)
+    }
+
+    /// The result is the chunk itself.
+    async fn process_bytes(&self, chunk: Bytes) -> io::Result<Bytes> {
+        Ok(chunk)
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        ExpansionEstimate::exact(input_bytes)
+    }
+}
+
+// --- Decoding ---
+
+/// Expands token streams back into the bytes they encode.
+pub struct TokenDecoder {
+    /// The pair each merged token replaced.
+    pairs: FxHashMap<u16, (u16, u16)>,
+    byte_offset: u16,
+}
+
+impl TokenDecoder {
+    /// Decodes tokens produced with `merges` (if any) and byte tokens shifted by `byte_offset`.
+    pub fn new(merges: Option<&BpeMerges>, byte_offset: u16) -> Self {
+        Self {
+            pairs: merges
+                .into_iter()
+                .flatten()
+                .map(|(&pair, &token)| (token, pair))
+                .collect(),
+            byte_offset,
+        }
+    }
+
+    /// Decodes big-endian 16-bit tokens. Special tokens carry no bytes and are skipped.
+    pub fn decode(&self, token_bytes: &[u8]) -> io::Result<Vec<u8>> {
+        if !token_bytes.len().is_multiple_of(2) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Token stream of {} bytes is not a whole number of 16-bit tokens",
+                    token_bytes.len()
+                ),
+            ));
+        }
+        self.decode_tokens(
+            token_bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]])),
+        )
+    }
+
+    /// Decodes tokens. Special tokens carry no bytes and are skipped.
+    pub fn decode_tokens(&self, tokens: impl IntoIterator<Item = u16>) -> io::Result<Vec<u8>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let tokens = tokens.into_iter();
+        let mut decoded = Vec::with_capacity(tokens.size_hint().0);
+        let mut pending = Vec::new();
+        for token in tokens {
+            if special_tokens::is_reserved(token) {
+                continue;
+            }
+            pending.push(token);
+            while let Some(token) = pending.pop() {
+                if let Some(&(first, second)) = self.pairs.get(&token) {
+                    // An acyclic table never nests deeper than it has merges.
+                    if pending.len() > self.pairs.len() {
+                        return Err(invalid("The merges table is cyclic".to_string()));
+                    }
+                    pending.push(second);
+                    pending.push(first);
+                } else {
+                    match token.checked_sub(self.byte_offset) {
+                        Some(byte) if byte < 256 => decoded.push(byte as u8),
+                        _ => {
+                            return Err(invalid(format!(
+                                "Token {token} is neither a byte token nor a merge"
+                            )))
+                        }
+                    }
+                }
+            }
+        }
+        Ok(decoded)
     }
 }
 
@@ -276,6 +536,11 @@ mod tests {
 
         let result = strategy.process_chunk(chunk).await?;
         assert_eq!(result, expected_bytes);
+
+        // The shared input is handed back as is, without a copy.
+        let shared = Bytes::from_static(chunk);
+        let result = strategy.process_bytes(shared.clone()).await?;
+        assert_eq!(result.as_ptr(), shared.as_ptr());
         Ok(())
     }
 
@@ -289,4 +554,89 @@ mod tests {
         assert_eq!(result, u16_vec_to_byte_vec(&expected_tokens));
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_byte_offset_strategy() -> io::Result<()> {
+        let bpe = Arc::new(create_bpe_strategy(vec![((97, 98), 512)]));
+        let strategy = ByteOffsetStrategy::new(bpe, 256)?;
+        let result = strategy.process_chunk(b"abc").await?;
+        assert_eq!(result, u16_vec_to_byte_vec(&[512, 256 + 99]));
+        assert!(ByteOffsetStrategy::new(Arc::new(BasicTokenizationStrategy), 128).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_line_boundary_strategy_keeps_newlines_unmerged() -> io::Result<()> {
+        // "a\n" and "\n\n" merge, hiding the newline from a plain BPE run.
+        let pairs = vec![((97, 10), 256), ((10, 10), 257)];
+        let merges: BpeMerges = pairs.iter().copied().collect();
+        assert!(merges_absorb_newline(&merges));
+        assert!(!merges_absorb_newline(
+            &[((97, 98), 256)].into_iter().collect()
+        ));
+        let bpe = Arc::new(create_bpe_strategy(pairs));
+        assert_eq!(
+            bpe.process_chunk(b"a\n\n\nb").await?,
+            u16_vec_to_byte_vec(&[256, 257, 98])
+        );
+
+        let strategy = LineBoundaryStrategy::new(bpe);
+        assert_eq!(
+            strategy.process_chunk(b"a\n\n\nb").await?,
+            u16_vec_to_byte_vec(&[97, 10, 10, 10, 98])
+        );
+        assert_eq!(strategy.process_chunk(b"").await?, Vec::<u8>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimated_expansion() {
+        assert_eq!(
+            BasicTokenizationStrategy.estimated_expansion(10),
+            ExpansionEstimate::exact(20)
+        );
+        assert_eq!(
+            PassthroughStrategy.estimated_expansion(10),
+            ExpansionEstimate::exact(10)
+        );
+
+        let strategy = create_bpe_strategy(vec![((97, 98), 256)]);
+        assert_eq!(strategy.estimated_expansion(10).expected, 20);
+        // "abab" encodes to two tokens: 4 output bytes for 4 input bytes.
+        let estimate = strategy.calibrated(b"abab").estimated_expansion(10);
+        assert_eq!(
+            estimate,
+            ExpansionEstimate {
+                expected: 10,
+                max: 20
+            }
+        );
+        assert_eq!(estimate.ratio(10), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_decoder_roundtrip() -> io::Result<()> {
+        let merges: BpeMerges = [((97, 98), 256), ((256, 99), 257)].into_iter().collect();
+        let strategy = BpeStrategy::new(Arc::new(merges.clone()));
+        let input = b"abcab abc";
+        let mut tokens = strategy.process_chunk(input).await?;
+        tokens.extend_from_slice(&special_tokens::PAD.to_be_bytes());
+        let decoder = TokenDecoder::new(Some(&merges), 0);
+        assert_eq!(decoder.decode(&tokens)?, input);
+
+        let shifted = TokenDecoder::new(None, 256);
+        assert_eq!(
+            shifted.decode(&u16_vec_to_byte_vec(&[256 + 104, 256 + 105]))?,
+            b"hi"
+        );
+        assert!(shifted.decode(&u16_vec_to_byte_vec(&[104])).is_err());
+        assert!(decoder.decode(&[0, 97, 0]).is_err());
+
+        let cyclic: BpeMerges = [((257, 97), 256), ((256, 97), 257)].into_iter().collect();
+        let err = TokenDecoder::new(Some(&cyclic), 0)
+            .decode(&u16_vec_to_byte_vec(&[256]))
+            .unwrap_err();
+        assert!(err.to_string().contains("cyclic"));
+        Ok(())
+    }
 }
@@ -0,0 +1,343 @@
+//! Resource usage accounting for tokenizer runs.
+//!
+//! Process-wide figures (peak RSS, CPU time, storage I/O) come from operating system counters
+//! sampled at the start and end of a run. Per-stage CPU time is measured with the calling
+//! thread's CPU clock around every poll of the pipeline's readers, tokenization futures, and
+//! output writers, so it stays accurate when tasks move between runtime threads.
+//!
+//! Some work is not attributed to a stage: standard input is read on Tokio's blocking pool,
+//! so the read stage only covers stream decoding, and sandboxed workers run in separate
+//! processes whose CPU time is not included.
+
+use crate::io_handler::InputSource;
+use crate::tokenizer::{ExpansionEstimate, TokenizationStrategy};
+use bytes::Bytes;
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// CPU time spent in each pipeline stage, in seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct StageTimes {
+    /// Reading input chunks from streams.
+    pub read_secs: f64,
+    /// Tokenizing chunks, including page faults on memory-mapped inputs.
+    pub tokenize_secs: f64,
+    /// Post-processing, encoding, compressing, and writing the output.
+    pub write_secs: f64,
+}
+
+/// Resources used by a run. Counters the platform does not provide are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ResourceUsage {
+    /// The process's peak resident set size. This is a high-water mark for the whole
+    /// process, so it also covers anything the process did before the run.
+    pub peak_rss_bytes: Option<u64>,
+    /// User CPU time consumed by the process during the run.
+    pub user_cpu_secs: Option<f64>,
+    /// System CPU time consumed by the process during the run.
+    pub system_cpu_secs: Option<f64>,
+    /// Bytes the process caused to be read from storage during the run.
+    pub io_read_bytes: Option<u64>,
+    /// Bytes the process caused to be written to storage during the run.
+    pub io_write_bytes: Option<u64>,
+    /// CPU time by pipeline stage.
+    pub stages: Option<StageTimes>,
+}
+
+/// A pipeline stage that CPU time is attributed to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Stage {
+    Read,
+    Tokenize,
+    Write,
+}
+
+/// Nanoseconds of CPU time accumulated per stage, shared by every task of a run.
+#[derive(Debug, Default)]
+pub(crate) struct StageCounters {
+    read: AtomicU64,
+    tokenize: AtomicU64,
+    write: AtomicU64,
+}
+
+impl StageCounters {
+    /// Runs `poll` and adds the CPU time the current thread spent in it to `stage`.
+    fn measure<T>(&self, stage: Stage, poll: impl FnOnce() -> T) -> T {
+        let start = thread_cpu_time();
+        let output = poll();
+        if let (Some(start), Some(end)) = (start, thread_cpu_time()) {
+            let counter = match stage {
+                Stage::Read => &self.read,
+                Stage::Tokenize => &self.tokenize,
+                Stage::Write => &self.write,
+            };
+            let nanos = end.saturating_sub(start).as_nanos() as u64;
+            counter.fetch_add(nanos, Ordering::Relaxed);
+        }
+        output
+    }
+
+    fn times(&self) -> StageTimes {
+        let secs = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as f64 / 1e9;
+        StageTimes {
+            read_secs: secs(&self.read),
+            tokenize_secs: secs(&self.tokenize),
+            write_secs: secs(&self.write),
+        }
+    }
+}
+
+/// Samples process counters at the start of a run and reports the difference at the end.
+pub(crate) struct UsageTracker {
+    start: ProcessCounters,
+    stages: Arc<StageCounters>,
+}
+
+impl UsageTracker {
+    pub(crate) fn start() -> Self {
+        Self {
+            start: ProcessCounters::sample(),
+            stages: Arc::default(),
+        }
+    }
+
+    /// The stage counters that timed readers, writers, and strategies add to.
+    pub(crate) fn stages(&self) -> &Arc<StageCounters> {
+        &self.stages
+    }
+
+    /// Wraps `strategy` so its chunk processing counts towards the tokenize stage.
+    pub(crate) fn time_strategy(
+        &self,
+        strategy: Arc<dyn TokenizationStrategy>,
+    ) -> Arc<dyn TokenizationStrategy> {
+        Arc::new(TimedStrategy {
+            inner: strategy,
+            stages: self.stages.clone(),
+        })
+    }
+
+    /// Wraps stream sources so reading them counts towards the read stage. Memory-mapped
+    /// inputs are read by the tokenizer itself.
+    pub(crate) fn time_source(&self, source: InputSource) -> InputSource {
+        let timed = |reader| Box::new(Timed::new(reader, self.stages.clone(), Stage::Read));
+        match source {
+            InputSource::Mmap(mmap) => InputSource::Mmap(mmap),
+            InputSource::Stdin(reader) => InputSource::Stdin(timed(reader)),
+            InputSource::Reader(reader) => InputSource::Reader(timed(reader)),
+        }
+    }
+
+    pub(crate) fn finish(self) -> ResourceUsage {
+        let end = ProcessCounters::sample();
+        let cpu_delta = |start: Option<Duration>, end: Option<Duration>| {
+            Some(end?.saturating_sub(start?).as_secs_f64())
+        };
+        let io_delta = |start: Option<u64>, end: Option<u64>| Some(end?.saturating_sub(start?));
+        ResourceUsage {
+            peak_rss_bytes: end.peak_rss_bytes,
+            user_cpu_secs: cpu_delta(self.start.user_cpu, end.user_cpu),
+            system_cpu_secs: cpu_delta(self.start.system_cpu, end.system_cpu),
+            io_read_bytes: io_delta(self.start.io_read_bytes, end.io_read_bytes),
+            io_write_bytes: io_delta(self.start.io_write_bytes, end.io_write_bytes),
+            stages: thread_cpu_time().map(|_| self.stages.times()),
+        }
+    }
+}
+
+/// A reader or writer whose polls count towards a stage.
+pub(crate) struct Timed<T> {
+    inner: T,
+    stages: Arc<StageCounters>,
+    stage: Stage,
+}
+
+impl<T> Timed<T> {
+    pub(crate) fn new(inner: T, stages: Arc<StageCounters>, stage: Stage) -> Self {
+        Self {
+            inner,
+            stages,
+            stage,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Timed<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.stages
+            .measure(this.stage, || Pin::new(&mut this.inner).poll_read(cx, buf))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Timed<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.stages
+            .measure(this.stage, || Pin::new(&mut this.inner).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.stages
+            .measure(this.stage, || Pin::new(&mut this.inner).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.stages
+            .measure(this.stage, || Pin::new(&mut this.inner).poll_shutdown(cx))
+    }
+}
+
+/// A strategy whose chunk processing counts towards the tokenize stage.
+struct TimedStrategy {
+    inner: Arc<dyn TokenizationStrategy>,
+    stages: Arc<StageCounters>,
+}
+
+impl TimedStrategy {
+    /// Counts the time spent polling `future` towards the tokenize stage.
+    async fn timed<T>(&self, future: impl Future<Output = T>) -> T {
+        let mut future = std::pin::pin!(future);
+        std::future::poll_fn(|cx| {
+            self.stages
+                .measure(Stage::Tokenize, || future.as_mut().poll(cx))
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenizationStrategy for TimedStrategy {
+    async fn process_chunk(&self, chunk_data: &[u8]) -> io::Result<Vec<u8>> {
+        self.timed(self.inner.process_chunk(chunk_data)).await
+    }
+
+    async fn process_bytes(&self, chunk: Bytes) -> io::Result<Bytes> {
+        self.timed(self.inner.process_bytes(chunk)).await
+    }
+
+    fn estimated_expansion(&self, input_bytes: u64) -> ExpansionEstimate {
+        self.inner.estimated_expansion(input_bytes)
+    }
+}
+
+/// Process-wide counters at one point in time.
+#[derive(Default)]
+struct ProcessCounters {
+    peak_rss_bytes: Option<u64>,
+    user_cpu: Option<Duration>,
+    system_cpu: Option<Duration>,
+    io_read_bytes: Option<u64>,
+    io_write_bytes: Option<u64>,
+}
+
+impl ProcessCounters {
+    fn sample() -> Self {
+        let mut counters = Self::cpu();
+        if let Ok(pid) = sysinfo::get_current_pid() {
+            let mut system = sysinfo::System::new();
+            let refresh = sysinfo::ProcessRefreshKind::new().with_disk_usage();
+            if system.refresh_process_specifics(pid, refresh) {
+                if let Some(process) = system.process(pid) {
+                    let disk = process.disk_usage();
+                    counters.io_read_bytes = Some(disk.total_read_bytes);
+                    counters.io_write_bytes = Some(disk.total_written_bytes);
+                }
+            }
+        }
+        counters
+    }
+
+    /// Samples only the peak RSS and CPU counters, which are cheap to read.
+    fn cpu() -> Self {
+        let mut counters = Self::default();
+        #[cfg(unix)]
+        if let Some(usage) = rusage() {
+            let timeval = |tv: libc::timeval| {
+                Duration::new(tv.tv_sec as u64, (tv.tv_usec as u32).saturating_mul(1000))
+            };
+            // Linux reports the peak in KiB, macOS in bytes.
+            let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+            counters.peak_rss_bytes = Some(usage.ru_maxrss as u64 * unit);
+            counters.user_cpu = Some(timeval(usage.ru_utime));
+            counters.system_cpu = Some(timeval(usage.ru_stime));
+        }
+        counters
+    }
+}
+
+/// The user and system CPU time consumed so far by the whole process.
+pub(crate) fn process_cpu_time() -> Option<Duration> {
+    let counters = ProcessCounters::cpu();
+    Some(counters.user_cpu? + counters.system_cpu?)
+}
+
+#[cfg(unix)]
+fn rusage() -> Option<libc::rusage> {
+    // SAFETY: `getrusage` only writes to the provided struct, which is valid when zeroed.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    (unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } == 0).then_some(usage)
+}
+
+/// The CPU time consumed so far by the calling thread.
+#[cfg(unix)]
+fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `clock_gettime` only writes to the provided timespec.
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    (result == 0).then(|| Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::BasicTokenizationStrategy;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_tracker_attributes_stages() {
+        let tracker = UsageTracker::start();
+        let strategy = tracker.time_strategy(Arc::new(BasicTokenizationStrategy));
+        let chunk = vec![7u8; 1 << 20];
+        let tokens = strategy.process_chunk(&chunk).await.unwrap();
+        assert_eq!(tokens.len(), 2 << 20);
+
+        let mut writer = Timed::new(Vec::new(), tracker.stages().clone(), Stage::Write);
+        writer.write_all(&tokens).await.unwrap();
+        assert_eq!(writer.inner, tokens);
+
+        let usage = tracker.finish();
+        if cfg!(unix) {
+            let stages = usage.stages.unwrap();
+            assert!(stages.tokenize_secs > 0.0);
+            assert_eq!(stages.read_secs, 0.0);
+            assert!(usage.peak_rss_bytes.unwrap() > 0);
+            assert!(usage.user_cpu_secs.is_some());
+        }
+    }
+}
@@ -7,7 +7,7 @@
 
 // The parse_chunk_size function was moved here from lib.rs
 // It's a utility for parsing human-readable size strings.
-pub(crate) fn parse_chunk_size_str(s: &str) -> Result<usize, String> {
+pub fn parse_chunk_size_str(s: &str) -> Result<usize, String> {
     let s_trimmed = s.trim();
     if s_trimmed.is_empty() {
         return Err("Input string is empty".to_string());
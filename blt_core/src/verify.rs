@@ -0,0 +1,348 @@
+//! Checking that tokenization is lossless.
+//!
+//! [`verify`] runs the input `config` describes through the pipeline, with the same strategy,
+//! pre-processing, and chunking as a real run, decodes every chunk's tokens as they are
+//! written, and compares the decoded bytes against the bytes the chunk was tokenized from.
+//! Nothing is written, and only one chunk of input is held at a time, so corpora of any size
+//! can be checked. Decoding skips special tokens, so record framing and region markers do not
+//! count as divergence.
+
+use crate::io_handler::{self, OutputWriter};
+use crate::pipeline::{self, FifoScheduler, PipelineOutput, Scheduler};
+use crate::tokenizer::TokenDecoder;
+use crate::{chunking, framed_strategy, preprocess, token_width, validate_config};
+use crate::{BltError, CoreConfig};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::AsyncWrite;
+use tracing::{info, instrument, warn};
+
+/// Where a document's decoded tokens first differ from its input.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    /// The document's source.
+    pub source: String,
+    /// The offset of the first differing byte within the document, after pre-processing.
+    pub offset: u64,
+    /// What differs there.
+    pub reason: String,
+}
+
+/// The outcome of a roundtrip verification.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VerifyReport {
+    /// Documents verified, including the one that diverged.
+    pub documents: u64,
+    /// Input bytes that decoded back exactly.
+    pub bytes_verified: u64,
+    /// Tokens decoded.
+    pub tokens: u64,
+    /// The first divergence, if any. Verification stops there.
+    pub divergence: Option<Divergence>,
+    /// Wall-clock duration of the verification.
+    pub wall_secs: f64,
+}
+
+impl VerifyReport {
+    /// Whether every document decoded back to its input.
+    pub fn passed(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.divergence {
+            None => writeln!(
+                f,
+                "OK: {} documents, {} bytes and {} tokens roundtrip exactly",
+                self.documents, self.bytes_verified, self.tokens
+            ),
+            Some(divergence) => writeln!(
+                f,
+                "FAILED: {} diverges at byte {}: {}",
+                divergence.source, divergence.offset, divergence.reason
+            ),
+        }
+    }
+}
+
+/// Tokenizes the input `config` describes, decodes the tokens, and compares them against the
+/// input, stopping at the first divergence. `config` must not name an output or a manifest.
+///
+/// Bytes are compared after pre-processing, which is lossy by design, so only the tokenizer
+/// is checked. Headers, markers, separators, and post-processing are never written, so their
+/// options make no difference.
+#[instrument(skip_all, fields(input = ?config.input, filelist = ?config.filelist))]
+pub async fn verify(config: &CoreConfig) -> Result<VerifyReport, BltError> {
+    let started = Instant::now();
+    if config.output.is_some()
+        || config.output_dir.is_some()
+        || config.index.is_some()
+        || config.tee_original.is_some()
+        || config.manifest.is_some()
+    {
+        return Err(BltError::Config(
+            "Verification writes nothing and cannot be combined with an output, output directory, index, mirrored input, or manifest"
+                .to_string(),
+        ));
+    }
+    let strategy = framed_strategy(config)?;
+    validate_config(config)?;
+    let chunk_size = chunking::get_effective_chunk_size(config);
+    let scheduler: Arc<dyn Scheduler> = match &config.scheduler {
+        Some(scheduler) => scheduler.clone(),
+        None => Arc::new(FifoScheduler::new(
+            config.num_threads,
+            config.thread_scaling,
+        )),
+    };
+    let decoder = match config.passthrough_mode {
+        true => None,
+        false => Some(Arc::new(TokenDecoder::new(
+            config.bpe_data.as_deref(),
+            config.byte_offset,
+        ))),
+    };
+    let mut report = VerifyReport {
+        documents: 0,
+        bytes_verified: 0,
+        tokens: 0,
+        divergence: None,
+        wall_secs: 0.0,
+    };
+    let mut documents = io_handler::setup_input(config)?;
+    while let Some(document) = documents.next().await? {
+        report.documents += 1;
+        let source = preprocess(config, document.source)?;
+        let roundtrip = Arc::new(Mutex::new(Roundtrip::new(decoder.clone())));
+        let mut tokens: OutputWriter = Box::new(DecodedWriter(roundtrip.clone()));
+        let mut original: OutputWriter = Box::new(ExpectedWriter(roundtrip.clone()));
+        let mut output = PipelineOutput::new(&mut tokens, Some(&mut original), None)
+            .with_entropy_markers(config.entropy_markers);
+        let result = pipeline::run(
+            source,
+            &mut output,
+            chunk_size,
+            config.deterministic,
+            config.records.as_ref(),
+            &scheduler,
+            strategy.clone(),
+        )
+        .await;
+        let summary = output.into_summary();
+        let mut roundtrip = roundtrip.lock().expect("roundtrip lock poisoned");
+        report.bytes_verified += roundtrip.verified;
+        report.tokens += summary.output_bytes / token_width(config);
+        if roundtrip.mismatch.is_none() {
+            result?;
+            roundtrip.finish();
+        }
+        if let Some((offset, reason)) = roundtrip.mismatch.take() {
+            warn!(source = %document.name, offset, %reason, "Roundtrip diverged");
+            report.divergence = Some(Divergence {
+                source: document.name,
+                offset,
+                reason,
+            });
+            break;
+        }
+    }
+    report.wall_secs = started.elapsed().as_secs_f64();
+    info!(
+        documents = report.documents,
+        bytes_verified = report.bytes_verified,
+        passed = report.passed(),
+        "Roundtrip verification finished"
+    );
+    Ok(report)
+}
+
+/// One document's comparison. The pipeline mirrors each chunk's input before writing its
+/// tokens, so at most one chunk of input is waiting to be matched at a time.
+struct Roundtrip {
+    /// Decodes tokens, or `None` for passthrough, whose tokens are the input bytes.
+    decoder: Option<Arc<TokenDecoder>>,
+    /// Input bytes not yet matched by decoded tokens.
+    expected: Vec<u8>,
+    /// Input bytes matched so far.
+    verified: u64,
+    /// The offset and description of the first divergence.
+    mismatch: Option<(u64, String)>,
+}
+
+impl Roundtrip {
+    fn new(decoder: Option<Arc<TokenDecoder>>) -> Self {
+        Self {
+            decoder,
+            expected: Vec::new(),
+            verified: 0,
+            mismatch: None,
+        }
+    }
+
+    /// Matches `tokens` against the input waiting to be matched, recording the first
+    /// divergence.
+    fn compare(&mut self, tokens: &[u8]) {
+        let decoded = match &self.decoder {
+            Some(decoder) => match decoder.decode(tokens) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    self.mismatch = Some((self.verified, e.to_string()));
+                    return;
+                }
+            },
+            None => tokens.to_vec(),
+        };
+        let matched = decoded
+            .iter()
+            .zip(&self.expected)
+            .take_while(|(decoded, expected)| decoded == expected)
+            .count();
+        let offset = self.verified + matched as u64;
+        self.mismatch = match (decoded.get(matched), self.expected.get(matched)) {
+            (Some(decoded), Some(expected)) => Some((
+                offset,
+                format!("expected 0x{expected:02x}, decoded 0x{decoded:02x}"),
+            )),
+            (Some(_), None) => Some((offset, "decoded bytes past the input".to_string())),
+            (None, _) => None,
+        };
+        self.expected.drain(..matched);
+        self.verified = offset;
+    }
+
+    /// Records input left over once every token has been decoded.
+    fn finish(&mut self) {
+        if !self.expected.is_empty() {
+            self.mismatch = Some((
+                self.verified,
+                format!("{} input bytes decoded from no token", self.expected.len()),
+            ));
+        }
+    }
+
+    /// The error that stops the pipeline once the roundtrip has diverged.
+    fn diverged() -> io::Error {
+        io::Error::other("Roundtrip diverged")
+    }
+}
+
+/// Receives the mirrored input of each chunk.
+struct ExpectedWriter(Arc<Mutex<Roundtrip>>);
+
+impl AsyncWrite for ExpectedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut roundtrip = self.0.lock().expect("roundtrip lock poisoned");
+        if roundtrip.mismatch.is_some() {
+            return Poll::Ready(Err(Roundtrip::diverged()));
+        }
+        roundtrip.expected.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Receives each chunk's tokens and matches them against the mirrored input.
+struct DecodedWriter(Arc<Mutex<Roundtrip>>);
+
+impl AsyncWrite for DecodedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut roundtrip = self.0.lock().expect("roundtrip lock poisoned");
+        if roundtrip.mismatch.is_none() {
+            roundtrip.compare(buf);
+        }
+        match roundtrip.mismatch {
+            Some(_) => Poll::Ready(Err(Roundtrip::diverged())),
+            None => Poll::Ready(Ok(buf.len())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BpeMerges;
+
+    #[tokio::test]
+    async fn test_verifies_roundtrip_and_reports_first_divergence() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a.txt"), "abcab".repeat(100_000))?;
+        std::fs::write(dir.path().join("b.txt"), "hello")?;
+        let mut merges = BpeMerges::default();
+        merges.insert((u16::from(b'a'), u16::from(b'b')), 256);
+        let config = CoreConfig {
+            input: Some(dir.path().to_path_buf()),
+            cli_chunk_size: Some(256 << 10),
+            bpe_data: Some(Arc::new(merges)),
+            ..CoreConfig::default()
+        };
+        let report = verify(&config).await?;
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.documents, 2);
+        assert_eq!(report.bytes_verified, 500_005);
+        // The chunk seam splits one "ab" pair, which costs a token but no bytes.
+        assert_eq!(report.tokens, 300_006);
+
+        let config = CoreConfig {
+            passthrough_mode: true,
+            bpe_data: None,
+            ..config
+        };
+        assert!(verify(&config).await?.passed());
+
+        // Every divergence is reported where it starts.
+        let mut roundtrip = Roundtrip::new(Some(Arc::new(TokenDecoder::new(None, 0))));
+        roundtrip.expected.extend_from_slice(b"abc");
+        roundtrip.compare(&[0, b'a', 0, b'x']);
+        assert_eq!(
+            roundtrip.mismatch,
+            Some((1, "expected 0x62, decoded 0x78".to_string()))
+        );
+        let mut roundtrip = Roundtrip::new(None);
+        roundtrip.expected.extend_from_slice(b"ab");
+        roundtrip.compare(b"a");
+        assert_eq!(roundtrip.mismatch, None);
+        roundtrip.finish();
+        assert_eq!(
+            roundtrip.mismatch,
+            Some((1, "1 input bytes decoded from no token".to_string()))
+        );
+
+        let config = CoreConfig {
+            output: Some(dir.path().join("out.bin")),
+            ..config
+        };
+        let err = verify(&config).await.unwrap_err();
+        assert!(matches!(err, BltError::Config(_)), "{err}");
+        Ok(())
+    }
+}
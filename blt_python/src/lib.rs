@@ -1,5 +1,5 @@
 #![allow(clippy::useless_conversion)]
-use blt_core::{run_tokenizer, ContentType, CoreConfig};
+use blt_core::{run_tokenizer, BltError, ContentType, CoreConfig};
 use pyo3::prelude::*;
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -129,9 +129,11 @@ impl ByteTokenizer {
                     self.chunk_size.clone(),
                     self.memory_cap,
                     false, // Don't use passthrough mode in Python API
-                )?;
+                )
+                .map_err(to_py_err)?
+                .with_overwrite(true);
 
-                run_tokenizer(config).await?;
+                run_tokenizer(config).await.map_err(to_py_err)?;
 
                 Some(temp_file)
             } else {
@@ -151,9 +153,11 @@ impl ByteTokenizer {
                     self.chunk_size.clone(),
                     self.memory_cap,
                     false, // Don't use passthrough mode in Python API
-                )?;
+                )
+                .map_err(to_py_err)?
+                .with_overwrite(true);
 
-                run_tokenizer(config).await?;
+                run_tokenizer(config).await.map_err(to_py_err)?;
 
                 None
             };
@@ -194,7 +198,18 @@ impl ByteTokenizer {
 #[pyfunction]
 #[allow(clippy::useless_conversion)]
 pub fn load_bpe_merges(path: &str) -> PyResult<HashMap<(u8, u8), u16>> {
-    blt_core::load_bpe_merges(&PathBuf::from(path)).map_err(|e| e.into())
+    blt_core::load_bpe_merges(&PathBuf::from(path)).map_err(to_py_err)
+}
+
+/// Raises invalid configurations and merges files as `ValueError`, and everything else as the
+/// `OSError` matching its I/O error kind.
+fn to_py_err(error: BltError) -> PyErr {
+    match error {
+        BltError::Config(_) | BltError::MergesParse { .. } => {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(error.to_string())
+        }
+        error => std::io::Error::from(error).into(),
+    }
 }
 
 /// Get the version of the BLT library.
@@ -0,0 +1,110 @@
+//! Options read from a `--config` file.
+//!
+//! A config file holds the options of a tokenization run as a TOML or YAML table keyed by their
+//! long names, with dashes or underscores (`output-dir` or `output_dir`). Each entry becomes the
+//! option it names, placed before the command line, so that options given on the command line
+//! override the file: a checked-in file records how a dataset is tokenized, and one-off changes
+//! need no edits to it. Flags take `true` or `false`, and options accepted more than once take
+//! a list.
+
+use crate::CliArgs;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command, CommandFactory, FromArgMatches};
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Parses `args`, a program name followed by its arguments, merging in the options of the file
+/// given with `--config`.
+pub(crate) fn parse_cli_args(args: Vec<OsString>) -> Result<CliArgs, clap::Error> {
+    let mut command = CliArgs::command();
+    let matches = command.try_get_matches_from_mut(&args)?;
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return CliArgs::from_arg_matches(&matches);
+    };
+    let file_args = read_options(path)
+        .and_then(|options| to_args(&command, &matches, options))
+        .map_err(|e| command.error(ErrorKind::InvalidValue, e))?;
+
+    let mut args = args.into_iter();
+    let merged: Vec<OsString> = args
+        .next()
+        .into_iter()
+        .chain(file_args)
+        .chain(args)
+        .collect();
+    let matches = command.try_get_matches_from_mut(merged)?;
+    CliArgs::from_arg_matches(&matches)
+}
+
+/// Reads the table of options in the file at `path`, parsed as TOML or YAML by its extension.
+fn read_options(path: &Path) -> Result<Map<String, Value>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {e}", path.display()))?;
+    let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&contents).map_err(|e| e.to_string()),
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+        _ => Err("expected a .toml, .yaml, or .yml file".to_string()),
+    };
+    match parsed.map_err(|e| format!("Invalid config file {}: {e}", path.display()))? {
+        Value::Object(options) => Ok(options),
+        // An empty YAML file.
+        Value::Null => Ok(Map::new()),
+        _ => Err(format!(
+            "Invalid config file {}: expected a table of options",
+            path.display()
+        )),
+    }
+}
+
+/// Turns `options` into arguments, leaving out those given on the command line in `matches`.
+fn to_args(
+    command: &Command,
+    matches: &ArgMatches,
+    options: Map<String, Value>,
+) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && !arg.is_hide_set())
+            .filter(|arg| arg.get_id() != "config")
+            .ok_or_else(|| format!("Unknown option '{key}' in the config file"))?;
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let flag = format!("--{long}");
+        let num_args = arg.get_num_args().unwrap_or_default();
+        match value {
+            Value::Null => {}
+            Value::Bool(set) if !num_args.takes_values() => {
+                if set {
+                    args.push(flag.into());
+                }
+            }
+            // An option whose value may be left out, such as `--index`.
+            Value::Bool(true) if num_args.min_values() == 0 => args.push(flag.into()),
+            Value::Array(values) => {
+                for value in values {
+                    args.push(format!("{flag}={}", scalar(&key, &value)?).into());
+                }
+            }
+            value => args.push(format!("{flag}={}", scalar(&key, &value)?).into()),
+        }
+    }
+    Ok(args)
+}
+
+/// Renders a string, number, or boolean as it would be written on the command line.
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(format!(
+            "Option '{key}' in the config file must be a string, number, or boolean"
+        )),
+    }
+}
@@ -1,21 +1,270 @@
-use blt_core::{ContentType as CoreContentType, CoreConfig};
+use blt_core::config_loader;
+use blt_core::content_types::ContentTypeRegistry;
+use blt_core::doc_ids::DocumentIdSource;
+use blt_core::entropy::EntropyMarking;
+use blt_core::hashing::HashAlgorithm;
+use blt_core::index;
+use blt_core::interrupt::{PartialOutput, EXIT_INTERRUPTED};
+use blt_core::io_handler::{
+    collect_input_files, ByteRange, Compression, DecodeErrorPolicy, Encoding, EncryptionKey,
+    HtmlMode, InputFilter, NumberBucketing, NumberForm, OutputDir, RateLimit, TimestampStripping,
+    Transcoding, WhitespaceNormalization,
+};
+use blt_core::pacing::{
+    BatteryHook, CommandHook, FixedDutyCycle, MinDutyCycle, Pacing, PacingHook,
+};
+use blt_core::pipeline::ThreadScaling;
+use blt_core::postprocess::{
+    LogRecordPolicy, PackingPolicy, PadToPolicy, ShiftedPairsPolicy, SpanCorruptionPolicy,
+    SpanLength, TruncationMode, TruncationPolicy,
+};
+use blt_core::priority::{IoPriority, Priority};
+use blt_core::progress::{Progress, ProgressRenderer, DEFAULT_REDRAW_INTERVAL};
+use blt_core::records::RecordLayout;
+use blt_core::sandbox::{self, SandboxConfig};
+use blt_core::special_tokens;
+use blt_core::stats::{self, AnomalyPolicy, QuarantineMode};
+use blt_core::{
+    BltError, ContentType as CoreContentType, ContentTypeFraming, CoreConfig, ErrorPolicy,
+    MarkerScope, OutputFormat, StreamMarkers,
+};
 use clap::Parser;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal};
+#[cfg(feature = "server")]
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod config_file;
 
 // Default memory capacity percentage is now handled in blt_core
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None, name = "blt")]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    name = "blt",
+    args_conflicts_with_subcommands = true
+)]
 struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        allow_hyphen_values = true,
+        help = "Run blt's threads at niceness N (-20 highest to 19 lowest)"
+    )]
+    nice: Option<i32>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "I/O scheduling class for blt's threads (Linux)"
+    )]
+    ionice: Option<CliIoClass>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "LEVEL",
+        default_value_t = 4,
+        help = "Level for --ionice best-effort, 0 (highest) to 7 (lowest)"
+    )]
+    ionice_level: u8,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Run blt's threads in the background QoS class (macOS)"
+    )]
+    background: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value = "text",
+        help = "Write log lines as human-readable text or as JSON objects for log aggregation"
+    )]
+    log_format: CliLogFormat,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read options from a TOML or YAML file keyed by their long names; options given on the command line override it"
+    )]
+    config: Option<PathBuf>,
+
     #[arg(
         short,
         long,
         value_name = "FILE",
-        help = "Input file path (or - for stdin)"
+        help = "Input file or directory path (or - for stdin)"
     )]
     input: Option<PathBuf>,
 
+    #[arg(
+        long,
+        value_name = "BYTES",
+        conflicts_with = "byte_range",
+        help = "Skip this many leading input bytes"
+    )]
+    skip: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        conflicts_with = "byte_range",
+        help = "Tokenize at most this many input bytes (after --skip)"
+    )]
+    limit: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "START..END",
+        help = "Tokenize only input bytes START up to END (either bound may be omitted)"
+    )]
+    byte_range: Option<ByteRange>,
+
+    #[arg(
+        long,
+        requires = "input",
+        conflicts_with_all = ["byte_range", "skip", "limit", "deterministic"],
+        help = "Keep reading the input file as it grows, like tail -f, writing tokens as they are produced, until SIGTERM or Ctrl-C"
+    )]
+    follow: bool,
+
+    #[arg(
+        long,
+        help = "Read input files with async reads instead of memory-mapping them, e.g. on NFS where page faults stall"
+    )]
+    no_mmap: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "no_mmap",
+        help = "Release the pages of memory-mapped inputs as soon as they are tokenized (MADV_DONTNEED), keeping huge scans from filling memory"
+    )]
+    mmap_dontneed: bool,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        conflicts_with = "no_mmap",
+        value_parser = blt_core::utils::parse_chunk_size_str,
+        help = "Touch the pages of memory-mapped inputs up to SIZE (e.g. 256MB) ahead of the workers on a background thread, hiding page-fault latency on cold files such as network block devices"
+    )]
+    mmap_prefault: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Read streamed input files and write output files through io_uring on Linux (requires the io-uring feature)"
+    )]
+    io_uring: bool,
+
+    #[arg(
+        long,
+        help = "Write output files with O_DIRECT, bypassing the page cache (Linux only)"
+    )]
+    direct_io: bool,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 250,
+        requires = "follow",
+        help = "Milliseconds between checks for appended data with --follow"
+    )]
+    follow_interval: u64,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ACTION",
+        default_value = "remove",
+        conflicts_with = "follow",
+        help = "What to do with the incomplete output when SIGINT or SIGTERM stops the run: remove it, keep it, or mark it by renaming it to OUTPUT.partial"
+    )]
+    on_interrupt: CliPartialOutput,
+
+    #[arg(
+        long,
+        value_name = "ENCODING",
+        num_args = 0..=1,
+        default_missing_value = "auto",
+        value_parser = parse_transcode,
+        help = "Transcode input to UTF-8 from ENCODING (utf-8, utf-16le, utf-16be, latin-1, windows-1252), or from the encoding detected per document with auto or no value"
+    )]
+    transcode: Option<SourceEncoding>,
+
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "replace",
+        requires = "transcode",
+        help = "What to do with undecodable bytes when transcoding: replace, skip, or fail"
+    )]
+    decode_errors: DecodeErrorPolicy,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        default_missing_value = "tags",
+        help = "Tokenize the text of HTML inputs: tags drops markup, scripts, and styles (the default with no value); content also drops the head, navigation, headers, footers, sidebars, and forms"
+    )]
+    strip_html: Option<HtmlMode>,
+
+    #[arg(
+        long,
+        help = "Collapse runs of spaces and tabs to one space, drop spaces before line breaks, and keep at most one blank line"
+    )]
+    collapse_whitespace: bool,
+
+    #[arg(long, help = "Drop whitespace at the start and end of each document")]
+    trim_whitespace: bool,
+
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Replace tabs with spaces up to the next multiple of WIDTH columns"
+    )]
+    expand_tabs: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "REGEX",
+        num_args = 0..=1,
+        default_missing_value = TimestampStripping::DEFAULT_PATTERN,
+        help = "Remove the first match of REGEX, and the spaces after it, from each line; without REGEX, a leading ISO 8601, syslog, or epoch timestamp"
+    )]
+    strip_timestamps: Option<TimestampStripping>,
+
+    #[arg(
+        long,
+        value_name = "FORM",
+        help = "Rewrite each run of digits: placeholder[:TEXT] (default <num>), shape (every digit becomes 0), or magnitude (<num:N> for N digits)"
+    )]
+    numbers: Option<NumberForm>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        requires = "numbers",
+        help = "Keep digit runs shorter than N as they are"
+    )]
+    numbers_min_digits: u16,
+
     #[arg(
         short,
         long,
@@ -24,6 +273,32 @@ struct CliArgs {
     )]
     output: Option<PathBuf>,
 
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "output",
+        help = "Write each input document to its own file under DIR, mirroring its relative path"
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[arg(short, long, help = "Overwrite output files that already exist")]
+    force: bool,
+
+    #[arg(
+        long,
+        value_name = "EXT",
+        requires = "output_dir",
+        help = "Extension of per-document outputs (default: bin, arrow, or parquet by --format)"
+    )]
+    output_extension: Option<String>,
+
+    #[arg(
+        long,
+        requires_all = ["output_dir", "manifest"],
+        help = "Store outputs under --output-dir by the hash of their bytes, deduplicating identical ones"
+    )]
+    content_addressed: bool,
+
     #[arg(
         long,
         value_name = "FILE",
@@ -34,8 +309,83 @@ struct CliArgs {
     #[arg(long, help = "Use passthrough mode (copy file without tokenization)")]
     passthrough: bool,
 
-    #[arg(long, value_enum, help = "Prepend content-type token")]
-    r#type: Option<CliContentType>,
+    #[arg(
+        long,
+        conflicts_with = "passthrough",
+        help = "Fail if tokenization produces a token in the reserved special-token range"
+    )]
+    strict_specials: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value = "fail",
+        help = "What to do when a chunk fails to tokenize: fail the run, or skip the chunk with a warning and list it in the --stats and --report output"
+    )]
+    on_error: CliErrorPolicy,
+
+    #[arg(
+        long,
+        value_name = "BITS",
+        num_args = 0..=1,
+        default_missing_value = "7.5",
+        conflicts_with = "passthrough",
+        help = "Write a compressed-region or plain-region token before each run of chunks whose byte entropy is at least BITS per byte, or below it"
+    )]
+    entropy_markers: Option<EntropyMarking>,
+
+    #[arg(
+        long,
+        value_name = "OFFSET",
+        default_value = "0",
+        value_parser = clap::builder::TypedValueParser::map(
+            clap::builder::PossibleValuesParser::new(["0", "256"]),
+            |offset| offset.parse::<u16>().expect("offset is a listed value"),
+        ),
+        help = "Add OFFSET to byte tokens (256 moves them to 256-511); recorded in the header"
+    )]
+    byte_offset: u16,
+
+    #[arg(
+        long,
+        value_name = "ID",
+        default_value_t = config_loader::DEFAULT_MERGE_ID_BASE,
+        requires = "merges",
+        help = "Assign merge IDs from ID upwards in file order (512 follows --byte-offset 256)"
+    )]
+    merge_id_base: u16,
+
+    #[arg(
+        long,
+        value_name = "TYPE",
+        help = "Prepend content-type token (text, audio, bin, video, or a --content-types name)"
+    )]
+    r#type: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "stream",
+        requires = "type",
+        help = "Write the --type token once per output (stream), per input document (document), or every N tokens (N)"
+    )]
+    type_framing: ContentTypeFraming,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "File registering custom content types, one `name token` pair per line"
+    )]
+    content_types: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "LAYOUT",
+        requires = "type",
+        help = "Align chunks and tokens to length-prefixed records, e.g. tag=1,len=u32be[,inclusive] (needs --type bin)"
+    )]
+    records: Option<RecordLayout>,
 
     #[arg(
         long,
@@ -44,6 +394,71 @@ struct CliArgs {
     )]
     threads: Option<usize>,
 
+    #[arg(
+        long,
+        value_name = "NUM",
+        help = "Adapt concurrency during the run, shrinking to no fewer than NUM chunk tasks (default: 1 with --max-threads)"
+    )]
+    min_threads: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "NUM",
+        help = "Adapt concurrency during the run, growing to at most NUM chunk tasks (default: --threads with --min-threads)"
+    )]
+    max_threads: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        help = "Idle workers so they spend at most FRACTION (0-1) of the time processing chunks"
+    )]
+    duty_cycle: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        help = "Duty cycle used while the machine runs on battery power (Linux)"
+    )]
+    battery_duty_cycle: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        help = "Shell command printing the current duty cycle (0-1), e.g. from grid carbon intensity"
+    )]
+    pace_command: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 30,
+        help = "Seconds between duty-cycle checks for --battery-duty-cycle and --pace-command"
+    )]
+    pace_interval: u64,
+
+    #[arg(
+        long,
+        help = "Process chunks in sandboxed worker processes (seccomp on Linux), trading throughput for isolation"
+    )]
+    sandbox: bool,
+
+    #[arg(
+        long,
+        value_name = "NUM",
+        requires = "sandbox",
+        help = "Maximum number of sandboxed worker processes (default: --threads)"
+    )]
+    sandbox_workers: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "SOCKET",
+        conflicts_with_all = ["merges", "passthrough", "sandbox"],
+        help = "Tokenize through the `blt daemon` listening on SOCKET, using its vocabulary"
+    )]
+    daemon: Option<PathBuf>,
+
     #[arg(
         long,
         value_name = "PERCENT",
@@ -57,50 +472,1751 @@ struct CliArgs {
         help = "Min/Max chunk size (e.g. 4MB, 256KB)."
     )]
     chunksize: Option<String>,
-}
 
-#[derive(clap::ValueEnum, Clone, Debug)]
-enum CliContentType {
-    Text,
-    Audio,
-    Bin,
-    Video,
-}
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also write the original input bytes to FILE (e.g. /dev/fd/3) in lockstep"
+    )]
+    tee_original: Option<PathBuf>,
 
-impl From<CliContentType> for CoreContentType {
-    fn from(cli_type: CliContentType) -> Self {
-        match cli_type {
-            CliContentType::Text => CoreContentType::Text,
-            CliContentType::Audio => CoreContentType::Audio,
-            CliContentType::Bin => CoreContentType::Bin,
-            CliContentType::Video => CoreContentType::Video,
-        }
-    }
-}
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write a JSON Lines manifest with per-document content hashes"
+    )]
+    manifest: Option<PathBuf>,
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    #[arg(
+        long,
+        value_name = "ALGO",
+        default_value = "sha256",
+        help = "Content hash algorithm for the manifest, --output-hash, and content-addressed outputs (sha256, blake3, or xxh3 with an optional seed as xxh3@SEED)"
+    )]
+    hash: HashAlgorithm,
 
-    let cli_args = CliArgs::parse();
+    #[arg(
+        long,
+        value_name = "ID",
+        help = "Tag the documents this run tokenizes with snapshot ID in the manifest"
+    )]
+    snapshot_id: Option<String>,
 
-    let core_config = CoreConfig::new_from_cli(
-        cli_args.input,
-        cli_args.output,
-        cli_args.merges,
-        cli_args.r#type.map(CoreContentType::from),
-        cli_args.threads,
-        cli_args.chunksize,
-        cli_args.memcap,
-        cli_args.passthrough,
-    )?;
+    #[arg(
+        long,
+        value_name = "MANIFEST",
+        requires = "manifest",
+        help = "Tokenize only documents new or changed since the snapshot MANIFEST describes, carrying the entries of unchanged ones over into --manifest"
+    )]
+    since: Option<PathBuf>,
 
-    if let Err(e) = blt_core::run_tokenizer(core_config).await {
-        eprintln!("Error running tokenizer: {e}");
-        std::process::exit(1);
-    }
+    #[arg(
+        long,
+        value_name = "SOURCE",
+        requires = "manifest",
+        help = "Record an id for each document in the manifest, taken from its path, its content-hash, or json-field:NAME of its first line, instead of only its ordinal"
+    )]
+    doc_id: Option<DocumentIdSource>,
 
-    Ok(())
+    #[arg(
+        long,
+        help = "Pin chunking so repeated runs over the same input write bit-identical output"
+    )]
+    deterministic: bool,
+
+    #[arg(
+        long,
+        help = "Print a hash of everything written (uncompressed, header included) using --hash"
+    )]
+    output_hash: bool,
+
+    #[arg(
+        long,
+        value_name = "HASH",
+        help = "Fail unless the output hashes to HASH, as printed by --output-hash"
+    )]
+    verify_hash: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write a JSON run report with byte counts, peak RSS, CPU time per stage, and I/O bytes"
+    )]
+    report: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Print a one-line JSON run summary (bytes, tokens, compression ratio, chunks, threads, wall time, stage timings) to stderr on completion"
+    )]
+    stats: bool,
+
+    #[arg(
+        long,
+        help = "Validate the options, load the merges, plan the chunks, and estimate the output size from a sample of the input, without writing anything"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        help = "Show bytes processed, throughput, and ETA on stderr when it is a terminal"
+    )]
+    progress: bool,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Log bytes read, tokens written, throughput, and chunks in flight at INFO every SECS seconds"
+    )]
+    heartbeat: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "With a directory --input, only process files matching GLOB (repeatable)"
+    )]
+    include: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "With a directory --input, skip files matching GLOB (repeatable)"
+    )]
+    exclude: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        default_value_t = special_tokens::SEPARATOR,
+        help = "Token inserted between concatenated input documents"
+    )]
+    doc_separator: u16,
+
+    #[arg(
+        long,
+        conflicts_with = "doc_separator",
+        help = "Concatenate input documents without a separator token"
+    )]
+    no_doc_separator: bool,
+
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        help = "Begin-of-stream token written before the output"
+    )]
+    bos: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        help = "End-of-stream token written after the output"
+    )]
+    eos: Option<u16>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "run",
+        help = "Write --bos/--eos around each output (run) or each input document (document)"
+    )]
+    marker_scope: CliMarkerScope,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input", "archive"],
+        help = "Read input paths from FILE (one per line) and tokenize them in list order"
+    )]
+    filelist: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "input",
+        help = "Treat --input as a tar, tar.gz, or zip archive and tokenize each member as a document"
+    )]
+    archive: bool,
+
+    #[arg(
+        long,
+        requires_all = ["input", "type"],
+        conflicts_with_all = ["archive", "follow"],
+        help = "Demux an MP4 --input (with --type video) into container metadata and per-track streams, each tokenized as a tagged document"
+    )]
+    demux: bool,
+
+    #[arg(
+        long,
+        value_name = "CODEC[:LEVEL]",
+        help = "Compress the output stream with zstd or gzip, e.g. zstd:19"
+    )]
+    compress: Option<Compression>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Encrypt outputs with AES-256-GCM using the key in FILE (64 hex digits or 32 raw bytes)"
+    )]
+    encrypt_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Prepend a self-describing header (magic, version, token width, strategy, merges hash)"
+    )]
+    header: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "raw",
+        help = "Output format (arrow/parquet require the matching cargo feature)"
+    )]
+    format: CliOutputFormat,
+
+    #[arg(
+        long,
+        value_name = "ROWS",
+        default_value_t = OutputFormat::DEFAULT_ROW_GROUP_SIZE,
+        help = "Documents per row group for --format parquet"
+    )]
+    row_group_size: usize,
+
+    #[arg(
+        long,
+        help = "Treat each line as a log record: drop blank lines, prefix a severity token, and end it with a record separator token"
+    )]
+    log_records: bool,
+
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        default_value_t = special_tokens::RECORD_SEPARATOR,
+        requires = "log_records",
+        help = "Token ending each log record"
+    )]
+    record_separator: u16,
+
+    #[arg(
+        long,
+        requires = "log_records",
+        help = "Do not prefix log records with a severity token"
+    )]
+    no_severity_tags: bool,
+
+    #[arg(
+        long,
+        value_name = "MAX_TOKENS",
+        help = "Truncate newline-delimited documents longer than MAX_TOKENS tokens"
+    )]
+    truncate: Option<usize>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "head",
+        requires = "truncate",
+        help = "Part of an over-long document to keep"
+    )]
+    truncate_mode: CliTruncationMode,
+
+    #[arg(
+        long,
+        value_name = "RATIO",
+        default_value_t = 0.5,
+        requires = "truncate",
+        help = "Fraction of the budget kept from the head with --truncate-mode head-tail"
+    )]
+    truncate_head_ratio: f64,
+
+    #[arg(
+        long,
+        value_name = "DENSITY",
+        help = "Emit T5-style span-corrupted (input, target) pairs masking DENSITY of each document"
+    )]
+    span_corruption: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "MEAN",
+        default_value_t = 3.0,
+        requires = "span_corruption",
+        help = "Mean masked span length"
+    )]
+    span_length: f64,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "geometric",
+        requires = "span_corruption",
+        help = "Distribution of masked span lengths"
+    )]
+    span_distribution: CliSpanDistribution,
+
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        default_value_t = special_tokens::MASK,
+        requires = "span_corruption",
+        help = "Token replacing each masked span"
+    )]
+    mask_token: u16,
+
+    #[arg(
+        long,
+        value_name = "SEED",
+        default_value_t = 0,
+        requires = "span_corruption",
+        help = "Seed for span placement"
+    )]
+    span_seed: u64,
+
+    #[arg(
+        long,
+        value_name = "SEQ_LEN",
+        help = "Pack newline-delimited documents into padded sequences of SEQ_LEN tokens"
+    )]
+    pack: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "TOKENS",
+        default_value_t = 0,
+        requires = "pack",
+        help = "Let documents shorter than TOKENS share packed sequences"
+    )]
+    pack_short_threshold: usize,
+
+    #[arg(
+        long,
+        help = "Emit next-token (input, label, mask) arrays for every sequence; combine with --pack"
+    )]
+    shifted_pairs: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Pad the final token count of each output to a multiple of N"
+    )]
+    pad_to: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Stop after writing N tokens across all outputs, dropping the rest"
+    )]
+    max_tokens: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "RATE[/BURST]",
+        help = "Write at most RATE tokens per second across all outputs, in bursts of up to BURST tokens (default: RATE)"
+    )]
+    token_rate: Option<RateLimit>,
+
+    #[arg(
+        long,
+        value_name = "ID",
+        default_value_t = special_tokens::PAD,
+        requires = "pad_to",
+        help = "Token used by --pad-to"
+    )]
+    pad_token: u16,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        help = "Write a sidecar index of document offsets (defaults to <OUTPUT>.idx)"
+    )]
+    index: Option<Option<PathBuf>>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Run blt as an HTTP server exposing tokenize, decode, health, and vocab endpoints, and
+    /// optionally a directory of token shards.
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+    /// Run blt as a daemon tokenizing requests from `--daemon` clients over a Unix socket.
+    #[cfg(feature = "daemon")]
+    Daemon(DaemonArgs),
+    /// Report statistics about how a vocabulary performs on a corpus.
+    Stats(StatsArgs),
+    /// Compare two merges files and estimate how much re-tokenization would change a sample.
+    VocabDiff(VocabDiffArgs),
+    /// Re-tokenize a sample with an old and a new vocabulary and gate the rollout on the deltas.
+    Canary(CanaryArgs),
+    /// Tokenize an input and print the token count of every document, without writing them.
+    Count(CountArgs),
+    /// Tokenize an input, decode the tokens, and check they reproduce it byte for byte.
+    Verify(VerifyArgs),
+    /// Sweep thread counts and chunk sizes on a sample and recommend the fastest configuration.
+    Bench(BenchArgs),
+    /// Check the outputs recorded in a run's manifest and re-tokenize only the damaged ones.
+    Repair(RepairArgs),
+    /// Rewrite a directory of indexed token shards into evenly sized shards.
+    Rebalance(RebalanceArgs),
+    /// Summarize the corpus a run's manifest describes as JSON, and optionally as an HTML page.
+    Report(ReportArgs),
+    /// Maintain run manifests.
+    Manifest(ManifestArgs),
+    /// Check a merges file or snapshot for duplicate pairs, undefined and reserved tokens.
+    Inspect(InspectArgs),
+    /// Write a vocabulary snapshot that loads faster than its merges file wherever one is accepted.
+    Snapshot(SnapshotArgs),
+    /// Decrypt an output written with --encrypt-key.
+    #[cfg(feature = "encryption")]
+    Decrypt(DecryptArgs),
+    /// Serve chunk processing for `--sandbox` over standard input and output.
+    #[command(hide = true)]
+    SandboxWorker,
+}
+
+#[derive(clap::Args, Debug)]
+struct CountArgs {
+    #[arg(long, help = "Print the counts as JSON")]
+    json: bool,
+
+    #[arg(long, help = "Only print the total")]
+    total: bool,
+
+    #[arg(
+        last = true,
+        required = true,
+        value_name = "OPTIONS",
+        help = "Options of the run to count, such as --input and --merges, without an output"
+    )]
+    options: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(
+        last = true,
+        required = true,
+        value_name = "OPTIONS",
+        help = "Options of the run to verify, such as --input and --merges, without an output"
+    )]
+    options: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Benchmark on the start of FILE instead of generated text"
+    )]
+    sample: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "64MB",
+        value_parser = blt_core::utils::parse_chunk_size_str,
+        help = "Bytes of generated text, or the most bytes read from --sample (e.g. 64MB)"
+    )]
+    size: usize,
+
+    #[arg(
+        long,
+        value_name = "NUM,...",
+        value_delimiter = ',',
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Thread counts to sweep (default: powers of two up to the number of cores)"
+    )]
+    threads: Vec<usize>,
+
+    #[arg(
+        long,
+        value_name = "SIZE,...",
+        value_delimiter = ',',
+        value_parser = blt_core::utils::parse_chunk_size_str,
+        help = "Chunk sizes to sweep (default: 256KB,1MB,4MB,16MB)"
+    )]
+    chunk_sizes: Vec<usize>,
+
+    #[arg(long, help = "Print the results as JSON")]
+    json: bool,
+
+    #[arg(
+        last = true,
+        value_name = "OPTIONS",
+        help = "Options of the run to benchmark, such as --merges"
+    )]
+    options: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RepairArgs {
+    #[arg(long, help = "Only report damaged outputs, failing if there are any")]
+    check: bool,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(
+        last = true,
+        required = true,
+        value_name = "OPTIONS",
+        help = "Options of the run that wrote the outputs, including --output-dir and --manifest"
+    )]
+    options: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReportArgs {
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also write the report as a self-contained HTML page to FILE"
+    )]
+    html: Option<PathBuf>,
+
+    #[arg(
+        value_name = "MANIFEST",
+        help = "Manifest written by a run with --manifest"
+    )]
+    manifest: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct ManifestArgs {
+    #[command(subcommand)]
+    command: ManifestCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ManifestCommand {
+    /// Migrate a manifest written by an older blt to the current manifest schema.
+    Upgrade(ManifestUpgradeArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ManifestUpgradeArgs {
+    #[arg(
+        long,
+        short,
+        value_name = "FILE",
+        help = "Where to write the upgraded manifest (default: replace MANIFEST)"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(
+        value_name = "MANIFEST",
+        help = "Manifest written by a run with --manifest"
+    )]
+    manifest: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct InspectArgs {
+    #[arg(
+        long,
+        value_name = "ID",
+        default_value_t = config_loader::DEFAULT_MERGE_ID_BASE,
+        help = "Number text merges from ID upwards, as --merge-id-base does"
+    )]
+    merge_id_base: u16,
+
+    #[arg(
+        long,
+        help = "Also print every merge with the bytes its token stands for"
+    )]
+    table: bool,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(long, help = "Fail if the file has problems or blt rejects it")]
+    check: bool,
+
+    #[arg(value_name = "MERGES", help = "BPE merges file or vocabulary snapshot")]
+    merges: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct SnapshotArgs {
+    #[arg(value_name = "MERGES", help = "BPE merges file to snapshot")]
+    merges: PathBuf,
+
+    #[arg(
+        long,
+        short,
+        value_name = "FILE",
+        help = "Where to write the snapshot, e.g. merges.snap"
+    )]
+    output: PathBuf,
+}
+
+#[cfg(feature = "encryption")]
+#[derive(clap::Args, Debug)]
+struct DecryptArgs {
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Key file the output was encrypted with"
+    )]
+    key: PathBuf,
+
+    #[arg(
+        long,
+        short,
+        value_name = "FILE",
+        help = "Where to write the decrypted bytes (stdout if omitted)"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(value_name = "INPUT", help = "Encrypted output file")]
+    input: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct RebalanceArgs {
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_target_size,
+        help = "Approximate size of each new shard (e.g. 512MB, 64KB)"
+    )]
+    target_size: u64,
+
+    #[arg(
+        long = "manifest",
+        value_name = "FILE",
+        help = "Refuse to rebalance if this manifest records a shard as an output (repeatable)"
+    )]
+    manifests: Vec<PathBuf>,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(
+        value_name = "DIR",
+        help = "Directory of .bin shards, each with its .idx index from --index"
+    )]
+    dir: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct CanaryArgs {
+    #[arg(long, value_name = "FILE", help = "Current BPE merges file")]
+    old: PathBuf,
+
+    #[arg(long, value_name = "FILE", help = "Candidate BPE merges file")]
+    new: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        help = "Fail if any document's segmentation divergence exceeds FRACTION (e.g. 0.2)"
+    )]
+    max_divergence: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        help = "Fail if any document's token count changes by more than FRACTION (e.g. 0.05)"
+    )]
+    max_token_delta: Option<f64>,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(
+        value_name = "INPUT",
+        required = true,
+        help = "Sample files, or directories whose files are all sampled"
+    )]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct VocabDiffArgs {
+    #[arg(value_name = "OLD", help = "Current BPE merges file")]
+    old: PathBuf,
+
+    #[arg(value_name = "NEW", help = "Candidate BPE merges file")]
+    new: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 1..,
+        help = "Sample corpus files to re-tokenize with both vocabularies"
+    )]
+    sample: Vec<PathBuf>,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    #[command(subcommand)]
+    command: StatsCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum StatsCommand {
+    /// Report the fraction of bytes merged vs emitted as raw byte tokens, per content type.
+    Coverage(CoverageArgs),
+    /// Rank merges by usage and write pruned merges files for target vocabulary sizes.
+    Prune(PruneArgs),
+    /// Report each input's byte histogram and flag anomalies such as binary data in text.
+    Bytes(BytesArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct BytesArgs {
+    #[arg(
+        long,
+        value_name = "TYPE",
+        help = "Content type of every input (default: guessed from each file extension)"
+    )]
+    r#type: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "File registering custom content types, one `name token` pair per line"
+    )]
+    content_types: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value_t = AnomalyPolicy::default().max_control_fraction,
+        help = "Flag text inputs with a larger fraction of control bytes"
+    )]
+    max_control: f64,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value_t = AnomalyPolicy::default().max_invalid_utf8_fraction,
+        help = "Flag text inputs with a larger fraction of bytes that are not valid UTF-8"
+    )]
+    max_invalid_utf8: f64,
+
+    #[arg(
+        long,
+        value_name = "BITS",
+        default_value_t = AnomalyPolicy::default().max_text_entropy,
+        help = "Flag text inputs whose entropy exceeds BITS per byte"
+    )]
+    max_entropy: f64,
+
+    #[arg(long, value_name = "DIR", help = "Move flagged inputs into DIR")]
+    quarantine: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "move",
+        requires = "quarantine",
+        help = "How flagged inputs are quarantined: move or copy"
+    )]
+    quarantine_mode: QuarantineMode,
+
+    #[arg(long, help = "Print the report, including full histograms, as JSON")]
+    json: bool,
+
+    #[arg(value_name = "INPUT", required = true, help = "Input files to check")]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct PruneArgs {
+    #[arg(long, value_name = "FILE", help = "BPE merges file to prune")]
+    merges: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "SIZES",
+        value_delimiter = ',',
+        required = true,
+        help = "Target vocabulary sizes (including the 256 byte tokens), e.g. 4096,8192"
+    )]
+    vocab_sizes: Vec<usize>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = ".",
+        help = "Directory receiving merges.<SIZE>.txt files"
+    )]
+    out_dir: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 20,
+        help = "Number of most used merges to list"
+    )]
+    top: usize,
+
+    #[arg(value_name = "INPUT", required = true, help = "Sample corpus files")]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct CoverageArgs {
+    #[arg(long, value_name = "FILE", help = "BPE merges file to evaluate")]
+    merges: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "TYPE",
+        help = "Content type of every input (default: guessed from each file extension)"
+    )]
+    r#type: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "File registering custom content types, one `name token` pair per line"
+    )]
+    content_types: Option<PathBuf>,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(value_name = "INPUT", required = true, help = "Sample corpus files")]
+    inputs: Vec<PathBuf>,
+}
+
+#[cfg(feature = "server")]
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    #[arg(
+        long,
+        value_name = "ADDR",
+        default_value = "127.0.0.1:8080",
+        help = "Address to listen on"
+    )]
+    listen: SocketAddr,
+
+    #[arg(
+        long,
+        help = "Serve a single connection over stdin/stdout (inetd mode)"
+    )]
+    inetd: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "BPE merges file to tokenize and decode with, loaded once at startup"
+    )]
+    merges: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "File registering custom content types, listed by /vocab"
+    )]
+    content_types: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "File of per-client quotas, one `API_KEY REQUESTS_PER_MIN BYTES_PER_MIN` line each"
+    )]
+    quotas: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "File of accepted API keys, one per line; requests without one get 401"
+    )]
+    api_keys: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_key",
+        help = "PEM certificate chain to serve HTTPS with (requires the `tls` feature)"
+    )]
+    tls_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_cert",
+        help = "PEM private key for --tls-cert"
+    )]
+    tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Coalesce small /tokenize requests into batches tokenized together"
+    )]
+    batch: bool,
+
+    #[arg(
+        long,
+        value_name = "MICROS",
+        default_value_t = 500,
+        requires = "batch",
+        help = "Microseconds a batch waits for more requests to join it with --batch"
+    )]
+    batch_window: u64,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "4KB",
+        value_parser = blt_core::utils::parse_chunk_size_str,
+        requires = "batch",
+        help = "Largest request body batched with --batch (e.g. 4KB)"
+    )]
+    batch_max_bytes: usize,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Directory of token shards to serve read-only under /shards, by byte range, token, or document"
+    )]
+    shards: Option<PathBuf>,
+}
+
+#[cfg(feature = "daemon")]
+#[derive(clap::Args, Debug)]
+struct DaemonArgs {
+    #[arg(long, value_name = "PATH", help = "Unix socket to listen on")]
+    socket: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "BPE merges file to tokenize and decode with, loaded once at startup"
+    )]
+    merges: Option<PathBuf>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CliLogFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CliIoClass {
+    Idle,
+    BestEffort,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CliMarkerScope {
+    Run,
+    Document,
+}
+
+impl From<CliMarkerScope> for MarkerScope {
+    fn from(scope: CliMarkerScope) -> Self {
+        match scope {
+            CliMarkerScope::Run => MarkerScope::Run,
+            CliMarkerScope::Document => MarkerScope::Document,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CliPartialOutput {
+    Remove,
+    Keep,
+    Mark,
+}
+
+impl From<CliPartialOutput> for PartialOutput {
+    fn from(action: CliPartialOutput) -> Self {
+        match action {
+            CliPartialOutput::Remove => PartialOutput::Remove,
+            CliPartialOutput::Keep => PartialOutput::Keep,
+            CliPartialOutput::Mark => PartialOutput::Mark,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CliErrorPolicy {
+    Fail,
+    Skip,
+}
+
+impl From<CliErrorPolicy> for ErrorPolicy {
+    fn from(policy: CliErrorPolicy) -> Self {
+        match policy {
+            CliErrorPolicy::Fail => ErrorPolicy::Fail,
+            CliErrorPolicy::Skip => ErrorPolicy::Skip,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum CliOutputFormat {
+    Raw,
+    Arrow,
+    Parquet,
+}
+
+impl CliOutputFormat {
+    fn into_core(self, row_group_size: usize) -> OutputFormat {
+        match self {
+            CliOutputFormat::Raw => OutputFormat::Raw,
+            CliOutputFormat::Arrow => OutputFormat::ArrowIpc,
+            CliOutputFormat::Parquet => OutputFormat::Parquet { row_group_size },
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CliTruncationMode {
+    Head,
+    Tail,
+    HeadTail,
+}
+
+impl CliTruncationMode {
+    fn into_core(self, head_ratio: f64) -> TruncationMode {
+        match self {
+            CliTruncationMode::Head => TruncationMode::Head,
+            CliTruncationMode::Tail => TruncationMode::Tail,
+            CliTruncationMode::HeadTail => TruncationMode::HeadTail { head_ratio },
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CliSpanDistribution {
+    Fixed,
+    Geometric,
+}
+
+impl CliSpanDistribution {
+    fn into_core(self, mean: f64) -> SpanLength {
+        match self {
+            CliSpanDistribution::Fixed => SpanLength::Fixed(mean.round().max(1.0) as usize),
+            CliSpanDistribution::Geometric => SpanLength::Geometric { mean },
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let cli_args =
+        config_file::parse_cli_args(std::env::args_os().collect()).unwrap_or_else(|e| e.exit());
+    if let Some(Command::SandboxWorker) = cli_args.command {
+        // Workers speak a binary protocol on stdout and must not start threads before the
+        // sandbox is installed, so they run without logging or an async runtime.
+        return sandbox::run_worker();
+    }
+
+    let logging = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+    match cli_args.log_format {
+        CliLogFormat::Text => logging.init(),
+        // One object per line, with the fields of the event and of the spans it is in.
+        CliLogFormat::Json => logging.json().init(),
+    }
+
+    let priority = Priority {
+        nice: cli_args.nice,
+        io: cli_args.ionice.map(|class| match class {
+            CliIoClass::Idle => IoPriority::Idle,
+            CliIoClass::BestEffort => IoPriority::BestEffort(cli_args.ionice_level),
+        }),
+        background_qos: cli_args.background,
+    };
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if !priority.is_default() {
+        priority.apply_to_current_thread()?;
+        runtime.on_thread_start(move || {
+            if let Err(e) = priority.apply_to_current_thread() {
+                tracing::warn!("Failed to set thread priority: {e}");
+            }
+        });
+    }
+    runtime.build()?.block_on(run(cli_args))
+}
+
+async fn run(cli_args: CliArgs) -> io::Result<()> {
+    match cli_args.command {
+        #[cfg(feature = "server")]
+        Some(Command::Serve(serve_args)) => run_serve(serve_args).await,
+        #[cfg(feature = "daemon")]
+        Some(Command::Daemon(daemon_args)) => run_daemon(daemon_args).await,
+        Some(Command::Stats(stats_args)) => run_stats(stats_args),
+        Some(Command::VocabDiff(args)) => run_vocab_diff(args),
+        Some(Command::Canary(args)) => run_canary(args),
+        Some(Command::Count(args)) => run_count(args).await,
+        Some(Command::Verify(args)) => run_verify(args).await,
+        Some(Command::Bench(args)) => run_bench(args).await,
+        Some(Command::Repair(args)) => run_repair(args).await,
+        Some(Command::Rebalance(args)) => run_rebalance(args).await,
+        Some(Command::Report(args)) => run_report(args).await,
+        Some(Command::Manifest(args)) => run_manifest(args).await,
+        Some(Command::Inspect(args)) => run_inspect(args),
+        Some(Command::Snapshot(args)) => run_snapshot(args),
+        #[cfg(feature = "encryption")]
+        Some(Command::Decrypt(args)) => run_decrypt(args),
+        Some(Command::SandboxWorker) => unreachable!("sandbox workers are dispatched in main"),
+        None => run_tokenize(cli_args).await,
+    }
+}
+
+async fn run_tokenize(cli_args: CliArgs) -> io::Result<()> {
+    let show_progress = cli_args.progress && io::stderr().is_terminal();
+    let stats = cli_args.stats;
+    let report_path = cli_args.report.clone();
+    let dry_run = cli_args.dry_run;
+    let core_config = tokenize_config(cli_args)?;
+    if dry_run {
+        let report = match blt_core::dry_run::dry_run(&core_config).await {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Error planning run: {e}");
+                std::process::exit(1);
+            }
+        };
+        print!("{report}");
+        if stats {
+            eprintln!("{}", serde_json::to_string(&report)?);
+        }
+        if let Some(path) = report_path {
+            std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        }
+        return Ok(());
+    }
+
+    let progress = show_progress.then(Progress::new);
+    let core_config = core_config.with_progress(progress.clone());
+    let renderer =
+        progress.map(|progress| ProgressRenderer::spawn(progress, DEFAULT_REDRAW_INTERVAL));
+    let result = blt_core::run_tokenizer(core_config).await;
+    if let Some(renderer) = renderer {
+        renderer.finish();
+    }
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error running tokenizer: {e}");
+            let code = match e {
+                BltError::Cancelled(_) => EXIT_INTERRUPTED,
+                _ => 1,
+            };
+            std::process::exit(code);
+        }
+    };
+    if let Some(hash) = &report.output_hash {
+        eprintln!("Output hash: {hash}");
+    }
+    if !report.skipped_chunks.is_empty() {
+        let bytes: u64 = report.skipped_chunks.iter().map(|c| c.bytes).sum();
+        eprintln!(
+            "Skipped {} chunks ({bytes} input bytes) that failed to tokenize",
+            report.skipped_chunks.len()
+        );
+    }
+    if stats {
+        eprintln!("{}", serde_json::to_string(&report)?);
+    }
+    if let Some(path) = report_path {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+
+    Ok(())
+}
+
+async fn run_count(args: CountArgs) -> io::Result<()> {
+    let program = std::env::args_os().next().unwrap_or_else(|| "blt".into());
+    let options = args.options.into_iter().map(Into::into);
+    let cli_args = config_file::parse_cli_args(std::iter::once(program).chain(options).collect())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let core_config = tokenize_config(cli_args)?;
+    let mut counts = blt_core::count::count_tokens(core_config).await?;
+    if args.total {
+        counts.documents.clear();
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&counts)?);
+    } else if args.total {
+        println!("{}", counts.tokens);
+    } else {
+        print!("{counts}");
+    }
+    Ok(())
+}
+
+async fn run_verify(args: VerifyArgs) -> io::Result<()> {
+    let program = std::env::args_os().next().unwrap_or_else(|| "blt".into());
+    let options = args.options.into_iter().map(Into::into);
+    let cli_args = config_file::parse_cli_args(std::iter::once(program).chain(options).collect())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let core_config = tokenize_config(cli_args)?;
+    let report = blt_core::verify::verify(&core_config).await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    match &report.divergence {
+        Some(divergence) => Err(io::Error::other(format!(
+            "Roundtrip verification failed at byte {} of {}",
+            divergence.offset, divergence.source
+        ))),
+        None => Ok(()),
+    }
+}
+
+async fn run_bench(args: BenchArgs) -> io::Result<()> {
+    let program = std::env::args_os().next().unwrap_or_else(|| "blt".into());
+    let options = args.options.into_iter().map(Into::into);
+    let cli_args = config_file::parse_cli_args(std::iter::once(program).chain(options).collect())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let core_config = tokenize_config(cli_args)?;
+    let input = match args.sample {
+        Some(path) => blt_core::bench::BenchInput::Sample(path, args.size),
+        None => blt_core::bench::BenchInput::Synthetic(args.size),
+    };
+    let threads = match args.threads.is_empty() {
+        true => blt_core::bench::default_thread_counts(),
+        false => args.threads,
+    };
+    let chunk_sizes = match args.chunk_sizes.is_empty() {
+        true => blt_core::bench::DEFAULT_CHUNK_SIZES.to_vec(),
+        false => args.chunk_sizes,
+    };
+    let report = blt_core::bench::bench(&core_config, &input, &threads, &chunk_sizes).await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    Ok(())
+}
+
+async fn run_repair(args: RepairArgs) -> io::Result<()> {
+    let program = std::env::args_os().next().unwrap_or_else(|| "blt".into());
+    let options = args.options.into_iter().map(Into::into);
+    let cli_args = config_file::parse_cli_args(std::iter::once(program).chain(options).collect())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let core_config = tokenize_config(cli_args)?;
+    let report = blt_core::repair::repair(core_config, args.check).await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    match (args.check, report.damaged.len()) {
+        (true, damaged) if damaged > 0 => Err(io::Error::other(format!(
+            "Check failed: {damaged} output(s) are damaged"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+async fn run_rebalance(args: RebalanceArgs) -> io::Result<()> {
+    let report =
+        blt_core::rebalance::rebalance(&args.dir, args.target_size, &args.manifests).await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    Ok(())
+}
+
+async fn run_report(args: ReportArgs) -> io::Result<()> {
+    let entries = blt_core::manifest::read_manifest(&args.manifest).await?;
+    let report = blt_core::stats::manifest_report(&entries);
+    if let Some(path) = &args.html {
+        let title = match args.manifest.file_name() {
+            Some(name) => format!("Corpus report: {}", name.to_string_lossy()),
+            None => "Corpus report".to_string(),
+        };
+        std::fs::write(path, report.to_html(&title))?;
+    }
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+async fn run_manifest(args: ManifestArgs) -> io::Result<()> {
+    match args.command {
+        ManifestCommand::Upgrade(args) => {
+            let output = args.output.as_deref().unwrap_or(&args.manifest);
+            let upgrade = blt_core::manifest::upgrade_manifest(&args.manifest, output).await?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&upgrade)?);
+            } else {
+                print!("{upgrade}");
+            }
+            Ok(())
+        }
+    }
+}
+
+fn run_inspect(args: InspectArgs) -> io::Result<()> {
+    let inspection = stats::inspect_merges(&args.merges, args.merge_id_base, args.table)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&inspection)?);
+    } else {
+        print!("{inspection}");
+        if args.table {
+            println!();
+            print!("{}", inspection.table());
+        }
+    }
+    match (args.check, inspection.problems(), &inspection.load_error) {
+        (true, problems, _) if problems > 0 => Err(io::Error::other(format!(
+            "Check failed: {problems} problem(s) in {}",
+            args.merges.display()
+        ))),
+        (true, _, Some(error)) => Err(io::Error::other(format!("Check failed: {error}"))),
+        _ => Ok(()),
+    }
+}
+
+fn run_snapshot(args: SnapshotArgs) -> io::Result<()> {
+    let snapshot = blt_core::snapshot::create(&args.merges, &args.output)?;
+    println!(
+        "Wrote a snapshot of {} merges (fingerprint {}) to {}",
+        snapshot.merges.len(),
+        config_loader::merges_fingerprint(&snapshot.merges),
+        args.output.display()
+    );
+    Ok(())
+}
+
+#[cfg(feature = "encryption")]
+fn run_decrypt(args: DecryptArgs) -> io::Result<()> {
+    let key = EncryptionKey::from_path(&args.key)?;
+    let input = io::BufReader::new(std::fs::File::open(&args.input)?);
+    let mut reader = blt_core::io_handler::DecryptingReader::new(input, &key)?;
+    match args.output {
+        Some(path) => {
+            let mut output = io::BufWriter::new(std::fs::File::create(&path)?);
+            let copied =
+                io::copy(&mut reader, &mut output).and_then(|_| io::Write::flush(&mut output));
+            if copied.is_err() {
+                // Segments before the damage were authenticated, but a partial output is
+                // never left behind.
+                let _ = std::fs::remove_file(&path);
+            }
+            copied
+        }
+        None => io::copy(&mut reader, &mut io::stdout().lock()).map(|_| ()),
+    }
+}
+
+/// Builds the tokenizer configuration from the top-level options.
+fn tokenize_config(cli_args: CliArgs) -> io::Result<CoreConfig> {
+    let content_types = load_content_types(cli_args.content_types.as_deref())?;
+    let content_type = cli_args
+        .r#type
+        .as_deref()
+        .map(|name| content_types.resolve(name))
+        .transpose()?;
+    let index_path = resolve_index_path(cli_args.index.clone(), cli_args.output.as_deref())?;
+    let pacing = pacing(&cli_args);
+    let encryption = cli_args
+        .encrypt_key
+        .as_deref()
+        .map(EncryptionKey::from_path)
+        .transpose()?;
+    let byte_range = match (cli_args.byte_range, cli_args.skip, cli_args.limit) {
+        (Some(range), _, _) => Some(range),
+        (None, None, None) => None,
+        (None, skip, limit) => Some(ByteRange {
+            offset: skip.unwrap_or(0),
+            length: limit,
+        }),
+    };
+    let core_config = CoreConfig::new_from_cli(
+        cli_args.input,
+        cli_args.output,
+        cli_args.merges,
+        content_type,
+        cli_args.threads,
+        cli_args.chunksize,
+        cli_args.memcap,
+        cli_args.passthrough,
+    )?
+    .with_content_types(content_types)
+    .with_records(cli_args.records)
+    .with_output_format(cli_args.format.into_core(cli_args.row_group_size))
+    .with_input_filter(InputFilter {
+        include: cli_args.include,
+        exclude: cli_args.exclude,
+    })
+    .with_output_dir(cli_args.output_dir.map(|dir| OutputDir {
+        dir,
+        extension: cli_args.output_extension,
+        content_addressed: cli_args.content_addressed,
+    }))
+    .with_overwrite(cli_args.force)
+    .with_filelist(cli_args.filelist)
+    .with_document_separator((!cli_args.no_doc_separator).then_some(cli_args.doc_separator))
+    .with_content_type_framing(cli_args.type_framing)
+    .with_strict_specials(cli_args.strict_specials)
+    .with_on_error(cli_args.on_error.into())
+    .with_entropy_markers(cli_args.entropy_markers)
+    .with_byte_offset(cli_args.byte_offset)
+    .with_merge_id_base(cli_args.merge_id_base)
+    .with_max_tokens(cli_args.max_tokens)
+    .with_token_rate(cli_args.token_rate)
+    .with_deterministic(cli_args.deterministic)
+    .with_output_hash(cli_args.output_hash.then_some(cli_args.hash))
+    .with_expected_output_hash(cli_args.verify_hash)
+    .with_stream_markers(StreamMarkers {
+        bos: cli_args.bos,
+        eos: cli_args.eos,
+        scope: cli_args.marker_scope.into(),
+    })
+    .with_archive(cli_args.archive)
+    .with_demux(cli_args.demux)
+    .with_compression(cli_args.compress)
+    .with_encryption(encryption)
+    .with_tee_original(cli_args.tee_original)
+    .with_manifest(cli_args.manifest, cli_args.hash)
+    .with_snapshot_id(cli_args.snapshot_id)
+    .with_previous_manifest(cli_args.since)
+    .with_document_ids(cli_args.doc_id.unwrap_or_default())
+    .with_header(cli_args.header)
+    .with_log_records(cli_args.log_records.then_some(LogRecordPolicy {
+        record_separator: cli_args.record_separator,
+        tag_severity: !cli_args.no_severity_tags,
+    }))
+    .with_truncation(cli_args.truncate.map(|max_tokens| {
+        let mode = cli_args
+            .truncate_mode
+            .into_core(cli_args.truncate_head_ratio);
+        TruncationPolicy::new(max_tokens, mode)
+    }))
+    .with_span_corruption(cli_args.span_corruption.map(|noise_density| {
+        let span_length = cli_args.span_distribution.into_core(cli_args.span_length);
+        SpanCorruptionPolicy {
+            mask_token: cli_args.mask_token,
+            seed: cli_args.span_seed,
+            ..SpanCorruptionPolicy::new(noise_density, span_length)
+        }
+    }))
+    .with_packing(
+        cli_args
+            .pack
+            .map(|seq_len| PackingPolicy::new(seq_len, cli_args.pack_short_threshold)),
+    )
+    .with_shifted_pairs(cli_args.shifted_pairs.then(ShiftedPairsPolicy::default))
+    .with_pad_to(cli_args.pad_to.map(|multiple| PadToPolicy {
+        pad_token: cli_args.pad_token,
+        ..PadToPolicy::new(multiple)
+    }))
+    .with_index(index_path);
+    let thread_scaling = match (cli_args.min_threads, cli_args.max_threads) {
+        (None, None) => None,
+        (min, max) => Some(ThreadScaling::new(
+            min.unwrap_or(1),
+            max.unwrap_or(core_config.num_threads),
+        )),
+    };
+    let core_config = core_config
+        .with_thread_scaling(thread_scaling)
+        .with_pacing(pacing)
+        .with_byte_range(byte_range)
+        .with_mmap(!cli_args.no_mmap)
+        .with_mmap_dontneed(cli_args.mmap_dontneed)
+        .with_mmap_prefault(cli_args.mmap_prefault)
+        .with_io_uring(cli_args.io_uring)
+        .with_direct_io(cli_args.direct_io)
+        .with_follow(
+            cli_args
+                .follow
+                .then_some(Duration::from_millis(cli_args.follow_interval.max(1))),
+        )
+        .with_heartbeat(cli_args.heartbeat.map(Duration::from_secs))
+        .with_stop_on_signal(true)
+        .with_partial_output(cli_args.on_interrupt.into())
+        .with_transcoding(cli_args.transcode.map(|encoding| Transcoding {
+            encoding,
+            on_error: cli_args.decode_errors,
+        }))
+        .with_strip_html(cli_args.strip_html)
+        .with_timestamp_stripping(cli_args.strip_timestamps)
+        .with_number_bucketing(cli_args.numbers.map(|form| NumberBucketing {
+            form,
+            min_digits: cli_args.numbers_min_digits.into(),
+        }))
+        .with_whitespace(WhitespaceNormalization {
+            tab_width: cli_args.expand_tabs.map(usize::from),
+            collapse: cli_args.collapse_whitespace,
+            trim: cli_args.trim_whitespace,
+        });
+    let sandbox_workers = cli_args
+        .sandbox_workers
+        .unwrap_or(core_config.max_threads());
+    let core_config = core_config
+        .with_sandbox(
+            cli_args
+                .sandbox
+                .then(|| SandboxConfig::new(sandbox_workers))
+                .transpose()?,
+        )
+        .with_daemon(cli_args.daemon);
+    Ok(core_config)
+}
+
+#[cfg(feature = "server")]
+async fn run_serve(serve_args: ServeArgs) -> io::Result<()> {
+    let content_types = load_content_types(serve_args.content_types.as_deref())?;
+    let core_config =
+        CoreConfig::new_from_cli(None, None, serve_args.merges, None, None, None, None, false)?
+            .with_content_types(content_types);
+    let quotas = serve_args.quotas.as_deref().map_or_else(
+        || Ok(Default::default()),
+        blt_core::server::QuotaConfig::from_path,
+    )?;
+    let api_keys = serve_args
+        .api_keys
+        .as_deref()
+        .map(blt_core::server::ApiKeys::from_path)
+        .transpose()?;
+    let tls = serve_args
+        .tls_cert
+        .zip(serve_args.tls_key)
+        .map(|(cert, key)| blt_core::server::TlsConfig { cert, key });
+    let server_config = blt_core::server::ServerConfig {
+        listener: blt_core::server::Listener::detect(serve_args.listen, serve_args.inetd),
+        core: core_config,
+        quotas,
+        api_keys,
+        tls,
+        batching: serve_args.batch.then(|| blt_core::server::BatchConfig {
+            max_request_bytes: serve_args.batch_max_bytes,
+            window: Duration::from_micros(serve_args.batch_window),
+            ..Default::default()
+        }),
+        shards: serve_args.shards,
+    };
+    blt_core::server::serve(server_config).await
+}
+
+#[cfg(feature = "daemon")]
+async fn run_daemon(daemon_args: DaemonArgs) -> io::Result<()> {
+    let core_config = CoreConfig::new_from_cli(
+        None,
+        None,
+        daemon_args.merges,
+        None,
+        None,
+        None,
+        None,
+        false,
+    )?;
+    blt_core::daemon::serve(blt_core::daemon::DaemonConfig {
+        socket: daemon_args.socket,
+        core: core_config,
+    })
+    .await
+}
+
+fn run_stats(stats_args: StatsArgs) -> io::Result<()> {
+    match stats_args.command {
+        StatsCommand::Coverage(args) => {
+            let merges = config_loader::load_bpe_merges_from_path(&args.merges)?;
+            let inputs = typed_inputs(
+                args.inputs,
+                args.r#type.as_deref(),
+                args.content_types.as_deref(),
+            )?;
+            let report = stats::coverage_report(merges, &inputs)?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
+            Ok(())
+        }
+        StatsCommand::Prune(args) => {
+            let merges = config_loader::load_bpe_merges_from_path(&args.merges)?;
+            let ranking = stats::merge_usage(merges, &args.inputs)?;
+            println!("{:>8} {:>8} {:>8}", "token", "count", "pair");
+            for usage in ranking.ranked.iter().take(args.top) {
+                let (a, b) = usage.pair;
+                println!("{:>8} {:>8} {a:>3} {b:>3}", usage.token, usage.count);
+            }
+            println!();
+            println!("{:>10} {:>8} {:>9}  file", "vocab", "merges", "retained");
+            std::fs::create_dir_all(&args.out_dir)?;
+            for vocab_size in args.vocab_sizes {
+                let pruned = ranking.pruned(vocab_size);
+                let path = args.out_dir.join(format!("merges.{vocab_size}.txt"));
+                std::fs::write(&path, stats::to_merges_file(&pruned))?;
+                println!(
+                    "{vocab_size:>10} {:>8} {:>8.2}%  {}",
+                    pruned.len(),
+                    ranking.retained_fraction(vocab_size) * 100.0,
+                    path.display()
+                );
+            }
+            Ok(())
+        }
+        StatsCommand::Bytes(args) => run_stats_bytes(args),
+    }
+}
+
+fn run_stats_bytes(args: BytesArgs) -> io::Result<()> {
+    let inputs = typed_inputs(
+        args.inputs,
+        args.r#type.as_deref(),
+        args.content_types.as_deref(),
+    )?;
+    let policy = AnomalyPolicy {
+        max_control_fraction: args.max_control,
+        max_invalid_utf8_fraction: args.max_invalid_utf8,
+        max_text_entropy: args.max_entropy,
+    };
+    let mut report = stats::byte_histogram_report(&inputs, &policy)?;
+    if let Some(dir) = &args.quarantine {
+        for file in report.files.iter_mut().filter(|file| file.is_flagged()) {
+            file.quarantined = Some(stats::quarantine(&file.path, dir, args.quarantine_mode)?);
+        }
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    Ok(())
+}
+
+/// Pairs each input with `type`, if given, or the content type guessed from its extension.
+fn typed_inputs(
+    inputs: Vec<PathBuf>,
+    r#type: Option<&str>,
+    content_types: Option<&Path>,
+) -> io::Result<Vec<(PathBuf, CoreContentType)>> {
+    let content_types = load_content_types(content_types)?;
+    let content_type = r#type.map(|name| content_types.resolve(name)).transpose()?;
+    Ok(inputs
+        .into_iter()
+        .map(|path| {
+            let ct = content_type
+                .clone()
+                .unwrap_or_else(|| CoreContentType::from_path(&path));
+            (path, ct)
+        })
+        .collect())
+}
+
+fn run_vocab_diff(args: VocabDiffArgs) -> io::Result<()> {
+    let old = config_loader::load_bpe_merges_from_path(&args.old)?;
+    let new = config_loader::load_bpe_merges_from_path(&args.new)?;
+    let report = stats::vocab_diff_report(old, new, &args.sample)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    Ok(())
+}
+
+fn run_canary(args: CanaryArgs) -> io::Result<()> {
+    let old = config_loader::load_bpe_merges_from_path(&args.old)?;
+    let new = config_loader::load_bpe_merges_from_path(&args.new)?;
+    let mut samples = Vec::new();
+    for input in args.inputs {
+        if input.is_dir() {
+            samples.extend(collect_input_files(&input, &InputFilter::default())?);
+        } else {
+            samples.push(input);
+        }
+    }
+    let gate = stats::CanaryGate {
+        max_divergence: args.max_divergence,
+        max_token_delta: args.max_token_delta,
+    };
+    let report = stats::canary_report(old, new, &samples, &gate)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    match report.failures() {
+        0 => Ok(()),
+        failures => Err(io::Error::other(format!(
+            "Canary failed: {failures} result(s) exceed the configured limits"
+        ))),
+    }
+}
+
+/// Loads `--content-types`, or just the built-in content types without it.
+fn load_content_types(path: Option<&Path>) -> io::Result<ContentTypeRegistry> {
+    path.map_or_else(
+        || Ok(ContentTypeRegistry::default()),
+        ContentTypeRegistry::from_path,
+    )
+}
+
+/// Resolves `--index`: an explicit path, or `<OUTPUT>.idx` when given without a value.
+fn resolve_index_path(
+    index: Option<Option<PathBuf>>,
+    output: Option<&Path>,
+) -> io::Result<Option<PathBuf>> {
+    match index {
+        None => Ok(None),
+        Some(Some(path)) => Ok(Some(path)),
+        Some(None) => output.map(|o| Some(index::default_path(o))).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--index without a path requires --output",
+            )
+        }),
+    }
+}
+
+/// The encoding `--transcode` decodes from, or `None` to detect it per document.
+type SourceEncoding = Option<Encoding>;
+
+/// Parses a `--transcode` value: an encoding name, or `auto` to detect it.
+fn parse_target_size(value: &str) -> Result<u64, String> {
+    blt_core::utils::parse_chunk_size_str(value).map(|size| size as u64)
+}
+
+fn parse_transcode(value: &str) -> Result<SourceEncoding, String> {
+    match value {
+        "auto" => Ok(None),
+        name => name.parse().map(Some),
+    }
+}
+
+/// Combines `--duty-cycle`, `--battery-duty-cycle`, and `--pace-command` into one pacing
+/// hook that uses the lowest duty cycle any of them reports.
+fn pacing(cli_args: &CliArgs) -> Option<Pacing> {
+    let mut hooks: Vec<Arc<dyn PacingHook>> = Vec::new();
+    if let Some(duty) = cli_args.duty_cycle {
+        hooks.push(Arc::new(FixedDutyCycle(duty)));
+    }
+    if let Some(on_battery) = cli_args.battery_duty_cycle {
+        hooks.push(Arc::new(BatteryHook { on_battery }));
+    }
+    if let Some(command) = &cli_args.pace_command {
+        hooks.push(Arc::new(CommandHook {
+            command: command.clone(),
+        }));
+    }
+    let hook: Arc<dyn PacingHook> = match hooks.len() {
+        0 => return None,
+        1 => hooks.pop()?,
+        _ => Arc::new(MinDutyCycle(hooks)),
+    };
+    Some(Pacing {
+        hook,
+        refresh: Duration::from_secs(cli_args.pace_interval.max(1)),
+    })
 }
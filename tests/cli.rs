@@ -1,3 +1,4 @@
+use blt_core::testkit::{self, Corpus};
 use std::fs::File; // Removed 'self'
 use std::io::{Read, Write};
 use std::path::PathBuf;
@@ -57,14 +58,38 @@ fn test_cli_input_output_files() {
     // The NamedTempFile will be deleted when output_file_path_holder goes out of scope.
     let output_file_path_holder = output_file.into_temp_path();
 
+    // The output already exists, so the run must refuse to replace it without --force.
+    let refused = Command::new(&cli_path)
+        .arg("--input")
+        .arg(input_path)
+        .arg("--output")
+        .arg(&output_file_path_holder)
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("already exists"));
+    assert!(std::fs::read(&output_file_path_holder).unwrap().is_empty());
+
     let mut cmd = Command::new(cli_path);
     cmd.arg("--input")
         .arg(input_path)
         .arg("--output")
-        .arg(&output_file_path_holder);
+        .arg(&output_file_path_holder)
+        .arg("--force");
 
     let status = cmd.status().expect("Failed to run CLI process");
     assert!(status.success());
+    // Only the output remains; the temporary file it was written to has been renamed.
+    let parent = output_file_path_holder.parent().unwrap();
+    let name = output_file_path_holder
+        .file_name()
+        .unwrap()
+        .to_string_lossy();
+    assert!(!std::fs::read_dir(parent).unwrap().any(|entry| entry
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with(&format!(".{name}."))));
 
     // Re-open the output file for reading
     let mut output_content = Vec::new();
@@ -212,3 +237,2405 @@ fn test_cli_passthrough_mode() {
     // Passthrough mode should return the input unchanged
     assert_eq!(output.stdout, b"passthrough test");
 }
+
+#[cfg(not(feature = "arrow"))]
+#[test]
+fn test_cli_arrow_format_requires_feature() {
+    let cli_path = get_cli_binary_path();
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::null());
+    cmd.arg("--format").arg("arrow");
+
+    let output = cmd.output().expect("Failed to run CLI process");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`arrow` feature"));
+}
+
+#[test]
+fn test_cli_tee_original() {
+    let cli_path = get_cli_binary_path();
+    let tee_file = NamedTempFile::new().unwrap().into_temp_path();
+    std::fs::write(&tee_file, b"previous mirror").unwrap();
+
+    // The mirror already exists, so the run must refuse to replace it without --force.
+    let refused = Command::new(&cli_path)
+        .arg("--tee-original")
+        .arg(&tee_file)
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("already exists"));
+    assert_eq!(std::fs::read(&tee_file).unwrap(), b"previous mirror");
+
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.arg("--tee-original").arg(&tee_file).arg("--force");
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin
+            .write_all(b"tee me")
+            .expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let mut expected_output = Vec::new();
+    for &byte in b"tee me" {
+        expected_output.extend_from_slice(&(byte as u16).to_be_bytes());
+    }
+    assert_eq!(output.stdout, expected_output);
+    assert_eq!(std::fs::read(&tee_file).unwrap(), b"tee me");
+}
+
+#[test]
+fn test_cli_manifest_with_content_hash() {
+    let cli_path = get_cli_binary_path();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"abc").unwrap();
+    let output_file = NamedTempFile::new().unwrap().into_temp_path();
+    let manifest_file = NamedTempFile::new().unwrap().into_temp_path();
+
+    let status = Command::new(cli_path)
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--output")
+        .arg(&output_file)
+        .arg("--manifest")
+        .arg(&manifest_file)
+        .arg("--force")
+        .status()
+        .expect("Failed to run CLI process");
+    assert!(status.success());
+
+    let manifest = std::fs::read_to_string(&manifest_file).unwrap();
+    assert_eq!(manifest.lines().count(), 1);
+    assert!(manifest.contains("\"input_bytes\":3"));
+    assert!(manifest.contains("\"tokens\":3"));
+    assert!(manifest
+        .contains("sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+}
+
+#[test]
+fn test_cli_seeded_hash() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"abc").unwrap();
+    let manifest_file = NamedTempFile::new().unwrap().into_temp_path();
+    let run = |algorithm: &str| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_file.path())
+            .arg("--manifest")
+            .arg(&manifest_file)
+            .args(["--hash", algorithm, "--output-hash"])
+            .output()
+            .expect("Failed to run CLI process");
+        assert!(output.status.success());
+        let manifest = std::fs::read_to_string(&manifest_file).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(manifest.trim()).unwrap();
+        let content_hash = entry["content_hash"].as_str().unwrap().to_string();
+        (content_hash, String::from_utf8(output.stderr).unwrap())
+    };
+
+    let (seeded, stderr) = run("xxh3@42");
+    assert!(seeded.starts_with("xxh3@42:"), "{seeded}");
+    assert!(stderr.contains("Output hash: xxh3@42:"), "{stderr}");
+    assert_eq!(run("xxh3@42").0, seeded);
+    let (unseeded, _) = run("xxh3");
+    assert!(unseeded.starts_with("xxh3:"));
+    assert_ne!(unseeded[5..], seeded[8..]);
+}
+
+#[test]
+fn test_cli_run_report() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"abc").unwrap();
+    let output_file = NamedTempFile::new().unwrap().into_temp_path();
+    let report_file = NamedTempFile::new().unwrap().into_temp_path();
+
+    let status = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--output")
+        .arg(&output_file)
+        .arg("--report")
+        .arg(&report_file)
+        .arg("--force")
+        .status()
+        .expect("Failed to run CLI process");
+    assert!(status.success());
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report_file).unwrap()).unwrap();
+    assert_eq!(report["documents"], 1);
+    assert_eq!(report["input_bytes"], 3);
+    assert_eq!(report["tokens"], 3);
+    assert!(report["usage"]["stages"].get("tokenize_secs").is_some());
+    assert!(report["usage"].get("peak_rss_bytes").is_some());
+}
+
+#[test]
+fn test_cli_stats_summary() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(&b"abcd".repeat(256 * 1024)).unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--stats", "--chunksize", "256KB", "--threads", "2"])
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 2 << 20);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let summary: serde_json::Value =
+        serde_json::from_str(stderr.lines().last().expect("summary is printed")).unwrap();
+    assert_eq!(summary["input_bytes"], 1 << 20);
+    assert_eq!(summary["output_bytes"], 2 << 20);
+    assert_eq!(summary["tokens"], 1 << 20);
+    assert_eq!(summary["compression_ratio"], 0.5);
+    assert_eq!(summary["chunks"], 4);
+    assert_eq!(summary["chunk_size_bytes"], 256 * 1024);
+    assert_eq!(summary["threads"], 2);
+    assert!(summary["wall_secs"].is_f64());
+    assert!(summary["usage"]["stages"].get("write_secs").is_some());
+}
+
+#[test]
+fn test_cli_header_argument() {
+    let cli_path = get_cli_binary_path();
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.arg("--header");
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(b"hi").expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let header = blt_core::header::OutputHeader::parse(&output.stdout).unwrap();
+    assert_eq!(header.0.strategy, blt_core::StrategyKind::Basic);
+    assert_eq!(&output.stdout[header.1..], &[0, b'h', 0, b'i']);
+}
+
+#[test]
+fn test_cli_byte_offset() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"abc\nd").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--byte-offset", "256", "--header", "--truncate", "1"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let (header, header_len) = blt_core::header::OutputHeader::parse(&output.stdout).unwrap();
+    assert_eq!(header.byte_offset, 256);
+    let tokens: Vec<u16> = output.stdout[header_len..]
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    assert_eq!(tokens, [256 + 97, 256 + 10, 256 + 100]);
+
+    let mut merges = NamedTempFile::new().unwrap();
+    writeln!(merges, "97 98").unwrap();
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--merges")
+        .arg(merges.path())
+        .args(["--byte-offset", "256"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+
+    // Starting merge IDs after the shifted byte tokens makes the layout coherent.
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--merges")
+        .arg(merges.path())
+        .args(["--byte-offset", "256", "--merge-id-base", "512"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let tokens: Vec<u16> = output
+        .stdout
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    assert_eq!(tokens, [512, 256 + 99, 256 + 10, 256 + 100]);
+}
+
+#[test]
+fn test_cli_pack_short_documents() {
+    use blt_core::special_tokens::{PAD, SEPARATOR};
+
+    let cli_path = get_cli_binary_path();
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.args(["--pack", "6", "--pack-short-threshold", "4"]);
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin
+            .write_all(b"a\nbc\nd\n")
+            .expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let a = b'a' as u16;
+    let b = b'b' as u16;
+    let c = b'c' as u16;
+    let d = b'd' as u16;
+    let expected: Vec<u8> = [a, SEPARATOR, b, c, SEPARATOR, PAD]
+        .into_iter()
+        .chain([d, SEPARATOR, PAD, PAD, PAD, PAD])
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn test_cli_index_sidecar() {
+    use blt_core::index::{self, DocumentIndex};
+
+    let cli_path = get_cli_binary_path();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"one\ntwo words\nend").unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_path = output_dir.path().join("tokens.bin");
+
+    let status = Command::new(cli_path)
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--output")
+        .arg(&output_path)
+        .arg("--index")
+        .status()
+        .expect("Failed to run CLI");
+    assert!(status.success());
+
+    let bytes = std::fs::read(index::default_path(&output_path)).unwrap();
+    let document_index = DocumentIndex::parse(&bytes).unwrap();
+    let lengths: Vec<u64> = document_index.entries().iter().map(|e| e.tokens).collect();
+    assert_eq!(lengths, vec![4, 10, 3]);
+    assert_eq!(document_index.entries()[2].byte_offset, 28);
+    assert_eq!(std::fs::metadata(&output_path).unwrap().len(), 34);
+
+    // An existing index is refused without --force, before the output is written.
+    std::fs::remove_file(&output_path).unwrap();
+    let refused = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--output")
+        .arg(&output_path)
+        .arg("--index")
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("already exists"));
+    assert!(!output_path.exists());
+    assert_eq!(
+        std::fs::read(index::default_path(&output_path)).unwrap(),
+        bytes
+    );
+}
+
+#[test]
+fn test_cli_truncate_head_tail() {
+    let cli_path = get_cli_binary_path();
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.args(["--truncate", "4", "--truncate-mode", "head-tail"]);
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin
+            .write_all(b"abcdefgh\nxy\n")
+            .expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let expected: Vec<u8> = b"abgh\nxy\n"
+        .iter()
+        .flat_map(|&byte| (byte as u16).to_be_bytes())
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_cli_gzip_compression() {
+    use std::io::Read;
+
+    let cli_path = get_cli_binary_path();
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.args(["--compress", "gzip:9"]);
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin
+            .write_all(b"compress me")
+            .expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(&output.stdout[..])
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    let expected: Vec<u8> = b"compress me"
+        .iter()
+        .flat_map(|&byte| (byte as u16).to_be_bytes())
+        .collect();
+    assert_eq!(decompressed, expected);
+}
+
+#[test]
+fn test_cli_span_corruption_pairs() {
+    use blt_core::special_tokens::MASK;
+
+    let cli_path = get_cli_binary_path();
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.args(["--span-corruption", "0.25", "--span-distribution", "fixed"]);
+    cmd.args(["--span-length", "2"]);
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin
+            .write_all(b"abcdefgh\n")
+            .expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let tokens: Vec<u16> = output
+        .stdout
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let halves: Vec<&[u16]> = tokens.split_inclusive(|&t| t == b'\n' as u16).collect();
+    assert_eq!(halves.len(), 2);
+    let (input, target) = (halves[0], halves[1]);
+    assert_eq!(input.iter().filter(|&&t| t == MASK).count(), 1);
+    assert_eq!(input.len(), 8);
+    assert_eq!(target.len(), 5);
+}
+
+#[test]
+fn test_cli_shifted_pairs_after_packing() {
+    use blt_core::special_tokens::{PAD, SEPARATOR};
+
+    let cli_path = get_cli_binary_path();
+    let mut cmd = Command::new(cli_path);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.args(["--pack", "4", "--shifted-pairs"]);
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(b"ab\n").expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let a = b'a' as u16;
+    let b = b'b' as u16;
+    let expected: Vec<u8> = [a, b, SEPARATOR, b, SEPARATOR, PAD, 1, 1, 0]
+        .into_iter()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn test_cli_packing_splits_documents_at_merged_newlines() {
+    use blt_core::special_tokens::{PAD, SEPARATOR};
+
+    // "a\n" and "\n\n" merge, so document boundaries must be kept out of the merges.
+    let mut merges_file = NamedTempFile::new().unwrap();
+    merges_file.write_all(b"97 10\n10 10\n").unwrap();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"a\n\nb\n").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--merges")
+        .arg(merges_file.path())
+        .args(["--pack", "4", "--stats"])
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(output.status.success());
+
+    let a = b'a' as u16;
+    let b = b'b' as u16;
+    let expected: Vec<u8> = [a, SEPARATOR, PAD, PAD, b, SEPARATOR, PAD, PAD]
+        .into_iter()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(output.stdout, expected);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let summary: serde_json::Value =
+        serde_json::from_str(stderr.lines().last().expect("summary is printed")).unwrap();
+    assert_eq!(summary["packing"]["sequences"], 2);
+    assert_eq!(summary["packing"]["content_tokens"], 4);
+    assert_eq!(summary["packing"]["padding_tokens"], 4);
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn test_cli_archive_members_are_separated() {
+    use blt_core::manifest;
+    use blt_core::special_tokens::SEPARATOR;
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, data) in [("one.txt", &b"ab"[..]), ("two.txt", &b"c"[..])] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data).unwrap();
+    }
+    let mut archive_file = NamedTempFile::new().unwrap();
+    archive_file
+        .write_all(&builder.into_inner().unwrap())
+        .unwrap();
+    let manifest_file = NamedTempFile::new().unwrap().into_temp_path();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(archive_file.path())
+        .arg("--archive")
+        .arg("--manifest")
+        .arg(&manifest_file)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+
+    let expected: Vec<u8> = [b'a' as u16, b'b' as u16, SEPARATOR, b'c' as u16]
+        .into_iter()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(output.stdout, expected);
+
+    let entries = manifest::from_jsonl(&std::fs::read_to_string(&manifest_file).unwrap()).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries[1].source.ends_with(":two.txt"));
+    assert_eq!(entries[1].doc_id, 1);
+}
+
+#[test]
+fn test_cli_stats_coverage_json() {
+    let mut merges_file = NamedTempFile::new().unwrap();
+    writeln!(merges_file, "97 98").unwrap();
+    let input_dir = tempfile::tempdir().unwrap();
+    let input_path = input_dir.path().join("sample.txt");
+    std::fs::write(&input_path, b"abab").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .args(["stats", "coverage", "--json", "--merges"])
+        .arg(merges_file.path())
+        .arg(&input_path)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["by_content_type"][0][0], "text");
+    assert_eq!(report["total"]["merged_bytes"], 4);
+    assert_eq!(report["total"]["tokens"], 2);
+}
+
+#[test]
+fn test_cli_stats_prune_writes_pruned_merges() {
+    let mut merges_file = NamedTempFile::new().unwrap();
+    writeln!(merges_file, "97 98").unwrap();
+    writeln!(merges_file, "120 121").unwrap();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"xyxyab").unwrap();
+    let out_dir = tempfile::tempdir().unwrap();
+
+    let status = Command::new(get_cli_binary_path())
+        .args(["stats", "prune", "--vocab-sizes", "257,258", "--merges"])
+        .arg(merges_file.path())
+        .arg("--out-dir")
+        .arg(out_dir.path())
+        .arg(input_file.path())
+        .stdout(Stdio::null())
+        .status()
+        .expect("Failed to run CLI");
+    assert!(status.success());
+
+    let pruned = std::fs::read_to_string(out_dir.path().join("merges.257.txt")).unwrap();
+    assert_eq!(pruned, "120 121\n");
+    let full = std::fs::read_to_string(out_dir.path().join("merges.258.txt")).unwrap();
+    assert_eq!(full, "97 98\n120 121\n");
+}
+
+#[test]
+fn test_cli_vocab_diff_json() {
+    let mut old_merges = NamedTempFile::new().unwrap();
+    writeln!(old_merges, "97 98").unwrap();
+    writeln!(old_merges, "99 100").unwrap();
+    let mut new_merges = NamedTempFile::new().unwrap();
+    writeln!(new_merges, "99 100").unwrap();
+    writeln!(new_merges, "101 102").unwrap();
+    let mut sample = NamedTempFile::new().unwrap();
+    sample.write_all(b"abcd").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("vocab-diff")
+        .arg(old_merges.path())
+        .arg(new_merges.path())
+        .arg("--sample")
+        .arg(sample.path())
+        .arg("--json")
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["merges"]["added"].as_array().unwrap().len(), 1);
+    assert_eq!(report["merges"]["removed"].as_array().unwrap().len(), 1);
+    assert_eq!(report["merges"]["remapped"].as_array().unwrap().len(), 1);
+    assert_eq!(report["total"]["old_tokens"], 2);
+    assert_eq!(report["total"]["new_tokens"], 3);
+    assert_eq!(report["total"]["remapped_tokens"], 1);
+}
+
+#[test]
+fn test_cli_directory_input_with_filters() {
+    use blt_core::special_tokens::SEPARATOR;
+
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(input_dir.path().join("nested")).unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"b").unwrap();
+    std::fs::write(input_dir.path().join("nested/a.txt"), b"a").unwrap();
+    std::fs::write(input_dir.path().join("nested/empty.txt"), b"").unwrap();
+    std::fs::write(input_dir.path().join("skip.log"), b"log").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_dir.path())
+        .args(["--include", "*.txt", "--exclude", "*empty*"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+
+    let expected: Vec<u8> = [b'b' as u16, SEPARATOR, b'a' as u16]
+        .into_iter()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn test_cli_filelist_preserves_list_order() {
+    use blt_core::special_tokens::SEPARATOR;
+
+    let input_dir = tempfile::tempdir().unwrap();
+    let first = input_dir.path().join("z.txt");
+    let second = input_dir.path().join("a.txt");
+    std::fs::write(&first, b"z").unwrap();
+    std::fs::write(&second, b"a").unwrap();
+    let list = input_dir.path().join("shards.txt");
+    std::fs::write(
+        &list,
+        format!("{}\n{}\n", first.display(), second.display()),
+    )
+    .unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--filelist")
+        .arg(&list)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+
+    let expected: Vec<u8> = [b'z' as u16, SEPARATOR, b'a' as u16]
+        .into_iter()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn test_cli_output_dir_mirrors_inputs() {
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(input_dir.path().join("nested")).unwrap();
+    std::fs::write(input_dir.path().join("top.txt"), b"t").unwrap();
+    std::fs::write(input_dir.path().join("nested/doc.txt"), b"ab").unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+
+    let status = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_dir.path())
+        .arg("--output-dir")
+        .arg(output_dir.path())
+        .status()
+        .expect("Failed to run CLI");
+    assert!(status.success());
+
+    let top = std::fs::read(output_dir.path().join("top.bin")).unwrap();
+    assert_eq!(top, (b't' as u16).to_be_bytes());
+    let nested = std::fs::read(output_dir.path().join("nested/doc.bin")).unwrap();
+    assert_eq!(nested, [0, b'a', 0, b'b']);
+}
+
+#[test]
+fn test_cli_content_addressed_output_dir() {
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::write(input_dir.path().join("a.txt"), b"same").unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"same").unwrap();
+    std::fs::write(input_dir.path().join("c.txt"), b"other").unwrap();
+    let work = tempfile::tempdir().unwrap();
+    let store = work.path().join("store");
+    let manifest = work.path().join("manifest.jsonl");
+
+    let status = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_dir.path())
+        .arg("--output-dir")
+        .arg(&store)
+        .arg("--content-addressed")
+        .arg("--manifest")
+        .arg(&manifest)
+        .status()
+        .expect("Failed to run CLI");
+    assert!(status.success());
+
+    let entries: Vec<serde_json::Value> = std::fs::read_to_string(&manifest)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 3);
+    let objects: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry["output"].as_str().unwrap())
+        .collect();
+    // Identical documents share one object.
+    assert_eq!(objects[0], objects[1]);
+    assert_ne!(objects[0], objects[2]);
+    let hex = entries[0]["output_hash"]
+        .as_str()
+        .unwrap()
+        .strip_prefix("sha256:")
+        .unwrap();
+    let expected = store
+        .join("sha256")
+        .join(&hex[..2])
+        .join(format!("{hex}.bin"));
+    assert_eq!(objects[0], expected.display().to_string());
+    assert_eq!(
+        std::fs::read(&expected).unwrap(),
+        [0, b's', 0, b'a', 0, b'm', 0, b'e']
+    );
+    assert_eq!(
+        std::fs::read_dir(store.join(".staging")).unwrap().count(),
+        0
+    );
+}
+
+#[test]
+fn test_cli_incremental_snapshot() {
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::write(input_dir.path().join("a.txt"), b"kept").unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"before").unwrap();
+    let work = tempfile::tempdir().unwrap();
+    let run = |snapshot: &str, since: Option<&str>| {
+        let mut cmd = Command::new(get_cli_binary_path());
+        cmd.arg("--input")
+            .arg(input_dir.path())
+            .arg("--output-dir")
+            .arg(work.path().join(snapshot))
+            .arg("--manifest")
+            .arg(work.path().join(format!("{snapshot}.jsonl")))
+            .args(["--snapshot-id", snapshot]);
+        if let Some(since) = since {
+            cmd.arg("--since")
+                .arg(work.path().join(format!("{since}.jsonl")));
+        }
+        assert!(cmd.status().expect("Failed to run CLI").success());
+        std::fs::read_to_string(work.path().join(format!("{snapshot}.jsonl")))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>()
+    };
+    run("2026-09", None);
+
+    std::fs::write(input_dir.path().join("b.txt"), b"after").unwrap();
+    std::fs::write(input_dir.path().join("c.txt"), b"new").unwrap();
+    let entries = run("2026-10", Some("2026-09"));
+
+    // Only the changed and new documents are tokenized into the new snapshot.
+    let delta = work.path().join("2026-10");
+    let mut written: Vec<_> = std::fs::read_dir(&delta)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    written.sort();
+    assert_eq!(written, ["b.bin", "c.bin"]);
+    let snapshots: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry["snapshot"].as_str().unwrap())
+        .collect();
+    assert_eq!(snapshots, ["2026-09", "2026-10", "2026-10"]);
+    let carried = work.path().join("2026-09").join("a.bin");
+    assert_eq!(entries[0]["output"], carried.display().to_string());
+    assert_eq!(entries[2]["doc_id"], 2);
+}
+
+#[test]
+fn test_cli_doc_id_sources() {
+    let corpus = Corpus::new().with_file(
+        "logs/a.json",
+        "{\"uuid\": \"rec-1\", \"text\": \"hi\"}\nrest",
+    );
+    let ids = |source: &str| {
+        let manifest = corpus.output("manifest.jsonl");
+        let status = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(corpus.input_dir())
+            .arg("--output")
+            .arg(corpus.output("out.bin"))
+            .arg("--manifest")
+            .arg(&manifest)
+            .arg("--force")
+            .args(["--doc-id", source])
+            .status()
+            .expect("Failed to run CLI");
+        assert!(status.success());
+        let entries = testkit::manifest_entries(&manifest);
+        testkit::assert_manifest_consistent(&entries);
+        let entry = entries.into_iter().next().unwrap();
+        (entry.id, entry.content_hash)
+    };
+    assert_eq!(ids("path").0.as_deref(), Some("logs/a.json"));
+    let (id, hash) = ids("content-hash");
+    assert_eq!(id, hash);
+    assert_eq!(ids("json-field:uuid").0.as_deref(), Some("rec-1"));
+    assert_eq!(ids("ordinal").0, None);
+}
+
+#[test]
+fn test_cli_repair_rebuilds_damaged_outputs() {
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::write(input_dir.path().join("a.txt"), b"first").unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"second").unwrap();
+    let work = tempfile::tempdir().unwrap();
+    let output_dir = work.path().join("out");
+    let manifest = work.path().join("manifest.jsonl");
+
+    // Repairs take the options of the original run after `--`.
+    let run = |subcommand: &[&str]| {
+        Command::new(get_cli_binary_path())
+            .args(subcommand)
+            .arg("--input")
+            .arg(input_dir.path())
+            .arg("--output-dir")
+            .arg(&output_dir)
+            .arg("--manifest")
+            .arg(&manifest)
+            .output()
+            .expect("Failed to run CLI")
+    };
+    assert!(run(&[]).status.success());
+    let original = std::fs::read(output_dir.join("a.bin")).unwrap();
+    std::fs::write(output_dir.join("a.bin"), b"\0x").unwrap();
+
+    let output = run(&["repair", "--check", "--json", "--"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["checked"], 2);
+    assert!(report["damaged"][0]["damage"]["hash_mismatch"].is_object());
+
+    let output = run(&["repair", "--"]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(output_dir.join("a.bin")).unwrap(), original);
+    assert!(run(&["repair", "--check", "--"]).status.success());
+}
+
+#[test]
+fn test_cli_json_log_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, b"logged").unwrap();
+    let output = Command::new(get_cli_binary_path())
+        .env("RUST_LOG", "info")
+        .args(["--log-format", "json", "--input"])
+        .arg(&input)
+        .arg("--output")
+        .arg(dir.path().join("output.bin"))
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let logs = String::from_utf8(output.stdout).unwrap();
+    let events: Vec<serde_json::Value> = logs
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let started = events
+        .iter()
+        .find(|event| event["fields"]["message"] == "Starting tokenizer")
+        .expect("no start event");
+    assert_eq!(started["level"], "INFO");
+    assert_eq!(started["span"]["name"], "run_tokenizer");
+}
+
+#[test]
+fn test_cli_heartbeat_logs_progress() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut child = Command::new(get_cli_binary_path())
+        .env("RUST_LOG", "info")
+        .args(["--log-format", "json", "--heartbeat", "1", "--output"])
+        .arg(dir.path().join("output.bin"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run CLI");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"slow").unwrap();
+    // Keep the run going past the first heartbeat.
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    stdin.write_all(b" input").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let logs = String::from_utf8(output.stdout).unwrap();
+    let heartbeat: serde_json::Value = logs
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|event| {
+            event["fields"]["message"]
+                .as_str()
+                .is_some_and(|m| m.starts_with("Heartbeat"))
+        })
+        .expect("no heartbeat event");
+    assert_eq!(heartbeat["level"], "INFO");
+    let fields = &heartbeat["fields"];
+    for field in [
+        "bytes_read",
+        "tokens_written",
+        "mb_per_sec",
+        "in_flight_chunks",
+    ] {
+        assert!(fields.get(field).is_some(), "missing {field}");
+    }
+}
+
+#[test]
+fn test_cli_dry_run_writes_nothing() {
+    let corpus = Corpus::new()
+        .with_file("a.txt", "hello world ".repeat(1000))
+        .with_file("merges.txt", "104 101\n108 108\n");
+    let output = corpus.output("out.bin");
+    let report = corpus.output("report.json");
+    let run = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(corpus.input("a.txt"))
+        .arg("--output")
+        .arg(&output)
+        .arg("--merges")
+        .arg(corpus.input("merges.txt"))
+        .arg("--report")
+        .arg(&report)
+        .arg("--dry-run")
+        .output()
+        .expect("Failed to run CLI");
+    assert!(run.status.success());
+    assert!(String::from_utf8(run.stdout)
+        .unwrap()
+        .contains("Estimated output:"));
+    assert!(!output.exists());
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["input_bytes"], 12000);
+    // "he" and "ll" merge, leaving 10 tokens per 12 bytes.
+    assert_eq!(report["estimated_tokens"], 10000);
+    assert_eq!(report["max_output_bytes"], 24000);
+
+    // Invalid merges fail the dry run as they would fail the run.
+    std::fs::write(corpus.input("merges.txt"), "not a merge\n").unwrap();
+    let status = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(corpus.input("a.txt"))
+        .arg("--merges")
+        .arg(corpus.input("merges.txt"))
+        .arg("--dry-run")
+        .status()
+        .expect("Failed to run CLI");
+    assert!(!status.success());
+}
+
+#[test]
+fn test_cli_inspect_merges() {
+    let corpus = Corpus::new()
+        .with_file("good.txt", "104 101\n108 108\n")
+        .with_file("bad.txt", "104 101\n104 101\n");
+    let inspect = |file: &str, args: &[&str]| {
+        Command::new(get_cli_binary_path())
+            .arg("inspect")
+            .args(args)
+            .arg(corpus.input(file))
+            .output()
+            .expect("Failed to run CLI")
+    };
+    let good = inspect("good.txt", &["--check", "--table"]);
+    assert!(good.status.success());
+    let stdout = String::from_utf8(good.stdout).unwrap();
+    assert!(stdout.contains("Vocabulary size:  258"), "{stdout}");
+    assert!(stdout.contains("\"ll\""), "{stdout}");
+
+    let bad = inspect("bad.txt", &["--json"]);
+    assert!(bad.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&bad.stdout).unwrap();
+    assert_eq!(
+        report["duplicates"][0]["pair"],
+        serde_json::json!([104, 101])
+    );
+    assert!(!inspect("bad.txt", &["--check"]).status.success());
+}
+
+#[test]
+fn test_cli_count_tokens() {
+    let corpus = Corpus::new()
+        .with_file("a.txt", "hello")
+        .with_file("docs/b.txt", "hi");
+    let count = |args: &[&str]| {
+        Command::new(get_cli_binary_path())
+            .arg("count")
+            .args(args)
+            .arg("--")
+            .arg("--input")
+            .arg(corpus.input_dir())
+            .output()
+            .expect("Failed to run CLI")
+    };
+    let json = count(&["--json"]);
+    assert!(json.status.success());
+    let counts: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(counts["tokens"], 7);
+    let tokens: Vec<u64> = counts["documents"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["tokens"].as_u64().unwrap())
+        .collect();
+    assert_eq!(tokens, [5, 2]);
+
+    let total = count(&["--total"]);
+    assert_eq!(String::from_utf8(total.stdout).unwrap(), "7\n");
+    assert_eq!(std::fs::read_dir(corpus.output_dir()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_cli_verify_roundtrip() {
+    let corpus = Corpus::new()
+        .with_file("a.txt", "abab hello")
+        .with_file("merges.txt", "97 98\n");
+    let verify = |args: &[&str]| {
+        Command::new(get_cli_binary_path())
+            .args(["verify", "--json", "--", "--input"])
+            .arg(corpus.input_dir().join("a.txt"))
+            .arg("--merges")
+            .arg(corpus.input_dir().join("merges.txt"))
+            .args(args)
+            .output()
+            .expect("Failed to run CLI")
+    };
+    let passed = verify(&[]);
+    assert!(
+        passed.status.success(),
+        "{}",
+        String::from_utf8_lossy(&passed.stderr)
+    );
+    let report: serde_json::Value = serde_json::from_slice(&passed.stdout).unwrap();
+    assert_eq!(report["bytes_verified"], 10);
+    assert_eq!(report["tokens"], 8);
+    assert!(report["divergence"].is_null());
+
+    let output = corpus.output_dir().join("out.bin");
+    let rejected = verify(&["--output", output.to_str().unwrap()]);
+    assert!(!rejected.status.success());
+    assert!(!output.exists());
+}
+
+#[test]
+fn test_cli_bench_sweeps_and_recommends() {
+    let corpus = Corpus::new().with_file("sample.txt", "hello world ".repeat(1000));
+    let output = Command::new(get_cli_binary_path())
+        .args(["bench", "--json", "--size", "64KB", "--threads", "1,2"])
+        .args(["--chunk-sizes", "256KB", "--sample"])
+        .arg(corpus.input_dir().join("sample.txt"))
+        .output()
+        .expect("Failed to run CLI");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["sample_bytes"], 12_000);
+    assert_eq!(report["results"].as_array().unwrap().len(), 2);
+    assert_eq!(report["recommended"]["chunk_size_bytes"], 256 << 10);
+
+    let table = Command::new(get_cli_binary_path())
+        .args(["bench", "--size", "300KB", "--threads", "1"])
+        .output()
+        .expect("Failed to run CLI");
+    let table = String::from_utf8(table.stdout).unwrap();
+    assert!(table.contains("(synthetic)"), "{table}");
+    assert!(
+        table.contains("Recommended: --threads 1 --chunksize"),
+        "{table}"
+    );
+}
+
+#[test]
+fn test_cli_config_file_with_overrides() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("in.txt"), b"ab").unwrap();
+    std::fs::write(
+        dir.path().join("blt.toml"),
+        "input = \"in.txt\"\noutput = \"out.bin\"\nbyte_offset = 256\nforce = true\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("blt.yaml"),
+        "input: in.txt\noutput: out.bin\nforce: true\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(get_cli_binary_path())
+            .current_dir(dir.path())
+            .args(args)
+            .output()
+            .expect("Failed to run CLI")
+    };
+
+    assert!(run(&["--config", "blt.toml"]).status.success());
+    let output = std::fs::read(dir.path().join("out.bin")).unwrap();
+    assert_eq!(output, [0x01, 0x61, 0x01, 0x62]);
+
+    // Options on the command line win over the file.
+    assert!(run(&["--config", "blt.toml", "--byte-offset", "0"])
+        .status
+        .success());
+    let output = std::fs::read(dir.path().join("out.bin")).unwrap();
+    assert_eq!(output, [0x00, 0x61, 0x00, 0x62]);
+
+    assert!(run(&["--config", "blt.yaml", "-o", "yaml.bin"])
+        .status
+        .success());
+    let output = std::fs::read(dir.path().join("yaml.bin")).unwrap();
+    assert_eq!(output, [0x00, 0x61, 0x00, 0x62]);
+
+    std::fs::write(dir.path().join("bad.toml"), "outptu = \"x\"\n").unwrap();
+    let refused = run(&["--config", "bad.toml"]);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Unknown option 'outptu'"));
+}
+
+#[test]
+fn test_cli_report_summarizes_manifest() {
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::write(input_dir.path().join("a.txt"), b"same text").unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"same text").unwrap();
+    std::fs::write(input_dir.path().join("c.txt"), b"other").unwrap();
+    let work = tempfile::tempdir().unwrap();
+    let manifest = work.path().join("manifest.jsonl");
+    let status = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_dir.path())
+        .arg("--output-dir")
+        .arg(work.path().join("out"))
+        .arg("--manifest")
+        .arg(&manifest)
+        .status()
+        .expect("Failed to run CLI");
+    assert!(status.success());
+
+    let html = work.path().join("card.html");
+    let output = Command::new(get_cli_binary_path())
+        .args(["report", "--html"])
+        .arg(&html)
+        .arg(&manifest)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["total"]["documents"], 3);
+    assert_eq!(report["total"]["tokens"], 23);
+    assert_eq!(report["by_content_type"][0][0], "text");
+    assert_eq!(report["dedup"]["duplicate_documents"], 1);
+    assert_eq!(report["dedup"]["duplicate_tokens"], 9);
+    let html = std::fs::read_to_string(html).unwrap();
+    assert!(html.contains("<h1>Corpus report: manifest.jsonl</h1>"));
+}
+
+#[test]
+fn test_cli_manifest_upgrade_migrates_old_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("manifest.jsonl");
+    // A line written before manifests recorded their schema version.
+    std::fs::write(
+        &manifest,
+        "{\"doc_id\":0,\"source\":\"a.txt\",\"input_bytes\":3,\"output_bytes\":6,\"tokens\":3}\n",
+    )
+    .unwrap();
+    let upgraded = dir.path().join("upgraded.jsonl");
+
+    let output = Command::new(get_cli_binary_path())
+        .args(["manifest", "upgrade", "--json", "-o"])
+        .arg(&upgraded)
+        .arg(&manifest)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["entries"], 1);
+    assert_eq!(report["upgraded"], serde_json::json!([[1, 1]]));
+
+    let line: serde_json::Value =
+        serde_json::from_str(std::fs::read_to_string(&upgraded).unwrap().trim()).unwrap();
+    assert_eq!(line["schema_version"], report["schema_version"]);
+    assert_eq!(line["source"], "a.txt");
+}
+
+#[test]
+fn test_cli_rebalance_cuts_shards_between_documents() {
+    let dir = tempfile::tempdir().unwrap();
+    let shards = dir.path().join("shards");
+    std::fs::create_dir(&shards).unwrap();
+    let inputs = [
+        ("a", "x".repeat(3000)),
+        ("b", "y".repeat(500)),
+        ("c", "z".repeat(500)),
+    ];
+    for (name, text) in &inputs {
+        let input = dir.path().join(format!("{name}.txt"));
+        std::fs::write(&input, format!("{text}\n{text}\n")).unwrap();
+        let status = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(&input)
+            .arg("--output")
+            .arg(shards.join(format!("{name}.bin")))
+            .args(["--index", "--header"])
+            .status()
+            .expect("Failed to run CLI");
+        assert!(status.success());
+    }
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("rebalance")
+        .args(["--target-size", "8KB", "--json"])
+        .arg(&shards)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["documents"], 6);
+    assert_eq!(report["shards"].as_array().unwrap().len(), 2);
+    let first = std::fs::read(shards.join("shard-00000.bin")).unwrap();
+    assert!(first.starts_with(b"BLTK"));
+    assert!(shards.join("shard-00001.bin.idx").exists());
+    assert!(!shards.join("a.bin").exists());
+}
+
+#[test]
+fn test_cli_canary_gates_on_token_delta() {
+    let mut old_merges = NamedTempFile::new().unwrap();
+    writeln!(old_merges, "97 98").unwrap();
+    let mut new_merges = NamedTempFile::new().unwrap();
+    writeln!(new_merges, "99 100").unwrap();
+    let mut sample = NamedTempFile::new().unwrap();
+    sample.write_all(b"abab").unwrap();
+
+    let run = |max_token_delta: &str| {
+        Command::new(get_cli_binary_path())
+            .arg("canary")
+            .arg("--old")
+            .arg(old_merges.path())
+            .arg("--new")
+            .arg(new_merges.path())
+            .args(["--max-token-delta", max_token_delta, "--json"])
+            .arg(sample.path())
+            .output()
+            .expect("Failed to run CLI")
+    };
+
+    // "abab" takes 2 tokens with the old table and 4 with the new one: a +100% delta.
+    let output = run("1.0");
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["documents"][0]["old_tokens"], 2);
+    assert_eq!(report["documents"][0]["new_tokens"], 4);
+    assert_eq!(report["documents"][0]["passed"], true);
+
+    let output = run("0.5");
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["total"]["passed"], false);
+}
+
+#[test]
+fn test_cli_custom_document_separator() {
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::write(input_dir.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"b").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_dir.path())
+            .args(args)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    let tokens =
+        |tokens: &[u16]| -> Vec<u8> { tokens.iter().flat_map(|t| t.to_be_bytes()).collect() };
+    assert_eq!(
+        run(&["--doc-separator", "65280"]),
+        tokens(&[b'a' as u16, 0xFF00, b'b' as u16])
+    );
+    assert_eq!(
+        run(&["--no-doc-separator"]),
+        tokens(&[b'a' as u16, b'b' as u16])
+    );
+}
+
+#[cfg(feature = "sandbox")]
+#[test]
+fn test_cli_sandbox_matches_in_process_output() {
+    let mut merges_file = NamedTempFile::new().unwrap();
+    writeln!(merges_file, "97 98").unwrap();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"abcab\nxyz").unwrap();
+
+    let run = |sandbox: bool| {
+        let mut command = Command::new(get_cli_binary_path());
+        command
+            .arg("--input")
+            .arg(input_file.path())
+            .arg("--merges")
+            .arg(merges_file.path());
+        if sandbox {
+            command.args(["--sandbox", "--sandbox-workers", "2"]);
+        }
+        let output = command.output().expect("Failed to run CLI");
+        assert!(output.status.success(), "{output:?}");
+        output.stdout
+    };
+
+    assert_eq!(run(true), run(false));
+}
+
+#[test]
+fn test_cli_custom_content_type() {
+    let mut registry = NamedTempFile::new().unwrap();
+    writeln!(registry, "image 0xFF10").unwrap();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"x").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--content-types")
+        .arg(registry.path())
+        .args(["--type", "image"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0xFF, 0x10, 0, b'x']);
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--type", "image"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_adaptive_threads() {
+    let input: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(&input).unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args([
+            "--chunksize",
+            "64KB",
+            "--min-threads",
+            "1",
+            "--max-threads",
+            "4",
+        ])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let expected: Vec<u8> = input
+        .iter()
+        .flat_map(|&b| (b as u16).to_be_bytes())
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn test_cli_duty_cycle_pacing() {
+    let input: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(&input).unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args([
+            "--chunksize",
+            "16KB",
+            "--duty-cycle",
+            "0.5",
+            "--pace-command",
+            "echo 0.8",
+        ])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let expected: Vec<u8> = input
+        .iter()
+        .flat_map(|&b| (b as u16).to_be_bytes())
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn test_cli_byte_range() {
+    let input: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(&input).unwrap();
+    let tokens = |bytes: &[u8]| -> Vec<u8> {
+        bytes
+            .iter()
+            .flat_map(|&b| (b as u16).to_be_bytes())
+            .collect()
+    };
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--byte-range", "4000..4100", "--chunksize", "64"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, tokens(&input[4000..4100]));
+
+    let mut child = Command::new(get_cli_binary_path())
+        .args(["--skip", "9000", "--limit", "5000"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run CLI");
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, tokens(&input[9000..]));
+}
+
+#[test]
+fn test_cli_bos_eos_markers() {
+    use blt_core::special_tokens::SEPARATOR;
+
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::write(input_dir.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"b").unwrap();
+    let run = |scope: &str| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_dir.path())
+            .args(["--bos", "1", "--eos", "2", "--marker-scope", scope])
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output.stdout
+    };
+    let tokens =
+        |tokens: &[u16]| -> Vec<u8> { tokens.iter().flat_map(|t| t.to_be_bytes()).collect() };
+    let (a, b) = (b'a' as u16, b'b' as u16);
+
+    assert_eq!(run("run"), tokens(&[1, a, SEPARATOR, b, 2]));
+    assert_eq!(run("document"), tokens(&[1, a, 2, SEPARATOR, 1, b, 2]));
+}
+
+#[test]
+fn test_cli_content_type_framing() {
+    use blt_core::special_tokens::SEPARATOR;
+
+    let input_dir = tempfile::tempdir().unwrap();
+    std::fs::write(input_dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(input_dir.path().join("b.txt"), b"d").unwrap();
+    let run = |framing: &str| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_dir.path())
+            .args(["--type", "text", "--type-framing", framing])
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output.stdout
+    };
+    let tokens =
+        |tokens: &[u16]| -> Vec<u8> { tokens.iter().flat_map(|t| t.to_be_bytes()).collect() };
+    let (a, b, c, d) = (b'a' as u16, b'b' as u16, b'c' as u16, b'd' as u16);
+    let text = 0xFF01;
+
+    assert_eq!(run("stream"), tokens(&[text, a, b, c, SEPARATOR, d]));
+    assert_eq!(
+        run("document"),
+        tokens(&[text, a, b, c, SEPARATOR, text, d])
+    );
+    assert_eq!(run("2"), tokens(&[text, a, b, text, c, SEPARATOR, text, d]));
+}
+
+#[test]
+fn test_cli_strict_specials() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"abc").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--strict-specials")
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0, b'a', 0, b'b', 0, b'c']);
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--strict-specials", "--passthrough"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_max_tokens() {
+    let input: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(&input).unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--chunksize", "4KB", "--max-tokens", "10001"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let expected: Vec<u8> = input[..10_001]
+        .iter()
+        .flat_map(|&b| (b as u16).to_be_bytes())
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[test]
+fn test_cli_token_rate() {
+    let input: Vec<u8> = (0..3_000u32).map(|i| (i % 251) as u8).collect();
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(&input).unwrap();
+
+    // A burst of 1,000 tokens, then 2,000 more at 10,000 per second.
+    let started = std::time::Instant::now();
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--token-rate", "10000/1000"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+    let expected: Vec<u8> = input
+        .iter()
+        .flat_map(|&b| (b as u16).to_be_bytes())
+        .collect();
+    assert_eq!(output.stdout, expected);
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--token-rate", "0"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_deterministic_output_hash() {
+    let mut merges = NamedTempFile::new().unwrap();
+    writeln!(merges, "97 98").unwrap();
+    let input = b"ab".repeat(300_000);
+
+    let run = |threads: &str, extra: &[&str]| {
+        let mut child = Command::new(get_cli_binary_path())
+            .arg("--merges")
+            .arg(merges.path())
+            .args(["--deterministic", "--output-hash", "--threads", threads])
+            .args(extra)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn CLI process");
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.clone();
+        let writer = std::thread::spawn(move || {
+            // Small writes so the pipe delivers the input in uneven pieces.
+            for piece in input.chunks(7_777) {
+                stdin.write_all(piece).unwrap();
+            }
+        });
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap();
+        output
+    };
+
+    let first = run("1", &[]);
+    assert!(first.status.success());
+    let stderr = String::from_utf8_lossy(&first.stderr);
+    let hash = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Output hash: "))
+        .expect("hash is printed")
+        .to_string();
+    assert!(hash.starts_with("sha256:"));
+
+    let second = run("8", &["--verify-hash", &hash]);
+    assert!(second.status.success());
+    assert_eq!(first.stdout, second.stdout);
+
+    let mismatch = run("2", &["--verify-hash", "sha256:00"]);
+    assert!(!mismatch.status.success());
+    assert!(String::from_utf8_lossy(&mismatch.stderr).contains("does not match"));
+}
+
+#[test]
+fn test_cli_verify_hash_mismatch_keeps_previous_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, "hello world\n").unwrap();
+    let output_path = dir.path().join("out.bin");
+    std::fs::write(&output_path, "old").unwrap();
+    let manifest = dir.path().join("manifest.jsonl");
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&output_path)
+        .arg("--manifest")
+        .arg(&manifest)
+        .args(["--force", "--verify-hash", "sha256:00"])
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not match"));
+
+    // The mismatched output is never committed, so the previous one is left in place.
+    assert_eq!(std::fs::read(&output_path).unwrap(), b"old");
+    assert!(!manifest.exists());
+    let mut names: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["input.txt", "out.bin"]);
+}
+
+#[test]
+fn test_cli_stats_bytes_quarantine() {
+    let dir = tempfile::tempdir().unwrap();
+    let clean = dir.path().join("clean.txt");
+    std::fs::write(&clean, "hello world\n").unwrap();
+    let binary = dir.path().join("binary.txt");
+    std::fs::write(&binary, [0x7f, b'E', b'L', b'F', 0, 0, 0, 0]).unwrap();
+    let quarantine = dir.path().join("quarantine");
+
+    let output = Command::new(get_cli_binary_path())
+        .args(["stats", "bytes", "--json", "--quarantine"])
+        .arg(&quarantine)
+        .arg(&clean)
+        .arg(&binary)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = report["files"].as_array().unwrap();
+    assert!(files[0]["anomalies"].as_array().unwrap().is_empty());
+    assert_eq!(files[1]["anomalies"][0]["kind"], "control_bytes");
+    assert_eq!(files[1]["histogram"][0], 4);
+    assert!(clean.exists());
+    assert!(!binary.exists());
+    assert!(quarantine.join("binary.txt").exists());
+}
+
+#[test]
+fn test_cli_progress_is_silent_without_terminal() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"hello").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg("--progress")
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 10);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_cli_transcode() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"caf\xe9\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_file.path())
+            .args(args)
+            .output()
+            .expect("Failed to run CLI")
+    };
+    let tokens = |stdout: &[u8]| -> Vec<u16> {
+        stdout
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    };
+
+    // The lone 0xE9 is not UTF-8, so detection falls back to Windows-1252 and emits "é".
+    for args in [&["--transcode"][..], &["--transcode", "latin-1"]] {
+        let output = run(args);
+        assert!(output.status.success());
+        assert_eq!(tokens(&output.stdout), [99, 97, 102, 0xC3, 0xA9, 10]);
+    }
+
+    let output = run(&["--transcode", "utf-8", "--decode-errors", "skip"]);
+    assert!(output.status.success());
+    assert_eq!(tokens(&output.stdout), [99, 97, 102, 10]);
+
+    let output = run(&["--transcode", "utf-8", "--decode-errors", "fail"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("byte offset 3"));
+
+    assert!(!run(&["--transcode", "ebcdic"]).status.success());
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_cli_strip_html() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file
+        .write_all(b"<nav>Menu</nav><p>Fish &amp; chips</p><script>x()</script>")
+        .unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_file.path())
+            .args(args)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output
+            .stdout
+            .chunks_exact(2)
+            .map(|pair| pair[1])
+            .collect::<Vec<u8>>()
+    };
+
+    assert_eq!(run(&["--strip-html"]), b"Menu\nFish & chips\n");
+    assert_eq!(run(&["--strip-html", "content"]), b"Fish & chips\n");
+}
+
+#[test]
+fn test_cli_whitespace_normalization() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"\n  a\tb  \n\n\n\nc\t \n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_file.path())
+            .args(args)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output
+            .stdout
+            .chunks_exact(2)
+            .map(|pair| pair[1])
+            .collect::<Vec<u8>>()
+    };
+
+    assert_eq!(
+        run(&["--collapse-whitespace", "--trim-whitespace"]),
+        b"a b\n\nc"
+    );
+    assert_eq!(run(&["--expand-tabs", "4"]), b"\n  a b  \n\n\n\nc    \n");
+    assert_eq!(run(&["--trim-whitespace"]), b"a\tb  \n\n\n\nc");
+}
+
+#[test]
+fn test_cli_number_bucketing() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"id=7 port 8080").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(input_file.path())
+            .args(args)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output
+            .stdout
+            .chunks_exact(2)
+            .map(|pair| pair[1])
+            .collect::<Vec<u8>>()
+    };
+
+    assert_eq!(run(&["--numbers", "placeholder"]), b"id=<num> port <num>");
+    assert_eq!(
+        run(&["--numbers", "magnitude", "--numbers-min-digits", "2"]),
+        b"id=7 port <num:4>"
+    );
+    assert_eq!(run(&["--numbers", "shape"]), b"id=0 port 0000");
+}
+
+#[test]
+fn test_cli_log_records() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file
+        .write_all(b"2024-01-15T10:00:00Z [ERROR] disk full\n\nJan  5 10:00:02 started\n")
+        .unwrap();
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--strip-timestamps", "--log-records"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let tokens: Vec<u16> = output
+        .stdout
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+
+    let (error, record_separator) = (0xFF0C, 0xFF07);
+    let mut expected = vec![error];
+    expected.extend(b"[ERROR] disk full".map(u16::from));
+    expected.push(record_separator);
+    expected.extend(b"started".map(u16::from));
+    expected.push(record_separator);
+    assert_eq!(tokens, expected);
+}
+
+#[cfg(all(unix, feature = "daemon"))]
+#[test]
+fn test_cli_daemon_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("blt.sock");
+    let merges = dir.path().join("merges.txt");
+    std::fs::write(&merges, "97 98\n").unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, "abcab").unwrap();
+
+    let mut daemon = Command::new(get_cli_binary_path())
+        .arg("daemon")
+        .arg("--socket")
+        .arg(&socket)
+        .arg("--merges")
+        .arg(&merges)
+        .spawn()
+        .expect("Failed to start daemon");
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .arg("--daemon")
+        .arg(&socket)
+        .output()
+        .expect("Failed to run CLI");
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, [1, 0, 0, 99, 1, 0]);
+}
+
+#[test]
+fn test_cli_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let merges = dir.path().join("merges.txt");
+    std::fs::write(&merges, "98 97\n").unwrap();
+    let input = dir.path().join("input.bin");
+    // Two records with a one-byte tag and a one-byte length, each holding "b".
+    std::fs::write(&input, b"a\x01ba\x01b").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .arg("--merges")
+        .arg(&merges)
+        .args(["--type", "bin", "--records", "tag=1,len=u8"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let tokens: Vec<u16> = output
+        .stdout
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    // "ba" spans a payload and the next tag, so it is not merged.
+    assert_eq!(tokens, [0xFF03, 97, 1, 98, 97, 1, 98]);
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .args(["--type", "text", "--records", "len=u8"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires the bin content type"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_follow() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("app.log");
+    std::fs::write(&input, "ab").unwrap();
+
+    let mut child = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .args(["--follow", "--follow-interval", "10"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start CLI");
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut tokens = [0u8; 2];
+        while stdout.read_exact(&mut tokens).is_ok() {
+            tx.send(u16::from_be_bytes(tokens)).unwrap();
+        }
+    });
+    let timeout = std::time::Duration::from_secs(10);
+    let next_tokens = |count: usize| -> Vec<u16> {
+        (0..count)
+            .map(|_| rx.recv_timeout(timeout).unwrap())
+            .collect()
+    };
+    assert_eq!(next_tokens(2), [97, 98]);
+
+    // Appended data is tokenized while the process keeps running.
+    let mut log = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&input)
+        .unwrap();
+    log.write_all(b"cd").unwrap();
+    assert_eq!(next_tokens(2), [99, 100]);
+
+    Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_named_pipe_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let fifo = dir.path().join("input.fifo");
+    assert!(Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap()
+        .success());
+    let feed = |fifo: PathBuf| std::thread::spawn(move || std::fs::write(fifo, "hello"));
+
+    // Named pipes cannot be memory-mapped, so they are streamed.
+    let writer = feed(fifo.clone());
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&fifo)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\0h\0e\0l\0l\0o");
+    writer.join().unwrap().unwrap();
+
+    let writer = feed(fifo.clone());
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&fifo)
+        .args(["--byte-range", "1..3"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\0e\0l");
+    // The reader may stop before the writer is done.
+    let _ = writer.join().unwrap();
+}
+
+#[cfg(feature = "video")]
+#[test]
+fn test_cli_demux_video() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("clip.mp4");
+    // An `ftyp` box and an `mdat` box holding "ab", without sample tables.
+    std::fs::write(&input, b"\0\0\0\x0cftypisom\0\0\0\x0amdatab").unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .args(["--type", "video", "--demux"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let tokens: Vec<u16> = output
+        .stdout
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let (video, metadata, separator, data) = (0xFF04, 0xFFF0, 0xFF05, 0xFFF3);
+    let mut expected = vec![video, metadata];
+    expected.extend(b"\0\0\0\x0cftypisom".map(u16::from));
+    expected.extend([separator, data, 97, 98]);
+    assert_eq!(tokens, expected);
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(dir.path().join("clip.mp4"))
+        .args(["--type", "text", "--demux"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_entropy_markers() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mixed.bin");
+    // A chunk of text, then a chunk in which every byte value is equally common.
+    let chunk = 256 * 1024;
+    let mut data = "plain text ".repeat(chunk / 10).into_bytes();
+    data.truncate(chunk);
+    data.extend((0..=255u8).cycle().take(chunk));
+    std::fs::write(&input, &data).unwrap();
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .args(["--chunksize", "256KB", "--entropy-markers"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success());
+    let tokens: Vec<u16> = output
+        .stdout
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let (compressed, plain) = (0xFFF4, 0xFFF5);
+    assert_eq!(tokens.len(), 2 * chunk + 2);
+    assert_eq!(tokens[0], plain);
+    assert_eq!(tokens[1], u16::from(b'p'));
+    assert_eq!(tokens[chunk + 1], compressed);
+    assert_eq!(tokens[chunk + 2], 0);
+}
+
+#[test]
+fn test_cli_no_mmap_matches_mapped_reads() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, "streamed, not mapped").unwrap();
+
+    let run = |extra: &[&str]| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(&input)
+            .args(extra)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output.stdout
+    };
+    assert_eq!(run(&["--no-mmap"]), run(&[]));
+    let range = ["--byte-range", "10..16"];
+    assert_eq!(run(&["--no-mmap", range[0], range[1]]), run(&range));
+    assert_eq!(run(&range), b"\0n\0o\0t\0 \0m\0a");
+}
+
+#[test]
+fn test_cli_mmap_dontneed_matches_mapped_reads() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    // Many pages, cut into chunks that do not end on page boundaries.
+    let text: String = (0..20_000).map(|i| format!("line {i}\n")).collect();
+    std::fs::write(&input, text).unwrap();
+
+    let run = |extra: &[&str]| {
+        let output = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(&input)
+            .args(["--chunksize", "3KB"])
+            .args(extra)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success());
+        output.stdout
+    };
+    assert_eq!(run(&["--mmap-dontneed", "--threads", "4"]), run(&[]));
+    let prefault = ["--mmap-prefault", "16KB", "--threads", "4"];
+    assert_eq!(run(&prefault), run(&[]));
+    // Stops at the token budget with the rest of the input untouched.
+    let limited = run(&[&prefault[..], &["--max-tokens", "10"]].concat());
+    assert_eq!(limited, run(&["--max-tokens", "10"]));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_cli_direct_io_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    // More than one aligned buffer, ending in a partial block.
+    let data: Vec<u8> = (0..700_001u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&input, &data).unwrap();
+
+    let run = |output: &str, extra: &[&str]| {
+        let output = dir.path().join(output);
+        let result = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(&input)
+            .arg("--output")
+            .arg(&output)
+            .args(extra)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(result.status.success());
+        std::fs::read(output).unwrap()
+    };
+    assert_eq!(run("direct.bin", &["--direct-io"]), run("cached.bin", &[]));
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .args(["--direct-io", "--follow"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+}
+
+#[cfg(feature = "io-uring")]
+#[test]
+fn test_cli_io_uring_matches_tokio_io() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&input, &data).unwrap();
+
+    let run = |output: &str, extra: &[&str]| {
+        let output = dir.path().join(output);
+        let status = Command::new(get_cli_binary_path())
+            .arg("--input")
+            .arg(&input)
+            .arg("--output")
+            .arg(&output)
+            .args(["--no-mmap", "--byte-range", "3.."])
+            .args(extra)
+            .status()
+            .expect("Failed to run CLI");
+        assert!(status.success());
+        std::fs::read(output).unwrap()
+    };
+    assert_eq!(run("uring.bin", &["--io-uring"]), run("tokio.bin", &[]));
+}
+
+#[cfg(not(feature = "io-uring"))]
+#[test]
+fn test_cli_io_uring_requires_feature() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"not mapped").unwrap();
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--no-mmap", "--io-uring"])
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`io-uring` feature"));
+}
+
+#[test]
+fn test_cli_pad_to_multiple() {
+    let mut cmd = Command::new(get_cli_binary_path());
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    cmd.args(["--pad-to", "4", "--pad-token", "9"]);
+
+    let mut child = cmd.spawn().expect("Failed to spawn CLI process");
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin
+            .write_all(b"ab\ncd\n")
+            .expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    assert!(output.status.success());
+
+    let expected: Vec<u8> = b"ab\ncd\n"
+        .iter()
+        .map(|&b| u16::from(b))
+        .chain([9, 9])
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(output.stdout, expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_nice_and_ionice() {
+    let run = |args: &[&str]| {
+        let mut child = Command::new(get_cli_binary_path())
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn CLI process");
+        child.stdin.take().unwrap().write_all(b"hi").unwrap();
+        child.wait_with_output().expect("Failed to read stdout")
+    };
+
+    let output = run(&["--nice", "19"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0, b'h', 0, b'i']);
+
+    let output = run(&["--ionice", "best-effort", "--ionice-level", "9"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_vocab_snapshot() {
+    let mut merges_file = NamedTempFile::new().unwrap();
+    merges_file.write_all(b"97 98\n99 32\n").unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let snapshot_path = dir.path().join("merges.snap");
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("snapshot")
+        .arg(merges_file.path())
+        .arg("--output")
+        .arg(&snapshot_path)
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2 merges"));
+
+    let tokenize = |merges: &std::path::Path| {
+        let mut child = Command::new(get_cli_binary_path())
+            .arg("--merges")
+            .arg(merges)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn CLI process");
+        child.stdin.take().unwrap().write_all(b"abc ab").unwrap();
+        let output = child.wait_with_output().expect("Failed to read stdout");
+        assert!(output.status.success());
+        output.stdout
+    };
+    let from_snapshot = tokenize(&snapshot_path);
+    assert_eq!(from_snapshot, tokenize(merges_file.path()));
+    assert_eq!(from_snapshot, [0x01, 0x00, 0x01, 0x01, 0x01, 0x00]);
+}
+
+#[test]
+fn test_cli_encrypted_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_path = dir.path().join("key.hex");
+    std::fs::write(&key_path, format!("{}\n", "2a".repeat(32))).unwrap();
+    let output_path = dir.path().join("out.bin.enc");
+
+    let mut child = Command::new(get_cli_binary_path())
+        .arg("--output")
+        .arg(&output_path)
+        .arg("--encrypt-key")
+        .arg(&key_path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn CLI process");
+    child.stdin.take().unwrap().write_all(b"hi").unwrap();
+    assert!(child.wait().unwrap().success());
+    let encrypted = std::fs::read(&output_path).unwrap();
+    assert!(encrypted.starts_with(b"BLTE"));
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("decrypt")
+        .arg("--key")
+        .arg(&key_path)
+        .arg(&output_path)
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0, b'h', 0, b'i']);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_interrupt_handles_partial_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let run = |action: &str| {
+        let output = dir.path().join(format!("{action}.bin"));
+        let mut child = Command::new(get_cli_binary_path())
+            .arg("--output")
+            .arg(&output)
+            .args(["--on-interrupt", action])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to run CLI");
+        // The input stays open, so the run only ends on the signal.
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"ab").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let status = Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let result = child.wait_with_output().unwrap();
+        assert_eq!(
+            result.status.code(),
+            Some(130),
+            "{}",
+            String::from_utf8_lossy(&result.stderr)
+        );
+        drop(stdin);
+        output
+    };
+
+    let removed = run("remove");
+    assert!(!removed.exists());
+    let kept = run("keep");
+    assert_eq!(std::fs::read(kept).unwrap(), [0, b'a', 0, b'b']);
+    let marked = run("mark");
+    assert!(!marked.exists());
+    let partial = dir.path().join("mark.bin.partial");
+    assert_eq!(std::fs::read(partial).unwrap(), [0, b'a', 0, b'b']);
+    // No temporary files are left behind.
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_cli_serve_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, "abc").unwrap();
+    let shard = dir.path().join("shards").join("a.bin");
+    std::fs::create_dir(shard.parent().unwrap()).unwrap();
+    let status = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&shard)
+        .args(["--header", "--index"])
+        .status()
+        .expect("Failed to run CLI process");
+    assert!(status.success());
+
+    let mut child = Command::new(get_cli_binary_path())
+        .args(["serve", "--inetd", "--shards"])
+        .arg(shard.parent().unwrap())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn CLI process");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"GET /shards/a.bin/documents?start=0&end=1 HTTP/1.1\r\nHost: blt\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let response = output.stdout;
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..head_end]).to_lowercase();
+    assert!(head.contains("x-token-width: 2"), "{head}");
+    assert_eq!(&response[head_end + 4..], [0, 97, 0, 98, 0, 99]);
+}
+
+#[test]
+fn test_cli_on_error_skip_reports_skipped_chunks() {
+    let mut input_file = NamedTempFile::new().unwrap();
+    input_file.write_all(b"abc").unwrap();
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--on-error", "skip", "--stats"])
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0, b'a', 0, b'b', 0, b'c']);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let report: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(report["skipped_chunks"], serde_json::json!([]));
+
+    let output = Command::new(get_cli_binary_path())
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--on-error", "ignore"])
+        .output()
+        .expect("Failed to run CLI process");
+    assert!(!output.status.success());
+}